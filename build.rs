@@ -0,0 +1,28 @@
+// Embeds the directory named by FILE_SHOVER_EMBED_DIR when the `embed`
+// feature is enabled; see src/embed.rs.
+
+#[path = "src/embed/codegen.rs"]
+mod codegen;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/embed/codegen.rs");
+    println!("cargo:rerun-if-env-changed=FILE_SHOVER_EMBED_DIR");
+    if env::var_os("CARGO_FEATURE_EMBED").is_none() {
+        return;
+    }
+
+    let out =
+        PathBuf::from(env::var_os("OUT_DIR").expect("cargo sets OUT_DIR")).join("embedded_site.rs");
+    match env::var_os("FILE_SHOVER_EMBED_DIR") {
+        Some(dir) => {
+            if let Err(e) = codegen::write_manifest(Path::new(&dir), &out) {
+                panic!("failed to embed {}: {}", Path::new(&dir).display(), e);
+            }
+        }
+        None => fs::write(&out, "&[]\n").expect("failed to write embedded site manifest"),
+    }
+}
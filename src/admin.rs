@@ -0,0 +1,507 @@
+/*
+* Admin module
+*
+* Runtime controls for a running server, answered by a second `Server` on its
+* own address (loopback unless a token protects it), so they are never
+* reachable through the public port:
+*
+*   GET    /                      HTML dashboard of the live stats
+*   GET    /_admin/stats          counters, open connections, maintenance
+*   GET    /_admin/connections    open connections per client
+*   GET    /_admin/top            most requested paths and clients;
+*                                 ?by=bytes ranks by bandwidth, ?limit=N
+*   POST   /_admin/purge          empty the in-memory caches; ?path=/dir/ or
+*                                 ?path=/file evicts just those entries
+*   POST   /_admin/reload         rebuild the pipeline from the configuration
+*   GET    /_admin/log-level      current filter; PUT ?filter=debug sets it
+*   GET    /_admin/maintenance    current state; PUT turns it on, DELETE off
+*   GET    /_admin/bans           banned clients; DELETE ?ip=<addr> unbans
+*
+* Every answer but the dashboard is JSON. Endpoints whose feature was not configured (no reload
+* function, no maintenance switch, no automatic bans) answer 501.
+*/
+
+use crate::auth::BearerAuth;
+use crate::ban::AutoBan;
+use crate::connlimit::ConnectionLimiter;
+use crate::dashboard;
+use crate::error::ServerError;
+use crate::maintenance::Maintenance;
+use crate::message::{HttpMethod, Request, Response, StatusCode};
+use crate::middleware::Handler;
+use crate::server::{ReloadHandle, Server};
+use crate::stats::{Ranking, Stats};
+use crate::target::decode_component;
+use crate::telemetry::LogLevel;
+use crate::Service;
+use serde_json::{json, Value};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Entries listed by `/_admin/top` unless `?limit=` asks otherwise.
+const TOP_LIMIT: usize = 20;
+
+/// Builds the pipeline again, e.g. from a re-read configuration file.
+pub type Reload = dyn Fn() -> Result<Service, ServerError> + Send + Sync;
+
+/// The admin API of one [`Server`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use file_shover::admin::Admin;
+/// use file_shover::Server;
+///
+/// let server = Server::builder().root("public").build()?;
+/// let admin = Admin::new(&server)
+///     .reload(|| Server::builder().root("public").build_service())
+///     .build("127.0.0.1:9090")?;
+/// std::thread::spawn(move || admin.run());
+/// server.run()?;
+/// # Ok::<(), file_shover::error::ServerError>(())
+/// ```
+pub struct Admin {
+    service: ReloadHandle,
+    stats: Arc<Stats>,
+    connections: Arc<ConnectionLimiter>,
+    maintenance: Option<Maintenance>,
+    auto_ban: Option<AutoBan>,
+    log_level: Option<LogLevel>,
+    reload: Option<Box<Reload>>,
+    bearer_auth: Option<BearerAuth>,
+}
+
+impl Admin {
+    /// Controls `server`: its counters, connections, caches and pipeline.
+    pub fn new(server: &Server) -> Self {
+        Self {
+            service: server.reload_handle(),
+            stats: server.stats(),
+            connections: server.connections(),
+            maintenance: None,
+            auto_ban: None,
+            log_level: None,
+            reload: None,
+            bearer_auth: None,
+        }
+    }
+
+    /// Lets `/_admin/maintenance` switch the server's maintenance mode.
+    pub fn maintenance(mut self, maintenance: Maintenance) -> Self {
+        self.maintenance = Some(maintenance);
+        self
+    }
+
+    /// Lets `/_admin/bans` list and lift the server's automatic bans.
+    pub fn auto_ban(mut self, bans: AutoBan) -> Self {
+        self.auto_ban = Some(bans);
+        self
+    }
+
+    /// Lets `/_admin/log-level` change the log filter.
+    pub fn log_level(mut self, log_level: LogLevel) -> Self {
+        self.log_level = Some(log_level);
+        self
+    }
+
+    /// Lets `/_admin/reload` replace the pipeline with the one `reload`
+    /// builds. Connections in progress finish with the old pipeline.
+    pub fn reload(
+        mut self,
+        reload: impl Fn() -> Result<Service, ServerError> + Send + Sync + 'static,
+    ) -> Self {
+        self.reload = Some(Box::new(reload));
+        self
+    }
+
+    /// Requires one of these bearer tokens on every admin request.
+    pub fn bearer_auth(mut self, auth: BearerAuth) -> Self {
+        self.bearer_auth = Some(auth);
+        self
+    }
+
+    /// Builds the admin server listening on `bind`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Io` if the socket cannot be bound.
+    pub fn build(mut self, bind: &str) -> Result<Server, ServerError> {
+        let mut builder = Server::builder().bind(bind).threads(2);
+        if let Some(auth) = self.bearer_auth.take() {
+            builder = builder.bearer_auth(auth);
+        }
+        builder.handler(self).build()
+    }
+
+    fn dashboard(&self) -> Response {
+        let maintenance = self.maintenance.as_ref().map(Maintenance::is_active);
+        let html = dashboard::render(&self.stats, self.connections.active(), maintenance);
+        Response::new()
+            .status(StatusCode::OK)
+            .content_type("text/html; charset=utf-8")
+            .header("Cache-Control", "no-store")
+            .content_length(html.len())
+            .body(html)
+    }
+
+    fn stats(&self) -> Response {
+        let mut stats = serde_json::to_value(self.stats.snapshot()).unwrap_or_default();
+        stats["connections_open"] = json!(self.connections.active());
+        stats["maintenance"] = json!(self.maintenance.as_ref().is_some_and(|m| m.is_active()));
+        json_response(StatusCode::OK, stats)
+    }
+
+    fn connections(&self) -> Response {
+        let clients: Vec<Value> = self
+            .connections
+            .clients()
+            .into_iter()
+            .map(|(ip, open)| json!({ "client": ip.to_string(), "open": open }))
+            .collect();
+        json_response(
+            StatusCode::OK,
+            json!({ "open": self.connections.active(), "clients": clients }),
+        )
+    }
+
+    fn top(&self, req: &Request) -> Response {
+        let ranking = match req.query_param("by") {
+            None => Ranking::default(),
+            Some(by) => match Ranking::parse(by) {
+                Some(ranking) => ranking,
+                None => {
+                    return error_response(StatusCode::BAD_REQUEST, "expected ?by=requests|bytes")
+                }
+            },
+        };
+        let limit = match req.query_param("limit").map(str::parse::<usize>) {
+            None => TOP_LIMIT,
+            Some(Ok(limit)) => limit,
+            Some(Err(_)) => return error_response(StatusCode::BAD_REQUEST, "expected ?limit=<n>"),
+        };
+        json_response(
+            StatusCode::OK,
+            json!({
+                "paths": self.stats.top_paths_by(ranking, limit),
+                "clients": self.stats.top_clients(ranking, limit),
+            }),
+        )
+    }
+
+    fn purge(&self, req: &Request) -> Response {
+        let path = match req.query_param("path").map(decode_component) {
+            Some(Ok(path)) if path.starts_with('/') => Some(path),
+            Some(_) => return error_response(StatusCode::BAD_REQUEST, "expected ?path=/<path>"),
+            None => None,
+        };
+        match self.service.current().purge_caches(path.as_deref()) {
+            Ok(purged) => {
+                info!(
+                    "Purged {} cache entries for {}",
+                    purged,
+                    path.as_deref().unwrap_or("all paths")
+                );
+                json_response(StatusCode::OK, json!({ "purged": purged }))
+            }
+            Err(e) => error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+        }
+    }
+
+    fn reload_service(&self) -> Response {
+        let Some(reload) = &self.reload else {
+            return not_configured("reload");
+        };
+        match reload() {
+            Ok(service) => {
+                self.service.replace(service);
+                info!("Configuration reloaded");
+                json_response(StatusCode::OK, json!({ "reloaded": true }))
+            }
+            Err(e) => {
+                warn!("Reload failed, keeping the running configuration: {}", e);
+                error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+            }
+        }
+    }
+
+    fn log_level_request(&self, req: &Request) -> Response {
+        let Some(log_level) = &self.log_level else {
+            return not_configured("log-level");
+        };
+        if req.method == HttpMethod::PUT {
+            let filter = match req.query_param("filter").map(decode_component) {
+                Some(Ok(filter)) => filter,
+                _ => {
+                    return error_response(StatusCode::BAD_REQUEST, "expected ?filter=<directives>")
+                }
+            };
+            if let Err(e) = log_level.set(&filter) {
+                return error_response(StatusCode::BAD_REQUEST, &e.to_string());
+            }
+            info!("Log filter set to {}", filter);
+        }
+        json_response(StatusCode::OK, json!({ "filter": log_level.current() }))
+    }
+
+    fn maintenance_request(&self, req: &Request) -> Response {
+        let Some(maintenance) = &self.maintenance else {
+            return not_configured("maintenance");
+        };
+        match req.method {
+            HttpMethod::PUT => {
+                maintenance.enable();
+                info!("🚧 Maintenance mode on");
+            }
+            HttpMethod::DELETE => {
+                maintenance.disable();
+                info!("🚧 Maintenance mode off");
+            }
+            _ => {}
+        }
+        json_response(StatusCode::OK, json!({ "active": maintenance.is_active() }))
+    }
+
+    fn bans_request(&self, req: &Request) -> Response {
+        let Some(bans) = &self.auto_ban else {
+            return not_configured("bans");
+        };
+        if req.method == HttpMethod::DELETE {
+            let ip = match req.query_param("ip").map(|ip| ip.parse::<IpAddr>()) {
+                Some(Ok(ip)) => ip,
+                _ => return error_response(StatusCode::BAD_REQUEST, "expected ?ip=<address>"),
+            };
+            let unbanned = bans.unban(ip);
+            if unbanned {
+                info!("Unbanned {}", ip);
+            }
+            return json_response(StatusCode::OK, json!({ "unbanned": unbanned }));
+        }
+        let banned: Vec<Value> = bans
+            .banned()
+            .into_iter()
+            .map(|(ip, remaining)| {
+                json!({ "client": ip.to_string(), "remaining_secs": remaining.as_secs() })
+            })
+            .collect();
+        json_response(StatusCode::OK, json!({ "banned": banned }))
+    }
+}
+
+impl Handler for Admin {
+    fn handle(&self, req: &Request) -> Response {
+        use HttpMethod::{DELETE, GET, HEAD, POST, PUT};
+
+        let (allowed, allow): (&[HttpMethod], &str) = match req.path() {
+            "/" | "/_admin/stats" | "/_admin/connections" | "/_admin/top" => {
+                (&[GET, HEAD], "GET, HEAD")
+            }
+            "/_admin/purge" | "/_admin/reload" => (&[POST], "POST"),
+            "/_admin/log-level" => (&[GET, HEAD, PUT], "GET, HEAD, PUT"),
+            "/_admin/maintenance" => (&[GET, HEAD, PUT, DELETE], "GET, HEAD, PUT, DELETE"),
+            "/_admin/bans" => (&[GET, HEAD, DELETE], "GET, HEAD, DELETE"),
+            _ => return error_response(StatusCode::NOT_FOUND, "no such admin endpoint"),
+        };
+        if !allowed.contains(&req.method) {
+            return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+                .header("Allow", allow);
+        }
+        match req.path() {
+            "/" => self.dashboard(),
+            "/_admin/stats" => self.stats(),
+            "/_admin/connections" => self.connections(),
+            "/_admin/top" => self.top(req),
+            "/_admin/purge" => self.purge(req),
+            "/_admin/reload" => self.reload_service(),
+            "/_admin/log-level" => self.log_level_request(req),
+            "/_admin/maintenance" => self.maintenance_request(req),
+            _ => self.bans_request(req),
+        }
+    }
+}
+
+fn json_response(status: StatusCode, value: Value) -> Response {
+    let body = value.to_string();
+    Response::new()
+        .status(status)
+        .content_type("application/json")
+        .header("Cache-Control", "no-store")
+        .content_length(body.len())
+        .body(body)
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    json_response(status, json!({ "error": message }))
+}
+
+fn not_configured(endpoint: &str) -> Response {
+    error_response(
+        StatusCode::NOT_IMPLEMENTED,
+        &format!("{} is not enabled on this server", endpoint),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::MemorySource;
+    use crate::message::Body;
+    use std::time::Duration;
+
+    fn body(response: Response) -> Value {
+        match response.body {
+            Body::Bytes(bytes) => serde_json::from_slice(&bytes).unwrap(),
+            _ => panic!("expected an in-memory body"),
+        }
+    }
+
+    fn call(admin: &Admin, method: HttpMethod, path: &str) -> Response {
+        admin.handle(&Request::builder().method(method).path(path).build())
+    }
+
+    #[test]
+    fn test_admin_endpoints() {
+        let server = Server::builder()
+            .bind("127.0.0.1:0")
+            .file_source(MemorySource::new().with("a.txt", "old"))
+            .build()
+            .unwrap();
+        let maintenance = Maintenance::new();
+        let admin = Admin::new(&server)
+            .maintenance(maintenance.clone())
+            .reload(|| {
+                Server::builder()
+                    .file_source(MemorySource::new().with("a.txt", "new"))
+                    .build_service()
+            });
+
+        let stats = body(call(&admin, HttpMethod::GET, "/_admin/stats"));
+        assert_eq!(stats["requests"], 0);
+        assert_eq!(stats["connections_open"], 0);
+        assert_eq!(stats["maintenance"], false);
+
+        let response = call(&admin, HttpMethod::PUT, "/_admin/maintenance");
+        assert_eq!(body(response)["active"], true);
+        assert!(maintenance.is_active());
+        call(&admin, HttpMethod::DELETE, "/_admin/maintenance");
+        assert!(!maintenance.is_active());
+
+        let raw = server
+            .service()
+            .serve_request(b"GET /a.txt HTTP/1.1\r\n\r\n");
+        assert!(raw.ends_with(b"old"));
+        let response = call(&admin, HttpMethod::POST, "/_admin/reload");
+        assert_eq!(response.status, StatusCode::OK);
+        let raw = server
+            .service()
+            .serve_request(b"GET /a.txt HTTP/1.1\r\n\r\n");
+        assert!(raw.ends_with(b"new"));
+
+        assert_eq!(
+            body(call(&admin, HttpMethod::POST, "/_admin/purge"))["purged"],
+            0
+        );
+        let response = call(&admin, HttpMethod::POST, "/_admin/stats");
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.headers.get("Allow"),
+            Some(&"GET, HEAD".to_string())
+        );
+        let response = call(&admin, HttpMethod::GET, "/_admin/log-level");
+        assert_eq!(response.status, StatusCode::NOT_IMPLEMENTED);
+        let response = call(&admin, HttpMethod::GET, "/");
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some(&"text/html; charset=utf-8".to_string())
+        );
+        admin.stats.record_path("/index.html", 100);
+        admin.stats.record_path("/index.html", 100);
+        admin.stats.record_path("/video.mp4", 50_000);
+        let top = body(call(&admin, HttpMethod::GET, "/_admin/top"));
+        assert_eq!(top["paths"][0]["path"], "/index.html");
+        let top = body(call(
+            &admin,
+            HttpMethod::GET,
+            "/_admin/top?by=bytes&limit=1",
+        ));
+        assert_eq!(top["paths"].as_array().unwrap().len(), 1);
+        assert_eq!(top["paths"][0]["path"], "/video.mp4");
+        let response = call(&admin, HttpMethod::GET, "/_admin/top?by=size");
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        let response = call(&admin, HttpMethod::GET, "/_admin/nope");
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_purge_path() {
+        let server = Server::builder()
+            .bind("127.0.0.1:0")
+            .file_source(
+                MemorySource::new()
+                    .with("assets/app.js", "js")
+                    .with("index.html", "html"),
+            )
+            .checksums(false)
+            .build()
+            .unwrap();
+        let admin = Admin::new(&server);
+        for path in ["/assets/app.js", "/index.html"] {
+            let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+            server.service().serve_request(raw.as_bytes());
+        }
+
+        let purge = |target: &str| call(&admin, HttpMethod::POST, target);
+        assert_eq!(
+            body(purge("/_admin/purge?path=/assets/app.js"))["purged"],
+            1
+        );
+        assert_eq!(body(purge("/_admin/purge?path=/assets/"))["purged"], 0);
+        assert_eq!(body(purge("/_admin/purge"))["purged"], 1);
+        assert_eq!(
+            purge("/_admin/purge?path=assets").status,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            purge("/_admin/purge?path=/a/%2E%2E/b").status,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn test_bans() {
+        let server = Server::builder()
+            .bind("127.0.0.1:0")
+            .file_source(MemorySource::new())
+            .build()
+            .unwrap();
+        assert_eq!(
+            call(&Admin::new(&server), HttpMethod::GET, "/_admin/bans").status,
+            StatusCode::NOT_IMPLEMENTED
+        );
+
+        let bans = AutoBan::new(1, Duration::from_secs(60), Duration::from_secs(600));
+        let admin = Admin::new(&server).auto_ban(bans.clone());
+        let scanner: IpAddr = "198.51.100.9".parse().unwrap();
+        bans.record(scanner, 404);
+
+        let listed = body(call(&admin, HttpMethod::GET, "/_admin/bans"));
+        assert_eq!(listed["banned"][0]["client"], "198.51.100.9");
+        assert!(listed["banned"][0]["remaining_secs"].as_u64().unwrap() <= 600);
+
+        let unban = |target: &str| call(&admin, HttpMethod::DELETE, target);
+        assert_eq!(
+            body(unban("/_admin/bans?ip=198.51.100.9"))["unbanned"],
+            true
+        );
+        assert!(!bans.is_banned(scanner));
+        assert_eq!(
+            body(unban("/_admin/bans?ip=198.51.100.9"))["unbanned"],
+            false
+        );
+        assert_eq!(
+            unban("/_admin/bans?ip=nope").status,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(unban("/_admin/bans").status, StatusCode::BAD_REQUEST);
+    }
+}
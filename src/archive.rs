@@ -0,0 +1,455 @@
+/*
+* Archive module
+*
+* On-the-fly zip and tar.gz downloads of a directory (`GET /photos/?archive=zip`).
+*
+* The directory is walked first so the size and entry limits can refuse a
+* request before anything is sent. The archive is then written by a helper
+* thread into a bounded channel and streamed to the client with chunked
+* encoding, so memory use stays at a few buffers whatever the archive size.
+* If reading a file fails midway, the error reaches the response body and
+* the connection is cut before the final chunk, so clients see a failed
+* download instead of a silently truncated archive.
+*
+* Hidden files and directories (names starting with `.`) are never included.
+*/
+
+use crate::data::get_mime_type;
+use crate::files::{FileMetadata, FileTree};
+use crate::units::utc_date_time;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use std::time::UNIX_EPOCH;
+use tracing::debug;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Size of the chunks handed from the archive thread to the connection.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks buffered between the archive thread and the connection.
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// Archive formats offered for directories.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Parses the `archive` query parameter: `zip`, `tar.gz` or `tgz`.
+    pub fn from_query(value: &str) -> Option<Self> {
+        match value {
+            "zip" => Some(ArchiveFormat::Zip),
+            "tar.gz" | "tgz" => Some(ArchiveFormat::TarGz),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// Upper bounds for one archive download.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArchiveLimits {
+    /// Total size of the archived files, before compression.
+    pub max_size: u64,
+    /// Number of files.
+    pub max_entries: usize,
+}
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        Self {
+            max_size: 1024 * 1024 * 1024,
+            max_entries: 10_000,
+        }
+    }
+}
+
+/// Why an archive was refused.
+#[derive(Debug)]
+pub enum ArchiveError {
+    TooLarge,
+    TooManyEntries,
+    Io(io::Error),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::TooLarge => write!(f, "directory exceeds the archive size limit"),
+            ArchiveError::TooManyEntries => write!(f, "directory exceeds the archive entry limit"),
+            ArchiveError::Io(err) => write!(f, "IO error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArchiveError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(err: io::Error) -> Self {
+        ArchiveError::Io(err)
+    }
+}
+
+/// A file to archive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    /// Path below the archived directory, `/`-separated.
+    pub name: String,
+    pub metadata: FileMetadata,
+}
+
+/// Lists the files below `dir`, skipping hidden ones and those whose
+/// request path `readable` refuses.
+///
+/// # Errors
+///
+/// Returns `TooLarge` or `TooManyEntries` as soon as a limit is exceeded,
+/// and I/O errors from the file tree (`NotFound` if `dir` is missing, hidden
+/// or not a directory).
+pub fn collect(
+    tree: &FileTree,
+    dir: &str,
+    limits: &ArchiveLimits,
+    readable: impl Fn(&str) -> bool,
+) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+    let dir = dir.trim_end_matches('/');
+    if dir.split('/').any(is_hidden) || !tree.metadata(dir)?.is_dir {
+        return Err(io::Error::from(ErrorKind::NotFound).into());
+    }
+    let mut entries = Vec::new();
+    let mut size = 0;
+    let mut pending = vec![String::new()];
+    while let Some(prefix) = pending.pop() {
+        for entry in tree.list(format!("{}/{}", dir, prefix))? {
+            if is_hidden(&entry.name) {
+                continue;
+            }
+            let name = format!("{}{}", prefix, entry.name);
+            if entry.metadata.is_dir {
+                pending.push(format!("{}/", name));
+                continue;
+            }
+            if !readable(&format!("{}/{}", dir, name)) {
+                continue;
+            }
+            size += entry.metadata.len;
+            if size > limits.max_size {
+                return Err(ArchiveError::TooLarge);
+            }
+            if entries.len() == limits.max_entries {
+                return Err(ArchiveError::TooManyEntries);
+            }
+            entries.push(ArchiveEntry {
+                name,
+                metadata: entry.metadata,
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+/// Starts writing an archive of `entries` (from [`collect`]) and returns its
+/// bytes as a reader. Entries are stored below a top-level `base` directory.
+///
+/// # Errors
+///
+/// Returns an error if the archive thread cannot be started.
+pub fn stream(
+    tree: Arc<FileTree>,
+    dir: &str,
+    base: &str,
+    entries: Vec<ArchiveEntry>,
+    format: ArchiveFormat,
+) -> io::Result<impl Read> {
+    let (sender, receiver) = mpsc::sync_channel(CHUNKS_IN_FLIGHT);
+    let errors = sender.clone();
+    let dir = dir.trim_end_matches('/').to_string();
+    let base = base.to_string();
+    thread::Builder::new()
+        .name("archive".to_string())
+        .spawn(move || {
+            let writer = ChannelWriter {
+                sender,
+                buffer: Vec::with_capacity(CHUNK_SIZE),
+            };
+            let written = match format {
+                ArchiveFormat::Zip => write_zip(writer, &tree, &dir, &base, &entries),
+                ArchiveFormat::TarGz => write_tar_gz(writer, &tree, &dir, &base, &entries),
+            };
+            if let Err(e) = written {
+                debug!("Archive of {} stopped: {}", dir, e);
+                // Fails too if the client went away, which is fine
+                let _ = errors.send(Err(e));
+            }
+        })?;
+    Ok(ChannelReader {
+        receiver,
+        chunk: Vec::new(),
+        pos: 0,
+    })
+}
+
+fn write_zip(
+    writer: ChannelWriter,
+    tree: &FileTree,
+    dir: &str,
+    base: &str,
+    entries: &[ArchiveEntry],
+) -> io::Result<()> {
+    let mut zip = ZipWriter::new_stream(writer);
+    for entry in entries {
+        // Compressing JPEGs and videos again only costs CPU
+        let method = if get_mime_type(&entry.name).is_media() {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+        let mut options = SimpleFileOptions::default()
+            .compression_method(method)
+            .unix_permissions(0o644)
+            .large_file(entry.metadata.len >= u32::MAX as u64);
+        if let Some(modified) = zip_time(&entry.metadata) {
+            options = options.last_modified_time(modified);
+        }
+        zip.start_file(format!("{}/{}", base, entry.name), options)
+            .map_err(io::Error::other)?;
+        let mut reader = tree.get_reader(format!("{}/{}", dir, entry.name))?.reader;
+        io::copy(&mut reader, &mut zip)?;
+    }
+    zip.finish().map_err(io::Error::other)?.into_inner().flush()
+}
+
+fn write_tar_gz(
+    writer: ChannelWriter,
+    tree: &FileTree,
+    dir: &str,
+    base: &str,
+    entries: &[ArchiveEntry],
+) -> io::Result<()> {
+    let mut tar = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(entry.metadata.len);
+        header.set_mode(0o644);
+        header.set_mtime(unix_time(&entry.metadata).unwrap_or(0));
+        let reader = tree.get_reader(format!("{}/{}", dir, entry.name))?.reader;
+        // A file that shrank since it was listed would misalign the archive
+        let mut reader = reader.take(entry.metadata.len);
+        tar.append_data(&mut header, format!("{}/{}", base, entry.name), &mut reader)?;
+    }
+    tar.into_inner()?.finish()?.flush()
+}
+
+fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+fn unix_time(metadata: &FileMetadata) -> Option<u64> {
+    metadata
+        .modified?
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|elapsed| elapsed.as_secs())
+}
+
+/// Converts a modification time to the zip (MS-DOS, UTC) format.
+fn zip_time(metadata: &FileMetadata) -> Option<zip::DateTime> {
+    let (year, month, day, hour, minute, second) = utc_date_time(unix_time(metadata)?);
+    zip::DateTime::from_date_and_time(u16::try_from(year).ok()?, month, day, hour, minute, second)
+        .ok()
+}
+
+/// Sends everything written in chunks to a [`ChannelReader`].
+struct ChannelWriter {
+    sender: SyncSender<io::Result<Vec<u8>>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+        self.sender
+            .send(Ok(chunk))
+            .map_err(|_| io::Error::from(ErrorKind::BrokenPipe))
+    }
+}
+
+/// Reads the chunks of a [`ChannelWriter`]; the end of the stream is reached
+/// when the writing thread finishes.
+struct ChannelReader {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::MemorySource;
+    use std::io::Cursor;
+    use std::time::{Duration, SystemTime};
+
+    fn tree() -> Arc<FileTree> {
+        Arc::new(FileTree::from_source(
+            MemorySource::new()
+                .with("photos/a.jpg", vec![1u8; 300_000])
+                .with("photos/trip/b.txt", "bee")
+                .with("photos/.secret", "x")
+                .with("photos/.git/config", "x")
+                .with("other.txt", "o"),
+        ))
+    }
+
+    #[test]
+    fn test_collect_skips_hidden() {
+        let entries = collect(&tree(), "/photos/", &ArchiveLimits::default(), |_| true).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["a.jpg", "trip/b.txt"]);
+
+        let err =
+            collect(&tree(), "/photos/.git", &ArchiveLimits::default(), |_| true).unwrap_err();
+        assert!(matches!(err, ArchiveError::Io(e) if e.kind() == ErrorKind::NotFound));
+        assert!(collect(&tree(), "/other.txt", &ArchiveLimits::default(), |_| true).is_err());
+    }
+
+    #[test]
+    fn test_collect_skips_unreadable() {
+        let readable = |path: &str| path != "/photos/trip/b.txt";
+        let entries = collect(&tree(), "/photos", &ArchiveLimits::default(), readable).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["a.jpg"]);
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = ArchiveLimits {
+            max_size: 1000,
+            max_entries: 10,
+        };
+        assert!(matches!(
+            collect(&tree(), "/photos", &limits, |_| true),
+            Err(ArchiveError::TooLarge)
+        ));
+        let limits = ArchiveLimits {
+            max_size: u64::MAX,
+            max_entries: 1,
+        };
+        assert!(matches!(
+            collect(&tree(), "/photos", &limits, |_| true),
+            Err(ArchiveError::TooManyEntries)
+        ));
+    }
+
+    #[test]
+    fn test_zip_round_trip() {
+        let tree = tree();
+        let entries = collect(&tree, "/photos", &ArchiveLimits::default(), |_| true).unwrap();
+        let mut bytes = Vec::new();
+        stream(
+            Arc::clone(&tree),
+            "/photos",
+            "photos",
+            entries,
+            ArchiveFormat::Zip,
+        )
+        .unwrap()
+        .read_to_end(&mut bytes)
+        .unwrap();
+
+        let mut zip = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let mut content = String::new();
+        zip.by_name("photos/trip/b.txt")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "bee");
+        assert_eq!(zip.by_name("photos/a.jpg").unwrap().size(), 300_000);
+        assert_eq!(zip.len(), 2);
+    }
+
+    #[test]
+    fn test_tar_gz_round_trip() {
+        let tree = tree();
+        let entries = collect(&tree, "/photos", &ArchiveLimits::default(), |_| true).unwrap();
+        let reader = stream(tree, "/photos", "photos", entries, ArchiveFormat::TarGz).unwrap();
+
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(reader));
+        let names: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(names, ["photos/a.jpg", "photos/trip/b.txt"]);
+    }
+
+    #[test]
+    fn test_zip_time() {
+        let metadata = FileMetadata {
+            len: 0,
+            is_dir: false,
+            // 2024-02-29 13:45:30 UTC
+            modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_214_330)),
+        };
+        let time = zip_time(&metadata).unwrap();
+        assert_eq!((time.year(), time.month(), time.day()), (2024, 2, 29));
+        assert_eq!((time.hour(), time.minute(), time.second()), (13, 45, 30));
+    }
+}
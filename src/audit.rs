@@ -0,0 +1,365 @@
+/*
+* Audit module
+*
+* Opt-in request auditing into an embedded SQLite database: one row per
+* answered request with its time, client, method, path, status, body bytes,
+* duration and User-Agent. Rows go through a bounded channel to a writer
+* thread that inserts them in batches, so request threads never wait on the
+* disk; if the writer falls behind, records are dropped and counted.
+*
+* A retention period deletes older rows when the log is opened and every
+* hour after that. The database uses WAL journaling, so `file-shover report`
+* can read it while the server keeps writing.
+*
+*   file-shover --root ./public --audit-db audit.sqlite --audit-retention 30d
+*   file-shover report audit.sqlite --since 7d --top 20
+*/
+
+use crate::config::ConfigError;
+use crate::error::ServerError;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, warn};
+
+/// Records queued for the writer before new ones are dropped.
+const QUEUE_CAPACITY: usize = 10_000;
+
+/// Most records inserted in one transaction.
+const BATCH: usize = 500;
+
+/// How often rows past the retention period are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS requests (
+        id INTEGER PRIMARY KEY,
+        timestamp INTEGER NOT NULL,
+        client TEXT NOT NULL,
+        method TEXT NOT NULL,
+        path TEXT NOT NULL,
+        status INTEGER NOT NULL,
+        bytes INTEGER NOT NULL,
+        duration_us INTEGER NOT NULL,
+        user_agent TEXT
+    );
+    CREATE INDEX IF NOT EXISTS requests_timestamp ON requests (timestamp);
+";
+
+/// One answered request.
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    /// When the response was sent.
+    pub timestamp: SystemTime,
+    pub client: IpAddr,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    /// Body bytes sent.
+    pub bytes: u64,
+    pub duration: Duration,
+    pub user_agent: Option<String>,
+}
+
+/// Handle to the audit database; clones share the writer thread.
+///
+/// The writer flushes its queue and stops once the last clone is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::audit::{self, AuditLog, AuditRecord};
+/// use std::time::{Duration, SystemTime};
+///
+/// let path = std::env::temp_dir().join(format!("file-shover-audit-doc-{}.sqlite", std::process::id()));
+/// let log = AuditLog::open(&path, Some(Duration::from_secs(30 * 86400)))?;
+/// log.record(AuditRecord {
+///     timestamp: SystemTime::now(),
+///     client: "203.0.113.7".parse().unwrap(),
+///     method: "GET".to_string(),
+///     path: "/releases/app.zip".to_string(),
+///     status: 200,
+///     bytes: 4096,
+///     duration: Duration::from_millis(12),
+///     user_agent: Some("curl/8.5.0".to_string()),
+/// });
+/// drop(log);
+///
+/// let report = audit::report(&path, None, 10)?;
+/// assert_eq!(report.requests, 1);
+/// assert_eq!(report.top_files[0].name, "/releases/app.zip");
+/// # std::fs::remove_file(&path).ok();
+/// # Ok::<(), file_shover::error::ServerError>(())
+/// ```
+#[derive(Clone)]
+pub struct AuditLog {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    sender: Option<SyncSender<AuditRecord>>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+    dropped: AtomicU64,
+}
+
+impl AuditLog {
+    /// Opens or creates the database at `path` and starts the writer.
+    /// With a `retention`, older rows are deleted now and every hour.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Config` if the database cannot be opened or
+    /// its schema created.
+    pub fn open(path: impl AsRef<Path>, retention: Option<Duration>) -> Result<Self, ServerError> {
+        let path = path.as_ref();
+        let invalid = |e: rusqlite::Error| {
+            ConfigError::Invalid(format!("audit database {}: {}", path.display(), e))
+        };
+        let conn = Connection::open(path).map_err(invalid)?;
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .map_err(invalid)?;
+        conn.execute_batch(SCHEMA).map_err(invalid)?;
+        if let Some(retention) = retention {
+            prune(&conn, retention).map_err(invalid)?;
+        }
+
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let writer = std::thread::Builder::new()
+            .name("audit-writer".to_string())
+            .spawn(move || write_loop(conn, receiver, retention))?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                sender: Some(sender),
+                writer: Mutex::new(Some(writer)),
+                dropped: AtomicU64::new(0),
+            }),
+        })
+    }
+
+    /// Queues `record` for writing; drops it if the writer is too far behind.
+    pub fn record(&self, record: AuditRecord) {
+        let Some(sender) = &self.inner.sender else {
+            return;
+        };
+        match sender.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                let dropped = self.inner.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped.is_power_of_two() {
+                    warn!("Audit queue full, {} records dropped so far", dropped);
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+
+    /// Records dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // Closing the channel lets the writer drain the queue and exit
+        self.sender.take();
+        let writer = self.writer.get_mut().unwrap_or_else(|e| e.into_inner());
+        if let Some(writer) = writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn write_loop(mut conn: Connection, receiver: Receiver<AuditRecord>, retention: Option<Duration>) {
+    let mut last_prune = Instant::now();
+    loop {
+        match receiver.recv_timeout(PRUNE_INTERVAL) {
+            Ok(first) => {
+                let batch: Vec<_> = std::iter::once(first)
+                    .chain(receiver.try_iter().take(BATCH - 1))
+                    .collect();
+                if let Err(e) = insert(&mut conn, &batch) {
+                    error!("Failed to write {} audit records: {}", batch.len(), e);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        if let Some(retention) = retention.filter(|_| last_prune.elapsed() >= PRUNE_INTERVAL) {
+            if let Err(e) = prune(&conn, retention) {
+                error!("Failed to delete old audit records: {}", e);
+            }
+            last_prune = Instant::now();
+        }
+    }
+}
+
+fn insert(conn: &mut Connection, batch: &[AuditRecord]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut insert = tx.prepare_cached(
+            "INSERT INTO requests (timestamp, client, method, path, status, bytes, duration_us, user_agent)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for record in batch {
+            insert.execute(params![
+                unix_secs(record.timestamp) as i64,
+                record.client.to_string(),
+                record.method,
+                record.path,
+                record.status,
+                record.bytes as i64,
+                record.duration.as_micros() as i64,
+                record.user_agent,
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+fn prune(conn: &Connection, retention: Duration) -> rusqlite::Result<usize> {
+    let cutoff = unix_secs(SystemTime::now()).saturating_sub(retention.as_secs());
+    conn.execute(
+        "DELETE FROM requests WHERE timestamp < ?1",
+        params![cutoff as i64],
+    )
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// Totals and top entries over the audited requests.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub requests: u64,
+    pub bytes: u64,
+    /// Files most often served successfully (2xx).
+    pub top_files: Vec<TopEntry>,
+    /// Clients with the most requests.
+    pub top_clients: Vec<TopEntry>,
+}
+
+/// A file or client in a [`Report`].
+#[derive(Debug, Serialize)]
+pub struct TopEntry {
+    pub name: String,
+    pub requests: u64,
+    pub bytes: u64,
+}
+
+/// Summarizes the requests recorded at or after `since` (all of them if
+/// `None`), with at most `limit` top files and clients.
+///
+/// # Errors
+///
+/// Returns `ServerError::Config` if the database cannot be opened or does
+/// not hold an audit log.
+pub fn report(
+    path: impl AsRef<Path>,
+    since: Option<SystemTime>,
+    limit: usize,
+) -> Result<Report, ServerError> {
+    let path = path.as_ref();
+    let invalid = |e: rusqlite::Error| {
+        ConfigError::Invalid(format!("audit database {}: {}", path.display(), e))
+    };
+    let conn = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(invalid)?;
+    let since = since.map_or(0, unix_secs) as i64;
+    let limit = limit as i64;
+
+    let (requests, bytes) = conn
+        .query_row(
+            "SELECT COUNT(*), COALESCE(SUM(bytes), 0) FROM requests WHERE timestamp >= ?1",
+            params![since],
+            |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64)),
+        )
+        .map_err(invalid)?;
+    let top = |sql: &str| -> rusqlite::Result<Vec<TopEntry>> {
+        let mut statement = conn.prepare(sql)?;
+        let rows = statement.query_map(params![since, limit], |row| {
+            Ok(TopEntry {
+                name: row.get(0)?,
+                requests: row.get::<_, i64>(1)? as u64,
+                bytes: row.get::<_, i64>(2)? as u64,
+            })
+        })?;
+        rows.collect()
+    };
+    let top_files = top("SELECT path, COUNT(*) AS hits, SUM(bytes) FROM requests
+         WHERE timestamp >= ?1 AND status BETWEEN 200 AND 299
+         GROUP BY path ORDER BY hits DESC, path LIMIT ?2")
+    .map_err(invalid)?;
+    let top_clients = top("SELECT client, COUNT(*) AS hits, SUM(bytes) FROM requests
+         WHERE timestamp >= ?1
+         GROUP BY client ORDER BY hits DESC, client LIMIT ?2")
+    .map_err(invalid)?;
+    Ok(Report {
+        requests,
+        bytes,
+        top_files,
+        top_clients,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(path: &str, client: &str, status: u16, age: Duration) -> AuditRecord {
+        AuditRecord {
+            timestamp: SystemTime::now() - age,
+            client: client.parse().unwrap(),
+            method: "GET".to_string(),
+            path: path.to_string(),
+            status,
+            bytes: 100,
+            duration: Duration::from_millis(1),
+            user_agent: None,
+        }
+    }
+
+    #[test]
+    fn test_report_and_retention() {
+        let path =
+            std::env::temp_dir().join(format!("file-shover-audit-{}.sqlite", std::process::id()));
+        let day = Duration::from_secs(86400);
+
+        let log = AuditLog::open(&path, None).unwrap();
+        log.record(record("/old.txt", "10.0.0.1", 200, 10 * day));
+        log.record(record("/a.txt", "10.0.0.1", 200, Duration::ZERO));
+        log.record(record("/a.txt", "10.0.0.2", 200, Duration::ZERO));
+        log.record(record("/b.txt", "10.0.0.2", 200, Duration::ZERO));
+        log.record(record("/missing", "10.0.0.2", 404, Duration::ZERO));
+        drop(log);
+
+        let all = report(&path, None, 10).unwrap();
+        assert_eq!(all.requests, 5);
+        assert_eq!(all.bytes, 500);
+        let recent = report(&path, Some(SystemTime::now() - day), 1).unwrap();
+        assert_eq!(recent.requests, 4);
+        assert_eq!(recent.top_files.len(), 1);
+        assert_eq!(recent.top_files[0].name, "/a.txt");
+        assert_eq!(recent.top_files[0].requests, 2);
+        assert_eq!(recent.top_clients[0].name, "10.0.0.2");
+        assert_eq!(recent.top_clients[0].requests, 3);
+
+        // Reopening with a retention period drops the old row
+        drop(AuditLog::open(&path, Some(7 * day)).unwrap());
+        assert_eq!(report(&path, None, 10).unwrap().requests, 4);
+
+        for suffix in ["", "-wal", "-shm"] {
+            let mut file = path.clone().into_os_string();
+            file.push(suffix);
+            std::fs::remove_file(file).ok();
+        }
+    }
+}
@@ -0,0 +1,535 @@
+/*
+* Authentication module
+*
+* Static bearer-token authentication, aimed at CI systems fetching artifacts:
+* requests must carry `Authorization: Bearer <token>` matching one of the
+* configured tokens.
+*
+* Token comparison is constant-time with respect to the token contents, so the
+* response timing does not reveal how many leading bytes of a guess were right.
+*
+* On top of that, an `AccessPolicy` built from the `[auth]` configuration maps
+* path globs to requirements (public, bearer token, or membership of a basic
+* auth group), so one instance can serve everything under `/public` openly
+* while `/internal` needs credentials. With mutual TLS, a rule can also
+* require a verified client certificate, optionally with a common name
+* matching a glob (`cert:*.ci.example.com`).
+*/
+
+use crate::config::{AuthConfig, ConfigError};
+use crate::glob::Glob;
+use crate::hex::from_hex;
+use crate::tls::ClientCert;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Compares two byte strings in time independent of where they differ.
+///
+/// Only the lengths may leak through timing, never the contents.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::auth::constant_time_eq;
+///
+/// assert!(constant_time_eq(b"secret", b"secret"));
+/// assert!(!constant_time_eq(b"secret", b"secreT"));
+/// assert!(!constant_time_eq(b"secret", b"secret2"));
+/// ```
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let len = a.len().max(b.len());
+    let mut diff = u8::from(a.len() != b.len());
+    for i in 0..len {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        diff |= x ^ y;
+    }
+    diff == 0
+}
+
+/// Set of accepted bearer tokens.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::auth::BearerAuth;
+///
+/// let auth = BearerAuth::new(vec!["ci-token".to_string()]);
+/// assert!(auth.is_authorized(Some("Bearer ci-token")));
+/// assert!(!auth.is_authorized(Some("Bearer wrong")));
+/// assert!(!auth.is_authorized(Some("Basic Y2k6dG9rZW4=")));
+/// assert!(!auth.is_authorized(None));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BearerAuth {
+    tokens: Vec<String>,
+}
+
+impl BearerAuth {
+    /// Creates an authenticator accepting any of `tokens`. Empty tokens are ignored.
+    pub fn new(tokens: Vec<String>) -> Self {
+        Self {
+            tokens: tokens.into_iter().filter(|t| !t.is_empty()).collect(),
+        }
+    }
+
+    /// Reads tokens from a file, one per line.
+    ///
+    /// Blank lines and lines starting with `#` are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read.
+    pub fn tokens_from_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Returns true if no tokens are configured.
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Checks the value of an `Authorization` header.
+    ///
+    /// Every configured token is compared, so the time taken does not depend on
+    /// which token (if any) matched.
+    pub fn is_authorized(&self, authorization: Option<&str>) -> bool {
+        let Some(token) = authorization.and_then(bearer_token) else {
+            return false;
+        };
+        self.tokens.iter().fold(false, |matched, candidate| {
+            matched | constant_time_eq(candidate.as_bytes(), token.as_bytes())
+        })
+    }
+}
+
+/// Extracts the credentials of the given scheme from an authorization value.
+fn credentials<'a>(authorization: &'a str, scheme: &str) -> Option<&'a str> {
+    let (name, value) = authorization.trim().split_once(' ')?;
+    name.eq_ignore_ascii_case(scheme)
+        .then(|| value.trim())
+        .filter(|value| !value.is_empty())
+}
+
+/// Extracts the credentials from a `Bearer` authorization value.
+fn bearer_token(authorization: &str) -> Option<&str> {
+    credentials(authorization, "Bearer")
+}
+
+/// What a path requires before it is served.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    /// Anyone may read.
+    Public,
+    /// A valid bearer token is required.
+    Token,
+    /// Basic auth credentials of a member of the named group are required.
+    Group(String),
+    /// A verified client certificate is required, with a common name
+    /// matching the glob if one is given.
+    Cert(Option<Glob>),
+}
+
+impl FromStr for Access {
+    type Err = ConfigError;
+
+    /// Parses `public`, `token`, `group:<name>`, `cert` or `cert:<glob>`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::auth::Access;
+    ///
+    /// assert_eq!("public".parse::<Access>().unwrap(), Access::Public);
+    /// assert_eq!("group:staff".parse::<Access>().unwrap(), Access::Group("staff".to_string()));
+    /// assert_eq!("cert".parse::<Access>().unwrap(), Access::Cert(None));
+    /// assert!("everyone".parse::<Access>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(Access::Public),
+            "token" => Ok(Access::Token),
+            "cert" => Ok(Access::Cert(None)),
+            _ => match (s.strip_prefix("group:"), s.strip_prefix("cert:")) {
+                (Some(group), _) if !group.is_empty() => Ok(Access::Group(group.to_string())),
+                (_, Some(name)) if !name.is_empty() => Ok(Access::Cert(Some(Glob::new(name)))),
+                _ => Err(ConfigError::Invalid(format!(
+                    "unknown access '{}' (expected 'public', 'token', 'group:<name>' or 'cert')",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+/// Result of checking a request against an [`Access`] requirement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthOutcome {
+    /// The request may proceed.
+    Granted,
+    /// Credentials are missing or wrong: reply 401 with this `WWW-Authenticate` value.
+    Challenge(&'static str),
+    /// Credentials are valid but not sufficient, or a required client
+    /// certificate is missing: reply 403.
+    Forbidden,
+}
+
+const BEARER_CHALLENGE: &str = "Bearer realm=\"file-shover\"";
+const BASIC_CHALLENGE: &str = "Basic realm=\"file-shover\", charset=\"UTF-8\"";
+
+#[derive(Debug, Clone)]
+enum Password {
+    Plain(String),
+    Sha256(Vec<u8>),
+}
+
+impl Password {
+    fn parse(value: &str) -> Result<Self, ConfigError> {
+        match value.strip_prefix("sha256:") {
+            Some(hex) => from_hex(hex)
+                .filter(|digest| digest.len() == 32)
+                .map(Password::Sha256)
+                .ok_or_else(|| ConfigError::Invalid("malformed sha256 password hash".to_string())),
+            None => Ok(Password::Plain(value.to_string())),
+        }
+    }
+
+    fn verify(&self, candidate: &str) -> bool {
+        match self {
+            Password::Plain(expected) => {
+                constant_time_eq(expected.as_bytes(), candidate.as_bytes())
+            }
+            Password::Sha256(expected) => {
+                constant_time_eq(expected, &Sha256::digest(candidate.as_bytes()))
+            }
+        }
+    }
+}
+
+/// Path rules plus the credentials they refer to.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::auth::{AccessPolicy, AuthOutcome, BearerAuth};
+/// use file_shover::config::Config;
+///
+/// let config = Config::from_toml(r#"
+///     [auth.users]
+///     alice = "wonderland"
+///     [auth.groups]
+///     staff = ["alice"]
+///     [[auth.rules]]
+///     path = "/internal/**"
+///     access = "group:staff"
+/// "#).unwrap();
+/// let policy = AccessPolicy::from_config(&config.auth).unwrap();
+/// let tokens = BearerAuth::default();
+///
+/// // "YWxpY2U6d29uZGVybGFuZA==" is base64("alice:wonderland")
+/// let access = policy.access_for("/internal/report.pdf").unwrap();
+/// assert_eq!(
+///     policy.check(access, Some("Basic YWxpY2U6d29uZGVybGFuZA=="), None, &tokens),
+///     AuthOutcome::Granted
+/// );
+/// assert!(matches!(policy.check(access, None, None, &tokens), AuthOutcome::Challenge(_)));
+/// assert!(policy.access_for("/index.html").is_none());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicy {
+    rules: Vec<(Glob, Access)>,
+    users: HashMap<String, Password>,
+    groups: HashMap<String, Vec<String>>,
+}
+
+impl AccessPolicy {
+    /// Builds the policy from the `[auth]` configuration section.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::Invalid`] for unknown access values, rules naming
+    /// undefined groups, groups naming undefined users, or malformed hashes.
+    pub fn from_config(config: &AuthConfig) -> Result<Self, ConfigError> {
+        let users = config
+            .users
+            .iter()
+            .map(|(name, password)| Ok((name.clone(), Password::parse(password)?)))
+            .collect::<Result<HashMap<_, _>, ConfigError>>()?;
+
+        for (group, members) in &config.groups {
+            if let Some(unknown) = members.iter().find(|m| !users.contains_key(*m)) {
+                return Err(ConfigError::Invalid(format!(
+                    "group '{}' refers to unknown user '{}'",
+                    group, unknown
+                )));
+            }
+        }
+
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                let access: Access = rule.access.parse()?;
+                if let Access::Group(group) = &access {
+                    if !config.groups.contains_key(group) {
+                        return Err(ConfigError::Invalid(format!(
+                            "rule for '{}' refers to unknown group '{}'",
+                            rule.path, group
+                        )));
+                    }
+                }
+                Ok((Glob::new(&rule.path), access))
+            })
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+
+        Ok(Self {
+            rules,
+            users,
+            groups: config.groups.clone(),
+        })
+    }
+
+    /// Returns true if no path rules are configured.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns true if any rule requires a bearer token.
+    pub fn requires_tokens(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|(_, access)| *access == Access::Token)
+    }
+
+    /// Returns true if any rule requires a client certificate.
+    pub fn requires_client_cert(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|(_, access)| matches!(access, Access::Cert(_)))
+    }
+
+    /// Returns the requirement of the first rule matching `path`.
+    pub fn access_for(&self, path: &str) -> Option<&Access> {
+        self.rule_for(path).map(|index| &self.rules[index].1)
+    }
+
+    /// Returns the position of the first rule matching `path`.
+    pub(crate) fn rule_for(&self, path: &str) -> Option<usize> {
+        self.rules.iter().position(|(glob, _)| glob.matches(path))
+    }
+
+    /// Checks an `Authorization` header value and the client certificate
+    /// of the connection against a requirement.
+    pub fn check(
+        &self,
+        access: &Access,
+        authorization: Option<&str>,
+        client_cert: Option<&ClientCert>,
+        tokens: &BearerAuth,
+    ) -> AuthOutcome {
+        match access {
+            Access::Public => AuthOutcome::Granted,
+            Access::Token if tokens.is_authorized(authorization) => AuthOutcome::Granted,
+            Access::Token => AuthOutcome::Challenge(BEARER_CHALLENGE),
+            Access::Group(group) => match authorization.and_then(|a| self.basic_user(a)) {
+                None => AuthOutcome::Challenge(BASIC_CHALLENGE),
+                Some(user) if self.groups[group].iter().any(|m| m == user) => AuthOutcome::Granted,
+                Some(_) => AuthOutcome::Forbidden,
+            },
+            // A certificate is presented during the handshake, so there is
+            // nothing to challenge the client for
+            Access::Cert(name) => match client_cert {
+                Some(cert)
+                    if name.as_ref().is_none_or(|glob| {
+                        cert.common_name
+                            .as_deref()
+                            .is_some_and(|cn| glob.matches(cn))
+                    }) =>
+                {
+                    AuthOutcome::Granted
+                }
+                _ => AuthOutcome::Forbidden,
+            },
+        }
+    }
+
+    /// Returns the authenticated user name for a valid `Basic` authorization value.
+    pub fn basic_user(&self, authorization: &str) -> Option<&str> {
+        let encoded = credentials(authorization, "Basic")?;
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        let (name, stored) = self.users.get_key_value(user)?;
+        stored.verify(password).then_some(name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq_lengths() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"", b"a"));
+        // Lengths differing by a multiple of 256 must not compare equal
+        let long = vec![b'a'; 256];
+        assert!(!constant_time_eq(b"", &long));
+    }
+
+    #[test]
+    fn test_scheme_is_case_insensitive() {
+        let auth = BearerAuth::new(vec!["abc".to_string()]);
+        assert!(auth.is_authorized(Some("bearer abc")));
+        assert!(auth.is_authorized(Some("BEARER   abc ")));
+    }
+
+    #[test]
+    fn test_multiple_tokens() {
+        let auth = BearerAuth::new(vec!["one".to_string(), "two".to_string()]);
+        assert!(auth.is_authorized(Some("Bearer one")));
+        assert!(auth.is_authorized(Some("Bearer two")));
+        assert!(!auth.is_authorized(Some("Bearer three")));
+    }
+
+    fn policy(toml: &str) -> Result<AccessPolicy, ConfigError> {
+        AccessPolicy::from_config(&crate::config::Config::from_toml(toml).unwrap().auth)
+    }
+
+    fn basic(user: &str, password: &str) -> String {
+        let encoded =
+            base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password));
+        format!("Basic {}", encoded)
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let policy = policy(
+            r#"
+            [[auth.rules]]
+            path = "/internal/public/**"
+            access = "public"
+            [[auth.rules]]
+            path = "/internal/**"
+            access = "token"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            policy.access_for("/internal/public/a"),
+            Some(&Access::Public)
+        );
+        assert_eq!(policy.access_for("/internal/a"), Some(&Access::Token));
+        assert!(policy.requires_tokens());
+    }
+
+    #[test]
+    fn test_group_membership() {
+        let policy = policy(
+            r#"
+            [auth.users]
+            alice = "sha256:a71a7c7011f53a1bab3642ec2ce12593f05230ace8de1e3e7645f69efac1443d"
+            bob = "builder"
+            [auth.groups]
+            staff = ["alice"]
+            "#,
+        )
+        .unwrap();
+        let staff = Access::Group("staff".to_string());
+        let tokens = BearerAuth::default();
+
+        let alice = basic("alice", "wonderland");
+        assert_eq!(
+            policy.check(&staff, Some(&alice), None, &tokens),
+            AuthOutcome::Granted
+        );
+
+        let bob = basic("bob", "builder");
+        assert_eq!(
+            policy.check(&staff, Some(&bob), None, &tokens),
+            AuthOutcome::Forbidden
+        );
+
+        let wrong = basic("alice", "looking-glass");
+        assert_eq!(
+            policy.check(&staff, Some(&wrong), None, &tokens),
+            AuthOutcome::Challenge(BASIC_CHALLENGE)
+        );
+    }
+
+    #[test]
+    fn test_token_rule_uses_bearer_tokens() {
+        let policy = AccessPolicy::default();
+        let tokens = BearerAuth::new(vec!["t".to_string()]);
+        assert_eq!(
+            policy.check(&Access::Token, Some("Bearer t"), None, &tokens),
+            AuthOutcome::Granted
+        );
+        assert_eq!(
+            policy.check(&Access::Token, None, None, &tokens),
+            AuthOutcome::Challenge(BEARER_CHALLENGE)
+        );
+    }
+
+    #[test]
+    fn test_cert_rule() {
+        let policy = AccessPolicy::default();
+        let tokens = BearerAuth::default();
+        let cert = |cn: Option<&str>| ClientCert {
+            subject: cn.map_or_else(|| "O=Example".to_string(), |cn| format!("CN={}", cn)),
+            common_name: cn.map(str::to_string),
+        };
+        let runner = cert(Some("build-1.ci.example.com"));
+
+        let any: Access = "cert".parse().unwrap();
+        assert_eq!(
+            policy.check(&any, None, Some(&cert(None)), &tokens),
+            AuthOutcome::Granted
+        );
+        assert_eq!(
+            policy.check(&any, Some("Bearer t"), None, &tokens),
+            AuthOutcome::Forbidden
+        );
+
+        let ci: Access = "cert:*.ci.example.com".parse().unwrap();
+        assert_eq!(
+            policy.check(&ci, None, Some(&runner), &tokens),
+            AuthOutcome::Granted
+        );
+        assert_eq!(
+            policy.check(&ci, None, Some(&cert(Some("laptop"))), &tokens),
+            AuthOutcome::Forbidden
+        );
+        assert_eq!(
+            policy.check(&ci, None, Some(&cert(None)), &tokens),
+            AuthOutcome::Forbidden
+        );
+    }
+
+    #[test]
+    fn test_invalid_policies() {
+        assert!(policy("[[auth.rules]]\npath = \"/a\"\naccess = \"group:none\"").is_err());
+        assert!(policy("[auth.groups]\nstaff = [\"ghost\"]").is_err());
+        assert!(policy("[auth.users]\nalice = \"sha256:abcd\"").is_err());
+        assert!(policy("[[auth.rules]]\npath = \"/a\"\naccess = \"everyone\"").is_err());
+    }
+
+    #[test]
+    fn test_empty_tokens_never_match() {
+        let auth = BearerAuth::new(vec![String::new()]);
+        assert!(auth.is_empty());
+        assert!(!auth.is_authorized(Some("Bearer ")));
+    }
+}
@@ -0,0 +1,197 @@
+/*
+* Ban module
+*
+* Fail2ban-style automatic bans. Scanners probing for `/wp-login.php`,
+* `/.env` and friends produce bursts of 4xx answers; a client that collects
+* `max_errors` of them within `window` is banned for `ban_for`, and its
+* connections are closed at accept time without an answer.
+*
+* 401 and 429 are not counted: the first is the normal start of an
+* authenticated exchange, the second is already the server pushing back.
+* Behind a trusted proxy the forwarded client is banned, not the proxy.
+*/
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Number of tracked clients above which expired strike windows are pruned.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug)]
+struct Strikes {
+    window_start: Instant,
+    count: u32,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    strikes: HashMap<IpAddr, Strikes>,
+    /// Banned clients and when their ban ends.
+    banned: HashMap<IpAddr, Instant>,
+}
+
+/// Bans clients that produce too many client errors in a short time.
+///
+/// Clones share the same ban list.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::ban::AutoBan;
+/// use std::net::{IpAddr, Ipv4Addr};
+/// use std::time::Duration;
+///
+/// let bans = AutoBan::new(3, Duration::from_secs(60), Duration::from_secs(600));
+/// let scanner = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 9));
+///
+/// for _ in 0..3 {
+///     bans.record(scanner, 404);
+/// }
+/// assert!(bans.is_banned(scanner));
+/// assert!(bans.unban(scanner));
+/// assert!(!bans.is_banned(scanner));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AutoBan {
+    max_errors: u32,
+    window: Duration,
+    ban_for: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl AutoBan {
+    /// Bans a client for `ban_for` once it gets `max_errors` client errors
+    /// within `window`.
+    pub fn new(max_errors: u32, window: Duration, ban_for: Duration) -> Self {
+        Self {
+            max_errors: max_errors.max(1),
+            window,
+            ban_for,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// Counts a response sent to `ip`, banning it if this was one error too
+    /// many.
+    pub fn record(&self, ip: IpAddr, status: u16) {
+        self.record_at(ip, status, Instant::now());
+    }
+
+    /// Returns true if connections from `ip` must be refused.
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.is_banned_at(ip, Instant::now())
+    }
+
+    /// Lifts the ban on `ip` and forgets its errors; returns false if it was
+    /// not banned.
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        let mut state = self.lock();
+        state.strikes.remove(&ip);
+        state
+            .banned
+            .remove(&ip)
+            .is_some_and(|until| until > Instant::now())
+    }
+
+    /// The banned clients and how long each ban still lasts.
+    pub fn banned(&self) -> Vec<(IpAddr, Duration)> {
+        let now = Instant::now();
+        let mut state = self.lock();
+        state.banned.retain(|_, until| *until > now);
+        let mut banned: Vec<_> = state
+            .banned
+            .iter()
+            .map(|(ip, until)| (*ip, until.duration_since(now)))
+            .collect();
+        banned.sort();
+        banned
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record_at(&self, ip: IpAddr, status: u16, now: Instant) {
+        if !(400..500).contains(&status) || status == 401 || status == 429 {
+            return;
+        }
+        let mut state = self.lock();
+        if state.strikes.len() >= PRUNE_THRESHOLD && !state.strikes.contains_key(&ip) {
+            let window = self.window;
+            state
+                .strikes
+                .retain(|_, strikes| now.saturating_duration_since(strikes.window_start) < window);
+        }
+        let strikes = state.strikes.entry(ip).or_insert(Strikes {
+            window_start: now,
+            count: 0,
+        });
+        if now.saturating_duration_since(strikes.window_start) >= self.window {
+            *strikes = Strikes {
+                window_start: now,
+                count: 0,
+            };
+        }
+        strikes.count += 1;
+        if strikes.count >= self.max_errors {
+            state.strikes.remove(&ip);
+            state.banned.insert(ip, now + self.ban_for);
+            warn!(
+                "Banned {} for {:?} after {} client errors",
+                ip, self.ban_for, self.max_errors
+            );
+        }
+    }
+
+    fn is_banned_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut state = self.lock();
+        match state.banned.get(&ip) {
+            Some(until) if *until > now => true,
+            Some(_) => {
+                state.banned.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[test]
+    fn test_burst_bans_until_expiry() {
+        let bans = AutoBan::new(3, Duration::from_secs(10), Duration::from_secs(60));
+        let start = Instant::now();
+
+        // Errors spread over more than the window never add up
+        for i in 0..6 {
+            bans.record_at(ip(1), 404, start + Duration::from_secs(i * 6));
+        }
+        assert!(!bans.is_banned_at(ip(1), start + Duration::from_secs(36)));
+
+        // Successes, auth challenges and throttling are not counted
+        for status in [200, 304, 401, 429, 500] {
+            bans.record_at(ip(2), status, start);
+            bans.record_at(ip(2), status, start);
+            bans.record_at(ip(2), status, start);
+        }
+        assert!(!bans.is_banned_at(ip(2), start));
+
+        for status in [404, 403, 400] {
+            bans.record_at(ip(3), status, start);
+        }
+        assert!(bans.is_banned_at(ip(3), start + Duration::from_secs(59)));
+        assert!(!bans.is_banned_at(ip(3), start + Duration::from_secs(60)));
+        assert!(!bans.unban(ip(3)));
+    }
+}
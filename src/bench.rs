@@ -0,0 +1,336 @@
+/*
+* Load testing module
+*
+* Backs `file-shover bench`: a fixed number of client threads request one URL
+* over and over for a set time, each on a fresh connection, and the run is
+* summarized as throughput and latency percentiles. The target can be any
+* running instance, or one started in-process for the run:
+*
+*   file-shover bench --url http://127.0.0.1:7878/index.html -c 20 -d 30s
+*   file-shover bench --root public --path /index.html
+*
+* Latency is measured from connecting to reading the last byte of the
+* response. Only plain `http://` is supported.
+*/
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Give up on a single request after this long.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A load test against one URL.
+///
+/// # Examples
+///
+/// ```no_run
+/// use file_shover::bench::LoadTest;
+/// use std::time::Duration;
+///
+/// let report = LoadTest::new("http://127.0.0.1:7878/index.html")?
+///     .concurrency(20)
+///     .duration(Duration::from_secs(30))
+///     .run();
+/// println!("{}", report);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct LoadTest {
+    address: SocketAddr,
+    host: String,
+    path: String,
+    concurrency: usize,
+    duration: Duration,
+}
+
+impl LoadTest {
+    /// Targets `url`, with 10 clients for 10 seconds.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ErrorKind::InvalidInput` for URLs that are not `http://`,
+    /// and the lookup error if the host does not resolve.
+    pub fn new(url: &str) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(ErrorKind::InvalidInput, message.to_string());
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid("only http:// URLs can be benchmarked"))?;
+        let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if host.is_empty() {
+            return Err(invalid("the URL has no host"));
+        }
+        let with_port = if host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()))
+        {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        let address = with_port
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| invalid("the host has no address"))?;
+        Ok(Self {
+            address,
+            host: host.to_string(),
+            path: if path.is_empty() { "/" } else { path }.to_string(),
+            concurrency: 10,
+            duration: Duration::from_secs(10),
+        })
+    }
+
+    /// Number of clients requesting at the same time.
+    pub fn concurrency(mut self, clients: usize) -> Self {
+        self.concurrency = clients.max(1);
+        self
+    }
+
+    /// How long to keep sending requests.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Runs the test and summarizes it.
+    pub fn run(&self) -> BenchReport {
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: file-shover-bench\r\nConnection: close\r\n\r\n",
+            self.path, self.host
+        );
+        let started = Instant::now();
+        let deadline = started + self.duration;
+        let results: Vec<Tally> = std::thread::scope(|scope| {
+            let clients: Vec<_> = (0..self.concurrency)
+                .map(|_| scope.spawn(|| self.client(request.as_bytes(), deadline)))
+                .collect();
+            clients
+                .into_iter()
+                .map(|client| client.join().unwrap_or_default())
+                .collect()
+        });
+        let elapsed = started.elapsed();
+
+        let mut total = Tally::default();
+        for tally in results {
+            total.latencies.extend(tally.latencies);
+            total.failed += tally.failed;
+            total.bytes += tally.bytes;
+            for (status, count) in tally.statuses {
+                *total.statuses.entry(status).or_default() += count;
+            }
+        }
+        BenchReport::new(total, elapsed)
+    }
+
+    /// One client's loop: request until the deadline.
+    fn client(&self, request: &[u8], deadline: Instant) -> Tally {
+        let mut tally = Tally::default();
+        let mut buffer = vec![0; 64 * 1024];
+        while Instant::now() < deadline {
+            let started = Instant::now();
+            match self.fetch(request, &mut buffer) {
+                Ok((status, bytes)) => {
+                    tally.latencies.push(started.elapsed());
+                    tally.bytes += bytes;
+                    *tally.statuses.entry(status).or_default() += 1;
+                }
+                Err(_) => tally.failed += 1,
+            }
+        }
+        tally
+    }
+
+    /// Sends one request; returns the status and the bytes received.
+    fn fetch(&self, request: &[u8], buffer: &mut [u8]) -> io::Result<(u16, u64)> {
+        let mut stream = TcpStream::connect_timeout(&self.address, REQUEST_TIMEOUT)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.write_all(request)?;
+
+        let mut status = None;
+        let mut bytes = 0u64;
+        loop {
+            let read = stream.read(buffer)?;
+            if read == 0 {
+                break;
+            }
+            if status.is_none() {
+                // "HTTP/1.1 200 ..." always arrives in the first read
+                status = std::str::from_utf8(&buffer[..read.min(12)])
+                    .ok()
+                    .and_then(|line| line.get(9..12))
+                    .and_then(|code| code.parse().ok());
+            }
+            bytes += read as u64;
+        }
+        let status =
+            status.ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "no HTTP status line"))?;
+        Ok((status, bytes))
+    }
+}
+
+/// What the clients counted.
+#[derive(Debug, Default)]
+struct Tally {
+    latencies: Vec<Duration>,
+    failed: u64,
+    bytes: u64,
+    statuses: BTreeMap<u16, u64>,
+}
+
+/// Results of a [`LoadTest`].
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    /// Responses received, whatever their status.
+    pub requests: u64,
+    /// Requests that failed to connect, send or read.
+    pub failed: u64,
+    /// Bytes received, headers included.
+    pub bytes: u64,
+    pub elapsed_secs: f64,
+    pub requests_per_sec: f64,
+    pub bytes_per_sec: f64,
+    /// Responses per status code.
+    pub statuses: BTreeMap<u16, u64>,
+    pub latency: Latency,
+}
+
+/// Latency distribution, in milliseconds.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Latency {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl BenchReport {
+    fn new(mut tally: Tally, elapsed: Duration) -> Self {
+        tally.latencies.sort_unstable();
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let percentile = |p: usize| {
+            let index = (tally.latencies.len() * p).div_ceil(100).saturating_sub(1);
+            tally.latencies.get(index).copied().map_or(0.0, millis)
+        };
+        let latency = match tally.latencies.last() {
+            Some(&max) => Latency {
+                mean: tally.latencies.iter().copied().map(millis).sum::<f64>()
+                    / tally.latencies.len() as f64,
+                p50: percentile(50),
+                p90: percentile(90),
+                p99: percentile(99),
+                max: millis(max),
+            },
+            None => Latency::default(),
+        };
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+        let requests = tally.latencies.len() as u64;
+        Self {
+            requests,
+            failed: tally.failed,
+            bytes: tally.bytes,
+            elapsed_secs: elapsed.as_secs_f64(),
+            requests_per_sec: requests as f64 / seconds,
+            bytes_per_sec: tally.bytes as f64 / seconds,
+            statuses: tally.statuses,
+            latency,
+        }
+    }
+}
+
+impl std::fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let statuses: Vec<String> = self
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{} x{}", status, count))
+            .collect();
+        writeln!(
+            f,
+            "Requests:   {} in {:.1}s ({} failed)",
+            self.requests, self.elapsed_secs, self.failed
+        )?;
+        writeln!(f, "Statuses:   {}", statuses.join(", "))?;
+        writeln!(
+            f,
+            "Throughput: {:.1} req/s, {}/s",
+            self.requests_per_sec,
+            crate::units::format_size(self.bytes_per_sec as u64)
+        )?;
+        write!(
+            f,
+            "Latency:    mean {:.2}ms, p50 {:.2}ms, p90 {:.2}ms, p99 {:.2}ms, max {:.2}ms",
+            self.latency.mean,
+            self.latency.p50,
+            self.latency.p90,
+            self.latency.p99,
+            self.latency.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Request, Response};
+    use crate::Server;
+
+    #[test]
+    fn test_urls() {
+        let test = LoadTest::new("http://127.0.0.1:8080/a/b.css?v=1").unwrap();
+        assert_eq!(test.address, "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(test.host, "127.0.0.1:8080");
+        assert_eq!(test.path, "/a/b.css?v=1");
+        assert_eq!(LoadTest::new("http://127.0.0.1").unwrap().path, "/");
+        assert_eq!(
+            LoadTest::new("http://127.0.0.1").unwrap().address.port(),
+            80
+        );
+        assert!(LoadTest::new("https://127.0.0.1/").is_err());
+        assert!(LoadTest::new("http:///index.html").is_err());
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let tally = Tally {
+            latencies: (1..=100).rev().map(Duration::from_millis).collect(),
+            ..Tally::default()
+        };
+        let report = BenchReport::new(tally, Duration::from_secs(2));
+        assert_eq!(report.requests, 100);
+        assert_eq!(report.requests_per_sec, 50.0);
+        assert_eq!(report.latency.p50, 50.0);
+        assert_eq!(report.latency.p99, 99.0);
+        assert_eq!(report.latency.max, 100.0);
+    }
+
+    #[test]
+    fn test_against_a_server() {
+        let server = Server::builder()
+            .bind("127.0.0.1:0")
+            .threads(2)
+            .route("/ping", |_req: &Request| Response::new().body("pong"))
+            .build()
+            .unwrap();
+        let url = format!("http://{}/ping", server.local_addr().unwrap());
+        let shutdown = server.shutdown_handle();
+        let running = std::thread::spawn(move || server.run());
+
+        let report = LoadTest::new(&url)
+            .unwrap()
+            .concurrency(2)
+            .duration(Duration::from_millis(200))
+            .run();
+        assert!(report.requests > 0);
+        assert_eq!(report.failed, 0);
+        assert_eq!(report.statuses.get(&200), Some(&report.requests));
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,591 @@
+/*
+* CGI module
+*
+* Opt-in execution of CGI/1.1 scripts (RFC 3875) from one directory, mounted
+* under a URL prefix:
+*
+*   --cgi-dir ./cgi-bin                 /cgi-bin/hello.sh/extra?x=1
+*                                       runs ./cgi-bin/hello.sh with
+*                                       SCRIPT_NAME=/cgi-bin/hello.sh,
+*                                       PATH_INFO=/extra, QUERY_STRING=x=1
+*
+* Only executable regular files directly inside the directory run; dot files
+* and subdirectories never do. Each request spawns the script with a cleared
+* environment holding the standard CGI variables, the request headers as
+* `HTTP_*` and the server's `PATH`. The request body is read in full (up to
+* `MAX_BODY`) and written to the script's stdin; `Authorization` and `Proxy`
+* are not passed on.
+*
+* The script's header block is parsed (`Status:` sets the status, a
+* `Location:` without one answers 302) and the rest of its stdout streamed to
+* the client as it is produced. Lines on stderr are logged as warnings. A
+* script still running after the timeout is killed, as is one whose client
+* went away.
+*/
+
+use crate::message::{
+    Body, HttpMethod, Request, Response, StatusCode, DEFAULT_BAD_GATEWAY_BODY,
+    DEFAULT_INTERNAL_ERROR_BODY,
+};
+use crate::upload::{self, UploadError};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Largest request body passed to a script.
+pub const MAX_BODY: u64 = 16 * 1024 * 1024;
+
+/// Longest header block accepted from a script.
+const MAX_HEADER_LENGTH: u64 = 64 * 1024;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Request headers never exposed to scripts: credentials, and `Proxy`, which
+/// would become the `HTTP_PROXY` variable HTTP clients honor ("httpoxy").
+const WITHHELD_HEADERS: &[&str] = &[
+    "Authorization",
+    "Proxy",
+    "Proxy-Authorization",
+    "Content-Type",
+    "Content-Length",
+    "Transfer-Encoding",
+    "Connection",
+];
+
+/// A directory of CGI scripts mounted under a URL prefix.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::cgi::Cgi;
+/// use std::time::Duration;
+///
+/// let cgi = Cgi::new("/srv/cgi-bin").prefix("/scripts").timeout(Duration::from_secs(10));
+/// assert_eq!(cgi.prefix_path(), "/scripts");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cgi {
+    prefix: String,
+    dir: PathBuf,
+    timeout: Duration,
+}
+
+/// A script matched by [`Cgi::find`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    /// The executable.
+    pub path: PathBuf,
+    /// URL path of the script, e.g. `/cgi-bin/hello.sh`.
+    pub name: String,
+    /// The request path after the script name, empty or starting with `/`.
+    pub path_info: String,
+}
+
+impl Cgi {
+    /// Runs the scripts in `dir` under `/cgi-bin`, killing them after 30
+    /// seconds.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            prefix: "/cgi-bin".to_string(),
+            dir: dir.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Mounts the scripts under `prefix` instead of `/cgi-bin`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        let prefix = prefix.into();
+        self.prefix = format!("/{}", prefix.trim_matches('/'));
+        self
+    }
+
+    /// Kills scripts still running after `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The URL prefix the scripts are mounted under.
+    pub fn prefix_path(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The script directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns whether `path` is under the prefix, whether or not it names
+    /// a script.
+    pub fn matches(&self, path: &str) -> bool {
+        path.strip_prefix(self.prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    /// Resolves a request path to the script it runs, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::cgi::Cgi;
+    ///
+    /// let cgi = Cgi::new("/nonexistent");
+    /// assert_eq!(cgi.find("/cgi-bin/missing.sh/x"), None);
+    /// assert_eq!(cgi.find("/static/app.js"), None);
+    /// ```
+    pub fn find(&self, path: &str) -> Option<Script> {
+        let rest = path.strip_prefix(self.prefix.as_str())?.strip_prefix('/')?;
+        let (file, path_info) = match rest.find('/') {
+            Some(end) => (&rest[..end], &rest[end..]),
+            None => (rest, ""),
+        };
+        if file.is_empty() || file.starts_with('.') {
+            return None;
+        }
+        let script = self.dir.join(file);
+        let metadata = std::fs::metadata(&script).ok()?;
+        if !metadata.is_file() || !is_executable(&metadata) {
+            return None;
+        }
+        Some(Script {
+            path: script,
+            name: format!("{}/{}", self.prefix, file),
+            path_info: path_info.to_string(),
+        })
+    }
+
+    /// Runs `script` for `req`, reading the request body from `conn`.
+    ///
+    /// The response streams the script's output; errors starting the script
+    /// or reading its headers answer 500 (502 for invalid output).
+    pub fn run<C: BufRead + Write>(
+        &self,
+        req: &Request,
+        script: &Script,
+        client_ip: IpAddr,
+        scheme: &str,
+        conn: &mut C,
+    ) -> Response {
+        let body = match read_body(req, conn) {
+            Ok(body) => body,
+            Err(e) => {
+                debug!("Failed to read CGI request body: {}", e);
+                return e.response();
+            }
+        };
+        let mut command = Command::new(&script.path);
+        command
+            .env_clear()
+            .envs(environment(req, script, &body, client_ip, scheme))
+            .env(
+                "PATH",
+                std::env::var_os("PATH").unwrap_or_else(|| DEFAULT_PATH.into()),
+            )
+            .current_dir(script.path.parent().unwrap_or(&self.dir))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                warn!("Failed to run {}: {}", script.path.display(), e);
+                return Response::error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    DEFAULT_INTERNAL_ERROR_BODY,
+                );
+            }
+        };
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let output = Output::watch(child, self.timeout);
+        if let Some(mut stdin) = stdin {
+            std::thread::spawn(move || {
+                // A script that ignores its input closes the pipe early
+                let _ = stdin.write_all(&body);
+            });
+        }
+        if let Some(stderr) = stderr {
+            let name = script.name.clone();
+            std::thread::spawn(move || {
+                for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                    warn!("{}: {}", name, line);
+                }
+            });
+        }
+        let Some(stdout) = stdout else {
+            return Response::error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                DEFAULT_INTERNAL_ERROR_BODY,
+            );
+        };
+        let mut stdout = BufReader::new(stdout);
+        let response = match read_headers(&mut stdout) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Invalid output from {}: {}", script.name, e);
+                return Response::error(StatusCode::BAD_GATEWAY, DEFAULT_BAD_GATEWAY_BODY);
+            }
+        };
+        if req.method == HttpMethod::HEAD {
+            return response;
+        }
+        let output = output.reading(stdout);
+        match response
+            .headers
+            .get("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok())
+        {
+            Some(length) => response.body(Body::reader(output.take(length))),
+            None => response.body(Body::chunked(output)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &std::fs::Metadata) -> bool {
+    true
+}
+
+/// Reads the whole request body, answering `100 Continue` first if asked.
+pub(crate) fn read_body<C: BufRead + Write>(
+    req: &Request,
+    conn: &mut C,
+) -> Result<Vec<u8>, UploadError> {
+    let chunked = req
+        .header("Transfer-Encoding")
+        .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+    let length = match req.header("Content-Length") {
+        Some(length) if !chunked => Some(
+            length
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| UploadError::BadBody(io::ErrorKind::InvalidData.into()))?,
+        ),
+        _ => None,
+    };
+    if length.is_some_and(|length| length > MAX_BODY) {
+        return Err(UploadError::TooLarge);
+    }
+    let mut body = Vec::new();
+    if chunked || length.is_some_and(|length| length > 0) {
+        upload::continue_if_expected(req, conn).map_err(UploadError::BadBody)?;
+    }
+    if chunked {
+        upload::copy_chunked(conn, &mut body, MAX_BODY)?;
+    } else if let Some(length) = length {
+        upload::copy_exact(conn, &mut body, length)?;
+    }
+    Ok(body)
+}
+
+/// The CGI/1.1 meta-variables for a request.
+pub(crate) fn environment(
+    req: &Request,
+    script: &Script,
+    body: &[u8],
+    client_ip: IpAddr,
+    scheme: &str,
+) -> Vec<(String, String)> {
+    let host = req.header("Host").unwrap_or("localhost");
+    let (server_name, server_port) = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => (name, port.to_string()),
+        _ if scheme == "https" => (host, "443".to_string()),
+        _ => (host, "80".to_string()),
+    };
+    let mut env = vec![
+        ("GATEWAY_INTERFACE", "CGI/1.1".to_string()),
+        (
+            "SERVER_SOFTWARE",
+            format!("file-shover/{}", env!("CARGO_PKG_VERSION")),
+        ),
+        ("SERVER_PROTOCOL", req.http_version.clone()),
+        ("SERVER_NAME", server_name.to_string()),
+        ("SERVER_PORT", server_port),
+        ("REQUEST_METHOD", req.method.to_string()),
+        ("REQUEST_SCHEME", scheme.to_string()),
+        ("REQUEST_URI", req.target.to_string()),
+        ("SCRIPT_NAME", script.name.clone()),
+        ("SCRIPT_FILENAME", script.path.display().to_string()),
+        ("PATH_INFO", script.path_info.clone()),
+        ("QUERY_STRING", req.query().unwrap_or_default().to_string()),
+        ("REMOTE_ADDR", client_ip.to_string()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect::<Vec<_>>();
+    if scheme == "https" {
+        env.push(("HTTPS".to_string(), "on".to_string()));
+    }
+    if !body.is_empty() || req.header("Content-Length").is_some() {
+        env.push(("CONTENT_LENGTH".to_string(), body.len().to_string()));
+    }
+    if let Some(content_type) = req.header("Content-Type") {
+        env.push(("CONTENT_TYPE".to_string(), content_type.to_string()));
+    }
+    for (name, value) in &req.headers {
+        if WITHHELD_HEADERS
+            .iter()
+            .any(|withheld| withheld.eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+        let name = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+        env.push((name, value.clone()));
+    }
+    env
+}
+
+/// Parses the header block of a script's output into a response.
+pub(crate) fn read_headers<R: BufRead>(stdout: &mut R) -> io::Result<Response> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut response = Response::new();
+    let mut status = None;
+    let mut limited = stdout.take(MAX_HEADER_LENGTH);
+    loop {
+        let mut line = String::new();
+        if limited.read_line(&mut line)? == 0 {
+            return Err(invalid("output ended before the end of the headers"));
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid("malformed header line"))?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("Status") {
+            let code = value.split_whitespace().next().unwrap_or_default();
+            status = Some(
+                code.parse::<u16>()
+                    .ok()
+                    .and_then(|code| StatusCode::from_u16(code).ok())
+                    .ok_or_else(|| invalid("invalid Status header"))?,
+            );
+        } else {
+            if response.headers.contains_key(name) {
+                debug!("Repeated {} header from script; keeping the last", name);
+            }
+            response.headers.insert(name.to_string(), value.to_string());
+        }
+    }
+    let redirect = response
+        .headers
+        .keys()
+        .any(|name| name.eq_ignore_ascii_case("Location"));
+    let has_type = response
+        .headers
+        .keys()
+        .any(|name| name.eq_ignore_ascii_case("Content-Type"));
+    if !redirect && !has_type && status.is_none() {
+        return Err(invalid("no Content-Type, Location or Status header"));
+    }
+    response.status = status.unwrap_or(if redirect {
+        StatusCode::FOUND
+    } else {
+        StatusCode::OK
+    });
+    Ok(response)
+}
+
+/// The running script: kills it on timeout and when dropped.
+struct Output {
+    child: Arc<Mutex<Child>>,
+    /// Dropping it stops the watchdog.
+    _done: Sender<()>,
+    stdout: Option<BufReader<ChildStdout>>,
+}
+
+impl Output {
+    fn watch(child: Child, timeout: Duration) -> Self {
+        let child = Arc::new(Mutex::new(child));
+        let (done, finished) = mpsc::channel::<()>();
+        let watched = Arc::clone(&child);
+        std::thread::spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(timeout) {
+                let mut child = watched.lock().unwrap_or_else(|e| e.into_inner());
+                if let Ok(None) = child.try_wait() {
+                    warn!("Killing CGI script after {:?}", timeout);
+                    let _ = child.kill();
+                }
+            }
+        });
+        Self {
+            child,
+            _done: done,
+            stdout: None,
+        }
+    }
+
+    fn reading(mut self, stdout: BufReader<ChildStdout>) -> Self {
+        self.stdout = Some(stdout);
+        self
+    }
+}
+
+impl Read for Output {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.stdout {
+            Some(stdout) => stdout.read(buf),
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        self.stdout = None;
+        let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        if let Ok(None) = child.try_wait() {
+            let _ = child.kill();
+        }
+        let _ = child.wait();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn script_dir(name: &str, source: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("file-shover-cgi-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("run.sh");
+        std::fs::write(&script, source).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        dir
+    }
+
+    fn run(cgi: &Cgi, raw: &[u8]) -> Response {
+        let mut conn = io::Cursor::new(raw.to_vec());
+        let req = Request::from_reader(&mut conn).unwrap();
+        let script = cgi.find(req.path()).unwrap();
+        let mut conn = Duplex(conn, Vec::new());
+        cgi.run(
+            &req,
+            &script,
+            "192.0.2.7".parse().unwrap(),
+            "http",
+            &mut conn,
+        )
+    }
+
+    /// Reads from a cursor, discards writes.
+    struct Duplex(io::Cursor<Vec<u8>>, Vec<u8>);
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl BufRead for Duplex {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            self.0.fill_buf()
+        }
+        fn consume(&mut self, amount: usize) {
+            self.0.consume(amount)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let dir = script_dir("find", "#!/bin/sh\n");
+        std::fs::write(dir.join("data.txt"), "not executable").unwrap();
+        let cgi = Cgi::new(&dir).prefix("/cgi-bin/");
+
+        let script = cgi.find("/cgi-bin/run.sh/a/b").unwrap();
+        assert_eq!(script.path, dir.join("run.sh"));
+        assert_eq!(script.name, "/cgi-bin/run.sh");
+        assert_eq!(script.path_info, "/a/b");
+        assert_eq!(cgi.find("/cgi-bin/run.sh").unwrap().path_info, "");
+        assert_eq!(cgi.find("/cgi-bin/data.txt"), None);
+        assert_eq!(cgi.find("/cgi-bin/"), None);
+        assert_eq!(cgi.find("/cgi-binary/run.sh"), None);
+        assert!(cgi.matches("/cgi-bin"));
+        assert!(!cgi.matches("/cgi-binary"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_passes_environment_and_body() {
+        let dir = script_dir(
+            "run",
+            "#!/bin/sh\n\
+             echo 'Status: 201 Created'\n\
+             echo 'Content-Type: text/plain'\n\
+             echo\n\
+             echo \"$REQUEST_METHOD $SCRIPT_NAME $PATH_INFO $QUERY_STRING $REMOTE_ADDR\"\n\
+             echo \"$CONTENT_LENGTH $HTTP_X_TOKEN ${HTTP_AUTHORIZATION:-none}\"\n\
+             cat\n",
+        );
+        let cgi = Cgi::new(&dir);
+        let response = run(
+            &cgi,
+            b"POST /cgi-bin/run.sh/extra?x=1 HTTP/1.1\r\nHost: example.com\r\n\
+              X-Token: abc\r\nAuthorization: Bearer secret\r\nContent-Length: 5\r\n\r\nhello",
+        );
+        assert_eq!(response.status, StatusCode::CREATED);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some(&"text/plain".to_string())
+        );
+        let mut output = String::new();
+        response
+            .body
+            .into_reader()
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(
+            output,
+            "POST /cgi-bin/run.sh /extra x=1 192.0.2.7\n5 abc none\nhello"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_rejects_invalid_output_and_redirects() {
+        let dir = script_dir("invalid", "#!/bin/sh\necho 'no headers here'\n");
+        let cgi = Cgi::new(&dir);
+        let response = run(&cgi, b"GET /cgi-bin/run.sh HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let dir = script_dir(
+            "redirect",
+            "#!/bin/sh\nprintf 'Location: /done\\r\\n\\r\\n'\n",
+        );
+        let cgi = Cgi::new(&dir);
+        let response = run(&cgi, b"GET /cgi-bin/run.sh HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status, StatusCode::FOUND);
+        assert_eq!(response.headers.get("Location"), Some(&"/done".to_string()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,440 @@
+/*
+* Root check module
+*
+* Walks a root directory before it is served and reports what would go
+* wrong or leak once it is:
+*
+*   unreadable        -> files or directories the server cannot open (404/500)
+*   escaping-symlink  -> links to outside the root, served like any file
+*   broken-symlink    -> links to nothing
+*   world-writable    -> content anyone on the host can replace
+*   large-directory   -> listings too big to render or browse
+*   unsafe-name       -> names that break or confuse URLs ('?', '#', '%',
+*                        control characters, invalid UTF-8, edge whitespace)
+*
+* `file-shover audit` prints the findings (or JSON for CI) and `serve
+* --self-check` logs them at startup. Symlinked directories inside the root
+* are not walked twice; their targets are checked where they live.
+*
+* Before any of that, `serve` refuses a root it could not serve at all
+* (missing, not a directory, unreadable by the user the server runs as),
+* rather than answering every request with 404s or 500s, and warns about
+* roots that would expose far more than intended, such as `/` or a home
+* directory.
+*/
+
+use crate::config::ConfigError;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directories with more entries than this are reported by default.
+const DEFAULT_MAX_DIR_ENTRIES: usize = 10_000;
+
+/// Characters in names that links must escape, and often don't.
+const URL_SPECIAL: &[char] = &['?', '#', '%', '\\'];
+
+/// A check of everything under a root directory.
+///
+/// # Examples
+///
+/// ```no_run
+/// use file_shover::check::RootCheck;
+///
+/// let report = RootCheck::new("public").max_dir_entries(5000).run()?;
+/// for finding in &report.findings {
+///     println!("{}", finding);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct RootCheck {
+    root: PathBuf,
+    max_dir_entries: usize,
+}
+
+/// What is wrong with a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Problem {
+    Unreadable,
+    EscapingSymlink,
+    BrokenSymlink,
+    WorldWritable,
+    LargeDirectory,
+    UnsafeName,
+}
+
+/// One problem found under the root.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    /// URL path of the entry, e.g. `/docs/a b?.html`.
+    pub path: String,
+    pub problem: Problem,
+    pub detail: String,
+}
+
+/// Results of a [`RootCheck`].
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckReport {
+    pub root: PathBuf,
+    pub files: u64,
+    pub directories: u64,
+    pub findings: Vec<Finding>,
+}
+
+impl RootCheck {
+    /// Checks everything under `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            max_dir_entries: DEFAULT_MAX_DIR_ENTRIES,
+        }
+    }
+
+    /// Reports directories with more than `entries` entries (default 10000).
+    pub fn max_dir_entries(mut self, entries: usize) -> Self {
+        self.max_dir_entries = entries;
+        self
+    }
+
+    /// Walks the root and collects findings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the root itself cannot be resolved; problems
+    /// below it are findings instead.
+    pub fn run(&self) -> io::Result<CheckReport> {
+        let root = fs::canonicalize(&self.root)?;
+        let mut report = CheckReport {
+            root: self.root.clone(),
+            files: 0,
+            directories: 0,
+            findings: Vec::new(),
+        };
+        let mut pending = vec![(root.clone(), String::new())];
+        while let Some((dir, url)) = pending.pop() {
+            report.directories += 1;
+            let shown = if url.is_empty() { "/" } else { &url };
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    report.add(shown, Problem::Unreadable, e.to_string());
+                    continue;
+                }
+            };
+            let mut count = 0;
+            for entry in entries {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        report.add(shown, Problem::Unreadable, e.to_string());
+                        continue;
+                    }
+                };
+                count += 1;
+                let name = entry.file_name();
+                let entry_url = format!("{}/{}", url, name.to_string_lossy());
+                match name.to_str() {
+                    Some(name) => {
+                        if let Some(detail) = unsafe_name(name) {
+                            report.add(&entry_url, Problem::UnsafeName, detail);
+                        }
+                    }
+                    None => report.add(
+                        &entry_url,
+                        Problem::UnsafeName,
+                        "not valid UTF-8, so no URL reaches it".to_string(),
+                    ),
+                }
+                if let Some(subdir) =
+                    self.check_entry(&root, &entry.path(), &entry_url, &mut report)
+                {
+                    pending.push((subdir, entry_url));
+                }
+            }
+            if count > self.max_dir_entries {
+                report.add(shown, Problem::LargeDirectory, format!("{} entries", count));
+            }
+        }
+        report.findings.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(report)
+    }
+
+    /// Checks one entry; returns it if it is a directory to walk.
+    fn check_entry(
+        &self,
+        root: &Path,
+        path: &Path,
+        url: &str,
+        report: &mut CheckReport,
+    ) -> Option<PathBuf> {
+        let link = match fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(e) => {
+                report.add(url, Problem::Unreadable, e.to_string());
+                return None;
+            }
+        };
+        let is_link = link.file_type().is_symlink();
+        if is_link {
+            match fs::canonicalize(path) {
+                Ok(target) if !target.starts_with(root) => {
+                    report.add(
+                        url,
+                        Problem::EscapingSymlink,
+                        format!("points to {}", target.display()),
+                    );
+                    return None;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    report.add(url, Problem::BrokenSymlink, e.to_string());
+                    return None;
+                }
+            }
+        }
+        let meta = match fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(e) => {
+                report.add(url, Problem::Unreadable, e.to_string());
+                return None;
+            }
+        };
+        if world_writable(&meta) {
+            report.add(
+                url,
+                Problem::WorldWritable,
+                "anyone may modify it".to_string(),
+            );
+        }
+        if meta.is_dir() {
+            return (!is_link).then(|| path.to_path_buf());
+        }
+        report.files += 1;
+        if let Err(e) = fs::File::open(path) {
+            report.add(url, Problem::Unreadable, e.to_string());
+        }
+        None
+    }
+}
+
+impl CheckReport {
+    fn add(&mut self, path: &str, problem: Problem, detail: String) {
+        self.findings.push(Finding {
+            path: path.to_string(),
+            problem,
+            detail,
+        });
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Problem::Unreadable => "unreadable",
+            Problem::EscapingSymlink => "escaping-symlink",
+            Problem::BrokenSymlink => "broken-symlink",
+            Problem::WorldWritable => "world-writable",
+            Problem::LargeDirectory => "large-directory",
+            Problem::UnsafeName => "unsafe-name",
+        })
+    }
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:<16} {}: {}", self.problem, self.path, self.detail)
+    }
+}
+
+/// Checks that `root` can be served: it exists, is a directory (or a file
+/// when `allow_file` is set) and the server can read it. Returns warnings
+/// about roots that expose more than a site usually should.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::check::validate_root;
+/// use std::path::Path;
+///
+/// assert!(validate_root(Path::new("test-sites"), false)?.is_empty());
+/// assert!(validate_root(Path::new("test-sites/missing"), false).is_err());
+/// assert!(validate_root(Path::new("test-sites/one-file/index.html"), false).is_err());
+/// assert!(!validate_root(Path::new("/"), false)?.is_empty());
+/// # Ok::<(), file_shover::config::ConfigError>(())
+/// ```
+///
+/// # Errors
+///
+/// Returns `ConfigError::Invalid` with what to fix when the root cannot be
+/// served.
+pub fn validate_root(root: &Path, allow_file: bool) -> Result<Vec<String>, ConfigError> {
+    let shown = root.display();
+    let unusable = |reason: String| ConfigError::Invalid(format!("root {} {}", shown, reason));
+    let unreadable = |e: io::Error| {
+        unusable(format!(
+            "is not readable by the user the server runs as ({}); grant it read permission, and execute permission on directories",
+            e
+        ))
+    };
+    let meta = fs::metadata(root).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => {
+            unusable("does not exist; check the --root path or create it".to_string())
+        }
+        _ => unreadable(e),
+    })?;
+    if meta.is_dir() {
+        let mut entries = fs::read_dir(root).map_err(unreadable)?;
+        // Listing needs read permission, opening what is listed needs execute
+        if let Some(Ok(entry)) = entries.next() {
+            fs::symlink_metadata(entry.path()).map_err(unreadable)?;
+        }
+    } else if meta.is_file() {
+        if !allow_file {
+            return Err(unusable(
+                "is a file; only a single --root may be a file".to_string(),
+            ));
+        }
+        fs::File::open(root).map_err(unreadable)?;
+    } else {
+        return Err(unusable("is neither a directory nor a file".to_string()));
+    }
+
+    let mut warnings = Vec::new();
+    let resolved = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    if resolved.parent().is_none() {
+        warnings.push(format!(
+            "root {} is the filesystem root; every file the server can read is served",
+            shown
+        ));
+    } else if is_home_directory(&resolved) {
+        warnings.push(format!(
+            "root {} is a home directory; private files such as .ssh keys may be served",
+            shown
+        ));
+    }
+    Ok(warnings)
+}
+
+/// Whether `dir` is `$HOME` or a directory directly in `/home`, `/Users`,
+/// or `/root`.
+fn is_home_directory(dir: &Path) -> bool {
+    let home = std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .and_then(|home| fs::canonicalize(home).ok());
+    home.as_deref() == Some(dir)
+        || dir == Path::new("/root")
+        || dir
+            .parent()
+            .is_some_and(|parent| parent == Path::new("/home") || parent == Path::new("/Users"))
+}
+
+/// Why `name` makes a poor URL segment, if it does.
+fn unsafe_name(name: &str) -> Option<String> {
+    if name.chars().any(char::is_control) {
+        return Some("contains control characters".to_string());
+    }
+    if name.starts_with(char::is_whitespace) || name.ends_with(char::is_whitespace) {
+        return Some("starts or ends with whitespace".to_string());
+    }
+    name.chars()
+        .find(|c| URL_SPECIAL.contains(c))
+        .map(|c| format!("contains '{}', which links must percent-encode", c))
+}
+
+#[cfg(unix)]
+fn world_writable(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o002 != 0
+}
+
+#[cfg(not(unix))]
+fn world_writable(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsafe_names() {
+        assert_eq!(unsafe_name("index.html"), None);
+        assert_eq!(unsafe_name("photo 1.jpg"), None);
+        assert_eq!(unsafe_name("café.txt"), None);
+        assert!(unsafe_name("what?.html").unwrap().contains("'?'"));
+        assert!(unsafe_name("100%.txt").is_some());
+        assert!(unsafe_name("notes.txt ").is_some());
+        assert!(unsafe_name("a\nb").is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_findings() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let root = std::env::temp_dir().join(format!("file-shover-check-{}", std::process::id()));
+        fs::create_dir_all(root.join("big")).unwrap();
+        fs::write(root.join("index.html"), "hi").unwrap();
+        fs::write(root.join("a#b.html"), "hi").unwrap();
+        for i in 0..10 {
+            fs::write(root.join("big").join(i.to_string()), "").unwrap();
+        }
+        fs::write(root.join("open.txt"), "").unwrap();
+        fs::set_permissions(root.join("open.txt"), fs::Permissions::from_mode(0o666)).unwrap();
+        symlink("/etc", root.join("etc")).unwrap();
+        symlink(root.join("missing"), root.join("dangling")).unwrap();
+        symlink(root.join("big"), root.join("alias")).unwrap();
+
+        let report = RootCheck::new(&root).max_dir_entries(8).run().unwrap();
+        let found: Vec<(&str, Problem)> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.path.as_str(), finding.problem))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("/a#b.html", Problem::UnsafeName),
+                ("/big", Problem::LargeDirectory),
+                ("/dangling", Problem::BrokenSymlink),
+                ("/etc", Problem::EscapingSymlink),
+                ("/open.txt", Problem::WorldWritable),
+            ]
+        );
+        // The link to big/ is not walked a second time
+        assert_eq!(report.files, 13);
+        assert_eq!(report.directories, 2);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_validate_root() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("file-shover-root-{}", std::process::id()));
+        fs::create_dir_all(root.join("site")).unwrap();
+        fs::write(root.join("site/index.html"), "hi").unwrap();
+        assert!(validate_root(&root.join("site"), false).unwrap().is_empty());
+        assert!(validate_root(&root.join("site/index.html"), true).is_ok());
+
+        let error = validate_root(&root.join("site/index.html"), false).unwrap_err();
+        assert!(error.to_string().contains("is a file"));
+        let error = validate_root(&root.join("missing"), false).unwrap_err();
+        assert!(error.to_string().contains("does not exist"));
+
+        // Permissions do not apply to root, who can read anything
+        fs::set_permissions(root.join("site"), fs::Permissions::from_mode(0o000)).unwrap();
+        if fs::read_dir(root.join("site")).is_err() {
+            let error = validate_root(&root.join("site"), false).unwrap_err();
+            assert!(error.to_string().contains("not readable"));
+        }
+        fs::set_permissions(root.join("site"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert!(validate_root(Path::new("/"), false).unwrap()[0].contains("filesystem root"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
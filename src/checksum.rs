@@ -0,0 +1,237 @@
+/*
+* Checksum module
+*
+* SHA-256 digests of served files, for `ETag` and `Content-Digest`
+* (RFC 9530) headers and `.sha256` sidecar files generated on demand.
+*
+* Hashing a large artifact means reading all of it, so digests are cached
+* per path and only recomputed when the file's size or modification time
+* changes.
+*/
+
+use crate::files::{is_within, FileMetadata, FileTree};
+use crate::flight::SingleFlight;
+use crate::hex::to_hex;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Paths remembered before the cache starts forgetting entries.
+const DEFAULT_CAPACITY: usize = 10_000;
+
+/// A SHA-256 digest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sha256Digest(pub [u8; 32]);
+
+impl Sha256Digest {
+    /// Hashes everything `reader` yields.
+    ///
+    /// # Errors
+    ///
+    /// Returns read errors.
+    pub fn of_reader(mut reader: impl Read) -> io::Result<Self> {
+        let mut hasher = Sha256::new();
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+        }
+        Ok(Self(hasher.finalize().into()))
+    }
+
+    /// Lowercase hex, as printed by `sha256sum`.
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.0)
+    }
+
+    /// Strong entity tag: the quoted hex digest.
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.to_hex())
+    }
+
+    /// `Content-Digest` header value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::checksum::Sha256Digest;
+    ///
+    /// let digest = Sha256Digest::of_reader(&b"hello"[..]).unwrap();
+    /// assert_eq!(
+    ///     digest.content_digest(),
+    ///     "sha-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:"
+    /// );
+    /// ```
+    pub fn content_digest(&self) -> String {
+        format!(
+            "sha-256=:{}:",
+            base64::engine::general_purpose::STANDARD.encode(self.0)
+        )
+    }
+
+    /// A `sha256sum`-compatible line for `name`.
+    pub fn sidecar(&self, name: &str) -> String {
+        format!("{}  {}\n", self.to_hex(), name)
+    }
+}
+
+/// Size and modification time a cached digest was computed for.
+type Stamp = (u64, Option<SystemTime>);
+
+/// Digests of files in a tree, cached by path.
+pub struct ChecksumCache {
+    entries: Mutex<HashMap<String, (Stamp, Sha256Digest)>>,
+    capacity: usize,
+    flights: SingleFlight,
+}
+
+impl Default for ChecksumCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl ChecksumCache {
+    /// Creates a cache remembering up to `capacity` paths.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            capacity,
+            flights: SingleFlight::default(),
+        }
+    }
+
+    /// Returns the digest of the file at `path`, hashing it unless the
+    /// cached digest was computed for the same size and modification time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::checksum::ChecksumCache;
+    /// use file_shover::files::{FileTree, MemorySource};
+    ///
+    /// let tree = FileTree::from_source(MemorySource::new().with("a.txt", "hello"));
+    /// let cache = ChecksumCache::default();
+    /// let metadata = tree.metadata("/a.txt").unwrap();
+    /// let digest = cache.sha256(&tree, "/a.txt", &metadata).unwrap();
+    /// assert!(digest.to_hex().starts_with("2cf24dba"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns errors from opening or reading the file.
+    pub fn sha256(
+        &self,
+        tree: &FileTree,
+        path: &str,
+        metadata: &FileMetadata,
+    ) -> io::Result<Sha256Digest> {
+        let stamp = (metadata.len, metadata.modified);
+        let cached = || match self.entries.lock().unwrap().get(path) {
+            Some((cached, digest)) if *cached == stamp => Some(*digest),
+            _ => None,
+        };
+        // Hash without holding the lock, and only once however many
+        // requests miss at the same time
+        self.flights.run(path, cached, || {
+            let digest = Sha256Digest::of_reader(tree.get_reader(path)?.reader)?;
+            let mut entries = self.entries.lock().unwrap();
+            if entries.len() >= self.capacity && !entries.contains_key(path) {
+                if let Some(evicted) = entries.keys().next().cloned() {
+                    entries.remove(&evicted);
+                }
+            }
+            entries.insert(path.to_string(), (stamp, digest));
+            Ok(digest)
+        })
+    }
+
+    /// Forgets the digests of `path` and the files below it, or all of them
+    /// for `None`, and returns how many were dropped.
+    pub fn purge(&self, path: Option<&str>) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|cached, _| !is_within(cached, path.unwrap_or("")));
+        before - entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn test_recomputed_on_change() {
+        let dir = std::env::temp_dir().join(format!("file-shover-sum-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.bin");
+        fs::write(&file, "one").unwrap();
+        let tree = FileTree::new(dir.clone());
+        let cache = ChecksumCache::new(1);
+
+        let first = cache
+            .sha256(&tree, "/a.bin", &tree.metadata("/a.bin").unwrap())
+            .unwrap();
+        assert_eq!(first, Sha256Digest::of_reader(&b"one"[..]).unwrap());
+
+        // Same size, later mtime
+        fs::write(&file, "two").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let second = cache
+            .sha256(&tree, "/a.bin", &tree.metadata("/a.bin").unwrap())
+            .unwrap();
+        assert_eq!(second, Sha256Digest::of_reader(&b"two"[..]).unwrap());
+
+        // A stale stamp is served from the cache without reading the file
+        let stale = FileMetadata {
+            len: 3,
+            is_dir: false,
+            modified: Some(later),
+        };
+        fs::write(&file, "three").unwrap();
+        assert_eq!(cache.sha256(&tree, "/a.bin", &stale).unwrap(), second);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_purge() {
+        let tree = FileTree::from_source(
+            crate::files::MemorySource::new()
+                .with("assets/app.js", "a")
+                .with("assets/app.css", "b")
+                .with("assets-old/app.js", "c"),
+        );
+        let cache = ChecksumCache::default();
+        for path in ["/assets/app.js", "/assets/app.css", "/assets-old/app.js"] {
+            cache
+                .sha256(&tree, path, &tree.metadata(path).unwrap())
+                .unwrap();
+        }
+        assert_eq!(cache.purge(Some("/assets/app.js")), 1);
+        assert_eq!(cache.purge(Some("/assets/")), 1);
+        assert_eq!(cache.purge(None), 1);
+        assert_eq!(cache.purge(None), 0);
+    }
+
+    #[test]
+    fn test_header_formats() {
+        let digest = Sha256Digest::of_reader(&b""[..]).unwrap();
+        let hex = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(digest.etag(), format!("\"{}\"", hex));
+        assert_eq!(digest.sidecar("empty.bin"), format!("{}  empty.bin\n", hex));
+    }
+}
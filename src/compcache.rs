@@ -0,0 +1,340 @@
+/*
+* Compressed-variant cache module
+*
+* On-the-fly gzip costs CPU on every request, and the hottest responses
+* (site CSS and JavaScript) are the same bytes each time. The cache keeps
+* compressed outputs, in memory or in a directory, keyed by
+*
+*   request target + encoding      -> validator, compressed bytes
+*
+* where the validator is the response's `ETag`, or its `Last-Modified` time
+* and length. A changed file has a new validator, so its stale variant is
+* simply replaced; responses with neither header (listings, generated pages)
+* are not cached.
+*
+* Concurrent first requests for the same variant are single-flighted: one
+* compresses while the others wait for its result instead of compressing
+* the same file in parallel.
+*
+* The memory store is bounded by total size and evicts the least recently
+* used variant. The disk store keeps one file per target and encoding, with
+* the validator on its first line.
+*/
+
+use crate::error::ServerError;
+use crate::flight::SingleFlight;
+use crate::hex::to_hex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
+
+/// Largest response compressed into the cache by default; bigger ones are
+/// streamed as before.
+const DEFAULT_MAX_ENTRY: u64 = 8 * 1024 * 1024;
+
+/// Cache of compressed response bodies.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::compcache::CompressionCache;
+/// use file_shover::middleware::Compression;
+///
+/// let compression = Compression::default().cache(CompressionCache::memory(64 * 1024 * 1024));
+/// # let _ = compression;
+/// ```
+#[derive(Debug)]
+pub struct CompressionCache {
+    store: Store,
+    max_entry: u64,
+    /// Variants being compressed right now, to wait on.
+    flights: SingleFlight,
+}
+
+#[derive(Debug)]
+enum Store {
+    Memory(Mutex<Memory>),
+    Disk(PathBuf),
+}
+
+#[derive(Debug, Default)]
+struct Memory {
+    max_bytes: u64,
+    bytes: u64,
+    /// Use counter, for least-recently-used eviction.
+    clock: u64,
+    entries: HashMap<String, Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    validator: String,
+    body: Arc<Vec<u8>>,
+    used: u64,
+}
+
+impl CompressionCache {
+    /// Keeps up to `max_bytes` of compressed bodies in memory.
+    pub fn memory(max_bytes: u64) -> Self {
+        Self::new(Store::Memory(Mutex::new(Memory {
+            max_bytes,
+            ..Memory::default()
+        })))
+    }
+
+    /// Keeps compressed bodies as files in `dir`, so they survive restarts.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Io` if the directory cannot be created.
+    pub fn disk(dir: impl Into<PathBuf>) -> Result<Self, ServerError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self::new(Store::Disk(dir)))
+    }
+
+    fn new(store: Store) -> Self {
+        Self {
+            store,
+            max_entry: DEFAULT_MAX_ENTRY,
+            flights: SingleFlight::default(),
+        }
+    }
+
+    /// Caches only responses of at most `bytes` before compression.
+    pub fn max_entry_size(mut self, bytes: u64) -> Self {
+        self.max_entry = bytes;
+        self
+    }
+
+    /// Largest uncompressed response that is cached.
+    pub(crate) fn max_entry(&self) -> u64 {
+        self.max_entry
+    }
+
+    /// The cached variant for `key` if it still matches `validator`,
+    /// otherwise the result of `compress`, stored for next time.
+    ///
+    /// Only one caller compresses a given key at a time; the others wait
+    /// and then find its result.
+    pub(crate) fn get_or_compress(
+        &self,
+        key: &str,
+        validator: &str,
+        compress: impl FnOnce() -> io::Result<Vec<u8>>,
+    ) -> io::Result<Arc<Vec<u8>>> {
+        self.flights.run(
+            key,
+            || self.get(key, validator),
+            || {
+                let body = Arc::new(compress()?);
+                self.put(key, validator, &body);
+                Ok(body)
+            },
+        )
+    }
+
+    fn get(&self, key: &str, validator: &str) -> Option<Arc<Vec<u8>>> {
+        match &self.store {
+            Store::Memory(memory) => {
+                let mut memory = memory.lock().unwrap_or_else(|e| e.into_inner());
+                memory.clock += 1;
+                let clock = memory.clock;
+                let entry = memory.entries.get_mut(key)?;
+                if entry.validator != validator {
+                    return None;
+                }
+                entry.used = clock;
+                Some(Arc::clone(&entry.body))
+            }
+            Store::Disk(dir) => match read_file(&dir.join(file_name(key)), validator) {
+                Ok(body) => body.map(Arc::new),
+                Err(e) => {
+                    debug!("Compressed variant of {} unreadable: {}", key, e);
+                    None
+                }
+            },
+        }
+    }
+
+    fn put(&self, key: &str, validator: &str, body: &Arc<Vec<u8>>) {
+        match &self.store {
+            Store::Memory(memory) => {
+                let mut memory = memory.lock().unwrap_or_else(|e| e.into_inner());
+                memory.insert(key, validator, body);
+            }
+            Store::Disk(dir) => {
+                if let Err(e) = write_file(dir, &file_name(key), validator, body) {
+                    warn!("Could not cache compressed {}: {}", key, e);
+                }
+            }
+        }
+    }
+}
+
+impl Memory {
+    fn insert(&mut self, key: &str, validator: &str, body: &Arc<Vec<u8>>) {
+        let size = body.len() as u64;
+        if size > self.max_bytes {
+            return;
+        }
+        if let Some(stale) = self.entries.remove(key) {
+            self.bytes -= stale.body.len() as u64;
+        }
+        while self.bytes + size > self.max_bytes {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.bytes -= evicted.body.len() as u64;
+            }
+        }
+        self.clock += 1;
+        self.bytes += size;
+        self.entries.insert(
+            key.to_string(),
+            Entry {
+                validator: validator.to_string(),
+                body: Arc::clone(body),
+                used: self.clock,
+            },
+        );
+    }
+}
+
+/// Names the file of `key` by its hash, so any target maps to a safe name.
+fn file_name(key: &str) -> String {
+    to_hex(&Sha256::digest(key.as_bytes())[..16])
+}
+
+/// The body stored in `path` if its validator line matches.
+fn read_file(path: &std::path::Path, validator: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut reader = match std::fs::File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut stored = String::new();
+    reader.read_line(&mut stored)?;
+    if stored.strip_suffix('\n') != Some(validator) {
+        return Ok(None);
+    }
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body)?;
+    Ok(Some(body))
+}
+
+/// Writes through a temporary file, so readers never see half a variant.
+fn write_file(dir: &std::path::Path, name: &str, validator: &str, body: &[u8]) -> io::Result<()> {
+    let temporary = dir.join(format!(".{}.{}", name, std::process::id()));
+    let mut file = std::fs::File::create(&temporary)?;
+    // Validators come from response headers, which hold no line breaks
+    writeln!(file, "{}", validator)?;
+    file.write_all(body)?;
+    drop(file);
+    std::fs::rename(&temporary, dir.join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn compressed(body: &[u8]) -> impl FnOnce() -> io::Result<Vec<u8>> + '_ {
+        move || Ok(body.to_vec())
+    }
+
+    #[test]
+    fn test_memory_hits_and_replacement() {
+        let cache = CompressionCache::memory(1024);
+        let first = cache
+            .get_or_compress("/a.css gzip", "v1", compressed(b"one"))
+            .unwrap();
+        assert_eq!(*first, b"one");
+        let hit = cache
+            .get_or_compress("/a.css gzip", "v1", || panic!("compressed twice"))
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &hit));
+        // A new validator replaces the stale variant
+        let second = cache
+            .get_or_compress("/a.css gzip", "v2", compressed(b"two"))
+            .unwrap();
+        assert_eq!(*second, b"two");
+    }
+
+    #[test]
+    fn test_memory_evicts_least_recently_used() {
+        let cache = CompressionCache::memory(10);
+        cache
+            .get_or_compress("a", "v", compressed(&[0; 4]))
+            .unwrap();
+        cache
+            .get_or_compress("b", "v", compressed(&[0; 4]))
+            .unwrap();
+        cache
+            .get_or_compress("a", "v", compressed(&[1; 4]))
+            .unwrap();
+        cache
+            .get_or_compress("c", "v", compressed(&[0; 4]))
+            .unwrap();
+        assert!(cache.get("a", "v").is_some());
+        assert!(cache.get("b", "v").is_none());
+        assert!(cache.get("c", "v").is_some());
+        // Too big to keep at all
+        cache
+            .get_or_compress("d", "v", compressed(&[0; 11]))
+            .unwrap();
+        assert!(cache.get("d", "v").is_none());
+    }
+
+    #[test]
+    fn test_disk_store() {
+        let dir =
+            std::env::temp_dir().join(format!("file-shover-compcache-{}", std::process::id()));
+        let cache = CompressionCache::disk(&dir).unwrap();
+        cache
+            .get_or_compress("/app.js gzip", "\"etag\"", compressed(b"\x1f\x8b\nbytes"))
+            .unwrap();
+        let reopened = CompressionCache::disk(&dir).unwrap();
+        let body = reopened
+            .get_or_compress("/app.js gzip", "\"etag\"", || panic!("not cached"))
+            .unwrap();
+        assert_eq!(*body, b"\x1f\x8b\nbytes");
+        assert!(reopened.get("/app.js gzip", "\"other\"").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_single_flight() {
+        let cache = Arc::new(CompressionCache::memory(1024));
+        let compressions = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (cache, compressions) = (Arc::clone(&cache), Arc::clone(&compressions));
+                std::thread::spawn(move || {
+                    cache
+                        .get_or_compress("/big.js gzip", "v", || {
+                            compressions.fetch_add(1, Ordering::SeqCst);
+                            std::thread::sleep(Duration::from_millis(50));
+                            Ok(b"gz".to_vec())
+                        })
+                        .unwrap()
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(*thread.join().unwrap(), b"gz");
+        }
+        assert_eq!(compressions.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.flights.len(), 0);
+    }
+}
@@ -0,0 +1,177 @@
+/*
+* Precondition module
+*
+* `If-Match` and `If-Unmodified-Since` let a client act only on the version
+* of a file it last saw, so two clients editing the same file cannot silently
+* overwrite each other (optimistic concurrency):
+*
+*   GET  /doc.txt                          -> 200, ETag: "ab12..."
+*   PUT  /doc.txt  If-Match: "ab12..."     -> 204 while unchanged
+*                                          -> 412 once someone else wrote it
+*
+* Evaluation follows RFC 9110 section 13.2.2: `If-Match` when present
+* (strong comparison, `*` meaning "exists"), otherwise `If-Unmodified-Since`
+* at one second resolution. Entity tags are the SHA-256 digests also sent as
+* `ETag`; they are only computed when a request asks for them.
+*
+* `If-Range` guards a `Range` request instead: a resumed download only gets
+* the missing part if the file is still the one it started with, and the
+* whole file otherwise.
+*/
+
+use crate::message::Request;
+use crate::units::parse_http_date;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Returns whether the preconditions of `req` hold for the current state of
+/// its target: whether it `exists`, when it was `modified` and its entity
+/// tag, computed by `etag` only if `If-Match` lists tags.
+///
+/// A request without preconditions always passes.
+pub(crate) fn preconditions_hold(
+    req: &Request,
+    exists: bool,
+    modified: Option<SystemTime>,
+    etag: impl FnOnce() -> Option<String>,
+) -> bool {
+    if let Some(if_match) = req.header("If-Match") {
+        let if_match = if_match.trim();
+        if if_match == "*" {
+            return exists;
+        }
+        if !exists {
+            return false;
+        }
+        return match etag() {
+            Some(current) => entity_tags(if_match).any(|tag| strong_match(tag, &current)),
+            None => false,
+        };
+    }
+    let (Some(since), Some(modified)) = (
+        req.header("If-Unmodified-Since").and_then(parse_http_date),
+        modified.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok()),
+    ) else {
+        // Invalid dates and unknown times are ignored
+        return true;
+    };
+    exists && modified.as_secs() <= since
+}
+
+/// Returns whether a `Range` in `req` may be served, given the file's
+/// modification time and entity tag: true without `If-Range`, or when it
+/// names the current version (a strong tag, or exactly `Last-Modified`).
+pub(crate) fn if_range_holds(
+    req: &Request,
+    modified: Option<SystemTime>,
+    etag: impl FnOnce() -> Option<String>,
+) -> bool {
+    let Some(if_range) = req.header("If-Range").map(str::trim) else {
+        return true;
+    };
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return etag().is_some_and(|current| strong_match(if_range, &current));
+    }
+    match (
+        parse_http_date(if_range),
+        modified.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok()),
+    ) {
+        (Some(date), Some(modified)) => modified.as_secs() == date,
+        _ => false,
+    }
+}
+
+/// Splits an entity tag list (`"a", W/"b"`) into its tags.
+fn entity_tags(list: &str) -> impl Iterator<Item = &str> {
+    let mut rest = list;
+    std::iter::from_fn(move || {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        let opaque = rest.strip_prefix("W/").unwrap_or(rest).strip_prefix('"')?;
+        let end = rest.len() - opaque.len() + opaque.find('"')? + 1;
+        let (tag, remaining) = rest.split_at(end);
+        rest = remaining;
+        Some(tag)
+    })
+}
+
+/// Strong comparison: weak tags never match.
+fn strong_match(tag: &str, current: &str) -> bool {
+    !tag.starts_with("W/") && !current.starts_with("W/") && tag == current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::HttpMethod;
+    use std::time::Duration;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        headers
+            .iter()
+            .fold(
+                Request::builder().method(HttpMethod::PUT).path("/doc.txt"),
+                |builder, (name, value)| builder.header(*name, *value),
+            )
+            .build()
+    }
+
+    #[test]
+    fn test_entity_tags() {
+        let tags: Vec<&str> = entity_tags(r#""a", W/"b","c,d" "#).collect();
+        assert_eq!(tags, [r#""a""#, r#"W/"b""#, r#""c,d""#]);
+        assert_eq!(entity_tags("abc").count(), 0);
+    }
+
+    #[test]
+    fn test_if_match() {
+        let etag = || Some("\"v2\"".to_string());
+        let holds = |value: &str, exists: bool| {
+            preconditions_hold(&request(&[("If-Match", value)]), exists, None, etag)
+        };
+        assert!(holds("\"v2\"", true));
+        assert!(holds("\"v1\", \"v2\"", true));
+        assert!(!holds("\"v1\"", true));
+        assert!(!holds("W/\"v2\"", true));
+        assert!(holds("*", true));
+        assert!(!holds("*", false));
+        assert!(!holds("\"v2\"", false));
+        assert!(preconditions_hold(&request(&[]), false, None, etag));
+    }
+
+    #[test]
+    fn test_if_unmodified_since() {
+        let modified = Some(UNIX_EPOCH + Duration::from_millis(784_111_777_500));
+        let holds = |value: &str| {
+            preconditions_hold(
+                &request(&[("If-Unmodified-Since", value)]),
+                true,
+                modified,
+                || None,
+            )
+        };
+        assert!(holds("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert!(!holds("Sun, 06 Nov 1994 08:49:36 GMT"));
+        assert!(holds("not a date"));
+        // If-Match takes precedence
+        let req = request(&[
+            ("If-Match", "*"),
+            ("If-Unmodified-Since", "Thu, 01 Jan 1970 00:00:00 GMT"),
+        ]);
+        assert!(preconditions_hold(&req, true, modified, || None));
+    }
+
+    #[test]
+    fn test_if_range() {
+        let modified = Some(UNIX_EPOCH + Duration::from_secs(784_111_777));
+        let holds = |value: &str| {
+            let req = request(&[("If-Range", value)]);
+            if_range_holds(&req, modified, || Some("\"v2\"".to_string()))
+        };
+        assert!(holds("\"v2\""));
+        assert!(!holds("\"v1\""));
+        assert!(!holds("W/\"v2\""));
+        assert!(holds("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert!(!holds("Sun, 06 Nov 1994 08:49:38 GMT"));
+        assert!(!holds("soon"));
+        assert!(if_range_holds(&request(&[]), None, || None));
+    }
+}
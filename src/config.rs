@@ -0,0 +1,438 @@
+/*
+* Configuration module
+*
+* Structured settings that do not fit on the command line (rule lists, user
+* tables, ...) are read from a TOML file passed with `--config`. Every section
+* is optional; an empty file is a valid configuration. The docs of `Config`
+* show a file using each section.
+*
+* `[[site]]` blocks run several servers in one process, each with its own
+* listener, root, TLS certificate and rules; the sections above may appear
+* inside a block and then apply to that site only:
+*
+*   [[site]]
+*   name = "photos"
+*   bind = "0.0.0.0:8080"
+*   root = "/srv/photos"
+*
+*   [[site]]
+*   name = "backups"
+*   bind = "0.0.0.0:8443"
+*   root = ["/srv/backups", "/srv/archive"]
+*   tls_cert = "/etc/ssl/nas.pem"
+*   tls_key = "/etc/ssl/nas.key"
+*   [site.auth]
+*   tokens = ["backup-secret"]
+*/
+
+use crate::units::parse_duration;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Errors raised while loading a configuration file.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "IO error: {}", err),
+            ConfigError::Parse(msg) => write!(f, "Invalid configuration syntax: {}", msg),
+            ConfigError::Invalid(msg) => write!(f, "Invalid configuration: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+/// Top-level configuration file.
+///
+/// A file using each section:
+///
+/// ```toml
+/// headers = [
+///   "/fonts/** => Access-Control-Allow-Origin: *",
+///   "*.pdf => Content-Disposition: attachment",
+/// ]
+///
+/// rewrites = [
+///   "^/blog/([0-9]+)$ => /blog.php?id=$1",
+///   "^/docs/v1/(.*)$ => /docs/v2/$1 [301]",
+/// ]
+///
+/// [auth]
+/// tokens = ["ci-secret"]
+///
+/// [auth.users]
+/// alice = "sha256:a71a7c7011f53a1bab3642ec2ce12593f05230ace8de1e3e7645f69efac1443d"
+///
+/// [auth.groups]
+/// staff = ["alice"]
+///
+/// [[auth.rules]]
+/// path = "/public/**"
+/// access = "public"
+///
+/// [[auth.rules]]
+/// path = "/internal/**"
+/// access = "group:staff"
+///
+/// [security_headers]
+/// enabled = true
+/// strict_transport_security = "max-age=31536000; includeSubDomains"
+///
+/// [[security_headers.overrides]]
+/// path = "/embed/**"
+/// headers = { "X-Frame-Options" = "" }
+///
+/// [cache_ttl]
+/// html = 0
+/// css = "1d"
+/// woff2 = "365d"
+///
+/// [tls]
+/// min_version = "1.3"
+/// cipher_suites = ["TLS13_AES_256_GCM_SHA384", "TLS13_CHACHA20_POLY1305_SHA256"]
+/// session_tickets = false
+/// ```
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::config::Config;
+///
+/// let config = Config::from_toml(r#"
+///     [[auth.rules]]
+///     path = "/internal/**"
+///     access = "token"
+/// "#).unwrap();
+/// assert_eq!(config.auth.rules.len(), 1);
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Custom header rules, `"<glob> => <Name>: <value>"` (see [`crate::headers::HeaderRule`]).
+    pub headers: Vec<String>,
+    /// Rewrite rules, `"<regex> => <target> [status]"` (see [`crate::rewrite::RewriteRule`]).
+    pub rewrites: Vec<String>,
+    pub auth: AuthConfig,
+    pub security_headers: SecurityHeadersConfig,
+    pub downloads: DownloadsConfig,
+    /// `Cache-Control` max-age per file extension, in seconds or with a
+    /// unit (`"1d"`); 0 means `no-cache`.
+    #[serde(deserialize_with = "durations")]
+    pub cache_ttl: HashMap<String, Duration>,
+    pub tls: TlsPolicyConfig,
+    /// Sites served side by side, each from its own `[[site]]` block.
+    #[serde(rename = "site")]
+    pub sites: Vec<SiteConfig>,
+}
+
+/// One `[[site]]` block: a server of its own within the process.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig {
+    /// Tag on the site's log lines; the bind address if unset.
+    pub name: Option<String>,
+    /// Address and port to listen on, e.g. `0.0.0.0:8080`.
+    pub bind: String,
+    /// Directory to serve, or a list of directories overlaid in order.
+    #[serde(deserialize_with = "one_or_many")]
+    pub root: Vec<PathBuf>,
+    /// PEM certificate chain; serves HTTPS together with `tls_key`.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key for `tls_cert`.
+    pub tls_key: Option<PathBuf>,
+    /// PEM file of CAs whose client certificates are accepted.
+    pub tls_client_ca: Option<PathBuf>,
+    pub headers: Vec<String>,
+    pub rewrites: Vec<String>,
+    pub auth: AuthConfig,
+    pub security_headers: SecurityHeadersConfig,
+    pub downloads: DownloadsConfig,
+    #[serde(deserialize_with = "durations")]
+    pub cache_ttl: HashMap<String, Duration>,
+    pub tls: TlsPolicyConfig,
+}
+
+impl SiteConfig {
+    /// The name to tag the site's log lines with.
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.bind)
+    }
+
+    /// The site's rule sections as a configuration of their own.
+    pub fn rules(&self) -> Config {
+        Config {
+            headers: self.headers.clone(),
+            rewrites: self.rewrites.clone(),
+            auth: self.auth.clone(),
+            security_headers: self.security_headers.clone(),
+            downloads: self.downloads.clone(),
+            cache_ttl: self.cache_ttl.clone(),
+            tls: self.tls.clone(),
+            sites: Vec::new(),
+        }
+    }
+}
+
+/// Accepts a single path where a list is expected.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PathBuf>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+/// Accepts durations as seconds or as strings with a unit, e.g. `"12h"`.
+fn durations<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, Duration>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum SecondsOrText {
+        Seconds(u64),
+        Text(String),
+    }
+    HashMap::<String, SecondsOrText>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key, value)| {
+            let duration = match value {
+                SecondsOrText::Seconds(seconds) => Duration::from_secs(seconds),
+                SecondsOrText::Text(text) => {
+                    parse_duration(&text).map_err(serde::de::Error::custom)?
+                }
+            };
+            Ok((key, duration))
+        })
+        .collect()
+}
+
+/// `[downloads]` section.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadsConfig {
+    /// Globs of files always sent with `Content-Disposition: attachment`,
+    /// e.g. `"*.iso"`.
+    pub attachment: Vec<String>,
+}
+
+/// `[tls]` section; only used when TLS is enabled.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsPolicyConfig {
+    /// Oldest protocol version accepted, `"1.2"` or `"1.3"`.
+    pub min_version: Option<String>,
+    /// Cipher suites offered, by IANA name (e.g. `TLS13_AES_256_GCM_SHA384`),
+    /// in order of preference; all supported suites if empty.
+    pub cipher_suites: Vec<String>,
+    /// ALPN protocols offered; `["http/1.1"]` if unset, none if empty.
+    pub alpn: Option<Vec<String>>,
+    /// Issue session tickets for resumption (stateless, also for TLS 1.2)
+    /// or none at all; the rustls defaults if unset.
+    pub session_tickets: Option<bool>,
+}
+
+/// `[auth]` section.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Bearer tokens accepted in addition to `--token`.
+    pub tokens: Vec<String>,
+    /// Basic auth users: name to password (`plain` or `sha256:<hex>`).
+    pub users: HashMap<String, String>,
+    /// Named groups of users.
+    pub groups: HashMap<String, Vec<String>>,
+    /// Ordered path rules; the first matching rule applies.
+    pub rules: Vec<AuthRuleConfig>,
+}
+
+/// One `[[auth.rules]]` entry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthRuleConfig {
+    /// Glob matched against the request path (see [`crate::glob::Glob`]).
+    pub path: String,
+    /// `public`, `token`, or `group:<name>`.
+    pub access: String,
+}
+
+/// `[security_headers]` section.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityHeadersConfig {
+    /// Adds the recommended defaults (nosniff, frame denial, referrer policy).
+    pub enabled: bool,
+    /// `Strict-Transport-Security` value; only meaningful behind TLS.
+    pub strict_transport_security: Option<String>,
+    /// `Content-Security-Policy` value.
+    pub content_security_policy: Option<String>,
+    /// Extra or replacement headers; an empty value removes a default.
+    pub headers: HashMap<String, String>,
+    /// Per-path adjustments applied after the defaults.
+    pub overrides: Vec<HeaderOverrideConfig>,
+}
+
+/// One `[[security_headers.overrides]]` entry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeaderOverrideConfig {
+    /// Glob matched against the request path (see [`crate::glob::Glob`]).
+    pub path: String,
+    /// Headers to set; an empty value removes the header.
+    pub headers: HashMap<String, String>,
+}
+
+impl Config {
+    /// Reads and parses a TOML configuration file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ConfigError`] if the file cannot be read or parsed.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path)?;
+        Self::from_toml(&content)
+    }
+
+    /// Parses configuration from a TOML string.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::Parse`] on syntax errors or unknown keys.
+    pub fn from_toml(content: &str) -> Result<Self, ConfigError> {
+        toml::from_str(content).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_config() {
+        let config = Config::from_toml("").unwrap();
+        assert!(config.auth.rules.is_empty());
+        assert!(config.auth.users.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        assert!(matches!(
+            Config::from_toml("[auth]\ntokenz = []"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_full_auth_section() {
+        let config = Config::from_toml(
+            r#"
+            [auth]
+            tokens = ["t1"]
+            [auth.users]
+            alice = "wonderland"
+            [auth.groups]
+            staff = ["alice"]
+            [[auth.rules]]
+            path = "/public/**"
+            access = "public"
+            [[auth.rules]]
+            path = "/internal/**"
+            access = "group:staff"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.auth.tokens, vec!["t1"]);
+        assert_eq!(config.auth.groups["staff"], vec!["alice"]);
+        assert_eq!(config.auth.rules[1].access, "group:staff");
+    }
+
+    #[test]
+    fn test_header_rules_before_sections() {
+        let config = Config::from_toml(
+            r#"
+            headers = ["*.pdf => Content-Disposition: attachment"]
+            [security_headers]
+            enabled = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.headers.len(), 1);
+        assert!(config.security_headers.enabled);
+    }
+
+    #[test]
+    fn test_downloads_section() {
+        let config = Config::from_toml("[downloads]\nattachment = [\"*.iso\", \"*.dmg\"]").unwrap();
+        assert_eq!(config.downloads.attachment, vec!["*.iso", "*.dmg"]);
+    }
+
+    #[test]
+    fn test_cache_ttl_section() {
+        let config =
+            Config::from_toml("[cache_ttl]\nhtml = 0\ncss = 86400\nwoff2 = \"365d\"").unwrap();
+        assert_eq!(config.cache_ttl["html"], Duration::ZERO);
+        assert_eq!(config.cache_ttl["css"], Duration::from_secs(86400));
+        assert_eq!(config.cache_ttl["woff2"], Duration::from_secs(31536000));
+        assert!(Config::from_toml("[cache_ttl]\ncss = \"1 fortnight\"").is_err());
+    }
+
+    #[test]
+    fn test_site_blocks() {
+        let config = Config::from_toml(
+            r#"
+            [[site]]
+            name = "photos"
+            bind = "0.0.0.0:8080"
+            root = "/srv/photos"
+
+            [[site]]
+            bind = "0.0.0.0:8443"
+            root = ["/srv/backups", "/srv/archive"]
+            tls_cert = "nas.pem"
+            tls_key = "nas.key"
+            [site.auth]
+            tokens = ["backup-secret"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.sites.len(), 2);
+        assert_eq!(config.sites[0].name(), "photos");
+        assert_eq!(config.sites[0].root, vec![PathBuf::from("/srv/photos")]);
+        assert_eq!(config.sites[1].name(), "0.0.0.0:8443");
+        assert_eq!(config.sites[1].root.len(), 2);
+        let rules = config.sites[1].rules();
+        assert_eq!(rules.auth.tokens, vec!["backup-secret"]);
+        assert!(config.auth.tokens.is_empty());
+        assert!(Config::from_toml(
+            "[[site]]
+port = 80"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,225 @@
+/*
+* Connection limiting module
+*
+* Caps the number of simultaneous connections per client IP and server-wide.
+* A slot is taken when a connection is accepted and released when the returned
+* guard is dropped, i.e. when the worker is done with the connection.
+*/
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Why a connection was refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitExceeded {
+    /// The client already holds `max_per_ip` connections.
+    PerIp,
+    /// The server already holds `max_total` connections.
+    Total,
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::PerIp => write!(f, "per-client connection limit reached"),
+            LimitExceeded::Total => write!(f, "server connection limit reached"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    per_ip: HashMap<IpAddr, usize>,
+    total: usize,
+}
+
+/// Tracks open connections and enforces per-IP and total caps.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::connlimit::{ConnectionLimiter, LimitExceeded};
+/// use std::net::{IpAddr, Ipv4Addr};
+/// use std::sync::Arc;
+///
+/// let limiter = Arc::new(ConnectionLimiter::new(Some(1), None));
+/// let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+///
+/// let guard = limiter.try_acquire(client).unwrap();
+/// assert_eq!(limiter.try_acquire(client).unwrap_err(), LimitExceeded::PerIp);
+///
+/// // Dropping the guard frees the slot
+/// drop(guard);
+/// assert!(limiter.try_acquire(client).is_ok());
+/// ```
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    max_per_ip: Option<usize>,
+    max_total: Option<usize>,
+    counts: Mutex<Counts>,
+    /// Notified when the last connection closes.
+    idle: Condvar,
+}
+
+impl ConnectionLimiter {
+    /// Creates a limiter. `None` disables the corresponding cap.
+    pub fn new(max_per_ip: Option<usize>, max_total: Option<usize>) -> Self {
+        Self {
+            max_per_ip,
+            max_total,
+            counts: Mutex::new(Counts::default()),
+            idle: Condvar::new(),
+        }
+    }
+
+    /// Reserves a connection slot for `ip`.
+    ///
+    /// # Errors
+    ///
+    /// Returns which limit was hit if the connection must be refused.
+    pub fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionGuard, LimitExceeded> {
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+
+        if self.max_total.is_some_and(|max| counts.total >= max) {
+            return Err(LimitExceeded::Total);
+        }
+        let open = counts.per_ip.get(&ip).copied().unwrap_or(0);
+        if self.max_per_ip.is_some_and(|max| open >= max) {
+            return Err(LimitExceeded::PerIp);
+        }
+
+        counts.total += 1;
+        *counts.per_ip.entry(ip).or_insert(0) += 1;
+        Ok(ConnectionGuard {
+            limiter: Arc::clone(self),
+            ip,
+        })
+    }
+
+    /// Number of connections currently open.
+    pub fn active(&self) -> usize {
+        self.counts.lock().unwrap_or_else(|e| e.into_inner()).total
+    }
+
+    /// Blocks until no connection is open or `timeout` has passed, and
+    /// returns whether all connections closed.
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        while counts.total > 0 {
+            let left = deadline.map_or(Duration::MAX, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+            if left.is_zero() {
+                return false;
+            }
+            counts = self
+                .idle
+                .wait_timeout(counts, left)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        true
+    }
+
+    /// Open connections per client, busiest first.
+    pub fn clients(&self) -> Vec<(IpAddr, usize)> {
+        let counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        let mut clients: Vec<_> = counts
+            .per_ip
+            .iter()
+            .map(|(ip, open)| (*ip, *open))
+            .collect();
+        clients.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        clients
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        counts.total = counts.total.saturating_sub(1);
+        if let Some(open) = counts.per_ip.get_mut(&ip) {
+            *open -= 1;
+            if *open == 0 {
+                counts.per_ip.remove(&ip);
+            }
+        }
+        if counts.total == 0 {
+            self.idle.notify_all();
+        }
+    }
+}
+
+/// Holds a connection slot until dropped.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[test]
+    fn test_per_ip_limit() {
+        let limiter = Arc::new(ConnectionLimiter::new(Some(2), None));
+        let _a = limiter.try_acquire(ip(1)).unwrap();
+        let _b = limiter.try_acquire(ip(1)).unwrap();
+        assert_eq!(
+            limiter.try_acquire(ip(1)).unwrap_err(),
+            LimitExceeded::PerIp
+        );
+        assert!(limiter.try_acquire(ip(2)).is_ok());
+    }
+
+    #[test]
+    fn test_total_limit() {
+        let limiter = Arc::new(ConnectionLimiter::new(None, Some(2)));
+        let _a = limiter.try_acquire(ip(1)).unwrap();
+        let _b = limiter.try_acquire(ip(2)).unwrap();
+        assert_eq!(
+            limiter.try_acquire(ip(3)).unwrap_err(),
+            LimitExceeded::Total
+        );
+        assert_eq!(limiter.active(), 2);
+    }
+
+    #[test]
+    fn test_release_on_drop() {
+        let limiter = Arc::new(ConnectionLimiter::new(Some(1), Some(1)));
+        {
+            let _guard = limiter.try_acquire(ip(1)).unwrap();
+            assert_eq!(limiter.active(), 1);
+        }
+        assert_eq!(limiter.active(), 0);
+        assert!(limiter.counts.lock().unwrap().per_ip.is_empty());
+        assert!(limiter.try_acquire(ip(1)).is_ok());
+    }
+
+    #[test]
+    fn test_wait_idle() {
+        let limiter = Arc::new(ConnectionLimiter::new(None, None));
+        assert!(limiter.wait_idle(Duration::ZERO));
+        let guard = limiter.try_acquire(ip(1)).unwrap();
+        assert!(!limiter.wait_idle(Duration::from_millis(10)));
+        let closer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            drop(guard);
+        });
+        assert!(limiter.wait_idle(Duration::from_secs(10)));
+        closer.join().unwrap();
+    }
+}
@@ -0,0 +1,260 @@
+/*
+* Cookie module
+*
+* Parsing of the request `Cookie` header and a typed `Set-Cookie` value for
+* responses (RFC 6265). Responses keep their cookies in a list of their own
+* because `Set-Cookie` is the one header that must never be folded into a
+* single comma-separated line.
+*/
+
+use crate::parser::is_token;
+use std::fmt;
+use std::time::Duration;
+
+/// Errors from building a cookie.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CookieError {
+    /// The name is empty or contains characters outside an HTTP token.
+    InvalidName(String),
+    /// The value contains characters not allowed in a cookie value.
+    InvalidValue(String),
+}
+
+impl fmt::Display for CookieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CookieError::InvalidName(name) => write!(f, "invalid cookie name: {:?}", name),
+            CookieError::InvalidValue(value) => write!(f, "invalid cookie value: {:?}", value),
+        }
+    }
+}
+
+impl std::error::Error for CookieError {}
+
+/// The `SameSite` cookie attribute.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+/// A cookie to send with `Set-Cookie`.
+///
+/// The name and value are checked when the cookie is created; `path` and
+/// `domain` are expected to be trusted, fixed strings.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::cookie::{Cookie, SameSite};
+/// use std::time::Duration;
+///
+/// let cookie = Cookie::new("session", "abc123")?
+///     .path("/")
+///     .max_age(Duration::from_secs(3600))
+///     .http_only(true)
+///     .same_site(SameSite::Lax);
+/// assert_eq!(
+///     cookie.to_string(),
+///     "session=abc123; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax"
+/// );
+///
+/// assert!(Cookie::new("bad name", "x").is_err());
+/// # Ok::<(), file_shover::cookie::CookieError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Creates a session cookie with no attributes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is not a token or the value contains
+    /// whitespace, quotes, commas, semicolons, backslashes or control
+    /// characters.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Result<Self, CookieError> {
+        let (name, value) = (name.into(), value.into());
+        if name.is_empty() || !name.bytes().all(is_token) {
+            return Err(CookieError::InvalidName(name));
+        }
+        if !value.bytes().all(is_cookie_octet) {
+            return Err(CookieError::InvalidValue(value));
+        }
+        Ok(Self {
+            name,
+            value,
+            path: None,
+            domain: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        })
+    }
+
+    /// A cookie that makes the client delete `name` (empty value, `Max-Age=0`).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is not a token.
+    pub fn removal(name: impl Into<String>) -> Result<Self, CookieError> {
+        Ok(Self::new(name, "")?.max_age(Duration::ZERO))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Lifetime in whole seconds; without it the cookie ends with the session.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Sets `SameSite`; `SameSite::None` also sets `Secure`, which browsers
+    /// require for it.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self.secure |= same_site == SameSite::None;
+        self
+    }
+}
+
+/// Formats the `Set-Cookie` header value.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a `Cookie` request header into name/value pairs.
+///
+/// Malformed pairs are skipped and quotes around a value are removed.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::cookie::parse_cookie_header;
+///
+/// let pairs: Vec<_> = parse_cookie_header(r#"theme=dark; sid="abc"; junk"#).collect();
+/// assert_eq!(pairs, vec![("theme", "dark"), ("sid", "abc")]);
+/// ```
+pub fn parse_cookie_header(header: &str) -> impl Iterator<Item = (&str, &str)> {
+    header.split(';').filter_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        let name = name.trim();
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        (!name.is_empty()).then_some((name, value))
+    })
+}
+
+/// RFC 6265 `cookie-octet`.
+fn is_cookie_octet(byte: u8) -> bool {
+    matches!(byte, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_values() {
+        assert!(Cookie::new("", "x").is_err());
+        assert!(Cookie::new("a;b", "x").is_err());
+        for value in ["a b", "a;b", "a,b", "a\"b", "a\\b", "a\r\nSet-Cookie: x=y"] {
+            assert_eq!(
+                Cookie::new("name", value),
+                Err(CookieError::InvalidValue(value.to_string()))
+            );
+        }
+        assert!(Cookie::new("name", "").is_ok());
+    }
+
+    #[test]
+    fn test_attributes() {
+        let cookie = Cookie::new("id", "1")
+            .unwrap()
+            .domain("example.com")
+            .same_site(SameSite::None);
+        assert_eq!(
+            cookie.to_string(),
+            "id=1; Domain=example.com; Secure; SameSite=None"
+        );
+        assert_eq!(Cookie::removal("id").unwrap().to_string(), "id=; Max-Age=0");
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let pairs: Vec<_> = parse_cookie_header("a=1;b=2 ;  c = 3;=4; d=").collect();
+        assert_eq!(pairs, vec![("a", "1"), ("b", "2"), ("c", "3"), ("d", "")]);
+    }
+}
@@ -0,0 +1,218 @@
+/*
+* Dashboard module
+*
+* The HTML page served at `/` on the admin port: request and bandwidth rates
+* with graphs of the last five minutes, the status class breakdown, the
+* client cache hit rate and the most requested paths. It is rendered on the
+* server from the in-memory `Stats`, graphs as inline SVG, and reloads itself
+* every few seconds, so there is no script and nothing to install.
+*
+* The cache hit rate is the share of successful answers that were
+* `304 Not Modified`, i.e. requests the client could serve from its cache.
+*/
+
+use crate::listing::escape_html;
+use crate::stats::{Sample, Stats, HISTORY_SECS};
+use crate::units::format_size;
+use std::fmt::Write;
+
+/// Seconds averaged for the current rates.
+const RATE_WINDOW: usize = 10;
+
+/// Paths listed in the top paths table.
+const TOP_PATHS: usize = 15;
+
+const GRAPH_WIDTH: usize = 600;
+const GRAPH_HEIGHT: usize = 120;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+    .cards{display:flex;flex-wrap:wrap;gap:1em}\
+    .card{border:1px solid #ddd;border-radius:6px;padding:0.8em 1.2em;min-width:9em}\
+    .card b{display:block;font-size:1.5em}\
+    .bar{display:flex;height:1.4em;border-radius:4px;overflow:hidden;background:#eee;max-width:600px}\
+    .bar span{display:block}\
+    svg{background:#fafafa;border:1px solid #ddd}\
+    td{padding:0.2em 1em 0.2em 0}td.n{text-align:right}";
+
+/// Status classes with their bar colors.
+const CLASSES: [(&str, &str); 5] = [
+    ("1xx", "#9e9e9e"),
+    ("2xx", "#43a047"),
+    ("3xx", "#1e88e5"),
+    ("4xx", "#fb8c00"),
+    ("5xx", "#e53935"),
+];
+
+/// Renders the dashboard; `maintenance` is `None` when no switch is configured.
+pub(crate) fn render(stats: &Stats, connections_open: usize, maintenance: Option<bool>) -> String {
+    let snapshot = stats.snapshot();
+    let history = stats.history();
+    let recent = &history[HISTORY_SECS - RATE_WINDOW..];
+    let rate = |value: fn(&Sample) -> u64| {
+        recent.iter().map(value).sum::<u64>() as f64 / RATE_WINDOW as f64
+    };
+    let responses = &snapshot.responses;
+    let classes = [
+        responses.informational,
+        responses.success,
+        responses.redirection,
+        responses.client_error,
+        responses.server_error,
+    ];
+    let hit_rate = match responses.success + snapshot.not_modified {
+        0 => "–".to_string(),
+        answered => format!(
+            "{:.1}%",
+            snapshot.not_modified as f64 * 100.0 / answered as f64
+        ),
+    };
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta http-equiv=\"refresh\" content=\"5\">\n\
+         <title>file-shover dashboard</title>\n<style>{STYLE}</style>\n</head>\n<body>\n\
+         <h1>file-shover</h1>\n<p>Up {uptime}, {connections_open} open connections{maintenance}</p>\n\
+         <div class=\"cards\">\n",
+        uptime = format_uptime(snapshot.uptime_secs),
+        maintenance = match maintenance {
+            Some(true) => ", <strong>in maintenance</strong>",
+            _ => "",
+        },
+    );
+    let cards = [
+        ("Requests/s", format!("{:.1}", rate(|s| s.requests))),
+        (
+            "Bandwidth",
+            format!("{}/s", format_size(rate(|s| s.bytes) as u64)),
+        ),
+        ("Requests", snapshot.requests.to_string()),
+        ("Sent", format_size(snapshot.bytes_sent)),
+        ("Received", format_size(snapshot.bytes_received)),
+        ("Cache hit rate", hit_rate),
+        ("Aborted", snapshot.aborted.to_string()),
+    ];
+    for (label, value) in cards {
+        let _ = writeln!(html, "<div class=\"card\">{label}<b>{value}</b></div>");
+    }
+    html.push_str("</div>\n<h2>Responses</h2>\n<div class=\"bar\">");
+    let total: u64 = classes.iter().sum();
+    for ((name, color), count) in CLASSES.iter().zip(classes) {
+        if count > 0 {
+            let _ = write!(
+                html,
+                "<span style=\"width:{:.2}%;background:{color}\" title=\"{name}: {count}\"></span>",
+                count as f64 * 100.0 / total as f64
+            );
+        }
+    }
+    html.push_str("</div>\n<p>");
+    let breakdown: Vec<String> = CLASSES
+        .iter()
+        .zip(classes)
+        .map(|((name, _), count)| format!("{name}: {count}"))
+        .collect();
+    html.push_str(&breakdown.join(" · "));
+    html.push_str("</p>\n");
+
+    let minutes = HISTORY_SECS / 60;
+    let _ = writeln!(html, "<h2>Requests per second, last {minutes} minutes</h2>");
+    graph(&mut html, &history, |s| s.requests, |peak| peak.to_string());
+    let _ = writeln!(html, "<h2>Bandwidth, last {minutes} minutes</h2>");
+    graph(
+        &mut html,
+        &history,
+        |s| s.bytes,
+        |peak| format!("{}/s", format_size(peak)),
+    );
+
+    html.push_str(
+        "<h2>Top paths</h2>\n<table>\n<tr><th>Path</th><th>Requests</th><th>Sent</th></tr>\n",
+    );
+    for path in stats.top_paths(TOP_PATHS) {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td></tr>",
+            escape_html(&path.path),
+            path.requests,
+            format_size(path.bytes)
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// Appends an SVG line graph of `value` over `history`, labelled with its peak.
+fn graph(
+    html: &mut String,
+    history: &[Sample],
+    value: fn(&Sample) -> u64,
+    label: impl Fn(u64) -> String,
+) {
+    let peak = history.iter().map(value).max().unwrap_or(0);
+    let scale = GRAPH_HEIGHT as f64 / peak.max(1) as f64;
+    let step = GRAPH_WIDTH as f64 / (history.len().max(2) - 1) as f64;
+    let points: Vec<String> = history
+        .iter()
+        .enumerate()
+        .map(|(i, sample)| {
+            format!(
+                "{:.1},{:.1}",
+                i as f64 * step,
+                GRAPH_HEIGHT as f64 - value(sample) as f64 * scale
+            )
+        })
+        .collect();
+    let _ = writeln!(
+        html,
+        "<svg width=\"{GRAPH_WIDTH}\" height=\"{GRAPH_HEIGHT}\" viewBox=\"0 0 {GRAPH_WIDTH} {GRAPH_HEIGHT}\">\
+         <polyline fill=\"none\" stroke=\"#1e88e5\" stroke-width=\"1.5\" points=\"{}\"/>\
+         <text x=\"4\" y=\"14\" font-size=\"12\">peak {}</text></svg>",
+        points.join(" "),
+        escape_html(&label(peak))
+    );
+}
+
+/// Formats a duration in its two largest units, e.g. `3h 12m`.
+pub(crate) fn format_uptime(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        3600..=86_399 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86_400, secs % 86_400 / 3600),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let stats = Stats::new();
+        stats.record_response(200, 2048);
+        stats.record_response(304, 0);
+        stats.record_response(404, 10);
+        stats.record_path("/<script>.js", 2048);
+
+        let html = render(&stats, 3, Some(true));
+        assert!(html.contains("3 open connections, <strong>in maintenance</strong>"));
+        assert!(html.contains("Cache hit rate<b>50.0%</b>"));
+        assert!(html.contains("2xx: 1 · 3xx: 1 · 4xx: 1"));
+        assert!(html.contains("<td>/&lt;script&gt;.js</td>"));
+        assert_eq!(html.matches("<svg").count(), 2);
+
+        let idle = render(&Stats::new(), 0, None);
+        assert!(idle.contains("Cache hit rate<b>–</b>"));
+        assert!(!idle.contains("maintenance"));
+    }
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(42), "42s");
+        assert_eq!(format_uptime(125), "2m 5s");
+        assert_eq!(format_uptime(7260), "2h 1m");
+        assert_eq!(format_uptime(90_000), "1d 1h");
+    }
+}
@@ -5,6 +5,15 @@ pub enum MimeType {
     TextCss,
     TextJavascript,
     ImageJpeg,
+    ImagePng,
+    ImageGif,
+    ImageWebp,
+    ImageSvg,
+    ImageIcon,
+    VideoMp4,
+    VideoWebm,
+    AudioMpeg,
+    AudioOgg,
     TextPlain,
 }
 
@@ -15,18 +24,23 @@ impl MimeType {
             MimeType::TextCss => "text/css",
             MimeType::TextJavascript => "text/javascript",
             MimeType::ImageJpeg => "image/jpeg",
+            MimeType::ImagePng => "image/png",
+            MimeType::ImageGif => "image/gif",
+            MimeType::ImageWebp => "image/webp",
+            MimeType::ImageSvg => "image/svg+xml",
+            MimeType::ImageIcon => "image/x-icon",
+            MimeType::VideoMp4 => "video/mp4",
+            MimeType::VideoWebm => "video/webm",
+            MimeType::AudioMpeg => "audio/mpeg",
+            MimeType::AudioOgg => "audio/ogg",
             MimeType::TextPlain => "text/plain",
         }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn test_mime_type_to_str() {
-        assert_eq!(MimeType::TextHtml.as_str(), "text/html");
+    /// Returns true for image, video and audio types, the usual hotlinking targets.
+    pub fn is_media(&self) -> bool {
+        let mime = self.as_str();
+        mime.starts_with("image/") || mime.starts_with("video/") || mime.starts_with("audio/")
     }
 }
 
@@ -36,7 +50,34 @@ pub fn get_mime_type<P: AsRef<Path>>(path: P) -> MimeType {
         Some("html") => MimeType::TextHtml,
         Some("css") => MimeType::TextCss,
         Some("js") => MimeType::TextJavascript,
-        Some("jpg") => MimeType::ImageJpeg,
+        Some("jpg" | "jpeg") => MimeType::ImageJpeg,
+        Some("png") => MimeType::ImagePng,
+        Some("gif") => MimeType::ImageGif,
+        Some("webp") => MimeType::ImageWebp,
+        Some("svg") => MimeType::ImageSvg,
+        Some("ico") => MimeType::ImageIcon,
+        Some("mp4") => MimeType::VideoMp4,
+        Some("webm") => MimeType::VideoWebm,
+        Some("mp3") => MimeType::AudioMpeg,
+        Some("ogg") => MimeType::AudioOgg,
         _ => MimeType::TextPlain,
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mime_type_to_str() {
+        assert_eq!(MimeType::TextHtml.as_str(), "text/html");
+    }
+
+    #[test]
+    fn test_media_types() {
+        assert!(get_mime_type("/a/photo.jpeg").is_media());
+        assert!(get_mime_type("/clip.webm").is_media());
+        assert!(!get_mime_type("/index.html").is_media());
+        assert!(!get_mime_type("/README").is_media());
+    }
+}
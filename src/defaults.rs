@@ -0,0 +1,213 @@
+/*
+* Defaults module
+*
+* Crawlers and browsers ask every site for `/robots.txt` and `/favicon.ico`.
+* A root without them turns those requests into a steady trickle of 404s in
+* the logs, so either can be answered with a built-in default instead:
+*
+*   User-agent: *
+*   Disallow: /private/
+*   Sitemap: https://example.com/sitemap.xml
+*
+* and a small folder icon. Defaults only fill in for files the tree lacks;
+* a real `robots.txt` or `favicon.ico` in the root is always served.
+*/
+
+use crate::message::{HttpMethod, Request, Response, StatusCode};
+use crate::middleware::Handler;
+
+/// Path of the robots file.
+pub const ROBOTS_PATH: &str = "/robots.txt";
+
+/// Path of the site icon.
+pub const FAVICON_PATH: &str = "/favicon.ico";
+
+/// The built-in icon, a 16x16 ICO.
+pub const FAVICON: &[u8] = include_bytes!("defaults/favicon.ico");
+
+/// A generated `robots.txt` for all user agents.
+///
+/// With no rules everything may be crawled.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::defaults::Robots;
+///
+/// let robots = Robots::new()
+///     .disallow("/private/")
+///     .allow("/private/press/")
+///     .sitemap("https://example.com/sitemap.xml");
+/// assert_eq!(
+///     robots.to_string(),
+///     "User-agent: *\n\
+///      Allow: /private/press/\n\
+///      Disallow: /private/\n\
+///      Sitemap: https://example.com/sitemap.xml\n"
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Robots {
+    allow: Vec<String>,
+    disallow: Vec<String>,
+    sitemaps: Vec<String>,
+}
+
+impl Robots {
+    /// Allows crawling everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows paths starting with `path`, overriding a broader disallow.
+    pub fn allow(mut self, path: impl Into<String>) -> Self {
+        self.allow.push(path.into());
+        self
+    }
+
+    /// Asks crawlers to stay out of paths starting with `path`.
+    pub fn disallow(mut self, path: impl Into<String>) -> Self {
+        self.disallow.push(path.into());
+        self
+    }
+
+    /// Points crawlers at a sitemap.
+    pub fn sitemap(mut self, url: impl Into<String>) -> Self {
+        self.sitemaps.push(url.into());
+        self
+    }
+}
+
+impl std::fmt::Display for Robots {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "User-agent: *")?;
+        for path in &self.allow {
+            writeln!(f, "Allow: {}", path)?;
+        }
+        for path in &self.disallow {
+            writeln!(f, "Disallow: {}", path)?;
+        }
+        // An empty Disallow is the explicit "crawl everything"
+        if self.allow.is_empty() && self.disallow.is_empty() {
+            writeln!(f, "Disallow:")?;
+        }
+        for url in &self.sitemaps {
+            writeln!(f, "Sitemap: {}", url)?;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps the file handler, answering its 404s for the robots file and icon.
+pub(crate) struct Defaults {
+    inner: Box<dyn Handler>,
+    robots: Option<String>,
+    favicon: bool,
+}
+
+impl Defaults {
+    pub(crate) fn new(inner: Box<dyn Handler>, robots: Option<&Robots>, favicon: bool) -> Self {
+        Self {
+            inner,
+            robots: robots.map(Robots::to_string),
+            favicon,
+        }
+    }
+
+    /// The default for `req`, if there is one.
+    fn fallback(&self, req: &Request) -> Option<Response> {
+        let (body, content_type) = match req.path() {
+            ROBOTS_PATH => (
+                self.robots.as_ref()?.as_bytes(),
+                "text/plain; charset=utf-8",
+            ),
+            FAVICON_PATH if self.favicon => (FAVICON, "image/x-icon"),
+            _ => return None,
+        };
+        let response = Response::new()
+            .status(StatusCode::OK)
+            .content_type(content_type)
+            .header("Cache-Control", "public, max-age=86400")
+            .content_length(body.len());
+        Some(match req.method {
+            HttpMethod::HEAD => response,
+            _ => response.body(body.to_vec()),
+        })
+    }
+}
+
+impl Handler for Defaults {
+    fn handle(&self, req: &Request) -> Response {
+        let response = self.inner.handle(req);
+        if response.status != StatusCode::NOT_FOUND
+            || !matches!(req.method, HttpMethod::GET | HttpMethod::HEAD)
+        {
+            return response;
+        }
+        self.fallback(req).unwrap_or(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn not_found(_req: &Request) -> Response {
+        Response::new().status(StatusCode::NOT_FOUND)
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        let mut body = Vec::new();
+        response.body.into_reader().read_to_end(&mut body).unwrap();
+        body
+    }
+
+    #[test]
+    fn test_robots_default_allows_everything() {
+        assert_eq!(Robots::new().to_string(), "User-agent: *\nDisallow:\n");
+    }
+
+    #[test]
+    fn test_fills_in_missing_files() {
+        let defaults = Defaults::new(Box::new(not_found), Some(&Robots::new()), true);
+        let response = defaults.handle(&Request::builder().path(ROBOTS_PATH).build());
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(body(response), b"User-agent: *\nDisallow:\n");
+
+        let response = defaults.handle(&Request::builder().path(FAVICON_PATH).build());
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            response.headers.get("Content-Type").map(String::as_str),
+            Some("image/x-icon")
+        );
+        assert_eq!(body(response), FAVICON);
+
+        let response = defaults.handle(&Request::builder().path("/other.txt").build());
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_real_files_and_disabled_defaults_win() {
+        let found = |_req: &Request| {
+            Response::new()
+                .status(StatusCode::OK)
+                .body(b"real".to_vec())
+        };
+        let defaults = Defaults::new(Box::new(found), Some(&Robots::new()), true);
+        let response = defaults.handle(&Request::builder().path(ROBOTS_PATH).build());
+        assert_eq!(body(response), b"real");
+
+        let defaults = Defaults::new(Box::new(not_found), None, false);
+        for path in [ROBOTS_PATH, FAVICON_PATH] {
+            let response = defaults.handle(&Request::builder().path(path).build());
+            assert_eq!(response.status, StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[test]
+    fn test_favicon_is_an_icon() {
+        // ICONDIR: reserved 0, type 1 (icon), one image
+        assert_eq!(&FAVICON[..6], &[0, 0, 1, 0, 1, 0]);
+    }
+}
@@ -0,0 +1,296 @@
+/*
+* Dump module
+*
+* Raw traffic in the log for troubleshooting odd clients (`--dump-http`).
+* The bytes a connection reads and writes are copied aside as they go
+* through, and once it is answered both heads are logged as they were on
+* the wire, header order and spelling included, in the style of `curl -v`:
+*
+*   > POST /upload HTTP/1.1
+*   > Authorization: [redacted]
+*   > Content-Length: 5120
+*   >
+*   > {"name": "report.pdf", ...
+*   > [4096 more body bytes]
+*   < HTTP/1.1 201 Created
+*   < Content-Length: 0
+*   <
+*
+* Requests that fail to parse are dumped too, which is usually the point.
+* Bodies are cut after 1 KiB by default, control characters are escaped,
+* and the values of `Authorization`, `Proxy-Authorization`, `Cookie`,
+* `Set-Cookie` and the `sig` of a signed URL are replaced unless secrets
+* are asked for. Copying costs a
+* buffer per connection, so keep it for debugging sessions.
+*/
+
+use tracing::info;
+
+/// Body bytes shown per request or response unless configured otherwise.
+pub const DEFAULT_BODY_LIMIT: usize = 1024;
+
+/// Headers whose values are hidden by default.
+pub const REDACTED_HEADERS: &[&str] = &[
+    "Authorization",
+    "Proxy-Authorization",
+    "Cookie",
+    "Set-Cookie",
+];
+
+/// Query parameters whose values are hidden by default.
+pub const REDACTED_QUERY_PARAMS: &[&str] = &["sig"];
+
+/// Head bytes kept per direction; a longer head is shown cut.
+const HEAD_LIMIT: usize = 16 * 1024;
+
+/// What to log of each exchange.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::dump::HttpDump;
+///
+/// let dump = HttpDump::new().body_limit(4);
+/// let raw = b"POST /a HTTP/1.1\r\nCookie: id=1\r\n\r\nhello";
+/// assert_eq!(
+///     dump.render('>', raw, raw.len() as u64),
+///     "> POST /a HTTP/1.1\n> Cookie: [redacted]\n>\n> hell\n> [1 more body bytes]"
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct HttpDump {
+    body_limit: usize,
+    redact: bool,
+}
+
+impl Default for HttpDump {
+    fn default() -> Self {
+        Self {
+            body_limit: DEFAULT_BODY_LIMIT,
+            redact: true,
+        }
+    }
+}
+
+impl HttpDump {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shows at most `limit` bytes of each body; 0 shows none.
+    pub fn body_limit(mut self, limit: usize) -> Self {
+        self.body_limit = limit;
+        self
+    }
+
+    /// Logs the values of [`REDACTED_HEADERS`] and
+    /// [`REDACTED_QUERY_PARAMS`] too.
+    pub fn show_secrets(mut self) -> Self {
+        self.redact = false;
+        self
+    }
+
+    /// `raw`, the first bytes of a message whose full size is `total`, as
+    /// log lines starting with `marker`.
+    pub fn render(&self, marker: char, raw: &[u8], total: u64) -> String {
+        let (head, body) = match find(raw, b"\r\n\r\n").or_else(|| find(raw, b"\n\n")) {
+            Some(end) => {
+                let separator = if raw[end] == b'\r' { 4 } else { 2 };
+                (&raw[..end], Some(&raw[end + separator..]))
+            }
+            None => (raw, None),
+        };
+        let mut lines: Vec<String> = String::from_utf8_lossy(head)
+            .split('\n')
+            .enumerate()
+            .map(|(i, line)| {
+                let line = line.trim_end_matches('\r');
+                let line = if i == 0 {
+                    self.start_line(line)
+                } else {
+                    self.header_line(line)
+                };
+                format!("{} {}", marker, line)
+            })
+            .collect();
+        let Some(body) = body else {
+            lines.push(format!(
+                "{} [no end of head after {} bytes]",
+                marker,
+                raw.len()
+            ));
+            return lines.join("\n");
+        };
+        lines.push(marker.to_string());
+        let body_total = total.saturating_sub((raw.len() - body.len()) as u64);
+        let shown = &body[..body.len().min(self.body_limit)];
+        if !shown.is_empty() {
+            for line in escape(shown).split('\n') {
+                lines.push(format!("{} {}", marker, line));
+            }
+        }
+        let more = body_total.saturating_sub(shown.len() as u64);
+        if more > 0 {
+            lines.push(format!("{} [{} more body bytes]", marker, more));
+        }
+        lines.join("\n")
+    }
+
+    /// `line`, a request or status line, with control characters escaped
+    /// and secret query values hidden.
+    fn start_line(&self, line: &str) -> String {
+        let Some(start) = line.find('?').filter(|_| self.redact) else {
+            return escape(line.as_bytes());
+        };
+        let end = line[start..]
+            .find([' ', '#'])
+            .map_or(line.len(), |end| start + end);
+        let query: Vec<String> = line[start + 1..end]
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((name, _)) if REDACTED_QUERY_PARAMS.contains(&name) => {
+                    format!("{}=[redacted]", name)
+                }
+                _ => pair.to_string(),
+            })
+            .collect();
+        let line = format!("{}?{}{}", &line[..start], query.join("&"), &line[end..]);
+        escape(line.as_bytes())
+    }
+
+    /// `line` with control characters escaped and a secret value hidden.
+    fn header_line(&self, line: &str) -> String {
+        if self.redact {
+            if let Some((name, _)) = line.split_once(':') {
+                if REDACTED_HEADERS
+                    .iter()
+                    .any(|secret| secret.eq_ignore_ascii_case(name.trim()))
+                {
+                    return format!("{}: [redacted]", escape(name.as_bytes()));
+                }
+            }
+        }
+        escape(line.as_bytes())
+    }
+
+    /// Starts copying a connection's traffic.
+    pub(crate) fn recording(&self) -> Recording {
+        Recording {
+            limit: HEAD_LIMIT + self.body_limit,
+            received: Vec::new(),
+            received_total: 0,
+            sent: Vec::new(),
+            sent_total: 0,
+        }
+    }
+
+    /// Logs what `recording` saw, if the client sent anything.
+    pub(crate) fn log(&self, recording: &Recording) {
+        if recording.received_total == 0 {
+            return;
+        }
+        info!(
+            "Request:\n{}",
+            self.render('>', &recording.received, recording.received_total)
+        );
+        if recording.sent_total > 0 {
+            info!(
+                "Response:\n{}",
+                self.render('<', &recording.sent, recording.sent_total)
+            );
+        }
+    }
+}
+
+/// The first bytes read and written on one connection.
+#[derive(Debug)]
+pub(crate) struct Recording {
+    limit: usize,
+    received: Vec<u8>,
+    received_total: u64,
+    sent: Vec<u8>,
+    sent_total: u64,
+}
+
+impl Recording {
+    pub(crate) fn received(&mut self, bytes: &[u8]) {
+        self.received_total += bytes.len() as u64;
+        keep(&mut self.received, bytes, self.limit);
+    }
+
+    pub(crate) fn sent(&mut self, bytes: &[u8]) {
+        self.sent_total += bytes.len() as u64;
+        keep(&mut self.sent, bytes, self.limit);
+    }
+}
+
+fn keep(kept: &mut Vec<u8>, bytes: &[u8], limit: usize) {
+    let room = limit.saturating_sub(kept.len());
+    kept.extend_from_slice(&bytes[..bytes.len().min(room)]);
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// `bytes` as text, with invalid UTF-8 replaced and control characters
+/// other than newlines and tabs escaped.
+fn escape(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for c in String::from_utf8_lossy(bytes).chars() {
+        if c.is_control() && c != '\n' && c != '\t' {
+            text.extend(c.escape_default());
+        } else {
+            text.push(c);
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction() {
+        let raw =
+            b"GET /f?expires=9&sig=def HTTP/1.1\r\nauthorization: Bearer abc\r\nX-Token: t\r\n\r\n";
+        let dump = HttpDump::new();
+        let rendered = dump.render('>', raw, raw.len() as u64);
+        assert!(rendered.starts_with("> GET /f?expires=9&sig=[redacted] HTTP/1.1\n"));
+        assert!(!rendered.contains("def"));
+        assert!(rendered.contains("> authorization: [redacted]"));
+        assert!(!rendered.contains("abc"));
+        assert!(rendered.contains("> X-Token: t"));
+        assert!(rendered.ends_with(">"));
+        let rendered = dump.show_secrets().render('>', raw, raw.len() as u64);
+        assert!(rendered.contains("Bearer abc"));
+        assert!(rendered.contains("sig=def"));
+    }
+
+    #[test]
+    fn test_binary_and_cut_heads() {
+        let dump = HttpDump::new().body_limit(0);
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n\x00\x01\x02";
+        assert_eq!(
+            dump.render('<', raw, raw.len() as u64),
+            "< HTTP/1.1 200 OK\n< Content-Length: 3\n<\n< [3 more body bytes]"
+        );
+        let rendered = HttpDump::new().render('>', b"GET /\x1b[31m HTTP/1.1\r\nHo", 23);
+        assert_eq!(
+            rendered,
+            "> GET /\\u{1b}[31m HTTP/1.1\n> Ho\n> [no end of head after 23 bytes]"
+        );
+    }
+
+    #[test]
+    fn test_recording_limit() {
+        let mut recording = HttpDump::new().body_limit(10).recording();
+        recording.sent(&[b'a'; HEAD_LIMIT]);
+        recording.sent(&[b'b'; 100]);
+        assert_eq!(recording.sent.len(), HEAD_LIMIT + 10);
+        assert_eq!(recording.sent_total, HEAD_LIMIT as u64 + 100);
+    }
+}
@@ -0,0 +1,62 @@
+/*
+* Embed module
+*
+* Compiles a directory into the binary so a single executable can carry its
+* own site. A build script lists the files with `write_manifest`, and
+* `include_site!` turns that list into a `MemorySource`:
+*
+*   build.rs:  write_manifest("public", OUT_DIR/site.rs)
+*   main.rs:   Server::builder().file_source(include_site!("site.rs"))
+*
+* With the `embed` feature, this crate's own build script embeds the
+* directory named by `FILE_SHOVER_EMBED_DIR`, served by `file-shover
+* --embedded`.
+*/
+
+mod codegen;
+
+pub use codegen::write_manifest;
+
+/// Builds a [`MemorySource`](crate::files::MemorySource) from a manifest
+/// that a build script wrote into `OUT_DIR` with [`write_manifest`].
+///
+/// The file contents are part of the binary and are served without copying.
+///
+/// # Examples
+///
+/// ```ignore
+/// use file_shover::{include_site, Server};
+///
+/// let server = Server::builder()
+///     .file_source(include_site!("site.rs"))
+///     .build()?;
+/// ```
+#[macro_export]
+macro_rules! include_site {
+    ($manifest:literal) => {
+        $crate::files::MemorySource::from_static(include!(concat!(env!("OUT_DIR"), "/", $manifest)))
+    };
+}
+
+/// The site embedded at build time from `FILE_SHOVER_EMBED_DIR`; empty if
+/// the variable was not set.
+#[cfg(feature = "embed")]
+pub fn site() -> crate::files::MemorySource {
+    crate::include_site!("embedded_site.rs")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_manifest_lists_files() {
+        let out = std::env::temp_dir().join(format!("file-shover-site-{}.rs", std::process::id()));
+        write_manifest("test-sites/one-file".as_ref(), &out).unwrap();
+        let manifest = fs::read_to_string(&out).unwrap();
+        fs::remove_file(&out).unwrap();
+        assert!(manifest.starts_with("&["));
+        assert!(manifest.contains("(\"index.html\", include_bytes!("));
+    }
+}
@@ -0,0 +1,74 @@
+/*
+* Embedded site code generation
+*
+* Shared by this crate's build script (through `#[path]`) and by downstream
+* build scripts, so it may only use std.
+*/
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Writes a Rust expression listing every file below `dir`, for
+/// [`include_site!`](crate::include_site) to pick up from `OUT_DIR`.
+///
+/// Call it from a build script. File contents are pulled in with
+/// `include_bytes!`, and `cargo:rerun-if-changed` lines are printed so the
+/// site is re-embedded when a file changes. Hidden files and directories
+/// (starting with `.`) are skipped.
+///
+/// # Examples
+///
+/// ```no_run
+/// // build.rs
+/// let out = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("site.rs");
+/// file_shover::embed::write_manifest("public".as_ref(), &out).unwrap();
+/// ```
+///
+/// # Errors
+///
+/// Returns any error from reading the directory or writing `out`.
+pub fn write_manifest(dir: &Path, out: &Path) -> io::Result<()> {
+    let dir = dir.canonicalize()?;
+    let mut files = Vec::new();
+    collect(&dir, &mut files)?;
+    files.sort();
+
+    let mut code = String::from("&[\n");
+    for path in &files {
+        let name = path
+            .strip_prefix(&dir)
+            .map_err(io::Error::other)?
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        code.push_str(&format!(
+            "    ({:?}, include_bytes!({:?}) as &[u8]),\n",
+            name,
+            path.to_string_lossy()
+        ));
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+    code.push_str("]\n");
+    fs::write(out, code)
+}
+
+/// Collects files below `dir`, printing a rerun line for every directory so
+/// added and removed files are noticed too.
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    println!("cargo:rerun-if-changed={}", dir.display());
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            collect(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
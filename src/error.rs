@@ -0,0 +1,71 @@
+/*
+* Error module
+*
+* The crate-wide error returned when building or running a server. Each
+* module keeps its own detailed error type; this enum only groups them by
+* origin so embedders can match on one type.
+*/
+
+use crate::config::ConfigError;
+use crate::message::RequestError;
+use std::fmt;
+use std::io;
+
+/// Errors from configuring, starting or running a server.
+#[derive(Debug)]
+pub enum ServerError {
+    /// Socket, file or thread pool failure.
+    Io(io::Error),
+    /// A request could not be parsed.
+    Parse(RequestError),
+    /// Inconsistent or invalid settings.
+    Config(ConfigError),
+    /// Certificate, key or TLS session failure.
+    Tls(rustls::Error),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Io(err) => write!(f, "IO error: {}", err),
+            ServerError::Parse(err) => write!(f, "Parse error: {}", err),
+            ServerError::Config(err) => write!(f, "{}", err),
+            ServerError::Tls(err) => write!(f, "TLS error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServerError::Io(err) => Some(err),
+            ServerError::Parse(err) => Some(err),
+            ServerError::Config(err) => Some(err),
+            ServerError::Tls(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(err: io::Error) -> Self {
+        ServerError::Io(err)
+    }
+}
+
+impl From<RequestError> for ServerError {
+    fn from(err: RequestError) -> Self {
+        ServerError::Parse(err)
+    }
+}
+
+impl From<ConfigError> for ServerError {
+    fn from(err: ConfigError) -> Self {
+        ServerError::Config(err)
+    }
+}
+
+impl From<rustls::Error> for ServerError {
+    fn from(err: rustls::Error) -> Self {
+        ServerError::Tls(err)
+    }
+}
@@ -0,0 +1,654 @@
+/*
+* FastCGI module
+*
+* A FastCGI client for the classic static + PHP setup: requests for `*.php`
+* files under the document root are forwarded to a FastCGI responder such as
+* php-fpm, everything else is served as files.
+*
+*   --fastcgi 127.0.0.1:9000              TCP
+*   --fastcgi unix:/run/php/php-fpm.sock  Unix socket
+*
+* `/blog/post.php/2024?x=1` runs `<root>/blog/post.php` with
+* SCRIPT_FILENAME=<root>/blog/post.php, SCRIPT_NAME=/blog/post.php,
+* PATH_INFO=/2024 and DOCUMENT_ROOT=<root>; a directory request runs its
+* `index.php`. The script must exist as a file under the root, so
+* `/uploads/avatar.jpg/x.php` runs nothing. When php-fpm sees the files at
+* another path (a container), the document root sent to it can be changed.
+*
+* Every request opens a fresh connection and sends one FCGI_RESPONDER
+* request (FastCGI 1.0):
+*
+*   BEGIN_REQUEST, PARAMS..., PARAMS(empty), STDIN..., STDIN(empty)  -->
+*   <--  STDOUT..., STDERR..., END_REQUEST
+*
+* The body is read in full first, as for CGI scripts. STDOUT carries CGI
+* output, whose headers are parsed and whose body is streamed to the client;
+* STDERR lines are logged as warnings.
+*/
+
+use crate::cgi::{self, Script};
+use crate::config::ConfigError;
+use crate::message::{Body, HttpMethod, Request, Response, StatusCode, DEFAULT_BAD_GATEWAY_BODY};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const VERSION: u8 = 1;
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+const RESPONDER: u16 = 1;
+const REQUEST_ID: u16 = 1;
+
+/// Largest record content.
+const MAX_CONTENT: usize = 65_535;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where the FastCGI responder listens: `host:port` or `unix:<path>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FastCgiAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for FastCgiAddress {
+    type Err = ConfigError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_prefix("unix:") {
+            Some("") => Err(ConfigError::Invalid(
+                "FastCGI socket path is empty".to_string(),
+            )),
+            Some(path) => Ok(FastCgiAddress::Unix(PathBuf::from(path))),
+            None if value
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok()) =>
+            {
+                Ok(FastCgiAddress::Tcp(value.to_string()))
+            }
+            None => Err(ConfigError::Invalid(format!(
+                "FastCGI address {:?} is neither host:port nor unix:<path>",
+                value
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for FastCgiAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FastCgiAddress::Tcp(address) => write!(f, "{}", address),
+            FastCgiAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Forwards `*.php` requests to a FastCGI responder.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::fastcgi::FastCgi;
+///
+/// let php = FastCgi::new("127.0.0.1:9000".parse()?, "/srv/www")
+///     .document_root("/var/www/html");
+/// assert_eq!(php.find("/style.css"), None);
+/// # Ok::<(), file_shover::config::ConfigError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct FastCgi {
+    address: FastCgiAddress,
+    root: PathBuf,
+    document_root: Option<PathBuf>,
+    timeout: Duration,
+}
+
+impl FastCgi {
+    /// Runs the `.php` files under `root` on the responder at `address`.
+    pub fn new(address: FastCgiAddress, root: impl Into<PathBuf>) -> Self {
+        Self {
+            address,
+            root: root.into(),
+            document_root: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// The root as the responder sees it, if not the same path as locally.
+    pub fn document_root(mut self, path: impl Into<PathBuf>) -> Self {
+        self.document_root = Some(path.into());
+        self
+    }
+
+    /// Gives up on a responder that sends nothing for `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The responder address.
+    pub fn address(&self) -> &FastCgiAddress {
+        &self.address
+    }
+
+    /// Resolves a request path to the PHP script it runs, if any.
+    ///
+    /// The first path segment ending in `.php` names the script and the rest
+    /// becomes `PATH_INFO`; a path ending in `/` runs that directory's
+    /// `index.php`. The script must be a file under the root.
+    pub fn find(&self, path: &str) -> Option<Script> {
+        let (name, path_info) = match path.match_indices(".php").find(|(end, _)| {
+            let rest = &path[end + 4..];
+            rest.is_empty() || rest.starts_with('/')
+        }) {
+            Some((end, _)) => (&path[..end + 4], &path[end + 4..]),
+            None if path.ends_with('/') => {
+                let index = format!("{}index.php", path);
+                let file = self.root.join(index.trim_start_matches('/'));
+                return (!is_hidden(path) && file.is_file()).then(|| self.script(index, file, ""));
+            }
+            None => return None,
+        };
+        let file = self.root.join(name.trim_start_matches('/'));
+        (!is_hidden(name) && file.is_file()).then(|| self.script(name.to_string(), file, path_info))
+    }
+
+    fn script(&self, name: String, file: PathBuf, path_info: &str) -> Script {
+        let path = match &self.document_root {
+            Some(document_root) => document_root.join(name.trim_start_matches('/')),
+            None => file,
+        };
+        Script {
+            path,
+            name,
+            path_info: path_info.to_string(),
+        }
+    }
+
+    /// Forwards `req` to the responder, reading the request body from
+    /// `conn`.
+    ///
+    /// The response streams the script's output; an unreachable responder
+    /// or invalid output answers 502.
+    pub fn run<C: BufRead + Write>(
+        &self,
+        req: &Request,
+        script: &Script,
+        client_ip: IpAddr,
+        scheme: &str,
+        conn: &mut C,
+    ) -> Response {
+        let body = match cgi::read_body(req, conn) {
+            Ok(body) => body,
+            Err(e) => {
+                debug!("Failed to read FastCGI request body: {}", e);
+                return e.response();
+            }
+        };
+        let mut params = cgi::environment(req, script, &body, client_ip, scheme);
+        let document_root = self.document_root.as_deref().unwrap_or(&self.root);
+        params.push((
+            "DOCUMENT_ROOT".to_string(),
+            document_root.display().to_string(),
+        ));
+        params.push(("REDIRECT_STATUS".to_string(), "200".to_string()));
+
+        let mut stream = match self.connect() {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("FastCGI responder {} unavailable: {}", self.address, e);
+                return Response::error(StatusCode::BAD_GATEWAY, DEFAULT_BAD_GATEWAY_BODY);
+            }
+        };
+        if let Err(e) = write_request(&mut stream, &params, &body) {
+            warn!("Failed to send request to {}: {}", self.address, e);
+            return Response::error(StatusCode::BAD_GATEWAY, DEFAULT_BAD_GATEWAY_BODY);
+        }
+        let mut stdout = BufReader::new(Records {
+            stream: BufReader::new(stream),
+            remaining: 0,
+            padding: 0,
+            script: script.name.clone(),
+            ended: false,
+        });
+        let response = match cgi::read_headers(&mut stdout) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Invalid FastCGI output for {}: {}", script.name, e);
+                return Response::error(StatusCode::BAD_GATEWAY, DEFAULT_BAD_GATEWAY_BODY);
+            }
+        };
+        if req.method == HttpMethod::HEAD {
+            return response;
+        }
+        match response
+            .headers
+            .get("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok())
+        {
+            Some(length) => response.body(Body::reader(stdout.take(length))),
+            None => response.body(Body::chunked(stdout)),
+        }
+    }
+
+    fn connect(&self) -> io::Result<Stream> {
+        let stream = match &self.address {
+            FastCgiAddress::Tcp(address) => {
+                let address = address
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no address"))?;
+                let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                Stream::Tcp(stream)
+            }
+            #[cfg(unix)]
+            FastCgiAddress::Unix(path) => {
+                let stream = UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                Stream::Unix(stream)
+            }
+            #[cfg(not(unix))]
+            FastCgiAddress::Unix(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "Unix sockets are not supported on this platform",
+                ))
+            }
+        };
+        Ok(stream)
+    }
+}
+
+/// Dot files and directories are never run.
+fn is_hidden(path: &str) -> bool {
+    path.split('/').any(|segment| segment.starts_with('.'))
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Writes one record; `content` must fit in a record.
+fn write_record<W: Write>(out: &mut W, kind: u8, content: &[u8]) -> io::Result<()> {
+    let length = content.len() as u16;
+    let padding = (8 - content.len() % 8) % 8;
+    let [length_hi, length_lo] = length.to_be_bytes();
+    let [id_hi, id_lo] = REQUEST_ID.to_be_bytes();
+    out.write_all(&[
+        VERSION,
+        kind,
+        id_hi,
+        id_lo,
+        length_hi,
+        length_lo,
+        padding as u8,
+        0,
+    ])?;
+    out.write_all(content)?;
+    out.write_all(&[0; 8][..padding])
+}
+
+/// Writes a stream as records of at most `MAX_CONTENT` bytes, then the empty
+/// record that ends it.
+fn write_stream<W: Write>(out: &mut W, kind: u8, data: &[u8]) -> io::Result<()> {
+    for chunk in data.chunks(MAX_CONTENT) {
+        write_record(out, kind, chunk)?;
+    }
+    write_record(out, kind, &[])
+}
+
+/// Appends a name-value pair in FastCGI's length-prefixed encoding.
+fn encode_pair(out: &mut Vec<u8>, name: &str, value: &str) {
+    for length in [name.len(), value.len()] {
+        if length < 128 {
+            out.push(length as u8);
+        } else {
+            out.extend_from_slice(&(length as u32 | 0x8000_0000).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn write_request<W: Write>(
+    out: &mut W,
+    params: &[(String, String)],
+    body: &[u8],
+) -> io::Result<()> {
+    let [role_hi, role_lo] = RESPONDER.to_be_bytes();
+    // Flags 0: the responder closes the connection when done
+    write_record(out, BEGIN_REQUEST, &[role_hi, role_lo, 0, 0, 0, 0, 0, 0])?;
+    let mut encoded = Vec::new();
+    for (name, value) in params {
+        encode_pair(&mut encoded, name, value);
+    }
+    write_stream(out, PARAMS, &encoded)?;
+    write_stream(out, STDIN, body)?;
+    out.flush()
+}
+
+/// The STDOUT stream of a response, logging STDERR on the way.
+struct Records<R> {
+    stream: R,
+    /// STDOUT bytes left in the current record, and the padding after them.
+    remaining: usize,
+    padding: usize,
+    script: String,
+    ended: bool,
+}
+
+impl<R: BufRead> Records<R> {
+    fn skip(&mut self, length: usize) -> io::Result<()> {
+        let skipped = io::copy(&mut (&mut self.stream).take(length as u64), &mut io::sink())?;
+        if skipped < length as u64 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for Records<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.ended || buf.is_empty() {
+                return Ok(0);
+            }
+            if self.remaining > 0 {
+                let limit = buf.len().min(self.remaining);
+                let n = self.stream.read(&mut buf[..limit])?;
+                if n == 0 {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+                self.remaining -= n;
+                if self.remaining == 0 {
+                    let padding = std::mem::take(&mut self.padding);
+                    self.skip(padding)?;
+                }
+                return Ok(n);
+            }
+            let mut header = [0; 8];
+            self.stream.read_exact(&mut header)?;
+            let kind = header[1];
+            let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+            let padding = header[6] as usize;
+            match kind {
+                STDOUT => {
+                    self.remaining = length;
+                    self.padding = padding;
+                    if length == 0 {
+                        self.skip(padding)?;
+                    }
+                }
+                STDERR => {
+                    let mut message = vec![0; length];
+                    self.stream.read_exact(&mut message)?;
+                    self.skip(padding)?;
+                    for line in String::from_utf8_lossy(&message).lines() {
+                        warn!("{}: {}", self.script, line);
+                    }
+                }
+                END_REQUEST => {
+                    self.skip(length + padding)?;
+                    self.ended = true;
+                }
+                _ => self.skip(length + padding)?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn site(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "file-shover-fastcgi-{}-{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(root.join("blog")).unwrap();
+        std::fs::write(root.join("index.php"), "<?php").unwrap();
+        std::fs::write(root.join("blog/post.php"), "<?php").unwrap();
+        std::fs::write(root.join("avatar.jpg"), "jpeg").unwrap();
+        root
+    }
+
+    /// Decodes name-value pairs.
+    fn decode_pairs(mut data: &[u8]) -> Vec<(String, String)> {
+        let length = |data: &mut &[u8]| {
+            if data[0] < 128 {
+                let length = data[0] as usize;
+                *data = &data[1..];
+                length
+            } else {
+                let length = u32::from_be_bytes([data[0] & 0x7f, data[1], data[2], data[3]]);
+                *data = &data[4..];
+                length as usize
+            }
+        };
+        let mut pairs = Vec::new();
+        while !data.is_empty() {
+            let name_length = length(&mut data);
+            let value_length = length(&mut data);
+            let name = String::from_utf8(data[..name_length].to_vec()).unwrap();
+            let value =
+                String::from_utf8(data[name_length..name_length + value_length].to_vec()).unwrap();
+            data = &data[name_length + value_length..];
+            pairs.push((name, value));
+        }
+        pairs
+    }
+
+    #[test]
+    fn test_find() {
+        let root = site("find");
+        let php = FastCgi::new("127.0.0.1:9000".parse().unwrap(), &root);
+
+        let script = php.find("/blog/post.php/2024/05").unwrap();
+        assert_eq!(script.path, root.join("blog/post.php"));
+        assert_eq!(script.name, "/blog/post.php");
+        assert_eq!(script.path_info, "/2024/05");
+        assert_eq!(php.find("/").unwrap().name, "/index.php");
+        assert_eq!(php.find("/blog/"), None);
+        assert_eq!(php.find("/missing.php"), None);
+        assert_eq!(php.find("/avatar.jpg/x.php"), None);
+        assert_eq!(php.find("/blog/post.phpx"), None);
+
+        let php = php.document_root("/var/www/html");
+        assert_eq!(
+            php.find("/blog/post.php").unwrap().path,
+            PathBuf::from("/var/www/html/blog/post.php")
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_address() {
+        assert_eq!(
+            "127.0.0.1:9000".parse::<FastCgiAddress>().unwrap(),
+            FastCgiAddress::Tcp("127.0.0.1:9000".to_string())
+        );
+        assert_eq!(
+            "unix:/run/php/fpm.sock".parse::<FastCgiAddress>().unwrap(),
+            FastCgiAddress::Unix(PathBuf::from("/run/php/fpm.sock"))
+        );
+        assert!("unix:".parse::<FastCgiAddress>().is_err());
+        assert!("localhost".parse::<FastCgiAddress>().is_err());
+    }
+
+    #[test]
+    fn test_run_against_responder() {
+        let root = site("run");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let responder = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut params = Vec::new();
+            let mut stdin = Vec::new();
+            loop {
+                let mut header = [0; 8];
+                stream.read_exact(&mut header).unwrap();
+                let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+                let mut content = vec![0; length + header[6] as usize];
+                stream.read_exact(&mut content).unwrap();
+                content.truncate(length);
+                match header[1] {
+                    PARAMS => params.extend(content),
+                    STDIN if length == 0 => break,
+                    STDIN => stdin.extend(content),
+                    _ => {}
+                }
+            }
+            let mut out = stream.into_inner();
+            write_record(&mut out, STDERR, b"PHP Notice: hi\n").unwrap();
+            write_record(
+                &mut out,
+                STDOUT,
+                b"Status: 201 Created\r\nContent-Type: text/plain\r\n\r\nbo",
+            )
+            .unwrap();
+            write_record(&mut out, STDOUT, b"dy").unwrap();
+            write_record(&mut out, STDOUT, b"").unwrap();
+            write_record(&mut out, END_REQUEST, &[0; 8]).unwrap();
+            (decode_pairs(&params), stdin)
+        });
+
+        let php = FastCgi::new(address.parse().unwrap(), &root);
+        let raw = b"POST /blog/post.php/x?page=2 HTTP/1.1\r\nContent-Length: 4\r\n\r\ndata";
+        let mut conn = io::Cursor::new(raw.to_vec());
+        let req = Request::from_reader(&mut conn).unwrap();
+        let script = php.find(req.path()).unwrap();
+        let mut conn = BufReader::new(conn);
+        let mut sink = Vec::new();
+        let mut duplex = Duplex(&mut conn, &mut sink);
+        let response = php.run(
+            &req,
+            &script,
+            "192.0.2.1".parse().unwrap(),
+            "http",
+            &mut duplex,
+        );
+        assert_eq!(response.status, StatusCode::CREATED);
+        let mut body = String::new();
+        response
+            .body
+            .into_reader()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "body");
+
+        let (params, stdin) = responder.join().unwrap();
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+        assert_eq!(stdin, b"data");
+        assert_eq!(
+            param("SCRIPT_FILENAME"),
+            Some(root.join("blog/post.php").display().to_string())
+        );
+        assert_eq!(param("DOCUMENT_ROOT"), Some(root.display().to_string()));
+        assert_eq!(param("PATH_INFO").as_deref(), Some("/x"));
+        assert_eq!(param("QUERY_STRING").as_deref(), Some("page=2"));
+        assert_eq!(param("CONTENT_LENGTH").as_deref(), Some("4"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_unreachable_responder() {
+        let root = site("down");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let php = FastCgi::new(address.parse().unwrap(), &root);
+        let req = Request::builder().path("/index.php").build();
+        let script = php.find(req.path()).unwrap();
+        let mut conn = Duplex(&mut io::Cursor::new(Vec::new()), &mut Vec::new());
+        let response = php.run(
+            &req,
+            &script,
+            "192.0.2.1".parse().unwrap(),
+            "http",
+            &mut conn,
+        );
+        assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Reads from one side, writes to the other.
+    struct Duplex<'a, R, W>(&'a mut R, &'a mut W);
+
+    impl<R: BufRead, W> Read for Duplex<'_, R, W> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl<R: BufRead, W> BufRead for Duplex<'_, R, W> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            self.0.fill_buf()
+        }
+        fn consume(&mut self, amount: usize) {
+            self.0.consume(amount)
+        }
+    }
+
+    impl<R, W: Write> Write for Duplex<'_, R, W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -4,6 +4,19 @@
 * Provides utilities for working with files within a designated root directory.
 * The FileTree struct offers safe file access by constraining operations to a root path.
 *
+* Where the bytes come from is up to a `FileSource`: the disk (`DiskSource`),
+* one file on disk (`SingleFileSource`), an in-memory map (`MemorySource`), a
+* stack of other sources (`LayeredSource`), or any other backend an embedder
+* plugs in. FileTree validates paths before they reach the source, so backends only
+* ever see clean relative paths such as `docs/index.html` ("" is the root).
+*
+* Excluded paths (`--exclude "*.log" --exclude node_modules`) are hidden
+* by FileTree itself: they are not found, not listed and not opened,
+* whatever the source holds, so every feature built on the tree (listings,
+* archives, search, the sitemap) leaves them out alike. A pattern excludes
+* everything below a matching directory too. A `.shoverignore` file at the
+* root (see the ignore module) hides paths the same way.
+*
 * This "first" version is primitive, it reads the file at every request.
 * It performs syscalls at every request which is not very efficient.
 * If we want to trade memory for speed, we can store those buffers in memory
@@ -12,9 +25,62 @@
 * Tradeoff: must update the buffers when files are changed on the disk.
 */
 
-use std::fs::{self, File, Metadata};
-use std::io::{BufReader, Error, Read};
+use crate::glob::Glob;
+use crate::ignore::IgnoreFile;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Error, ErrorKind, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// A backend that files are served from.
+///
+/// Paths are relative and already validated by [`FileTree`]: no leading `/`,
+/// no `.` or `..` segments, and `""` for the root directory.
+pub trait FileSource: Send + Sync {
+    /// Opens a file for reading.
+    fn open(&self, path: &str) -> Result<Box<dyn Read + Send>, Error>;
+
+    /// Opens a file for reading from byte `offset` on, for range requests.
+    ///
+    /// The default reads and discards everything before `offset`; sources
+    /// that can seek should override it.
+    fn open_at(&self, path: &str, offset: u64) -> Result<Box<dyn Read + Send>, Error> {
+        let mut reader = self.open(path)?;
+        io::copy(&mut reader.by_ref().take(offset), &mut io::sink())?;
+        Ok(reader)
+    }
+
+    /// Describes a file or directory.
+    fn metadata(&self, path: &str) -> Result<FileMetadata, Error>;
+
+    /// Lists a directory, sorted by name.
+    fn list(&self, path: &str) -> Result<Vec<DirEntry>, Error>;
+
+    /// Drops cached data for `path` and everything below it, or for every
+    /// path with `None`, and returns how many entries were dropped.
+    ///
+    /// Sources without a cache have nothing to drop.
+    fn purge(&self, _path: Option<&str>) -> usize {
+        0
+    }
+}
+
+/// What a [`FileSource`] knows about a file or directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileMetadata {
+    pub len: u64,
+    pub is_dir: bool,
+    pub modified: Option<SystemTime>,
+}
+
+/// One entry of a directory listing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirEntry {
+    pub name: String,
+    pub metadata: FileMetadata,
+}
 
 /// A file tree rooted at a specific directory path.
 ///
@@ -31,12 +97,14 @@ use std::path::{Path, PathBuf};
 /// Ok::<(), std::io::Error>(())
 /// ```
 pub struct FileTree {
-    root: PathBuf,
+    source: Box<dyn FileSource>,
+    excluded: Vec<Glob>,
+    ignore_file: Option<IgnoreFile>,
 }
 
 pub struct FileData {
-    pub reader: BufReader<File>,
-    pub metadata: Metadata,
+    pub reader: Box<dyn Read + Send>,
+    pub metadata: FileMetadata,
 }
 
 impl FileTree {
@@ -55,7 +123,132 @@ impl FileTree {
     /// let tree = FileTree::new(PathBuf::from("/home/user/documents"));
     /// ```
     pub fn new(root: PathBuf) -> Self {
-        Self { root }
+        Self::from_source(DiskSource::new(root))
+    }
+
+    /// Creates a FileTree over any backend.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::files::{FileTree, MemorySource};
+    ///
+    /// let tree = FileTree::from_source(MemorySource::new().with("index.html", "<h1>Hi</h1>"));
+    /// assert_eq!(tree.get_reader("/index.html")?.metadata.len, 11);
+    /// Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn from_source(source: impl FileSource + 'static) -> Self {
+        Self::boxed(Box::new(source))
+    }
+
+    pub(crate) fn boxed(source: Box<dyn FileSource>) -> Self {
+        Self {
+            source,
+            excluded: Vec::new(),
+            ignore_file: None,
+        }
+    }
+
+    /// Hides paths matching `pattern`, and everything below a matching
+    /// directory, as if they did not exist.
+    ///
+    /// Patterns without a leading `/` but with a `/` inside, such as
+    /// `node_modules/**`, are taken relative to the root.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::files::{FileTree, MemorySource};
+    ///
+    /// let tree = FileTree::from_source(
+    ///     MemorySource::new()
+    ///         .with("index.html", "")
+    ///         .with("debug.log", "")
+    ///         .with("node_modules/pkg/index.js", ""),
+    /// )
+    /// .exclude("*.log")
+    /// .exclude("node_modules/**");
+    /// assert!(tree.get_reader("/debug.log").is_err());
+    /// assert!(tree.get_reader("/node_modules/pkg/index.js").is_err());
+    /// assert_eq!(tree.list("/")?.len(), 1);
+    /// Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        let anchored = if pattern.contains('/') && !pattern.starts_with('/') {
+            Glob::new(&format!("/{}", pattern))
+        } else {
+            Glob::new(pattern)
+        };
+        self.excluded.push(anchored);
+        self
+    }
+
+    /// Also hides what the gitignore-style file `name` at the root lists
+    /// (see [`crate::ignore`]); the file is reread when it changes and never
+    /// served itself.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::files::{FileTree, MemorySource};
+    ///
+    /// let tree = FileTree::from_source(
+    ///     MemorySource::new()
+    ///         .with(".shoverignore", "drafts/\n")
+    ///         .with("drafts/post.html", "")
+    ///         .with("index.html", ""),
+    /// )
+    /// .ignore_file(".shoverignore");
+    /// assert!(tree.get_reader("/drafts/post.html").is_err());
+    /// assert!(tree.get_reader("/.shoverignore").is_err());
+    /// assert!(tree.get_reader("/index.html").is_ok());
+    /// ```
+    pub fn ignore_file(mut self, name: &str) -> Self {
+        self.ignore_file = Some(IgnoreFile::new(name));
+        self
+    }
+
+    /// Returns true if `path` (relative, as a source sees it) or one of its
+    /// parent directories is excluded. `is_dir` is only called when the
+    /// ignore file needs to know.
+    fn is_excluded(&self, path: &str, is_dir: impl FnOnce() -> bool) -> bool {
+        if path.is_empty() {
+            return false;
+        }
+        let path = format!("/{}", path);
+        if !self.excluded.is_empty()
+            && path
+                .match_indices('/')
+                .skip(1)
+                .map(|(end, _)| &path[..end])
+                .chain(std::iter::once(path.as_str()))
+                .any(|prefix| self.excluded.iter().any(|glob| glob.matches(prefix)))
+        {
+            return true;
+        }
+        match &self.ignore_file {
+            Some(ignore_file) => {
+                path[1..] == *ignore_file.name()
+                    || ignore_file
+                        .rules(self.source.as_ref())
+                        .is_ignored(&path, is_dir)
+            }
+            None => false,
+        }
+    }
+
+    /// Validates `path` and refuses excluded ones as `NotFound`.
+    fn visible<'a>(&self, path: &'a Path) -> Result<&'a str, Error> {
+        let clean_path = clean(path)?;
+        let is_dir = || {
+            self.source
+                .metadata(clean_path)
+                .is_ok_and(|metadata| metadata.is_dir)
+        };
+        if self.is_excluded(clean_path, is_dir) {
+            return Err(Error::new(ErrorKind::NotFound, "Excluded"));
+        }
+        Ok(clean_path)
     }
 
     /// Opens a file relative to the root directory and returns a buffered reader.
@@ -66,7 +259,8 @@ impl FileTree {
     ///
     /// # Returns
     ///
-    /// Returns a `Result` containing a `BufReader<File>` on success, or an `Error` on failure.
+    /// Returns a `Result` containing the reader and metadata on success, or an
+    /// `Error` on failure. Directories are reported as `NotFound`.
     ///
     /// # Examples
     ///
@@ -81,56 +275,507 @@ impl FileTree {
     /// }
     /// ```
     pub fn get_reader<P: AsRef<Path>>(&self, path: P) -> Result<FileData, Error> {
-        let path_str = path.as_ref().to_str().ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid UTF-8 in path")
-        })?;
+        let clean_path = self.visible(path.as_ref())?;
+        // The root itself is a directory
+        if clean_path.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, "Is a directory"));
+        }
 
-        // Strip leading "/" if present (HTTP paths start with /)
-        let clean_path = path_str.strip_prefix('/').unwrap_or(path_str);
+        let metadata = self.source.metadata(clean_path)?;
+        if metadata.is_dir {
+            return Err(Error::new(ErrorKind::NotFound, "Is a directory"));
+        }
+        Ok(FileData {
+            reader: self.source.open(clean_path)?,
+            metadata,
+        })
+    }
+
+    /// Opens a file relative to the root directory from byte `offset` on.
+    pub fn open_at<P: AsRef<Path>>(
+        &self,
+        path: P,
+        offset: u64,
+    ) -> Result<Box<dyn Read + Send>, Error> {
+        self.source.open_at(self.visible(path.as_ref())?, offset)
+    }
 
-        // Security checks
-        if clean_path == "." || clean_path == ".." {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Illegal path",
-            ));
+    /// Describes a file or directory relative to the root directory.
+    pub fn metadata<P: AsRef<Path>>(&self, path: P) -> Result<FileMetadata, Error> {
+        self.source.metadata(self.visible(path.as_ref())?)
+    }
+
+    /// Lists a directory relative to the root directory; `/` lists the root.
+    pub fn list<P: AsRef<Path>>(&self, path: P) -> Result<Vec<DirEntry>, Error> {
+        let dir = self.visible(path.as_ref())?;
+        let mut entries = self.source.list(dir)?;
+        if !self.excluded.is_empty() || self.ignore_file.is_some() {
+            entries.retain(|entry| {
+                let path = if dir.is_empty() {
+                    entry.name.clone()
+                } else {
+                    format!("{}/{}", dir, entry.name)
+                };
+                !self.is_excluded(&path, || entry.metadata.is_dir)
+            });
         }
+        Ok(entries)
+    }
 
-        if clean_path.is_empty() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Empty path",
-            ));
+    /// Drops what the source caches for `path` and below (everything for
+    /// `None`), so replaced files are fetched again.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` for paths that could escape the root.
+    pub fn purge<P: AsRef<Path>>(&self, path: Option<P>) -> Result<usize, Error> {
+        match path {
+            Some(path) => Ok(self.source.purge(Some(clean(path.as_ref())?))),
+            None => Ok(self.source.purge(None)),
         }
+    }
+}
+
+/// Returns true if `path` is `dir` or lies below it; an empty `dir` (or
+/// `/`) contains everything.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::is_within;
+///
+/// assert!(is_within("/assets/app.js", "/assets/"));
+/// assert!(is_within("/assets/app.js", "/assets/app.js"));
+/// assert!(!is_within("/assets-old/app.js", "/assets"));
+/// assert!(is_within("css/site.css", ""));
+/// ```
+pub fn is_within(path: &str, dir: &str) -> bool {
+    let dir = dir.trim_end_matches('/');
+    dir.is_empty()
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Turns a request path into the relative path a source expects.
+fn clean(path: &Path) -> Result<&str, Error> {
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid UTF-8 in path"))?;
+
+    // Strip leading "/" if present (HTTP paths start with /)
+    let clean_path = path_str.strip_prefix('/').unwrap_or(path_str);
+
+    // Security checks
+    if clean_path == "." || clean_path == ".." {
+        return Err(Error::new(ErrorKind::InvalidInput, "Illegal path"));
+    }
+
+    // Additional security: prevent path traversal
+    if clean_path.contains("..") {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Path traversal not allowed",
+        ));
+    }
+
+    Ok(clean_path.trim_end_matches('/'))
+}
+
+/// Files in a directory on disk.
+pub struct DiskSource {
+    root: PathBuf,
+}
+
+impl DiskSource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl FileSource for DiskSource {
+    fn open(&self, path: &str) -> Result<Box<dyn Read + Send>, Error> {
+        let file = File::open(self.root.join(path))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
 
-        // Additional security: prevent path traversal
-        if clean_path.contains("..") {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Path traversal not allowed",
-            ));
+    fn open_at(&self, path: &str, offset: u64) -> Result<Box<dyn Read + Send>, Error> {
+        let mut file = File::open(self.root.join(path))?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+
+    fn metadata(&self, path: &str) -> Result<FileMetadata, Error> {
+        let meta = fs::metadata(self.root.join(path))?;
+        Ok(FileMetadata {
+            len: meta.len(),
+            is_dir: meta.is_dir(),
+            modified: meta.modified().ok(),
+        })
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<DirEntry>, Error> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.root.join(path))? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            entries.push(DirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                metadata: FileMetadata {
+                    len: meta.len(),
+                    is_dir: meta.is_dir(),
+                    modified: meta.modified().ok(),
+                },
+            });
         }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+}
 
-        let file = File::open(self.root.join(clean_path))?;
-        let meta = fs::metadata(self.root.join(clean_path))?;
-        Ok(FileData {
-            reader: BufReader::new(file),
-            metadata: meta,
+/// One file on disk, alone in the root under its own name, for sharing a
+/// single file without exposing the rest of its directory.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::{FileSource, SingleFileSource};
+///
+/// let source = SingleFileSource::new("test-sites/one-file/index.html")?;
+/// assert_eq!(source.name(), "index.html");
+/// assert_eq!(source.list("")?.len(), 1);
+/// assert!(source.metadata("../index.html").is_err());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct SingleFileSource {
+    dir: DiskSource,
+    name: String,
+}
+
+impl SingleFileSource {
+    /// Serves the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if `path` does not end in a UTF-8 file name.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Not a file name"))?;
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        Ok(Self {
+            dir: DiskSource::new(dir),
+            name: name.to_string(),
         })
     }
+
+    /// The name the file is served under.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check(&self, path: &str) -> Result<(), Error> {
+        if path == self.name {
+            Ok(())
+        } else {
+            Err(ErrorKind::NotFound.into())
+        }
+    }
+}
+
+impl FileSource for SingleFileSource {
+    fn open(&self, path: &str) -> Result<Box<dyn Read + Send>, Error> {
+        self.check(path)?;
+        self.dir.open(path)
+    }
+
+    fn open_at(&self, path: &str, offset: u64) -> Result<Box<dyn Read + Send>, Error> {
+        self.check(path)?;
+        self.dir.open_at(path, offset)
+    }
+
+    fn metadata(&self, path: &str) -> Result<FileMetadata, Error> {
+        if path.is_empty() {
+            return Ok(FileMetadata {
+                len: 0,
+                is_dir: true,
+                modified: None,
+            });
+        }
+        self.check(path)?;
+        self.dir.metadata(path)
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<DirEntry>, Error> {
+        if !path.is_empty() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        Ok(vec![DirEntry {
+            name: self.name.clone(),
+            metadata: self.metadata(&self.name)?,
+        }])
+    }
+}
+
+/// Several sources overlaid on the same URL space; earlier layers win.
+///
+/// A path resolves to the first layer that has it, so `overrides/` can patch
+/// single files of a generated `dist/`. Directory listings merge all layers.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::{FileSource, LayeredSource, MemorySource};
+///
+/// let source = LayeredSource::new()
+///     .layer(MemorySource::new().with("theme.css", "a { color: red }"))
+///     .layer(
+///         MemorySource::new()
+///             .with("theme.css", "a {}")
+///             .with("index.html", "<h1>Hi</h1>"),
+///     );
+/// assert_eq!(source.metadata("theme.css")?.len, 16);
+/// assert_eq!(source.list("")?.len(), 2);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Default)]
+pub struct LayeredSource {
+    layers: Vec<Box<dyn FileSource>>,
+}
+
+impl LayeredSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a layer below the existing ones.
+    pub fn layer(mut self, source: impl FileSource + 'static) -> Self {
+        self.layers.push(Box::new(source));
+        self
+    }
+
+    /// The first layer that has `path`, with its metadata.
+    fn resolve(&self, path: &str) -> Result<(&dyn FileSource, FileMetadata), Error> {
+        for layer in &self.layers {
+            match layer.metadata(path) {
+                Ok(metadata) => return Ok((layer.as_ref(), metadata)),
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(ErrorKind::NotFound.into())
+    }
+}
+
+impl FileSource for LayeredSource {
+    fn open(&self, path: &str) -> Result<Box<dyn Read + Send>, Error> {
+        self.resolve(path)?.0.open(path)
+    }
+
+    fn open_at(&self, path: &str, offset: u64) -> Result<Box<dyn Read + Send>, Error> {
+        self.resolve(path)?.0.open_at(path, offset)
+    }
+
+    fn metadata(&self, path: &str) -> Result<FileMetadata, Error> {
+        Ok(self.resolve(path)?.1)
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<DirEntry>, Error> {
+        let mut merged: BTreeMap<String, DirEntry> = BTreeMap::new();
+        let mut found = false;
+        for layer in &self.layers {
+            let entries = match layer.list(path) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            found = true;
+            for entry in entries {
+                merged.entry(entry.name.clone()).or_insert(entry);
+            }
+        }
+        if !found {
+            return Err(ErrorKind::NotFound.into());
+        }
+        Ok(merged.into_values().collect())
+    }
+
+    fn purge(&self, path: Option<&str>) -> usize {
+        self.layers.iter().map(|layer| layer.purge(path)).sum()
+    }
+}
+
+/// Files held in memory, keyed by relative path (`css/site.css`).
+///
+/// Directories exist implicitly wherever a file path has a parent.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::{FileSource, MemorySource};
+///
+/// let source = MemorySource::new()
+///     .with("index.html", "<h1>Home</h1>")
+///     .with("css/site.css", "body {}");
+/// assert!(source.metadata("css")?.is_dir);
+/// let names: Vec<_> = source.list("")?.into_iter().map(|entry| entry.name).collect();
+/// assert_eq!(names, ["css", "index.html"]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    files: BTreeMap<String, Contents>,
+}
+
+/// File contents, either compiled into the binary or added at run time.
+#[derive(Debug, Clone)]
+enum Contents {
+    Static(&'static [u8]),
+    Shared(Arc<[u8]>),
+}
+
+impl AsRef<[u8]> for Contents {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Contents::Static(bytes) => bytes,
+            Contents::Shared(bytes) => bytes,
+        }
+    }
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves `(path, contents)` pairs without copying them, e.g. the
+    /// output of [`include_site!`](crate::include_site).
+    pub fn from_static(files: &'static [(&'static str, &'static [u8])]) -> Self {
+        let files = files
+            .iter()
+            .map(|&(path, bytes)| {
+                (
+                    path.trim_start_matches('/').to_string(),
+                    Contents::Static(bytes),
+                )
+            })
+            .collect();
+        Self { files }
+    }
+
+    /// Adds or replaces a file; a leading `/` is ignored.
+    pub fn insert(&mut self, path: &str, contents: impl Into<Vec<u8>>) {
+        let path = path.trim_start_matches('/').to_string();
+        self.files
+            .insert(path, Contents::Shared(contents.into().into()));
+    }
+
+    /// Number of files.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Adds a file, builder style.
+    pub fn with(mut self, path: &str, contents: impl Into<Vec<u8>>) -> Self {
+        self.insert(path, contents);
+        self
+    }
+
+    /// Whether any file lives below `path`.
+    fn is_dir(&self, path: &str) -> bool {
+        path.is_empty() || self.children(path).next().is_some()
+    }
+
+    /// Files below directory `path`, with the path relative to it.
+    fn children<'a>(&'a self, path: &str) -> impl Iterator<Item = (&'a str, &'a [u8])> {
+        let prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", path)
+        };
+        let skip = prefix.len();
+        self.files
+            .range(prefix.clone()..)
+            .take_while(move |(name, _)| name.starts_with(&prefix))
+            .map(move |(name, contents)| (&name[skip..], contents.as_ref()))
+    }
+}
+
+impl FileSource for MemorySource {
+    fn open(&self, path: &str) -> Result<Box<dyn Read + Send>, Error> {
+        match self.files.get(path) {
+            Some(contents) => Ok(Box::new(Cursor::new(contents.clone()))),
+            None => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn metadata(&self, path: &str) -> Result<FileMetadata, Error> {
+        match self.files.get(path) {
+            Some(contents) => Ok(FileMetadata {
+                len: contents.as_ref().len() as u64,
+                is_dir: false,
+                modified: None,
+            }),
+            None if self.is_dir(path) => Ok(FileMetadata {
+                len: 0,
+                is_dir: true,
+                modified: None,
+            }),
+            None => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<DirEntry>, Error> {
+        if !self.is_dir(path) {
+            return Err(ErrorKind::NotFound.into());
+        }
+        let mut entries: Vec<DirEntry> = Vec::new();
+        for (relative, contents) in self.children(path) {
+            let entry = match relative.split_once('/') {
+                Some((dir, _)) => DirEntry {
+                    name: dir.to_string(),
+                    metadata: FileMetadata {
+                        len: 0,
+                        is_dir: true,
+                        modified: None,
+                    },
+                },
+                None => DirEntry {
+                    name: relative.to_string(),
+                    metadata: FileMetadata {
+                        len: contents.len() as u64,
+                        is_dir: false,
+                        modified: None,
+                    },
+                },
+            };
+            // Keys are sorted, so a directory's files are adjacent
+            if entries.last().map(|last| &last.name) != Some(&entry.name) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Read;
 
     #[test]
     fn test_works() {
         let tree = FileTree::new(PathBuf::from("."));
-        let FileData {
-            mut reader,
-            metadata,
-        } = tree
+        let FileData { mut reader, .. } = tree
             .get_reader(Path::new("test-sites/one-file/index.html"))
             .expect("Failed to open test file");
         let mut buff = Vec::new();
@@ -140,6 +785,22 @@ mod tests {
         assert_eq!(buff, "<h1>Hello World</h1>".as_bytes().to_vec())
     }
 
+    #[test]
+    fn test_open_at() {
+        let disk = FileTree::new(PathBuf::from("test-sites"));
+        let memory = FileTree::from_source(
+            MemorySource::new().with("one-file/index.html", "<h1>Hello World</h1>"),
+        );
+        for tree in [disk, memory] {
+            let mut rest = String::new();
+            tree.open_at("one-file/index.html", 4)
+                .unwrap()
+                .read_to_string(&mut rest)
+                .unwrap();
+            assert_eq!(rest, "Hello World</h1>");
+        }
+    }
+
     #[test]
     fn test_file_not_found() {
         let tree = FileTree::new(PathBuf::from("."));
@@ -150,10 +811,7 @@ mod tests {
     #[test]
     fn test_root_directory_handling() {
         let tree = FileTree::new(PathBuf::from("test-sites"));
-        let FileData {
-            mut reader,
-            metadata,
-        } = tree
+        let FileData { mut reader, .. } = tree
             .get_reader("one-file/index.html")
             .expect("Failed to open file with different root");
         let mut buff = Vec::new();
@@ -176,4 +834,137 @@ mod tests {
         let result = tree.get_reader("..");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_directory_is_not_a_file() {
+        let tree = FileTree::new(PathBuf::from("test-sites"));
+        let err = tree.get_reader("one-file").err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(
+            tree.get_reader("/").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        let names: Vec<_> = tree
+            .list("/one-file/")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert!(names.contains(&"index.html".to_string()));
+    }
+
+    #[test]
+    fn test_memory_source() {
+        let tree = FileTree::from_source(
+            MemorySource::new()
+                .with("/a/b/c.txt", "c")
+                .with("a/d.txt", "dd")
+                .with("ab.txt", "ab"),
+        );
+        let FileData {
+            mut reader,
+            metadata,
+        } = tree.get_reader("/a/d.txt").unwrap();
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        assert_eq!((content.as_str(), metadata.len), ("dd", 2));
+
+        let listing: Vec<_> = tree
+            .list("/a")
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.name, entry.metadata.is_dir))
+            .collect();
+        assert_eq!(
+            listing,
+            [("b".to_string(), true), ("d.txt".to_string(), false)]
+        );
+        assert_eq!(
+            tree.get_reader("/a").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert!(tree.list("/a/d.txt").is_err());
+        assert!(tree.get_reader("/../ab.txt").is_err());
+    }
+
+    #[test]
+    fn test_single_file_source() {
+        let source = SingleFileSource::new("test-sites/multi-page-site/about.html").unwrap();
+        let tree = FileTree::from_source(source);
+        assert!(tree.get_reader("/about.html").is_ok());
+        // Its neighbours stay hidden
+        assert_eq!(
+            tree.get_reader("/index.html").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert!(tree.metadata("/subdir").is_err());
+        assert!(tree.metadata("/").unwrap().is_dir);
+        assert!(tree.list("/about.html").is_err());
+        assert!(SingleFileSource::new("/").is_err());
+    }
+
+    #[test]
+    fn test_layered_source() {
+        let tree = FileTree::from_source(
+            LayeredSource::new()
+                .layer(MemorySource::new().with("css/site.css", "patched"))
+                .layer(DiskSource::new("test-sites")),
+        );
+        let FileData { mut reader, .. } = tree.get_reader("/css/site.css").unwrap();
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "patched");
+        assert!(tree.get_reader("/one-file/index.html").is_ok());
+        assert_eq!(
+            tree.get_reader("/missing.txt").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        let names: Vec<_> = tree
+            .list("/")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert!(names.contains(&"css".to_string()));
+        assert!(names.contains(&"one-file".to_string()));
+    }
+
+    #[test]
+    fn test_excluded_paths() {
+        let tree = FileTree::from_source(
+            MemorySource::new()
+                .with("app/index.html", "")
+                .with("app/logs/today.log", "")
+                .with("app/node_modules/pkg/index.js", "")
+                .with("node_modules/pkg/index.js", "")
+                .with(".git/HEAD", ""),
+        )
+        .exclude("*.log")
+        .exclude("node_modules/**")
+        .exclude(".git");
+        for path in [
+            "/app/logs/today.log",
+            "/node_modules",
+            "/node_modules/pkg/index.js",
+            "/.git/HEAD",
+        ] {
+            assert_eq!(
+                tree.metadata(path).err().map(|e| e.kind()),
+                Some(ErrorKind::NotFound),
+                "{}",
+                path
+            );
+        }
+        // Anchored to the root, unlike a bare name
+        assert!(tree.get_reader("/app/node_modules/pkg/index.js").is_ok());
+        assert!(tree.list("/app/logs").unwrap().is_empty());
+        let names: Vec<_> = tree
+            .list("/")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, vec!["app"]);
+    }
 }
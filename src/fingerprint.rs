@@ -0,0 +1,394 @@
+/*
+* Fingerprinting module
+*
+* Content-hashed asset URLs for sites without a bundler. Every stylesheet,
+* script, image and font is also served under a name carrying a hash of its
+* content, and a manifest maps the plain names to the hashed ones:
+*
+*   /css/site.css        -> /css/site.3f2a9c1b.css   (same bytes)
+*   /asset-manifest.json -> {"/css/site.css": "/css/site.3f2a9c1b.css", ...}
+*
+* A hashed URL names one exact version, so it is answered with
+* `Cache-Control: public, max-age=31536000, immutable`; when the file
+* changes its hash does too, and the old URL stops resolving. Templates or a
+* build step read the manifest to link the hashed names. The plain names keep
+* working with the usual validation.
+*
+* Hashes are computed when the handler is built and refreshed with a file
+* watcher (`--watch`) on the next request after a change, otherwise at most
+* once a minute; only files whose size or modification time changed are
+* hashed again.
+*/
+
+use crate::checksum::Sha256Digest;
+use crate::files::FileTree;
+use crate::glob::Glob;
+use crate::headers::IMMUTABLE;
+use crate::livereload::LiveReload;
+use crate::message::{HttpMethod, Request, Response, StatusCode};
+use crate::middleware::Handler;
+use crate::target::{encode_path, RequestTarget};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, info, warn};
+
+/// Path the manifest is served at.
+pub const MANIFEST_PATH: &str = "/asset-manifest.json";
+
+/// How long hashes are reused without a file watcher.
+const REFRESH: Duration = Duration::from_secs(60);
+
+/// Hex digits of the content hash put in names.
+const HASH_LEN: usize = 8;
+
+/// Extensions fingerprinted by default.
+const DEFAULT_EXTENSIONS: &[&str] = &[
+    "css", "js", "mjs", "map", "png", "jpg", "jpeg", "gif", "svg", "webp", "avif", "ico", "woff",
+    "woff2", "ttf", "otf",
+];
+
+/// Fingerprinting settings.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::MemorySource;
+/// use file_shover::fingerprint::Fingerprints;
+/// use file_shover::message::Request;
+/// use file_shover::server::Server;
+///
+/// let service = Server::builder()
+///     .file_source(MemorySource::new().with("app.js", "alert(1)"))
+///     .fingerprints(Fingerprints::new())
+///     .build_service()?;
+/// let response = service.handle(&Request::builder().path("/asset-manifest.json").build());
+/// let mut manifest = String::new();
+/// response.body.into_reader().read_to_string(&mut manifest)?;
+/// assert!(manifest.contains("\"/app.js\": \"/app."));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Fingerprints {
+    extensions: Vec<String>,
+    exclude: Vec<Glob>,
+    changes: Option<LiveReload>,
+}
+
+impl Default for Fingerprints {
+    fn default() -> Self {
+        Self {
+            extensions: DEFAULT_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+            exclude: Vec::new(),
+            changes: None,
+        }
+    }
+}
+
+impl Fingerprints {
+    /// Fingerprints stylesheets, scripts, images and fonts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fingerprints files with these extensions instead (without the dot).
+    pub fn extensions(mut self, extensions: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.extensions = extensions
+            .into_iter()
+            .map(|e| e.into().to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Leaves out paths matching `pattern`.
+    pub fn exclude(mut self, pattern: Glob) -> Self {
+        self.exclude.push(pattern);
+        self
+    }
+
+    /// Rehashes whenever `watcher` sees files change, instead of every
+    /// minute.
+    pub fn watch(mut self, watcher: LiveReload) -> Self {
+        self.changes = Some(watcher);
+        self
+    }
+
+    /// Wraps the file handler `inner` so it also answers hashed names and
+    /// the manifest.
+    pub(crate) fn handler(
+        self,
+        inner: Box<dyn Handler>,
+        file_tree: Arc<FileTree>,
+    ) -> Fingerprinted {
+        let handler = Fingerprinted {
+            fingerprints: self,
+            inner,
+            file_tree,
+            state: Mutex::new(State::default()),
+        };
+        handler.assets();
+        handler
+    }
+}
+
+/// Size and modification time a hash was computed for.
+type Stamp = (u64, Option<SystemTime>);
+
+/// The hashed names as of the last scan.
+#[derive(Default)]
+struct State {
+    /// Plain path to its stamp and hashed path.
+    hashes: HashMap<String, (Stamp, String)>,
+    assets: Arc<Assets>,
+    generation: u64,
+    scanned: Option<Instant>,
+}
+
+/// Both directions of the mapping.
+#[derive(Default)]
+struct Assets {
+    hashed: BTreeMap<String, String>,
+    plain: HashMap<String, String>,
+}
+
+/// The file handler, plus hashed names and the manifest.
+pub(crate) struct Fingerprinted {
+    fingerprints: Fingerprints,
+    inner: Box<dyn Handler>,
+    file_tree: Arc<FileTree>,
+    state: Mutex<State>,
+}
+
+impl Fingerprinted {
+    /// The current mapping, rescanned if files changed or it is too old.
+    fn assets(&self) -> Arc<Assets> {
+        let generation = self
+            .fingerprints
+            .changes
+            .as_ref()
+            .map(LiveReload::generation);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let fresh = state.scanned.is_some_and(|scanned| match generation {
+            Some(generation) => state.generation == generation,
+            None => scanned.elapsed() < REFRESH,
+        });
+        if fresh {
+            return Arc::clone(&state.assets);
+        }
+        let started = Instant::now();
+        let mut files = Vec::new();
+        if let Err(e) = self.collect("/", &mut files) {
+            warn!("Fingerprinting failed: {}", e);
+        }
+        let mut hashes = HashMap::with_capacity(files.len());
+        for (path, stamp) in files {
+            let hashed = match state.hashes.remove(&path) {
+                Some((cached, hashed)) if cached == stamp => hashed,
+                _ => match self.hash(&path) {
+                    Ok(hashed) => hashed,
+                    Err(e) => {
+                        debug!("Could not fingerprint {}: {}", path, e);
+                        continue;
+                    }
+                },
+            };
+            hashes.insert(path, (stamp, hashed));
+        }
+        let assets = Assets {
+            hashed: hashes
+                .iter()
+                .map(|(path, (_, hashed))| (path.clone(), hashed.clone()))
+                .collect(),
+            plain: hashes
+                .iter()
+                .map(|(path, (_, hashed))| (hashed.clone(), path.clone()))
+                .collect(),
+        };
+        info!(
+            "🔖 Fingerprinted {} assets in {:?}",
+            assets.hashed.len(),
+            started.elapsed()
+        );
+        state.hashes = hashes;
+        state.assets = Arc::new(assets);
+        state.generation = generation.unwrap_or_default();
+        state.scanned = Some(Instant::now());
+        Arc::clone(&state.assets)
+    }
+
+    /// Adds the assets below `dir` (which ends in `/`) to `files`.
+    fn collect(&self, dir: &str, files: &mut Vec<(String, Stamp)>) -> io::Result<()> {
+        for entry in self.file_tree.list(dir)? {
+            if entry.name.starts_with('.') {
+                continue;
+            }
+            let path = format!("{}{}", dir, entry.name);
+            if self
+                .fingerprints
+                .exclude
+                .iter()
+                .any(|glob| glob.matches(&path))
+            {
+                continue;
+            }
+            if entry.metadata.is_dir {
+                self.collect(&format!("{}/", path), files)?;
+            } else if extension(&entry.name).is_some_and(|extension| {
+                self.fingerprints
+                    .extensions
+                    .iter()
+                    .any(|e| *e == extension.to_ascii_lowercase())
+            }) {
+                files.push((path, (entry.metadata.len, entry.metadata.modified)));
+            }
+        }
+        Ok(())
+    }
+
+    /// The hashed name of the file at `path`.
+    fn hash(&self, path: &str) -> io::Result<String> {
+        let digest = Sha256Digest::of_reader(self.file_tree.get_reader(path)?.reader)?;
+        Ok(hashed_name(path, &digest.to_hex()[..HASH_LEN]))
+    }
+
+    fn manifest(&self, req: &Request) -> Response {
+        let assets = self.assets();
+        // A map of strings always serializes
+        let json = serde_json::to_string_pretty(&assets.hashed).unwrap_or_default();
+        let response = Response::new()
+            .status(StatusCode::OK)
+            .content_type("application/json")
+            .header("Cache-Control", "no-cache")
+            .content_length(json.len());
+        match req.method {
+            HttpMethod::HEAD => response,
+            _ => response.body(json),
+        }
+    }
+}
+
+impl Handler for Fingerprinted {
+    fn handle(&self, req: &Request) -> Response {
+        if !matches!(req.method, HttpMethod::GET | HttpMethod::HEAD) {
+            return self.inner.handle(req);
+        }
+        if req.path() == MANIFEST_PATH && self.file_tree.metadata(MANIFEST_PATH).is_err() {
+            return self.manifest(req);
+        }
+        let assets = self.assets();
+        let Some(plain) = assets.plain.get(req.path()) else {
+            return self.inner.handle(req);
+        };
+        let target = match req.target.query() {
+            Some(query) => format!("{}?{}", encode_path(plain), query),
+            None => encode_path(plain),
+        };
+        let Ok(target) = RequestTarget::parse(&target) else {
+            return self.inner.handle(req);
+        };
+        let mut plain_req = req.clone();
+        plain_req.target = target;
+        let response = self.inner.handle(&plain_req);
+        if matches!(
+            response.status,
+            StatusCode::OK | StatusCode::PARTIAL_CONTENT
+        ) {
+            response.header("Cache-Control", IMMUTABLE)
+        } else {
+            response
+        }
+    }
+}
+
+/// The extension of `name`, if it has one after a non-empty stem.
+fn extension(name: &str) -> Option<&str> {
+    name.rsplit_once('.')
+        .filter(|(stem, _)| !stem.is_empty())
+        .map(|(_, extension)| extension)
+}
+
+/// `path` with `hash` before its extension: `/css/site.css` -> `/css/site.<hash>.css`.
+fn hashed_name(path: &str, hash: &str) -> String {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    match extension(name) {
+        Some(extension) => format!(
+            "{}/{}.{}.{}",
+            dir,
+            &name[..name.len() - extension.len() - 1],
+            hash,
+            extension
+        ),
+        None => format!("{}/{}.{}", dir, name, hash),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::MemorySource;
+    use std::io::Read;
+
+    fn not_found(_req: &Request) -> Response {
+        Response::new().status(StatusCode::NOT_FOUND)
+    }
+
+    fn body(response: Response) -> String {
+        let mut body = String::new();
+        response
+            .body
+            .into_reader()
+            .read_to_string(&mut body)
+            .unwrap();
+        body
+    }
+
+    #[test]
+    fn test_hashed_names() {
+        assert_eq!(hashed_name("/css/site.css", "abc"), "/css/site.abc.css");
+        assert_eq!(hashed_name("/app.min.js", "abc"), "/app.min.abc.js");
+        assert_eq!(hashed_name("/.env", "abc"), "/.env.abc");
+    }
+
+    #[test]
+    fn test_serves_hashed_names_immutably() {
+        let tree = Arc::new(FileTree::from_source(
+            MemorySource::new()
+                .with("css/site.css", "a {}")
+                .with("index.html", "<h1>Hi</h1>")
+                .with("vendor/lib.js", "x"),
+        ));
+        let serve = {
+            let tree = Arc::clone(&tree);
+            move |req: &Request| match tree.get_reader(req.path()) {
+                Ok(mut file) => {
+                    let mut body = Vec::new();
+                    file.reader.read_to_end(&mut body).unwrap();
+                    Response::new().body(body)
+                }
+                Err(_) => not_found(req),
+            }
+        };
+        let handler = Fingerprints::new()
+            .exclude(Glob::new("/vendor/**"))
+            .handler(Box::new(serve), tree);
+
+        let manifest: BTreeMap<String, String> = serde_json::from_str(&body(
+            handler.handle(&Request::builder().path(MANIFEST_PATH).build()),
+        ))
+        .unwrap();
+        assert_eq!(manifest.len(), 1);
+        let hashed = &manifest["/css/site.css"];
+        assert!(hashed.starts_with("/css/site.") && hashed.ends_with(".css"));
+
+        let response = handler.handle(&Request::builder().path(hashed).build());
+        assert_eq!(response.headers.get("Cache-Control").unwrap(), IMMUTABLE);
+        assert_eq!(body(response), "a {}");
+
+        // The plain name is served as before, and a wrong hash is not found
+        let response = handler.handle(&Request::builder().path("/css/site.css").build());
+        assert!(!response.headers.contains_key("Cache-Control"));
+        let response = handler.handle(&Request::builder().path("/css/site.00000000.css").build());
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,116 @@
+/*
+* Single-flight helper shared by the caches.
+*
+* When a popular entry is missing or invalidated, every request arriving in
+* the meantime would otherwise do the same expensive work (read and hash a
+* file, fetch an object, compress a body) at once. `SingleFlight` lets one
+* caller per key do it while the others wait, and the waiters then find the
+* result in the cache.
+*/
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Per-key locks for work that fills a cache.
+#[derive(Debug, Default)]
+pub(crate) struct SingleFlight {
+    flights: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+}
+
+impl SingleFlight {
+    /// Returns `cached()` if it has a value, otherwise runs `work` for
+    /// `key`, one caller at a time.
+    ///
+    /// `work` is expected to store its result where `cached` looks, so a
+    /// caller that waited returns that instead of running `work` again. If
+    /// `work` fails, the next waiter tries itself.
+    pub(crate) fn run<T, E>(
+        &self,
+        key: &str,
+        mut cached: impl FnMut() -> Option<T>,
+        work: impl FnOnce() -> Result<T, E>,
+    ) -> Result<T, E> {
+        if let Some(value) = cached() {
+            return Ok(value);
+        }
+        let flight = Arc::clone(
+            self.flights
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(key.to_string())
+                .or_default(),
+        );
+        let result = {
+            let _flight = flight.lock().unwrap_or_else(|e| e.into_inner());
+            match cached() {
+                Some(value) => Ok(value),
+                None => work(),
+            }
+        };
+        // Handles are only cloned and dropped under this lock, so the last
+        // caller out sees the map's handle alone and removes it
+        let mut flights = self.flights.lock().unwrap_or_else(|e| e.into_inner());
+        drop(flight);
+        if flights
+            .get(key)
+            .is_some_and(|current| Arc::strong_count(current) == 1)
+        {
+            flights.remove(key);
+        }
+        result
+    }
+
+    /// Number of keys with work in progress or callers waiting.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.flights.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_one_caller_works() {
+        let flight = Arc::new(SingleFlight::default());
+        let stored = Arc::new(Mutex::new(None));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (flight, stored, runs) =
+                    (Arc::clone(&flight), Arc::clone(&stored), Arc::clone(&runs));
+                std::thread::spawn(move || {
+                    flight.run(
+                        "/popular.css",
+                        || *stored.lock().unwrap(),
+                        || {
+                            runs.fetch_add(1, Ordering::SeqCst);
+                            std::thread::sleep(Duration::from_millis(50));
+                            *stored.lock().unwrap() = Some(42);
+                            Ok::<_, ()>(42)
+                        },
+                    )
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), Ok(42));
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(flight.len(), 0);
+    }
+
+    #[test]
+    fn test_failures_are_retried() {
+        let flight = SingleFlight::default();
+        assert_eq!(
+            flight.run("a", || None, || Err::<u8, _>("down")),
+            Err("down")
+        );
+        assert_eq!(flight.run("a", || None, || Ok::<_, &str>(1)), Ok(1));
+        assert_eq!(flight.len(), 0);
+    }
+}
@@ -0,0 +1,184 @@
+/*
+* GeoIP module
+*
+* Country lookups in a MaxMind-format (`.mmdb`) database: GeoLite2 or GeoIP2
+* Country/City, or any other provider shipping the same format (DB-IP,
+* IP2Location's MMDB editions). The database is read into memory once at
+* startup; replacing the file takes effect on restart or reload.
+*
+* Lookups serve two purposes: allow/deny rules by ISO 3166 country code,
+* applied next to the CIDR rules of the IP filter, and a `country` field on
+* every request span so access logs show where clients come from.
+*
+*   --allow-country DE --allow-country AT   only clients located in DE or AT
+*   --deny-country XX                       everyone except XX
+*
+* An address the database does not locate (private ranges, new allocations)
+* passes deny rules but fails an allowlist.
+*/
+
+use crate::config::ConfigError;
+use crate::error::ServerError;
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use std::net::IpAddr;
+use std::path::Path;
+use tracing::debug;
+
+/// A country database with optional allow/deny rules.
+///
+/// # Examples
+///
+/// ```no_run
+/// use file_shover::geoip::GeoIp;
+///
+/// let geoip = GeoIp::open("/var/lib/GeoIP/GeoLite2-Country.mmdb")?
+///     .deny(vec!["XX".to_string()]);
+/// assert_eq!(geoip.country("81.2.69.142".parse().unwrap()).as_deref(), Some("GB"));
+/// # Ok::<(), file_shover::error::ServerError>(())
+/// ```
+pub struct GeoIp {
+    reader: Reader<Vec<u8>>,
+    rules: CountryRules,
+}
+
+impl GeoIp {
+    /// Loads the database at `path`; all countries are allowed until rules
+    /// are added.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Config` if the file cannot be read or is not a
+    /// MaxMind database.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ServerError> {
+        let path = path.as_ref();
+        let reader = Reader::open_readfile(path).map_err(|e| {
+            ConfigError::Invalid(format!("GeoIP database {}: {}", path.display(), e))
+        })?;
+        Ok(Self {
+            reader,
+            rules: CountryRules::default(),
+        })
+    }
+
+    /// Only admits clients located in one of `countries` (ISO codes).
+    pub fn allow(mut self, countries: Vec<String>) -> Self {
+        self.rules.allow = normalize(countries);
+        self
+    }
+
+    /// Refuses clients located in any of `countries` (ISO codes).
+    pub fn deny(mut self, countries: Vec<String>) -> Self {
+        self.rules.deny = normalize(countries);
+        self
+    }
+
+    /// Returns true if neither allow nor deny rules are set.
+    pub fn has_rules(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    /// The ISO country code of `ip`, or `None` if the database has no
+    /// location for it.
+    ///
+    /// The country the address is located in is preferred over the one it
+    /// is registered to.
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        let record: geoip2::Country = match self.reader.lookup(ip) {
+            Ok(record) => record,
+            Err(MaxMindDBError::AddressNotFoundError(_)) => return None,
+            Err(e) => {
+                debug!("GeoIP lookup of {} failed: {}", ip, e);
+                return None;
+            }
+        };
+        record
+            .country
+            .and_then(|country| country.iso_code)
+            .or_else(|| record.registered_country.and_then(|c| c.iso_code))
+            .map(str::to_string)
+    }
+
+    /// Returns whether the country rules admit a client at `ip`.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        self.rules.is_empty() || self.rules.admits(self.country(ip).as_deref())
+    }
+}
+
+impl std::fmt::Debug for GeoIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GeoIp")
+            .field("database", &self.reader.metadata.database_type)
+            .field("rules", &self.rules)
+            .finish()
+    }
+}
+
+#[derive(Debug, Default)]
+struct CountryRules {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl CountryRules {
+    fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    fn admits(&self, country: Option<&str>) -> bool {
+        match country {
+            Some(code) if self.deny.iter().any(|c| c == code) => false,
+            Some(code) => self.allow.is_empty() || self.allow.iter().any(|c| c == code),
+            None => self.allow.is_empty(),
+        }
+    }
+}
+
+fn normalize(countries: Vec<String>) -> Vec<String> {
+    countries
+        .into_iter()
+        .map(|code| code.trim().to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(allow: &[&str], deny: &[&str]) -> CountryRules {
+        let codes = |list: &[&str]| normalize(list.iter().map(|c| c.to_string()).collect());
+        CountryRules {
+            allow: codes(allow),
+            deny: codes(deny),
+        }
+    }
+
+    #[test]
+    fn test_country_rules() {
+        let allowlist = rules(&["de", "AT"], &[]);
+        assert!(allowlist.admits(Some("DE")));
+        assert!(allowlist.admits(Some("AT")));
+        assert!(!allowlist.admits(Some("FR")));
+        assert!(!allowlist.admits(None));
+
+        let denylist = rules(&[], &["XX"]);
+        assert!(!denylist.admits(Some("XX")));
+        assert!(denylist.admits(Some("FR")));
+        assert!(denylist.admits(None));
+
+        // A country on both lists is denied
+        assert!(!rules(&["DE"], &["DE"]).admits(Some("DE")));
+    }
+
+    #[test]
+    fn test_open_rejects_invalid_database() {
+        let path =
+            std::env::temp_dir().join(format!("file-shover-geoip-{}.mmdb", std::process::id()));
+        std::fs::write(&path, b"not a database").unwrap();
+        assert!(matches!(
+            GeoIp::open(&path),
+            Err(ServerError::Config(ConfigError::Invalid(_)))
+        ));
+        std::fs::remove_file(&path).unwrap();
+        assert!(GeoIp::open(&path).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+/*
+* Git module
+*
+* A `FileSource` that serves the tree of a branch, tag or commit straight
+* from a git repository's object database, so no checkout is needed and a
+* bare repository works too:
+*
+*   --git /srv/docs.git@v1.2.3       tree of tag v1.2.3
+*   --git /srv/site.git@main:public  subdirectory of branch main
+*
+* The revision is resolved once at startup; later commits to the branch are
+* not picked up until the server restarts. libgit2 handles are not `Sync`,
+* so lookups take turns on one repository handle.
+*/
+
+use crate::config::ConfigError;
+use crate::error::ServerError;
+use crate::files::{DirEntry, FileMetadata, FileSource};
+use git2::{ErrorCode, ObjectType, Oid, Repository, Tree};
+use std::io::{Cursor, Error, ErrorKind, Read};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Serves the files of one git tree.
+///
+/// # Examples
+///
+/// ```no_run
+/// use file_shover::git::GitSource;
+/// use file_shover::Server;
+///
+/// let server = Server::builder()
+///     .file_source(GitSource::open("/srv/docs.git", "v1.2.3")?)
+///     .build()?;
+/// # Ok::<(), file_shover::error::ServerError>(())
+/// ```
+pub struct GitSource {
+    repo: Mutex<Repository>,
+    tree: Oid,
+    /// Commit time of the revision, reported as every file's modification time.
+    modified: Option<SystemTime>,
+}
+
+impl GitSource {
+    /// Opens `repo` (bare or not) and resolves `revision`, which may be any
+    /// revision git understands (`main`, `v1.2.3`, `a1b2c3d`, `HEAD~2`),
+    /// optionally followed by `:subdir`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Config` if the repository cannot be opened or
+    /// the revision does not name a tree.
+    pub fn open(repo: impl AsRef<Path>, revision: &str) -> Result<Self, ServerError> {
+        let repo_path = repo.as_ref();
+        let invalid = |e: git2::Error| {
+            ConfigError::Invalid(format!(
+                "git revision {}@{}: {}",
+                repo_path.display(),
+                revision,
+                e.message()
+            ))
+        };
+        let repo = Repository::open(repo_path).map_err(invalid)?;
+        let tree = repo
+            .revparse_single(revision)
+            .and_then(|object| object.peel_to_tree())
+            .map_err(invalid)?
+            .id();
+        let commit = revision.split_once(':').map_or(revision, |(rev, _)| rev);
+        let modified = repo
+            .revparse_single(commit)
+            .and_then(|object| object.peel_to_commit())
+            .ok()
+            .map(|commit| UNIX_EPOCH + Duration::from_secs(commit.time().seconds().max(0) as u64));
+        Ok(Self {
+            repo: Mutex::new(repo),
+            tree,
+            modified,
+        })
+    }
+
+    /// Parses `PATH@REVISION`; without `@` the repository's `HEAD` is served.
+    ///
+    /// # Errors
+    ///
+    /// See [`open`](GitSource::open).
+    pub fn from_spec(spec: &str) -> Result<Self, ServerError> {
+        match spec.rsplit_once('@') {
+            Some((repo, revision)) => Self::open(repo, revision),
+            None => Self::open(spec, "HEAD"),
+        }
+    }
+
+    fn file(&self, len: u64) -> FileMetadata {
+        FileMetadata {
+            len,
+            is_dir: false,
+            modified: self.modified,
+        }
+    }
+
+    fn directory(&self) -> FileMetadata {
+        FileMetadata {
+            len: 0,
+            is_dir: true,
+            modified: self.modified,
+        }
+    }
+
+    /// Looks up `path` in the served tree: the tree itself for `""`.
+    fn find(&self, repo: &Repository, path: &str) -> Result<(Oid, ObjectType), Error> {
+        if path.is_empty() {
+            return Ok((self.tree, ObjectType::Tree));
+        }
+        let entry = self
+            .root(repo)?
+            .get_path(Path::new(path))
+            .map_err(io_error)?;
+        match entry.kind() {
+            Some(kind @ (ObjectType::Tree | ObjectType::Blob)) => Ok((entry.id(), kind)),
+            // Submodules have no content here
+            _ => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn root<'r>(&self, repo: &'r Repository) -> Result<Tree<'r>, Error> {
+        repo.find_tree(self.tree).map_err(io_error)
+    }
+}
+
+impl FileSource for GitSource {
+    fn open(&self, path: &str) -> Result<Box<dyn Read + Send>, Error> {
+        let repo = self.repo.lock().unwrap();
+        match self.find(&repo, path)? {
+            (id, ObjectType::Blob) => {
+                let blob = repo.find_blob(id).map_err(io_error)?;
+                Ok(Box::new(Cursor::new(blob.content().to_vec())))
+            }
+            _ => Err(Error::new(ErrorKind::NotFound, "Is a directory")),
+        }
+    }
+
+    fn metadata(&self, path: &str) -> Result<FileMetadata, Error> {
+        let repo = self.repo.lock().unwrap();
+        match self.find(&repo, path)? {
+            (id, ObjectType::Blob) => Ok(self.file(blob_size(&repo, id)?)),
+            _ => Ok(self.directory()),
+        }
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<DirEntry>, Error> {
+        let repo = self.repo.lock().unwrap();
+        let (id, kind) = self.find(&repo, path)?;
+        if kind != ObjectType::Tree {
+            return Err(Error::new(ErrorKind::NotFound, "Not a directory"));
+        }
+        let tree = repo.find_tree(id).map_err(io_error)?;
+        let mut entries = Vec::new();
+        for entry in tree.iter() {
+            let metadata = match entry.kind() {
+                Some(ObjectType::Tree) => self.directory(),
+                Some(ObjectType::Blob) => self.file(blob_size(&repo, entry.id())?),
+                _ => continue,
+            };
+            entries.push(DirEntry {
+                name: String::from_utf8_lossy(entry.name_bytes()).into_owned(),
+                metadata,
+            });
+        }
+        // Git sorts directories as if they ended in '/'; use plain name order
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+}
+
+/// Blob size from the object header, without loading the content.
+fn blob_size(repo: &Repository, id: Oid) -> Result<u64, Error> {
+    let (size, _) = repo
+        .odb()
+        .and_then(|odb| odb.read_header(id))
+        .map_err(io_error)?;
+    Ok(size as u64)
+}
+
+fn io_error(e: git2::Error) -> Error {
+    match e.code() {
+        ErrorCode::NotFound => Error::new(ErrorKind::NotFound, e.message().to_string()),
+        _ => Error::other(e.message().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::FileTree;
+    use git2::Signature;
+    use std::path::PathBuf;
+
+    /// A bare repository with `v1` (index.html, docs/guide.md) and a later
+    /// commit on `main` that changes index.html.
+    fn repository(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("file-shover-git-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = Repository::init_bare(&dir).unwrap();
+        let signature = Signature::new(
+            "Test",
+            "test@example.com",
+            &git2::Time::new(1_700_000_000, 0),
+        )
+        .unwrap();
+
+        let commit = |index: &[u8], parents: &[&git2::Commit]| {
+            let mut docs = repo.treebuilder(None).unwrap();
+            let guide = repo.blob(b"# Guide").unwrap();
+            docs.insert("guide.md", guide, 0o100644).unwrap();
+            let docs = docs.write().unwrap();
+            let mut root = repo.treebuilder(None).unwrap();
+            root.insert("index.html", repo.blob(index).unwrap(), 0o100644)
+                .unwrap();
+            root.insert("docs", docs, 0o040000).unwrap();
+            let tree = repo.find_tree(root.write().unwrap()).unwrap();
+            repo.commit(
+                Some("refs/heads/main"),
+                &signature,
+                &signature,
+                "site",
+                &tree,
+                parents,
+            )
+            .unwrap()
+        };
+        let v1 = commit(b"<h1>v1</h1>", &[]);
+        repo.tag_lightweight("v1", &repo.find_object(v1, None).unwrap(), false)
+            .unwrap();
+        let parent = repo.find_commit(v1).unwrap();
+        commit(b"<h1>v2 draft</h1>", &[&parent]);
+        repo.set_head("refs/heads/main").unwrap();
+        dir
+    }
+
+    fn read(tree: &FileTree, path: &str) -> String {
+        let mut content = String::new();
+        tree.get_reader(path)
+            .unwrap()
+            .reader
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn test_serves_revisions() {
+        let dir = repository("revisions");
+        let spec = format!("{}@v1", dir.display());
+        let tagged = FileTree::from_source(GitSource::from_spec(&spec).unwrap());
+        assert_eq!(read(&tagged, "/index.html"), "<h1>v1</h1>");
+        assert_eq!(read(&tagged, "/docs/guide.md"), "# Guide");
+
+        let head = FileTree::from_source(GitSource::from_spec(&dir.to_string_lossy()).unwrap());
+        assert_eq!(read(&head, "/index.html"), "<h1>v2 draft</h1>");
+
+        let subdir = GitSource::open(&dir, "main:docs").unwrap();
+        assert_eq!(subdir.metadata("guide.md").unwrap().len, 7);
+        assert_eq!(
+            subdir.metadata("guide.md").unwrap().modified,
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_directories_and_errors() {
+        let dir = repository("errors");
+        let source = GitSource::open(&dir, "main").unwrap();
+        let names: Vec<_> = source
+            .list("")
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.name, entry.metadata.is_dir))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("docs".to_string(), true),
+                ("index.html".to_string(), false)
+            ]
+        );
+        assert!(source.metadata("docs").unwrap().is_dir);
+        assert_eq!(
+            source.open("missing.txt").err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+        assert!(matches!(
+            GitSource::open(&dir, "no-such-tag"),
+            Err(ServerError::Config(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
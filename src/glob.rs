@@ -0,0 +1,174 @@
+/*
+* Glob module
+*
+* Minimal path globbing used by configuration rules:
+*
+*   *    any run of characters except '/'
+*   **   any run of characters including '/'
+*   ?    a single character except '/'
+*
+* Patterns starting with '/' are matched against the whole request path.
+* Other patterns (e.g. `*.pdf`) are matched against the last path segment.
+* A pattern ending in a `**` segment also matches the directory holding
+* that segment, so the one for everything below `/public` covers `/public`
+* itself.
+*/
+
+/// A compiled glob pattern.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::glob::Glob;
+///
+/// let internal = Glob::new("/internal/**");
+/// assert!(internal.matches("/internal/reports/q3.pdf"));
+/// assert!(internal.matches("/internal"));
+/// assert!(!internal.matches("/internals"));
+///
+/// let pdf = Glob::new("*.pdf");
+/// assert!(pdf.matches("/docs/manual.pdf"));
+/// assert!(!pdf.matches("/docs/manual.pdf.html"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob {
+    pattern: String,
+}
+
+impl Glob {
+    /// Compiles a glob pattern.
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+        }
+    }
+
+    /// Returns the pattern as written.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns whether `path` (a request path such as `/a/b.txt`) matches.
+    pub fn matches(&self, path: &str) -> bool {
+        if !self.pattern.starts_with('/') {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            return match_bytes(self.pattern.as_bytes(), name.as_bytes());
+        }
+        if match_bytes(self.pattern.as_bytes(), path.as_bytes()) {
+            return true;
+        }
+        match self.pattern.strip_suffix("/**") {
+            Some(dir) => path.trim_end_matches('/') == dir,
+            None => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Glob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+/// Matches by walking both strings once. On a mismatch the last `*` takes
+/// one more character; once it would have to take a '/', the last `**`
+/// does instead and matching resumes after it.
+fn match_bytes(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Pattern position after the star and text position its match ends at
+    let mut star: Option<(usize, usize)> = None;
+    let mut globstar: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') if pattern.get(p + 1) == Some(&b'*') => {
+                p += 2;
+                globstar = Some((p, t));
+                star = None;
+                continue;
+            }
+            Some(b'*') => {
+                p += 1;
+                star = Some((p, t));
+                continue;
+            }
+            Some(b'?') if text[t] != b'/' => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some(&c) if c == text[t] => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match (star, globstar) {
+            (Some((after, end)), _) if text[end] != b'/' => {
+                star = Some((after, end + 1));
+                (p, t) = (after, end + 1);
+            }
+            (_, Some((after, end))) => {
+                globstar = Some((after, end + 1));
+                star = None;
+                (p, t) = (after, end + 1);
+            }
+            _ => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_star_stays_in_segment() {
+        let glob = Glob::new("/assets/*.js");
+        assert!(glob.matches("/assets/app.js"));
+        assert!(!glob.matches("/assets/vendor/lib.js"));
+    }
+
+    #[test]
+    fn test_double_star_crosses_segments() {
+        let glob = Glob::new("/assets/**/*.js");
+        assert!(glob.matches("/assets/vendor/lib.js"));
+        assert!(glob.matches("/assets/a/b/c.js"));
+        assert!(!glob.matches("/other/a.js"));
+    }
+
+    #[test]
+    fn test_question_mark() {
+        let glob = Glob::new("/v?/index.html");
+        assert!(glob.matches("/v1/index.html"));
+        assert!(!glob.matches("/v10/index.html"));
+        assert!(!glob.matches("/v//index.html"));
+    }
+
+    #[test]
+    fn test_exact_match() {
+        let glob = Glob::new("/robots.txt");
+        assert!(glob.matches("/robots.txt"));
+        assert!(!glob.matches("/robots.txt.bak"));
+    }
+
+    #[test]
+    fn test_star_backtracking() {
+        assert!(Glob::new("/a*b*c").matches("/abxbyc"));
+        assert!(!Glob::new("/a*c").matches("/ab/c"));
+        assert!(Glob::new("/**/x*/*.js").matches("/a/x/b/xy/c.js"));
+        assert!(!Glob::new("/**/x*/*.js").matches("/a/x/b/c.js"));
+        assert!(Glob::new("/**").matches("/"));
+        // Exponential with naive backtracking
+        let pattern = format!("/{}b", "**a".repeat(20));
+        assert!(!Glob::new(&pattern).matches(&format!("/{}", "a".repeat(200))));
+    }
+
+    #[test]
+    fn test_basename_patterns() {
+        let glob = Glob::new("node_modules");
+        assert!(glob.matches("/app/node_modules"));
+        assert!(!glob.matches("/app/node_modules/x.js"));
+    }
+}
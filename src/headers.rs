@@ -0,0 +1,646 @@
+/*
+* Response headers module
+*
+* Headers injected into every response after it has been built:
+*
+* - Security headers: a recommended default set (nosniff, frame denial,
+*   referrer policy) that can be extended with HSTS/CSP and adjusted per path,
+*   e.g. allowing framing under `/embed`.
+* - Custom rules: `"<glob> => <Name>: <value>"` lines from the config file or
+*   `--header-rule`, e.g. `"*.woff2 => Access-Control-Allow-Origin: *"`.
+*   Rules run after the security headers, in order, so later rules win.
+*
+* Values set to an empty string remove the header, which is how a path
+* override opts out of a default. `Vary` is the exception to "later rules
+* win": a rule's value is added to what the response already varies on.
+*
+* Cache lifetimes can be set per file extension (`[cache_ttl]` or
+* `--cache-ttl css=1d`), a simpler knob than a header rule per glob. They
+* only fill in a `Cache-Control` the response does not have yet, so
+* fingerprinted assets stay immutable and header rules still win.
+*
+* File names that already carry a content hash (`app.3f2a9c1b.js`, see
+* `--immutable-hashed`) are cached for a year without revalidation, the
+* usual setup for bundler output. This runs before the per-extension
+* lifetimes, so `*.js` may be short-lived while hashed scripts are not.
+*
+* Attachment mode is decided per request instead: `?download=1` or a
+* `[downloads]` pattern makes browsers save a file rather than render it,
+* with the file name encoded for non-ASCII names (RFC 6266 / RFC 5987).
+*/
+
+use crate::config::{ConfigError, DownloadsConfig, SecurityHeadersConfig};
+use crate::glob::Glob;
+use crate::message::{Request, Response, StatusCode};
+use crate::target::encode_path;
+use regex::Regex;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Security headers applied when `--security-headers` is given.
+pub const DEFAULT_SECURITY_HEADERS: &[(&str, &str)] = &[
+    ("X-Content-Type-Options", "nosniff"),
+    ("X-Frame-Options", "DENY"),
+    ("Referrer-Policy", "strict-origin-when-cross-origin"),
+];
+
+/// Hex run between dots, e.g. `app.3f2a9c1b.js`, as bundlers name output.
+pub const DEFAULT_HASHED_NAME: &str = r"\.[0-9a-f]{8,}\.";
+
+/// Caching of content-hashed names: a year, and never revalidated.
+pub(crate) const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// A set of header changes applied to paths matching a glob.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::headers::HeaderRule;
+/// use file_shover::message::Response;
+///
+/// let rule: HeaderRule = "*.pdf => Content-Disposition: attachment".parse().unwrap();
+///
+/// let mut response = Response::new();
+/// rule.apply("/docs/manual.pdf", &mut response);
+/// assert_eq!(
+///     response.headers.get("Content-Disposition"),
+///     Some(&"attachment".to_string())
+/// );
+///
+/// let mut response = Response::new();
+/// rule.apply("/docs/index.html", &mut response);
+/// assert_eq!(response.headers.get("Content-Disposition"), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct HeaderRule {
+    pattern: Glob,
+    headers: Vec<(String, String)>,
+}
+
+impl HeaderRule {
+    /// Creates a rule setting `headers` on paths matching `pattern`.
+    pub fn new(pattern: Glob, headers: Vec<(String, String)>) -> Self {
+        Self { pattern, headers }
+    }
+
+    /// Applies the rule if `path` matches.
+    pub fn apply(&self, path: &str, response: &mut Response) {
+        if self.pattern.matches(path) {
+            for (name, value) in &self.headers {
+                set_or_remove(response, name, value);
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for HeaderRule {
+    type Err = ConfigError;
+
+    /// Parses `"<glob> => <Name>: <value>"`. An empty value removes the header.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |reason: &str| ConfigError::Invalid(format!("header rule '{}': {}", s, reason));
+        let (pattern, header) = s
+            .split_once("=>")
+            .ok_or_else(|| invalid("expected '<glob> => <Name>: <value>'"))?;
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| invalid("expected '<Name>: <value>' after '=>'"))?;
+        let (pattern, name, value) = (pattern.trim(), name.trim(), value.trim());
+        if pattern.is_empty() {
+            return Err(invalid("missing path pattern"));
+        }
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+            return Err(invalid("invalid header name"));
+        }
+        if value.contains(['\r', '\n']) {
+            return Err(invalid("header value contains a line break"));
+        }
+        Ok(Self::new(
+            Glob::new(pattern),
+            vec![(name.to_string(), value.to_string())],
+        ))
+    }
+}
+
+/// Sets a header, or removes it (whatever its case) when `value` is empty.
+/// `Vary` values are added to the ones already listed.
+fn set_or_remove(response: &mut Response, name: &str, value: &str) {
+    if name.eq_ignore_ascii_case("Vary") && !value.is_empty() {
+        *response = std::mem::take(response).vary(value);
+        return;
+    }
+    response
+        .headers
+        .retain(|existing, _| !existing.eq_ignore_ascii_case(name));
+    if !value.is_empty() {
+        response.headers.insert(name.to_string(), value.to_string());
+    }
+}
+
+/// Default security headers plus per-path overrides.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::config::Config;
+/// use file_shover::headers::SecurityHeaders;
+/// use file_shover::message::Response;
+///
+/// let config = Config::from_toml(r#"
+///     [security_headers]
+///     enabled = true
+///     [[security_headers.overrides]]
+///     path = "/embed/**"
+///     headers = { "X-Frame-Options" = "" }
+/// "#).unwrap();
+/// let security = SecurityHeaders::from_config(&config.security_headers);
+///
+/// let mut response = Response::new();
+/// security.apply("/index.html", &mut response);
+/// assert_eq!(response.headers.get("X-Frame-Options"), Some(&"DENY".to_string()));
+///
+/// let mut response = Response::new();
+/// security.apply("/embed/widget.html", &mut response);
+/// assert_eq!(response.headers.get("X-Frame-Options"), None);
+/// assert_eq!(response.headers.get("X-Content-Type-Options"), Some(&"nosniff".to_string()));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SecurityHeaders {
+    defaults: Vec<(String, String)>,
+    overrides: Vec<HeaderRule>,
+}
+
+impl SecurityHeaders {
+    /// Builds the header set from the `[security_headers]` section.
+    ///
+    /// The recommended defaults are only included when `enabled` is set;
+    /// explicitly configured headers are always applied.
+    pub fn from_config(config: &SecurityHeadersConfig) -> Self {
+        let mut defaults: Vec<(String, String)> = Vec::new();
+        let mut set = |name: &str, value: &str| {
+            defaults.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+            defaults.push((name.to_string(), value.to_string()));
+        };
+
+        if config.enabled {
+            for (name, value) in DEFAULT_SECURITY_HEADERS {
+                set(name, value);
+            }
+        }
+        if let Some(hsts) = &config.strict_transport_security {
+            set("Strict-Transport-Security", hsts);
+        }
+        if let Some(csp) = &config.content_security_policy {
+            set("Content-Security-Policy", csp);
+        }
+        for (name, value) in &config.headers {
+            set(name, value);
+        }
+
+        let overrides = config
+            .overrides
+            .iter()
+            .map(|rule| {
+                let headers = rule
+                    .headers
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect();
+                HeaderRule::new(Glob::new(&rule.path), headers)
+            })
+            .collect();
+
+        Self {
+            defaults,
+            overrides,
+        }
+    }
+
+    /// Returns true if applying the headers would never change a response.
+    pub fn is_empty(&self) -> bool {
+        self.defaults.is_empty() && self.overrides.is_empty()
+    }
+
+    /// Adds the security headers for `path` to `response`.
+    pub fn apply(&self, path: &str, response: &mut Response) {
+        for (name, value) in &self.defaults {
+            set_or_remove(response, name, value);
+        }
+        for rule in &self.overrides {
+            rule.apply(path, response);
+        }
+    }
+}
+
+/// `Cache-Control` lifetimes by file extension.
+///
+/// A lifetime of zero asks clients to revalidate every time (`no-cache`).
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::headers::CacheTtls;
+/// use file_shover::message::Response;
+/// use std::time::Duration;
+///
+/// let ttls = CacheTtls::new()
+///     .ttl("html", Duration::ZERO)
+///     .ttl("css", Duration::from_secs(86400));
+///
+/// let mut response = Response::new();
+/// ttls.apply("/static/site.CSS", &mut response);
+/// assert_eq!(response.headers.get("Cache-Control"), Some(&"max-age=86400".to_string()));
+///
+/// let mut response = Response::new();
+/// ttls.apply("/index.html", &mut response);
+/// assert_eq!(response.headers.get("Cache-Control"), Some(&"no-cache".to_string()));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CacheTtls {
+    ttls: HashMap<String, Duration>,
+}
+
+impl CacheTtls {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caches files ending in `.extension` for `ttl`; extensions are
+    /// compared without regard to case.
+    pub fn ttl(mut self, extension: &str, ttl: Duration) -> Self {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        self.ttls.insert(extension, ttl);
+        self
+    }
+
+    /// Builds the lifetimes of a `[cache_ttl]` section.
+    pub fn from_config(config: &HashMap<String, Duration>) -> Self {
+        config.iter().fold(Self::new(), |ttls, (extension, ttl)| {
+            ttls.ttl(extension, *ttl)
+        })
+    }
+
+    /// Number of extensions with a lifetime.
+    pub fn len(&self) -> usize {
+        self.ttls.len()
+    }
+
+    /// Returns true if no extension has a lifetime.
+    pub fn is_empty(&self) -> bool {
+        self.ttls.is_empty()
+    }
+
+    /// Sets `Cache-Control` on a successful or 304 response for `path`,
+    /// unless the response already has one.
+    pub fn apply(&self, path: &str, response: &mut Response) {
+        if self.ttls.is_empty() || !lacks_cache_control(response) {
+            return;
+        }
+        let Some((_, extension)) = file_name(path).rsplit_once('.') else {
+            return;
+        };
+        if let Some(ttl) = self.ttls.get(&extension.to_ascii_lowercase()) {
+            let value = match ttl.as_secs() {
+                0 => "no-cache".to_string(),
+                seconds => format!("max-age={}", seconds),
+            };
+            response.headers.insert("Cache-Control".to_string(), value);
+        }
+    }
+}
+
+/// Marks files whose names carry a content hash, as bundlers write them
+/// (`app.3f2a9c1b.js`), as cacheable forever.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::headers::{HashedNames, DEFAULT_HASHED_NAME};
+/// use file_shover::message::Response;
+///
+/// let hashed = HashedNames::new(DEFAULT_HASHED_NAME).unwrap();
+///
+/// let mut response = Response::new();
+/// hashed.apply("/assets/app.3f2a9c1b.js", &mut response);
+/// assert_eq!(
+///     response.headers.get("Cache-Control"),
+///     Some(&"public, max-age=31536000, immutable".to_string())
+/// );
+///
+/// let mut response = Response::new();
+/// hashed.apply("/assets/app.js", &mut response);
+/// assert_eq!(response.headers.get("Cache-Control"), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct HashedNames {
+    pattern: Regex,
+}
+
+impl HashedNames {
+    /// Treats file names matching the regex `pattern` as hashed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Invalid` if `pattern` is not a valid regex.
+    pub fn new(pattern: &str) -> Result<Self, ConfigError> {
+        let pattern = Regex::new(pattern).map_err(|e| {
+            ConfigError::Invalid(format!("hashed name pattern '{}': {}", pattern, e))
+        })?;
+        Ok(Self { pattern })
+    }
+
+    /// Sets an immutable `Cache-Control` on a successful or 304 response for
+    /// a hashed `path`, unless the response already has one.
+    pub fn apply(&self, path: &str, response: &mut Response) {
+        if lacks_cache_control(response) && self.pattern.is_match(file_name(path)) {
+            response
+                .headers
+                .insert("Cache-Control".to_string(), IMMUTABLE.to_string());
+        }
+    }
+}
+
+/// Whether `response` may still be given a cache lifetime: it is a success
+/// or 304 without a `Cache-Control` of its own.
+fn lacks_cache_control(response: &Response) -> bool {
+    (response.status.is_success() || response.status == StatusCode::NOT_MODIFIED)
+        && !response
+            .headers
+            .keys()
+            .any(|name| name.eq_ignore_ascii_case("Cache-Control"))
+}
+
+/// The last segment of `path`.
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Decides which files are sent as attachments.
+///
+/// `?download=1` (or `true`) forces an attachment and `?download=0` (or
+/// `false`) forces inline display; otherwise the configured patterns decide.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::glob::Glob;
+/// use file_shover::headers::Attachments;
+/// use file_shover::message::Request;
+///
+/// let attachments = Attachments::new(vec![Glob::new("*.iso")]);
+/// assert!(attachments.wants(&Request::builder().path("/os/debian.iso").build()));
+/// assert!(!attachments.wants(&Request::builder().path("/os/debian.iso?download=0").build()));
+/// assert!(attachments.wants(&Request::builder().path("/notes.txt?download=1").build()));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Attachments {
+    patterns: Vec<Glob>,
+}
+
+impl Attachments {
+    /// Sends files matching any of `patterns` as attachments.
+    pub fn new(patterns: Vec<Glob>) -> Self {
+        Self { patterns }
+    }
+
+    /// Builds the patterns of a `[downloads]` section.
+    pub fn from_config(config: &DownloadsConfig) -> Self {
+        Self::new(config.attachment.iter().map(|p| Glob::new(p)).collect())
+    }
+
+    /// Returns whether the response to `req` should be an attachment.
+    pub fn wants(&self, req: &Request) -> bool {
+        match req.query_param("download") {
+            Some("1" | "true") => true,
+            Some("0" | "false") => false,
+            _ => self.patterns.iter().any(|glob| glob.matches(req.path())),
+        }
+    }
+}
+
+/// `Content-Disposition` value for downloading a file as `filename`.
+///
+/// Names that are not plain ASCII get an RFC 5987 `filename*` next to an
+/// ASCII fallback for old clients.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::headers::content_disposition;
+///
+/// assert_eq!(content_disposition("report.pdf"), "attachment; filename=\"report.pdf\"");
+/// assert_eq!(
+///     content_disposition("Übersicht 2024.pdf"),
+///     "attachment; filename=\"_bersicht 2024.pdf\"; filename*=UTF-8''%C3%9Cbersicht%202024.pdf"
+/// );
+/// ```
+pub fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if fallback == filename {
+        return format!("attachment; filename=\"{}\"", filename);
+    }
+    // attr-char excludes a few characters a path may keep
+    let encoded = encode_path(filename)
+        .replace('/', "%2F")
+        .replace('\'', "%27")
+        .replace('(', "%28")
+        .replace(')', "%29")
+        .replace('*', "%2A")
+        .replace(',', "%2C")
+        .replace(';', "%3B")
+        .replace('=', "%3D")
+        .replace('@', "%40")
+        .replace(':', "%3A");
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn security(toml: &str) -> SecurityHeaders {
+        SecurityHeaders::from_config(&Config::from_toml(toml).unwrap().security_headers)
+    }
+
+    #[test]
+    fn test_parse_header_rule() {
+        let rule: HeaderRule = "/fonts/** => Access-Control-Allow-Origin: *"
+            .parse()
+            .unwrap();
+        let mut response = Response::new();
+        rule.apply("/fonts/inter.woff2", &mut response);
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some(&"*".to_string())
+        );
+
+        // Only the first ':' separates name and value
+        let rule: HeaderRule = "/ => Link: <https://cdn.example>; rel=preconnect"
+            .parse()
+            .unwrap();
+        let mut response = Response::new();
+        rule.apply("/", &mut response);
+        assert_eq!(
+            response.headers.get("Link"),
+            Some(&"<https://cdn.example>; rel=preconnect".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_header_rules() {
+        for rule in [
+            "/a Access-Control-Allow-Origin: *",
+            "/a => Access-Control-Allow-Origin",
+            " => X-Test: 1",
+            "/a => Bad Name: 1",
+        ] {
+            assert!(
+                matches!(rule.parse::<HeaderRule>(), Err(ConfigError::Invalid(_))),
+                "{}",
+                rule
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty_value_removes_header() {
+        let rule: HeaderRule = "*.html => server:".parse().unwrap();
+        let mut response = Response::new().header("Server", "file-shover/1.0");
+        rule.apply("/index.html", &mut response);
+        assert_eq!(response.headers.get("Server"), None);
+    }
+
+    #[test]
+    fn test_vary_rules_add_to_vary() {
+        let rule: HeaderRule = "/fonts/** => Vary: Origin".parse().unwrap();
+        let mut response = Response::new().header("vary", "Accept-Encoding");
+        rule.apply("/fonts/a.woff2", &mut response);
+        assert_eq!(
+            response.headers.get("Vary"),
+            Some(&"Accept-Encoding, Origin".to_string())
+        );
+        assert!(!response.headers.contains_key("vary"));
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        assert!(security("").is_empty());
+    }
+
+    #[test]
+    fn test_hsts_and_csp() {
+        let security = security(
+            r#"
+            [security_headers]
+            strict_transport_security = "max-age=63072000"
+            content_security_policy = "default-src 'self'"
+            "#,
+        );
+        let mut response = Response::new();
+        security.apply("/", &mut response);
+        assert_eq!(
+            response.headers.get("Strict-Transport-Security"),
+            Some(&"max-age=63072000".to_string())
+        );
+        // Defaults are only added when enabled
+        assert_eq!(response.headers.get("X-Frame-Options"), None);
+    }
+
+    #[test]
+    fn test_configured_headers_replace_defaults() {
+        let security = security(
+            r#"
+            [security_headers]
+            enabled = true
+            headers = { "x-frame-options" = "SAMEORIGIN", "Referrer-Policy" = "" }
+            "#,
+        );
+        let mut response = Response::new();
+        security.apply("/", &mut response);
+        assert_eq!(
+            response.headers.get("x-frame-options"),
+            Some(&"SAMEORIGIN".to_string())
+        );
+        assert_eq!(response.headers.get("X-Frame-Options"), None);
+        assert_eq!(response.headers.get("Referrer-Policy"), None);
+    }
+
+    #[test]
+    fn test_override_replaces_value() {
+        let security = security(
+            r#"
+            [security_headers]
+            enabled = true
+            [[security_headers.overrides]]
+            path = "/embed/**"
+            headers = { "X-Frame-Options" = "SAMEORIGIN" }
+            "#,
+        );
+        let mut response = Response::new();
+        security.apply("/embed/a.html", &mut response);
+        assert_eq!(
+            response.headers.get("X-Frame-Options"),
+            Some(&"SAMEORIGIN".to_string())
+        );
+    }
+
+    #[test]
+    fn test_cache_ttls_leave_existing_and_errors_alone() {
+        let ttls = CacheTtls::new().ttl(".woff2", Duration::from_secs(31536000));
+
+        let mut response = Response::new().header("Cache-Control", "public, immutable");
+        ttls.apply("/fonts/inter.woff2", &mut response);
+        assert_eq!(
+            response.headers.get("Cache-Control"),
+            Some(&"public, immutable".to_string())
+        );
+
+        let mut response = Response::error(StatusCode::NOT_FOUND, "");
+        ttls.apply("/fonts/missing.woff2", &mut response);
+        assert_eq!(response.headers.get("Cache-Control"), None);
+
+        let mut response = Response::new();
+        ttls.apply("/fonts.woff2/readme", &mut response);
+        assert_eq!(response.headers.get("Cache-Control"), None);
+    }
+
+    #[test]
+    fn test_hashed_names() {
+        let hashed = HashedNames::new(DEFAULT_HASHED_NAME).unwrap();
+        for path in ["/main.0123abcdef.css", "/a/chunk.deadbeef.min.js"] {
+            let mut response = Response::new();
+            hashed.apply(path, &mut response);
+            assert_eq!(
+                response.headers.get("Cache-Control"),
+                Some(&IMMUTABLE.to_string()),
+                "{}",
+                path
+            );
+        }
+        // Too short, not hex, or only in a directory name
+        for path in [
+            "/main.0123abc.css",
+            "/main.0123abcdeg.css",
+            "/v.deadbeef0./app.js",
+        ] {
+            let mut response = Response::new();
+            hashed.apply(path, &mut response);
+            assert_eq!(response.headers.get("Cache-Control"), None, "{}", path);
+        }
+        assert!(HashedNames::new("[").is_err());
+    }
+}
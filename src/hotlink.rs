@@ -0,0 +1,122 @@
+/*
+* Hotlink protection module
+*
+* Media requests (images, video, audio) are only honoured when the `Referer`
+* header points at the site itself or at one of the allowed hosts. Requests
+* without a `Referer` (direct visits, privacy-conscious browsers) are allowed
+* unless configured otherwise.
+*
+* Allowed hosts are exact names (`example.com`) or wildcard subdomains
+* (`*.example.com`). Ports are ignored.
+*/
+
+/// Referer-based access check for media files.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::hotlink::HotlinkGuard;
+///
+/// let guard = HotlinkGuard::new(vec!["*.example.com".to_string()], true);
+///
+/// assert!(guard.is_allowed(Some("https://blog.example.com/post"), Some("files.local")));
+/// assert!(guard.is_allowed(Some("http://files.local:7878/"), Some("files.local:7878")));
+/// assert!(guard.is_allowed(None, Some("files.local")));
+/// assert!(!guard.is_allowed(Some("https://evil.test/page"), Some("files.local")));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct HotlinkGuard {
+    allowed_hosts: Vec<String>,
+    allow_missing_referer: bool,
+}
+
+impl HotlinkGuard {
+    /// Creates a guard allowing `allowed_hosts` in addition to the request's own host.
+    pub fn new(allowed_hosts: Vec<String>, allow_missing_referer: bool) -> Self {
+        Self {
+            allowed_hosts: allowed_hosts
+                .into_iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect(),
+            allow_missing_referer,
+        }
+    }
+
+    /// Checks a `Referer` header against the allowed hosts and the `Host` header.
+    pub fn is_allowed(&self, referer: Option<&str>, host: Option<&str>) -> bool {
+        let Some(referer) = referer.filter(|r| !r.trim().is_empty()) else {
+            return self.allow_missing_referer;
+        };
+        let Some(referer_host) = referer_host(referer) else {
+            return false;
+        };
+
+        if host.is_some_and(|host| strip_port(host).eq_ignore_ascii_case(&referer_host)) {
+            return true;
+        }
+        self.allowed_hosts
+            .iter()
+            .any(|allowed| host_matches(allowed, &referer_host))
+    }
+}
+
+/// Extracts the lowercase host name from a referer URL.
+fn referer_host(referer: &str) -> Option<String> {
+    let (_, rest) = referer.trim().split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_port = authority.rsplit('@').next()?;
+    let host = strip_port(host_port);
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+fn strip_port(host: &str) -> &str {
+    if let Some(bracketed) = host.strip_prefix('[') {
+        // IPv6 literal: [::1]:8080
+        return bracketed.split(']').next().unwrap_or(bracketed);
+    }
+    host.split(':').next().unwrap_or(host)
+}
+
+fn host_matches(allowed: &str, host: &str) -> bool {
+    match allowed.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.')),
+        None => allowed == host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_referer_host_parsing() {
+        assert_eq!(
+            referer_host("https://user:pw@Example.COM:8443/a?b#c"),
+            Some("example.com".to_string())
+        );
+        assert_eq!(referer_host("http://[::1]:7878/"), Some("::1".to_string()));
+        assert_eq!(referer_host("not a url"), None);
+    }
+
+    #[test]
+    fn test_wildcard_does_not_match_apex_or_lookalikes() {
+        assert!(host_matches("*.example.com", "cdn.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", "badexample.com"));
+    }
+
+    #[test]
+    fn test_missing_referer_policy() {
+        let strict = HotlinkGuard::new(vec![], false);
+        assert!(!strict.is_allowed(None, Some("files.local")));
+        assert!(!strict.is_allowed(Some(""), Some("files.local")));
+    }
+
+    #[test]
+    fn test_malformed_referer_is_rejected() {
+        let guard = HotlinkGuard::new(vec![], true);
+        assert!(!guard.is_allowed(Some("garbage"), Some("files.local")));
+    }
+}
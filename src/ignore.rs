@@ -0,0 +1,257 @@
+/*
+* Ignore module
+*
+* A `.shoverignore` file at the root lists what is not served, in gitignore
+* syntax, so exclusions live with the content instead of in server flags:
+*
+*   # build output and secrets
+*   *.log
+*   /drafts/
+*   node_modules/
+*   !/drafts/announcement.html
+*
+* As in git, a pattern without a slash matches a name at any depth, one with
+* a slash is taken from the root, a trailing `/` only matches directories and
+* `!` brings back what an earlier line ignored; the last matching line wins.
+* Nothing below an ignored directory can be brought back.
+*
+* The file is read through the tree's own source, so it works for any
+* backend, and checked for changes at most once a second while requests come
+* in. It is never served itself.
+*/
+
+use crate::files::FileSource;
+use crate::glob::Glob;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+/// Name of the ignore file looked for at the root.
+pub const IGNORE_FILE: &str = ".shoverignore";
+
+/// Minimum time between checks of the file for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Parsed lines of an ignore file.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::ignore::IgnoreRules;
+///
+/// let rules = IgnoreRules::parse("*.log\n/drafts/\n!/drafts/announcement.html\n");
+/// assert!(rules.is_ignored("/logs/today.log", || false));
+/// assert!(rules.is_ignored("/drafts", || true));
+/// assert!(!rules.is_ignored("/posts/drafts", || true));
+/// // The directory stays ignored, so its files cannot be brought back
+/// assert!(rules.is_ignored("/drafts/announcement.html", || false));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+/// One line of the file.
+#[derive(Debug, Clone)]
+struct Rule {
+    /// Alternatives, since `a/**/b` also matches `a/b`.
+    globs: Vec<Glob>,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl IgnoreRules {
+    /// Parses the content of an ignore file; lines that are blank or start
+    /// with `#` are skipped.
+    pub fn parse(content: &str) -> Self {
+        let rules = content.lines().filter_map(Rule::parse).collect();
+        Self { rules }
+    }
+
+    /// Number of patterns.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Returns true if nothing is ignored.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns whether `path` (such as `/a/b.txt`) or one of its parent
+    /// directories is ignored. `is_dir` is only called when a
+    /// directory-only line could decide.
+    pub fn is_ignored(&self, path: &str, is_dir: impl FnOnce() -> bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let ancestor_ignored = path
+            .match_indices('/')
+            .skip(1)
+            .any(|(end, _)| self.decide(&path[..end], &mut || true) == Some(true));
+        if ancestor_ignored {
+            return true;
+        }
+        let mut is_dir = Some(is_dir);
+        let mut known = None;
+        let mut lazy = || *known.get_or_insert_with(|| is_dir.take().is_some_and(|f| f()));
+        self.decide(path, &mut lazy) == Some(true)
+    }
+
+    /// Whether the last line matching `path` ignores it, if any matches.
+    fn decide(&self, path: &str, is_dir: &mut dyn FnMut() -> bool) -> Option<bool> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                rule.globs.iter().any(|glob| glob.matches(path)) && (!rule.dir_only || is_dir())
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, pattern) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let pattern = pattern.strip_prefix("**/").unwrap_or(pattern);
+        if pattern.is_empty() {
+            return None;
+        }
+        let globs = if pattern.trim_start_matches('/').contains('/') || pattern.starts_with('/') {
+            let anchored = format!("/{}", pattern.trim_start_matches('/'));
+            let mut globs = vec![Glob::new(&anchored)];
+            if anchored.contains("/**/") {
+                globs.push(Glob::new(&anchored.replace("/**/", "/")));
+            }
+            globs
+        } else {
+            vec![Glob::new(pattern)]
+        };
+        Some(Self {
+            globs,
+            negated,
+            dir_only,
+        })
+    }
+}
+
+/// An ignore file at the root of a source, reloaded when it changes.
+#[derive(Debug)]
+pub(crate) struct IgnoreFile {
+    name: String,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    rules: Arc<IgnoreRules>,
+    modified: Option<SystemTime>,
+    checked: Option<Instant>,
+}
+
+impl IgnoreFile {
+    /// Reads `name`, relative to the root, on first use.
+    pub(crate) fn new(name: &str) -> Self {
+        Self {
+            name: name.trim_start_matches('/').to_string(),
+            state: Mutex::new(State {
+                rules: Arc::new(IgnoreRules::default()),
+                modified: None,
+                checked: None,
+            }),
+        }
+    }
+
+    /// The file's path relative to the root.
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The current rules, reloading the file from `source` first if it
+    /// changed. A file that cannot be read keeps the previous rules.
+    pub(crate) fn rules(&self, source: &dyn FileSource) -> Arc<IgnoreRules> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state
+            .checked
+            .is_some_and(|checked| now.saturating_duration_since(checked) < RELOAD_INTERVAL)
+        {
+            return Arc::clone(&state.rules);
+        }
+        let first = state.checked.replace(now).is_none();
+        let metadata = source.metadata(&self.name).ok().filter(|m| !m.is_dir);
+        let modified = metadata.as_ref().and_then(|m| m.modified);
+        if metadata.is_none() {
+            if !state.rules.is_empty() {
+                info!("🙈 {} removed, nothing is ignored", self.name);
+                state.rules = Arc::new(IgnoreRules::default());
+            }
+            state.modified = None;
+        } else if modified != state.modified || modified.is_none() {
+            match read(source, &self.name) {
+                Ok(rules) => {
+                    if first || modified != state.modified {
+                        info!(
+                            "🙈 Loaded {} ignore patterns from {}",
+                            rules.len(),
+                            self.name
+                        );
+                    }
+                    state.rules = Arc::new(rules);
+                    state.modified = modified;
+                }
+                Err(e) => warn!("Keeping previous ignore patterns: {}: {}", self.name, e),
+            }
+        }
+        Arc::clone(&state.rules)
+    }
+}
+
+fn read(source: &dyn FileSource, name: &str) -> std::io::Result<IgnoreRules> {
+    let mut content = String::new();
+    source.open(name)?.read_to_string(&mut content)?;
+    Ok(IgnoreRules::parse(&content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gitignore_syntax() {
+        let rules = IgnoreRules::parse(
+            "# comment\n\n*.log\n!keep.log\nbuild/\ndocs/**/draft.md\n**/tmp\n\\#notes\n",
+        );
+        assert_eq!(rules.len(), 6);
+        assert!(rules.is_ignored("/a/b/error.log", || false));
+        assert!(!rules.is_ignored("/a/keep.log", || false));
+        assert!(rules.is_ignored("/build", || true));
+        assert!(rules.is_ignored("/src/build/out.js", || false));
+        assert!(!rules.is_ignored("/build", || false));
+        assert!(rules.is_ignored("/docs/draft.md", || false));
+        assert!(rules.is_ignored("/docs/guide/draft.md", || false));
+        assert!(!rules.is_ignored("/blog/draft.md", || false));
+        assert!(rules.is_ignored("/x/tmp/a", || false));
+        assert!(rules.is_ignored("/#notes", || false));
+    }
+
+    #[test]
+    fn test_is_dir_asked_only_when_needed() {
+        let rules = IgnoreRules::parse("*.log\ncache/\n");
+        assert!(rules.is_ignored("/a.log", || panic!("not needed")));
+        assert!(!rules.is_ignored("/a.txt", || panic!("not needed")));
+        assert!(!rules.is_ignored("/cache", || false));
+    }
+}
@@ -0,0 +1,277 @@
+/*
+* IP filtering module
+*
+* Allow/deny rules over CIDR blocks, evaluated against the peer address as soon
+* as a connection is accepted (before the request is even read). Connections
+* from `--trusted-proxies` are instead checked once the forwarded client
+* address is known.
+*
+* Rule precedence: the most specific matching block (longest prefix) decides,
+* with deny winning ties. Addresses matching no rule are allowed, unless only
+* allow rules were configured, in which case the allow rules act as an allowlist.
+*
+*   --allow 10.0.0.0/8 --deny 0.0.0.0/0   only 10.x.x.x may connect
+*   --deny 192.0.2.0/24                   everyone except 192.0.2.x
+*   --allow 10.0.0.5 --deny 10.0.0.0/8    10.0.0.5 and non-10.x clients
+*/
+
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Error returned when a CIDR block or deny action cannot be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError(String);
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A block of IP addresses such as `10.0.0.0/8` or `2001:db8::/32`.
+///
+/// A bare address is treated as a single-host block (`/32` or `/128`).
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::ipfilter::Cidr;
+/// use std::net::IpAddr;
+///
+/// let block: Cidr = "192.168.0.0/16".parse().unwrap();
+/// assert!(block.contains("192.168.4.2".parse::<IpAddr>().unwrap()));
+/// assert!(!block.contains("10.0.0.1".parse::<IpAddr>().unwrap()));
+///
+/// let host: Cidr = "::1".parse().unwrap();
+/// assert_eq!(host.prefix_len(), 128);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Number of leading bits that must match.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns whether `ip` falls inside this block.
+    ///
+    /// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) match IPv4 blocks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = mask_u32(self.prefix_len);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = mask_u128(self.prefix_len);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network = canonical(
+            addr.trim()
+                .parse::<IpAddr>()
+                .map_err(|_| ParseError(format!("invalid IP address in '{}'", s)))?,
+        );
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| ParseError(format!("invalid prefix length in '{}'", s)))?,
+            None => max_len,
+        };
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+fn mask_u32(prefix_len: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0)
+}
+
+fn mask_u128(prefix_len: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0)
+}
+
+/// What to do with a connection from a denied address.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DenyAction {
+    /// Reply `403 Forbidden` and close.
+    #[default]
+    Forbid,
+    /// Close the connection without sending anything.
+    Drop,
+}
+
+impl FromStr for DenyAction {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forbid" => Ok(DenyAction::Forbid),
+            "drop" => Ok(DenyAction::Drop),
+            _ => Err(ParseError(format!(
+                "unknown deny action '{}' (expected 'forbid' or 'drop')",
+                s
+            ))),
+        }
+    }
+}
+
+/// Allow/deny rule set evaluated against client addresses.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::ipfilter::IpFilter;
+///
+/// let filter = IpFilter::new(
+///     vec!["10.0.0.0/8".parse().unwrap()],
+///     vec!["0.0.0.0/0".parse().unwrap()],
+/// );
+/// assert!(filter.is_allowed("10.1.2.3".parse().unwrap()));
+/// assert!(!filter.is_allowed("203.0.113.7".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    /// Creates a filter from allow and deny blocks.
+    pub fn new(allow: Vec<Cidr>, deny: Vec<Cidr>) -> Self {
+        Self { allow, deny }
+    }
+
+    /// Returns true if no rules are configured.
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Returns whether a client at `ip` may connect.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let most_specific = |rules: &[Cidr]| {
+            rules
+                .iter()
+                .filter(|cidr| cidr.contains(ip))
+                .map(Cidr::prefix_len)
+                .max()
+        };
+
+        match (most_specific(&self.allow), most_specific(&self.deny)) {
+            (Some(allow), Some(deny)) => allow > deny,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => self.allow.is_empty() || !self.deny.is_empty(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidrs(list: &[&str]) -> Vec<Cidr> {
+        list.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr_parse_errors() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("not-an-ip/8".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_cidr_contains() {
+        let zero: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(zero.contains(ip("255.255.255.255")));
+        assert!(!zero.contains(ip("::1")));
+
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(ip("2001:db8:1::5")));
+        assert!(!v6.contains(ip("2001:db9::1")));
+    }
+
+    #[test]
+    fn test_ipv4_mapped_addresses_match_ipv4_rules() {
+        let block: Cidr = "127.0.0.0/8".parse().unwrap();
+        assert!(block.contains(ip("::ffff:127.0.0.1")));
+    }
+
+    #[test]
+    fn test_allowlist_only() {
+        let filter = IpFilter::new(cidrs(&["10.0.0.0/8"]), vec![]);
+        assert!(filter.is_allowed(ip("10.9.9.9")));
+        assert!(!filter.is_allowed(ip("192.168.1.1")));
+    }
+
+    #[test]
+    fn test_denylist_only() {
+        let filter = IpFilter::new(vec![], cidrs(&["192.0.2.0/24"]));
+        assert!(!filter.is_allowed(ip("192.0.2.10")));
+        assert!(filter.is_allowed(ip("198.51.100.1")));
+    }
+
+    #[test]
+    fn test_most_specific_rule_wins() {
+        let filter = IpFilter::new(cidrs(&["10.0.0.5"]), cidrs(&["10.0.0.0/8"]));
+        assert!(filter.is_allowed(ip("10.0.0.5")));
+        assert!(!filter.is_allowed(ip("10.0.0.6")));
+        assert!(filter.is_allowed(ip("172.16.0.1")));
+    }
+
+    #[test]
+    fn test_deny_wins_ties() {
+        let filter = IpFilter::new(cidrs(&["10.0.0.0/8"]), cidrs(&["10.0.0.0/8"]));
+        assert!(!filter.is_allowed(ip("10.1.1.1")));
+    }
+
+    #[test]
+    fn test_deny_action_from_str() {
+        assert_eq!("drop".parse::<DenyAction>().unwrap(), DenyAction::Drop);
+        assert_eq!("forbid".parse::<DenyAction>().unwrap(), DenyAction::Forbid);
+        assert!("reset".parse::<DenyAction>().is_err());
+    }
+}
@@ -0,0 +1,125 @@
+/*
+* LAN address module
+*
+* A share listening on 0.0.0.0 is reachable under every address of the
+* machine, but the wildcard is all the log shows. At startup the server
+* lists one URL per network address instead, and can print the most likely
+* one as a QR code, so a phone on the same network opens the share with its
+* camera.
+*
+* Loopback and link-local addresses are left out: they are useless from
+* another device. Private IPv4 addresses (home and office networks) come
+* first, then other IPv4 addresses, then IPv6 ones.
+*/
+
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use tracing::debug;
+
+/// URLs under which a server listening on `bind` can be reached from other
+/// machines, the most likely first.
+///
+/// A wildcard `bind` gives one URL per interface address (IPv4 only for
+/// `0.0.0.0`), a specific address just itself, and loopback none.
+pub fn lan_urls(scheme: &str, bind: SocketAddr) -> Vec<String> {
+    let ips = if bind.ip().is_unspecified() {
+        match if_addrs::get_if_addrs() {
+            Ok(interfaces) => interfaces
+                .iter()
+                .map(|interface| interface.ip())
+                .filter(|ip| bind.is_ipv6() || ip.is_ipv4())
+                .collect(),
+            Err(e) => {
+                debug!("Failed to list network interfaces: {}", e);
+                Vec::new()
+            }
+        }
+    } else {
+        vec![bind.ip()]
+    };
+    rank(ips)
+        .into_iter()
+        .map(|ip| format!("{}://{}/", scheme, SocketAddr::new(ip, bind.port())))
+        .collect()
+}
+
+/// `text` as a QR code drawn with half-block characters, light on dark as
+/// terminals usually are, or `None` if it is too long to encode.
+pub fn qr_code(text: &str) -> Option<String> {
+    let code = QrCode::new(text).ok()?;
+    Some(
+        code.render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build(),
+    )
+}
+
+/// Drops addresses other devices cannot use and orders the rest by how
+/// likely they are to be the machine's LAN address.
+fn rank(ips: Vec<IpAddr>) -> Vec<IpAddr> {
+    let mut usable: Vec<IpAddr> = ips
+        .into_iter()
+        .filter(|ip| {
+            !ip.is_loopback()
+                && !ip.is_unspecified()
+                && match ip {
+                    IpAddr::V4(v4) => !v4.is_link_local(),
+                    IpAddr::V6(v6) => !v6.is_unicast_link_local(),
+                }
+        })
+        .collect();
+    let mut seen = HashSet::new();
+    usable.retain(|ip| seen.insert(*ip));
+    usable.sort_by_key(|ip| match ip {
+        IpAddr::V4(v4) if v4.is_private() => 0,
+        IpAddr::V4(_) => 1,
+        IpAddr::V6(_) => 2,
+    });
+    usable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rank() {
+        let ips: Vec<IpAddr> = [
+            "127.0.0.1",
+            "fe80::1",
+            "2001:db8::5",
+            "169.254.3.4",
+            "203.0.113.9",
+            "192.168.1.20",
+            "192.168.1.20",
+        ]
+        .iter()
+        .map(|ip| ip.parse().unwrap())
+        .collect();
+        let ranked: Vec<String> = rank(ips).iter().map(IpAddr::to_string).collect();
+        assert_eq!(ranked, ["192.168.1.20", "203.0.113.9", "2001:db8::5"]);
+    }
+
+    #[test]
+    fn test_lan_urls_of_specific_addresses() {
+        let bind = "192.168.1.20:7878".parse().unwrap();
+        assert_eq!(lan_urls("http", bind), ["http://192.168.1.20:7878/"]);
+        let bind = "[2001:db8::5]:443".parse().unwrap();
+        assert_eq!(lan_urls("https", bind), ["https://[2001:db8::5]:443/"]);
+        assert!(lan_urls("http", "127.0.0.1:7878".parse().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_qr_code() {
+        let code = qr_code("http://192.168.1.20:7878/").unwrap();
+        let lines: Vec<&str> = code.lines().collect();
+        // Two modules per character row, with a quiet zone all around
+        assert!(lines.len() > 10);
+        assert!(lines
+            .iter()
+            .all(|line| line.chars().count() == lines[0].chars().count()));
+    }
+}
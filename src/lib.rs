@@ -1,3 +1,81 @@
-pub mod files;
+pub mod admin;
+pub mod archive;
+#[cfg(feature = "audit")]
+pub mod audit;
+pub mod auth;
+pub mod ban;
+pub mod bench;
+pub mod cgi;
+pub mod check;
+pub mod checksum;
+pub mod compcache;
+mod conditional;
+pub mod config;
+pub mod connlimit;
+pub mod cookie;
+mod dashboard;
 pub mod data;
+pub mod defaults;
+pub mod dump;
+pub mod embed;
+pub mod error;
+pub mod fastcgi;
+pub mod files;
+pub mod fingerprint;
+mod flight;
+#[cfg(feature = "geoip")]
+pub mod geoip;
+#[cfg(feature = "git")]
+pub mod git;
+pub mod glob;
+pub mod headers;
+mod hex;
+pub mod hotlink;
+pub mod ignore;
+pub mod ipfilter;
+pub mod lan;
+pub mod listing;
+pub mod livereload;
+pub mod maintenance;
 pub mod message;
+pub mod middleware;
+pub mod multipart;
+#[cfg(feature = "object-store")]
+pub mod objectstore;
+pub mod parser;
+pub mod pool;
+pub mod prewarm;
+pub mod proxy;
+pub mod quota;
+mod range;
+pub mod ratelimit;
+pub mod reaper;
+pub mod redirects;
+pub mod rewrite;
+pub mod router;
+pub mod search;
+pub mod sendfile;
+pub mod server;
+pub mod share;
+pub mod signing;
+pub mod sitemap;
+pub mod sites;
+pub mod stats;
+pub mod suggest;
+pub mod syslog;
+pub mod target;
+pub mod telemetry;
+pub mod throttle;
+pub mod tls;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod tus;
+pub mod units;
+#[cfg(unix)]
+pub mod upgrade;
+pub mod upload;
+pub mod upstream;
+pub mod variants;
+pub mod webdav;
+
+pub use server::{Server, Service};
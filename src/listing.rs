@@ -0,0 +1,759 @@
+/*
+* Listing module
+*
+* Auto-index pages for directories without an index file. A `Listing` holds
+* everything a page shows (entries, breadcrumbs, server info) and
+* `AutoIndex` renders it, either with the built-in page or, with the
+* `templates` feature, a user-supplied Tera template:
+*
+*   <h1>{{ path }}</h1>
+*   {% for entry in entries %}
+*     <a href="{{ entry.href }}">{{ entry.name }}</a> {{ entry.size | filesizeformat }}
+*   {% endfor %}
+*
+* Listings are sorted and filtered on the server from the query string,
+* `?sort=size|name|mtime&order=asc|desc&filter=*.log`, and served as JSON
+* with `format=json`. `TreeNode` describes a whole subtree at once, for the
+* `/_api/tree` endpoint. Hidden entries (names starting with `.`) are never
+* listed.
+*/
+
+use crate::files::{DirEntry, FileMetadata, FileTree};
+use crate::glob::Glob;
+use crate::target::{decode_component, encode_path};
+use crate::units::{format_size, utc_date_time};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One entry of a directory listing.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListingEntry {
+    pub name: String,
+    /// Percent-encoded link relative to the listed directory; directories end in `/`.
+    pub href: String,
+    pub is_dir: bool,
+    /// Size in bytes; 0 for directories.
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch, if known.
+    pub modified: Option<u64>,
+}
+
+/// A link to one of the directories above the listed one.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Breadcrumb {
+    pub name: String,
+    /// Percent-encoded absolute path, ending in `/`.
+    pub href: String,
+}
+
+/// Name and version of the server, for page footers.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServerInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+}
+
+impl Default for ServerInfo {
+    fn default() -> Self {
+        Self {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
+
+/// What listing entries are sorted by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Mtime,
+}
+
+/// Sort direction of a listing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Listing options taken from the query string.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ListingQuery {
+    pub sort: SortKey,
+    pub order: SortOrder,
+    /// Glob that file names must match, e.g. `*.log`.
+    pub filter: Option<String>,
+    /// Answer with JSON instead of HTML (`format=json`).
+    pub json: bool,
+}
+
+impl ListingQuery {
+    /// Parses `sort`, `order`, `filter` and `format`; other parameters are
+    /// ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::listing::{ListingQuery, SortKey, SortOrder};
+    ///
+    /// let query = ListingQuery::parse(Some("sort=size&order=desc&filter=*.log")).unwrap();
+    /// assert_eq!(query.sort, SortKey::Size);
+    /// assert_eq!(query.order, SortOrder::Desc);
+    /// assert_eq!(query.filter.as_deref(), Some("*.log"));
+    /// assert!(ListingQuery::parse(Some("sort=colour")).is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the first unknown or malformed value.
+    pub fn parse(query: Option<&str>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let pairs = query
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")));
+        for (key, value) in pairs {
+            match (key, value) {
+                ("sort", "name") => parsed.sort = SortKey::Name,
+                ("sort", "size") => parsed.sort = SortKey::Size,
+                ("sort", "mtime") => parsed.sort = SortKey::Mtime,
+                ("order", "asc") => parsed.order = SortOrder::Asc,
+                ("order", "desc") => parsed.order = SortOrder::Desc,
+                ("format", "html") => parsed.json = false,
+                ("format", "json") => parsed.json = true,
+                ("filter", "") => parsed.filter = None,
+                ("filter", value) => {
+                    let filter = decode_component(value)
+                        .map_err(|e| format!("invalid filter '{}': {}", value, e))?;
+                    if filter.contains('/') {
+                        return Err(format!("filter '{}' may not contain '/'", filter));
+                    }
+                    parsed.filter = Some(filter);
+                }
+                ("sort" | "order" | "format", value) => {
+                    return Err(format!("invalid {} '{}'", key, value))
+                }
+                _ => {}
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// The contents of an auto-index page; this is also the context a custom
+/// template receives.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Listing {
+    /// Decoded path of the directory, ending in `/`.
+    pub path: String,
+    /// From the root (`/`) down to the listed directory itself.
+    pub breadcrumbs: Vec<Breadcrumb>,
+    /// Directories first, then files, each in `sort` and `order`.
+    pub entries: Vec<ListingEntry>,
+    pub sort: SortKey,
+    pub order: SortOrder,
+    /// The glob applied to file names, if any.
+    pub filter: Option<String>,
+    /// Whether the page should offer a form that uploads into the directory.
+    pub upload: bool,
+    /// Whether the page should offer a search box for `/_search`.
+    pub search: bool,
+    pub server: ServerInfo,
+}
+
+impl Listing {
+    /// Builds the listing of `dir` (a request path) from its entries.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::files::{FileTree, MemorySource};
+    /// use file_shover::listing::Listing;
+    ///
+    /// let tree = FileTree::from_source(
+    ///     MemorySource::new().with("docs/guide.md", "# Guide").with("docs/.draft", ""),
+    /// );
+    /// let listing = Listing::new("/docs/", tree.list("/docs/").unwrap());
+    /// assert_eq!(listing.entries.len(), 1);
+    /// assert_eq!(listing.entries[0].href, "guide.md");
+    /// assert_eq!(listing.breadcrumbs.last().unwrap().href, "/docs/");
+    /// ```
+    pub fn new(dir: &str, entries: Vec<DirEntry>) -> Self {
+        let path = format!("{}/", dir.trim_end_matches('/'));
+        let mut breadcrumbs = vec![Breadcrumb {
+            name: "/".to_string(),
+            href: "/".to_string(),
+        }];
+        let mut href = String::from("/");
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            href.push_str(&encode_path(segment));
+            href.push('/');
+            breadcrumbs.push(Breadcrumb {
+                name: segment.to_string(),
+                href: href.clone(),
+            });
+        }
+
+        let mut listing = Self {
+            path,
+            breadcrumbs,
+            entries: Vec::new(),
+            sort: SortKey::Name,
+            order: SortOrder::Asc,
+            filter: None,
+            upload: false,
+            search: false,
+            server: ServerInfo::default(),
+        };
+        listing.entries = entries
+            .into_iter()
+            .filter(|entry| !entry.name.starts_with('.'))
+            .map(|entry| {
+                let mut href = encode_path(&entry.name);
+                if entry.metadata.is_dir {
+                    href.push('/');
+                }
+                ListingEntry {
+                    href,
+                    is_dir: entry.metadata.is_dir,
+                    size: if entry.metadata.is_dir {
+                        0
+                    } else {
+                        entry.metadata.len
+                    },
+                    modified: unix_secs(entry.metadata.modified),
+                    name: entry.name,
+                }
+            })
+            .collect();
+        listing.sort(SortKey::Name, SortOrder::Asc);
+        listing
+    }
+
+    /// Reorders the entries; directories stay ahead of files, and ties are
+    /// broken by name.
+    pub fn sort(&mut self, key: SortKey, order: SortOrder) {
+        self.entries.sort_by(|a, b| {
+            let ordering = match key {
+                SortKey::Name => Ordering::Equal,
+                SortKey::Size => a.size.cmp(&b.size),
+                SortKey::Mtime => a.modified.cmp(&b.modified),
+            }
+            .then_with(|| a.name.cmp(&b.name));
+            let ordering = match order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            };
+            b.is_dir.cmp(&a.is_dir).then(ordering)
+        });
+        self.sort = key;
+        self.order = order;
+    }
+
+    /// Keeps only the files whose name matches `pattern`; directories are
+    /// kept so the tree stays navigable.
+    pub fn filter(&mut self, pattern: &str) {
+        let glob = Glob::new(pattern);
+        self.entries
+            .retain(|entry| entry.is_dir || glob.matches(&entry.name));
+        self.filter = Some(pattern.to_string());
+    }
+
+    /// Applies the sort order and filter of `query`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::files::{FileTree, MemorySource};
+    /// use file_shover::listing::{Listing, ListingQuery};
+    ///
+    /// let tree = FileTree::from_source(
+    ///     MemorySource::new()
+    ///         .with("logs/app.log", "0123456789")
+    ///         .with("logs/db.log", "01")
+    ///         .with("logs/notes.txt", ""),
+    /// );
+    /// let mut listing = Listing::new("/logs/", tree.list("/logs/").unwrap());
+    /// listing.apply(&ListingQuery::parse(Some("sort=size&filter=*.log")).unwrap());
+    /// let names: Vec<_> = listing.entries.iter().map(|e| e.name.as_str()).collect();
+    /// assert_eq!(names, ["db.log", "app.log"]);
+    /// ```
+    pub fn apply(&mut self, query: &ListingQuery) {
+        if let Some(pattern) = &query.filter {
+            self.filter(pattern);
+        }
+        self.sort(query.sort, query.order);
+    }
+}
+
+/// Renders directory listings as HTML.
+#[derive(Default)]
+pub struct AutoIndex {
+    #[cfg(feature = "templates")]
+    template: Option<tera::Tera>,
+}
+
+impl AutoIndex {
+    /// Auto-index with the built-in page.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Auto-index rendered with the Tera template at `path`.
+    ///
+    /// The template receives the fields of [`Listing`]; output is
+    /// HTML-escaped unless a value is marked `| safe`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Invalid` if the template cannot be read or
+    /// does not compile.
+    #[cfg(feature = "templates")]
+    pub fn with_template(
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Self, crate::config::ConfigError> {
+        let path = path.as_ref();
+        let mut tera = tera::Tera::default();
+        // The .html name turns on Tera's autoescaping
+        tera.add_template_file(path, Some("listing.html"))
+            .map_err(|e| {
+                crate::config::ConfigError::Invalid(format!(
+                    "listing template {}: {}",
+                    path.display(),
+                    template_error(&e)
+                ))
+            })?;
+        Ok(Self {
+            template: Some(tera),
+        })
+    }
+
+    /// Renders `listing` as an HTML page.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a custom template fails to render, for example
+    /// because it uses a variable that does not exist.
+    pub fn render(&self, listing: &Listing) -> io::Result<String> {
+        #[cfg(feature = "templates")]
+        if let Some(tera) = &self.template {
+            let context = tera::Context::from_serialize(listing)
+                .map_err(|e| io::Error::other(template_error(&e)))?;
+            return tera
+                .render("listing.html", &context)
+                .map_err(|e| io::Error::other(template_error(&e)));
+        }
+        Ok(render_builtin(listing))
+    }
+}
+
+/// Tera errors keep the useful part in their sources.
+#[cfg(feature = "templates")]
+fn template_error(err: &tera::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = std::error::Error::source(err);
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// A file or directory with everything below it, down to a depth limit.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TreeNode {
+    pub name: String,
+    pub is_dir: bool,
+    /// Size in bytes; 0 for directories.
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch, if known.
+    pub modified: Option<u64>,
+    /// Contents of a directory, sorted by name; absent for files and for
+    /// directories at the depth limit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub children: Option<Vec<TreeNode>>,
+}
+
+impl TreeNode {
+    /// Walks `path` (a request path) in `tree`, expanding directories at
+    /// most `depth` levels down; `depth` 0 describes `path` alone. Entries
+    /// whose request path `readable` refuses are left out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::files::{FileTree, MemorySource};
+    /// use file_shover::listing::TreeNode;
+    ///
+    /// let tree = FileTree::from_source(
+    ///     MemorySource::new().with("docs/guide.md", "# Guide").with("docs/api/index.html", ""),
+    /// );
+    /// let node = TreeNode::walk(&tree, "/docs", 1, |_| true).unwrap();
+    /// let children = node.children.unwrap();
+    /// assert_eq!(children[0].name, "api");
+    /// assert!(children[0].children.is_none());
+    /// assert_eq!(children[1].size, 7);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if `path` does not exist or is hidden, and other
+    /// I/O errors from the file tree.
+    pub fn walk(
+        tree: &FileTree,
+        path: &str,
+        depth: usize,
+        readable: impl Fn(&str) -> bool,
+    ) -> io::Result<Self> {
+        let path = path.trim_end_matches('/');
+        if path.split('/').any(|segment| segment.starts_with('.')) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let name = path.rsplit('/').next().unwrap_or_default();
+        let mut node = Self::leaf(name, &tree.metadata(path)?);
+        node.expand(tree, path, depth, &readable)?;
+        Ok(node)
+    }
+
+    fn leaf(name: &str, metadata: &FileMetadata) -> Self {
+        Self {
+            name: name.to_string(),
+            is_dir: metadata.is_dir,
+            size: if metadata.is_dir { 0 } else { metadata.len },
+            modified: unix_secs(metadata.modified),
+            children: None,
+        }
+    }
+
+    fn expand(
+        &mut self,
+        tree: &FileTree,
+        path: &str,
+        depth: usize,
+        readable: &impl Fn(&str) -> bool,
+    ) -> io::Result<()> {
+        if !self.is_dir || depth == 0 {
+            return Ok(());
+        }
+        let mut children = Vec::new();
+        for entry in tree.list(format!("{}/", path))? {
+            let child_path = format!("{}/{}", path, entry.name);
+            if entry.name.starts_with('.') || !readable(&child_path) {
+                continue;
+            }
+            let mut child = Self::leaf(&entry.name, &entry.metadata);
+            child.expand(tree, &child_path, depth - 1, readable)?;
+            children.push(child);
+        }
+        children.sort_by(|a, b| a.name.cmp(&b.name));
+        self.children = Some(children);
+        Ok(())
+    }
+}
+
+fn unix_secs(time: Option<SystemTime>) -> Option<u64> {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|elapsed| elapsed.as_secs())
+}
+
+fn render_builtin(listing: &Listing) -> String {
+    let title = escape_html(&listing.path);
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Index of {title}</title>\n\
+         <style>body{{font-family:sans-serif;margin:2em}}td{{padding:0.2em 1em 0.2em 0}}\
+         td.size{{text-align:right}}</style>\n</head>\n<body>\n<h1>Index of "
+    );
+    for (i, crumb) in listing.breadcrumbs.iter().enumerate() {
+        if i > 1 {
+            html.push('/');
+        }
+        let _ = write!(
+            html,
+            "<a href=\"{}\">{}</a>",
+            escape_html(&crumb.href),
+            escape_html(&crumb.name)
+        );
+    }
+    html.push_str("</h1>\n");
+    let filter = listing.filter.as_deref().unwrap_or("");
+    let _ = writeln!(
+        html,
+        "<form><input type=\"hidden\" name=\"sort\" value=\"{}\">\
+         <input type=\"hidden\" name=\"order\" value=\"{}\">\
+         <input name=\"filter\" placeholder=\"*.log\" value=\"{}\"> <button>Filter</button></form>",
+        sort_name(listing.sort),
+        order_name(listing.order),
+        escape_html(filter)
+    );
+    if listing.search {
+        let _ = writeln!(
+            html,
+            "<form action=\"/_search\"><input type=\"hidden\" name=\"format\" value=\"html\">\
+             <input type=\"hidden\" name=\"path\" value=\"{}\">\
+             <input type=\"search\" name=\"q\" placeholder=\"Search {}\"> <button>Search</button></form>",
+            escape_html(&listing.path),
+            escape_html(&listing.path)
+        );
+    }
+    html.push_str("<table>\n<tr>");
+    for (key, label) in [
+        (SortKey::Name, "Name"),
+        (SortKey::Size, "Size"),
+        (SortKey::Mtime, "Modified"),
+    ] {
+        // Clicking the current column again flips the order
+        let order = if key == listing.sort && listing.order == SortOrder::Asc {
+            SortOrder::Desc
+        } else {
+            SortOrder::Asc
+        };
+        let mut href = format!("?sort={}&order={}", sort_name(key), order_name(order));
+        if !filter.is_empty() {
+            href.push_str("&filter=");
+            href.push_str(&encode_query_value(filter));
+        }
+        let _ = write!(
+            html,
+            "<th><a href=\"{}\">{}</a></th>",
+            escape_html(&href),
+            label
+        );
+    }
+    html.push_str("</tr>\n");
+    if listing.breadcrumbs.len() > 1 {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in &listing.entries {
+        let name = if entry.is_dir {
+            format!("{}/", entry.name)
+        } else {
+            entry.name.clone()
+        };
+        let size = if entry.is_dir {
+            "-".to_string()
+        } else {
+            format_size(entry.size)
+        };
+        let modified = entry.modified.map_or_else(String::new, |secs| {
+            let (year, month, day, hour, minute, _) = utc_date_time(secs);
+            format!("{year}-{month:02}-{day:02} {hour:02}:{minute:02}")
+        });
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{}\">{}</a></td><td class=\"size\">{}</td><td>{}</td></tr>",
+            escape_html(&entry.href),
+            escape_html(&name),
+            size,
+            modified
+        );
+    }
+    html.push_str("</table>\n");
+    if listing.upload {
+        html.push_str(
+            "<hr>\n<form method=\"post\" enctype=\"multipart/form-data\">\
+             <input type=\"file\" name=\"file\" multiple required> \
+             <button>Upload</button></form>\n",
+        );
+    }
+    let _ = write!(
+        html,
+        "<hr>\n<address>{} {}</address>\n</body>\n</html>\n",
+        listing.server.name, listing.server.version
+    );
+    html
+}
+
+fn sort_name(key: SortKey) -> &'static str {
+    match key {
+        SortKey::Name => "name",
+        SortKey::Size => "size",
+        SortKey::Mtime => "mtime",
+    }
+}
+
+fn order_name(order: SortOrder) -> &'static str {
+    match order {
+        SortOrder::Asc => "asc",
+        SortOrder::Desc => "desc",
+    }
+}
+
+fn encode_query_value(value: &str) -> String {
+    encode_path(value)
+        .replace('&', "%26")
+        .replace('=', "%3D")
+        .replace('+', "%2B")
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::FileMetadata;
+    use std::time::{Duration, SystemTime};
+
+    fn entry(name: &str, is_dir: bool, len: u64) -> DirEntry {
+        DirEntry {
+            name: name.to_string(),
+            metadata: FileMetadata {
+                len,
+                is_dir,
+                modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_214_330)),
+            },
+        }
+    }
+
+    fn sample() -> Listing {
+        Listing::new(
+            "/my docs/2024",
+            vec![
+                entry("b.txt", false, 2048),
+                entry("<script>.html", false, 1),
+                entry("archive", true, 4096),
+                entry(".git", true, 0),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_listing_entries_and_breadcrumbs() {
+        let listing = sample();
+        assert_eq!(listing.path, "/my docs/2024/");
+        let names: Vec<_> = listing.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["archive", "<script>.html", "b.txt"]);
+        assert_eq!(listing.entries[0].href, "archive/");
+        assert_eq!(listing.entries[0].size, 0);
+        assert_eq!(listing.entries[2].modified, Some(1_709_214_330));
+        let crumbs: Vec<_> = listing
+            .breadcrumbs
+            .iter()
+            .map(|c| c.href.as_str())
+            .collect();
+        assert_eq!(crumbs, ["/", "/my%20docs/", "/my%20docs/2024/"]);
+    }
+
+    #[test]
+    fn test_sort_and_filter() {
+        let mut listing = sample();
+        listing.sort(SortKey::Size, SortOrder::Desc);
+        let names: Vec<_> = listing.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["archive", "b.txt", "<script>.html"]);
+
+        listing.apply(&ListingQuery::parse(Some("filter=*.txt&sort=mtime")).unwrap());
+        let names: Vec<_> = listing.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["archive", "b.txt"]);
+        assert_eq!(listing.sort, SortKey::Mtime);
+        assert_eq!(listing.filter.as_deref(), Some("*.txt"));
+
+        let json = serde_json::to_value(&listing).unwrap();
+        assert_eq!(json["sort"], "mtime");
+        assert_eq!(json["order"], "asc");
+        assert_eq!(json["entries"][1]["size"], 2048);
+    }
+
+    #[test]
+    fn test_tree_walk() {
+        let tree = FileTree::from_source(
+            crate::files::MemorySource::new()
+                .with("a/b/c/deep.txt", "deep")
+                .with("a/top.txt", "top")
+                .with("a/.hidden/x", "x"),
+        );
+        let node = TreeNode::walk(&tree, "/a/", 2, |_| true).unwrap();
+        assert_eq!(node.name, "a");
+        let children = node.children.as_ref().unwrap();
+        let names: Vec<_> = children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["b", "top.txt"]);
+        let c = &children[0].children.as_ref().unwrap()[0];
+        assert_eq!(c.name, "c");
+        assert!(c.children.is_none());
+
+        let json = serde_json::to_value(TreeNode::walk(&tree, "/a/top.txt", 5, |_| true).unwrap())
+            .unwrap();
+        assert_eq!(json["size"], 3);
+        assert!(json.get("children").is_none());
+        assert_eq!(TreeNode::walk(&tree, "/", 0, |_| true).unwrap().name, "");
+        let err = TreeNode::walk(&tree, "/a/.hidden", 1, |_| true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let node = TreeNode::walk(&tree, "/a", 2, |path| path != "/a/b").unwrap();
+        assert_eq!(node.children.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_query_errors() {
+        assert_eq!(ListingQuery::parse(None).unwrap(), ListingQuery::default());
+        assert!(ListingQuery::parse(Some("format=json")).unwrap().json);
+        assert!(ListingQuery::parse(Some("order=up")).is_err());
+        assert!(ListingQuery::parse(Some("filter=../*")).is_err());
+        assert!(ListingQuery::parse(Some("filter=%ff")).is_err());
+        assert!(ListingQuery::parse(Some("page=2&archive=zip")).is_ok());
+    }
+
+    #[test]
+    fn test_builtin_page() {
+        let html = AutoIndex::new().render(&sample()).unwrap();
+        assert!(html.contains("<title>Index of /my docs/2024/</title>"));
+        assert!(html.contains("<a href=\"%3Cscript%3E.html\">&lt;script&gt;.html</a>"));
+        assert!(html.contains("<td class=\"size\">2.0 KiB</td><td>2024-02-29 13:45</td>"));
+        assert!(html.contains("<a href=\"../\">"));
+        assert!(!html.contains(".git"));
+        assert!(html.contains("<th><a href=\"?sort=name&amp;order=desc\">Name</a></th>"));
+
+        let mut listing = sample();
+        listing.apply(&ListingQuery::parse(Some("sort=size&filter=a%26b+*")).unwrap());
+        let html = AutoIndex::new().render(&listing).unwrap();
+        assert!(html.contains("href=\"?sort=size&amp;order=desc&amp;filter=a%26b%20*\""));
+        assert!(html.contains("value=\"a&amp;b *\""));
+    }
+
+    #[cfg(feature = "templates")]
+    #[test]
+    fn test_custom_template() {
+        let path =
+            std::env::temp_dir().join(format!("file-shover-listing-{}.html", std::process::id()));
+        std::fs::write(
+            &path,
+            "{{ breadcrumbs | length }}|{% for e in entries %}{{ e.name }}={{ e.size | filesizeformat }};{% endfor %}|{{ server.name }}",
+        )
+        .unwrap();
+        let index = AutoIndex::with_template(&path).unwrap();
+        let page = index.render(&sample()).unwrap();
+        assert_eq!(
+            page,
+            "3|archive=0 B;&lt;script&gt;.html=1 B;b.txt=2 kB;|file-shover"
+        );
+
+        std::fs::write(&path, "{% for e in entries %}").unwrap();
+        assert!(AutoIndex::with_template(&path).is_err());
+        std::fs::write(&path, "{{ missing }}").unwrap();
+        let index = AutoIndex::with_template(&path).unwrap();
+        assert!(index.render(&sample()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
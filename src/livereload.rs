@@ -0,0 +1,320 @@
+/*
+* Live reload module
+*
+* Development helper behind `--watch`: HTML pages get a small script that
+* opens a Server-Sent Events stream at `/_livereload`, and a polling watcher
+* bumps a change counter whenever a file under the watched roots is added,
+* removed or modified. Every open stream then sends a `reload` event and the
+* page refreshes itself.
+*
+* Polling (every few hundred milliseconds, skipping dot files) needs no
+* platform file-notification API and coalesces editors' burst of writes into
+* one reload. The script is served from `/_livereload.js` rather than inline
+* so a `script-src 'self'` Content-Security-Policy still admits it.
+*
+* Each open page holds one worker thread for its event stream; streams send
+* a comment every 15 seconds so closed tabs are noticed and their thread
+* freed.
+*/
+
+use crate::message::{Body, Request, Response, StatusCode, DEFAULT_INTERNAL_ERROR_BODY};
+use crate::middleware::Middleware;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info};
+
+/// Path of the event stream.
+const EVENTS_PATH: &str = "/_livereload";
+
+/// Path of the injected script.
+const SCRIPT_PATH: &str = "/_livereload.js";
+
+const SCRIPT: &str =
+    "new EventSource(\"/_livereload\").addEventListener(\"reload\", () => location.reload());\n";
+
+const SCRIPT_TAG: &str = "<script src=\"/_livereload.js\"></script>";
+
+/// Time between keep-alive comments on an idle event stream.
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// Reloads browsers when watched files change.
+///
+/// Clones share the change counter.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::livereload::LiveReload;
+/// use file_shover::message::{Request, Response};
+/// use file_shover::middleware::Middleware;
+///
+/// let live = LiveReload::new();
+/// let req = Request::builder().path("/index.html").build();
+/// let page = Response::new()
+///     .content_type("text/html")
+///     .body("<html><body>Hi</body></html>");
+/// let page = live.after(&req, page);
+/// assert_eq!(
+///     page.body.known_length(),
+///     Some("<html><body>Hi<script src=\"/_livereload.js\"></script></body></html>".len() as u64)
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LiveReload {
+    changes: Arc<(Mutex<u64>, Condvar)>,
+}
+
+impl LiveReload {
+    /// Creates a live reloader that only reloads on [`LiveReload::changed`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Polls the files under `roots` every `interval` on a background
+    /// thread, reloading browsers whenever they change.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the watcher thread cannot be started.
+    pub fn watch(&self, roots: Vec<PathBuf>, interval: Duration) -> io::Result<()> {
+        let live = self.clone();
+        let mut last = fingerprint(&roots);
+        std::thread::Builder::new()
+            .name("livereload".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                let current = fingerprint(&roots);
+                if current != last {
+                    last = current;
+                    info!("🔄 Files changed, reloading browsers");
+                    live.changed();
+                }
+            })?;
+        Ok(())
+    }
+
+    /// Tells every open page to reload.
+    pub fn changed(&self) {
+        let (count, wakeup) = &*self.changes;
+        *count.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        wakeup.notify_all();
+    }
+
+    /// Number of changes seen so far; other caches compare it to notice
+    /// changed files.
+    pub fn generation(&self) -> u64 {
+        *self.changes.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn events(&self) -> Response {
+        let seen = self.generation();
+        Response::new()
+            .status(StatusCode::OK)
+            .content_type("text/event-stream")
+            .header("Cache-Control", "no-store")
+            .body(Body::chunked(Events {
+                live: self.clone(),
+                seen,
+                pending: Cursor::new(b"retry: 1000\n\n".to_vec()),
+            }))
+    }
+}
+
+impl Middleware for LiveReload {
+    fn before(&self, req: &Request) -> Option<Response> {
+        match req.path() {
+            EVENTS_PATH => Some(self.events()),
+            SCRIPT_PATH => Some(
+                Response::new()
+                    .status(StatusCode::OK)
+                    .content_type("application/javascript")
+                    .header("Cache-Control", "no-store")
+                    .body(SCRIPT),
+            ),
+            _ => None,
+        }
+    }
+
+    fn after(&self, _req: &Request, mut response: Response) -> Response {
+        let is_html = response
+            .headers
+            .get("Content-Type")
+            .is_some_and(|mime| mime.starts_with("text/html"));
+        if response.status != StatusCode::OK
+            || !is_html
+            || response.headers.contains_key("Content-Encoding")
+        {
+            return response;
+        }
+        // HEAD answers carry the length of a body they do not send
+        if response.body.is_empty() {
+            let length = response
+                .headers
+                .get("Content-Length")
+                .and_then(|length| length.parse::<u64>().ok());
+            return match length {
+                Some(length) => response.content_length(length + SCRIPT_TAG.len() as u64),
+                None => response,
+            };
+        }
+        let mut html = Vec::new();
+        if let Err(e) = std::mem::take(&mut response.body)
+            .into_reader()
+            .read_to_end(&mut html)
+        {
+            debug!("Failed to read page for live reload: {}", e);
+            return Response::error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                DEFAULT_INTERNAL_ERROR_BODY,
+            );
+        }
+        let at = find_last(&html, b"</body>").unwrap_or(html.len());
+        html.splice(at..at, SCRIPT_TAG.bytes());
+        // The digest described the file, not the page with the script
+        response.headers.remove("Content-Digest");
+        response.content_length(html.len()).body(html)
+    }
+}
+
+/// The event stream of one page: blocks until the next change.
+struct Events {
+    live: LiveReload,
+    seen: u64,
+    pending: Cursor<Vec<u8>>,
+}
+
+impl Read for Events {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.position() as usize >= self.pending.get_ref().len() {
+            let (count, wakeup) = &*self.live.changes;
+            let count = count.lock().unwrap_or_else(|e| e.into_inner());
+            let (count, _) = wakeup
+                .wait_timeout_while(count, HEARTBEAT, |count| *count == self.seen)
+                .unwrap_or_else(|e| e.into_inner());
+            let event: &[u8] = if *count != self.seen {
+                self.seen = *count;
+                b"event: reload\ndata: reload\n\n"
+            } else {
+                b": ping\n\n"
+            };
+            self.pending = Cursor::new(event.to_vec());
+        }
+        self.pending.read(buf)
+    }
+}
+
+fn find_last(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .rposition(|window| window.eq_ignore_ascii_case(needle))
+}
+
+/// A hash of the name, size and modification time of every file below
+/// `roots`, skipping dot files.
+fn fingerprint(roots: &[PathBuf]) -> u64 {
+    fn walk(dir: &Path, hasher: &mut DefaultHasher) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        let mut entries: Vec<_> = entries.filter_map(Result::ok).collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            entry.file_name().hash(hasher);
+            if metadata.is_dir() {
+                walk(&entry.path(), hasher);
+            } else {
+                metadata.len().hash(hasher);
+                metadata
+                    .modified()
+                    .unwrap_or(SystemTime::UNIX_EPOCH)
+                    .hash(hasher);
+            }
+        }
+    }
+
+    let mut hasher = DefaultHasher::new();
+    for root in roots {
+        walk(root, &mut hasher);
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::HttpMethod;
+
+    #[test]
+    fn test_injects_into_html_only() {
+        let live = LiveReload::new();
+        let req = Request::builder().path("/").build();
+        let page = live.after(
+            &req,
+            Response::new()
+                .content_type("text/html; charset=utf-8")
+                .header("Content-Digest", "sha-256=:x:")
+                .body(Body::reader(Cursor::new("<p>no body tag</p>"))),
+        );
+        assert!(!page.headers.contains_key("Content-Digest"));
+        let mut html = String::new();
+        page.body.into_reader().read_to_string(&mut html).unwrap();
+        assert_eq!(html, format!("<p>no body tag</p>{}", SCRIPT_TAG));
+
+        let css = live.after(&req, Response::new().content_type("text/css").body("p{}"));
+        assert_eq!(css.body.known_length(), Some(3));
+
+        let head = Request::builder()
+            .method(HttpMethod::HEAD)
+            .path("/")
+            .build();
+        let head = live.after(
+            &head,
+            Response::new()
+                .content_type("text/html")
+                .content_length(10u64),
+        );
+        assert_eq!(
+            head.headers.get("Content-Length"),
+            Some(&(10 + SCRIPT_TAG.len()).to_string())
+        );
+    }
+
+    #[test]
+    fn test_events_and_watch() {
+        let root = std::env::temp_dir().join(format!("file-shover-live-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("index.html"), "v1").unwrap();
+
+        let live = LiveReload::new();
+        live.watch(vec![root.clone()], Duration::from_millis(20))
+            .unwrap();
+        let req = Request::builder().path(EVENTS_PATH).build();
+        let response = live.before(&req).unwrap();
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some(&"text/event-stream".to_string())
+        );
+        let mut events = response.body.into_reader();
+        let mut buf = [0; 64];
+        let n = events.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"retry: 1000\n\n");
+
+        // Dot files are ignored; a longer file is a change
+        std::fs::write(root.join(".swp"), "x").unwrap();
+        std::fs::write(root.join("index.html"), "v2 and more").unwrap();
+        let n = events.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"event: reload\ndata: reload\n\n");
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
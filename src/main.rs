@@ -1,139 +1,2133 @@
-use clap::Parser;
-use env_logger;
-use file_shover::files::FileData;
-use log::{debug, error, info, warn};
-use std::io::{Cursor, ErrorKind, Read};
+use clap::{CommandFactory, Parser, Subcommand};
+use std::ffi::OsString;
+use std::io::{ErrorKind, Read};
+use std::net::SocketAddr;
 use std::net::TcpListener;
-use std::net::TcpStream;
-use std::path::PathBuf;
-use std::sync::Arc;
-
-mod data;
-mod files;
-mod message;
-
-use data::get_mime_type;
-use files::FileTree;
-use message::{
-    HttpStatus, Request, Response, DEFAULT_BAD_REQUEST_BODY, DEFAULT_INTERNAL_ERROR_BODY,
-    DEFAULT_NOT_FOUND_BODY,
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info, info_span, warn};
+
+use file_shover::admin::Admin;
+use file_shover::archive::ArchiveLimits;
+use file_shover::auth::{AccessPolicy, BearerAuth};
+use file_shover::ban::AutoBan;
+use file_shover::bench::LoadTest;
+use file_shover::cgi::Cgi;
+use file_shover::check::{validate_root, RootCheck};
+use file_shover::compcache::CompressionCache;
+use file_shover::config::{Config, ConfigError};
+use file_shover::data::get_mime_type;
+use file_shover::defaults::Robots;
+use file_shover::dump::HttpDump;
+use file_shover::error::ServerError;
+use file_shover::fastcgi::{FastCgi, FastCgiAddress};
+use file_shover::files::{MemorySource, SingleFileSource};
+use file_shover::fingerprint::Fingerprints;
+use file_shover::glob::Glob;
+use file_shover::headers::{
+    Attachments, CacheTtls, HashedNames, HeaderRule, SecurityHeaders, DEFAULT_HASHED_NAME,
 };
+use file_shover::hotlink::HotlinkGuard;
+use file_shover::ignore::IGNORE_FILE;
+use file_shover::ipfilter::{Cidr, DenyAction, IpFilter};
+use file_shover::lan::{lan_urls, qr_code};
+use file_shover::listing::AutoIndex;
+use file_shover::livereload::LiveReload;
+use file_shover::maintenance::Maintenance;
+use file_shover::middleware::Compression;
+use file_shover::parser::Limits;
+use file_shover::proxy::TrustedProxies;
+use file_shover::quota::DownloadQuota;
+use file_shover::ratelimit::{Quota, RateLimiter};
+use file_shover::reaper::Reaper;
+use file_shover::redirects::RedirectMap;
+use file_shover::rewrite::{RewriteAction, RewriteRule};
+use file_shover::search::Search;
+use file_shover::server::{ServerBuilder, Service, ShutdownHandle};
+use file_shover::share::DownloadLimit;
+use file_shover::signing::{unix_now, UrlSigner};
+use file_shover::sitemap::Sitemap;
+use file_shover::sites::SiteManager;
+use file_shover::syslog::Facility;
+use file_shover::target::{encode_path, RequestTarget};
+use file_shover::telemetry::{self, LogLevel, LogTarget};
+use file_shover::throttle::Bandwidth;
+use file_shover::tls::{TlsConfig, TlsPolicy};
+use file_shover::units::{format_size, parse_duration, parse_rate, parse_size};
+use file_shover::upload::Uploads;
+use file_shover::upstream::{ProxyRoute, Upstream};
+use file_shover::variants::Variants;
+use file_shover::Server;
+
+/// How long an upgraded binary must keep running before it takes over.
+const UPGRADE_GRACE: Duration = Duration::from_secs(2);
+
+/// Following ports `share` tries when its port is taken.
+const SHARE_PORT_RETRIES: u16 = 10;
+
+/// How often certificate files are checked for renewals.
+const CERTIFICATE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Names a self-signed certificate covers unless others are given.
+const SELF_SIGNED_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
 
 /// A simple static file server
 #[derive(Parser, Debug)]
 #[command(name = "file-shover")]
 #[command(about = "A static file server written in Rust")]
 #[command(version = "1.0")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+// Parsed once at startup, so the size difference between variants is irrelevant
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serve files over HTTP (the default when no subcommand is given)
+    Serve(Args),
+    /// Print a signed, expiring URL for a file
+    Sign(SignArgs),
+    /// Summarize an --audit-db database: totals, top files and top clients
+    Report(ReportArgs),
+    /// Load-test a running server, or one started for the run
+    Bench(BenchArgs),
+    /// Check a root for problems before serving it: unreadable files, escaping symlinks, unsafe names
+    Audit(AuditArgs),
+    /// Serve one file or directory until it was downloaded N times or a timeout passes, then exit
+    Share(ShareArgs),
+}
+
+#[derive(clap::Args, Clone, Debug)]
 struct Args {
-    /// Root directory to serve files from
+    /// Root directory to serve files from; repeat to overlay directories, first match wins. A single file is served alone, at / and under its name
+    #[arg(
+        short,
+        long,
+        value_name = "PATH",
+        required_unless_present_any = ["embedded", "object_store", "git", "stdin", "config"]
+    )]
+    root: Vec<PathBuf>,
+
+    /// Serve the site compiled into the binary (requires the `embed` feature)
+    #[arg(long, conflicts_with = "root")]
+    embedded: bool,
+
+    /// Serve from a bucket, e.g. s3://bucket/site, gs://... or az://... (requires the `object-store` feature)
+    #[arg(long, value_name = "URL", conflicts_with_all = ["root", "embedded"])]
+    object_store: Option<String>,
+
+    /// Serve a revision of a git repository without a checkout, e.g. /srv/docs.git@v1.2.3 or repo@main:public (requires the `git` feature)
+    #[arg(long, value_name = "REPO@REV", conflicts_with_all = ["root", "embedded", "object_store"])]
+    git: Option<String>,
+
+    /// Read standard input to the end and serve it, at / and under --name
+    #[arg(long, conflicts_with_all = ["root", "embedded", "object_store", "git"])]
+    stdin: bool,
+
+    /// File name standard input is served under; its extension sets the content type
+    #[arg(long, value_name = "NAME", default_value = "stdin", requires = "stdin")]
+    name: String,
+
+    /// Hide matching paths as if they did not exist, e.g. "*.log" or "node_modules/**" (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Serve everything even if the root has a .shoverignore file
+    #[arg(long)]
+    no_ignore_file: bool,
+
+    /// Answer proxied responses carrying X-Shover-Send: /path with that file, ranges and caching included
+    #[arg(long)]
+    sendfile: bool,
+
+    /// Read up to this much of the served files at startup (e.g. 256M), smallest first, to warm the caches
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    prewarm: Option<u64>,
+
+    /// Check each --root for problems at startup and log them (see the audit subcommand)
+    #[arg(long)]
+    self_check: bool,
+
+    /// Port to listen on; 0 lets the system pick a free one
+    #[arg(short, long, default_value = "7878")]
+    port: u16,
+
+    /// If the port is taken, try up to N following ports instead of exiting
+    #[arg(long, value_name = "N", default_value = "0")]
+    port_retry: u16,
+
+    /// Write the port actually listened on to this file once bound, for scripts using --port 0
+    #[arg(long, value_name = "PATH")]
+    port_file: Option<PathBuf>,
+
+    /// Export request spans to this OTLP/HTTP collector (requires the `otel` feature)
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// Write log lines to stderr or, on Unix, to the local syslog daemon
+    #[arg(long, value_name = "TARGET", default_value = "stderr")]
+    log_target: LogTarget,
+
+    /// Syslog facility: user, daemon, auth or local0-local7
+    #[arg(long, value_name = "FACILITY", default_value = "daemon")]
+    syslog_facility: Facility,
+
+    /// Show a live view of requests, connections and throughput instead of log lines (requires the `tui` feature)
+    #[arg(long)]
+    tui: bool,
+
+    /// Print the address other devices on the network can use as a QR code at startup
+    #[arg(long)]
+    qr: bool,
+
+    /// Maximum sustained requests per second for each client IP
+    #[arg(long, value_name = "RPS", value_parser = parse_rps)]
+    rate_limit: Option<f64>,
+
+    /// Requests a client IP may burst above --rate-limit (defaults to the rate)
+    #[arg(long, value_name = "N", default_value = "0", requires = "rate_limit")]
+    rate_burst: u32,
+
+    /// Maximum sustained requests per second across all clients
+    #[arg(long, value_name = "RPS", value_parser = parse_rps)]
+    global_rate_limit: Option<f64>,
+
+    /// Requests all clients together may burst above --global-rate-limit
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "0",
+        requires = "global_rate_limit"
+    )]
+    global_rate_burst: u32,
+
+    /// Maximum simultaneous connections from a single client IP
+    #[arg(long, value_name = "N")]
+    max_connections_per_ip: Option<usize>,
+
+    /// Maximum simultaneous connections across all clients
+    #[arg(long, value_name = "N")]
+    max_connections: Option<usize>,
+
+    /// Connections that may wait for a free worker; further ones get 503 (default 1024)
+    #[arg(long, value_name = "N")]
+    max_queue: Option<usize>,
+
+    /// Longest request line; longer ones get 414
+    #[arg(long, value_name = "SIZE", default_value = "8K", value_parser = parse_size)]
+    max_request_line: u64,
+
+    /// Largest request head (request line and headers); larger ones get 431
+    #[arg(long, value_name = "SIZE", default_value = "64K", value_parser = parse_size)]
+    max_header_size: u64,
+
+    /// Most request headers; more get 431
+    #[arg(long, value_name = "N", default_value = "100")]
+    max_headers: usize,
+
+    /// Largest request body announced with Content-Length; larger ones get 413
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_body_size: Option<u64>,
+
+    /// Log the raw head of every request and response, with bodies cut and secrets redacted
+    #[arg(long)]
+    dump_http: bool,
+
+    /// Body bytes shown per message by --dump-http, 0 for none
+    #[arg(long, value_name = "SIZE", default_value = "1KB", value_parser = parse_size, requires = "dump_http")]
+    dump_http_body: u64,
+
+    /// Show Authorization, Cookie and Set-Cookie values in --dump-http output
+    #[arg(long, requires = "dump_http")]
+    dump_http_secrets: bool,
+
+    /// Warn about requests taking longer than this to parse, open and write, e.g. 500ms
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    slow_request: Option<Duration>,
+
+    /// Close connections whose client sends or reads nothing for this long
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    idle_timeout: Option<Duration>,
+
+    /// Close connections open for longer than this, busy or not
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    max_connection_age: Option<Duration>,
+
+    /// Close connections whose client sends slower than this while a request
+    /// is being read, e.g. 1KB/s
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    min_read_rate: Option<u64>,
+
+    /// Time spent reading over which --min-read-rate is averaged (default 30s)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "min_read_rate")]
+    min_read_rate_window: Option<Duration>,
+
+    /// Allow clients in this CIDR block (repeatable); with no --deny, everyone else is denied
+    #[arg(long, value_name = "CIDR")]
+    allow: Vec<Cidr>,
+
+    /// Deny clients in this CIDR block (repeatable); the most specific rule wins
+    #[arg(long, value_name = "CIDR")]
+    deny: Vec<Cidr>,
+
+    /// How to treat denied clients: "forbid" (403) or "drop" (close silently)
+    #[arg(long, value_name = "ACTION", default_value = "forbid")]
+    deny_action: DenyAction,
+
+    /// MaxMind-format country database (.mmdb) used for --allow-country, --deny-country and request logs
+    #[arg(long, value_name = "PATH")]
+    geoip_db: Option<PathBuf>,
+
+    /// Allow clients located in this country, as an ISO code (repeatable); everyone else is denied
+    #[arg(long, value_name = "CODE", requires = "geoip_db")]
+    allow_country: Vec<String>,
+
+    /// Deny clients located in this country, as an ISO code (repeatable)
+    #[arg(long, value_name = "CODE", requires = "geoip_db")]
+    deny_country: Vec<String>,
+
+    /// Proxy CIDR blocks whose Forwarded/X-Forwarded-For headers name the real client (comma-separated or repeatable)
+    #[arg(long, value_name = "CIDR", value_delimiter = ',')]
+    trusted_proxies: Vec<Cidr>,
+
+    /// Require `Authorization: Bearer <TOKEN>` on every request (repeatable)
+    #[arg(long, value_name = "TOKEN")]
+    token: Vec<String>,
+
+    /// Read accepted bearer tokens from a file, one per line
+    #[arg(long, value_name = "PATH")]
+    token_file: Option<PathBuf>,
+
+    /// Secret used to verify signed URLs (see `file-shover sign`)
+    #[arg(long, value_name = "SECRET", conflicts_with = "sign_secret_file")]
+    sign_secret: Option<String>,
+
+    /// Read the signed URL secret from a file
+    #[arg(long, value_name = "PATH")]
+    sign_secret_file: Option<PathBuf>,
+
+    /// Refuse requests that carry neither a valid signature nor a valid bearer token
+    #[arg(long)]
+    require_signed: bool,
+
+    /// Reject media requests whose Referer is not this site or an allowed host
+    #[arg(long)]
+    hotlink_protection: bool,
+
+    /// Host allowed to embed media (repeatable, `*.example.com` for subdomains); implies --hotlink-protection
+    #[arg(long, value_name = "HOST")]
+    hotlink_allow: Vec<String>,
+
+    /// Also reject media requests that carry no Referer at all
+    #[arg(long)]
+    hotlink_block_empty_referer: bool,
+
+    /// File served instead of hotlinked media (default: 403)
+    #[arg(long, value_name = "PATH")]
+    hotlink_placeholder: Option<PathBuf>,
+
+    /// List the contents of directories that are requested directly
+    #[arg(long)]
+    auto_index: bool,
+
+    /// Tera template for directory listings (requires the `templates` feature); implies --auto-index
+    #[arg(long, value_name = "PATH")]
+    listing_template: Option<PathBuf>,
+
+    /// Add SHA-256 ETag and Content-Digest headers to files
+    #[arg(long)]
+    checksums: bool,
+
+    /// Answer /file.sha256 with a generated checksum when the file has no sidecar; implies --checksums
+    #[arg(long)]
+    checksum_sidecars: bool,
+
+    /// Accept PUT uploads and DELETE requests in the (first) root directory
+    #[arg(long)]
+    writable: bool,
+
+    /// Largest accepted upload
+    #[arg(long, value_name = "SIZE", default_value = "100M", value_parser = parse_size, requires = "writable")]
+    max_upload_size: u64,
+
+    /// Where DELETE moves files, relative to the root unless absolute [default: .trash]
+    #[arg(long, value_name = "DIR", requires = "writable")]
+    trash_dir: Option<PathBuf>,
+
+    /// Delete files for good instead of moving them to the trash
+    #[arg(long, requires = "writable", conflicts_with = "trash_dir")]
+    permanent_delete: bool,
+
+    /// Offer an upload form on directory listings and accept its multipart POSTs
+    #[arg(long, requires = "writable")]
+    upload_form: bool,
+
+    /// Accept resumable uploads with the tus protocol at /_tus/
+    #[arg(long, requires = "writable")]
+    resumable: bool,
+
+    /// Answer WebDAV PROPFIND (and MKCOL, COPY and MOVE with --writable) to mount the tree as a drive
+    #[arg(long)]
+    webdav: bool,
+
+    /// Only allow writes to paths matching this glob (can be repeated)
+    #[arg(long, value_name = "GLOB", requires = "writable")]
+    write_allow: Vec<String>,
+
+    /// Reload open HTML pages in the browser when files under --root change (development)
+    #[arg(long, requires = "root")]
+    watch: bool,
+
+    /// Serve the file tree as JSON at /_api/tree?path=/dir&depth=N
+    #[arg(long)]
+    tree_api: bool,
+
+    /// Deepest level /_api/tree expands
+    #[arg(long, value_name = "N", default_value = "10", requires = "tree_api")]
+    tree_api_max_depth: usize,
+
+    /// Generate /sitemap.xml from the HTML files, with URLs under this site address, e.g. https://example.com
+    #[arg(long, value_name = "URL")]
+    sitemap: Option<String>,
+
+    /// Leave paths matching this glob out of the sitemap (repeatable)
+    #[arg(long, value_name = "GLOB", requires = "sitemap")]
+    sitemap_exclude: Vec<String>,
+
+    /// Also serve stylesheets, scripts, images and fonts under content-hashed names (app.<hash>.js) cached as immutable, listed in /asset-manifest.json
+    #[arg(long)]
+    fingerprint: bool,
+
+    /// Leave paths matching this glob unfingerprinted (repeatable)
+    #[arg(long, value_name = "GLOB", requires = "fingerprint")]
+    fingerprint_exclude: Vec<String>,
+
+    /// Serve /data from data.html or data.json according to the Accept header
+    #[arg(long)]
+    negotiate: bool,
+
+    /// Offer this variant instead of the HTML and JSON defaults, most preferred first (repeatable), e.g. text/csv=csv
+    #[arg(long, value_name = "TYPE=EXT", requires = "negotiate", value_parser = parse_variant)]
+    variant: Vec<(String, String)>,
+
+    /// Serve full-text search over text, Markdown and HTML files at /_search
+    #[arg(long)]
+    search: bool,
+
+    /// Leave paths matching this glob out of the search index (repeatable)
+    #[arg(long, value_name = "GLOB", requires = "search")]
+    search_exclude: Vec<String>,
+
+    /// Answer /robots.txt with a generated file when the root has none
+    #[arg(long)]
+    robots: bool,
+
+    /// Path prefix crawlers may visit despite a broader disallow (repeatable)
+    #[arg(long, value_name = "PATH", requires = "robots")]
+    robots_allow: Vec<String>,
+
+    /// Path prefix crawlers are asked to stay out of (repeatable)
+    #[arg(long, value_name = "PATH", requires = "robots")]
+    robots_disallow: Vec<String>,
+
+    /// Answer /favicon.ico with a built-in icon when the root has none
+    #[arg(long)]
+    favicon: bool,
+
+    /// Offer similarly named files on 404 pages and in a Link header
+    #[arg(long)]
+    suggest: bool,
+
+    /// Send each response no faster than this, e.g. 2MB/s
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    limit_rate: Option<u64>,
+
+    /// Cap the combined sending rate of all connections, e.g. 10MB/s
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    global_limit_rate: Option<u64>,
+
+    /// Bytes each client may download per --download-quota-window before getting 429
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    download_quota: Option<u64>,
+
+    /// Length of the download quota window
+    #[arg(long, value_name = "DURATION", default_value = "24h", value_parser = parse_duration, requires = "download_quota")]
+    download_quota_window: Duration,
+
+    /// Ban clients for --auto-ban-duration after --auto-ban-errors client errors within --auto-ban-window
+    #[arg(long)]
+    auto_ban: bool,
+
+    /// Client errors (4xx other than 401 and 429) that get a client banned
+    #[arg(long, value_name = "N", default_value_t = 20, requires = "auto_ban")]
+    auto_ban_errors: u32,
+
+    /// Window in which --auto-ban-errors must occur
+    #[arg(long, value_name = "DURATION", default_value = "1m", value_parser = parse_duration, requires = "auto_ban")]
+    auto_ban_window: Duration,
+
+    /// How long a ban lasts
+    #[arg(long, value_name = "DURATION", default_value = "10m", value_parser = parse_duration, requires = "auto_ban")]
+    auto_ban_duration: Duration,
+
+    /// Record every request (time, client, path, status, bytes, duration, User-Agent) in this SQLite database
+    #[arg(long, value_name = "PATH")]
+    audit_db: Option<PathBuf>,
+
+    /// Delete audit records older than this
+    #[arg(long, value_name = "DURATION", default_value = "30d", value_parser = parse_duration, requires = "audit_db")]
+    audit_retention: Duration,
+
+    /// Answer every request with 503 while <root>/.maintenance exists; SIGUSR1 toggles it too
+    #[arg(long)]
+    maintenance: bool,
+
+    /// Flag file checked instead of <root>/.maintenance; implies --maintenance
+    #[arg(long, value_name = "PATH")]
+    maintenance_file: Option<PathBuf>,
+
+    /// HTML page sent with the 503 during maintenance; implies --maintenance
+    #[arg(long, value_name = "PATH")]
+    maintenance_page: Option<PathBuf>,
+
+    /// Retry-After sent during maintenance
+    #[arg(long, value_name = "DURATION", default_value = "5m", value_parser = parse_duration)]
+    maintenance_retry_after: Duration,
+
+    /// Serve the admin API (stats, reload, purge, log level, maintenance, bans) on this address, e.g. 127.0.0.1:9090
+    #[arg(long, value_name = "ADDR")]
+    admin_bind: Option<SocketAddr>,
+
+    /// Bearer token required by the admin API; mandatory when --admin-bind is not a loopback address
+    #[arg(long, value_name = "TOKEN", requires = "admin_bind")]
+    admin_token: Option<String>,
+
+    /// After handing over to an upgraded binary (SIGUSR2), how long to keep serving open connections before exiting
+    #[arg(long, value_name = "DURATION", default_value = "1h", value_parser = parse_duration)]
+    drain_timeout: Duration,
+
+    /// Let clients download directories with ?archive=zip or ?archive=tar.gz
+    #[arg(long)]
+    archive_downloads: bool,
+
+    /// Largest total file size put into one archive
+    #[arg(long, value_name = "SIZE", default_value = "1G", value_parser = parse_size, requires = "archive_downloads")]
+    archive_max_size: u64,
+
+    /// Most files put into one archive
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "10000",
+        requires = "archive_downloads"
+    )]
+    archive_max_entries: usize,
+
+    /// Add X-Content-Type-Options, X-Frame-Options and Referrer-Policy to every response
+    #[arg(long)]
+    security_headers: bool,
+
+    /// Gzip text, JSON, JavaScript and SVG responses for clients that accept it
+    #[arg(long)]
+    compress: bool,
+
+    /// Keep up to this much gzipped output in memory instead of compressing every request
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "compress", conflicts_with = "compress_cache_dir")]
+    compress_cache: Option<u64>,
+
+    /// Keep gzipped output as files in this directory, surviving restarts
+    #[arg(long, value_name = "DIR", requires = "compress")]
+    compress_cache_dir: Option<PathBuf>,
+
+    /// Header added to matching paths, e.g. "*.pdf => Content-Disposition: attachment" (repeatable)
+    #[arg(long, value_name = "RULE")]
+    header_rule: Vec<HeaderRule>,
+
+    /// Cache-Control max-age for files with this extension, 0 for no-cache (repeatable), e.g. css=1d
+    #[arg(long, value_name = "EXT=DURATION", value_parser = parse_cache_ttl)]
+    cache_ttl: Vec<(String, Duration)>,
+
+    /// Cache files with a content hash in their name, e.g. app.3f2a9c1b.js, for a year without revalidation
+    #[arg(long)]
+    immutable_hashed: bool,
+
+    /// Regex a file name must match to count as hashed
+    #[arg(long, value_name = "REGEX", default_value = DEFAULT_HASHED_NAME, requires = "immutable_hashed")]
+    hashed_name_pattern: String,
+
+    /// Rewrite or redirect matching paths, e.g. "^/old/(.*)$ => /new/$1 [301]" (repeatable, first match applies)
+    #[arg(long, value_name = "RULE")]
+    rewrite: Vec<RewriteRule>,
+
+    /// Redirects file with "<from> <to> [status]" lines, reloaded when it changes
+    #[arg(long, value_name = "PATH")]
+    redirects: Option<PathBuf>,
+
+    /// Forward a path prefix to an upstream, e.g. /api=http://127.0.0.1:8081 (repeatable)
+    #[arg(long = "proxy", value_name = "PREFIX=URL")]
+    proxy_routes: Vec<ProxyRoute>,
+
+    /// Forward requests that match no file (and non-GET methods) to this http:// upstream
+    #[arg(long, value_name = "URL")]
+    proxy_fallback: Option<Upstream>,
+
+    /// Run the executable files in this directory as CGI scripts under --cgi-prefix
+    #[arg(long, value_name = "PATH")]
+    cgi_dir: Option<PathBuf>,
+
+    /// URL prefix of the CGI scripts
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        default_value = "/cgi-bin",
+        requires = "cgi_dir"
+    )]
+    cgi_prefix: String,
+
+    /// Kill CGI scripts still running after this long
+    #[arg(long, value_name = "DURATION", default_value = "30s", value_parser = parse_duration, requires = "cgi_dir")]
+    cgi_timeout: Duration,
+
+    /// Forward requests for .php files under the first --root to this FastCGI responder (host:port or unix:PATH)
+    #[arg(long, value_name = "ADDRESS", requires = "root")]
+    fastcgi: Option<FastCgiAddress>,
+
+    /// Document root as seen by the FastCGI responder, if it differs from --root
+    #[arg(long, value_name = "PATH", requires = "fastcgi")]
+    fastcgi_root: Option<PathBuf>,
+
+    /// Give up on a FastCGI responder that sends nothing for this long
+    #[arg(long, value_name = "DURATION", default_value = "60s", value_parser = parse_duration, requires = "fastcgi")]
+    fastcgi_timeout: Duration,
+
+    /// PEM certificate chain; serves HTTPS together with --tls-key
+    #[arg(long, value_name = "PATH", requires = "tls_key", group = "tls")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, value_name = "PATH", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Serve HTTPS with a generated self-signed certificate for these names
+    /// (default: localhost, 127.0.0.1 and ::1); meant for local testing
+    #[arg(long, value_name = "HOST", num_args = 0.., value_delimiter = ',', group = "tls")]
+    tls_self_signed: Option<Vec<String>>,
+
+    /// Keep the self-signed certificate in this directory and reuse it, so
+    /// browsers only have to be told to trust it once
+    #[arg(long, value_name = "DIR", requires = "tls_self_signed")]
+    tls_self_signed_dir: Option<PathBuf>,
+
+    /// PEM file of CAs whose client certificates are accepted (mutual TLS)
+    #[arg(long, value_name = "PATH", requires = "tls")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Also let in clients without a certificate; path rules can still
+    /// require one with access = "cert"
+    #[arg(long, requires = "tls_client_ca")]
+    tls_client_optional: bool,
+
+    /// TOML configuration file with rules that do not fit on the command line
     #[arg(short, long, value_name = "PATH")]
+    config: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct SignArgs {
+    /// URL path of the file to share, e.g. /releases/app.zip
+    path: String,
+
+    /// Secret shared with the server's --sign-secret
+    #[arg(long, value_name = "SECRET", conflicts_with = "secret_file")]
+    secret: Option<String>,
+
+    /// Read the secret from a file
+    #[arg(long, value_name = "PATH")]
+    secret_file: Option<PathBuf>,
+
+    /// How long the link stays valid (e.g. 30m, 12h, 7d)
+    #[arg(long, value_name = "DURATION", default_value = "1h", value_parser = parse_duration)]
+    expires_in: Duration,
+
+    /// Scheme and host to prefix the signed path with
+    #[arg(long, value_name = "URL", default_value = "http://localhost:7878")]
+    base_url: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReportArgs {
+    /// Database written by --audit-db
+    db: PathBuf,
+
+    /// Only count requests from this far back (e.g. 24h, 7d)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    since: Option<Duration>,
+
+    /// Number of files and clients to list
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    top: usize,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct BenchArgs {
+    /// URL to request, e.g. http://127.0.0.1:7878/index.html
+    #[arg(long, value_name = "URL", required_unless_present = "root")]
+    url: Option<String>,
+
+    /// Serve this directory in-process and benchmark it instead of --url
+    #[arg(long, value_name = "PATH", conflicts_with = "url")]
+    root: Option<PathBuf>,
+
+    /// Path to request from the in-process server
+    #[arg(long, value_name = "PATH", default_value = "/", requires = "root")]
+    path: String,
+
+    /// Clients requesting at the same time
+    #[arg(short, long, value_name = "N", default_value_t = 10)]
+    concurrency: usize,
+
+    /// How long to keep sending requests
+    #[arg(short, long, value_name = "DURATION", default_value = "10s", value_parser = parse_duration)]
+    duration: Duration,
+
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct AuditArgs {
+    /// Directory to check
     root: PathBuf,
 
-    /// Port to listen on
+    /// Report directories with more entries than this
+    #[arg(long, value_name = "N", default_value_t = 10_000)]
+    max_dir_entries: usize,
+
+    /// Print the findings as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ShareArgs {
+    /// File to share; a directory is shared as a zip archive
+    path: PathBuf,
+
+    /// Exit after this many complete downloads
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    max_downloads: u64,
+
+    /// Exit after this long, downloaded or not (e.g. 10m, 2h)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    timeout: Option<Duration>,
+
+    /// Print a signed link that expires with --timeout and refuse unsigned requests
+    #[arg(long, requires = "timeout")]
+    sign: bool,
+
+    /// Port to listen on; if it is taken, the following ports are tried
     #[arg(short, long, default_value = "7878")]
     port: u16,
+
+    /// Print a QR code of the link for phones on the same network
+    #[arg(long)]
+    qr: bool,
+}
+
+/// Inserts the `serve` subcommand when none is given, so `file-shover --root .` keeps working.
+fn with_default_subcommand(mut argv: Vec<OsString>) -> Vec<OsString> {
+    let first = argv.get(1).and_then(|arg| arg.to_str());
+    let is_subcommand = first.is_some_and(|first| {
+        Cli::command()
+            .get_subcommands()
+            .any(|sub| sub.get_name() == first)
+    });
+    let is_top_level_flag = matches!(first, Some("-h" | "--help" | "-V" | "--version" | "help"));
+    if !is_subcommand && !is_top_level_flag {
+        argv.insert(1.min(argv.len()), OsString::from("serve"));
+    }
+    argv
+}
+
+fn read_secret(
+    secret: &Option<String>,
+    file: &Option<PathBuf>,
+) -> std::io::Result<Option<Vec<u8>>> {
+    match (secret, file) {
+        (Some(secret), _) => Ok(Some(secret.as_bytes().to_vec())),
+        (None, Some(path)) => {
+            let content = std::fs::read_to_string(path)?;
+            Ok(Some(
+                content.trim_end_matches(['\r', '\n']).as_bytes().to_vec(),
+            ))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+fn rate_limiter(args: &Args) -> Option<RateLimiter> {
+    let per_ip = args
+        .rate_limit
+        .map(|rate| Quota::new(rate, args.rate_burst));
+    let global = args
+        .global_rate_limit
+        .map(|rate| Quota::new(rate, args.global_rate_burst));
+    if per_ip.is_none() && global.is_none() {
+        return None;
+    }
+    Some(RateLimiter::new(per_ip, global))
+}
+
+fn bearer_auth(args: &Args, config: &Config) -> std::io::Result<BearerAuth> {
+    let mut tokens = args.token.clone();
+    tokens.extend(config.auth.tokens.iter().cloned());
+    if let Some(path) = &args.token_file {
+        tokens.extend(BearerAuth::tokens_from_file(path)?);
+    }
+    Ok(BearerAuth::new(tokens))
+}
+
+fn load_config(args: &Args) -> Result<Config, ConfigError> {
+    match &args.config {
+        Some(path) => Config::load(path),
+        None => Ok(Config::default()),
+    }
+}
+
+/// Rewrite rules from the config file followed by `--rewrite` flags.
+fn rewrite_rules(args: &Args, config: &Config) -> Result<Vec<RewriteRule>, ConfigError> {
+    let mut rules = config
+        .rewrites
+        .iter()
+        .map(|rule| rule.parse())
+        .collect::<Result<Vec<RewriteRule>, _>>()?;
+    rules.extend(args.rewrite.iter().cloned());
+    if let Some(name) = single_file(args) {
+        // `$` would start a capture reference in the target
+        let target = format!("/{}", name.replace('$', "$$"));
+        rules.push(RewriteRule::new("^/$", target, RewriteAction::Rewrite)?);
+    }
+    Ok(rules)
+}
+
+/// Header rules from the config file followed by `--header-rule` flags.
+fn header_rules(args: &Args, config: &Config) -> Result<Vec<HeaderRule>, ConfigError> {
+    let mut rules = config
+        .headers
+        .iter()
+        .map(|rule| rule.parse())
+        .collect::<Result<Vec<HeaderRule>, _>>()?;
+    rules.extend(args.header_rule.iter().cloned());
+    Ok(rules)
+}
+
+/// Parses a `--rate-limit` value: a positive number of requests per second.
+fn parse_rps(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(format!(
+            "expected a positive number of requests per second, got '{}'",
+            s
+        )),
+    }
+}
+
+/// Parses a `--cache-ttl` value: an extension and a duration, `css=1d`.
+fn parse_cache_ttl(s: &str) -> Result<(String, Duration), String> {
+    match s.split_once('=') {
+        Some((extension, ttl)) if !extension.trim().is_empty() => {
+            Ok((extension.trim().to_string(), parse_duration(ttl)?))
+        }
+        _ => Err(format!("expected EXT=DURATION, e.g. css=1d, got '{}'", s)),
+    }
+}
+
+/// Parses a `--variant` value: a media type and an extension, `text/csv=csv`.
+fn parse_variant(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((mime, extension)) if mime.contains('/') && !extension.is_empty() => {
+            Ok((mime.trim().to_string(), extension.trim().to_string()))
+        }
+        _ => Err(format!("expected TYPE=EXT, e.g. text/csv=csv, got '{}'", s)),
+    }
 }
 
-// parse request
-fn handle_client(mut stream: TcpStream, file_tree: &FileTree) {
-    // Parse the request and handle parsing errors
-    let req = match Request::from_bytes(&stream) {
-        Ok(request) => request,
-        Err(e) => {
-            debug!("Failed to parse request: {}", e);
-            let mut response = Response::new()
-                .status(HttpStatus::BadRequest)
-                .content_type("text/html")
-                .content_length(DEFAULT_NOT_FOUND_BODY.as_bytes().len())
-                .body(Box::new(Cursor::new(DEFAULT_BAD_REQUEST_BODY.as_bytes())));
-
-            if let Err(write_err) = response.write(&mut stream) {
-                debug!("Failed to write error response: {}", write_err);
+#[cfg(feature = "embed")]
+fn embedded_site() -> Result<MemorySource, ServerError> {
+    Ok(file_shover::embed::site())
+}
+
+#[cfg(not(feature = "embed"))]
+fn embedded_site() -> Result<MemorySource, ServerError> {
+    Err(
+        ConfigError::Invalid("--embedded requires a build with the `embed` feature".to_string())
+            .into(),
+    )
+}
+
+#[cfg(feature = "object-store")]
+fn with_object_store(builder: ServerBuilder, url: &str) -> Result<ServerBuilder, ServerError> {
+    let source = file_shover::objectstore::ObjectStoreSource::from_url(url)?;
+    Ok(builder.file_source(source))
+}
+
+#[cfg(not(feature = "object-store"))]
+fn with_object_store(_builder: ServerBuilder, _url: &str) -> Result<ServerBuilder, ServerError> {
+    Err(ConfigError::Invalid(
+        "--object-store requires a build with the `object-store` feature".to_string(),
+    )
+    .into())
+}
+
+#[cfg(feature = "geoip")]
+fn with_geoip(
+    builder: ServerBuilder,
+    args: &Args,
+    path: &Path,
+) -> Result<ServerBuilder, ServerError> {
+    let geoip = file_shover::geoip::GeoIp::open(path)?
+        .allow(args.allow_country.clone())
+        .deny(args.deny_country.clone());
+    if geoip.has_rules() {
+        info!(
+            "🌍 Country rules: {} allow, {} deny",
+            args.allow_country.len(),
+            args.deny_country.len()
+        );
+    }
+    Ok(builder.geoip(geoip))
+}
+
+#[cfg(not(feature = "geoip"))]
+fn with_geoip(
+    _builder: ServerBuilder,
+    _args: &Args,
+    _path: &Path,
+) -> Result<ServerBuilder, ServerError> {
+    Err(
+        ConfigError::Invalid("--geoip-db requires a build with the `geoip` feature".to_string())
+            .into(),
+    )
+}
+
+#[cfg(feature = "audit")]
+fn with_audit(builder: ServerBuilder, shared: &Shared) -> Result<ServerBuilder, ServerError> {
+    Ok(match &shared.audit_log {
+        Some(log) => builder.audit_log(log.clone()),
+        None => builder,
+    })
+}
+
+#[cfg(not(feature = "audit"))]
+fn with_audit(_builder: ServerBuilder, _shared: &Shared) -> Result<ServerBuilder, ServerError> {
+    Err(
+        ConfigError::Invalid("--audit-db requires a build with the `audit` feature".to_string())
+            .into(),
+    )
+}
+
+#[cfg(feature = "tui")]
+fn with_tui(server: file_shover::Server) -> Result<(), ServerError> {
+    let tui = file_shover::tui::Tui::new(&server)?.start(server.shutdown_handle())?;
+    let result = server.run();
+    tui.stop()?;
+    result
+}
+
+#[cfg(not(feature = "tui"))]
+fn with_tui(_server: file_shover::Server) -> Result<(), ServerError> {
+    Err(ConfigError::Invalid("--tui requires a build with the `tui` feature".to_string()).into())
+}
+
+#[cfg(feature = "audit")]
+fn report(args: ReportArgs) -> Result<(), ServerError> {
+    let since = args.since.map(|since| std::time::SystemTime::now() - since);
+    let report = file_shover::audit::report(&args.db, since, args.top)?;
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(std::io::Error::other)?
+        );
+        return Ok(());
+    }
+    println!(
+        "{} requests, {} sent",
+        report.requests,
+        format_size(report.bytes)
+    );
+    for (title, entries) in [
+        ("Top files", &report.top_files),
+        ("Top clients", &report.top_clients),
+    ] {
+        println!("\n{}:", title);
+        for entry in entries {
+            println!(
+                "{:>10} {:>12}  {}",
+                entry.requests,
+                format_size(entry.bytes),
+                entry.name
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "audit"))]
+fn report(_args: ReportArgs) -> Result<(), ServerError> {
+    Err(ConfigError::Invalid("report requires a build with the `audit` feature".to_string()).into())
+}
+
+#[cfg(feature = "git")]
+fn with_git(builder: ServerBuilder, spec: &str) -> Result<ServerBuilder, ServerError> {
+    let source = file_shover::git::GitSource::from_spec(spec)?;
+    Ok(builder.file_source(source))
+}
+
+#[cfg(not(feature = "git"))]
+fn with_git(_builder: ServerBuilder, _spec: &str) -> Result<ServerBuilder, ServerError> {
+    Err(ConfigError::Invalid("--git requires a build with the `git` feature".to_string()).into())
+}
+
+/// Flips maintenance mode each time the process receives SIGUSR1.
+#[cfg(unix)]
+fn toggle_on_sigusr1(maintenance: Maintenance) -> std::io::Result<()> {
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGUSR1])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            if maintenance.toggle() {
+                info!("🚧 Maintenance mode on");
+            } else {
+                info!("🚧 Maintenance mode off");
             }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn toggle_on_sigusr1(_maintenance: Maintenance) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// The listening socket handed over by the process this one upgrades.
+#[cfg(unix)]
+fn inherited_listener() -> std::io::Result<Option<TcpListener>> {
+    file_shover::upgrade::inherited_listener()
+}
 
-            if let Err(e) = stream.shutdown(std::net::Shutdown::Both) {
-                debug!("Failed to shutdown stream: {}", e);
+#[cfg(not(unix))]
+fn inherited_listener() -> std::io::Result<Option<TcpListener>> {
+    Ok(None)
+}
+
+/// Starts the binary again on SIGUSR2 and hands it the listening socket
+/// and the admin port once it is up.
+#[cfg(unix)]
+fn upgrade_on_sigusr2(server: &Server, admin: AdminHandle, stdin: bool) -> std::io::Result<()> {
+    let listener = server.try_clone_listener()?;
+    let shutdown = server.shutdown_handle();
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGUSR2])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            if stdin {
+                warn!("Cannot upgrade: standard input cannot be read again");
+                continue;
+            }
+            info!("⬆️ Upgrade requested, starting the new binary");
+            match file_shover::upgrade::spawn_successor(&listener, UPGRADE_GRACE) {
+                Ok(child) => {
+                    info!(
+                        "⬆️ Process {} took over, handing over the listener",
+                        child.id()
+                    );
+                    if let Some(admin) = admin.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                        admin.shutdown();
+                    }
+                    shutdown.hand_over();
+                    break;
+                }
+                Err(e) => error!("Upgrade failed, still serving: {}", e),
             }
-            return;
         }
-    };
+    });
+    Ok(())
+}
 
-    info!("Request: {} {}", req.method, req.path);
-
-    let mut response = match file_tree.get_reader(&req.path) {
-        Err(e) => {
-            if e.kind() == ErrorKind::NotFound {
-                info!("File not found: {}", req.path);
-                Response::new()
-                    .status(HttpStatus::NotFound)
-                    .content_type("text/html")
-                    .content_length(DEFAULT_NOT_FOUND_BODY.as_bytes().len())
-                    .body(Box::new(Cursor::new(DEFAULT_NOT_FOUND_BODY.as_bytes())))
-            } else {
-                info!("Server error for {}: {}", req.path, e);
-                Response::new()
-                    .status(HttpStatus::InternalServerError)
-                    .content_type("text/html")
-                    .content_length(DEFAULT_INTERNAL_ERROR_BODY.as_bytes().len())
-                    .body(Box::new(Cursor::new(
-                        DEFAULT_INTERNAL_ERROR_BODY.as_bytes(),
-                    )))
+#[cfg(not(unix))]
+fn upgrade_on_sigusr2(_server: &Server, _admin: AdminHandle, _stdin: bool) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(feature = "templates")]
+fn listing_template(path: &Path) -> Result<AutoIndex, ServerError> {
+    Ok(AutoIndex::with_template(path)?)
+}
+
+#[cfg(not(feature = "templates"))]
+fn listing_template(_path: &Path) -> Result<AutoIndex, ServerError> {
+    Err(ConfigError::Invalid(
+        "--listing-template requires a build with the `templates` feature".to_string(),
+    )
+    .into())
+}
+
+fn main() -> Result<(), ServerError> {
+    let cli = Cli::parse_from(with_default_subcommand(std::env::args_os().collect()));
+    match cli.command {
+        Command::Serve(args) => serve(args),
+        Command::Sign(args) => Ok(sign(args)?),
+        Command::Report(args) => report(args),
+        Command::Bench(args) => bench(args),
+        Command::Audit(args) => audit(args),
+        Command::Share(args) => share(args),
+    }
+}
+
+/// Warms the caches in the background, so serving starts right away.
+fn prewarm(service: Arc<Service>, budget: u64) {
+    info!("🔥 Pre-warming up to {} of files", format_size(budget));
+    let spawned = std::thread::Builder::new()
+        .name("prewarm".to_string())
+        .spawn(move || match service.prewarm(budget) {
+            Ok(warmed) => info!(
+                "🔥 Pre-warmed {} files ({}) in {:?}",
+                warmed.files,
+                format_size(warmed.bytes),
+                warmed.elapsed
+            ),
+            Err(e) => warn!("Pre-warming failed: {}", e),
+        });
+    if let Err(e) = spawned {
+        warn!("Could not start pre-warming: {}", e);
+    }
+}
+
+/// Fails unless every root can be served, and logs roots that expose too
+/// much; a lone root may be a file.
+fn validate_roots(roots: &[PathBuf]) -> Result<(), ConfigError> {
+    for root in roots {
+        for warning in validate_root(root, roots.len() == 1)? {
+            warn!("⚠️ {}", warning);
+        }
+    }
+    Ok(())
+}
+
+/// Logs the findings under each root; serving goes ahead regardless.
+fn self_check(roots: &[PathBuf]) {
+    for root in roots {
+        match RootCheck::new(root).run() {
+            Ok(report) => {
+                for finding in &report.findings {
+                    warn!("🩺 {}: {}", root.display(), finding);
+                }
+                info!(
+                    "🩺 Checked {} files in {}: {} problems",
+                    report.files,
+                    root.display(),
+                    report.findings.len()
+                );
             }
+            Err(e) => warn!("🩺 Could not check {}: {}", root.display(), e),
+        }
+    }
+}
+
+/// Prints the findings under a root; exits with status 1 if there are any,
+/// so CI can gate deploys on it.
+fn audit(args: AuditArgs) -> Result<(), ServerError> {
+    let report = RootCheck::new(&args.root)
+        .max_dir_entries(args.max_dir_entries)
+        .run()?;
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(std::io::Error::other)?
+        );
+    } else {
+        for finding in &report.findings {
+            println!("{}", finding);
         }
-        Ok(files::FileData {
-            mut reader,
-            metadata,
-        }) => {
-            info!("Successfully served: {}", req.path);
-            let mime_type = get_mime_type(&req.path);
-            Response::new()
-                .status(HttpStatus::Ok)
-                .content_type(mime_type.as_str())
-                .content_length(metadata.len())
-                .body(Box::new(reader))
+        println!(
+            "{} files in {} directories checked, {} problems",
+            report.files,
+            report.directories,
+            report.findings.len()
+        );
+    }
+    if !report.findings.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn bench(args: BenchArgs) -> Result<(), ServerError> {
+    // An in-process server lives until the run is over
+    let (url, server) = match &args.root {
+        // clap requires --url without --root
+        None => (args.url.clone().unwrap_or_default(), None),
+        Some(root) => {
+            let server = Server::builder()
+                .root(root.clone())
+                .bind("127.0.0.1:0")
+                .build()?;
+            let url = format!("http://{}{}", server.local_addr()?, args.path);
+            let shutdown = server.shutdown_handle();
+            (
+                url,
+                Some((shutdown, std::thread::spawn(move || server.run()))),
+            )
         }
     };
-
-    if let Err(e) = response.write(&mut stream) {
-        debug!("Failed to write response: {}", e);
+    eprintln!(
+        "Benchmarking {} with {} clients for {:?}...",
+        url, args.concurrency, args.duration
+    );
+    let report = LoadTest::new(&url)?
+        .concurrency(args.concurrency)
+        .duration(args.duration)
+        .run();
+    if let Some((shutdown, running)) = server {
+        shutdown.shutdown();
+        running
+            .join()
+            .map_err(|_| std::io::Error::other("in-process server panicked"))??;
     }
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(std::io::Error::other)?
+        );
+    } else {
+        println!("{}", report);
+    }
+    Ok(())
+}
 
-    if let Err(e) = stream.shutdown(std::net::Shutdown::Both) {
-        debug!("Failed to shutdown stream: {}", e);
+fn sign(args: SignArgs) -> std::io::Result<()> {
+    let secret = read_secret(&args.secret, &args.secret_file)?.ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "a secret is required (--secret or --secret-file)",
+        )
+    })?;
+    if !args.path.starts_with('/') {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "path must start with '/'",
+        ));
+    }
+    // Sign the path the server will see after decoding and normalizing
+    let target = RequestTarget::parse(&args.path).map_err(|e| {
+        std::io::Error::new(ErrorKind::InvalidInput, format!("invalid path: {}", e))
+    })?;
+    if target.query().is_some() {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "path must not contain a query string",
+        ));
     }
+
+    let expires = unix_now() + args.expires_in.as_secs();
+    let signer = UrlSigner::new(&secret);
+    println!(
+        "{}{}?{}",
+        args.base_url.trim_end_matches('/'),
+        target.encoded_path(),
+        signer.signed_query(target.path(), expires)
+    );
+    Ok(())
 }
 
-fn main() -> std::io::Result<()> {
-    env_logger::init();
+/// Serves one path until the download limit or the timeout is reached.
+fn share(args: ShareArgs) -> Result<(), ServerError> {
+    let _telemetry = telemetry::init(None, false, LogTarget::Stderr, Facility::default())
+        .map_err(|e| std::io::Error::other(format!("Failed to initialize telemetry: {}", e)))?;
+
+    let limit = DownloadLimit::new(args.max_downloads);
+    let builder = Server::builder()
+        .bind(format!("0.0.0.0:{}", args.port))
+        .port_retries(SHARE_PORT_RETRIES)
+        .download_limit(limit.clone());
+    // A directory goes out whole, as one archive
+    let (builder, path, query) = if args.path.is_dir() {
+        let builder = builder
+            .root(args.path.clone())
+            .archive_downloads(ArchiveLimits::default());
+        (builder, "/".to_string(), Some("archive=zip"))
+    } else {
+        let file = SingleFileSource::new(&args.path)?;
+        let path = format!("/{}", file.name());
+        (builder.file_source(file), path, None)
+    };
+    let mut query: Vec<String> = query.into_iter().map(str::to_string).collect();
+    let builder = if args.sign {
+        // clap requires --timeout with --sign
+        let expires_in = args.timeout.unwrap_or_default();
+        let signer = UrlSigner::random();
+        query.push(signer.signed_query(&path, unix_now() + expires_in.as_secs()));
+        builder.url_signer(signer).require_signed(true)
+    } else {
+        builder
+    };
+    let server = builder.build()?;
+
+    let mut link = encode_path(&path);
+    if !query.is_empty() {
+        link = format!("{}?{}", link, query.join("&"));
+    }
+    let mut urls = lan_urls(server.scheme(), server.local_addr()?);
+    if urls.is_empty() {
+        urls.push(format!(
+            "{}://localhost:{}/",
+            server.scheme(),
+            server.local_addr()?.port()
+        ));
+    }
+    let urls: Vec<String> = urls
+        .iter()
+        .map(|url| format!("{}{}", url.trim_end_matches('/'), link))
+        .collect();
+    info!(
+        "📦 Sharing {} for {} download(s){}",
+        args.path.display(),
+        args.max_downloads,
+        args.timeout
+            .map(|timeout| format!(" or {:?}", timeout))
+            .unwrap_or_default()
+    );
+    for url in &urls {
+        println!("{}", url);
+    }
+    if args.qr {
+        if let Some(code) = qr_code(&urls[0]) {
+            println!("{}", code);
+        }
+    }
 
-    let args = Args::parse();
+    let shutdown = server.shutdown_handle();
+    let timeout = args.timeout.unwrap_or(Duration::MAX);
+    std::thread::Builder::new()
+        .name("share".to_string())
+        .spawn(move || {
+            if limit.wait(timeout) {
+                info!("📦 Download limit reached, exiting");
+            } else {
+                info!("⏰ Share timed out, exiting");
+            }
+            shutdown.shutdown();
+        })?;
+    server.run()
+}
 
-    let bind_address = format!("0.0.0.0:{}", args.port);
-    let listener = TcpListener::bind(&bind_address)?;
-    let file_tree = Arc::new(FileTree::new(args.root.clone()));
-    let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(10)
-        .build()
-        .unwrap();
+fn serve(args: Args) -> Result<(), ServerError> {
+    let telemetry = telemetry::init(
+        args.otlp_endpoint.as_deref(),
+        args.tui && cfg!(feature = "tui"),
+        args.log_target,
+        args.syslog_facility,
+    )
+    .map_err(|e| std::io::Error::other(format!("Failed to initialize telemetry: {}", e)))?;
 
     info!("🚀 File Shover server starting...");
-    info!("📁 Serving files from: {}", args.root.display());
-    info!("🌐 Listening on: http://{}", bind_address);
-    info!("🔀 Thread pool size: 10");
+    let config = load_config(&args)?;
+    if !config.sites.is_empty() {
+        return serve_sites(args, config);
+    }
+    let has_source =
+        !args.root.is_empty() || args.embedded || args.object_store.is_some() || args.git.is_some();
+    if !has_source && !args.stdin {
+        return Err(ConfigError::Invalid(
+            "--root is required unless the config file has [[site]] blocks".to_string(),
+        )
+        .into());
+    }
+    validate_roots(&args.root)?;
+    if args.self_check {
+        self_check(&args.root);
+    }
+    let shared = Shared {
+        maintenance: maintenance(&args)?,
+        auto_ban: auto_ban(&args),
+        live_reload: live_reload(&args)?,
+        #[cfg(feature = "audit")]
+        audit_log: audit_log(&args)?,
+        stdin: read_stdin(&args)?,
+    };
+    let mut builder = configure(&args, config, &shared)?;
+    let taking_over = match inherited_listener()? {
+        Some(listener) => {
+            info!("⬆️ Took over the listening socket of the previous process");
+            builder = builder.listener(listener);
+            true
+        }
+        None => false,
+    };
+    let server = builder.build()?;
+    if let Some(budget) = args.prewarm {
+        prewarm(server.service(), budget);
+    }
+
+    if let Some(path) = &args.port_file {
+        write_port_file(path, server.local_addr()?.port())?;
+    }
+    let urls = lan_urls(server.scheme(), server.local_addr()?);
+    for url in &urls {
+        info!("📱 On your network: {}", url);
+    }
+    if args.qr {
+        match urls.first().and_then(|url| qr_code(url)) {
+            Some(code) => println!("{}", code),
+            None => warn!("No network address to show as a QR code"),
+        }
+    }
+    let (tui, stdin, drain_timeout) = (args.tui, args.stdin, args.drain_timeout);
+    let admin = match args.admin_bind {
+        Some(bind) => {
+            let log_level = telemetry.log_level();
+            start_admin(
+                &server,
+                bind,
+                Arc::new(args),
+                shared,
+                log_level,
+                taking_over,
+            )?
+        }
+        None => AdminHandle::default(),
+    };
+
+    if tui {
+        return with_tui(server);
+    }
+    upgrade_on_sigusr2(&server, admin, stdin)?;
+    if let Some(tls) = server.tls() {
+        tls.watch(CERTIFICATE_POLL_INTERVAL)?;
+    }
+    let connections = server.connections();
     info!("Press Ctrl+C to stop the server");
+    server.run()?;
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                let file_tree = Arc::clone(&file_tree);
-                pool.spawn(move || {
-                    handle_client(stream, &file_tree);
-                });
-            }
-            Err(e) => {
-                eprintln!("Connection failed: {}", e);
-            }
+    // Only a handover stops the accept loop: let downloads finish first
+    info!(
+        "⏳ Waiting up to {:?} for {} open connections",
+        drain_timeout,
+        connections.active()
+    );
+    if !connections.wait_idle(drain_timeout) {
+        warn!(
+            "Exiting with {} connections still open",
+            connections.active()
+        );
+    }
+    Ok(())
+}
+
+/// Runs a server per `[[site]]` block: configured from the command line,
+/// with the block's address, root, TLS and rules on top.
+fn serve_sites(args: Args, config: Config) -> Result<(), ServerError> {
+    if args.admin_bind.is_some() || args.tui || args.stdin || args.port_file.is_some() {
+        return Err(ConfigError::Invalid(
+            "--admin-bind, --tui, --stdin and --port-file cannot be combined with [[site]] blocks"
+                .to_string(),
+        )
+        .into());
+    }
+    #[cfg(feature = "audit")]
+    let audit = audit_log(&args)?;
+    let mut sites = SiteManager::new();
+    for site in &config.sites {
+        let _site = info_span!("site", name = %site.name()).entered();
+        if site.bind.is_empty() || site.root.is_empty() {
+            return Err(ConfigError::Invalid(format!(
+                "site '{}' needs both bind and root",
+                site.name()
+            ))
+            .into());
         }
+        let mut site_args = args.clone();
+        site_args.root = site.root.clone();
+        if site.tls_cert.is_some() || site.tls_key.is_some() {
+            site_args.tls_cert = site.tls_cert.clone();
+            site_args.tls_key = site.tls_key.clone();
+            site_args.tls_client_ca = site.tls_client_ca.clone();
+        }
+        validate_roots(&site_args.root)?;
+        if site_args.self_check {
+            self_check(&site_args.root);
+        }
+        let shared = Shared {
+            maintenance: maintenance(&site_args)?,
+            auto_ban: auto_ban(&site_args),
+            live_reload: live_reload(&site_args)?,
+            #[cfg(feature = "audit")]
+            audit_log: audit.clone(),
+            stdin: None,
+        };
+        let server = configure(&site_args, site.rules(), &shared)?
+            .bind(site.bind.clone())
+            .name(site.name())
+            .build()?;
+        if let Some(budget) = site_args.prewarm {
+            prewarm(server.service(), budget);
+        }
+        if let Some(tls) = server.tls() {
+            tls.watch(CERTIFICATE_POLL_INTERVAL)?;
+        }
+        sites = sites.site(server);
     }
+    info!("🗂️ Serving {} sites", sites.len());
+    sites.run()
+}
+
+/// Writes `port` to `path` through a temporary file, so a script polling
+/// for it never reads a partial number.
+fn write_port_file(path: &Path, port: u16) -> std::io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    std::fs::write(&partial, format!("{}\n", port))?;
+    std::fs::rename(&partial, path)?;
+    info!("📝 Port {} written to {}", port, path.display());
     Ok(())
 }
+
+/// Stops the admin API once it is listening, to free its port for an
+/// upgraded binary.
+type AdminHandle = Arc<Mutex<Option<ShutdownHandle>>>;
+
+/// Server state that outlives a reload of the configuration.
+#[derive(Clone, Default)]
+struct Shared {
+    maintenance: Option<Maintenance>,
+    auto_ban: Option<AutoBan>,
+    live_reload: Option<LiveReload>,
+    #[cfg(feature = "audit")]
+    audit_log: Option<file_shover::audit::AuditLog>,
+    /// What --stdin read, kept since it cannot be read again.
+    stdin: Option<MemorySource>,
+}
+
+/// Standard input as a one-file site for --stdin.
+fn read_stdin(args: &Args) -> Result<Option<MemorySource>, ServerError> {
+    if !args.stdin {
+        return Ok(None);
+    }
+    if args.name.is_empty() || args.name.contains('/') || args.name == ".." {
+        return Err(
+            ConfigError::Invalid(format!("--name '{}' is not a file name", args.name)).into(),
+        );
+    }
+    let mut contents = Vec::new();
+    std::io::stdin().read_to_end(&mut contents)?;
+    info!(
+        "📥 Read {} from standard input",
+        format_size(contents.len() as u64)
+    );
+    Ok(Some(MemorySource::new().with(&args.name, contents)))
+}
+
+/// The name of the one file served by --stdin or a --root naming a file,
+/// which `/` serves as well.
+fn single_file(args: &Args) -> Option<String> {
+    if args.stdin {
+        return Some(args.name.clone());
+    }
+    match args.root.as_slice() {
+        [root] if root.is_file() => root.file_name()?.to_str().map(str::to_string),
+        _ => None,
+    }
+}
+
+/// Live reload for --watch, with its file watcher started.
+fn live_reload(args: &Args) -> std::io::Result<Option<LiveReload>> {
+    if !args.watch {
+        return Ok(None);
+    }
+    info!("🔄 Watching files, pages reload when they change");
+    let live_reload = LiveReload::new();
+    live_reload.watch(args.root.clone(), Duration::from_millis(300))?;
+    Ok(Some(live_reload))
+}
+
+/// The audit database named by --audit-db, opened once so its writer
+/// survives reloads.
+#[cfg(feature = "audit")]
+fn audit_log(args: &Args) -> Result<Option<file_shover::audit::AuditLog>, ServerError> {
+    let Some(path) = &args.audit_db else {
+        return Ok(None);
+    };
+    info!(
+        "📒 Auditing requests to {} (kept {:?})",
+        path.display(),
+        args.audit_retention
+    );
+    file_shover::audit::AuditLog::open(path, Some(args.audit_retention)).map(Some)
+}
+
+/// Automatic bans as configured on the command line.
+fn auto_ban(args: &Args) -> Option<AutoBan> {
+    if !args.auto_ban {
+        return None;
+    }
+    info!(
+        "🚫 Auto-ban: {} client errors within {:?} ban a client for {:?}",
+        args.auto_ban_errors, args.auto_ban_window, args.auto_ban_duration
+    );
+    Some(AutoBan::new(
+        args.auto_ban_errors,
+        args.auto_ban_window,
+        args.auto_ban_duration,
+    ))
+}
+
+/// Maintenance mode as configured on the command line, with SIGUSR1 hooked up.
+fn maintenance(args: &Args) -> Result<Option<Maintenance>, ServerError> {
+    if !args.maintenance && args.maintenance_file.is_none() && args.maintenance_page.is_none() {
+        return Ok(None);
+    }
+    let mut maintenance = Maintenance::new().retry_after(args.maintenance_retry_after);
+    match (&args.maintenance_file, args.root.first()) {
+        (Some(path), _) => maintenance = maintenance.flag_file(path),
+        (None, Some(root)) => maintenance = maintenance.flag_file(root.join(".maintenance")),
+        (None, None) => {}
+    }
+    if let Some(path) = &args.maintenance_page {
+        maintenance = maintenance.page(std::fs::read(path)?);
+    }
+    info!("🚧 Maintenance mode switchable (flag file or SIGUSR1)");
+    toggle_on_sigusr1(maintenance.clone())?;
+    Ok(Some(maintenance))
+}
+
+/// Starts the admin API on its own thread; the pipeline is rebuilt from
+/// `args` and a freshly read config file on reload.
+fn start_admin(
+    server: &Server,
+    bind: SocketAddr,
+    args: Arc<Args>,
+    shared: Shared,
+    log_level: Option<LogLevel>,
+    taking_over: bool,
+) -> Result<AdminHandle, ServerError> {
+    if !bind.ip().is_loopback() && args.admin_token.is_none() {
+        return Err(ConfigError::Invalid(
+            "--admin-bind on a non-loopback address requires --admin-token".to_string(),
+        )
+        .into());
+    }
+    let mut admin = Admin::new(server);
+    if let Some(token) = &args.admin_token {
+        admin = admin.bearer_auth(BearerAuth::new(vec![token.clone()]));
+    }
+    if let Some(maintenance) = &shared.maintenance {
+        admin = admin.maintenance(maintenance.clone());
+    }
+    if let Some(bans) = &shared.auto_ban {
+        admin = admin.auto_ban(bans.clone());
+    }
+    if let Some(log_level) = log_level {
+        admin = admin.log_level(log_level);
+    }
+    let admin =
+        admin.reload(move || configure(&args, load_config(&args)?, &shared)?.build_service());
+    let handle = AdminHandle::default();
+    let slot = Arc::clone(&handle);
+    let start = move || -> Result<Server, ServerError> {
+        let admin = admin.build(&bind.to_string())?;
+        info!("🛠️ Admin API on http://{}/_admin/", admin.local_addr()?);
+        *slot.lock().unwrap_or_else(|e| e.into_inner()) = Some(admin.shutdown_handle());
+        Ok(admin)
+    };
+    let run = |admin: Server| {
+        if let Err(e) = admin.run() {
+            error!("Admin API stopped: {}", e);
+        }
+    };
+    if !taking_over {
+        let admin = start()?;
+        std::thread::spawn(move || run(admin));
+        return Ok(handle);
+    }
+    // The previous process frees the port once it has handed over
+    std::thread::spawn(move || {
+        while TcpListener::bind(bind).is_err() {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        match start() {
+            Ok(admin) => run(admin),
+            Err(e) => error!("Admin API failed to start: {}", e),
+        }
+    });
+    Ok(handle)
+}
+
+/// Builds the server from the command line and config file.
+fn configure(
+    args: &Args,
+    mut config: Config,
+    shared: &Shared,
+) -> Result<ServerBuilder, ServerError> {
+    config.security_headers.enabled |= args.security_headers;
+    let bearer_auth = bearer_auth(args, &config)?;
+    let access_policy = AccessPolicy::from_config(&config.auth)?;
+    if access_policy.requires_tokens() && bearer_auth.is_empty() {
+        return Err(ConfigError::Invalid(
+            "auth rules require a token but none is configured (--token, --token-file or auth.tokens)".to_string(),
+        )
+        .into());
+    }
+    if access_policy.requires_client_cert() && args.tls_client_ca.is_none() {
+        return Err(ConfigError::Invalid(
+            "auth rules require a client certificate but no --tls-client-ca is configured"
+                .to_string(),
+        )
+        .into());
+    }
+    let security_headers = SecurityHeaders::from_config(&config.security_headers);
+    let header_rules = header_rules(args, &config)?;
+    let cache_ttls = args.cache_ttl.iter().fold(
+        CacheTtls::from_config(&config.cache_ttl),
+        |ttls, (extension, ttl)| ttls.ttl(extension, *ttl),
+    );
+    let rewrite_rules = rewrite_rules(args, &config)?;
+    let ip_filter = IpFilter::new(args.allow.clone(), args.deny.clone());
+
+    if let Some(rate) = args.rate_limit {
+        info!("🚦 Rate limit: {} req/s per client", rate);
+    }
+    if let Some(rate) = args.global_rate_limit {
+        info!("🚦 Global rate limit: {} req/s", rate);
+    }
+    if let Some(max) = args.max_connections_per_ip {
+        info!("🔌 Connection limit: {} per client", max);
+    }
+    if let Some(max) = args.max_connections {
+        info!("🔌 Connection limit: {} total", max);
+    }
+    if let Some(max) = args.max_queue {
+        info!("🔌 Worker queue limit: {} connections", max);
+    }
+    if !ip_filter.is_empty() {
+        info!(
+            "🛡️ IP rules: {} allow, {} deny ({:?} denied clients)",
+            args.allow.len(),
+            args.deny.len(),
+            args.deny_action
+        );
+    }
+    if !bearer_auth.is_empty() {
+        info!("🔑 Bearer token authentication enabled");
+    }
+    if !access_policy.is_empty() {
+        info!("🔐 Per-path access rules loaded");
+    }
+    if !security_headers.is_empty() {
+        info!("🪖 Security headers enabled");
+    }
+    if !args.trusted_proxies.is_empty() {
+        info!(
+            "🔁 Trusting forwarding headers from {} proxy blocks",
+            args.trusted_proxies.len()
+        );
+    }
+    if !rewrite_rules.is_empty() {
+        info!("🔀 {} rewrite rules", rewrite_rules.len());
+    }
+    if !header_rules.is_empty() {
+        info!("🏷️ {} custom header rules", header_rules.len());
+    }
+    if !cache_ttls.is_empty() {
+        info!(
+            "⏳ Cache lifetimes for {} file extensions",
+            cache_ttls.len()
+        );
+    }
+
+    let source = (
+        args.root.first(),
+        &args.object_store,
+        &args.git,
+        &shared.stdin,
+    );
+    let mut builder = match source {
+        (_, _, _, Some(stdin)) => {
+            info!("📥 Serving standard input as /{}", args.name);
+            Server::builder().file_source(stdin.clone())
+        }
+        (Some(root), _, _, _) if args.root.len() == 1 && root.is_file() => {
+            info!("📄 Serving one file: {}", root.display());
+            Server::builder().file_source(SingleFileSource::new(root)?)
+        }
+        (Some(_), _, _, _) => {
+            let roots: Vec<String> = args
+                .root
+                .iter()
+                .map(|root| root.display().to_string())
+                .collect();
+            info!("📁 Serving files from: {}", roots.join(", then "));
+            args.root.iter().fold(Server::builder(), |builder, root| {
+                builder.root(root.clone())
+            })
+        }
+        (None, Some(url), _, _) => {
+            info!("🪣 Serving files from: {}", url);
+            with_object_store(Server::builder(), url)?
+        }
+        (None, None, Some(spec), _) => {
+            info!("🌿 Serving files from git: {}", spec);
+            with_git(Server::builder(), spec)?
+        }
+        (None, None, None, None) => {
+            let site = embedded_site()?;
+            info!("📦 Serving {} embedded files", site.len());
+            Server::builder().file_source(site)
+        }
+    };
+    if !args.exclude.is_empty() {
+        info!("🙈 Excluding {}", args.exclude.join(", "));
+    }
+    if !args.no_ignore_file {
+        builder = builder.ignore_file(IGNORE_FILE);
+    }
+    if args.sendfile {
+        info!("📨 Honouring X-Shover-Send from backends");
+        builder = builder.sendfile();
+    }
+    builder = builder
+        .exclude(&args.exclude)
+        .bind(format!("0.0.0.0:{}", args.port))
+        .port_retries(args.port_retry)
+        .connection_limits(args.max_connections_per_ip, args.max_connections)
+        .ip_filter(ip_filter, args.deny_action)
+        .trusted_proxies(TrustedProxies::new(args.trusted_proxies.clone()))
+        .bearer_auth(bearer_auth)
+        .access_policy(access_policy)
+        .require_signed(args.require_signed)
+        .security_headers(security_headers)
+        .cache_ttls(cache_ttls)
+        .attachments(Attachments::from_config(&config.downloads));
+    if args.immutable_hashed {
+        info!(
+            "♾️ Hashed file names cached as immutable: {}",
+            args.hashed_name_pattern
+        );
+        builder = builder.hashed_names(HashedNames::new(&args.hashed_name_pattern)?);
+    }
+    let tls = if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+        info!("🔒 TLS enabled with {}", cert.display());
+        Some(TlsConfig::from_pem_files(cert, key)?)
+    } else if let Some(names) = &args.tls_self_signed {
+        let names = if names.is_empty() {
+            SELF_SIGNED_NAMES
+                .iter()
+                .map(|name| name.to_string())
+                .collect()
+        } else {
+            names.clone()
+        };
+        info!(
+            "🔒 TLS enabled with a self-signed certificate for {}",
+            names.join(", ")
+        );
+        Some(TlsConfig::self_signed(
+            &names,
+            args.tls_self_signed_dir.as_deref(),
+        )?)
+    } else {
+        None
+    };
+    if let Some(tls) = tls {
+        let mut tls = tls.policy(TlsPolicy::from_config(&config.tls)?)?;
+        if let Some(ca) = &args.tls_client_ca {
+            info!("🪪 Client certificates verified against {}", ca.display());
+            tls = tls.client_auth(ca, !args.tls_client_optional)?;
+        }
+        builder = builder.tls(tls);
+    }
+    if let Some(path) = &args.geoip_db {
+        info!("🌍 GeoIP database: {}", path.display());
+        builder = with_geoip(builder, args, path)?;
+    }
+    if args.audit_db.is_some() {
+        builder = with_audit(builder, shared)?;
+    }
+    if let Some(limiter) = rate_limiter(args) {
+        builder = builder.rate_limiter(limiter);
+    }
+    if let Some(maintenance) = &shared.maintenance {
+        builder = builder.maintenance(maintenance.clone());
+    }
+    if let Some(bans) = &shared.auto_ban {
+        builder = builder.auto_ban(bans.clone());
+    }
+    if let Some(max) = args.max_queue {
+        builder = builder.max_queue(max);
+    }
+    builder = builder.request_limits(Limits {
+        max_request_line: usize::try_from(args.max_request_line).unwrap_or(usize::MAX),
+        max_headers: args.max_headers,
+        max_head: usize::try_from(args.max_header_size).unwrap_or(usize::MAX),
+        max_body: args.max_body_size,
+    });
+    if args.dump_http {
+        let mut dump =
+            HttpDump::new().body_limit(usize::try_from(args.dump_http_body).unwrap_or(usize::MAX));
+        if args.dump_http_secrets {
+            warn!("🔓 Dumping raw HTTP traffic with secrets; the log now holds credentials");
+            dump = dump.show_secrets();
+        } else {
+            info!(
+                "🔍 Dumping raw HTTP traffic (first {} of bodies)",
+                format_size(args.dump_http_body)
+            );
+        }
+        builder = builder.dump_http(dump);
+    }
+    if let Some(threshold) = args.slow_request {
+        info!("🐢 Warning about requests slower than {:?}", threshold);
+        builder = builder.slow_request(threshold);
+    }
+    if args.idle_timeout.is_some()
+        || args.max_connection_age.is_some()
+        || args.min_read_rate.is_some()
+    {
+        let mut reaper = Reaper::new();
+        if let Some(timeout) = args.idle_timeout {
+            info!("⏱️ Closing connections idle for {:?}", timeout);
+            reaper = reaper.idle_timeout(timeout);
+        }
+        if let Some(age) = args.max_connection_age {
+            info!("⏱️ Closing connections open for {:?}", age);
+            reaper = reaper.max_lifetime(age);
+        }
+        if let Some(rate) = args.min_read_rate {
+            let window = args.min_read_rate_window.unwrap_or(Duration::from_secs(30));
+            info!(
+                "⏱️ Closing connections sending under {}/s for {:?}",
+                format_size(rate),
+                window
+            );
+            reaper = reaper.min_read_rate(rate, window);
+        }
+        builder = builder.reaper(reaper);
+    }
+    if let Some(rate) = args.limit_rate {
+        info!("🐢 Bandwidth limit: {}/s per connection", format_size(rate));
+    }
+    if let Some(rate) = args.global_limit_rate {
+        info!("🐢 Bandwidth limit: {}/s total", format_size(rate));
+    }
+    builder = builder.bandwidth(Bandwidth::new(args.limit_rate, args.global_limit_rate));
+    if let Some(limit) = args.download_quota {
+        info!(
+            "🧮 Download quota: {} per client every {:?}",
+            format_size(limit),
+            args.download_quota_window
+        );
+        builder = builder.download_quota(DownloadQuota::new(limit, args.download_quota_window));
+    }
+    if let Some(secret) = read_secret(&args.sign_secret, &args.sign_secret_file)? {
+        info!("✍️ Signed URLs accepted");
+        builder = builder.url_signer(UrlSigner::new(&secret));
+    }
+    if args.hotlink_protection || !args.hotlink_allow.is_empty() {
+        info!("🖼️ Hotlink protection enabled");
+        let placeholder = match &args.hotlink_placeholder {
+            Some(path) => Some((
+                std::fs::read(path)?,
+                get_mime_type(path).as_str().to_string(),
+            )),
+            None => None,
+        };
+        let guard = HotlinkGuard::new(
+            args.hotlink_allow.clone(),
+            !args.hotlink_block_empty_referer,
+        );
+        builder = builder.hotlink_protection(guard, placeholder);
+    }
+    if let Some(template) = &args.listing_template {
+        info!("🗂️ Directory listings rendered with {}", template.display());
+        builder = builder.auto_index(listing_template(template)?);
+    } else if args.auto_index {
+        info!("🗂️ Directory listings enabled");
+        builder = builder.auto_index(AutoIndex::new());
+    }
+    if args.checksums || args.checksum_sidecars {
+        info!("🔏 SHA-256 checksums enabled");
+        builder = builder.checksums(args.checksum_sidecars);
+    }
+    if args.writable {
+        let root = args.root.first().ok_or_else(|| {
+            ConfigError::Invalid("--writable requires a --root directory".to_string())
+        })?;
+        info!("✏️ Uploads accepted into {}", root.display());
+        let mut uploads = Uploads::new(root, args.max_upload_size);
+        if args.permanent_delete {
+            uploads = uploads.permanent_delete();
+        } else if let Some(dir) = &args.trash_dir {
+            uploads = uploads.trash_dir(dir);
+        }
+        if args.upload_form {
+            uploads = uploads.upload_form();
+        }
+        if args.resumable {
+            info!("⏯️ Resumable uploads at /_tus/");
+            uploads = uploads.resumable();
+        }
+        if !args.write_allow.is_empty() {
+            let allowed: Vec<Glob> = args.write_allow.iter().map(|g| Glob::new(g)).collect();
+            uploads = uploads
+                .authorize(move |req, _op| allowed.iter().any(|glob| glob.matches(req.path())));
+        }
+        builder = builder.writable(uploads);
+    }
+    if args.webdav {
+        info!("💽 WebDAV enabled");
+        builder = builder.webdav();
+    }
+    if args.tree_api {
+        info!("🌳 File tree API at /_api/tree");
+        builder = builder.tree_api(args.tree_api_max_depth);
+    }
+    if let Some(base_url) = &args.sitemap {
+        info!("🗺️ Sitemap at /sitemap.xml for {}", base_url);
+        let mut sitemap = Sitemap::new(base_url.as_str());
+        for pattern in &args.sitemap_exclude {
+            sitemap = sitemap.exclude(Glob::new(pattern));
+        }
+        if let Some(live_reload) = &shared.live_reload {
+            sitemap = sitemap.watch(live_reload.clone());
+        }
+        builder = builder.sitemap(sitemap);
+    }
+    if args.fingerprint {
+        info!("🔖 Fingerprinted assets, manifest at /asset-manifest.json");
+        let mut fingerprints = Fingerprints::new();
+        for pattern in &args.fingerprint_exclude {
+            fingerprints = fingerprints.exclude(Glob::new(pattern));
+        }
+        if let Some(live_reload) = &shared.live_reload {
+            fingerprints = fingerprints.watch(live_reload.clone());
+        }
+        builder = builder.fingerprints(fingerprints);
+    }
+    if args.negotiate {
+        let mut variants = Variants::new();
+        if !args.variant.is_empty() {
+            variants = variants.variants(args.variant.clone());
+        }
+        info!("🤝 Content negotiation for extensionless paths");
+        builder = builder.variants(variants);
+    }
+    if args.search {
+        info!("🔎 Full-text search at /_search");
+        let mut search = Search::new();
+        for pattern in &args.search_exclude {
+            search = search.exclude(Glob::new(pattern));
+        }
+        if let Some(live_reload) = &shared.live_reload {
+            search = search.watch(live_reload.clone());
+        }
+        builder = builder.search(search);
+    }
+    if args.robots {
+        let mut robots = Robots::new();
+        for path in &args.robots_allow {
+            robots = robots.allow(path.as_str());
+        }
+        for path in &args.robots_disallow {
+            robots = robots.disallow(path.as_str());
+        }
+        if let Some(base_url) = &args.sitemap {
+            robots = robots.sitemap(format!("{}/sitemap.xml", base_url.trim_end_matches('/')));
+        }
+        info!(
+            "🤖 Default robots.txt ({} disallowed)",
+            args.robots_disallow.len()
+        );
+        builder = builder.default_robots(robots);
+    }
+    if args.favicon {
+        info!("🖼️ Default favicon.ico");
+        builder = builder.default_favicon();
+    }
+    if args.suggest {
+        info!("🔎 Suggesting similar names on 404s");
+        builder = builder.suggestions();
+    }
+    if args.archive_downloads {
+        info!("🗃️ Directory archive downloads enabled");
+        builder = builder.archive_downloads(ArchiveLimits {
+            max_size: args.archive_max_size,
+            max_entries: args.archive_max_entries,
+        });
+    }
+    if args.compress {
+        info!("🗜️ Gzip compression enabled");
+        let mut compression = Compression::default();
+        if let Some(max_bytes) = args.compress_cache {
+            info!(
+                "🗜️ Caching up to {} of compressed output",
+                format_size(max_bytes)
+            );
+            compression = compression.cache(CompressionCache::memory(max_bytes));
+        } else if let Some(dir) = &args.compress_cache_dir {
+            info!("🗜️ Caching compressed output in {}", dir.display());
+            compression = compression.cache(CompressionCache::disk(dir)?);
+        }
+        builder = builder.middleware(compression);
+    }
+    if let Some(live_reload) = &shared.live_reload {
+        builder = builder.live_reload(live_reload.clone());
+    }
+    for rule in header_rules {
+        builder = builder.header_rule(rule);
+    }
+    for rule in rewrite_rules {
+        builder = builder.rewrite_rule(rule);
+    }
+    if let Some(path) = &args.redirects {
+        let redirects = RedirectMap::load(path)?;
+        info!("🔀 {} redirects from {}", redirects.len(), path.display());
+        builder = builder.redirects(redirects);
+    }
+    for route in &args.proxy_routes {
+        info!("↪️ Proxying {}", route);
+        builder = builder.proxy_route(route.clone());
+    }
+    if let Some(upstream) = &args.proxy_fallback {
+        info!("↪️ Unmatched requests forwarded to {}", upstream);
+        builder = builder.proxy_fallback(upstream.clone());
+    }
+    if let Some(dir) = &args.cgi_dir {
+        let cgi = Cgi::new(dir)
+            .prefix(args.cgi_prefix.as_str())
+            .timeout(args.cgi_timeout);
+        info!(
+            "⚙️ Running CGI scripts from {} under {}",
+            dir.display(),
+            cgi.prefix_path()
+        );
+        builder = builder.cgi(cgi);
+    }
+    if let (Some(address), Some(root)) = (&args.fastcgi, args.root.first()) {
+        // SCRIPT_FILENAME must be absolute for the responder
+        let root = std::fs::canonicalize(root)
+            .map_err(|e| ConfigError::Invalid(format!("FastCGI root {}: {}", root.display(), e)))?;
+        let mut fastcgi = FastCgi::new(address.clone(), root).timeout(args.fastcgi_timeout);
+        if let Some(document_root) = &args.fastcgi_root {
+            fastcgi = fastcgi.document_root(document_root);
+        }
+        info!("🐘 Forwarding .php requests to FastCGI at {}", address);
+        builder = builder.fastcgi(fastcgi);
+    }
+    Ok(builder)
+}
@@ -0,0 +1,191 @@
+/*
+* Maintenance module
+*
+* A switch that makes every request answer 503 with `Retry-After` during a
+* deploy window. It is flipped at runtime, either through a cloned
+* `Maintenance` handle (the binary does this on SIGUSR1) or by creating a
+* flag file such as `<root>/.maintenance`, which is checked on each request
+* so a deploy script needs nothing but `touch` and `rm`.
+*
+* The check runs as the first middleware layer, so uploads and proxied
+* requests are refused as well, and rate limits are not charged.
+*/
+
+use crate::message::{Request, Response, StatusCode, DEFAULT_SERVICE_UNAVAILABLE_BODY};
+use crate::middleware::Middleware;
+use crate::units::retry_after_secs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `Retry-After` sent unless configured otherwise.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Maintenance mode: a shared switch plus an optional flag file.
+///
+/// Clones share the switch, so keep one to toggle the mode of a server
+/// built with [`ServerBuilder::maintenance`](crate::server::ServerBuilder::maintenance).
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::maintenance::Maintenance;
+/// use file_shover::message::{Request, Response, StatusCode};
+/// use file_shover::middleware::{Chain, Handler};
+/// use std::time::Duration;
+///
+/// let maintenance = Maintenance::new().retry_after(Duration::from_secs(60));
+/// let chain = Chain::new(|_req: &Request| Response::new()).with(maintenance.clone());
+/// let req = Request::builder().path("/").build();
+/// assert_eq!(chain.handle(&req).status, StatusCode::OK);
+///
+/// maintenance.enable();
+/// let response = chain.handle(&req);
+/// assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+/// assert_eq!(response.headers.get("Retry-After"), Some(&"60".to_string()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Maintenance {
+    switch: Arc<AtomicBool>,
+    flag_file: Option<PathBuf>,
+    retry_after: Duration,
+    page: Option<Arc<Vec<u8>>>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Maintenance {
+    /// Creates a switch that starts off, with a 5 minute `Retry-After`.
+    pub fn new() -> Self {
+        Self {
+            switch: Arc::new(AtomicBool::new(false)),
+            flag_file: None,
+            retry_after: DEFAULT_RETRY_AFTER,
+            page: None,
+        }
+    }
+
+    /// Also answers 503 whenever `path` exists.
+    pub fn flag_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.flag_file = Some(path.into());
+        self
+    }
+
+    /// How long clients are told to wait before retrying; sent in whole
+    /// seconds, rounded up.
+    pub fn retry_after(mut self, wait: Duration) -> Self {
+        self.retry_after = wait;
+        self
+    }
+
+    /// HTML page sent instead of the default 503 body.
+    pub fn page(mut self, html: impl Into<Vec<u8>>) -> Self {
+        self.page = Some(Arc::new(html.into()));
+        self
+    }
+
+    /// Turns maintenance mode on.
+    pub fn enable(&self) {
+        self.switch.store(true, Ordering::SeqCst);
+    }
+
+    /// Turns maintenance mode off; an existing flag file still applies.
+    pub fn disable(&self) {
+        self.switch.store(false, Ordering::SeqCst);
+    }
+
+    /// Flips the switch and returns its new state.
+    pub fn toggle(&self) -> bool {
+        !self.switch.fetch_xor(true, Ordering::SeqCst)
+    }
+
+    /// Returns true if requests are currently refused.
+    pub fn is_active(&self) -> bool {
+        self.switch.load(Ordering::SeqCst)
+            || self.flag_file.as_ref().is_some_and(|path| path.exists())
+    }
+
+    fn response(&self) -> Response {
+        let retry_after = retry_after_secs(self.retry_after);
+        let response = match &self.page {
+            Some(page) => Response::new()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .content_type("text/html")
+                .content_length(page.len())
+                .body(page.to_vec()),
+            None => Response::error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                DEFAULT_SERVICE_UNAVAILABLE_BODY,
+            ),
+        };
+        response
+            .header("Retry-After", retry_after.to_string())
+            .header("Cache-Control", "no-store")
+    }
+}
+
+impl Middleware for Maintenance {
+    fn before(&self, _req: &Request) -> Option<Response> {
+        self.is_active().then(|| self.response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Body;
+    use std::fs;
+
+    #[test]
+    fn test_switch_and_flag_file() {
+        let flag =
+            std::env::temp_dir().join(format!("file-shover-maintenance-{}", std::process::id()));
+        let _ = fs::remove_file(&flag);
+        let maintenance = Maintenance::new().flag_file(&flag);
+        let handle = maintenance.clone();
+        let req = Request::builder().path("/index.html").build();
+        assert!(maintenance.before(&req).is_none());
+
+        assert!(handle.toggle());
+        assert!(maintenance.is_active());
+        assert!(!handle.toggle());
+        assert!(!maintenance.is_active());
+
+        fs::write(&flag, "").unwrap();
+        let response = maintenance.before(&req).unwrap();
+        assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers.get("Retry-After"),
+            Some(&"300".to_string())
+        );
+        handle.disable();
+        assert!(maintenance.is_active());
+        fs::remove_file(&flag).unwrap();
+        assert!(!maintenance.is_active());
+    }
+
+    #[test]
+    fn test_custom_page() {
+        let maintenance = Maintenance::new()
+            .retry_after(Duration::from_millis(1500))
+            .page("<h1>Back soon</h1>");
+        maintenance.enable();
+        let response = maintenance
+            .before(&Request::builder().path("/").build())
+            .unwrap();
+        assert_eq!(response.headers.get("Retry-After"), Some(&"2".to_string()));
+        assert_eq!(
+            response.headers.get("Content-Length"),
+            Some(&"18".to_string())
+        );
+        match response.body {
+            Body::Bytes(bytes) => assert_eq!(bytes, b"<h1>Back soon</h1>"),
+            _ => panic!("expected an in-memory body"),
+        }
+    }
+}
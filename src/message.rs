@@ -1,9 +1,30 @@
+use crate::cookie::{parse_cookie_header, Cookie};
+use crate::parser::{self, Limits};
+use crate::pool;
+use crate::target::{RequestTarget, TargetError};
+use crate::tls::ClientCert;
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{BufRead, BufReader, Cursor, ErrorKind, IoSlice, Read, Write};
+use std::net::IpAddr;
+use tracing::warn;
 
 pub const DEFAULT_BAD_REQUEST_BODY: &str = "<h1>400 Bad Request</h1>";
+pub const DEFAULT_UNAUTHORIZED_BODY: &str = "<h1>401 Unauthorized</h1>";
+pub const DEFAULT_FORBIDDEN_BODY: &str = "<h1>403 Forbidden</h1>";
 pub const DEFAULT_NOT_FOUND_BODY: &str = "<h1>404 Not Found</h1>";
+pub const DEFAULT_METHOD_NOT_ALLOWED_BODY: &str = "<h1>405 Method Not Allowed</h1>";
+pub const DEFAULT_CONFLICT_BODY: &str = "<h1>409 Conflict</h1>";
+pub const DEFAULT_LENGTH_REQUIRED_BODY: &str = "<h1>411 Length Required</h1>";
+pub const DEFAULT_PRECONDITION_FAILED_BODY: &str = "<h1>412 Precondition Failed</h1>";
+pub const DEFAULT_PAYLOAD_TOO_LARGE_BODY: &str = "<h1>413 Content Too Large</h1>";
+pub const DEFAULT_URI_TOO_LONG_BODY: &str = "<h1>414 URI Too Long</h1>";
+pub const DEFAULT_RANGE_NOT_SATISFIABLE_BODY: &str = "<h1>416 Range Not Satisfiable</h1>";
+pub const DEFAULT_TOO_MANY_REQUESTS_BODY: &str = "<h1>429 Too Many Requests</h1>";
+pub const DEFAULT_HEADERS_TOO_LARGE_BODY: &str = "<h1>431 Request Header Fields Too Large</h1>";
 pub const DEFAULT_INTERNAL_ERROR_BODY: &str = "<h1>500 Internal Server Error</h1>";
+pub const DEFAULT_BAD_GATEWAY_BODY: &str = "<h1>502 Bad Gateway</h1>";
+pub const DEFAULT_SERVICE_UNAVAILABLE_BODY: &str = "<h1>503 Service Unavailable</h1>";
 
 const BUFFER_SIZE: usize = 64 * 1024;
 /// Errors that can occur when parsing HTTP requests.
@@ -31,6 +52,14 @@ pub enum RequestError {
     Io(std::io::Error),
     InvalidFormat,
     MissingHeader(String),
+    /// The request target is malformed or unsafe.
+    InvalidTarget(TargetError),
+    /// The request line exceeds the parser's line limit.
+    UriTooLong,
+    /// The number of headers or the size of the head exceeds its limit.
+    HeadersTooLarge,
+    /// The announced body exceeds the body size limit.
+    PayloadTooLarge,
 }
 
 impl std::fmt::Display for RequestError {
@@ -39,6 +68,10 @@ impl std::fmt::Display for RequestError {
             RequestError::Io(err) => write!(f, "IO error: {}", err),
             RequestError::InvalidFormat => write!(f, "Invalid request format"),
             RequestError::MissingHeader(header) => write!(f, "Missing required header: {}", header),
+            RequestError::InvalidTarget(err) => write!(f, "Invalid request target: {}", err),
+            RequestError::UriTooLong => write!(f, "Request line too long"),
+            RequestError::HeadersTooLarge => write!(f, "Request headers too large"),
+            RequestError::PayloadTooLarge => write!(f, "Request body too large"),
         }
     }
 }
@@ -47,6 +80,7 @@ impl std::error::Error for RequestError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             RequestError::Io(err) => Some(err),
+            RequestError::InvalidTarget(err) => Some(err),
             _ => None,
         }
     }
@@ -61,8 +95,10 @@ impl From<std::io::Error> for RequestError {
 /// HTTP methods supported by the server.
 ///
 /// This enum covers the basic HTTP methods that a static file server typically needs to handle.
-/// Currently supports GET for retrieving resources, HEAD for metadata only, and OPTIONS for
-/// CORS preflight requests.
+/// The file tree answers GET for retrieving resources, HEAD for metadata only, and OPTIONS for
+/// CORS preflight requests. POST, PUT, DELETE and PATCH are parsed so that they can be
+/// forwarded to a proxy upstream or used for uploads, and PROPFIND, MKCOL, COPY and MOVE
+/// for WebDAV.
 ///
 /// # Examples
 ///
@@ -82,6 +118,14 @@ pub enum HttpMethod {
     GET,
     HEAD,
     OPTIONS,
+    POST,
+    PUT,
+    DELETE,
+    PATCH,
+    PROPFIND,
+    MKCOL,
+    COPY,
+    MOVE,
 }
 
 impl std::str::FromStr for HttpMethod {
@@ -98,9 +142,10 @@ impl std::str::FromStr for HttpMethod {
     /// assert_eq!(HttpMethod::from_str("GET").unwrap(), HttpMethod::GET);
     /// assert_eq!(HttpMethod::from_str("HEAD").unwrap(), HttpMethod::HEAD);
     /// assert_eq!(HttpMethod::from_str("OPTIONS").unwrap(), HttpMethod::OPTIONS);
+    /// assert_eq!(HttpMethod::from_str("POST").unwrap(), HttpMethod::POST);
     ///
     /// // Invalid methods return an error
-    /// assert!(HttpMethod::from_str("POST").is_err());
+    /// assert!(HttpMethod::from_str("BREW").is_err());
     /// assert!(HttpMethod::from_str("get").is_err()); // case sensitive
     /// ```
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -108,6 +153,14 @@ impl std::str::FromStr for HttpMethod {
             "GET" => Ok(HttpMethod::GET),
             "HEAD" => Ok(HttpMethod::HEAD),
             "OPTIONS" => Ok(HttpMethod::OPTIONS),
+            "POST" => Ok(HttpMethod::POST),
+            "PUT" => Ok(HttpMethod::PUT),
+            "DELETE" => Ok(HttpMethod::DELETE),
+            "PATCH" => Ok(HttpMethod::PATCH),
+            "PROPFIND" => Ok(HttpMethod::PROPFIND),
+            "MKCOL" => Ok(HttpMethod::MKCOL),
+            "COPY" => Ok(HttpMethod::COPY),
+            "MOVE" => Ok(HttpMethod::MOVE),
             _ => Err(RequestError::InvalidFormat),
         }
     }
@@ -119,6 +172,14 @@ impl std::fmt::Display for HttpMethod {
             HttpMethod::GET => "GET",
             HttpMethod::HEAD => "HEAD",
             HttpMethod::OPTIONS => "OPTIONS",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::PROPFIND => "PROPFIND",
+            HttpMethod::MKCOL => "MKCOL",
+            HttpMethod::COPY => "COPY",
+            HttpMethod::MOVE => "MOVE",
         };
         write!(f, "{}", method_str)
     }
@@ -137,74 +198,332 @@ impl std::fmt::Display for HttpMethod {
 /// let request = Request::from_bytes(cursor).unwrap();
 ///
 /// assert_eq!(request.method, HttpMethod::GET);
-/// assert_eq!(request.path, "/index.html");
+/// assert_eq!(request.path(), "/index.html");
 /// assert_eq!(request.http_version, "HTTP/1.1");
 /// assert_eq!(request.headers.get("Host"), Some(&"example.com".to_string()));
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Request {
     pub method: HttpMethod,
-    pub path: String,
+    /// Normalized path and raw query string.
+    pub target: RequestTarget,
     pub http_version: String,
     pub headers: HashMap<String, String>,
+    /// Address of the client, set by the server once known (behind a trusted
+    /// proxy this is the forwarded client, not the proxy).
+    pub client_ip: Option<IpAddr>,
+    /// Verified certificate the client presented over mutual TLS, set by
+    /// the server.
+    pub client_cert: Option<ClientCert>,
 }
 
-/// HTTP status codes.
+/// Builds a [`Request`] (or its raw bytes) without parsing, mostly for tests.
+///
+/// Defaults to `GET / HTTP/1.1` with no headers.
 ///
 /// # Examples
 ///
 /// ```
-/// use file_shover::message::HttpStatus;
+/// use file_shover::message::{HttpMethod, Request};
 ///
-/// let status = HttpStatus::Ok;
-/// assert_eq!(status.as_str(), "200 OK");
-/// assert_eq!(status.to_string(), "200 OK");
-/// assert_eq!(status as u16, 200);
+/// let request = Request::builder()
+///     .method(HttpMethod::POST)
+///     .path("/api/items?page=2")
+///     .header("Content-Type", "application/json")
+///     .build();
+/// assert_eq!(request.path(), "/api/items");
+/// assert_eq!(request.query_param("page"), Some("2"));
+///
+/// let raw = Request::builder().path("/").body("{}").into_bytes();
+/// assert_eq!(raw, b"GET / HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}");
 /// ```
-#[derive(Debug, Clone, PartialEq)]
-pub enum HttpStatus {
-    Ok = 200,
-    NotModified = 304,
-    BadRequest = 400,
-    Forbidden = 403,
-    NotFound = 404,
-    MethodNotAllowed = 405,
-    InternalServerError = 500,
+#[derive(Debug, Clone)]
+pub struct RequestBuilder {
+    method: HttpMethod,
+    target: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    client_ip: Option<IpAddr>,
+    client_cert: Option<ClientCert>,
 }
 
-impl HttpStatus {
-    /// Returns the status code and reason phrase as a string.
+impl Default for RequestBuilder {
+    fn default() -> Self {
+        Self {
+            method: HttpMethod::GET,
+            target: "/".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            client_ip: None,
+            client_cert: None,
+        }
+    }
+}
+
+impl RequestBuilder {
+    pub fn method(mut self, method: HttpMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Request target, optionally with a query string.
+    pub fn path(mut self, target: impl Into<String>) -> Self {
+        self.target = target.into();
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets the body and a matching `Content-Length`; only used by
+    /// [`into_bytes`](RequestBuilder::into_bytes), since a parsed request
+    /// leaves its body on the connection.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn client_ip(mut self, ip: IpAddr) -> Self {
+        self.client_ip = Some(ip);
+        self
+    }
+
+    pub fn client_cert(mut self, cert: ClientCert) -> Self {
+        self.client_cert = Some(cert);
+        self
+    }
+
+    /// # Panics
     ///
-    /// # Examples
+    /// Panics if the target cannot be parsed.
+    pub fn build(self) -> Request {
+        let target = RequestTarget::parse(&self.target)
+            .unwrap_or_else(|e| panic!("invalid request target {:?}: {}", self.target, e));
+        Request {
+            method: self.method,
+            target,
+            http_version: "HTTP/1.1".to_string(),
+            headers: self.headers.into_iter().collect(),
+            client_ip: self.client_ip,
+            client_cert: self.client_cert,
+        }
+    }
+
+    /// Serializes the request as it would arrive on the wire.
+    pub fn into_bytes(self) -> Vec<u8> {
+        let mut raw = format!("{} {} HTTP/1.1\r\n", self.method, self.target);
+        for (name, value) in &self.headers {
+            raw.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !self.body.is_empty() {
+            raw.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        raw.push_str("\r\n");
+        let mut raw = raw.into_bytes();
+        raw.extend_from_slice(&self.body);
+        raw
+    }
+}
+
+macro_rules! status_codes {
+    ($(($code:literal, $name:ident, $reason:literal),)+) => {
+        impl StatusCode {
+            $(
+                #[doc = concat!("`", $code, " ", $reason, "`")]
+                pub const $name: StatusCode = StatusCode($code);
+            )+
+
+            /// The registered reason phrase, if the code is registered.
+            ///
+            /// # Examples
+            ///
+            /// ```
+            /// use file_shover::message::StatusCode;
+            ///
+            /// assert_eq!(StatusCode::PAYLOAD_TOO_LARGE.reason(), Some("Content Too Large"));
+            /// assert_eq!(StatusCode::from_u16(299).unwrap().reason(), None);
+            /// ```
+            pub fn reason(self) -> Option<&'static str> {
+                match self.0 {
+                    $($code => Some($reason),)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+/// HTTP status code.
+///
+/// Any three-digit code can be represented; the registered ones have named
+/// constants and a canonical reason phrase.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::message::StatusCode;
+///
+/// let status = StatusCode::OK;
+/// assert_eq!(status.to_string(), "200 OK");
+/// assert_eq!(status.as_u16(), 200);
+///
+/// let custom = StatusCode::from_u16(599).unwrap();
+/// assert_eq!(custom.to_string(), "599");
+/// assert!(custom.is_server_error());
+/// assert!(StatusCode::from_u16(42).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StatusCode(u16);
+
+status_codes! {
+    (100, CONTINUE, "Continue"),
+    (101, SWITCHING_PROTOCOLS, "Switching Protocols"),
+    (102, PROCESSING, "Processing"),
+    (103, EARLY_HINTS, "Early Hints"),
+    (200, OK, "OK"),
+    (201, CREATED, "Created"),
+    (202, ACCEPTED, "Accepted"),
+    (203, NON_AUTHORITATIVE_INFORMATION, "Non-Authoritative Information"),
+    (204, NO_CONTENT, "No Content"),
+    (205, RESET_CONTENT, "Reset Content"),
+    (206, PARTIAL_CONTENT, "Partial Content"),
+    (207, MULTI_STATUS, "Multi-Status"),
+    (208, ALREADY_REPORTED, "Already Reported"),
+    (226, IM_USED, "IM Used"),
+    (300, MULTIPLE_CHOICES, "Multiple Choices"),
+    (301, MOVED_PERMANENTLY, "Moved Permanently"),
+    (302, FOUND, "Found"),
+    (303, SEE_OTHER, "See Other"),
+    (304, NOT_MODIFIED, "Not Modified"),
+    (305, USE_PROXY, "Use Proxy"),
+    (307, TEMPORARY_REDIRECT, "Temporary Redirect"),
+    (308, PERMANENT_REDIRECT, "Permanent Redirect"),
+    (400, BAD_REQUEST, "Bad Request"),
+    (401, UNAUTHORIZED, "Unauthorized"),
+    (402, PAYMENT_REQUIRED, "Payment Required"),
+    (403, FORBIDDEN, "Forbidden"),
+    (404, NOT_FOUND, "Not Found"),
+    (405, METHOD_NOT_ALLOWED, "Method Not Allowed"),
+    (406, NOT_ACCEPTABLE, "Not Acceptable"),
+    (407, PROXY_AUTHENTICATION_REQUIRED, "Proxy Authentication Required"),
+    (408, REQUEST_TIMEOUT, "Request Timeout"),
+    (409, CONFLICT, "Conflict"),
+    (410, GONE, "Gone"),
+    (411, LENGTH_REQUIRED, "Length Required"),
+    (412, PRECONDITION_FAILED, "Precondition Failed"),
+    (413, PAYLOAD_TOO_LARGE, "Content Too Large"),
+    (414, URI_TOO_LONG, "URI Too Long"),
+    (415, UNSUPPORTED_MEDIA_TYPE, "Unsupported Media Type"),
+    (416, RANGE_NOT_SATISFIABLE, "Range Not Satisfiable"),
+    (417, EXPECTATION_FAILED, "Expectation Failed"),
+    (421, MISDIRECTED_REQUEST, "Misdirected Request"),
+    (422, UNPROCESSABLE_CONTENT, "Unprocessable Content"),
+    (423, LOCKED, "Locked"),
+    (424, FAILED_DEPENDENCY, "Failed Dependency"),
+    (425, TOO_EARLY, "Too Early"),
+    (426, UPGRADE_REQUIRED, "Upgrade Required"),
+    (428, PRECONDITION_REQUIRED, "Precondition Required"),
+    (429, TOO_MANY_REQUESTS, "Too Many Requests"),
+    (431, REQUEST_HEADER_FIELDS_TOO_LARGE, "Request Header Fields Too Large"),
+    (451, UNAVAILABLE_FOR_LEGAL_REASONS, "Unavailable For Legal Reasons"),
+    (500, INTERNAL_SERVER_ERROR, "Internal Server Error"),
+    (501, NOT_IMPLEMENTED, "Not Implemented"),
+    (502, BAD_GATEWAY, "Bad Gateway"),
+    (503, SERVICE_UNAVAILABLE, "Service Unavailable"),
+    (504, GATEWAY_TIMEOUT, "Gateway Timeout"),
+    (505, HTTP_VERSION_NOT_SUPPORTED, "HTTP Version Not Supported"),
+    (506, VARIANT_ALSO_NEGOTIATES, "Variant Also Negotiates"),
+    (507, INSUFFICIENT_STORAGE, "Insufficient Storage"),
+    (508, LOOP_DETECTED, "Loop Detected"),
+    (510, NOT_EXTENDED, "Not Extended"),
+    (511, NETWORK_AUTHENTICATION_REQUIRED, "Network Authentication Required"),
+}
+
+impl StatusCode {
+    /// Creates a status code from its number.
     ///
-    /// ```
-    /// use file_shover::message::HttpStatus;
+    /// # Errors
     ///
-    /// assert_eq!(HttpStatus::Ok.as_str(), "200 OK");
-    /// assert_eq!(HttpStatus::NotFound.as_str(), "404 Not Found");
-    /// assert_eq!(HttpStatus::InternalServerError.as_str(), "500 Internal Server Error");
-    /// ```
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            HttpStatus::Ok => "200 OK",
-            HttpStatus::NotModified => "304 Not Modified",
-            HttpStatus::BadRequest => "400 Bad Request",
-            HttpStatus::Forbidden => "403 Forbidden",
-            HttpStatus::NotFound => "404 Not Found",
-            HttpStatus::MethodNotAllowed => "405 Method Not Allowed",
-            HttpStatus::InternalServerError => "500 Internal Server Error",
+    /// Returns `InvalidStatusCode` unless `code` has three digits (100-999).
+    pub fn from_u16(code: u16) -> Result<Self, InvalidStatusCode> {
+        if (100..1000).contains(&code) {
+            Ok(StatusCode(code))
+        } else {
+            Err(InvalidStatusCode(code))
         }
     }
+
+    pub fn as_u16(self) -> u16 {
+        self.0
+    }
+
+    /// 1xx
+    pub fn is_informational(self) -> bool {
+        (100..200).contains(&self.0)
+    }
+
+    /// 2xx
+    pub fn is_success(self) -> bool {
+        (200..300).contains(&self.0)
+    }
+
+    /// 3xx
+    pub fn is_redirection(self) -> bool {
+        (300..400).contains(&self.0)
+    }
+
+    /// 4xx
+    pub fn is_client_error(self) -> bool {
+        (400..500).contains(&self.0)
+    }
+
+    /// 5xx
+    pub fn is_server_error(self) -> bool {
+        (500..600).contains(&self.0)
+    }
+}
+
+/// Formats as it appears in a status line: the code, then the reason phrase
+/// if there is one.
+impl std::fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.reason() {
+            Some(reason) => write!(f, "{} {}", self.0, reason),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl From<StatusCode> for u16 {
+    fn from(status: StatusCode) -> Self {
+        status.0
+    }
 }
 
-impl std::fmt::Display for HttpStatus {
+impl TryFrom<u16> for StatusCode {
+    type Error = InvalidStatusCode;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        Self::from_u16(code)
+    }
+}
+
+/// A number outside the three-digit status code range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidStatusCode(pub u16);
+
+impl std::fmt::Display for InvalidStatusCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.as_str())
+        write!(f, "invalid status code: {}", self.0)
     }
 }
 
+impl std::error::Error for InvalidStatusCode {}
+
 /// Wrapper type for content length values that accepts both usize and u64.
-/// 
+///
 /// This allows the `content_length` method to work with both file metadata (u64)
 /// and slice/string lengths (usize) without requiring explicit casting.
 #[derive(Debug, Clone, Copy)]
@@ -223,19 +542,220 @@ impl From<usize> for ContentLength {
     }
 }
 
+/// The body of a [`Response`].
+///
+/// Readers are streamed through a fixed-size buffer, so large files are never
+/// held in memory. [`Response::write`] fills in the framing headers: a
+/// `Content-Length` for `Bytes` (unless already set) and
+/// `Transfer-Encoding: chunked` for `Chunked`.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::message::Body;
+/// use std::io::{Cursor, Read};
+///
+/// let body = Body::from("Hello");
+/// assert_eq!(body.known_length(), Some(5));
+///
+/// let mut streamed = String::new();
+/// Body::reader(Cursor::new("Hello"))
+///     .into_reader()
+///     .read_to_string(&mut streamed)
+///     .unwrap();
+/// assert_eq!(streamed, "Hello");
+/// ```
+#[derive(Default)]
+pub enum Body {
+    #[default]
+    Empty,
+    Bytes(Vec<u8>),
+    /// A stream whose length is announced separately (usually `Content-Length`)
+    /// or delimited by closing the connection.
+    Reader(Box<dyn Read>),
+    /// A stream of unknown length, sent with chunked transfer encoding.
+    Chunked(Box<dyn Read>),
+}
+
+impl Body {
+    /// Streams `reader` as the body.
+    pub fn reader(reader: impl Read + 'static) -> Self {
+        Body::Reader(Box::new(reader))
+    }
+
+    /// Streams `reader` with chunked transfer encoding.
+    pub fn chunked(reader: impl Read + 'static) -> Self {
+        Body::Chunked(Box::new(reader))
+    }
+
+    /// Returns true if there is no body at all.
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Body::Empty)
+    }
+
+    /// The length in bytes, if known without reading the body.
+    pub fn known_length(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Reader(_) | Body::Chunked(_) => None,
+        }
+    }
+
+    /// Turns the body into a plain reader over its content.
+    pub fn into_reader(self) -> Box<dyn Read> {
+        match self {
+            Body::Empty => Box::new(std::io::empty()),
+            Body::Bytes(bytes) => Box::new(Cursor::new(bytes)),
+            Body::Reader(reader) | Body::Chunked(reader) => reader,
+        }
+    }
+
+    /// Writes `head` and then the content, chunk-framed if needed, counting
+    /// the content bytes in `written` as they go out.
+    ///
+    /// A streamed body with an announced `length` is cut off there, and
+    /// fails with `UnexpectedEof` if it ends sooner, e.g. when the file was
+    /// truncated while being sent. The caller then closes the connection, so
+    /// the client sees an incomplete response instead of a short one it
+    /// would take as complete. Chunked bodies that fail are likewise left
+    /// without their final chunk.
+    ///
+    /// The head goes out together with the body, or with the first read of a
+    /// streamed one, so a small response takes one write call (and usually
+    /// one TCP segment). Chunked bodies send the head on its own: they may
+    /// not produce anything for a while.
+    fn write_to<W: Write>(
+        &mut self,
+        head: &[u8],
+        length: Option<u64>,
+        stream: &mut W,
+        written: &mut u64,
+    ) -> std::io::Result<()> {
+        let (reader, chunked): (&mut dyn Read, bool) = match self {
+            Body::Empty => return stream.write_all(head),
+            Body::Bytes(bytes) => {
+                write_all_vectored(stream, &mut [IoSlice::new(head), IoSlice::new(bytes)])?;
+                *written = bytes.len() as u64;
+                return Ok(());
+            }
+            Body::Reader(reader) => (reader, false),
+            Body::Chunked(reader) => (reader, true),
+        };
+
+        let length = length.filter(|_| !chunked);
+        let mut bounded = reader.take(length.unwrap_or(u64::MAX));
+        let reader: &mut dyn Read = &mut bounded;
+        let mut buffer = pool::take(BUFFER_SIZE);
+        if chunked {
+            stream.write_all(head)?;
+        } else {
+            let bytes_read = read_retrying(reader, &mut buffer)?;
+            write_all_vectored(
+                stream,
+                &mut [IoSlice::new(head), IoSlice::new(&buffer[..bytes_read])],
+            )?;
+            *written += bytes_read as u64;
+        }
+        loop {
+            let bytes_read = reader.read(&mut buffer)?;
+            if bytes_read == 0 {
+                break;
+            }
+            if chunked {
+                write!(stream, "{:X}\r\n", bytes_read)?;
+                stream.write_all(&buffer[..bytes_read])?;
+                stream.write_all(b"\r\n")?;
+                // Streamed bodies (e.g. event streams) must not sit in a
+                // TLS buffer until the next chunk arrives
+                stream.flush()?;
+            } else {
+                stream.write_all(&buffer[..bytes_read])?;
+            }
+            *written += bytes_read as u64;
+        }
+        if chunked {
+            stream.write_all(b"0\r\n\r\n")?;
+        }
+        if let Some(length) = length.filter(|length| *written < *length) {
+            return Err(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("body ended after {} of {} bytes", written, length),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Reads once, retrying when interrupted.
+fn read_retrying(reader: &mut dyn Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+        match reader.read(buffer) {
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// `write_all` over several buffers, using vectored writes where the
+/// stream supports them.
+fn write_all_vectored<W: Write>(
+    stream: &mut W,
+    mut slices: &mut [IoSlice<'_>],
+) -> std::io::Result<()> {
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        match stream.write_vectored(slices) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(written) => IoSlice::advance_slices(&mut slices, written),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+impl std::fmt::Debug for Body {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Body::Empty => write!(f, "Empty"),
+            Body::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Body::Reader(_) => write!(f, "Reader"),
+            Body::Chunked(_) => write!(f, "Chunked"),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(text: &'static str) -> Self {
+        Body::Bytes(text.as_bytes().to_vec())
+    }
+}
+
 /// HTTP response.
 ///
 /// # Examples
 ///
 /// ```
-/// use file_shover::message::{Response, HttpStatus};
-/// use std::io::Cursor;
+/// use file_shover::message::{Response, StatusCode};
 ///
 /// let mut response = Response::new()
-///     .status(HttpStatus::Ok)
+///     .status(StatusCode::OK)
 ///     .content_type("text/html")
 ///     .server("file-shover/1.0")
-///     .body(Box::new(Cursor::new("<html><body>Hello World</body></html>".as_bytes())));
+///     .body("<html><body>Hello World</body></html>");
 ///
 /// // Write to a buffer
 /// let mut buffer = Vec::new();
@@ -247,17 +767,20 @@ impl From<usize> for ContentLength {
 /// assert!(response_str.contains("Hello World"));
 /// ```
 pub struct Response {
-    pub status: HttpStatus,
+    pub status: StatusCode,
     pub headers: HashMap<String, String>,
-    pub body: Option<Box<dyn Read>>,
+    /// Sent as one `Set-Cookie` header each.
+    pub cookies: Vec<Cookie>,
+    pub body: Body,
 }
 
 impl Default for Response {
     fn default() -> Self {
         let df = Self {
-            status: HttpStatus::Ok,
+            status: StatusCode::OK,
             headers: HashMap::new(),
-            body: None,
+            cookies: Vec::new(),
+            body: Body::Empty,
         };
         df.server("file-shover/1.0").header("Connection", "close")
     }
@@ -269,27 +792,46 @@ impl Response {
     /// # Examples
     ///
     /// ```
-    /// use file_shover::message::{Response, HttpStatus};
+    /// use file_shover::message::{Response, StatusCode};
     ///
     /// let response = Response::new();
-    /// assert_eq!(response.status, HttpStatus::Ok);
-    /// assert!(response.body.is_none());
+    /// assert_eq!(response.status, StatusCode::OK);
+    /// assert!(response.body.is_empty());
     /// ```
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Creates a small HTML error page with the given status.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::message::{StatusCode, Response, DEFAULT_NOT_FOUND_BODY};
+    ///
+    /// let response = Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY);
+    /// assert_eq!(response.status, StatusCode::NOT_FOUND);
+    /// assert_eq!(response.headers.get("Content-Type"), Some(&"text/html".to_string()));
+    /// ```
+    pub fn error(status: StatusCode, body: &'static str) -> Self {
+        Self::new()
+            .status(status)
+            .content_type("text/html")
+            .content_length(body.len())
+            .body(body)
+    }
+
     /// Sets the HTTP status code for this response.
     ///
     /// # Examples
     ///
     /// ```
-    /// use file_shover::message::{Response, HttpStatus};
+    /// use file_shover::message::{Response, StatusCode};
     ///
-    /// let mut response = Response::new().status(HttpStatus::NotFound);
-    /// assert_eq!(response.status, HttpStatus::NotFound);
+    /// let mut response = Response::new().status(StatusCode::NOT_FOUND);
+    /// assert_eq!(response.status, StatusCode::NOT_FOUND);
     /// ```
-    pub fn status(mut self, status: HttpStatus) -> Self {
+    pub fn status(mut self, status: StatusCode) -> Self {
         self.status = status;
         self
     }
@@ -308,8 +850,107 @@ impl Response {
     /// assert_eq!(response.headers.get("Content-Type"), Some(&"application/json".to_string()));
     /// assert_eq!(response.headers.get("Cache-Control"), Some(&"no-cache".to_string()));
     /// ```
+    ///
+    /// Headers cannot be used to split the response: a header whose name is
+    /// not a valid token is dropped, and control characters in values (line
+    /// breaks included) are percent-encoded.
+    ///
+    /// ```
+    /// use file_shover::message::Response;
+    ///
+    /// let response = Response::new()
+    ///     .header("Location", "/a\r\nSet-Cookie: x=1")
+    ///     .header("Bad Name", "value");
+    /// assert_eq!(
+    ///     response.headers.get("Location"),
+    ///     Some(&"/a%0D%0ASet-Cookie: x=1".to_string())
+    /// );
+    /// assert!(!response.headers.contains_key("Bad Name"));
+    /// ```
     pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
-        self.headers.insert(name.into(), value.into());
+        let name = name.into();
+        if !is_token(&name) {
+            warn!("Dropped response header with invalid name {:?}", name);
+            return self;
+        }
+        let value = value.into();
+        let value = match escape_header_value(&value) {
+            Cow::Borrowed(_) => value,
+            Cow::Owned(escaped) => escaped,
+        };
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Adds request headers to `Vary`, keeping those already listed, so
+    /// every feature that varies the response can name its own.
+    ///
+    /// Names are compared without case and listed once; `*` replaces the
+    /// list. `headers` may itself be a comma-separated list.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::message::Response;
+    ///
+    /// let response = Response::new()
+    ///     .vary("Accept-Encoding")
+    ///     .vary("Origin, accept-encoding")
+    ///     .vary("Accept-Language");
+    /// assert_eq!(
+    ///     response.headers.get("Vary"),
+    ///     Some(&"Accept-Encoding, Origin, Accept-Language".to_string())
+    /// );
+    /// ```
+    pub fn vary(mut self, headers: &str) -> Self {
+        let mut listed = Vec::new();
+        self.headers.retain(|name, value| {
+            let is_vary = name.eq_ignore_ascii_case("Vary");
+            if is_vary {
+                listed.push(std::mem::take(value));
+            }
+            !is_vary
+        });
+        listed.push(headers.to_string());
+        let mut names: Vec<&str> = Vec::new();
+        for name in listed
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+        {
+            if !name.is_empty() && !names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+                names.push(name);
+            }
+        }
+        let value = if names.contains(&"*") {
+            "*".to_string()
+        } else {
+            names.join(", ")
+        };
+        self.header("Vary", value)
+    }
+
+    /// Adds a `Set-Cookie` header.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::cookie::Cookie;
+    /// use file_shover::message::Response;
+    ///
+    /// let mut response = Response::new()
+    ///     .set_cookie(Cookie::new("theme", "dark")?.path("/"))
+    ///     .set_cookie(Cookie::new("lang", "en")?);
+    ///
+    /// let mut buffer = Vec::new();
+    /// response.write(&mut buffer).unwrap();
+    /// let text = String::from_utf8(buffer).unwrap();
+    /// assert!(text.contains("Set-Cookie: theme=dark; Path=/\n"));
+    /// assert!(text.contains("Set-Cookie: lang=en\n"));
+    /// # Ok::<(), file_shover::cookie::CookieError>(())
+    /// ```
+    pub fn set_cookie(mut self, cookie: Cookie) -> Self {
+        self.cookies.push(cookie);
         self
     }
 
@@ -318,14 +959,17 @@ impl Response {
     /// # Examples
     ///
     /// ```
+    /// use file_shover::message::{Body, Response};
     /// use std::io::Cursor;
-    /// use file_shover::message::Response;
     ///
-    /// let mut response = Response::new().body(Box::new(Cursor::new("Hello, World!".as_bytes())));
-    /// //assert_eq!(response.body, Some(Box::new(Cursor::new("Hello, World!".as_bytes()))));
+    /// let response = Response::new().body("Hello, World!");
+    /// assert_eq!(response.body.known_length(), Some(13));
+    ///
+    /// let streamed = Response::new().body(Body::reader(Cursor::new("Hello, World!")));
+    /// assert_eq!(streamed.body.known_length(), None);
     /// ```
-    pub fn body(mut self, body: Box<dyn Read>) -> Self {
-        self.body = Some(body);
+    pub fn body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
         self
     }
 
@@ -361,7 +1005,7 @@ impl Response {
 
     /// Sets the Content-Length header.
     /// Accepts both usize and u64 values for maximum convenience.
-    /// 
+    ///
     /// # Examples
     ///
     /// ```
@@ -370,7 +1014,7 @@ impl Response {
     /// // Works with u64 (file metadata)
     /// let response = Response::new().content_length(66u64);
     /// assert_eq!(response.headers.get("Content-Length"), Some(&"66".to_string()));
-    /// 
+    ///
     /// // Also works with usize (slice/string lengths)
     /// let data = "hello world";
     /// let response = Response::new().content_length(data.len());
@@ -385,13 +1029,12 @@ impl Response {
     /// # Examples
     ///
     /// ```
-    /// use file_shover::message::{Response, HttpStatus};
-    /// use std::io::Cursor;
+    /// use file_shover::message::{Response, StatusCode};
     ///
     /// let mut response = Response::new()
-    ///     .status(HttpStatus::Ok)
+    ///     .status(StatusCode::OK)
     ///     .content_type("text/plain")
-    ///     .body(Box::new(Cursor::new("Hello, World!".as_bytes())));
+    ///     .body("Hello, World!");
     ///
     /// let mut buffer = Vec::new();
     /// response.write(&mut buffer).unwrap();
@@ -399,41 +1042,187 @@ impl Response {
     /// let response_str = String::from_utf8(buffer).unwrap();
     /// assert!(response_str.starts_with("HTTP/1.1 200 OK"));
     /// assert!(response_str.contains("Content-Type: text/plain"));
+    /// assert!(response_str.contains("Content-Length: 13"));
     /// assert!(response_str.ends_with("Hello, World!"));
     /// ```
     ///
+    /// Returns the number of body bytes written, excluding chunk framing.
+    ///
     /// # Errors
     ///
     /// Returns an `std::io::Error` if writing to the stream fails.
-    pub fn write<W: Write>(&mut self, stream: &mut W) -> std::io::Result<()> {
-        // Status line
-        writeln!(stream, "HTTP/1.1 {}", self.status.as_str())?;
+    pub fn write<W: Write>(&mut self, stream: &mut W) -> std::io::Result<u64> {
+        let mut written = 0;
+        self.write_counted(stream, &mut written)?;
+        Ok(written)
+    }
 
-        // Headers
-        for (name, value) in &self.headers {
-            writeln!(stream, "{}: {}", name, value)?;
+    /// Like [`write`](Response::write), but counts the body bytes sent in
+    /// `written` as they go out, so a failed write (e.g. the client went
+    /// away) still tells how much of the body got through.
+    pub(crate) fn write_counted<W: Write>(
+        &mut self,
+        stream: &mut W,
+        written: &mut u64,
+    ) -> std::io::Result<()> {
+        // Framing headers implied by the body
+        match &self.body {
+            Body::Bytes(bytes) if !self.headers.contains_key("Content-Length") => {
+                self.headers
+                    .insert("Content-Length".to_string(), bytes.len().to_string());
+            }
+            Body::Chunked(_) => {
+                self.headers.remove("Content-Length");
+                self.headers
+                    .insert("Transfer-Encoding".to_string(), "chunked".to_string());
+            }
+            _ => {}
         }
 
-        // Empty line to separate headers from body
-        writeln!(stream)?;
-
-        // Body (if present)
-        if let Some(ref mut body) = self.body {
-            let mut buffer = [0; BUFFER_SIZE];
-            loop {
-                let bytes_read = body.read(&mut buffer)?;
-                if bytes_read == 0 {
-                    break;
-                }
-                stream.write_all(&buffer[..bytes_read])?;
+        // The head is assembled in one buffer, not written a line at a time
+        let mut head = pool::take(0);
+
+        // Status line; the space before the reason phrase is required even
+        // when the phrase is empty
+        writeln!(
+            head,
+            "HTTP/1.1 {} {}",
+            self.status.as_u16(),
+            self.status.reason().unwrap_or_default()
+        )?;
+
+        // Headers; the map is public, so values set without `header` are
+        // checked again here
+        for (name, value) in &self.headers {
+            if is_token(name) {
+                writeln!(head, "{}: {}", name, escape_header_value(value))?;
             }
         }
+        for cookie in &self.cookies {
+            writeln!(head, "Set-Cookie: {}", cookie)?;
+        }
 
-        Ok(())
+        // Empty line to separate headers from body
+        writeln!(head)?;
+
+        let length = self
+            .headers
+            .get("Content-Length")
+            .and_then(|length| length.parse().ok());
+        self.body.write_to(&head, length, stream, written)
+    }
+}
+
+/// Returns whether `name` is a valid header name (an RFC 9110 token).
+fn is_token(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Percent-encodes the control characters in a header value, except tabs.
+fn escape_header_value(value: &str) -> Cow<'_, str> {
+    let is_unsafe = |c: char| c.is_ascii_control() && c != '\t';
+    if !value.contains(is_unsafe) {
+        return Cow::Borrowed(value);
     }
+    let mut escaped = String::with_capacity(value.len() + 8);
+    for c in value.chars() {
+        if is_unsafe(c) {
+            escaped.push_str(&format!("%{:02X}", c as u8));
+        } else {
+            escaped.push(c);
+        }
+    }
+    Cow::Owned(escaped)
 }
 
 impl Request {
+    /// Starts building a request without parsing.
+    pub fn builder() -> RequestBuilder {
+        RequestBuilder::default()
+    }
+
+    /// Looks up a header value, ignoring the case of the header name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::message::Request;
+    /// use std::io::Cursor;
+    ///
+    /// let request_data = "GET / HTTP/1.1\r\nauthorization: Bearer abc\r\n\r\n";
+    /// let request = Request::from_bytes(Cursor::new(request_data.as_bytes())).unwrap();
+    ///
+    /// assert_eq!(request.header("Authorization"), Some("Bearer abc"));
+    /// assert_eq!(request.header("Cookie"), None);
+    /// ```
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the cookies sent in the `Cookie` header, in order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::message::Request;
+    ///
+    /// let request = Request::builder().header("Cookie", "theme=dark; sid=abc").build();
+    /// assert_eq!(request.cookies(), vec![("theme", "dark"), ("sid", "abc")]);
+    /// assert_eq!(request.cookie("sid"), Some("abc"));
+    /// assert_eq!(request.cookie("missing"), None);
+    /// ```
+    pub fn cookies(&self) -> Vec<(&str, &str)> {
+        self.header("Cookie")
+            .map(|header| parse_cookie_header(header).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the value of the first cookie called `name`.
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        parse_cookie_header(self.header("Cookie")?)
+            .find(|(cookie, _)| *cookie == name)
+            .map(|(_, value)| value)
+    }
+
+    /// The decoded, normalized request path.
+    pub fn path(&self) -> &str {
+        self.target.path()
+    }
+
+    /// The raw query string, without the `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.target.query()
+    }
+
+    /// Returns the first value of a query string parameter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::message::Request;
+    /// use std::io::Cursor;
+    ///
+    /// let request_data = "GET /file.zip?expires=100&sig=ab HTTP/1.1\r\n\r\n";
+    /// let request = Request::from_bytes(Cursor::new(request_data.as_bytes())).unwrap();
+    ///
+    /// assert_eq!(request.path(), "/file.zip");
+    /// assert_eq!(request.query_param("sig"), Some("ab"));
+    /// assert_eq!(request.query_param("missing"), None);
+    /// ```
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query()?
+            .split('&')
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
     /// Parses an HTTP request from a byte stream.
     ///
     /// This method reads and parses an HTTP request from any type that implements
@@ -451,7 +1240,7 @@ impl Request {
     /// let request = Request::from_bytes(cursor).unwrap();
     ///
     /// assert_eq!(request.method, HttpMethod::GET);
-    /// assert_eq!(request.path, "/path");
+    /// assert_eq!(request.path(), "/path");
     /// assert_eq!(request.http_version, "HTTP/1.1");
     /// assert_eq!(request.headers.get("Host"), Some(&"example.com".to_string()));
     /// assert_eq!(request.headers.get("User-Agent"), Some(&"test".to_string()));
@@ -465,48 +1254,43 @@ impl Request {
     /// - Required components are missing
     /// - Headers are malformed
     pub fn from_bytes<R: Read>(stream: R) -> Result<Self, RequestError> {
-        let mut reader = BufReader::new(stream);
-        let mut request_line = String::new();
-        reader.read_line(&mut request_line)?;
-
-        // Parse request line
-        let mut parts = request_line.trim().split_ascii_whitespace();
-        let method = parts
-            .next()
-            .ok_or(RequestError::InvalidFormat)?
-            .parse::<HttpMethod>()?;
-        let path = parts.next().ok_or(RequestError::InvalidFormat)?.to_string();
-        let http_version = parts.next().ok_or(RequestError::InvalidFormat)?.to_string();
-
-        // Parse headers
-        let headers: Result<HashMap<String, String>, RequestError> = reader
-            .lines()
-            .take_while(|line_result| line_result.as_ref().map_or(false, |line| !line.is_empty()))
-            .map(|line_result| {
-                let line = line_result?;
-                let mut parts = line.splitn(2, ": ");
-                let key = parts.next().ok_or(RequestError::InvalidFormat)?.to_string();
-                let value = parts.next().ok_or(RequestError::InvalidFormat)?.to_string();
-                Ok((key, value))
-            })
-            .collect();
-
-        let headers = headers?;
-
-        Ok(Request {
-            method,
-            path,
-            http_version,
-            headers,
-        })
+        Self::from_reader(&mut BufReader::new(stream))
+    }
+
+    /// Parses the request line and headers, leaving any body unread in `reader`.
+    ///
+    /// Unlike [`Request::from_bytes`], the buffered reader stays with the
+    /// caller, so bytes of the body that were read ahead are not lost.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::message::{Request, HttpMethod};
+    /// use std::io::{BufReader, Cursor, Read};
+    ///
+    /// let request_data = "POST /api HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+    /// let mut reader = BufReader::new(Cursor::new(request_data.as_bytes()));
+    /// let request = Request::from_reader(&mut reader).unwrap();
+    ///
+    /// assert_eq!(request.method, HttpMethod::POST);
+    /// let mut body = String::new();
+    /// reader.read_to_string(&mut body).unwrap();
+    /// assert_eq!(body, "hello");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns a `RequestError` under the same conditions as [`Request::from_bytes`].
+    pub fn from_reader<R: BufRead>(reader: &mut R) -> Result<Self, RequestError> {
+        parser::read_request(reader, &Limits::default())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::str::FromStr;
     use std::io::Cursor;
+    use std::str::FromStr;
 
     #[test]
     fn test_http_method_from_str_valid_cases() {
@@ -517,15 +1301,19 @@ mod tests {
             HttpMethod::from_str("OPTIONS").unwrap(),
             HttpMethod::OPTIONS
         );
+        assert_eq!(HttpMethod::from_str("POST").unwrap(), HttpMethod::POST);
+        assert_eq!(HttpMethod::from_str("PUT").unwrap(), HttpMethod::PUT);
+        assert_eq!(HttpMethod::from_str("DELETE").unwrap(), HttpMethod::DELETE);
+        assert_eq!(HttpMethod::from_str("PATCH").unwrap(), HttpMethod::PATCH);
     }
 
     #[test]
     fn test_http_method_from_str_invalid_cases() {
         // Test invalid methods return errors
-        assert!(HttpMethod::from_str("POST").is_err());
-        assert!(HttpMethod::from_str("PUT").is_err());
-        assert!(HttpMethod::from_str("DELETE").is_err());
-        assert!(HttpMethod::from_str("PATCH").is_err());
+        assert!(HttpMethod::from_str("CONNECT").is_err());
+        assert!(HttpMethod::from_str("TRACE").is_err());
+        assert!(HttpMethod::from_str("BREW").is_err());
+        assert!(HttpMethod::from_str("post").is_err());
         assert!(HttpMethod::from_str("").is_err());
         assert!(HttpMethod::from_str("get").is_err()); // lowercase
         assert!(HttpMethod::from_str("Get").is_err()); // mixed case
@@ -535,7 +1323,7 @@ mod tests {
     #[test]
     fn test_http_method_from_str_error_type() {
         // Test that invalid methods return the correct error type
-        match HttpMethod::from_str("POST") {
+        match HttpMethod::from_str("BREW") {
             Err(RequestError::InvalidFormat) => (), // Expected
             Err(other) => panic!("Expected InvalidFormat, got {:?}", other),
             Ok(method) => panic!("Expected error, got {:?}", method),
@@ -551,26 +1339,40 @@ mod tests {
     }
 
     #[test]
-    fn test_http_status_display() {
+    fn test_status_code_display() {
         // Test HTTP status display
-        assert_eq!(HttpStatus::Ok.to_string(), "200 OK");
-        assert_eq!(HttpStatus::NotFound.to_string(), "404 Not Found");
+        assert_eq!(StatusCode::OK.to_string(), "200 OK");
+        assert_eq!(StatusCode::NOT_FOUND.to_string(), "404 Not Found");
         assert_eq!(
-            HttpStatus::InternalServerError.to_string(),
+            StatusCode::INTERNAL_SERVER_ERROR.to_string(),
             "500 Internal Server Error"
         );
     }
 
+    #[test]
+    fn test_custom_status_code() {
+        assert_eq!(
+            StatusCode::from_u16(307),
+            Ok(StatusCode::TEMPORARY_REDIRECT)
+        );
+        assert_eq!(StatusCode::from_u16(1000), Err(InvalidStatusCode(1000)));
+
+        let mut response = Response::new().status(StatusCode::from_u16(299).unwrap());
+        let mut buffer = Vec::new();
+        response.write(&mut buffer).unwrap();
+        assert!(buffer.starts_with(b"HTTP/1.1 299 \n"));
+    }
+
     #[test]
     fn test_response_builder() {
         // Test the response builder pattern
-        let mut response = Response::new()
-            .status(HttpStatus::Ok)
+        let response = Response::new()
+            .status(StatusCode::OK)
             .content_type("text/html")
             .server("test-server")
-            .body(Box::new(Cursor::new("Hello World".as_bytes())));
+            .body(Body::reader(Cursor::new("Hello World")));
 
-        assert_eq!(response.status, HttpStatus::Ok);
+        assert_eq!(response.status, StatusCode::OK);
         assert_eq!(
             response.headers.get("Content-Type"),
             Some(&"text/html".to_string())
@@ -581,7 +1383,145 @@ mod tests {
         );
         // body
         let mut body = Vec::new();
-        response.body.unwrap().read_to_end(&mut body).unwrap();
+        response.body.into_reader().read_to_end(&mut body).unwrap();
         assert_eq!(body, "Hello World".as_bytes().to_vec());
     }
+
+    #[test]
+    fn test_headers_cannot_split_the_response() {
+        let mut response = Response::new()
+            .header("Content-Disposition", "attachment; filename=\"a\nb\"")
+            .header("X-Bad\r\nInjected", "1")
+            .body("ok");
+        // Values inserted into the map directly are escaped when written
+        response
+            .headers
+            .insert("X-Raw".to_string(), "1\r\n\r\n<script>".to_string());
+        response
+            .headers
+            .insert("X-Raw: 2\r\nX-Other".to_string(), "3".to_string());
+        let mut buffer = Vec::new();
+        response.write(&mut buffer).unwrap();
+        let raw = String::from_utf8(buffer).unwrap();
+        assert!(raw.contains("Content-Disposition: attachment; filename=\"a%0Ab\"\n"));
+        assert!(raw.contains("X-Raw: 1%0D%0A%0D%0A<script>\n"));
+        assert!(!raw.contains("Injected"));
+        assert!(!raw.contains("X-Other"));
+        assert!(raw.ends_with("\n\nok"));
+    }
+
+    #[test]
+    fn test_chunked_body() {
+        let mut response = Response::new().body(Body::chunked(Cursor::new("Hello World")));
+        let mut buffer = Vec::new();
+        assert_eq!(response.write(&mut buffer).unwrap(), 11);
+
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(text.contains("Transfer-Encoding: chunked"));
+        assert!(!text.contains("Content-Length"));
+        assert!(text.ends_with("B\r\nHello World\r\n0\r\n\r\n"));
+    }
+
+    /// Records each write call; vectored writes take everything at once.
+    #[derive(Default)]
+    struct Calls {
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl Write for Calls {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+            self.writes
+                .push(bufs.iter().flat_map(|buf| buf.iter().copied()).collect());
+            Ok(bufs.iter().map(|buf| buf.len()).sum())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_streamed_body_is_held_to_its_length() {
+        // A file that grew while being sent: only the announced bytes go out
+        let mut response = Response::new()
+            .content_length(5u64)
+            .body(Body::reader(Cursor::new("Hello, appended")));
+        let mut buffer = Vec::new();
+        assert_eq!(response.write(&mut buffer).unwrap(), 5);
+        assert!(String::from_utf8(buffer).unwrap().ends_with("\n\nHello"));
+
+        // One that shrank fails instead of looking complete
+        let mut response = Response::new()
+            .content_length(100u64)
+            .body(Body::reader(Cursor::new("Hello")));
+        let err = response.write(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_disconnect_reports_bytes_sent() {
+        // A client that goes away after taking a little over one buffer
+        struct Hangup(usize);
+        impl Write for Hangup {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if self.0 == 0 {
+                    return Err(ErrorKind::BrokenPipe.into());
+                }
+                let len = buf.len().min(self.0);
+                self.0 -= len;
+                Ok(len)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut response =
+            Response::new().body(Body::reader(Cursor::new(vec![b'x'; 4 * BUFFER_SIZE])));
+        let mut written = 0;
+        let err = response
+            .write_counted(&mut Hangup(BUFFER_SIZE + 1024), &mut written)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+        assert!(written > 0 && written < 4 * BUFFER_SIZE as u64);
+    }
+
+    #[test]
+    fn test_small_responses_take_one_write() {
+        for body in [
+            Body::from("Hello"),
+            Body::reader(Cursor::new("Hello")),
+            Body::Empty,
+        ] {
+            let mut response = Response::new().content_type("text/plain").body(body);
+            let mut calls = Calls::default();
+            response.write(&mut calls).unwrap();
+            assert_eq!(calls.writes.len(), 1);
+            let text = String::from_utf8(calls.writes.remove(0)).unwrap();
+            assert!(text.starts_with("HTTP/1.1 200 OK"));
+        }
+
+        // A write taking part of the head still sends everything
+        struct Trickle(Vec<u8>);
+        impl Write for Trickle {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                let len = buf.len().min(3);
+                self.0.extend_from_slice(&buf[..len]);
+                Ok(len)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut trickle = Trickle(Vec::new());
+        let mut response = Response::new().body("Hello");
+        response.write(&mut trickle).unwrap();
+        assert!(String::from_utf8(trickle.0).unwrap().ends_with("\n\nHello"));
+    }
 }
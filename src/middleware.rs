@@ -0,0 +1,670 @@
+/*
+* Middleware module
+*
+* Requests answered by the server itself pass through a chain of layers
+* wrapped around a terminal handler (normally the file tree):
+*
+*   before:  logging -> maintenance -> rate limit -> quota -> auth -> user layers
+*            -> live reload
+*   handler
+*   after:   live reload -> user layers -> ... -> logging   (reverse order)
+*
+* A `before` hook may answer the request itself (e.g. 401). Later layers and
+* the handler are then skipped, but the `after` hooks of the layers that
+* already ran still see the response. Proxied requests only run the `before`
+* hooks, because their response is streamed from the upstream untouched.
+*/
+
+use crate::auth::{Access, AccessPolicy, AuthOutcome, BearerAuth};
+use crate::compcache::CompressionCache;
+use crate::message::{
+    Body, HttpMethod, Request, Response, StatusCode, DEFAULT_FORBIDDEN_BODY,
+    DEFAULT_INTERNAL_ERROR_BODY, DEFAULT_TOO_MANY_REQUESTS_BODY, DEFAULT_UNAUTHORIZED_BODY,
+};
+use crate::ratelimit::RateLimiter;
+use crate::signing::{unix_now, UrlSigner};
+use crate::units::retry_after_secs;
+use flate2::read::GzEncoder;
+use flate2::Compression as GzLevel;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+/// Produces the response for a request.
+///
+/// Implemented for closures, so a handler can be written inline.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::message::{Request, Response};
+/// use file_shover::middleware::Handler;
+/// use std::io::Cursor;
+///
+/// let hello = |_req: &Request| Response::new().content_type("text/plain");
+/// let req = Request::from_bytes(Cursor::new("GET / HTTP/1.1\r\n\r\n".as_bytes())).unwrap();
+/// assert_eq!(
+///     hello.handle(&req).headers.get("Content-Type"),
+///     Some(&"text/plain".to_string())
+/// );
+/// ```
+pub trait Handler: Send + Sync {
+    fn handle(&self, req: &Request) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Response + Send + Sync,
+{
+    fn handle(&self, req: &Request) -> Response {
+        self(req)
+    }
+}
+
+/// A layer around the handler; both hooks default to doing nothing.
+pub trait Middleware: Send + Sync {
+    /// Runs before the handler. Returning a response answers the request.
+    fn before(&self, _req: &Request) -> Option<Response> {
+        None
+    }
+
+    /// Runs on the way out and may replace or modify the response.
+    fn after(&self, _req: &Request, response: Response) -> Response {
+        response
+    }
+}
+
+/// A handler wrapped in middleware layers.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::message::{StatusCode, Request, Response};
+/// use file_shover::middleware::{Chain, Handler, Middleware};
+/// use std::io::Cursor;
+///
+/// struct Tag;
+/// impl Middleware for Tag {
+///     fn after(&self, _req: &Request, response: Response) -> Response {
+///         response.header("X-Tag", "seen")
+///     }
+/// }
+///
+/// struct Deny;
+/// impl Middleware for Deny {
+///     fn before(&self, req: &Request) -> Option<Response> {
+///         req.path().starts_with("/private").then(|| Response::new().status(StatusCode::FORBIDDEN))
+///     }
+/// }
+///
+/// let chain = Chain::new(|_req: &Request| Response::new()).with(Tag).with(Deny);
+/// let get = |path: &str| {
+///     let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+///     chain.handle(&Request::from_bytes(Cursor::new(raw.into_bytes())).unwrap())
+/// };
+///
+/// assert_eq!(get("/").status, StatusCode::OK);
+/// let denied = get("/private/x");
+/// assert_eq!(denied.status, StatusCode::FORBIDDEN);
+/// assert_eq!(denied.headers.get("X-Tag"), Some(&"seen".to_string()));
+/// ```
+pub struct Chain {
+    layers: Vec<Box<dyn Middleware>>,
+    handler: Box<dyn Handler>,
+}
+
+impl Chain {
+    /// Creates a chain with no layers around `handler`.
+    pub fn new(handler: impl Handler + 'static) -> Self {
+        Self::boxed(Box::new(handler))
+    }
+
+    pub(crate) fn boxed(handler: Box<dyn Handler>) -> Self {
+        Self {
+            layers: Vec::new(),
+            handler,
+        }
+    }
+
+    /// Adds a layer inside the existing ones (closer to the handler).
+    pub fn with(mut self, layer: impl Middleware + 'static) -> Self {
+        self.push(Box::new(layer));
+        self
+    }
+
+    /// Adds a boxed layer inside the existing ones.
+    pub fn push(&mut self, layer: Box<dyn Middleware>) {
+        self.layers.push(layer);
+    }
+
+    /// Runs only the `before` hooks, for requests answered elsewhere (proxying).
+    ///
+    /// Returns the response of the first layer that answered, if any.
+    pub fn before(&self, req: &Request) -> Option<Response> {
+        self.layers.iter().find_map(|layer| layer.before(req))
+    }
+}
+
+impl Handler for Chain {
+    fn handle(&self, req: &Request) -> Response {
+        let mut entered = 0;
+        let mut early = None;
+        for layer in &self.layers {
+            entered += 1;
+            if let Some(response) = layer.before(req) {
+                early = Some(response);
+                break;
+            }
+        }
+        let response = early.unwrap_or_else(|| self.handler.handle(req));
+        self.layers[..entered]
+            .iter()
+            .rev()
+            .fold(response, |response, layer| layer.after(req, response))
+    }
+}
+
+/// Logs request and response headers at debug level.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Logging;
+
+impl Middleware for Logging {
+    fn before(&self, req: &Request) -> Option<Response> {
+        debug!("Request headers: {:?}", req.headers);
+        None
+    }
+
+    fn after(&self, _req: &Request, response: Response) -> Response {
+        debug!(
+            "Response {} headers: {:?}",
+            response.status, response.headers
+        );
+        response
+    }
+}
+
+/// Answers 429 with `Retry-After` once a client exceeds its quota.
+#[derive(Debug)]
+pub struct RateLimit {
+    limiter: RateLimiter,
+}
+
+impl RateLimit {
+    pub fn new(limiter: RateLimiter) -> Self {
+        Self { limiter }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn before(&self, req: &Request) -> Option<Response> {
+        let client_ip = req.client_ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let wait = self.limiter.check(client_ip).err()?;
+        warn!("Rate limit exceeded for {}", client_ip);
+        let retry_after = retry_after_secs(wait);
+        Some(
+            Response::error(
+                StatusCode::TOO_MANY_REQUESTS,
+                DEFAULT_TOO_MANY_REQUESTS_BODY,
+            )
+            .header("Retry-After", retry_after.to_string()),
+        )
+    }
+}
+
+/// Signed URLs, bearer tokens and per-path access rules.
+///
+/// A valid signature grants read access (GET and HEAD) on its own; it
+/// covers only the path and expiry, so other methods ignore it. Otherwise
+/// the path's access rule applies; paths without a rule need a token whenever tokens are
+/// configured, are refused when signatures are required, and are public
+/// otherwise.
+#[derive(Clone, Default)]
+pub struct Auth {
+    bearer_auth: BearerAuth,
+    access_policy: AccessPolicy,
+    url_signer: Option<UrlSigner>,
+    require_signed: bool,
+}
+
+impl Auth {
+    pub fn new(
+        bearer_auth: BearerAuth,
+        access_policy: AccessPolicy,
+        url_signer: Option<UrlSigner>,
+        require_signed: bool,
+    ) -> Self {
+        Self {
+            bearer_auth,
+            access_policy,
+            url_signer,
+            require_signed,
+        }
+    }
+
+    /// Whether the credentials of `req` satisfy the access rule of `path`,
+    /// a path the request touches other than its own, such as the
+    /// destination of a WebDAV `MOVE`. Signatures are not considered.
+    pub fn allows(&self, req: &Request, path: &str) -> bool {
+        self.outcome(req, path) == AuthOutcome::Granted
+    }
+
+    /// Whether `req` may read `path`, a file it reaches through another
+    /// path, such as an entry of a directory archive. A signed request also
+    /// reads the files under the same rule as the path it was signed for.
+    pub fn can_read(&self, req: &Request, path: &str) -> bool {
+        self.allows(req, path)
+            || (self.is_signed(req)
+                && self.access_policy.rule_for(path) == self.access_policy.rule_for(req.path()))
+    }
+
+    /// Whether anyone may read `path` without credentials.
+    pub fn is_public(&self, path: &str) -> bool {
+        self.access(path) == Some(&Access::Public)
+    }
+
+    /// Whether `req` is a read with a valid signature for its path.
+    fn is_signed(&self, req: &Request) -> bool {
+        let read = matches!(req.method, HttpMethod::GET | HttpMethod::HEAD);
+        self.url_signer.as_ref().is_some_and(|signer| {
+            read && signer
+                .verify(
+                    req.path(),
+                    req.query_param("expires"),
+                    req.query_param("sig"),
+                    unix_now(),
+                )
+                .is_ok()
+        })
+    }
+
+    /// The requirement of `path`: its rule, or the default for paths
+    /// without one (`None` when nothing but a signature is accepted).
+    fn access(&self, path: &str) -> Option<&Access> {
+        let default_access = if !self.bearer_auth.is_empty() {
+            Some(&Access::Token)
+        } else if self.require_signed {
+            None
+        } else {
+            Some(&Access::Public)
+        };
+        self.access_policy.access_for(path).or(default_access)
+    }
+
+    /// Checks the access rule of `path` against the credentials of `req`.
+    fn outcome(&self, req: &Request, path: &str) -> AuthOutcome {
+        match self.access(path) {
+            Some(access) => self.access_policy.check(
+                access,
+                req.header("Authorization"),
+                req.client_cert.as_ref(),
+                &self.bearer_auth,
+            ),
+            None => AuthOutcome::Forbidden,
+        }
+    }
+
+    /// Returns true if every request would be granted.
+    pub fn is_open(&self) -> bool {
+        self.bearer_auth.is_empty()
+            && self.access_policy.is_empty()
+            && self.url_signer.is_none()
+            && !self.require_signed
+    }
+}
+
+impl Middleware for Auth {
+    fn before(&self, req: &Request) -> Option<Response> {
+        if let Some(signer) = &self.url_signer {
+            let read = matches!(req.method, HttpMethod::GET | HttpMethod::HEAD);
+            if read && req.query_param("sig").is_some() {
+                let verified = signer.verify(
+                    req.path(),
+                    req.query_param("expires"),
+                    req.query_param("sig"),
+                    unix_now(),
+                );
+                return match verified {
+                    Ok(()) => None,
+                    Err(e) => {
+                        info!("Rejected signed URL for {}: {}", req.path(), e);
+                        Some(Response::error(
+                            StatusCode::FORBIDDEN,
+                            DEFAULT_FORBIDDEN_BODY,
+                        ))
+                    }
+                };
+            }
+        }
+
+        match self.outcome(req, req.path()) {
+            AuthOutcome::Granted => None,
+            AuthOutcome::Challenge(challenge) => {
+                info!("Rejected unauthenticated request for {}", req.path());
+                Some(
+                    Response::error(StatusCode::UNAUTHORIZED, DEFAULT_UNAUTHORIZED_BODY)
+                        .header("WWW-Authenticate", challenge),
+                )
+            }
+            AuthOutcome::Forbidden => {
+                info!("Rejected unauthorized request for {}", req.path());
+                Some(Response::error(
+                    StatusCode::FORBIDDEN,
+                    DEFAULT_FORBIDDEN_BODY,
+                ))
+            }
+        }
+    }
+}
+
+/// Gzip-compresses text-like responses for clients that accept it.
+///
+/// The body stays streamed; since its compressed size is unknown up front,
+/// it is sent with chunked transfer encoding. With a [`CompressionCache`],
+/// responses carrying a validator are compressed once and then served from
+/// the cache with a `Content-Length`.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    min_size: u64,
+    cache: Option<Arc<CompressionCache>>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
+
+impl Compression {
+    /// Compresses bodies of at least `min_size` bytes (or of unknown size).
+    pub fn new(min_size: u64) -> Self {
+        Self {
+            min_size,
+            cache: None,
+        }
+    }
+
+    /// Keeps compressed bodies in `cache` instead of compressing each time.
+    pub fn cache(mut self, cache: CompressionCache) -> Self {
+        self.cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Answers from the cache if `response` can be cached there.
+    fn cached(
+        &self,
+        req: &Request,
+        response: &mut Response,
+        length: Option<u64>,
+    ) -> Option<io::Result<Arc<Vec<u8>>>> {
+        let cache = self.cache.as_ref()?;
+        let length = length.filter(|length| *length <= cache.max_entry())?;
+        let validator = match (
+            response.headers.get("ETag"),
+            response.headers.get("Last-Modified"),
+        ) {
+            (Some(etag), _) => etag.clone(),
+            (None, Some(modified)) => format!("{} {}", modified, length),
+            (None, None) => return None,
+        };
+        let key = format!("{} gzip", req.target);
+        let body = std::mem::take(&mut response.body);
+        Some(cache.get_or_compress(&key, &validator, || {
+            let mut compressed = Vec::new();
+            GzEncoder::new(body.into_reader(), GzLevel::default()).read_to_end(&mut compressed)?;
+            debug!(
+                "Compressed {} ({} -> {} bytes)",
+                key,
+                length,
+                compressed.len()
+            );
+            Ok(compressed)
+        }))
+    }
+
+    fn is_compressible(content_type: &str) -> bool {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        (mime.starts_with("text/") && mime != "text/event-stream")
+            || matches!(
+                mime,
+                "application/javascript" | "application/json" | "application/xml" | "image/svg+xml"
+            )
+    }
+}
+
+impl Middleware for Compression {
+    fn after(&self, req: &Request, mut response: Response) -> Response {
+        let accepts_gzip = req.header("Accept-Encoding").is_some_and(|encodings| {
+            encodings.split(',').any(|encoding| {
+                let mut params = encoding.split(';');
+                let name = params.next().unwrap_or_default().trim();
+                let refused = params.any(|param| {
+                    param
+                        .trim()
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        == Some(0.0)
+                });
+                name.eq_ignore_ascii_case("gzip") && !refused
+            })
+        });
+        let length = response
+            .headers
+            .get("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok())
+            .or(response.body.known_length());
+        let compressible = response.status == StatusCode::OK
+            && !response.body.is_empty()
+            && !response.headers.contains_key("Content-Encoding")
+            && response
+                .headers
+                .get("Content-Type")
+                .is_some_and(|mime| Self::is_compressible(mime))
+            && length.is_none_or(|length| length >= self.min_size);
+        if !compressible {
+            return response;
+        }
+        response = response.vary("Accept-Encoding");
+        if !accepts_gzip {
+            return response;
+        }
+
+        if let Some(cached) = self.cached(req, &mut response, length) {
+            return match cached {
+                Ok(compressed) => {
+                    response.headers.remove("Content-Length");
+                    response
+                        .header("Content-Encoding", "gzip")
+                        .content_length(compressed.len())
+                        .body(compressed.to_vec())
+                }
+                Err(e) => {
+                    warn!("Compressing {} failed: {}", req.path(), e);
+                    Response::error(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        DEFAULT_INTERNAL_ERROR_BODY,
+                    )
+                }
+            };
+        }
+
+        let body = std::mem::take(&mut response.body).into_reader();
+        response
+            .header("Content-Encoding", "gzip")
+            .body(Body::chunked(GzEncoder::new(body, GzLevel::default())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::{Cursor, Read};
+
+    fn request(raw: &str) -> Request {
+        Request::from_bytes(Cursor::new(raw.as_bytes())).unwrap()
+    }
+
+    fn text(size: usize) -> Response {
+        Response::new()
+            .content_type("text/plain")
+            .content_length(size)
+            .body(Body::reader(Cursor::new(vec![b'a'; size])))
+    }
+
+    #[test]
+    fn test_after_hooks_run_in_reverse() {
+        struct Append(&'static str);
+        impl Middleware for Append {
+            fn after(&self, _req: &Request, response: Response) -> Response {
+                let trail = response.headers.get("X-Trail").cloned().unwrap_or_default();
+                response.header("X-Trail", format!("{}{}", trail, self.0))
+            }
+        }
+        let chain = Chain::new(|_req: &Request| Response::new())
+            .with(Append("outer"))
+            .with(Append("inner,"));
+        let response = chain.handle(&request("GET / HTTP/1.1\r\n\r\n"));
+        assert_eq!(
+            response.headers.get("X-Trail"),
+            Some(&"inner,outer".to_string())
+        );
+    }
+
+    #[test]
+    fn test_compression() {
+        let req = request("GET /a.txt HTTP/1.1\r\nAccept-Encoding: br, gzip\r\n\r\n");
+        let response = Compression::default().after(&req, text(4096));
+        assert_eq!(
+            response.headers.get("Content-Encoding"),
+            Some(&"gzip".to_string())
+        );
+        assert!(matches!(response.body, Body::Chunked(_)));
+
+        let mut decoded = Vec::new();
+        GzDecoder::new(response.body.into_reader())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, vec![b'a'; 4096]);
+    }
+
+    #[test]
+    fn test_compression_cache() {
+        let compression = Compression::default().cache(CompressionCache::memory(1 << 20));
+        let req = request("GET /app.css HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+        let file = |byte: u8, modified: &str| {
+            Response::new()
+                .status(StatusCode::OK)
+                .content_type("text/css")
+                .content_length(4096u64)
+                .header("Last-Modified", modified)
+                .body(Body::reader(Cursor::new(vec![byte; 4096])))
+        };
+        let decoded = |response: Response| {
+            assert!(matches!(response.body, Body::Bytes(_)));
+            let mut decoded = Vec::new();
+            GzDecoder::new(response.body.into_reader())
+                .read_to_end(&mut decoded)
+                .unwrap();
+            decoded
+        };
+
+        let first = compression.after(&req, file(b'a', "Mon, 01 Jan 2024 00:00:00 GMT"));
+        let length = first.headers.get("Content-Length").cloned().unwrap();
+        assert_eq!(decoded(first), vec![b'a'; 4096]);
+
+        // Same validator: served from the cache, whatever the body says now
+        let cached = compression.after(&req, file(b'b', "Mon, 01 Jan 2024 00:00:00 GMT"));
+        assert_eq!(cached.headers.get("Content-Length"), Some(&length));
+        assert_eq!(decoded(cached), vec![b'a'; 4096]);
+
+        let changed = compression.after(&req, file(b'c', "Tue, 02 Jan 2024 00:00:00 GMT"));
+        assert_eq!(decoded(changed), vec![b'c'; 4096]);
+
+        // Without a validator the body is streamed as before
+        let listing = compression.after(&req, text(4096));
+        assert!(matches!(listing.body, Body::Chunked(_)));
+    }
+
+    #[test]
+    fn test_compression_skipped() {
+        let gzip = request("GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+        let small = Compression::default().after(&gzip, text(10));
+        assert_eq!(small.headers.get("Content-Encoding"), None);
+
+        let refused = request("GET / HTTP/1.1\r\nAccept-Encoding: gzip;q=0\r\n\r\n");
+        let response = Compression::default().after(&refused, text(4096));
+        assert_eq!(response.headers.get("Content-Encoding"), None);
+        assert_eq!(
+            response.headers.get("Vary"),
+            Some(&"Accept-Encoding".to_string())
+        );
+
+        let image = Response::new()
+            .content_type("image/png")
+            .body(vec![0; 4096]);
+        let response = Compression::default().after(&gzip, image);
+        assert_eq!(response.headers.get("Content-Encoding"), None);
+    }
+
+    #[test]
+    fn test_auth_requires_token() {
+        let auth = Auth::new(
+            BearerAuth::new(vec!["t".to_string()]),
+            AccessPolicy::default(),
+            None,
+            false,
+        );
+        let denied = auth.before(&request("GET / HTTP/1.1\r\n\r\n")).unwrap();
+        assert_eq!(denied.status, StatusCode::UNAUTHORIZED);
+        assert!(auth
+            .before(&request(
+                "GET / HTTP/1.1\r\nAuthorization: Bearer t\r\n\r\n"
+            ))
+            .is_none());
+    }
+
+    #[test]
+    fn test_signature_grants_reads_only() {
+        let signer = UrlSigner::new(b"key");
+        let auth = Auth::new(
+            BearerAuth::new(Vec::new()),
+            AccessPolicy::default(),
+            Some(signer.clone()),
+            true,
+        );
+        let query = signer.signed_query("/x", unix_now() + 60);
+        let get = request(&format!("GET /x?{} HTTP/1.1\r\n\r\n", query));
+        assert!(auth.before(&get).is_none());
+        let head = request(&format!("HEAD /x?{} HTTP/1.1\r\n\r\n", query));
+        assert!(auth.before(&head).is_none());
+        for method in ["PUT", "DELETE", "POST", "MOVE"] {
+            let write = request(&format!("{} /x?{} HTTP/1.1\r\n\r\n", method, query));
+            let denied = auth.before(&write).unwrap();
+            assert_eq!(denied.status, StatusCode::FORBIDDEN, "{}", method);
+        }
+    }
+
+    #[test]
+    fn test_signed_directory_reads() {
+        let config = crate::config::Config::from_toml(
+            "[[auth.rules]]\npath = \"/d/private/**\"\naccess = \"token\"",
+        )
+        .unwrap();
+        let signer = UrlSigner::new(b"key");
+        let auth = Auth::new(
+            BearerAuth::new(vec!["t".to_string()]),
+            AccessPolicy::from_config(&config.auth).unwrap(),
+            Some(signer.clone()),
+            false,
+        );
+        let query = signer.signed_query("/d/", unix_now() + 60);
+        let signed = request(&format!("GET /d/?{} HTTP/1.1\r\n\r\n", query));
+        assert!(auth.can_read(&signed, "/d/a.txt"));
+        assert!(!auth.can_read(&signed, "/d/private/b.txt"));
+        let unsigned = request("GET /d/ HTTP/1.1\r\n\r\n");
+        assert!(!auth.can_read(&unsigned, "/d/a.txt"));
+        let token = request("GET /d/ HTTP/1.1\r\nAuthorization: Bearer t\r\n\r\n");
+        assert!(auth.can_read(&token, "/d/private/b.txt"));
+    }
+}
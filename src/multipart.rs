@@ -0,0 +1,365 @@
+/*
+* Multipart module
+*
+* A streaming `multipart/form-data` (RFC 7578) reader, used for browser
+* uploads. Parts are read one at a time and their bodies copied straight to
+* a writer, so a large file never has to fit in memory.
+*
+*   --boundary\r\n
+*   Content-Disposition: form-data; name="file"; filename="a.txt"\r\n
+*   \r\n
+*   <body>\r\n--boundary--
+*
+* Only the delimiter search needs lookahead: up to one delimiter's worth of
+* bytes is held back until it is known not to start the next boundary.
+*/
+
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Longest part header line accepted.
+const MAX_HEADER_LINE: usize = 4096;
+
+/// Most header lines accepted in one part.
+const MAX_HEADERS: usize = 32;
+
+/// Bytes requested from the underlying reader at a time.
+const READ_SIZE: usize = 8 * 1024;
+
+/// The headers of one part.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Part {
+    /// The form field name.
+    pub name: Option<String>,
+    /// The file name sent by the client, for file fields.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+/// Reads the parts of a `multipart/form-data` body.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::multipart::Multipart;
+///
+/// let body = "--xyz\r\n\
+///             Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+///             \r\n\
+///             hello\r\n\
+///             --xyz--\r\n";
+/// let mut form = Multipart::new(body.as_bytes(), "xyz");
+/// let part = form.next_part().unwrap().unwrap();
+/// assert_eq!(part.filename.as_deref(), Some("a.txt"));
+/// let mut contents = Vec::new();
+/// form.copy_body(&mut contents).unwrap();
+/// assert_eq!(contents, b"hello");
+/// assert!(form.next_part().unwrap().is_none());
+/// ```
+pub struct Multipart<R> {
+    reader: R,
+    /// `\r\n--boundary`; the first one is found by seeding the buffer with
+    /// `\r\n`.
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    /// The last delimiter read has not been followed by part headers yet.
+    at_delimiter: bool,
+    done: bool,
+}
+
+impl<R: Read> Multipart<R> {
+    /// Reads a body whose `Content-Type` names `boundary`.
+    pub fn new(reader: R, boundary: &str) -> Self {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+        Self {
+            reader,
+            delimiter,
+            buffer: b"\r\n".to_vec(),
+            at_delimiter: false,
+            done: false,
+        }
+    }
+
+    /// Moves to the next part, skipping whatever is left of the current
+    /// one, and returns its headers; `None` after the closing boundary.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidData` for malformed framing or headers, `UnexpectedEof` for
+    /// a truncated body, and read errors.
+    pub fn next_part(&mut self) -> io::Result<Option<Part>> {
+        if self.done {
+            return Ok(None);
+        }
+        if !self.at_delimiter {
+            self.copy_body(&mut io::sink())?;
+        }
+        self.at_delimiter = false;
+        self.fill_to(2)?;
+        if self.buffer.starts_with(b"--") {
+            self.done = true;
+            return Ok(None);
+        }
+        // Transport padding may follow the boundary before its line break
+        let line = self.read_line()?;
+        if !line.iter().all(|b| *b == b' ' || *b == b'\t') {
+            return Err(invalid("garbage after boundary"));
+        }
+
+        let mut part = Part::default();
+        for _ in 0..=MAX_HEADERS {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                return Ok(Some(part));
+            }
+            let line = String::from_utf8(line).map_err(|_| invalid("non-UTF-8 part header"))?;
+            let Some((name, value)) = line.split_once(':') else {
+                return Err(invalid("malformed part header"));
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("Content-Disposition") {
+                for (key, value) in parameters(value) {
+                    match key.to_ascii_lowercase().as_str() {
+                        "name" => part.name = Some(value),
+                        "filename" => part.filename = Some(value),
+                        _ => {}
+                    }
+                }
+            } else if name.eq_ignore_ascii_case("Content-Type") {
+                part.content_type = Some(value.to_string());
+            }
+        }
+        Err(invalid("too many part headers"))
+    }
+
+    /// Copies the rest of the current part's body to `out` and returns the
+    /// number of bytes copied.
+    ///
+    /// # Errors
+    ///
+    /// `UnexpectedEof` if the body ends before the next boundary, and read
+    /// or write errors.
+    pub fn copy_body<W: Write + ?Sized>(&mut self, out: &mut W) -> io::Result<u64> {
+        if self.at_delimiter || self.done {
+            return Ok(0);
+        }
+        let mut copied = 0u64;
+        let keep = self.delimiter.len() - 1;
+        loop {
+            if let Some(pos) = find(&self.buffer, &self.delimiter) {
+                out.write_all(&self.buffer[..pos])?;
+                self.buffer.drain(..pos + self.delimiter.len());
+                self.at_delimiter = true;
+                return Ok(copied + pos as u64);
+            }
+            // Hold back what could be the start of a delimiter
+            if self.buffer.len() > keep {
+                let flush = self.buffer.len() - keep;
+                out.write_all(&self.buffer[..flush])?;
+                self.buffer.drain(..flush);
+                copied += flush as u64;
+            }
+            if self.fill()? == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    /// Returns the underlying reader, positioned somewhere after the bytes
+    /// consumed so far.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn fill(&mut self) -> io::Result<usize> {
+        let start = self.buffer.len();
+        self.buffer.resize(start + READ_SIZE, 0);
+        let read = loop {
+            match self.reader.read(&mut self.buffer[start..]) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+        self.buffer.truncate(start + *read.as_ref().unwrap_or(&0));
+        read
+    }
+
+    fn fill_to(&mut self, len: usize) -> io::Result<()> {
+        while self.buffer.len() < len {
+            if self.fill()? == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes one CRLF-terminated line out of the buffer, without the CRLF.
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(end) = find(&self.buffer, b"\r\n") {
+                let line = self.buffer[..end].to_vec();
+                self.buffer.drain(..end + 2);
+                return Ok(line);
+            }
+            if self.buffer.len() > MAX_HEADER_LINE {
+                return Err(invalid("part header line too long"));
+            }
+            if self.fill()? == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
+
+/// Extracts the boundary from a `multipart/form-data` content type.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::multipart::boundary;
+///
+/// assert_eq!(
+///     boundary("multipart/form-data; boundary=\"a b\"").as_deref(),
+///     Some("a b")
+/// );
+/// assert_eq!(boundary("text/plain; boundary=x"), None);
+/// ```
+pub fn boundary(content_type: &str) -> Option<String> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    parameters(params)
+        .into_iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty() && value.len() <= 70)
+}
+
+/// Splits `key=value; key="quoted; value"` pairs, skipping a leading token
+/// such as `form-data`.
+fn parameters(value: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut chars = value.chars().peekable();
+    loop {
+        // Skip to the start of a key
+        while chars.peek().is_some_and(|c| *c == ';' || c.is_whitespace()) {
+            chars.next();
+        }
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && *c != ';') {
+            key.push(c);
+        }
+        if chars.peek().is_none() && key.is_empty() {
+            return pairs;
+        }
+        if chars.next_if_eq(&'=').is_none() {
+            continue;
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            // Browsers percent-encode quotes rather than escaping them, and
+            // old ones send Windows paths, so backslashes are kept as is
+            for c in chars.by_ref() {
+                if c == '"' {
+                    break;
+                }
+                value.push(c);
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ';') {
+                value.push(c);
+            }
+        }
+        pairs.push((key.trim().to_string(), value.trim_end().to_string()));
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Yields one byte per read, to exercise boundaries split across reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((first, rest)) if !buf.is_empty() => {
+                    buf[0] = *first;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    const BODY: &str = "preamble\r\n\
+        --b0undary\r\n\
+        Content-Disposition: form-data; name=\"note\"\r\n\
+        \r\n\
+        skipped\r\n\
+        --b0undary  \r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"semi;colon %22q%22.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line one\r\n--b0undar\r\nline two\r\n\
+        --b0undary--\r\n\
+        epilogue";
+
+    #[test]
+    fn test_parts() {
+        for trickle in [false, true] {
+            let mut form: Multipart<Box<dyn Read>> = if trickle {
+                Multipart::new(Box::new(Trickle(BODY.as_bytes())), "b0undary")
+            } else {
+                Multipart::new(Box::new(BODY.as_bytes()), "b0undary")
+            };
+            let note = form.next_part().unwrap().unwrap();
+            assert_eq!(note.name.as_deref(), Some("note"));
+            assert_eq!(note.filename, None);
+
+            // The note's body is skipped
+            let file = form.next_part().unwrap().unwrap();
+            assert_eq!(file.filename.as_deref(), Some("semi;colon %22q%22.txt"));
+            assert_eq!(file.content_type.as_deref(), Some("text/plain"));
+            let mut contents = Vec::new();
+            assert_eq!(form.copy_body(&mut contents).unwrap(), 29);
+            assert_eq!(contents, b"line one\r\n--b0undar\r\nline two");
+            assert!(form.next_part().unwrap().is_none());
+            assert!(form.next_part().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_malformed() {
+        let truncated = "--b\r\nContent-Disposition: form-data; name=\"f\"\r\n\r\nno end";
+        let mut form = Multipart::new(truncated.as_bytes(), "b");
+        form.next_part().unwrap().unwrap();
+        let err = form.copy_body(&mut io::sink()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        let garbage = "--bx\r\n\r\n--b--";
+        let err = Multipart::new(garbage.as_bytes(), "b")
+            .next_part()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(
+            boundary("Multipart/Form-Data; charset=utf-8; boundary=abc").as_deref(),
+            Some("abc")
+        );
+    }
+}
@@ -0,0 +1,444 @@
+/*
+* Object store module
+*
+* A `FileSource` over the `object_store` crate, so the same frontend serves
+* from S3, GCS or Azure buckets (or anything else with an `ObjectStore`
+* implementation). Object stores are async; each call blocks the worker on a
+* small tokio runtime owned by the source.
+*
+* Buckets have no directories, only keys: a "directory" is any prefix that
+* has keys below it, the same view `list_with_delimiter` gives.
+*
+* Round trips to a bucket are slow compared to a disk, so metadata and small
+* objects are kept in a local cache for a short time. Larger objects are
+* streamed and never cached.
+*/
+
+use crate::config::ConfigError;
+use crate::error::ServerError;
+use crate::files::{is_within, DirEntry, FileMetadata, FileSource};
+use crate::flight::SingleFlight;
+use bytes::Bytes;
+use futures::stream::{BoxStream, StreamExt};
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectMeta, ObjectStore};
+use std::collections::HashMap;
+use std::io::{self, Cursor, Error, ErrorKind, Read};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DEFAULT_CACHE_CAPACITY: usize = 64 * 1024 * 1024;
+const DEFAULT_MAX_CACHED_OBJECT: usize = 1024 * 1024;
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Serves files from an object store bucket, optionally below a key prefix.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::{FileSource, FileTree};
+/// use file_shover::objectstore::ObjectStoreSource;
+/// use object_store::memory::InMemory;
+/// use object_store::{path::Path, ObjectStore};
+///
+/// let bucket = InMemory::new();
+/// futures::executor::block_on(bucket.put(&Path::from("site/index.html"), "<h1>Hi</h1>".into()))?;
+///
+/// let source = ObjectStoreSource::new(bucket, "site")?;
+/// assert_eq!(source.metadata("index.html")?.len, 11);
+/// let tree = FileTree::from_source(source);
+/// assert!(tree.get_reader("/index.html").is_ok());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct ObjectStoreSource {
+    store: Arc<dyn ObjectStore>,
+    prefix: ObjectPath,
+    runtime: Arc<tokio::runtime::Runtime>,
+    cache: Mutex<Cache>,
+    /// Objects being fetched into the cache right now.
+    flights: SingleFlight,
+}
+
+impl ObjectStoreSource {
+    /// Serves keys below `prefix` ("" for the whole bucket) from `store`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the async runtime cannot be started.
+    pub fn new(store: impl ObjectStore, prefix: &str) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("object-store")
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            store: Arc::new(store),
+            prefix: ObjectPath::from(prefix),
+            runtime: Arc::new(runtime),
+            cache: Mutex::new(Cache::new(
+                DEFAULT_CACHE_CAPACITY,
+                DEFAULT_MAX_CACHED_OBJECT,
+                DEFAULT_CACHE_TTL,
+            )),
+            flights: SingleFlight::default(),
+        })
+    }
+
+    /// Opens a store from a URL such as `s3://bucket/site`,
+    /// `gs://bucket/site` or `az://container/site`.
+    ///
+    /// Credentials and region come from the usual environment variables
+    /// (`AWS_ACCESS_KEY_ID`, `GOOGLE_SERVICE_ACCOUNT`, `AZURE_STORAGE_ACCOUNT_NAME`, ...).
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Config` for an unsupported or malformed URL and
+    /// `ServerError::Io` if the runtime cannot be started.
+    pub fn from_url(url: &str) -> Result<Self, ServerError> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            ConfigError::Invalid(format!("object store URL {}: {}", url, e))
+        };
+        let parsed = url::Url::parse(url).map_err(|e| invalid(&e))?;
+        // Unknown keys are ignored, so the whole environment can be passed
+        let options = std::env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value));
+        let (store, prefix) =
+            object_store::parse_url_opts(&parsed, options).map_err(|e| invalid(&e))?;
+        let mut source = Self::new(store, "")?;
+        source.prefix = prefix;
+        Ok(source)
+    }
+
+    /// Caches objects up to `max_object` bytes for `ttl`, keeping at most
+    /// `capacity` bytes in total. A capacity of 0 disables the cache.
+    pub fn cache(self, capacity: usize, max_object: usize, ttl: Duration) -> Self {
+        Self {
+            cache: Mutex::new(Cache::new(capacity, max_object, ttl)),
+            ..self
+        }
+    }
+
+    fn key(&self, path: &str) -> ObjectPath {
+        path.split('/')
+            .filter(|part| !part.is_empty())
+            .fold(self.prefix.clone(), |key, part| key.child(part))
+    }
+
+    fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    fn cached(&self, path: &str) -> Option<Cached> {
+        self.cache.lock().unwrap().get(path)
+    }
+
+    fn remember(&self, path: &str, metadata: FileMetadata, bytes: Option<Bytes>) {
+        self.cache.lock().unwrap().insert(path, metadata, bytes);
+    }
+
+    /// Whether any key lives below `path`.
+    fn is_prefix(&self, path: &str) -> Result<bool, Error> {
+        let key = self.key(path);
+        let listing = self.block_on(self.store.list_with_delimiter(Some(&key)))?;
+        Ok(!listing.objects.is_empty() || !listing.common_prefixes.is_empty())
+    }
+}
+
+impl FileSource for ObjectStoreSource {
+    fn open(&self, path: &str) -> Result<Box<dyn Read + Send>, Error> {
+        let cached_bytes = || match self.cached(path) {
+            Some(Cached {
+                bytes: Some(bytes), ..
+            }) => Some(bytes),
+            _ => None,
+        };
+        if let Some(bytes) = cached_bytes() {
+            return Ok(Box::new(Cursor::new(bytes)));
+        }
+
+        // A known small object is fetched once however many requests miss
+        // it together; the others wait and read it from the cache
+        let max_object = self.cache.lock().unwrap().max_object;
+        if self.cached(path).is_some_and(|cached| {
+            !cached.metadata.is_dir && cached.metadata.len as usize <= max_object
+        }) {
+            let bytes = self.flights.run(path, cached_bytes, || {
+                let result = self.block_on(self.store.get(&self.key(path)))?;
+                let metadata = file_metadata(&result.meta);
+                let bytes = self.block_on(result.bytes())?;
+                self.remember(path, metadata, Some(bytes.clone()));
+                Ok::<_, Error>(bytes)
+            })?;
+            return Ok(Box::new(Cursor::new(bytes)));
+        }
+
+        let result = self.block_on(self.store.get(&self.key(path)))?;
+        let metadata = file_metadata(&result.meta);
+        if metadata.len as usize <= self.cache.lock().unwrap().max_object {
+            let bytes = self.block_on(result.bytes())?;
+            self.remember(path, metadata, Some(bytes.clone()));
+            return Ok(Box::new(Cursor::new(bytes)));
+        }
+        Ok(Box::new(StreamReader {
+            stream: result.into_stream(),
+            current: Bytes::new(),
+            runtime: Arc::clone(&self.runtime),
+        }))
+    }
+
+    fn metadata(&self, path: &str) -> Result<FileMetadata, Error> {
+        if path.is_empty() {
+            return Ok(directory());
+        }
+        if let Some(cached) = self.cached(path) {
+            return Ok(cached.metadata);
+        }
+        let metadata = match self.block_on(self.store.head(&self.key(path))) {
+            Ok(meta) => file_metadata(&meta),
+            Err(object_store::Error::NotFound { .. }) if self.is_prefix(path)? => directory(),
+            Err(e) => return Err(e.into()),
+        };
+        self.remember(path, metadata.clone(), None);
+        Ok(metadata)
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<DirEntry>, Error> {
+        let key = self.key(path);
+        let listing = self.block_on(self.store.list_with_delimiter(Some(&key)))?;
+        if listing.objects.is_empty() && listing.common_prefixes.is_empty() && !path.is_empty() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        let dirs = listing.common_prefixes.iter().map(|prefix| DirEntry {
+            name: prefix.filename().unwrap_or_default().to_string(),
+            metadata: directory(),
+        });
+        let files = listing.objects.iter().map(|meta| DirEntry {
+            name: meta.location.filename().unwrap_or_default().to_string(),
+            metadata: file_metadata(meta),
+        });
+        let mut entries: Vec<DirEntry> = dirs.chain(files).collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn purge(&self, path: Option<&str>) -> usize {
+        self.cache.lock().unwrap().purge(path.unwrap_or(""))
+    }
+}
+
+fn file_metadata(meta: &ObjectMeta) -> FileMetadata {
+    FileMetadata {
+        len: meta.size,
+        is_dir: false,
+        modified: Some(meta.last_modified.into()),
+    }
+}
+
+fn directory() -> FileMetadata {
+    FileMetadata {
+        len: 0,
+        is_dir: true,
+        modified: None,
+    }
+}
+
+/// Reads an object's byte stream from blocking code.
+struct StreamReader {
+    stream: BoxStream<'static, object_store::Result<Bytes>>,
+    current: Bytes,
+    runtime: Arc<tokio::runtime::Runtime>,
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.runtime.block_on(self.stream.next()) {
+                Some(chunk) => self.current = chunk?,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.current.len());
+        buf[..n].copy_from_slice(&self.current.split_to(n));
+        Ok(n)
+    }
+}
+
+#[derive(Clone)]
+struct Cached {
+    metadata: FileMetadata,
+    bytes: Option<Bytes>,
+    fetched: Instant,
+}
+
+/// Recently fetched metadata and small objects, bounded by total size.
+struct Cache {
+    entries: HashMap<String, Cached>,
+    size: usize,
+    capacity: usize,
+    max_object: usize,
+    ttl: Duration,
+}
+
+impl Cache {
+    fn new(capacity: usize, max_object: usize, ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            size: 0,
+            capacity,
+            max_object: max_object.min(capacity),
+            ttl,
+        }
+    }
+
+    fn get(&mut self, path: &str) -> Option<Cached> {
+        match self.entries.get(path) {
+            Some(cached) if cached.fetched.elapsed() < self.ttl => Some(cached.clone()),
+            Some(_) => {
+                self.remove(path);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&mut self, path: &str, metadata: FileMetadata, bytes: Option<Bytes>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(path);
+        let added = bytes.as_ref().map_or(0, Bytes::len);
+        // Evict the oldest entries until the new one fits
+        while self.size + added > self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.fetched)
+                .map(|(path, _)| path.clone());
+            match oldest {
+                Some(oldest) => self.remove(&oldest),
+                None => break,
+            }
+        }
+        self.size += added;
+        self.entries.insert(
+            path.to_string(),
+            Cached {
+                metadata,
+                bytes,
+                fetched: Instant::now(),
+            },
+        );
+    }
+
+    fn remove(&mut self, path: &str) {
+        if let Some(cached) = self.entries.remove(path) {
+            self.size -= cached.bytes.as_ref().map_or(0, Bytes::len);
+        }
+    }
+
+    /// Removes `dir` and the entries below it; `""` empties the cache.
+    fn purge(&mut self, dir: &str) -> usize {
+        let purged: Vec<String> = self
+            .entries
+            .keys()
+            .filter(|path| is_within(path, dir))
+            .cloned()
+            .collect();
+        for path in &purged {
+            self.remove(path);
+        }
+        purged.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    fn bucket(files: &[(&str, &str)]) -> InMemory {
+        let store = InMemory::new();
+        for (key, contents) in files {
+            futures::executor::block_on(
+                store.put(&ObjectPath::from(*key), contents.to_string().into()),
+            )
+            .unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn test_directories_from_prefixes() {
+        let source = ObjectStoreSource::new(
+            bucket(&[
+                ("site/a/b.txt", "b"),
+                ("site/c.txt", "cc"),
+                ("other.txt", ""),
+            ]),
+            "site",
+        )
+        .unwrap();
+        assert!(source.metadata("a").unwrap().is_dir);
+        assert_eq!(source.metadata("c.txt").unwrap().len, 2);
+        assert_eq!(
+            source.metadata("missing").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        let names: Vec<_> = source
+            .list("")
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.name, entry.metadata.is_dir))
+            .collect();
+        assert_eq!(
+            names,
+            [("a".to_string(), true), ("c.txt".to_string(), false)]
+        );
+    }
+
+    #[test]
+    fn test_cache_and_streaming() {
+        let store: Arc<dyn ObjectStore> =
+            Arc::new(bucket(&[("small.txt", "tiny"), ("big.txt", "0123456789")]));
+        let source = ObjectStoreSource::new(Arc::clone(&store), "")
+            .unwrap()
+            .cache(100, 5, Duration::from_secs(60));
+
+        let mut content = String::new();
+        source
+            .open("small.txt")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "tiny");
+        // Served from the cache even after the object is gone
+        futures::executor::block_on(store.delete(&ObjectPath::from("small.txt"))).unwrap();
+        assert!(source.open("small.txt").is_ok());
+        // Until it is purged
+        assert_eq!(source.purge(Some("small.txt")), 1);
+        assert!(source.open("small.txt").is_err());
+
+        let mut content = String::new();
+        source
+            .open("big.txt")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "0123456789");
+        assert!(source.cached("big.txt").is_none());
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let mut cache = Cache::new(10, 10, Duration::from_secs(60));
+        let file = |len| FileMetadata {
+            len,
+            is_dir: false,
+            modified: None,
+        };
+        cache.insert("a", file(6), Some(Bytes::from_static(b"aaaaaa")));
+        cache.insert("b", file(6), Some(Bytes::from_static(b"bbbbbb")));
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+        assert_eq!(cache.size, 6);
+    }
+}
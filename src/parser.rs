@@ -0,0 +1,435 @@
+/*
+* Parser module
+*
+* Incremental request-head parser working on a byte buffer. The caller keeps
+* appending received bytes and calls `parse` again until it reports
+* `Progress::Complete`; nothing is consumed until then, so the parser holds
+* no state between calls and works equally for blocking readers and
+* event-driven frontends.
+*
+* Lines are sliced out of the buffer instead of being copied into Strings;
+* only the finished Request owns its method, target and headers. Limits on
+* request line length, header count and total head size are enforced while
+* bytes arrive, so an oversized request is refused before it is fully
+* buffered. A body announced larger than allowed is refused once the head is
+* complete, before any of it is read.
+*/
+
+use crate::message::{HttpMethod, Request, RequestError};
+use crate::pool;
+use crate::target::RequestTarget;
+use std::collections::HashMap;
+use std::io::{BufRead, ErrorKind};
+use std::str::FromStr;
+
+/// Size limits applied while parsing a request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Longest request line, without its line ending; longer ones are
+    /// refused with 414.
+    pub max_request_line: usize,
+    /// Most header fields accepted; more are refused with 431.
+    pub max_headers: usize,
+    /// Largest head (request line, headers and the blank line) in bytes;
+    /// larger ones are refused with 431.
+    pub max_head: usize,
+    /// Largest body announced with `Content-Length`; larger ones are refused
+    /// with 413. Unlimited if `None`, leaving it to the handlers (uploads
+    /// have their own limit).
+    pub max_body: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_request_line: 8 * 1024,
+            max_headers: 100,
+            max_head: 64 * 1024,
+            max_body: None,
+        }
+    }
+}
+
+/// Outcome of one [`parse`] call.
+#[derive(Debug)]
+pub enum Progress {
+    /// The head is complete; the second value is its length in bytes, where
+    /// the body (if any) starts.
+    Complete(Request, usize),
+    /// More bytes are needed.
+    Partial,
+}
+
+/// Parses a request head from the start of `buf`.
+///
+/// Both `\r\n` and bare `\n` line endings are accepted, as are empty lines
+/// before the request line.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::parser::{parse, Limits, Progress};
+///
+/// let raw = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\nbody";
+/// assert!(matches!(parse(&raw[..20], &Limits::default()), Ok(Progress::Partial)));
+///
+/// match parse(raw, &Limits::default()).unwrap() {
+///     Progress::Complete(request, length) => {
+///         assert_eq!(request.path(), "/index.html");
+///         assert_eq!(request.header("host"), Some("example.com"));
+///         assert_eq!(&raw[length..], b"body");
+///     }
+///     Progress::Partial => unreachable!(),
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Returns `InvalidFormat` for malformed input, `UriTooLong` if the request
+/// line exceeds the line limit and `HeadersTooLarge` if a header line, the
+/// header count or the whole head exceeds its limit.
+pub fn parse(buf: &[u8], limits: &Limits) -> Result<Progress, RequestError> {
+    let mut lines = Lines { buf, pos: 0 };
+
+    // Skip empty lines before the request line
+    let request_line = loop {
+        match lines.next(limits.max_request_line) {
+            Line::Complete(b"") => continue,
+            Line::Complete(line) => break line,
+            Line::Partial => return partial(buf, limits),
+            Line::TooLong => return Err(RequestError::UriTooLong),
+        }
+    };
+
+    let mut parts = request_line.split(|&byte| byte == b' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(RequestError::InvalidFormat);
+    };
+    let method = HttpMethod::from_str(text(method)?)?;
+    let target = text(target)?;
+    let version = text(version)?;
+    if target.is_empty() || !version.starts_with("HTTP/") {
+        return Err(RequestError::InvalidFormat);
+    }
+
+    let mut headers = HashMap::new();
+    loop {
+        let line = match lines.next(limits.max_head) {
+            Line::Complete(line) => line,
+            Line::Partial => return partial(buf, limits),
+            Line::TooLong => return Err(RequestError::HeadersTooLarge),
+        };
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == limits.max_headers {
+            return Err(RequestError::HeadersTooLarge);
+        }
+        let (name, value) = header(line)?;
+        add_header(&mut headers, name, value)?;
+    }
+    if lines.pos > limits.max_head {
+        return Err(RequestError::HeadersTooLarge);
+    }
+    // Two ways to frame the body would let another server see a different
+    // request boundary (RFC 9112 section 6.3)
+    let has = |wanted: &str| headers.keys().any(|name| name.eq_ignore_ascii_case(wanted));
+    if has("Content-Length") && has("Transfer-Encoding") {
+        return Err(RequestError::InvalidFormat);
+    }
+    let length = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, value)| value.parse::<u64>().ok());
+    if length
+        .zip(limits.max_body)
+        .is_some_and(|(length, max)| length > max)
+    {
+        return Err(RequestError::PayloadTooLarge);
+    }
+
+    let request = Request {
+        method,
+        target: RequestTarget::parse(target).map_err(RequestError::InvalidTarget)?,
+        http_version: version.to_string(),
+        headers,
+        client_ip: None,
+        client_cert: None,
+    };
+    Ok(Progress::Complete(request, lines.pos))
+}
+
+/// Reads a request head from `reader` with [`parse`], consuming exactly the
+/// head and leaving the body in the reader.
+///
+/// At most one read beyond the limits is buffered, so a request line or
+/// head that never ends is refused rather than held in memory.
+///
+/// # Errors
+///
+/// Returns the errors of [`parse`], I/O errors, and an `UnexpectedEof` I/O
+/// error if the stream ends before the head is complete.
+pub fn read_request<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, RequestError> {
+    let mut head = pool::take(0);
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            return Err(RequestError::Io(ErrorKind::UnexpectedEof.into()));
+        }
+        let start = head.len();
+        head.extend_from_slice(available);
+        let received = available.len();
+
+        match parse(&head, limits)? {
+            Progress::Complete(request, length) => {
+                reader.consume(length - start);
+                return Ok(request);
+            }
+            Progress::Partial => reader.consume(received),
+        }
+    }
+}
+
+/// Adds a header field, merging a repeated name (in any case) into the
+/// first one with `, `. Repeated `Content-Length` fields must agree.
+fn add_header(
+    headers: &mut HashMap<String, String>,
+    name: &str,
+    value: &str,
+) -> Result<(), RequestError> {
+    let Some(existing) = headers
+        .iter_mut()
+        .find(|(existing, _)| existing.eq_ignore_ascii_case(name))
+        .map(|(_, existing)| existing)
+    else {
+        headers.insert(name.to_string(), value.to_string());
+        return Ok(());
+    };
+    if name.eq_ignore_ascii_case("Content-Length") {
+        if existing != value {
+            return Err(RequestError::InvalidFormat);
+        }
+    } else {
+        existing.push_str(", ");
+        existing.push_str(value);
+    }
+    Ok(())
+}
+
+/// The head is incomplete; refuse it if it cannot fit the limits anyway.
+fn partial(buf: &[u8], limits: &Limits) -> Result<Progress, RequestError> {
+    if buf.len() > limits.max_head {
+        Err(RequestError::HeadersTooLarge)
+    } else {
+        Ok(Progress::Partial)
+    }
+}
+
+fn text(bytes: &[u8]) -> Result<&str, RequestError> {
+    std::str::from_utf8(bytes).map_err(|_| RequestError::InvalidFormat)
+}
+
+/// Splits `Name: value`, trimming optional whitespace around the value.
+fn header(line: &[u8]) -> Result<(&str, &str), RequestError> {
+    let colon = line
+        .iter()
+        .position(|&byte| byte == b':')
+        .ok_or(RequestError::InvalidFormat)?;
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+    // No whitespace in or around the name; this also rejects obsolete line folding
+    if name.is_empty() || !name.iter().all(|&byte| is_token(byte)) {
+        return Err(RequestError::InvalidFormat);
+    }
+    Ok((text(name)?, text(value)?.trim_matches([' ', '\t'])))
+}
+
+/// RFC 9110 `tchar`.
+pub(crate) fn is_token(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+enum Line<'a> {
+    Complete(&'a [u8]),
+    Partial,
+    TooLong,
+}
+
+/// Line cursor over the buffer.
+struct Lines<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn next(&mut self, max_line: usize) -> Line<'a> {
+        let rest = &self.buf[self.pos..];
+        match rest.iter().position(|&byte| byte == b'\n') {
+            Some(end) => {
+                let line = rest[..end].strip_suffix(b"\r").unwrap_or(&rest[..end]);
+                if line.len() > max_line {
+                    return Line::TooLong;
+                }
+                self.pos += end + 1;
+                Line::Complete(line)
+            }
+            // Allow for a pending '\r' before deciding the line is too long
+            None if rest.len() > max_line + 1 => Line::TooLong,
+            None => Line::Partial,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor, Read};
+
+    fn complete(raw: &[u8]) -> (Request, usize) {
+        match parse(raw, &Limits::default()).unwrap() {
+            Progress::Complete(request, length) => (request, length),
+            Progress::Partial => panic!("expected a complete head"),
+        }
+    }
+
+    #[test]
+    fn test_every_prefix_is_partial() {
+        let raw = b"GET /a?b=c HTTP/1.1\r\nHost: x\r\nAccept: */*\r\n\r\n";
+        for end in 0..raw.len() {
+            assert!(matches!(
+                parse(&raw[..end], &Limits::default()),
+                Ok(Progress::Partial)
+            ));
+        }
+        let (request, length) = complete(raw);
+        assert_eq!(length, raw.len());
+        assert_eq!(request.query(), Some("b=c"));
+        assert_eq!(request.header("accept"), Some("*/*"));
+    }
+
+    #[test]
+    fn test_lenient_line_endings() {
+        let (request, length) = complete(b"\r\nGET / HTTP/1.0\nHost:  x \n\nrest");
+        assert_eq!(request.http_version, "HTTP/1.0");
+        assert_eq!(request.header("Host"), Some("x"));
+        assert_eq!(length, 28);
+    }
+
+    #[test]
+    fn test_malformed() {
+        for raw in [
+            &b"GET /\r\n\r\n"[..],
+            b"GET / HTTP/1.1 extra\r\n\r\n",
+            b"GET / FTP/1.0\r\n\r\n",
+            b"GET / HTTP/1.1\r\nNo colon\r\n\r\n",
+            b"GET / HTTP/1.1\r\nName : value\r\n\r\n",
+            b"GET / HTTP/1.1\r\nA: b\r\n folded\r\n\r\n",
+            b"BREW / HTTP/1.1\r\n\r\n",
+            b"PUT / HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 50\r\n\r\n",
+            b"PUT / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n",
+            b"PUT / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n",
+        ] {
+            assert!(
+                matches!(
+                    parse(raw, &Limits::default()),
+                    Err(RequestError::InvalidFormat)
+                ),
+                "{:?}",
+                String::from_utf8_lossy(raw)
+            );
+        }
+    }
+
+    #[test]
+    fn test_repeated_headers() {
+        let raw = b"GET / HTTP/1.1\r\nAccept: text/html\r\naccept: */*\r\n\
+                    Content-Length: 0\r\ncontent-length: 0\r\n\r\n";
+        let Ok(Progress::Complete(request, _)) = parse(raw, &Limits::default()) else {
+            panic!("not parsed");
+        };
+        assert_eq!(request.header("Accept"), Some("text/html, */*"));
+        assert_eq!(request.header("Content-Length"), Some("0"));
+        assert_eq!(request.headers.len(), 2);
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_request_line: 32,
+            max_headers: 2,
+            max_head: 96,
+            max_body: Some(10),
+        };
+        let long_target = format!("GET /{} HTTP/1.1", "a".repeat(40));
+        assert!(matches!(
+            parse(long_target.as_bytes(), &limits),
+            Err(RequestError::UriTooLong)
+        ));
+
+        let long_header = format!("GET / HTTP/1.1\r\nX: {}", "a".repeat(100));
+        assert!(matches!(
+            parse(long_header.as_bytes(), &limits),
+            Err(RequestError::HeadersTooLarge)
+        ));
+
+        let many = b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert!(matches!(
+            parse(many, &limits),
+            Err(RequestError::HeadersTooLarge)
+        ));
+
+        let large = b"PUT /a HTTP/1.1\r\nContent-Length: 11\r\n\r\n";
+        assert!(matches!(
+            parse(large, &limits),
+            Err(RequestError::PayloadTooLarge)
+        ));
+        let small = b"PUT /a HTTP/1.1\r\ncontent-length: 10\r\n\r\n";
+        assert!(matches!(parse(small, &limits), Ok(Progress::Complete(..))));
+
+        let endless = format!("GET / HTTP/1.1\r\n{}", "A: 1\r\n".repeat(20));
+        let limits = Limits {
+            max_headers: 100,
+            ..limits
+        };
+        assert!(matches!(
+            parse(endless.as_bytes(), &limits),
+            Err(RequestError::HeadersTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_read_request_leaves_body() {
+        // A tiny buffer forces several partial parses
+        let raw = b"POST /upload HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let mut reader = BufReader::with_capacity(7, Cursor::new(&raw[..]));
+        let request = read_request(&mut reader, &Limits::default()).unwrap();
+        assert_eq!(request.method, HttpMethod::POST);
+
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello");
+    }
+
+    #[test]
+    fn test_endless_request_line() {
+        // A scanner sending a path that never ends is refused once the line
+        // passes the limit, with only that much of it buffered
+        let endless = Cursor::new(&b"GET /"[..]).chain(std::io::repeat(b'a'));
+        let mut reader = BufReader::with_capacity(1024, endless);
+        let limits = Limits::default();
+        assert!(matches!(
+            read_request(&mut reader, &limits),
+            Err(RequestError::UriTooLong)
+        ));
+    }
+
+    #[test]
+    fn test_read_request_eof() {
+        let mut reader = BufReader::new(Cursor::new(&b"GET / HTTP/1.1\r\n"[..]));
+        match read_request(&mut reader, &Limits::default()) {
+            Err(RequestError::Io(e)) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+            other => panic!("expected EOF, got {:?}", other),
+        }
+    }
+}
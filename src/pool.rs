@@ -0,0 +1,139 @@
+/*
+* Pool module
+*
+* Per-thread free lists of byte buffers. Every request used to allocate a
+* read buffer for the connection, a `Vec` for the request head, another for
+* the response head and a 64 KiB copy buffer for the body; on small files
+* that churn showed up ahead of the actual I/O. Each worker thread now keeps
+* the buffers it used last and hands them out again:
+*
+*   let mut buffer = pool::take(BUFFER_SIZE);
+*   let read = reader.read(&mut buffer)?;
+*   // dropped: back on this thread's list
+*
+* Buffers are thread-local, so taking and returning one needs no locking. A
+* buffer that grew past `MAX_CAPACITY` (a huge request head, say) is freed
+* instead of kept, and each thread keeps at most `MAX_POOLED`.
+*/
+
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+
+/// Buffers kept per thread.
+const MAX_POOLED: usize = 8;
+
+/// Largest capacity a returned buffer may have and still be kept.
+const MAX_CAPACITY: usize = 256 * 1024;
+
+thread_local! {
+    static FREE: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A byte buffer that goes back to its thread's pool when dropped.
+///
+/// Dereferences to the `Vec<u8>` it wraps.
+#[derive(Debug, Default)]
+pub struct Buffer {
+    bytes: Vec<u8>,
+}
+
+/// Takes a buffer of `len` bytes from this thread's pool, or allocates one.
+///
+/// Only newly grown bytes are zeroed: a reused buffer may still hold data
+/// from its last use, so read into it and use what was read.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::pool;
+///
+/// let mut buffer = pool::take(0);
+/// buffer.extend_from_slice(b"GET / HTTP/1.1\r\n");
+/// let capacity = buffer.capacity();
+/// drop(buffer);
+///
+/// // The same allocation comes back on this thread
+/// let buffer = pool::take(4);
+/// assert_eq!(buffer.len(), 4);
+/// assert_eq!(buffer.capacity(), capacity);
+/// ```
+pub fn take(len: usize) -> Buffer {
+    let mut bytes = FREE
+        .try_with(|free| free.borrow_mut().pop())
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    if bytes.len() >= len {
+        bytes.truncate(len);
+    } else {
+        bytes.resize(len, 0);
+    }
+    Buffer { bytes }
+}
+
+impl Deref for Buffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.bytes
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.bytes
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        let bytes = std::mem::take(&mut self.bytes);
+        if bytes.capacity() == 0 || bytes.capacity() > MAX_CAPACITY {
+            return;
+        }
+        // The thread may be exiting, in which case the buffer is just freed
+        let _ = FREE.try_with(|free| {
+            let mut free = free.borrow_mut();
+            if free.len() < MAX_POOLED {
+                free.push(bytes);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pooled() -> usize {
+        FREE.with(|free| free.borrow().len())
+    }
+
+    #[test]
+    fn test_reuse_and_limits() {
+        FREE.with(|free| free.borrow_mut().clear());
+        let buffers: Vec<Buffer> = (0..MAX_POOLED + 2).map(|_| take(16)).collect();
+        drop(buffers);
+        assert_eq!(pooled(), MAX_POOLED);
+
+        let buffer = take(1);
+        assert_eq!(pooled(), MAX_POOLED - 1);
+        assert_eq!(buffer.len(), 1);
+        drop(buffer);
+
+        // Oversized buffers are not kept
+        let mut buffer = take(0);
+        buffer.reserve(MAX_CAPACITY + 1);
+        drop(buffer);
+        assert_eq!(pooled(), MAX_POOLED - 1);
+    }
+
+    #[test]
+    fn test_growth_is_zeroed() {
+        let mut buffer = take(0);
+        buffer.extend_from_slice(&[7; 4]);
+        drop(buffer);
+        let buffer = take(8);
+        assert_eq!(&buffer[4..], &[0; 4]);
+    }
+}
@@ -0,0 +1,110 @@
+/*
+* Pre-warming module
+*
+* Right after a deploy every cache is cold: the first request for each file
+* reads it from disk or the bucket and, with checksums on, hashes it before
+* the response can start. Pre-warming crawls the tree at startup and does
+* that work ahead of the visitors:
+*
+*   walk the tree -> smallest files first, up to the byte budget
+*                 -> metadata (cached by sources that cache it)
+*                 -> SHA-256 digest (ETag cache), or a plain read
+*
+* Reading pulls the file into the object store's cache or the OS page cache.
+* Smallest first means the pages, stylesheets and scripts every visit needs
+* are warm before the large downloads that would exhaust the budget alone.
+*/
+
+use crate::checksum::ChecksumCache;
+use crate::files::FileTree;
+use std::io;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// What a pre-warming run covered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Prewarmed {
+    pub files: u64,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+/// Reads (and with `checksums`, hashes) files of `tree` until `budget` bytes
+/// were read. Files that fail are skipped.
+pub(crate) fn prewarm(
+    tree: &FileTree,
+    checksums: Option<&ChecksumCache>,
+    budget: u64,
+) -> io::Result<Prewarmed> {
+    let started = Instant::now();
+    let mut files = Vec::new();
+    collect(tree, "/", &mut files)?;
+    files.sort_unstable_by_key(|(_, len)| *len);
+
+    let mut warmed = Prewarmed::default();
+    for (path, len) in files {
+        if warmed.bytes + len > budget {
+            break;
+        }
+        match warm(tree, checksums, &path) {
+            Ok(()) => {
+                warmed.files += 1;
+                warmed.bytes += len;
+            }
+            Err(e) => debug!("Could not pre-warm {}: {}", path, e),
+        }
+    }
+    warmed.elapsed = started.elapsed();
+    Ok(warmed)
+}
+
+/// Collects the paths and sizes of the files below `dir`, skipping hidden ones.
+fn collect(tree: &FileTree, dir: &str, files: &mut Vec<(String, u64)>) -> io::Result<()> {
+    for entry in tree.list(dir)? {
+        if entry.name.starts_with('.') {
+            continue;
+        }
+        let path = format!("{}{}", dir, entry.name);
+        if entry.metadata.is_dir {
+            collect(tree, &format!("{}/", path), files)?;
+        } else {
+            files.push((path, entry.metadata.len));
+        }
+    }
+    Ok(())
+}
+
+fn warm(tree: &FileTree, checksums: Option<&ChecksumCache>, path: &str) -> io::Result<()> {
+    let metadata = tree.metadata(path)?;
+    match checksums {
+        Some(checksums) => {
+            checksums.sha256(tree, path, &metadata)?;
+        }
+        None => {
+            io::copy(&mut tree.get_reader(path)?.reader, &mut io::sink())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::MemorySource;
+
+    #[test]
+    fn test_smallest_first_within_budget() {
+        let tree = FileTree::from_source(
+            MemorySource::new()
+                .with("index.html", "<h1>Hi</h1>")
+                .with("css/site.css", "a {}")
+                .with("video.mp4", vec![0; 1000])
+                .with(".git/config", "[core]"),
+        );
+        let checksums = ChecksumCache::default();
+        let warmed = prewarm(&tree, Some(&checksums), 100).unwrap();
+        assert_eq!((warmed.files, warmed.bytes), (2, 15));
+        // The digests are ready for the first requests
+        assert_eq!(checksums.purge(None), 2);
+    }
+}
@@ -0,0 +1,167 @@
+/*
+* Trusted proxy module
+*
+* Behind a reverse proxy every connection comes from the proxy's address, so
+* the real client is taken from the `Forwarded` (RFC 7239) or
+* `X-Forwarded-For` header instead — but only when the peer is listed in
+* `--trusted-proxies`, since anyone can send those headers.
+*
+* The forwarding chain is walked from the nearest hop outwards, skipping
+* addresses that are themselves trusted proxies; the first untrusted address
+* is the client. `Forwarded` takes precedence over `X-Forwarded-For`.
+*/
+
+use crate::ipfilter::Cidr;
+use std::net::{IpAddr, SocketAddr};
+
+/// The set of proxies whose forwarding headers are believed.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::proxy::TrustedProxies;
+/// use std::net::IpAddr;
+///
+/// let proxies = TrustedProxies::new(vec!["10.0.0.0/8".parse().unwrap()]);
+/// let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+/// let stranger: IpAddr = "198.51.100.7".parse().unwrap();
+///
+/// let header = Some("203.0.113.9, 10.0.0.3");
+/// assert_eq!(proxies.client_ip(proxy, None, header), "203.0.113.9".parse::<IpAddr>().unwrap());
+/// // Headers from untrusted peers are ignored
+/// assert_eq!(proxies.client_ip(stranger, None, header), stranger);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    proxies: Vec<Cidr>,
+}
+
+impl TrustedProxies {
+    /// Creates the set from CIDR blocks.
+    pub fn new(proxies: Vec<Cidr>) -> Self {
+        Self { proxies }
+    }
+
+    /// Returns true if no proxy is trusted.
+    pub fn is_empty(&self) -> bool {
+        self.proxies.is_empty()
+    }
+
+    /// Returns whether `ip` belongs to a trusted proxy.
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.proxies.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /// Determines the client address of a request received from `peer`.
+    ///
+    /// `forwarded` and `x_forwarded_for` are the raw header values, if any.
+    /// Unparseable entries (e.g. `for=unknown` or obfuscated identifiers) end
+    /// the walk, leaving the last address that could be resolved.
+    pub fn client_ip(
+        &self,
+        peer: IpAddr,
+        forwarded: Option<&str>,
+        x_forwarded_for: Option<&str>,
+    ) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+        let hops: Vec<&str> = match (forwarded, x_forwarded_for) {
+            (Some(header), _) => header.split(',').filter_map(forwarded_for).collect(),
+            (None, Some(header)) => header.split(',').collect(),
+            (None, None) => return peer,
+        };
+
+        let mut client = peer;
+        for hop in hops.iter().rev() {
+            match parse_node(hop) {
+                Some(ip) => {
+                    client = ip;
+                    if !self.is_trusted(ip) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        client
+    }
+}
+
+/// Extracts the `for=` parameter of one `Forwarded` element.
+fn forwarded_for(element: &str) -> Option<&str> {
+    element.split(';').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        key.trim().eq_ignore_ascii_case("for").then_some(value)
+    })
+}
+
+/// Parses a node such as `192.0.2.1`, `192.0.2.1:80`, `"[2001:db8::1]:443"` or `2001:db8::1`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::new(vec!["10.0.0.0/8".parse().unwrap()])
+    }
+
+    #[test]
+    fn test_parse_node() {
+        assert_eq!(parse_node(" 192.0.2.1"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("192.0.2.1:8080"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("\"[2001:db8::1]:443\""), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("\"[2001:db8::1]\""), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+
+    #[test]
+    fn test_skips_trusted_hops() {
+        let client = proxies().client_ip(
+            ip("10.0.0.1"),
+            None,
+            Some("198.51.100.1, 203.0.113.5, 10.1.1.1"),
+        );
+        // 198.51.100.1 was supplied by the client itself and is not trusted
+        assert_eq!(client, ip("203.0.113.5"));
+    }
+
+    #[test]
+    fn test_forwarded_takes_precedence() {
+        let client = proxies().client_ip(
+            ip("10.0.0.1"),
+            Some("for=192.0.2.60;proto=https, For=\"[2001:db8:cafe::17]:4711\""),
+            Some("198.51.100.1"),
+        );
+        assert_eq!(client, ip("2001:db8:cafe::17"));
+    }
+
+    #[test]
+    fn test_unknown_hop_stops_walk() {
+        let client = proxies().client_ip(ip("10.0.0.1"), Some("for=unknown"), None);
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_no_header() {
+        assert_eq!(
+            proxies().client_ip(ip("10.0.0.1"), None, None),
+            ip("10.0.0.1")
+        );
+    }
+}
@@ -0,0 +1,162 @@
+/*
+* Quota module
+*
+* Download quotas: each client IP may receive a fixed number of body bytes
+* per time window, for public mirrors where a few clients could otherwise
+* pull terabytes. A client's window starts with its first counted response
+* and resets once it has elapsed.
+*
+* The quota is checked before a request is answered and charged after the
+* response was written, with the bytes actually sent. A download that starts
+* under the quota therefore finishes; the next request gets 429 with
+* `Retry-After` pointing at the end of the window.
+*/
+
+use crate::message::{Request, Response, StatusCode, DEFAULT_TOO_MANY_REQUESTS_BODY};
+use crate::middleware::Middleware;
+use crate::units::retry_after_secs;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Number of tracked clients above which expired windows are pruned.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug)]
+struct Usage {
+    window_start: Instant,
+    bytes: u64,
+}
+
+/// Bytes each client may download per window.
+///
+/// Clones share the per-client counters.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::quota::DownloadQuota;
+/// use std::net::{IpAddr, Ipv4Addr};
+/// use std::time::Duration;
+///
+/// let quota = DownloadQuota::new(1000, Duration::from_secs(3600));
+/// let client = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+///
+/// assert!(quota.check(client).is_ok());
+/// quota.record(client, 1500);
+/// // Over quota: the error tells the client when the window ends
+/// assert!(quota.check(client).unwrap_err() <= Duration::from_secs(3600));
+/// ```
+#[derive(Debug, Clone)]
+pub struct DownloadQuota {
+    limit: u64,
+    window: Duration,
+    clients: Arc<Mutex<HashMap<IpAddr, Usage>>>,
+}
+
+impl DownloadQuota {
+    /// Allows `limit` bytes per client in every `window`.
+    pub fn new(limit: u64, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            clients: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Checks whether `ip` may start another download.
+    ///
+    /// # Errors
+    ///
+    /// Returns the time until the client's window resets once its quota is
+    /// used up.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    /// Charges `bytes` sent to `ip`.
+    pub fn record(&self, ip: IpAddr, bytes: u64) {
+        self.record_at(ip, bytes, Instant::now());
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        match clients.get(&ip) {
+            Some(usage) if usage.bytes >= self.limit => {
+                let ends = usage.window_start + self.window;
+                match ends.checked_duration_since(now) {
+                    Some(wait) if !wait.is_zero() => Err(wait),
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn record_at(&self, ip: IpAddr, bytes: u64, now: Instant) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if clients.len() >= PRUNE_THRESHOLD && !clients.contains_key(&ip) {
+            clients
+                .retain(|_, usage| now.saturating_duration_since(usage.window_start) < self.window);
+        }
+        let usage = clients.entry(ip).or_insert(Usage {
+            window_start: now,
+            bytes: 0,
+        });
+        if now.saturating_duration_since(usage.window_start) >= self.window {
+            *usage = Usage {
+                window_start: now,
+                bytes: 0,
+            };
+        }
+        usage.bytes = usage.bytes.saturating_add(bytes);
+    }
+}
+
+impl Middleware for DownloadQuota {
+    fn before(&self, req: &Request) -> Option<Response> {
+        let client_ip = req.client_ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let wait = self.check(client_ip).err()?;
+        warn!("Download quota exceeded for {}", client_ip);
+        let retry_after = retry_after_secs(wait);
+        Some(
+            Response::error(
+                StatusCode::TOO_MANY_REQUESTS,
+                DEFAULT_TOO_MANY_REQUESTS_BODY,
+            )
+            .header("Retry-After", retry_after.to_string()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[test]
+    fn test_window_resets() {
+        let quota = DownloadQuota::new(100, Duration::from_secs(60));
+        let start = Instant::now();
+
+        quota.record_at(ip(1), 60, start);
+        assert!(quota.check_at(ip(1), start).is_ok());
+        quota.record_at(ip(1), 60, start + Duration::from_secs(10));
+        assert_eq!(
+            quota.check_at(ip(1), start + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+        // Other clients are unaffected
+        assert!(quota.check_at(ip(2), start).is_ok());
+
+        let later = start + Duration::from_secs(60);
+        assert!(quota.check_at(ip(1), later).is_ok());
+        quota.record_at(ip(1), 10, later);
+        assert!(quota.check_at(ip(1), later).is_ok());
+    }
+}
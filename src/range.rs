@@ -0,0 +1,131 @@
+/*
+* Byte range module
+*
+* Resolves a `Range` header against the length of a file, so interrupted
+* downloads can resume and media players can seek:
+*
+*   Range: bytes=0-499      -> 206, Content-Range: bytes 0-499/1000
+*   Range: bytes=500-       -> 206, Content-Range: bytes 500-999/1000
+*   Range: bytes=-200       -> 206, Content-Range: bytes 800-999/1000
+*   Range: bytes=2000-      -> 416, with the length in Content-Range
+*
+* Only single ranges are served. Several ranges would need a
+* `multipart/byteranges` body, so such requests get the whole file (which
+* RFC 9110 allows), unless none of the ranges overlaps it. Malformed headers
+* and other units are ignored the same way.
+*/
+
+/// What a `Range` header asks of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ByteRange {
+    /// No usable range: serve the whole file.
+    Full,
+    /// Serve bytes `start..=end`.
+    Partial { start: u64, end: u64 },
+    /// No requested range overlaps the file.
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Resolves the value of a `Range` header for a file of `len` bytes.
+    pub(crate) fn resolve(header: &str, len: u64) -> Self {
+        let Some(specs) = header.trim().strip_prefix("bytes=") else {
+            return ByteRange::Full;
+        };
+        let mut satisfiable = Vec::new();
+        for spec in specs.split(',') {
+            match Self::spec(spec.trim(), len) {
+                Some(Some(range)) => satisfiable.push(range),
+                Some(None) => {}
+                None => return ByteRange::Full,
+            }
+        }
+        match satisfiable.as_slice() {
+            [] => ByteRange::Unsatisfiable,
+            [range] => *range,
+            _ => ByteRange::Full,
+        }
+    }
+
+    /// Parses one `first-last` or `-suffix` spec: `None` if malformed,
+    /// `Some(None)` if it lies outside the file.
+    fn spec(spec: &str, len: u64) -> Option<Option<ByteRange>> {
+        let (first, last) = spec.split_once('-')?;
+        let number = |digits: &str| {
+            let valid = !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit());
+            valid.then(|| digits.parse::<u64>().ok()).flatten()
+        };
+        if first.is_empty() {
+            let suffix = number(last)?;
+            return Some((suffix > 0 && len > 0).then(|| ByteRange::Partial {
+                start: len - suffix.min(len),
+                end: len - 1,
+            }));
+        }
+        let start = number(first)?;
+        let end = match last {
+            "" => u64::MAX,
+            last => number(last)?,
+        };
+        if end < start {
+            return None;
+        }
+        Some((start < len).then(|| ByteRange::Partial {
+            start,
+            end: end.min(len - 1),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_ranges() {
+        let partial = |start, end| ByteRange::Partial { start, end };
+        assert_eq!(ByteRange::resolve("bytes=0-499", 1000), partial(0, 499));
+        assert_eq!(ByteRange::resolve("bytes=500-", 1000), partial(500, 999));
+        assert_eq!(
+            ByteRange::resolve("bytes=900-5000", 1000),
+            partial(900, 999)
+        );
+        assert_eq!(ByteRange::resolve("bytes=-200", 1000), partial(800, 999));
+        assert_eq!(ByteRange::resolve("bytes=-5000", 1000), partial(0, 999));
+        assert_eq!(
+            ByteRange::resolve("bytes=1000-", 1000),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            ByteRange::resolve("bytes=-0", 1000),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(ByteRange::resolve("bytes=0-", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn test_ignored_ranges() {
+        for header in [
+            "items=0-1",
+            "bytes=5-1",
+            "bytes=a-b",
+            "bytes=+1-2",
+            "bytes=0-1,4-5",
+        ] {
+            assert_eq!(
+                ByteRange::resolve(header, 1000),
+                ByteRange::Full,
+                "{}",
+                header
+            );
+        }
+        assert_eq!(
+            ByteRange::resolve("bytes=2000-2100, 0-9", 1000),
+            ByteRange::Partial { start: 0, end: 9 }
+        );
+        assert_eq!(
+            ByteRange::resolve("bytes=2000-2100, 3000-", 1000),
+            ByteRange::Unsatisfiable
+        );
+    }
+}
@@ -0,0 +1,255 @@
+/*
+* Rate limiting module
+*
+* Token-bucket rate limiting keyed by client IP, with an optional global bucket
+* shared by every client. Each bucket refills continuously at `rate` tokens per
+* second up to `burst` tokens; a request consumes one token.
+*
+* Buckets for idle clients are pruned once the map grows large, so a scan from
+* many addresses cannot grow memory without bound.
+*/
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Number of tracked clients above which fully refilled buckets are pruned.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Rate and burst size of a token bucket.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::ratelimit::Quota;
+///
+/// // 5 requests per second with bursts of up to 20 requests
+/// let quota = Quota::new(5.0, 20);
+/// assert_eq!(quota.burst, 20);
+///
+/// // Without an explicit burst, one second worth of requests is allowed at once
+/// assert_eq!(Quota::new(2.5, 0).burst, 3);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+impl Quota {
+    /// Creates a quota of `rate` requests per second with `burst` capacity.
+    ///
+    /// A `burst` of 0 defaults to the rate rounded up (at least 1).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not a positive, finite number.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        assert!(
+            rate.is_finite() && rate > 0.0,
+            "rate must be positive and finite, got {}",
+            rate
+        );
+        let burst = if burst == 0 {
+            (rate.ceil() as u32).max(1)
+        } else {
+            burst
+        };
+        Self { rate, burst }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    quota: Quota,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(quota: Quota, now: Instant) -> Self {
+        Self {
+            quota,
+            tokens: quota.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.quota.rate).min(self.quota.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// Time until one token is available, or `None` if one is available now.
+    fn wait_time(&mut self, now: Instant) -> Option<Duration> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            return None;
+        }
+        // A rate too small for the wait to fit in a `Duration` never refills
+        let wait = (1.0 - self.tokens) / self.quota.rate;
+        Some(Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX))
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.quota.burst as f64
+    }
+}
+
+/// Per-IP and global token-bucket rate limiter.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::ratelimit::{Quota, RateLimiter};
+/// use std::net::{IpAddr, Ipv4Addr};
+///
+/// let limiter = RateLimiter::new(Some(Quota::new(1.0, 2)), None);
+/// let client = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+///
+/// assert!(limiter.check(client).is_ok());
+/// assert!(limiter.check(client).is_ok());
+/// // Burst exhausted: the error tells the client how long to wait
+/// assert!(limiter.check(client).is_err());
+/// ```
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_ip: Option<Quota>,
+    clients: Mutex<HashMap<IpAddr, TokenBucket>>,
+    global: Option<Mutex<TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter.
+    ///
+    /// # Arguments
+    ///
+    /// * `per_ip` - Quota applied to each client address individually
+    /// * `global` - Quota shared by all clients
+    pub fn new(per_ip: Option<Quota>, global: Option<Quota>) -> Self {
+        let now = Instant::now();
+        Self {
+            per_ip,
+            clients: Mutex::new(HashMap::new()),
+            global: global.map(|quota| Mutex::new(TokenBucket::new(quota, now))),
+        }
+    }
+
+    /// Consumes one token for `ip`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the time after which the client may retry when either the
+    /// client's bucket or the global bucket is empty. No token is consumed from
+    /// either bucket in that case.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        let mut global = self
+            .global
+            .as_ref()
+            .map(|bucket| bucket.lock().unwrap_or_else(|e| e.into_inner()));
+
+        if let Some(quota) = self.per_ip {
+            if clients.len() >= PRUNE_THRESHOLD && !clients.contains_key(&ip) {
+                clients.retain(|_, bucket| !bucket.is_full(now));
+            }
+            let bucket = clients
+                .entry(ip)
+                .or_insert_with(|| TokenBucket::new(quota, now));
+            if let Some(wait) = bucket.wait_time(now) {
+                return Err(wait);
+            }
+        }
+
+        if let Some(global) = global.as_mut() {
+            if let Some(wait) = global.wait_time(now) {
+                return Err(wait);
+            }
+            global.tokens -= 1.0;
+        }
+
+        if let Some(bucket) = clients.get_mut(&ip) {
+            bucket.tokens -= 1.0;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[test]
+    fn test_burst_then_refill() {
+        let limiter = RateLimiter::new(Some(Quota::new(2.0, 3)), None);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at(ip(1), start).is_ok());
+        }
+        let wait = limiter.check_at(ip(1), start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+
+        // Half a second later one token has been refilled
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check_at(ip(1), later).is_ok());
+        assert!(limiter.check_at(ip(1), later).is_err());
+    }
+
+    #[test]
+    fn test_tiny_rate() {
+        let limiter = RateLimiter::new(Some(Quota::new(1e-300, 1)), None);
+        let now = Instant::now();
+        assert!(limiter.check_at(ip(1), now).is_ok());
+        assert_eq!(limiter.check_at(ip(1), now).unwrap_err(), Duration::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "rate must be positive")]
+    fn test_invalid_rate() {
+        Quota::new(f64::NAN, 1);
+    }
+
+    #[test]
+    fn test_clients_are_independent() {
+        let limiter = RateLimiter::new(Some(Quota::new(1.0, 1)), None);
+        let now = Instant::now();
+        assert!(limiter.check_at(ip(1), now).is_ok());
+        assert!(limiter.check_at(ip(1), now).is_err());
+        assert!(limiter.check_at(ip(2), now).is_ok());
+    }
+
+    #[test]
+    fn test_global_limit_applies_to_all_clients() {
+        let limiter = RateLimiter::new(None, Some(Quota::new(1.0, 2)));
+        let now = Instant::now();
+        assert!(limiter.check_at(ip(1), now).is_ok());
+        assert!(limiter.check_at(ip(2), now).is_ok());
+        assert!(limiter.check_at(ip(3), now).is_err());
+    }
+
+    #[test]
+    fn test_rejected_request_does_not_consume_global_token() {
+        let limiter = RateLimiter::new(Some(Quota::new(1.0, 1)), Some(Quota::new(1.0, 2)));
+        let now = Instant::now();
+        assert!(limiter.check_at(ip(1), now).is_ok());
+        // Client 1 is over its own quota; the global bucket must be untouched
+        assert!(limiter.check_at(ip(1), now).is_err());
+        assert!(limiter.check_at(ip(2), now).is_ok());
+    }
+}
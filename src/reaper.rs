@@ -0,0 +1,483 @@
+/*
+* Connection reaper module
+*
+* A worker serving a connection blocks on the client: a client that opens a
+* connection and then sends nothing (or stops reading its response) holds a
+* worker and a descriptor for as long as it likes. The reaper closes such
+* connections from a background thread:
+*
+*   idle timeout  -> a read or write on the client made no progress this long
+*   max lifetime  -> the connection has been open this long, busy or not
+*   min read rate -> the client sent less than this per second over a window
+*                    of time spent reading from it
+*
+* Time a handler spends computing does not count as idle; only waiting on
+* the client does. The read rate catches clients that drip-feed a request a
+* byte at a time, never idle long enough to be closed; it is averaged over
+* the whole window, so a slow link that briefly stalls is left alone. Closing is a `shutdown()` of the socket, which fails the
+* worker's blocked read or write so it finishes the connection normally.
+*/
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Shortest and longest pause between two sweeps.
+const MIN_SWEEP: Duration = Duration::from_millis(50);
+const MAX_SWEEP: Duration = Duration::from_secs(1);
+
+/// Closes connections that are idle, too slow or open for too long.
+///
+/// # Examples
+///
+/// ```no_run
+/// use file_shover::reaper::Reaper;
+/// use file_shover::Server;
+/// use std::time::Duration;
+///
+/// let server = Server::builder()
+///     .root("public")
+///     .reaper(
+///         Reaper::new()
+///             .idle_timeout(Duration::from_secs(30))
+///             .max_lifetime(Duration::from_secs(600))
+///             .min_read_rate(1024, Duration::from_secs(30)),
+///     )
+///     .build()?;
+/// # Ok::<(), file_shover::error::ServerError>(())
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Reaper {
+    idle_timeout: Option<Duration>,
+    max_lifetime: Option<Duration>,
+    min_read_rate: Option<(u64, Duration)>,
+}
+
+impl Reaper {
+    /// A reaper that closes nothing until a limit is set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Closes connections whose client sent or accepted nothing for `timeout`.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Closes connections open for longer than `lifetime`.
+    pub fn max_lifetime(mut self, lifetime: Duration) -> Self {
+        self.max_lifetime = Some(lifetime);
+        self
+    }
+
+    /// Closes connections whose client sent fewer than `bytes_per_sec` on
+    /// average over `window` of waiting to read from it.
+    pub fn min_read_rate(mut self, bytes_per_sec: u64, window: Duration) -> Self {
+        self.min_read_rate = Some((bytes_per_sec, window));
+        self
+    }
+
+    /// Starts the sweeping thread; it stops when the returned registry is
+    /// dropped.
+    pub(crate) fn start(self) -> Arc<Reaping> {
+        let reaping = Arc::new(Reaping {
+            open: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        });
+        if self.idle_timeout.is_none()
+            && self.max_lifetime.is_none()
+            && self.min_read_rate.is_none()
+        {
+            return reaping;
+        }
+        let weak = Arc::downgrade(&reaping);
+        let spawned = std::thread::Builder::new()
+            .name("connection-reaper".to_string())
+            .spawn(move || self.run(&weak));
+        if let Err(e) = spawned {
+            warn!("Could not start the connection reaper: {}", e);
+        }
+        reaping
+    }
+
+    fn run(&self, reaping: &Weak<Reaping>) {
+        let window = self.min_read_rate.map(|(_, window)| window);
+        let interval = [self.idle_timeout, self.max_lifetime, window]
+            .into_iter()
+            .flatten()
+            .min()
+            .map_or(MAX_SWEEP, |shortest| {
+                (shortest / 4).clamp(MIN_SWEEP, MAX_SWEEP)
+            });
+        loop {
+            std::thread::sleep(interval);
+            let Some(reaping) = reaping.upgrade() else {
+                return;
+            };
+            reaping.sweep(self);
+        }
+    }
+}
+
+/// The connections being served, for the reaper to check.
+#[derive(Debug)]
+pub(crate) struct Reaping {
+    open: Mutex<HashMap<u64, Open>>,
+    next_id: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Open {
+    stream: TcpStream,
+    activity: Arc<Activity>,
+    /// Time spent reading and bytes read when the current read rate window
+    /// started.
+    window_start: (Duration, u64),
+}
+
+impl Reaping {
+    /// Registers `stream` until the returned guard is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the socket cannot be duplicated.
+    pub(crate) fn track(self: &Arc<Self>, stream: &TcpStream) -> io::Result<Tracked> {
+        let activity = Arc::new(Activity::new());
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.open.lock().unwrap_or_else(|e| e.into_inner()).insert(
+            id,
+            Open {
+                stream: stream.try_clone()?,
+                activity: Arc::clone(&activity),
+                window_start: (Duration::ZERO, 0),
+            },
+        );
+        Ok(Tracked {
+            id,
+            reaping: Arc::clone(self),
+            activity,
+        })
+    }
+
+    /// Closes and forgets every connection past a limit.
+    fn sweep(&self, limits: &Reaper) {
+        let now = Instant::now();
+        self.open
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|_, open| {
+                let reason = if limits
+                    .max_lifetime
+                    .is_some_and(|max| now.duration_since(open.activity.opened) > max)
+                {
+                    "open too long"
+                } else if limits
+                    .idle_timeout
+                    .is_some_and(|max| open.activity.idle(now).is_some_and(|idle| idle > max))
+                {
+                    "idle"
+                } else if limits
+                    .min_read_rate
+                    .is_some_and(|(rate, window)| open.reads_too_slowly(now, rate, window))
+                {
+                    "reading too slowly"
+                } else {
+                    return true;
+                };
+                debug!(
+                    "Closing connection from {}: {}",
+                    open.stream
+                        .peer_addr()
+                        .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string()),
+                    reason
+                );
+                if let Err(e) = open.stream.shutdown(Shutdown::Both) {
+                    debug!("Failed to shutdown stream: {}", e);
+                }
+                false
+            });
+    }
+
+    /// Number of connections being tracked.
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.open.lock().unwrap().len()
+    }
+}
+
+impl Open {
+    /// Whether the client sent fewer than `rate` bytes per second over the
+    /// last `window` of reading; starts a new window once one is complete.
+    fn reads_too_slowly(&mut self, now: Instant, rate: u64, window: Duration) -> bool {
+        let (reading, read) = (self.activity.reading(now), self.activity.read_bytes());
+        let (started, read_before) = self.window_start;
+        let elapsed = reading.saturating_sub(started);
+        if elapsed < window {
+            return false;
+        }
+        self.window_start = (reading, read);
+        ((read - read_before) as f64) < rate as f64 * elapsed.as_secs_f64()
+    }
+}
+
+/// A tracked connection; dropping it stops the tracking.
+#[derive(Debug)]
+pub(crate) struct Tracked {
+    id: u64,
+    reaping: Arc<Reaping>,
+    activity: Arc<Activity>,
+}
+
+impl Tracked {
+    /// Wraps `stream` so reads and writes on it count as activity.
+    pub(crate) fn watch<'a>(&'a self, stream: &'a TcpStream) -> Watched<'a> {
+        Watched {
+            stream,
+            activity: Some(&self.activity),
+        }
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.reaping
+            .open
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+/// When a connection last made progress, and whether it is waiting on the
+/// client right now.
+#[derive(Debug)]
+struct Activity {
+    opened: Instant,
+    /// Milliseconds from `opened` to the last progress.
+    last: AtomicU64,
+    waiting: AtomicBool,
+    /// Whether the wait is for a read.
+    reading: AtomicBool,
+    /// Microseconds spent in reads that returned, and the bytes they read.
+    read_micros: AtomicU64,
+    read_bytes: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            opened: Instant::now(),
+            last: AtomicU64::new(0),
+            waiting: AtomicBool::new(false),
+            reading: AtomicBool::new(false),
+            read_micros: AtomicU64::new(0),
+            read_bytes: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self, waiting: bool) {
+        let elapsed = self.opened.elapsed().as_millis() as u64;
+        self.last.store(elapsed, Ordering::Relaxed);
+        self.waiting.store(waiting, Ordering::Relaxed);
+    }
+
+    /// How long the connection has waited on the client, if it is waiting.
+    fn idle(&self, now: Instant) -> Option<Duration> {
+        if !self.waiting.load(Ordering::Relaxed) {
+            return None;
+        }
+        let last = self.opened + Duration::from_millis(self.last.load(Ordering::Relaxed));
+        Some(now.saturating_duration_since(last))
+    }
+
+    /// Records a read of `bytes` that took `took`.
+    fn record_read(&self, bytes: usize, took: Duration) {
+        self.read_micros
+            .fetch_add(took.as_micros() as u64, Ordering::Relaxed);
+        self.read_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Total time spent reading from the client, including a read still
+    /// waiting.
+    fn reading(&self, now: Instant) -> Duration {
+        let done = Duration::from_micros(self.read_micros.load(Ordering::Relaxed));
+        let waiting = if self.reading.load(Ordering::Relaxed) {
+            self.idle(now).unwrap_or_default()
+        } else {
+            Duration::ZERO
+        };
+        done + waiting
+    }
+
+    fn read_bytes(&self) -> u64 {
+        self.read_bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// A client socket whose reads and writes are reported to the reaper.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Watched<'a> {
+    stream: &'a TcpStream,
+    activity: Option<&'a Activity>,
+}
+
+impl<'a> Watched<'a> {
+    /// A socket nobody watches.
+    pub(crate) fn unwatched(stream: &'a TcpStream) -> Self {
+        Self {
+            stream,
+            activity: None,
+        }
+    }
+
+    fn around<T>(&mut self, io: impl FnOnce(&mut &'a TcpStream) -> T) -> T {
+        if let Some(activity) = self.activity {
+            activity.touch(true);
+        }
+        let result = io(&mut self.stream);
+        if let Some(activity) = self.activity {
+            activity.touch(false);
+        }
+        result
+    }
+}
+
+impl Read for Watched<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(activity) = self.activity else {
+            return self.stream.read(buf);
+        };
+        let started = Instant::now();
+        activity.reading.store(true, Ordering::Relaxed);
+        let result = self.around(|stream| stream.read(buf));
+        activity.reading.store(false, Ordering::Relaxed);
+        activity.record_read(*result.as_ref().unwrap_or(&0), started.elapsed());
+        result
+    }
+}
+
+impl Write for Watched<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.around(|stream| stream.write(buf))
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        self.around(|stream| stream.write_vectored(bufs))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.around(|stream| stream.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (listener.accept().unwrap().0, client)
+    }
+
+    #[test]
+    fn test_idle_connections_are_closed() {
+        let reaping = Reaper::new()
+            .idle_timeout(Duration::from_millis(200))
+            .start();
+        let (server, mut client) = pair();
+        let tracked = reaping.track(&server).unwrap();
+        client.write_all(b"GET").unwrap();
+
+        let started = Instant::now();
+        let mut watched = tracked.watch(&server);
+        let mut buf = [0; 16];
+        assert_eq!(watched.read(&mut buf).unwrap(), 3);
+        // Stalls after the first bytes, until the reaper steps in
+        assert_eq!(watched.read(&mut buf).unwrap_or(0), 0);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(reaping.len(), 0);
+    }
+
+    #[test]
+    fn test_busy_connections_live_until_max_lifetime() {
+        let reaping = Reaper::new()
+            .idle_timeout(Duration::from_millis(200))
+            .max_lifetime(Duration::from_millis(600))
+            .start();
+        let (server, mut client) = pair();
+        let tracked = reaping.track(&server).unwrap();
+        let talking = std::thread::spawn(move || {
+            while client.write_all(b".").is_ok() {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let started = Instant::now();
+        let mut watched = tracked.watch(&server);
+        let mut buf = [0; 16];
+        while watched.read(&mut buf).unwrap_or(0) > 0 {}
+        assert!(started.elapsed() >= Duration::from_millis(600));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        drop(server);
+        talking.join().unwrap();
+    }
+
+    #[test]
+    fn test_slow_readers_are_closed() {
+        let reaping = Reaper::new()
+            .min_read_rate(100, Duration::from_millis(300))
+            .start();
+        let (server, mut client) = pair();
+        let tracked = reaping.track(&server).unwrap();
+        // A byte every 50ms is 20 bytes per second, never idle for long
+        let dripping = std::thread::spawn(move || {
+            while client.write_all(b".").is_ok() {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let started = Instant::now();
+        let mut watched = tracked.watch(&server);
+        let mut buf = [0; 16];
+        while watched.read(&mut buf).unwrap_or(0) > 0 {}
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        drop(server);
+        dripping.join().unwrap();
+    }
+
+    #[test]
+    fn test_time_not_spent_reading_is_not_slow() {
+        let reaping = Reaper::new()
+            .min_read_rate(100, Duration::from_millis(100))
+            .start();
+        let (server, mut client) = pair();
+        let tracked = reaping.track(&server).unwrap();
+        client.write_all(b"GET / HTTP/1.1").unwrap();
+
+        let mut watched = tracked.watch(&server);
+        let mut buf = [0; 16];
+        assert_eq!(watched.read(&mut buf).unwrap(), 14);
+        // A handler busy for a while after a quick request
+        std::thread::sleep(Duration::from_millis(500));
+        assert_eq!(reaping.len(), 1);
+    }
+
+    #[test]
+    fn test_tracking_ends_with_the_guard() {
+        let reaping = Reaper::new().start();
+        let (server, _client) = pair();
+        let tracked = reaping.track(&server).unwrap();
+        assert_eq!(reaping.len(), 1);
+        drop(tracked);
+        assert_eq!(reaping.len(), 0);
+    }
+}
@@ -0,0 +1,342 @@
+/*
+* Redirects module
+*
+* A plain-text redirect map in the style of Netlify's `_redirects`, so moved
+* content needs an edit to a file rather than a restart. Each line holds a
+* path pattern, a target and an optional status; `RedirectMap` shows a file.
+*
+* Lines are tried top to bottom and the first match applies. `:name`
+* matches one path segment and `*` at the end the rest of the path (or
+* nothing); both expand in the target, the rest as `:splat`. The status
+* defaults to 301, and 200 serves the target in place of the requested path
+* instead of redirecting. A trailing slash on the request path is ignored and
+* the query string carries over to the target.
+*
+* The file is checked for changes at most once a second while requests come
+* in. A version that fails to parse is logged and the previous rules stay in
+* effect.
+*/
+
+use crate::config::ConfigError;
+use crate::error::ServerError;
+use crate::message::{Request, StatusCode};
+use crate::rewrite::{resolve, RewriteAction, Rewritten, REDIRECTS};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+/// Minimum time between checks of the file for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A redirects file, reloaded when it changes.
+///
+/// Clones share the loaded rules. A file looks like this:
+///
+/// ```text
+/// # from                 to                       status
+/// /old-path              /new-path                301
+/// /blog/:year/:slug      /posts/:year-:slug       301
+/// /docs/*                /manual/:splat           302
+/// /app/*                 /app/index.html          200
+/// /chat                  https://chat.example.com
+/// ```
+///
+/// # Examples
+///
+/// ```no_run
+/// use file_shover::message::Request;
+/// use file_shover::redirects::RedirectMap;
+/// use file_shover::rewrite::Rewritten;
+///
+/// let map = RedirectMap::load("site/_redirects")?;
+/// let req = Request::builder().path("/old-path").build();
+/// if let Some(Rewritten::Redirect { status, location }) = map.lookup(&req) {
+///     println!("{} -> {}", status.as_u16(), location);
+/// }
+/// # Ok::<(), file_shover::error::ServerError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct RedirectMap {
+    path: PathBuf,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    redirects: Arc<Vec<Redirect>>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl RedirectMap {
+    /// Reads the redirects file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Config` if the file cannot be read or has an
+    /// invalid line.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, ServerError> {
+        let path = path.into();
+        let (redirects, modified) = read(&path)?;
+        Ok(Self {
+            path,
+            state: Arc::new(Mutex::new(State {
+                redirects: Arc::new(redirects),
+                modified,
+                checked: Instant::now(),
+            })),
+        })
+    }
+
+    /// Number of rules currently loaded.
+    pub fn len(&self) -> usize {
+        self.redirects_at(Instant::now()).len()
+    }
+
+    /// Returns true if no rules are loaded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The outcome of the first rule matching `req`, if any.
+    pub fn lookup(&self, req: &Request) -> Option<Rewritten> {
+        self.lookup_at(req, Instant::now())
+    }
+
+    fn lookup_at(&self, req: &Request, now: Instant) -> Option<Rewritten> {
+        let path = match req.path().trim_end_matches('/') {
+            "" => "/",
+            path => path,
+        };
+        self.redirects_at(now).iter().find_map(|redirect| {
+            let target = redirect.matches(path)?;
+            resolve(target, req.query(), redirect.action)
+        })
+    }
+
+    /// The current rules, reloading the file first if it changed.
+    fn redirects_at(&self, now: Instant) -> Arc<Vec<Redirect>> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if now.saturating_duration_since(state.checked) >= RELOAD_INTERVAL {
+            state.checked = now;
+            let modified = std::fs::metadata(&self.path)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            if modified != state.modified {
+                state.modified = modified;
+                match read(&self.path) {
+                    Ok((redirects, _)) => {
+                        info!(
+                            "🔀 Reloaded {} redirects from {}",
+                            redirects.len(),
+                            self.path.display()
+                        );
+                        state.redirects = Arc::new(redirects);
+                    }
+                    Err(e) => warn!("Keeping previous redirects: {}", e),
+                }
+            }
+        }
+        Arc::clone(&state.redirects)
+    }
+}
+
+/// One line of the file.
+#[derive(Debug, Clone, PartialEq)]
+struct Redirect {
+    /// Segments of the source path; `:name` segments are placeholders.
+    from: Vec<String>,
+    /// The source ends in `*`.
+    splat: bool,
+    to: String,
+    action: RewriteAction,
+}
+
+impl Redirect {
+    /// The target with placeholders expanded if `path` matches.
+    fn matches(&self, path: &str) -> Option<String> {
+        let mut segments = path.split('/').skip(1).filter(|s| !s.is_empty());
+        let mut values = Vec::new();
+        for expected in &self.from {
+            let segment = segments.next()?;
+            match expected.strip_prefix(':') {
+                Some(name) => values.push((name, segment)),
+                None if expected == segment => {}
+                None => return None,
+            }
+        }
+        let rest: Vec<&str> = segments.collect();
+        if !self.splat && !rest.is_empty() {
+            return None;
+        }
+        let splat = rest.join("/");
+        values.push(("splat", &splat));
+        // Longest names first, so `:id` does not replace the start of `:idx`
+        values.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        let mut target = self.to.clone();
+        for (name, value) in values {
+            target = target.replace(&format!(":{}", name), value);
+        }
+        Some(target)
+    }
+}
+
+/// Parses the file, returning its rules and modification time.
+fn read(path: &Path) -> Result<(Vec<Redirect>, Option<SystemTime>), ConfigError> {
+    let modified = std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| ConfigError::Invalid(format!("redirects file {}: {}", path.display(), e)))?;
+    let redirects = parse(&content).map_err(|(line, reason)| {
+        ConfigError::Invalid(format!("{} line {}: {}", path.display(), line, reason))
+    })?;
+    Ok((redirects, modified))
+}
+
+/// Parses every rule, or returns the first bad line number and why.
+fn parse(content: &str) -> Result<Vec<Redirect>, (usize, String)> {
+    let mut redirects = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        // Comments start a line or follow a space; URLs may hold fragments
+        let line = if line.trim_start().starts_with('#') {
+            ""
+        } else {
+            line.split(" #").next().unwrap_or_default()
+        };
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let invalid = |reason: &str| (number + 1, reason.to_string());
+        let (from, to, status) = match fields[..] {
+            [] => continue,
+            [from, to] => (from, to, None),
+            [from, to, status] => (from, to, Some(status)),
+            _ => return Err(invalid("expected '<from> <to> [status]'")),
+        };
+        if !from.starts_with('/') {
+            return Err(invalid("the source must be a path"));
+        }
+        let action = match status.map(|status| {
+            status
+                .parse::<u16>()
+                .ok()
+                .and_then(|code| StatusCode::from_u16(code).ok())
+        }) {
+            None => RewriteAction::Redirect(StatusCode::MOVED_PERMANENTLY),
+            Some(Some(StatusCode::OK)) if to.starts_with('/') => RewriteAction::Rewrite,
+            Some(Some(StatusCode::OK)) => return Err(invalid("a 200 rule must target a path")),
+            Some(Some(status)) if REDIRECTS.contains(&status) => RewriteAction::Redirect(status),
+            Some(_) => return Err(invalid("the status must be 200, 301, 302, 307 or 308")),
+        };
+        let (from, splat) = match from.strip_suffix('*') {
+            Some(prefix) if prefix.ends_with('/') => (prefix, true),
+            Some(_) => return Err(invalid("'*' must be a whole last segment")),
+            None => (from, false),
+        };
+        if from.contains('*') {
+            return Err(invalid("'*' must be a whole last segment"));
+        }
+        redirects.push(Redirect {
+            from: from
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(str::to_string)
+                .collect(),
+            splat,
+            to: to.to_string(),
+            action,
+        });
+    }
+    Ok(redirects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(redirects: &[Redirect], path: &str) -> Option<String> {
+        redirects.iter().find_map(|redirect| redirect.matches(path))
+    }
+
+    #[test]
+    fn test_parse_and_match() {
+        let redirects = parse(
+            "# moved content\n\
+             /old-path /new-path\n\
+             /blog/:year/:slug /posts/:year-:slug 302\n\
+             /docs/* /manual/:splat\n\
+             /app/* /app/index.html 200\n\
+             \n\
+             /chat https://chat.example.com 307  # external\n",
+        )
+        .unwrap();
+        assert_eq!(redirects.len(), 5);
+        assert_eq!(
+            redirects[0].action,
+            RewriteAction::Redirect(StatusCode::MOVED_PERMANENTLY)
+        );
+        assert_eq!(redirects[3].action, RewriteAction::Rewrite);
+
+        assert_eq!(
+            target(&redirects, "/old-path").as_deref(),
+            Some("/new-path")
+        );
+        assert_eq!(target(&redirects, "/old-path/more"), None);
+        assert_eq!(
+            target(&redirects, "/blog/2024/hello").as_deref(),
+            Some("/posts/2024-hello")
+        );
+        assert_eq!(target(&redirects, "/blog/2024"), None);
+        assert_eq!(
+            target(&redirects, "/docs/guide/intro.html").as_deref(),
+            Some("/manual/guide/intro.html")
+        );
+        assert_eq!(target(&redirects, "/docs").as_deref(), Some("/manual/"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("/a /b 404").unwrap_err().0, 1);
+        assert_eq!(parse("/a /b\nold /b").unwrap_err().0, 2);
+        assert!(parse("/a* /b").is_err());
+        assert!(parse("/a/*/c /b").is_err());
+        assert!(parse("/a https://example.com 200").is_err());
+        assert!(parse("/a /b 301 extra").is_err());
+    }
+
+    #[test]
+    fn test_lookup_and_reload() {
+        let path =
+            std::env::temp_dir().join(format!("file-shover-redirects-{}", std::process::id()));
+        std::fs::write(&path, "/old /new\n").unwrap();
+        let map = RedirectMap::load(&path).unwrap();
+        let req = Request::builder().path("/old/?ref=mail").build();
+        match map.lookup(&req) {
+            Some(Rewritten::Redirect { status, location }) => {
+                assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+                assert_eq!(location, "/new?ref=mail");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // A broken edit keeps the old rules, a fixed one replaces them
+        let later = Instant::now() + RELOAD_INTERVAL;
+        std::fs::write(&path, "/old\n").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert!(map.lookup_at(&req, later).is_some());
+
+        std::fs::write(&path, "/other /new 200\n").unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(20))
+            .unwrap();
+        assert!(map.lookup_at(&req, later + RELOAD_INTERVAL).is_none());
+        let req = Request::builder().path("/other").build();
+        assert!(matches!(
+            map.lookup_at(&req, later + RELOAD_INTERVAL),
+            Some(Rewritten::Internal(_))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+/*
+* Rewrite module
+*
+* Ordered regex rewrite rules, applied to the request path before anything
+* else looks at it (files, CGI, proxy routes). The first rule whose pattern
+* matches the decoded path applies; later rules are not tried and the result
+* is not matched again, so rules cannot loop.
+*
+*   "^/blog/([0-9]+)$ => /blog.php?id=$1"        internal rewrite
+*   "^/about$ => /about.html"                    pretty URL
+*   "^/docs/v1/(.*)$ => /docs/v2/$1 [301]"       moved content
+*   "^/shop(/.*)?$ => https://shop.example$1 [302]"
+*
+* An internal rewrite serves another path as if it had been requested; a
+* redirect (`[301]`, `[302]`, `[307]` or `[308]`) sends the client to the
+* target. `$1`, `${name}` and `$0` expand to captures of the pattern. The
+* original query string is appended to a target's own query; a target ending
+* in `?` drops it.
+*/
+
+use crate::config::ConfigError;
+use crate::message::{Request, StatusCode};
+use crate::target::{encode_path, RequestTarget};
+use regex::Regex;
+
+/// Redirect flags accepted after a target.
+pub(crate) const REDIRECTS: [StatusCode; 4] = [
+    StatusCode::MOVED_PERMANENTLY,
+    StatusCode::FOUND,
+    StatusCode::TEMPORARY_REDIRECT,
+    StatusCode::PERMANENT_REDIRECT,
+];
+
+/// What a matching rule does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RewriteAction {
+    /// Serve the target instead of the requested path.
+    Rewrite,
+    /// Redirect the client to the target with this status.
+    Redirect(StatusCode),
+}
+
+/// One rule, written `"<regex> => <target> [<status>]"`.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::message::Request;
+/// use file_shover::rewrite::{Rewritten, RewriteRule};
+///
+/// let rule: RewriteRule = r"^/blog/(\d+)$ => /blog.php?id=$1".parse()?;
+/// let req = Request::builder().path("/blog/42?ref=feed").build();
+/// match rule.apply(&req) {
+///     Some(Rewritten::Internal(target)) => {
+///         assert_eq!(target.path(), "/blog.php");
+///         assert_eq!(target.query(), Some("id=42&ref=feed"));
+///     }
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// # Ok::<(), file_shover::config::ConfigError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct RewriteRule {
+    pattern: Regex,
+    target: String,
+    action: RewriteAction,
+}
+
+/// The outcome of a matching rule.
+#[derive(Debug)]
+pub enum Rewritten {
+    /// The request target to serve instead.
+    Internal(RequestTarget),
+    /// A redirect for the client.
+    Redirect {
+        status: StatusCode,
+        location: String,
+    },
+}
+
+impl RewriteRule {
+    /// Creates a rule sending paths matching `pattern` to `target`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Invalid` if `pattern` is not a valid regex.
+    pub fn new(
+        pattern: &str,
+        target: impl Into<String>,
+        action: RewriteAction,
+    ) -> Result<Self, ConfigError> {
+        let pattern = Regex::new(pattern)
+            .map_err(|e| ConfigError::Invalid(format!("rewrite pattern '{}': {}", pattern, e)))?;
+        Ok(Self {
+            pattern,
+            target: target.into(),
+            action,
+        })
+    }
+
+    /// The rewritten target if the rule matches `req`.
+    ///
+    /// An internal rewrite whose result is not a valid path (say a capture
+    /// producing a control character) is skipped.
+    pub fn apply(&self, req: &Request) -> Option<Rewritten> {
+        let captures = self.pattern.captures(req.path())?;
+        let mut target = String::new();
+        captures.expand(&self.target, &mut target);
+        resolve(target, req.query(), self.action)
+    }
+}
+
+impl std::str::FromStr for RewriteRule {
+    type Err = ConfigError;
+
+    /// Parses `"<regex> => <target>"`, optionally followed by a redirect
+    /// status in brackets.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |reason: &str| ConfigError::Invalid(format!("rewrite rule '{}': {}", s, reason));
+        let (pattern, target) = s
+            .rsplit_once("=>")
+            .ok_or_else(|| invalid("expected '<regex> => <target> [status]'"))?;
+        let (pattern, target) = (pattern.trim(), target.trim());
+        let (target, action) = match target.rsplit_once('[') {
+            Some((target, flag)) => {
+                let status = flag
+                    .strip_suffix(']')
+                    .and_then(|code| code.trim().parse::<u16>().ok())
+                    .and_then(|code| StatusCode::from_u16(code).ok())
+                    .filter(|status| REDIRECTS.contains(status))
+                    .ok_or_else(|| invalid("the flag must be [301], [302], [307] or [308]"))?;
+                (target.trim(), RewriteAction::Redirect(status))
+            }
+            None => (target, RewriteAction::Rewrite),
+        };
+        if pattern.is_empty() || target.is_empty() {
+            return Err(invalid("missing pattern or target"));
+        }
+        if target.contains(char::is_whitespace) {
+            return Err(invalid("the target contains whitespace"));
+        }
+        if action == RewriteAction::Rewrite && !target.starts_with('/') {
+            return Err(invalid("an internal rewrite must target a path"));
+        }
+        Self::new(pattern, target, action)
+    }
+}
+
+/// Turns an expanded target into the outcome of `action`, appending the
+/// request's `query` unless the target ends in `?`.
+pub(crate) fn resolve(
+    target: String,
+    query: Option<&str>,
+    action: RewriteAction,
+) -> Option<Rewritten> {
+    let target = match (target.strip_suffix('?'), target.contains('?'), query) {
+        (Some(without_query), _, _) => without_query.to_string(),
+        (None, _, None) => target,
+        (None, true, Some(query)) => format!("{}&{}", target, query),
+        (None, false, Some(query)) => format!("{}?{}", target, query),
+    };
+    // Captures come from the decoded path, so paths are encoded again
+    let encoded = |target: &str| {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        match query {
+            "" => encode_path(path),
+            query => format!("{}?{}", encode_path(path), query),
+        }
+    };
+    match action {
+        RewriteAction::Rewrite => RequestTarget::parse(&encoded(&target))
+            .ok()
+            .map(Rewritten::Internal),
+        RewriteAction::Redirect(status) => Some(Rewritten::Redirect {
+            status,
+            location: if target.starts_with('/') {
+                encoded(&target)
+            } else {
+                target
+            },
+        }),
+    }
+}
+
+/// Applies the first of `rules` that matches `req`.
+pub fn rewrite(rules: &[RewriteRule], req: &Request) -> Option<Rewritten> {
+    rules.iter().find_map(|rule| rule.apply(req))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(rules: &[&str], target: &str) -> Option<Rewritten> {
+        let rules: Vec<RewriteRule> = rules.iter().map(|rule| rule.parse().unwrap()).collect();
+        rewrite(&rules, &Request::builder().path(target).build())
+    }
+
+    fn location(rewritten: Option<Rewritten>) -> (u16, String) {
+        match rewritten {
+            Some(Rewritten::Redirect { status, location }) => (status.as_u16(), location),
+            other => panic!("expected a redirect, got {:?}", other),
+        }
+    }
+
+    fn internal(rewritten: Option<Rewritten>) -> String {
+        match rewritten {
+            Some(Rewritten::Internal(target)) => target.to_string(),
+            other => panic!("expected a rewrite, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let rule: RewriteRule = "^/old/(.*)$ => /new/$1 [301]".parse().unwrap();
+        assert_eq!(
+            rule.action,
+            RewriteAction::Redirect(StatusCode::MOVED_PERMANENTLY)
+        );
+        assert_eq!(rule.target, "/new/$1");
+        assert!("^/a$ => /b [200]".parse::<RewriteRule>().is_err());
+        assert!("^/a$ => https://example.com/"
+            .parse::<RewriteRule>()
+            .is_err());
+        assert!("^/(a$ => /b".parse::<RewriteRule>().is_err());
+        assert!("^/a$".parse::<RewriteRule>().is_err());
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let rules = ["^/about$ => /about.html", "^/(.*)$ => /index.html"];
+        assert_eq!(internal(apply(&rules, "/about")), "/about.html");
+        assert_eq!(internal(apply(&rules, "/contact")), "/index.html");
+        assert!(apply(&["^/about$ => /about.html"], "/about/team").is_none());
+    }
+
+    #[test]
+    fn test_query_strings() {
+        let rule = [r"^/blog/(?P<id>\d+)$ => /blog.php?id=${id}"];
+        assert_eq!(internal(apply(&rule, "/blog/7")), "/blog.php?id=7");
+        assert_eq!(
+            internal(apply(&rule, "/blog/7?page=2")),
+            "/blog.php?id=7&page=2"
+        );
+        assert_eq!(internal(apply(&["^/a$ => /b"], "/a?x=1")), "/b?x=1");
+        assert_eq!(internal(apply(&["^/a$ => /b?"], "/a?x=1")), "/b");
+    }
+
+    #[test]
+    fn test_redirects() {
+        assert_eq!(
+            location(apply(
+                &["^/docs/v1/(.*)$ => /docs/v2/$1 [301]"],
+                "/docs/v1/a b.html"
+            )),
+            (301, "/docs/v2/a%20b.html".to_string())
+        );
+        assert_eq!(
+            location(apply(
+                &["^/shop(/.*)?$ => https://shop.example$1 [302]"],
+                "/shop/cart?item=3"
+            )),
+            (302, "https://shop.example/cart?item=3".to_string())
+        );
+    }
+
+    #[test]
+    fn test_rewrite_cannot_escape_root() {
+        assert_eq!(
+            internal(apply(
+                &["^/files/(.*)$ => /public/../$1"],
+                "/files/etc/passwd"
+            )),
+            "/etc/passwd"
+        );
+    }
+}
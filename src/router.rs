@@ -0,0 +1,118 @@
+/*
+* Router module
+*
+* Dynamic endpoints registered by embedders. Each route pairs a path pattern
+* (see the glob module; a plain path such as `/api/version` matches exactly)
+* with a handler. Routes are tried in registration order and the first match
+* answers; anything else goes to the fallback, normally the file tree.
+*
+* Routing happens inside the middleware chain, so routes are rate limited,
+* authenticated and compressed like files.
+*/
+
+use crate::glob::Glob;
+use crate::message::{Request, Response};
+use crate::middleware::Handler;
+
+/// Dispatches requests to handlers by path, with a fallback.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::message::{StatusCode, Request, Response};
+/// use file_shover::middleware::Handler;
+/// use file_shover::router::Router;
+/// use std::io::Cursor;
+///
+/// let not_found = |_req: &Request| Response::new().status(StatusCode::NOT_FOUND);
+/// let router = Router::new(not_found)
+///     .route("/api/version", |_req: &Request| Response::new().header("X-Version", "1"));
+///
+/// let get = |path: &str| {
+///     let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+///     router.handle(&Request::from_bytes(Cursor::new(raw.into_bytes())).unwrap())
+/// };
+/// assert_eq!(get("/api/version").status, StatusCode::OK);
+/// assert_eq!(get("/index.html").status, StatusCode::NOT_FOUND);
+/// ```
+pub struct Router {
+    routes: Vec<(Glob, Box<dyn Handler>)>,
+    fallback: Box<dyn Handler>,
+}
+
+impl Router {
+    /// Creates a router that sends every request to `fallback`.
+    pub fn new(fallback: impl Handler + 'static) -> Self {
+        Self::boxed(Box::new(fallback))
+    }
+
+    pub(crate) fn boxed(fallback: Box<dyn Handler>) -> Self {
+        Self {
+            routes: Vec::new(),
+            fallback,
+        }
+    }
+
+    /// Answers requests whose path matches `pattern` with `handler`.
+    pub fn route(mut self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.push(pattern, Box::new(handler));
+        self
+    }
+
+    pub(crate) fn push(&mut self, pattern: &str, handler: Box<dyn Handler>) {
+        self.routes.push((Glob::new(pattern), handler));
+    }
+
+    /// Returns true if no routes are registered.
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, req: &Request) -> Response {
+        self.routes
+            .iter()
+            .find(|(pattern, _)| pattern.matches(req.path()))
+            .map_or(&self.fallback, |(_, handler)| handler)
+            .handle(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::StatusCode;
+    use std::io::Cursor;
+
+    fn get(router: &Router, path: &str) -> Response {
+        let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+        router.handle(&Request::from_bytes(Cursor::new(raw.into_bytes())).unwrap())
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let router = Router::new(|_req: &Request| Response::new().status(StatusCode::NOT_FOUND))
+            .route("/api/health", |_req: &Request| {
+                Response::new().header("X-Route", "health")
+            })
+            .route("/api/**", |_req: &Request| {
+                Response::new().header("X-Route", "api")
+            });
+
+        let route = |path| get(&router, path).headers.get("X-Route").cloned();
+        assert_eq!(route("/api/health"), Some("health".to_string()));
+        assert_eq!(route("/api/users/1"), Some("api".to_string()));
+        assert_eq!(get(&router, "/apix").status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_query_is_ignored() {
+        let router = Router::new(|_req: &Request| Response::new().status(StatusCode::NOT_FOUND))
+            .route("/search", |req: &Request| {
+                Response::new().header("X-Query", req.query_param("q").unwrap_or_default())
+            });
+        let response = get(&router, "/search?q=rust");
+        assert_eq!(response.headers.get("X-Query"), Some(&"rust".to_string()));
+    }
+}
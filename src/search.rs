@@ -0,0 +1,712 @@
+/*
+* Search module
+*
+* Full-text search over the served text, Markdown and HTML files, so a
+* folder of docs is searchable without an external service:
+*
+*   GET /_search?q=install+guide              JSON hits, best first
+*   GET /_search?q=install&path=/docs/        only below /docs/
+*   GET /_search?q=install&format=html        a results page
+*
+* A background thread walks the tree into a small in-memory inverted index
+* (word -> documents and counts) and swaps it in when done; queries never
+* wait for it. With a file watcher (`--watch`) the index is rebuilt after
+* each change, otherwise every five minutes.
+*
+* Every query word must appear in a hit. Hits are ranked by term frequency
+* weighted by how rare each word is, with a bonus for words in the title.
+* HTML is reduced to its visible text (scripts and styles dropped) and its
+* `<title>`; Markdown keeps its first `#` heading as the title. Dot files,
+* excluded paths and files over the size limit are not indexed, and neither
+* are files that need credentials: answers and snippets go to anyone.
+*/
+
+use crate::files::FileTree;
+use crate::glob::Glob;
+use crate::listing::escape_html;
+use crate::livereload::LiveReload;
+use crate::message::{
+    HttpMethod, Request, Response, StatusCode, DEFAULT_BAD_REQUEST_BODY,
+    DEFAULT_METHOD_NOT_ALLOWED_BODY,
+};
+use crate::middleware::{Auth, Handler};
+use crate::target::{decode_component, encode_path};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Path of the search endpoint.
+pub const SEARCH_PATH: &str = "/_search";
+
+/// How often the indexer checks for changes or a due rebuild.
+const POLL: Duration = Duration::from_secs(1);
+
+/// How long an index is kept without a file watcher.
+const REFRESH: Duration = Duration::from_secs(300);
+
+/// Hits returned when the query sets no `limit`.
+const DEFAULT_LIMIT: usize = 10;
+
+/// Most hits one query may ask for.
+const MAX_LIMIT: usize = 50;
+
+/// Characters of context shown around the first match.
+const SNIPPET_LEN: usize = 160;
+
+/// Extensions of indexed files.
+const EXTENSIONS: [&str; 5] = ["txt", "md", "markdown", "html", "htm"];
+
+/// Search settings: what to leave out and when to reindex.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::MemorySource;
+/// use file_shover::glob::Glob;
+/// use file_shover::search::Search;
+/// use file_shover::server::Server;
+///
+/// let service = Server::builder()
+///     .file_source(MemorySource::new().with("guide.md", "# Guide"))
+///     .search(Search::new().exclude(Glob::new("/drafts/**")))
+///     .build_service()?;
+/// # Ok::<(), file_shover::error::ServerError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Search {
+    exclude: Vec<Glob>,
+    changes: Option<LiveReload>,
+    max_file_size: u64,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self {
+            exclude: Vec::new(),
+            changes: None,
+            max_file_size: 1024 * 1024,
+        }
+    }
+}
+
+impl Search {
+    /// Indexes every text, Markdown and HTML file up to 1 MiB.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Leaves out paths matching `pattern`.
+    pub fn exclude(mut self, pattern: Glob) -> Self {
+        self.exclude.push(pattern);
+        self
+    }
+
+    /// Reindexes whenever `watcher` sees files change, instead of every five
+    /// minutes.
+    pub fn watch(mut self, watcher: LiveReload) -> Self {
+        self.changes = Some(watcher);
+        self
+    }
+
+    /// Skips files larger than `bytes`.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    /// The handler answering queries over the files of `file_tree` that
+    /// `access` makes public, with its indexer started in the background.
+    pub(crate) fn handler(self, file_tree: Arc<FileTree>, access: Auth) -> SearchHandler {
+        let index = Arc::new(Mutex::new(Arc::new(Index::default())));
+        let shared = Arc::downgrade(&index);
+        let spawned = std::thread::Builder::new()
+            .name("search-index".to_string())
+            .spawn(move || self.run(&file_tree, &access, &shared));
+        if let Err(e) = spawned {
+            warn!("Could not start the search indexer: {}", e);
+        }
+        SearchHandler { index }
+    }
+
+    /// Rebuilds the index into `shared` until the handler is dropped.
+    fn run(&self, file_tree: &FileTree, access: &Auth, shared: &Weak<Mutex<Arc<Index>>>) {
+        loop {
+            let generation = self.changes.as_ref().map(LiveReload::generation);
+            let started = Instant::now();
+            let index = match self.build(file_tree, access) {
+                Ok(index) => index,
+                Err(e) => {
+                    warn!("Search indexing failed: {}", e);
+                    Index::default()
+                }
+            };
+            info!(
+                "🔎 Indexed {} documents for search in {:?}",
+                index.documents.len(),
+                started.elapsed()
+            );
+            match shared.upgrade() {
+                Some(current) => {
+                    *current.lock().unwrap_or_else(|e| e.into_inner()) = Arc::new(index)
+                }
+                None => return,
+            }
+            loop {
+                std::thread::sleep(POLL);
+                if shared.strong_count() == 0 {
+                    return;
+                }
+                let due = match (&self.changes, generation) {
+                    (Some(watcher), Some(generation)) => watcher.generation() != generation,
+                    _ => started.elapsed() >= REFRESH,
+                };
+                if due {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Indexes every eligible file of `file_tree`.
+    fn build(&self, file_tree: &FileTree, access: &Auth) -> io::Result<Index> {
+        let mut index = Index::default();
+        self.collect(file_tree, access, "/", &mut index)?;
+        Ok(index)
+    }
+
+    fn collect(
+        &self,
+        file_tree: &FileTree,
+        access: &Auth,
+        dir: &str,
+        index: &mut Index,
+    ) -> io::Result<()> {
+        for entry in file_tree.list(dir)? {
+            if entry.name.starts_with('.') {
+                continue;
+            }
+            let path = format!("{}{}", dir, entry.name);
+            if self.exclude.iter().any(|glob| glob.matches(&path)) {
+                continue;
+            }
+            if entry.metadata.is_dir {
+                self.collect(file_tree, access, &format!("{}/", path), index)?;
+                continue;
+            }
+            // Results go to anyone, so only files anyone may read
+            if !access.is_public(&path) {
+                continue;
+            }
+            let extension = entry
+                .name
+                .rsplit_once('.')
+                .map(|(_, extension)| extension.to_ascii_lowercase());
+            let Some(extension) = extension.filter(|e| EXTENSIONS.contains(&e.as_str())) else {
+                continue;
+            };
+            if entry.metadata.len > self.max_file_size {
+                continue;
+            }
+            let mut content = Vec::new();
+            file_tree
+                .get_reader(&path)?
+                .reader
+                .take(self.max_file_size)
+                .read_to_end(&mut content)?;
+            let content = String::from_utf8_lossy(&content);
+            let (title, text) = match extension.as_str() {
+                "html" | "htm" => html_text(&content),
+                "md" | "markdown" => markdown_text(&content),
+                _ => (None, collapse_whitespace(&content)),
+            };
+            index.add(Document {
+                title: title.unwrap_or_else(|| entry.name.clone()),
+                path,
+                text,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// One indexed file.
+#[derive(Debug)]
+struct Document {
+    path: String,
+    title: String,
+    /// Visible text with whitespace collapsed.
+    text: String,
+}
+
+/// An inverted index: each word with the documents holding it and how often.
+#[derive(Debug, Default)]
+struct Index {
+    documents: Vec<Document>,
+    postings: HashMap<String, Vec<(usize, u32)>>,
+}
+
+/// One search result.
+#[derive(Debug, Serialize)]
+struct Hit {
+    path: String,
+    title: String,
+    score: f64,
+    snippet: String,
+}
+
+/// The JSON answer to a query.
+#[derive(Debug, Serialize)]
+struct Results {
+    query: String,
+    /// Hits before `limit` was applied.
+    total: usize,
+    hits: Vec<Hit>,
+}
+
+impl Index {
+    fn add(&mut self, document: Document) {
+        let id = self.documents.len();
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for (_, word) in words(&document.text).chain(words(&document.title)) {
+            *counts.entry(word).or_default() += 1;
+        }
+        for (word, count) in counts {
+            self.postings.entry(word).or_default().push((id, count));
+        }
+        self.documents.push(document);
+    }
+
+    /// The best `limit` documents below `scope` holding every word of
+    /// `query`, and how many matched in all.
+    fn search(&self, query: &str, scope: &str, limit: usize) -> (usize, Vec<Hit>) {
+        let mut terms: Vec<String> = words(query).map(|(_, word)| word).collect();
+        terms.sort();
+        terms.dedup();
+        if terms.is_empty() {
+            return (0, Vec::new());
+        }
+        let total = self.documents.len() as f64;
+        let mut scores: Option<HashMap<usize, f64>> = None;
+        for term in &terms {
+            let Some(postings) = self.postings.get(term) else {
+                return (0, Vec::new());
+            };
+            let idf = (1.0 + total / postings.len() as f64).ln();
+            let mut next = HashMap::new();
+            for &(id, count) in postings {
+                let previous = match &scores {
+                    Some(scores) => match scores.get(&id) {
+                        Some(score) => *score,
+                        None => continue,
+                    },
+                    None => 0.0,
+                };
+                let mut score = (1.0 + f64::from(count).ln()) * idf;
+                if self.documents[id]
+                    .title
+                    .to_lowercase()
+                    .contains(term.as_str())
+                {
+                    score += 2.0 * idf;
+                }
+                next.insert(id, previous + score);
+            }
+            scores = Some(next);
+        }
+        let mut ranked: Vec<(usize, f64)> = scores
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(id, _)| self.documents[*id].path.starts_with(scope))
+            .collect();
+        ranked.sort_by(|a, b| {
+            b.1.total_cmp(&a.1)
+                .then_with(|| self.documents[a.0].path.cmp(&self.documents[b.0].path))
+        });
+        let matched = ranked.len();
+        let hits = ranked
+            .into_iter()
+            .take(limit)
+            .map(|(id, score)| {
+                let document = &self.documents[id];
+                Hit {
+                    path: document.path.clone(),
+                    title: document.title.clone(),
+                    score: (score * 1000.0).round() / 1000.0,
+                    snippet: snippet(&document.text, &terms),
+                }
+            })
+            .collect();
+        (matched, hits)
+    }
+}
+
+/// Answers `/_search` from the latest index.
+pub(crate) struct SearchHandler {
+    index: Arc<Mutex<Arc<Index>>>,
+}
+
+impl Handler for SearchHandler {
+    fn handle(&self, req: &Request) -> Response {
+        if !matches!(req.method, HttpMethod::GET | HttpMethod::HEAD) {
+            return Response::error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                DEFAULT_METHOD_NOT_ALLOWED_BODY,
+            )
+            .header("Allow", "GET, HEAD");
+        }
+        let param = |name: &str| req.query_param(name).map(decode_component).transpose();
+        let (query, scope, limit) = match (param("q"), param("path"), param("limit")) {
+            (Ok(query), Ok(scope), Ok(limit)) => (query, scope, limit),
+            _ => return Response::error(StatusCode::BAD_REQUEST, DEFAULT_BAD_REQUEST_BODY),
+        };
+        let limit = match limit.map(|limit| limit.parse::<usize>()) {
+            None => DEFAULT_LIMIT,
+            Some(Ok(limit)) => limit.min(MAX_LIMIT),
+            Some(Err(_)) => {
+                return Response::error(StatusCode::BAD_REQUEST, DEFAULT_BAD_REQUEST_BODY)
+            }
+        };
+        let query = query.unwrap_or_default();
+        let scope = scope.unwrap_or_else(|| "/".to_string());
+        let index = Arc::clone(&self.index.lock().unwrap_or_else(|e| e.into_inner()));
+        let (total, hits) = index.search(&query, &scope, limit);
+        let results = Results { query, total, hits };
+        let (body, content_type) = if req.query_param("format") == Some("html") {
+            (render_html(&results), "text/html; charset=utf-8")
+        } else {
+            (
+                serde_json::to_string(&results).unwrap_or_default(),
+                "application/json",
+            )
+        };
+        let response = Response::new()
+            .status(StatusCode::OK)
+            .content_type(content_type)
+            .header("Cache-Control", "no-cache")
+            .content_length(body.len());
+        match req.method {
+            HttpMethod::HEAD => response,
+            _ => response.body(body),
+        }
+    }
+}
+
+/// The lowercased words of `text` with their byte offsets.
+fn words(text: &str) -> impl Iterator<Item = (usize, String)> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && word.len() <= 64)
+        .map(move |word| {
+            let offset = word.as_ptr() as usize - text.as_ptr() as usize;
+            (offset, word.to_lowercase())
+        })
+}
+
+/// About `SNIPPET_LEN` characters of `text` around the first query word.
+fn snippet(text: &str, terms: &[String]) -> String {
+    let start = words(text)
+        .find(|(_, word)| terms.contains(word))
+        .map_or(0, |(offset, _)| offset);
+    // Open a little before the match, at a word boundary
+    let mut from = text[..start]
+        .char_indices()
+        .rev()
+        .take(SNIPPET_LEN / 4)
+        .last()
+        .map_or(start, |(i, _)| i);
+    if from > 0 {
+        from = text[from..start]
+            .find(' ')
+            .map_or(from, |space| from + space + 1);
+    }
+    let rest = &text[from..];
+    let end = rest
+        .char_indices()
+        .nth(SNIPPET_LEN)
+        .map_or(rest.len(), |(i, _)| rest[..i].rfind(' ').unwrap_or(i));
+    let mut snippet = String::new();
+    if from > 0 {
+        snippet.push('…');
+    }
+    snippet.push_str(rest[..end].trim());
+    if end < rest.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+/// The `<title>` and visible text of an HTML page.
+fn html_text(html: &str) -> (Option<String>, String) {
+    let mut text = String::with_capacity(html.len() / 2);
+    let mut title = None;
+    let mut rest = html;
+    while let Some(open) = rest.find('<') {
+        text.push_str(&decode_entities(&rest[..open]));
+        rest = &rest[open + 1..];
+        let name: String = rest
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '!' || *c == '-')
+            .collect::<String>()
+            .to_ascii_lowercase();
+        // Contents of these are not visible text
+        let skip_to = match name.as_str() {
+            "script" | "style" | "title" => Some(format!("</{}", name)),
+            _ if name.starts_with("!--") => Some("-->".to_string()),
+            _ => None,
+        };
+        let Some(close) = rest.find('>') else {
+            rest = "";
+            break;
+        };
+        rest = &rest[close + 1..];
+        if let Some(end_tag) = skip_to {
+            let end = find_ignore_case(rest, &end_tag).unwrap_or(rest.len());
+            if name == "title" {
+                title = Some(collapse_whitespace(&decode_entities(&rest[..end])));
+            }
+            rest = &rest[end..];
+            if let Some(close) = rest.find('>') {
+                rest = &rest[close + 1..];
+            }
+        }
+        // Tags separate words: "a<br>b" is two
+        text.push(' ');
+    }
+    text.push_str(&decode_entities(rest));
+    (
+        title.filter(|title| !title.is_empty()),
+        collapse_whitespace(&text),
+    )
+}
+
+/// The first `#` heading and the text of a Markdown file.
+fn markdown_text(markdown: &str) -> (Option<String>, String) {
+    let title = markdown
+        .lines()
+        .find_map(|line| line.strip_prefix("# "))
+        .map(|title| title.trim().to_string());
+    let text: String = markdown
+        .lines()
+        .map(|line| line.trim_start_matches(['#', '>', ' ']))
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace(['*', '`'], "");
+    (title, collapse_whitespace(&text))
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Decodes the common named entities and numeric character references.
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[1..=end]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let number = entity.strip_prefix('#')?;
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => number.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+        match (character, entity) {
+            (Some(character), Some(entity)) => {
+                decoded.push(character);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn render_html(results: &Results) -> String {
+    let query = escape_html(&results.query);
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Search: {query}</title>\n\
+         <style>body{{font-family:sans-serif;margin:2em}}p.snippet{{margin-top:0.2em;color:#444}}</style>\n\
+         </head>\n<body>\n<h1>Search</h1>\n\
+         <form><input type=\"hidden\" name=\"format\" value=\"html\">\
+         <input name=\"q\" value=\"{query}\" autofocus> <button>Search</button></form>\n"
+    );
+    let _ = writeln!(
+        html,
+        "<p>{} result{}</p>",
+        results.total,
+        if results.total == 1 { "" } else { "s" }
+    );
+    for hit in &results.hits {
+        let _ = writeln!(
+            html,
+            "<h3><a href=\"{}\">{}</a></h3>\n<p class=\"snippet\">{}</p>",
+            escape_html(&encode_path(&hit.path)),
+            escape_html(&hit.title),
+            escape_html(&hit.snippet)
+        );
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{AccessPolicy, BearerAuth};
+    use crate::files::MemorySource;
+
+    fn index(source: MemorySource, search: Search) -> Index {
+        search
+            .build(&FileTree::from_source(source), &Auth::default())
+            .unwrap()
+    }
+
+    fn sample() -> Index {
+        index(
+            MemorySource::new()
+                .with(
+                    "docs/install.html",
+                    "<html><head><title>Install guide</title><style>p{color:red}</style></head>\
+                     <body><h1>Installing</h1><p>Run the installer &amp; restart.</p>\
+                     <script>var install = 1;</script></body></html>",
+                )
+                .with(
+                    "docs/usage.md",
+                    "# Usage\n\nStart the server, then **install** plugins.\n",
+                )
+                .with("notes.txt", "Shopping list: apples, pears")
+                .with("logo.png", "install")
+                .with(".hidden.txt", "install")
+                .with("drafts/install.txt", "install install install"),
+            Search::new().exclude(Glob::new("/drafts/**")),
+        )
+    }
+
+    #[test]
+    fn test_html_text() {
+        let (title, text) = html_text(
+            "<title>A &lt;B&gt;</title><!-- <p>gone</p> --><p>one<br>two&#33; &#x263A;</p>",
+        );
+        assert_eq!(title.as_deref(), Some("A <B>"));
+        assert_eq!(text, "one two! ☺");
+    }
+
+    #[test]
+    fn test_markdown_text() {
+        let (title, text) = markdown_text("Intro\n# Guide\n> quoted `code` and **bold**\n");
+        assert_eq!(title.as_deref(), Some("Guide"));
+        assert_eq!(text, "Intro Guide quoted code and bold");
+    }
+
+    #[test]
+    fn test_search_ranks_and_filters() {
+        let index = sample();
+        assert_eq!(index.documents.len(), 3);
+
+        // "installer" is another word; the title counts extra
+        let (total, hits) = index.search("INSTALL", "/", 10);
+        assert_eq!(total, 2);
+        assert_eq!(hits[0].path, "/docs/install.html");
+        assert_eq!(hits[1].path, "/docs/usage.md");
+        assert_eq!(index.search("var", "/", 10).0, 0);
+
+        let (total, hits) = index.search("guide", "/", 10);
+        assert_eq!(total, 1);
+        assert_eq!(hits[0].title, "Install guide");
+        assert_eq!(hits[0].snippet, "Installing Run the installer & restart.");
+
+        assert_eq!(index.search("apples pears", "/", 10).0, 1);
+        assert_eq!(index.search("apples server", "/", 10).0, 0);
+        assert_eq!(index.search("apples", "/docs/", 10).0, 0);
+        assert_eq!(index.search("", "/", 10).0, 0);
+    }
+
+    #[test]
+    fn test_skips_non_public() {
+        let config = crate::config::Config::from_toml(
+            "[[auth.rules]]\npath = \"/private/**\"\naccess = \"token\"",
+        )
+        .unwrap();
+        let access = Auth::new(
+            BearerAuth::new(Vec::new()),
+            AccessPolicy::from_config(&config.auth).unwrap(),
+            None,
+            false,
+        );
+        let source = MemorySource::new()
+            .with("private/plans.txt", "secret merger")
+            .with("news.txt", "merger announced");
+        let index = Search::new()
+            .build(&FileTree::from_source(source), &access)
+            .unwrap();
+        let (total, hits) = index.search("merger", "/", 10);
+        assert_eq!(total, 1);
+        assert_eq!(hits[0].path, "/news.txt");
+    }
+
+    #[test]
+    fn test_snippet_window() {
+        let text = format!("{} needle {}", "word ".repeat(100), "tail ".repeat(100));
+        let snippet = snippet(text.trim(), &["needle".to_string()]);
+        assert!(snippet.starts_with("…word"));
+        assert!(snippet.ends_with("tail…"));
+        assert!(snippet.contains("needle"));
+        assert!(snippet.chars().count() <= SNIPPET_LEN + 2);
+    }
+
+    #[test]
+    fn test_handler() {
+        let handler = SearchHandler {
+            index: Arc::new(Mutex::new(Arc::new(sample()))),
+        };
+        let response = handler.handle(&Request::builder().path("/_search?q=usage&limit=5").build());
+        assert_eq!(response.status, StatusCode::OK);
+        let mut body = String::new();
+        response
+            .body
+            .into_reader()
+            .read_to_string(&mut body)
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["total"], 1);
+        assert_eq!(json["hits"][0]["path"], "/docs/usage.md");
+
+        let response = handler.handle(&Request::builder().path("/_search?q=x&limit=many").build());
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,166 @@
+/*
+* Sendfile module
+*
+* Internal redirects in the style of X-Sendfile / X-Accel-Redirect: a route
+* handler or proxied backend decides *whether* a file may be downloaded
+* (authorization, accounting) and answers with an empty response carrying
+*
+*   X-Shover-Send: /reports/2024/q3.pdf
+*
+* The static layer then serves that file in its place, with everything the
+* file handler does (ranges, conditional requests, validators, throttling),
+* instead of the application streaming the bytes itself:
+*
+*   client --GET /download/42--> handler --X-Shover-Send: /store/a.bin--+
+*   client <----------------- file handler serves /store/a.bin <--------+
+*
+* The target is resolved in the served tree without routes, rewrites or
+* access rules, so it may point at files clients cannot request directly.
+* `Cache-Control`, `Content-Disposition` and `Expires` from the delegating
+* response are kept. Honouring the header is opt-in (`--sendfile`); without
+* it the header is passed on like any other.
+*/
+
+use crate::message::{HttpMethod, Request, Response, StatusCode, DEFAULT_NOT_FOUND_BODY};
+use crate::middleware::Handler;
+use crate::target::RequestTarget;
+use tracing::{debug, warn};
+
+/// Response header naming the file to serve instead.
+pub const SEND_HEADER: &str = "X-Shover-Send";
+
+/// Headers of the delegating response that carry over to the file.
+const KEPT_HEADERS: &[&str] = &["Cache-Control", "Content-Disposition", "Expires"];
+
+/// Serves the file `response` delegates to with `files`, or returns
+/// `response` unchanged if it does not delegate.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::{FileTree, MemorySource};
+/// use file_shover::message::{Request, Response, StatusCode};
+/// use file_shover::sendfile::{delegate, SEND_HEADER};
+///
+/// let tree = FileTree::from_source(MemorySource::new().with("store/a.bin", "bytes"));
+/// let files = |req: &Request| match tree.get_reader(req.path()) {
+///     Ok(_) => Response::new().body(req.path().to_string()),
+///     Err(_) => Response::new().status(StatusCode::NOT_FOUND),
+/// };
+///
+/// let req = Request::builder().path("/download/42").build();
+/// let response = Response::new()
+///     .header(SEND_HEADER, "/store/a.bin")
+///     .header("Content-Disposition", "attachment");
+/// let response = delegate(&files, &req, response);
+/// assert_eq!(response.status, StatusCode::OK);
+/// assert_eq!(response.headers.get("Content-Disposition"), Some(&"attachment".to_string()));
+/// assert!(!response.headers.contains_key(SEND_HEADER));
+/// ```
+pub fn delegate(files: &dyn Handler, req: &Request, mut response: Response) -> Response {
+    let Some(name) = response
+        .headers
+        .keys()
+        .find(|name| name.eq_ignore_ascii_case(SEND_HEADER))
+        .cloned()
+    else {
+        return response;
+    };
+    let target = response.headers.remove(&name).unwrap_or_default();
+    let target = match RequestTarget::parse(target.trim()) {
+        Ok(target) if target.path().starts_with('/') => target,
+        _ => {
+            warn!(
+                "Invalid {} target for {}: {:?}",
+                SEND_HEADER,
+                req.path(),
+                target
+            );
+            return Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY);
+        }
+    };
+    debug!("Sending {} for {}", target.path(), req.path());
+    let mut internal = req.clone();
+    internal.target = target;
+    if internal.method != HttpMethod::HEAD {
+        internal.method = HttpMethod::GET;
+    }
+    let mut file = files.handle(&internal);
+    if file.status == StatusCode::NOT_FOUND {
+        warn!(
+            "{} target of {} not found: {}",
+            SEND_HEADER,
+            req.path(),
+            internal.path()
+        );
+        return file;
+    }
+    for (name, value) in response.headers {
+        if KEPT_HEADERS
+            .iter()
+            .any(|kept| kept.eq_ignore_ascii_case(&name))
+        {
+            file.headers
+                .retain(|existing, _| !existing.eq_ignore_ascii_case(&name));
+            file.headers.insert(name, value);
+        }
+    }
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn files(req: &Request) -> Response {
+        match req.path() {
+            "/store/a b.bin" => Response::new()
+                .header("Cache-Control", "no-cache")
+                .body(format!(
+                    "{} {}",
+                    req.method,
+                    req.header("Range").unwrap_or("-")
+                )),
+            _ => Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY),
+        }
+    }
+
+    #[test]
+    fn test_delegated_request_keeps_method_and_range() {
+        let req = Request::builder()
+            .method(HttpMethod::POST)
+            .path("/download/42?token=x")
+            .header("Range", "bytes=0-9")
+            .build();
+        let response = Response::new()
+            .header("x-shover-send", "/store/a%20b.bin")
+            .header("Cache-Control", "private, max-age=60")
+            .header("Set-Cookie", "seen=1");
+        let response = delegate(&files, &req, response);
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            response.headers.get("Cache-Control"),
+            Some(&"private, max-age=60".to_string())
+        );
+        assert!(!response.headers.contains_key("Set-Cookie"));
+        let mut body = String::new();
+        response
+            .body
+            .into_reader()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "GET bytes=0-9");
+    }
+
+    #[test]
+    fn test_missing_or_invalid_target() {
+        let req = Request::builder().path("/download/42").build();
+        for target in ["/store/missing.bin", "store/a b.bin", "/%zz"] {
+            let response = delegate(&files, &req, Response::new().header(SEND_HEADER, target));
+            assert_eq!(response.status, StatusCode::NOT_FOUND, "{}", target);
+        }
+        let plain = delegate(&files, &req, Response::new().body("inline"));
+        assert_eq!(plain.status, StatusCode::OK);
+    }
+}
@@ -0,0 +1,3533 @@
+/*
+* Server module
+*
+* The embeddable server: accept loop, worker pool and the request pipeline
+* shared by every connection.
+*
+*   accept -> IP filter -> connection limits -> worker pool
+*   worker -> (TLS) -> parse -> forwarded-client check
+*          -> middleware (logging, rate limit, auth, user layers)
+*          -> proxy routes | handler (file tree) -> fallback proxy
+*          -> response headers -> write
+*
+* A panic in a handler or middleware layer is caught at the chain and
+* answered with 500, so one bad request cannot take down a worker.
+*
+* `Server::builder()` collects the optional pieces; the CLI is a thin layer
+* that turns flags and the config file into builder calls.
+*/
+
+use crate::archive::{self, ArchiveError, ArchiveFormat, ArchiveLimits};
+#[cfg(feature = "audit")]
+use crate::audit::{AuditLog, AuditRecord};
+use crate::auth::{AccessPolicy, BearerAuth};
+use crate::ban::AutoBan;
+use crate::cgi::Cgi;
+use crate::checksum::ChecksumCache;
+use crate::conditional::{if_range_holds, preconditions_hold};
+use crate::config::ConfigError;
+use crate::connlimit::ConnectionLimiter;
+use crate::data::get_mime_type;
+use crate::defaults::{Defaults, Robots};
+use crate::dump::{HttpDump, Recording};
+use crate::error::ServerError;
+use crate::fastcgi::FastCgi;
+use crate::files::{DiskSource, FileData, FileSource, FileTree, LayeredSource};
+use crate::fingerprint::Fingerprints;
+#[cfg(feature = "geoip")]
+use crate::geoip::GeoIp;
+use crate::headers::{
+    content_disposition, Attachments, CacheTtls, HashedNames, HeaderRule, SecurityHeaders,
+};
+use crate::hotlink::HotlinkGuard;
+use crate::ipfilter::{DenyAction, IpFilter};
+use crate::listing::{AutoIndex, Listing, ListingQuery, TreeNode};
+use crate::livereload::LiveReload;
+use crate::maintenance::Maintenance;
+use crate::message::{
+    Body, HttpMethod, Request, RequestError, Response, StatusCode, DEFAULT_BAD_GATEWAY_BODY,
+    DEFAULT_BAD_REQUEST_BODY, DEFAULT_FORBIDDEN_BODY, DEFAULT_HEADERS_TOO_LARGE_BODY,
+    DEFAULT_INTERNAL_ERROR_BODY, DEFAULT_METHOD_NOT_ALLOWED_BODY, DEFAULT_NOT_FOUND_BODY,
+    DEFAULT_PAYLOAD_TOO_LARGE_BODY, DEFAULT_PRECONDITION_FAILED_BODY,
+    DEFAULT_RANGE_NOT_SATISFIABLE_BODY, DEFAULT_SERVICE_UNAVAILABLE_BODY,
+    DEFAULT_URI_TOO_LONG_BODY,
+};
+use crate::middleware::{Auth, Chain, Handler, Logging, Middleware, RateLimit};
+use crate::parser::{self, Limits};
+use crate::pool;
+use crate::prewarm::{self, Prewarmed};
+use crate::proxy::TrustedProxies;
+use crate::quota::DownloadQuota;
+use crate::range::ByteRange;
+use crate::ratelimit::RateLimiter;
+use crate::reaper::{Reaper, Reaping, Watched};
+use crate::redirects::RedirectMap;
+use crate::rewrite::{self, RewriteRule, Rewritten};
+use crate::router::Router;
+use crate::search::{Search, SEARCH_PATH};
+use crate::sendfile;
+use crate::share::DownloadLimit;
+use crate::signing::UrlSigner;
+use crate::sitemap::{Sitemap, SITEMAP_PATH};
+use crate::stats::Stats;
+use crate::suggest;
+use crate::target::decode_component;
+use crate::throttle::Bandwidth;
+use crate::tls::{self, ClientCert, TlsConfig};
+use crate::units::http_date;
+use crate::upload::{UploadError, Uploaded, Uploads};
+use crate::upstream::{ProxyRoute, Relayed, Upstream, UpstreamError};
+use crate::variants::Variants;
+use crate::webdav;
+use std::any::Any;
+use std::fmt;
+use std::io::{self, BufRead, Cursor, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tracing::{debug, error, field, info, info_span, warn, Span};
+
+/// How long the accept loop may block writing a 503 to a rejected client.
+const REJECT_WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:7878";
+const DEFAULT_THREADS: usize = 10;
+/// Accepted connections that may wait for a free worker by default.
+const DEFAULT_MAX_QUEUE: usize = 1024;
+/// First pause after `accept()` fails for lack of descriptors or memory.
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+/// Longest pause between `accept()` retries.
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+/// Pause between wake-up connections while handing the listener over.
+const HAND_OVER_RETRY: Duration = Duration::from_millis(50);
+const TREE_API_PATH: &str = "/_api/tree";
+/// Read buffer of a client connection, as BufReader's default.
+const CONNECTION_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Configures and creates a [`Server`].
+///
+/// A [`root`](ServerBuilder::root) or [`file_source`](ServerBuilder::file_source),
+/// a custom [`handler`](ServerBuilder::handler) or at least one
+/// [`route`](ServerBuilder::route) is required; every other feature is off
+/// until configured.
+///
+/// # Examples
+///
+/// ```no_run
+/// use file_shover::Server;
+///
+/// let server = Server::builder()
+///     .root("public")
+///     .bind("127.0.0.1:8080")
+///     .threads(4)
+///     .build()?;
+/// let shutdown = server.shutdown_handle();
+/// std::thread::spawn(move || server.run());
+/// // ...
+/// shutdown.shutdown();
+/// # Ok::<(), file_shover::error::ServerError>(())
+/// ```
+#[derive(Default)]
+pub struct ServerBuilder {
+    roots: Vec<PathBuf>,
+    file_source: Option<Box<dyn FileSource>>,
+    excluded: Vec<String>,
+    ignore_file: Option<String>,
+    sendfile: bool,
+    bind: Option<String>,
+    port_retries: u16,
+    listener: Option<TcpListener>,
+    name: Option<String>,
+    threads: Option<usize>,
+    max_queue: Option<usize>,
+    reaper: Option<Reaper>,
+    tls: Option<TlsConfig>,
+    rate_limiter: Option<RateLimiter>,
+    bandwidth: Bandwidth,
+    download_quota: Option<DownloadQuota>,
+    download_limit: Option<DownloadLimit>,
+    request_limits: Limits,
+    slow_request: Option<Duration>,
+    dump: Option<HttpDump>,
+    maintenance: Option<Maintenance>,
+    live_reload: Option<LiveReload>,
+    max_connections_per_ip: Option<usize>,
+    max_connections: Option<usize>,
+    ip_filter: Option<IpFilter>,
+    deny_action: DenyAction,
+    #[cfg(feature = "geoip")]
+    geoip: Option<Arc<GeoIp>>,
+    #[cfg(feature = "audit")]
+    audit_log: Option<AuditLog>,
+    auto_ban: Option<AutoBan>,
+    trusted_proxies: TrustedProxies,
+    bearer_auth: Option<BearerAuth>,
+    access_policy: Option<AccessPolicy>,
+    url_signer: Option<UrlSigner>,
+    require_signed: bool,
+    hotlink_guard: Option<HotlinkGuard>,
+    hotlink_placeholder: Option<(Vec<u8>, String)>,
+    archive_limits: Option<ArchiveLimits>,
+    auto_index: Option<AutoIndex>,
+    tree_api_depth: Option<usize>,
+    sitemap: Option<Sitemap>,
+    fingerprints: Option<Fingerprints>,
+    variants: Option<Variants>,
+    search: Option<Search>,
+    robots: Option<Robots>,
+    favicon: bool,
+    suggestions: bool,
+    checksums: Option<bool>,
+    uploads: Option<Uploads>,
+    webdav: bool,
+    attachments: Attachments,
+    security_headers: SecurityHeaders,
+    hashed_names: Option<HashedNames>,
+    cache_ttls: CacheTtls,
+    header_rules: Vec<HeaderRule>,
+    rewrite_rules: Vec<RewriteRule>,
+    redirects: Option<RedirectMap>,
+    proxy_routes: Vec<ProxyRoute>,
+    proxy_fallback: Option<Upstream>,
+    cgi: Option<Cgi>,
+    fastcgi: Option<FastCgi>,
+    middleware: Vec<Box<dyn Middleware>>,
+    handler: Option<Box<dyn Handler>>,
+    routes: Vec<(String, Box<dyn Handler>)>,
+}
+
+impl ServerBuilder {
+    /// Directory to serve files from.
+    ///
+    /// Calling it again layers another directory below the earlier ones: a
+    /// path is served from the first root that has it.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.roots.push(root.into());
+        self
+    }
+
+    /// Serves files from `source` instead of a root directory.
+    pub fn file_source(mut self, source: impl FileSource + 'static) -> Self {
+        self.file_source = Some(Box::new(source));
+        self
+    }
+
+    /// Hides paths matching any of the globs `patterns` from every feature
+    /// (see [`FileTree::exclude`]).
+    pub fn exclude(mut self, patterns: &[String]) -> Self {
+        self.excluded.extend(patterns.iter().cloned());
+        self
+    }
+
+    /// Also hides what the gitignore-style file `name` at the root lists,
+    /// usually [`IGNORE_FILE`](crate::ignore::IGNORE_FILE).
+    pub fn ignore_file(mut self, name: impl Into<String>) -> Self {
+        self.ignore_file = Some(name.into());
+        self
+    }
+
+    /// Answers responses of route handlers and proxied backends naming a
+    /// file in `X-Shover-Send` with that file (see [`crate::sendfile`]).
+    pub fn sendfile(mut self) -> Self {
+        self.sendfile = true;
+        self
+    }
+
+    /// Address to listen on (default `0.0.0.0:7878`; use port 0 for any free port).
+    pub fn bind(mut self, address: impl Into<String>) -> Self {
+        self.bind = Some(address.into());
+        self
+    }
+
+    /// If the port is taken, tries up to `retries` following ports (7879,
+    /// 7880, ...) before giving up; [`Server::local_addr`] tells which one
+    /// was bound.
+    pub fn port_retries(mut self, retries: u16) -> Self {
+        self.port_retries = retries;
+        self
+    }
+
+    /// Serves on an already listening socket, e.g. one inherited from the
+    /// process being upgraded, instead of binding an address.
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Tags the server's log lines with `site{name=...}`, to tell servers
+    /// in one process apart.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Number of worker threads (default 10).
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Connections that may wait for a free worker (default 1024); beyond
+    /// that, new connections are answered 503 at once instead of queueing.
+    pub fn max_queue(mut self, connections: usize) -> Self {
+        self.max_queue = Some(connections);
+        self
+    }
+
+    /// Closes connections that stall or stay open too long.
+    pub fn reaper(mut self, reaper: Reaper) -> Self {
+        self.reaper = Some(reaper);
+        self
+    }
+
+    /// Serves HTTPS instead of HTTP.
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Per-client and global request rate limits.
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Answers 429 to clients that downloaded more than their quota in the
+    /// current window.
+    pub fn download_quota(mut self, quota: DownloadQuota) -> Self {
+        self.download_quota = Some(quota);
+        self
+    }
+
+    /// Answers 410 once `limit` downloads have completed.
+    pub fn download_limit(mut self, limit: DownloadLimit) -> Self {
+        self.download_limit = Some(limit);
+        self
+    }
+
+    /// Refuses requests whose line, head or announced body exceed `limits`
+    /// with 414, 431 or 413 (see [`Limits::default`] for the defaults).
+    pub fn request_limits(mut self, limits: Limits) -> Self {
+        self.request_limits = limits;
+        self
+    }
+
+    /// Logs a warning with the time spent parsing, opening and writing for
+    /// requests that take longer than `threshold` in total.
+    pub fn slow_request(mut self, threshold: Duration) -> Self {
+        self.slow_request = Some(threshold);
+        self
+    }
+
+    /// Logs the raw request and response heads of every connection, with
+    /// bodies cut and secrets hidden as `dump` says.
+    pub fn dump_http(mut self, dump: HttpDump) -> Self {
+        self.dump = Some(dump);
+        self
+    }
+
+    /// Caps how fast response bodies are sent, per connection and in total.
+    pub fn bandwidth(mut self, bandwidth: Bandwidth) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
+
+    /// Answers every request with 503 while maintenance mode is on; keep a
+    /// clone of `maintenance` to switch it at runtime.
+    pub fn maintenance(mut self, maintenance: Maintenance) -> Self {
+        self.maintenance = Some(maintenance);
+        self
+    }
+
+    /// Limits on simultaneous connections per client and in total.
+    pub fn connection_limits(mut self, per_ip: Option<usize>, total: Option<usize>) -> Self {
+        self.max_connections_per_ip = per_ip;
+        self.max_connections = total;
+        self
+    }
+
+    /// CIDR allow/deny rules and how denied clients are treated.
+    pub fn ip_filter(mut self, filter: IpFilter, action: DenyAction) -> Self {
+        self.ip_filter = Some(filter);
+        self.deny_action = action;
+        self
+    }
+
+    /// Country lookups for the request logs, and country allow/deny rules
+    /// enforced like the CIDR rules of [`ServerBuilder::ip_filter`].
+    #[cfg(feature = "geoip")]
+    pub fn geoip(mut self, geoip: GeoIp) -> Self {
+        self.geoip = Some(Arc::new(geoip));
+        self
+    }
+
+    /// Records every answered request in an audit database.
+    #[cfg(feature = "audit")]
+    pub fn audit_log(mut self, log: AuditLog) -> Self {
+        self.audit_log = Some(log);
+        self
+    }
+
+    /// Injects the live reload script into HTML pages and serves its event
+    /// stream; see [`LiveReload::watch`] for reloading on file changes.
+    pub fn live_reload(mut self, live_reload: LiveReload) -> Self {
+        self.live_reload = Some(live_reload);
+        self
+    }
+
+    /// Bans clients that cause bursts of client errors; their connections
+    /// are closed at accept time. Keep a clone to lift bans at runtime.
+    pub fn auto_ban(mut self, bans: AutoBan) -> Self {
+        self.auto_ban = Some(bans);
+        self
+    }
+
+    /// Proxies whose forwarding headers identify the real client.
+    pub fn trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = proxies;
+        self
+    }
+
+    /// Bearer tokens; once set, paths without an access rule require a token.
+    pub fn bearer_auth(mut self, auth: BearerAuth) -> Self {
+        self.bearer_auth = Some(auth);
+        self
+    }
+
+    /// Per-path access rules.
+    pub fn access_policy(mut self, policy: AccessPolicy) -> Self {
+        self.access_policy = Some(policy);
+        self
+    }
+
+    /// Accepts signed URLs created with the same secret.
+    pub fn url_signer(mut self, signer: UrlSigner) -> Self {
+        self.url_signer = Some(signer);
+        self
+    }
+
+    /// Refuses requests that are neither signed nor token-authenticated.
+    pub fn require_signed(mut self, require: bool) -> Self {
+        self.require_signed = require;
+        self
+    }
+
+    /// Referer checks for media files, optionally answering with a placeholder
+    /// (content and MIME type) instead of 403.
+    pub fn hotlink_protection(
+        mut self,
+        guard: HotlinkGuard,
+        placeholder: Option<(Vec<u8>, String)>,
+    ) -> Self {
+        self.hotlink_guard = Some(guard);
+        self.hotlink_placeholder = placeholder;
+        self
+    }
+
+    /// Lets clients download directories as archives with
+    /// `?archive=zip` or `?archive=tar.gz`, within `limits`.
+    pub fn archive_downloads(mut self, limits: ArchiveLimits) -> Self {
+        self.archive_limits = Some(limits);
+        self
+    }
+
+    /// Answers requests for directories with a listing of their contents.
+    pub fn auto_index(mut self, index: AutoIndex) -> Self {
+        self.auto_index = Some(index);
+        self
+    }
+
+    /// Adds SHA-256 `ETag` and `Content-Digest` headers to files, and with
+    /// `sidecars` answers `/file.bin.sha256` with a generated checksum line
+    /// when no such file exists.
+    pub fn checksums(mut self, sidecars: bool) -> Self {
+        self.checksums = Some(sidecars);
+        self
+    }
+
+    /// Accepts `PUT` uploads and `DELETE` requests in a directory, normally
+    /// the served root. Its trash directory is not served.
+    ///
+    /// Uploads pass the same middleware as other requests, so combine this
+    /// with bearer tokens or access rules.
+    pub fn writable(mut self, uploads: Uploads) -> Self {
+        self.uploads = Some(uploads);
+        self
+    }
+
+    /// Answers WebDAV `PROPFIND` so the tree can be mounted as a drive, and
+    /// with [`writable`](Self::writable) also `MKCOL`, `COPY` and `MOVE`.
+    pub fn webdav(mut self) -> Self {
+        self.webdav = true;
+        self
+    }
+
+    /// Serves the file tree as JSON at `/_api/tree?path=/docs&depth=2`,
+    /// expanding at most `max_depth` levels.
+    pub fn tree_api(mut self, max_depth: usize) -> Self {
+        self.tree_api_depth = Some(max_depth);
+        self
+    }
+
+    /// Also serves assets under content-hashed names, with immutable caching,
+    /// and their manifest at `/asset-manifest.json`.
+    pub fn fingerprints(mut self, fingerprints: Fingerprints) -> Self {
+        self.fingerprints = Some(fingerprints);
+        self
+    }
+
+    /// Serves `/data` from `data.html` or `data.json` (or other configured
+    /// variants) according to the request's `Accept` header.
+    pub fn variants(mut self, variants: Variants) -> Self {
+        self.variants = Some(variants);
+        self
+    }
+
+    /// Generates `/sitemap.xml` from the HTML files, unless the tree has one.
+    pub fn sitemap(mut self, sitemap: Sitemap) -> Self {
+        self.sitemap = Some(sitemap);
+        self
+    }
+
+    /// Serves full-text search over the text, Markdown and HTML files at
+    /// `/_search?q=...`, and adds a search box to directory listings.
+    pub fn search(mut self, search: Search) -> Self {
+        self.search = Some(search);
+        self
+    }
+
+    /// Answers `/robots.txt` with `robots` when the tree has no such file.
+    pub fn default_robots(mut self, robots: Robots) -> Self {
+        self.robots = Some(robots);
+        self
+    }
+
+    /// Answers `/favicon.ico` with a built-in icon when the tree has none.
+    pub fn default_favicon(mut self) -> Self {
+        self.favicon = true;
+        self
+    }
+
+    /// Offers similarly named files when a file is not found (see
+    /// [`crate::suggest`]).
+    pub fn suggestions(mut self) -> Self {
+        self.suggestions = true;
+        self
+    }
+
+    /// Which files are sent as attachments; `?download=1` works regardless.
+    pub fn attachments(mut self, attachments: Attachments) -> Self {
+        self.attachments = attachments;
+        self
+    }
+
+    /// Security headers added to every response.
+    pub fn security_headers(mut self, headers: SecurityHeaders) -> Self {
+        self.security_headers = headers;
+        self
+    }
+
+    /// Caches files whose names carry a content hash for a year, without
+    /// revalidation.
+    pub fn hashed_names(mut self, names: HashedNames) -> Self {
+        self.hashed_names = Some(names);
+        self
+    }
+
+    /// `Cache-Control` lifetimes for responses that do not set one.
+    pub fn cache_ttls(mut self, ttls: CacheTtls) -> Self {
+        self.cache_ttls = ttls;
+        self
+    }
+
+    /// Adds a custom header rule; rules apply in the order they were added.
+    pub fn header_rule(mut self, rule: HeaderRule) -> Self {
+        self.header_rules.push(rule);
+        self
+    }
+
+    /// Adds a rewrite rule; the first rule matching a request applies.
+    pub fn rewrite_rule(mut self, rule: RewriteRule) -> Self {
+        self.rewrite_rules.push(rule);
+        self
+    }
+
+    /// Applies a redirects file after the rewrite rules.
+    pub fn redirects(mut self, redirects: RedirectMap) -> Self {
+        self.redirects = Some(redirects);
+        self
+    }
+
+    /// Mounts an upstream under a path prefix.
+    pub fn proxy_route(mut self, route: ProxyRoute) -> Self {
+        self.proxy_routes.push(route);
+        self
+    }
+
+    /// Upstream for requests that match no file.
+    pub fn proxy_fallback(mut self, upstream: Upstream) -> Self {
+        self.proxy_fallback = Some(upstream);
+        self
+    }
+
+    /// Runs the CGI scripts of a directory under its prefix.
+    pub fn cgi(mut self, cgi: Cgi) -> Self {
+        self.cgi = Some(cgi);
+        self
+    }
+
+    /// Forwards requests for existing `.php` files to a FastCGI responder.
+    pub fn fastcgi(mut self, fastcgi: FastCgi) -> Self {
+        self.fastcgi = Some(fastcgi);
+        self
+    }
+
+    /// Adds a middleware layer inside the built-in ones (logging, rate limit,
+    /// auth); layers run in the order they were added.
+    pub fn middleware(mut self, layer: impl Middleware + 'static) -> Self {
+        self.middleware.push(Box::new(layer));
+        self
+    }
+
+    /// Answers requests with `handler` instead of serving files from the root.
+    pub fn handler(mut self, handler: impl Handler + 'static) -> Self {
+        self.handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Answers requests whose path matches `pattern` with `handler`.
+    ///
+    /// Routes run inside the middleware chain and are tried in the order
+    /// they were added; unmatched requests go to the file tree (or the
+    /// custom handler).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use file_shover::message::{Request, Response};
+    /// use file_shover::Server;
+    ///
+    /// let server = Server::builder()
+    ///     .root("public")
+    ///     .route("/api/version", |_req: &Request| {
+    ///         Response::new()
+    ///             .content_type("text/plain")
+    ///             .body(env!("CARGO_PKG_VERSION"))
+    ///     })
+    ///     .build()?;
+    /// # Ok::<(), file_shover::error::ServerError>(())
+    /// ```
+    pub fn route(mut self, pattern: impl Into<String>, handler: impl Handler + 'static) -> Self {
+        self.routes.push((pattern.into(), Box::new(handler)));
+        self
+    }
+
+    /// Validates the configuration and binds the listening socket.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Config` for inconsistent settings (nothing to
+    /// serve, token rules without tokens) and `ServerError::Io` if the socket
+    /// cannot be bound.
+    pub fn build(mut self) -> Result<Server, ServerError> {
+        let bind = self.bind.take();
+        let port_retries = self.port_retries;
+        let inherited = self.listener.take();
+        let name = self.name.take();
+        let tls = self.tls.take();
+        let connection_limiter =
+            ConnectionLimiter::new(self.max_connections_per_ip, self.max_connections);
+        let threads = self.threads.unwrap_or(DEFAULT_THREADS);
+        let max_queue = self.max_queue;
+        let reaping = self.reaper.take().map(Reaper::start);
+        let service = self.build_service()?;
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(io::Error::other)?;
+        let listener = match inherited {
+            Some(listener) => listener,
+            None => bind_listener(
+                bind.as_deref().unwrap_or(DEFAULT_BIND_ADDRESS),
+                port_retries,
+            )?,
+        };
+
+        Ok(Server {
+            listener,
+            pool,
+            threads,
+            queued: Arc::new(AtomicUsize::new(0)),
+            max_queue: max_queue.unwrap_or(DEFAULT_MAX_QUEUE),
+            reaping,
+            tls,
+            connection_limiter: Arc::new(connection_limiter),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            stopped: Arc::new(AtomicBool::new(false)),
+            name: name.map(Arc::from),
+            service: Arc::new(RwLock::new(Arc::new(service))),
+            stats: Arc::new(Stats::new()),
+        })
+    }
+
+    /// Validates the configuration and builds the request pipeline alone,
+    /// without binding a socket.
+    ///
+    /// Listener settings (address, threads, TLS, connection limits) are
+    /// ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Config` for inconsistent settings (nothing to
+    /// serve, token rules without tokens).
+    pub fn build_service(self) -> Result<Service, ServerError> {
+        let file_tree = match (self.file_source, self.roots.len()) {
+            (Some(source), _) => Some(FileTree::boxed(source)),
+            (None, 0) => None,
+            (None, 1) => self.roots.into_iter().next().map(FileTree::new),
+            (None, _) => Some(FileTree::from_source(
+                self.roots
+                    .into_iter()
+                    .fold(LayeredSource::new(), |layers, root| {
+                        layers.layer(DiskSource::new(root))
+                    }),
+            )),
+        };
+        let (excluded, ignore_file) = (self.excluded, self.ignore_file);
+        let file_tree = file_tree.map(|tree| {
+            let tree = excluded
+                .iter()
+                .fold(tree, |tree, pattern| tree.exclude(pattern));
+            Arc::new(match &ignore_file {
+                Some(name) => tree.ignore_file(name),
+                None => tree,
+            })
+        });
+        let bearer_auth = self
+            .bearer_auth
+            .unwrap_or_else(|| BearerAuth::new(Vec::new()));
+        let access_policy = self.access_policy.unwrap_or_default();
+        if access_policy.requires_tokens() && bearer_auth.is_empty() {
+            return Err(ConfigError::Invalid(
+                "auth rules require a token but none is configured".to_string(),
+            )
+            .into());
+        }
+        let auth = Auth::new(
+            bearer_auth,
+            access_policy,
+            self.url_signer,
+            self.require_signed,
+        );
+        let checksums = self.checksums.map(|_| Arc::new(ChecksumCache::default()));
+        let mut routes = self.routes;
+        if let (Some(sitemap), Some(file_tree)) = (self.sitemap, &file_tree) {
+            let handler = sitemap.handler(Arc::clone(file_tree), auth.clone());
+            routes.insert(0, (SITEMAP_PATH.to_string(), Box::new(handler)));
+        }
+        let search = self.search.is_some();
+        if let (Some(search), Some(file_tree)) = (self.search, &file_tree) {
+            let handler = search.handler(Arc::clone(file_tree), auth.clone());
+            routes.insert(0, (SEARCH_PATH.to_string(), Box::new(handler)));
+        }
+        if let (Some(max_depth), Some(file_tree)) = (self.tree_api_depth, &file_tree) {
+            let api = TreeApi {
+                file_tree: Arc::clone(file_tree),
+                max_depth,
+                access: auth.clone(),
+            };
+            routes.insert(0, (TREE_API_PATH.to_string(), Box::new(api)));
+        }
+        let sendfile: Option<Box<dyn Handler>> = match (self.sendfile, &file_tree) {
+            (true, Some(file_tree)) => Some(Box::new(FileHandler {
+                file_tree: Arc::clone(file_tree),
+                hotlink_guard: None,
+                hotlink_placeholder: None,
+                archive_limits: None,
+                auto_index: None,
+                checksums: checksums.clone(),
+                checksum_sidecars: false,
+                uploads: self.uploads.clone(),
+                access: auth.clone(),
+                webdav: false,
+                attachments: Attachments::default(),
+                search: false,
+                suggestions: false,
+            })),
+            _ => None,
+        };
+        let handler = match (self.handler, file_tree.clone()) {
+            (Some(handler), _) => handler,
+            (None, Some(file_tree)) => Box::new(FileHandler {
+                file_tree,
+                hotlink_guard: self.hotlink_guard,
+                hotlink_placeholder: self.hotlink_placeholder,
+                archive_limits: self.archive_limits,
+                auto_index: self.auto_index,
+                checksums: checksums.clone(),
+                checksum_sidecars: self.checksums.unwrap_or(false),
+                uploads: self.uploads.clone(),
+                access: auth.clone(),
+                webdav: self.webdav,
+                attachments: self.attachments,
+                search,
+                suggestions: self.suggestions,
+            }),
+            (None, None) if !routes.is_empty() => Box::new(|_req: &Request| {
+                Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY)
+            }),
+            (None, None) => {
+                return Err(ConfigError::Invalid("no root directory configured".to_string()).into())
+            }
+        };
+        let handler: Box<dyn Handler> = match (self.variants, &file_tree) {
+            (Some(variants), Some(file_tree)) => {
+                Box::new(variants.handler(handler, Arc::clone(file_tree)))
+            }
+            _ => handler,
+        };
+        let handler: Box<dyn Handler> = match (self.fingerprints, &file_tree) {
+            (Some(fingerprints), Some(file_tree)) => {
+                Box::new(fingerprints.handler(handler, Arc::clone(file_tree)))
+            }
+            _ => handler,
+        };
+        let handler: Box<dyn Handler> = if self.robots.is_some() || self.favicon {
+            Box::new(Defaults::new(handler, self.robots.as_ref(), self.favicon))
+        } else {
+            handler
+        };
+        let handler: Box<dyn Handler> = if routes.is_empty() {
+            handler
+        } else {
+            let mut router = Router::boxed(handler);
+            for (pattern, route) in routes {
+                router.push(&pattern, route);
+            }
+            Box::new(router)
+        };
+
+        let mut chain = Chain::boxed(handler);
+        chain.push(Box::new(Logging));
+        if let Some(maintenance) = self.maintenance {
+            chain.push(Box::new(maintenance));
+        }
+        if let Some(limiter) = self.rate_limiter {
+            chain.push(Box::new(RateLimit::new(limiter)));
+        }
+        if let Some(quota) = &self.download_quota {
+            chain.push(Box::new(quota.clone()));
+        }
+        let uploads = match self.uploads {
+            Some(uploads) if !auth.is_open() => Some(uploads.access(auth.clone())),
+            uploads => uploads,
+        };
+        if !auth.is_open() {
+            chain.push(Box::new(auth));
+        }
+        if let Some(limit) = &self.download_limit {
+            chain.push(Box::new(limit.clone()));
+        }
+        for layer in self.middleware {
+            chain.push(layer);
+        }
+        // Innermost, so the script goes in before compression
+        if let Some(live_reload) = self.live_reload {
+            chain.push(Box::new(live_reload));
+        }
+
+        Ok(Service {
+            chain,
+            security_headers: self.security_headers,
+            hashed_names: self.hashed_names,
+            cache_ttls: self.cache_ttls,
+            header_rules: self.header_rules,
+            sendfile,
+            rewrite_rules: self.rewrite_rules,
+            redirects: self.redirects,
+            ip_filter: self.ip_filter.unwrap_or_default(),
+            deny_action: self.deny_action,
+            #[cfg(feature = "geoip")]
+            geoip: self.geoip,
+            #[cfg(feature = "audit")]
+            audit_log: self.audit_log,
+            auto_ban: self.auto_ban,
+            trusted_proxies: self.trusted_proxies,
+            proxy_routes: self.proxy_routes,
+            proxy_fallback: self.proxy_fallback,
+            cgi: self.cgi,
+            fastcgi: self.fastcgi,
+            uploads,
+            webdav: self.webdav,
+            file_tree,
+            checksums,
+            bandwidth: self.bandwidth,
+            download_quota: self.download_quota,
+            download_limit: self.download_limit,
+            request_limits: self.request_limits,
+            slow_request: self.slow_request,
+            dump: self.dump,
+        })
+    }
+}
+
+/// A bound file server, ready to [`run`](Server::run).
+pub struct Server {
+    listener: TcpListener,
+    pool: rayon::ThreadPool,
+    threads: usize,
+    /// Connections handed to the pool that no worker has picked up yet.
+    queued: Arc<AtomicUsize>,
+    max_queue: usize,
+    reaping: Option<Arc<Reaping>>,
+    tls: Option<TlsConfig>,
+    connection_limiter: Arc<ConnectionLimiter>,
+    shutdown: Arc<AtomicBool>,
+    /// Set once the accept loop has returned.
+    stopped: Arc<AtomicBool>,
+    name: Option<Arc<str>>,
+    service: Arc<RwLock<Arc<Service>>>,
+    stats: Arc<Stats>,
+}
+
+/// Stops a running [`Server`] from another thread.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    flag: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+    wake_address: SocketAddr,
+}
+
+impl ShutdownHandle {
+    /// Stops accepting connections; [`Server::run`] returns shortly after.
+    ///
+    /// Requests already being served are allowed to finish.
+    pub fn shutdown(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+        // Unblock the accept loop so it notices the flag
+        if let Err(e) = TcpStream::connect_timeout(&self.wake_address, Duration::from_secs(1)) {
+            debug!("Failed to wake accept loop: {}", e);
+        }
+    }
+
+    /// Stops accepting on a listener shared with another process, e.g. the
+    /// successor of a binary upgrade, and returns once [`Server::run`] has
+    /// stopped accepting.
+    ///
+    /// The other process may accept the connections meant to wake this one,
+    /// so they are repeated until the accept loop notices.
+    pub fn hand_over(&self) {
+        while !self.stopped.load(Ordering::SeqCst) {
+            self.shutdown();
+            std::thread::sleep(HAND_OVER_RETRY);
+        }
+    }
+}
+
+/// Swaps the request pipeline of a running [`Server`], e.g. after the
+/// configuration was reloaded.
+#[derive(Clone)]
+pub struct ReloadHandle {
+    service: Arc<RwLock<Arc<Service>>>,
+}
+
+impl ReloadHandle {
+    /// The pipeline currently answering requests.
+    pub fn current(&self) -> Arc<Service> {
+        Arc::clone(&self.service.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Answers new connections with `service`; connections already being
+    /// served finish with the old one.
+    pub fn replace(&self, service: Service) {
+        *self.service.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(service);
+    }
+}
+
+impl Server {
+    /// Starts configuring a server.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// The request pipeline, for answering requests in-process.
+    pub fn service(&self) -> Arc<Service> {
+        self.reload_handle().current()
+    }
+
+    /// Returns a handle that replaces the request pipeline while running.
+    pub fn reload_handle(&self) -> ReloadHandle {
+        ReloadHandle {
+            service: Arc::clone(&self.service),
+        }
+    }
+
+    /// Live request and connection counters.
+    pub fn stats(&self) -> Arc<Stats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// The open connections, per client.
+    pub fn connections(&self) -> Arc<ConnectionLimiter> {
+        Arc::clone(&self.connection_limiter)
+    }
+
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// A second handle to the listening socket, to pass to another process.
+    pub fn try_clone_listener(&self) -> io::Result<TcpListener> {
+        self.listener.try_clone()
+    }
+
+    /// `https` when serving TLS, `http` otherwise.
+    pub fn scheme(&self) -> &'static str {
+        if self.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    /// TLS settings, if serving HTTPS; clones share the certificate, so
+    /// reloading one changes what this server presents.
+    pub fn tls(&self) -> Option<&TlsConfig> {
+        self.tls.as_ref()
+    }
+
+    /// Returns a handle that stops [`run`](Server::run) when triggered.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the listening socket's address cannot be determined.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        let mut wake_address = self.local_addr().expect("listener has a local address");
+        if wake_address.ip().is_unspecified() {
+            wake_address.set_ip(match wake_address.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        ShutdownHandle {
+            flag: Arc::clone(&self.shutdown),
+            stopped: Arc::clone(&self.stopped),
+            wake_address,
+        }
+    }
+
+    /// Accepts and serves connections until shut down.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the listening socket's address cannot be read;
+    /// failures on individual connections are logged instead.
+    pub fn run(self) -> Result<(), ServerError> {
+        let _site = self.site_span().map(Span::entered);
+        info!(
+            "🌐 Listening on: {}://{}",
+            self.scheme(),
+            self.local_addr()?
+        );
+        info!("🔀 Thread pool size: {}", self.threads);
+
+        let mut backoff = ACCEPT_BACKOFF_MIN;
+        for stream in self.listener.incoming() {
+            if self.shutdown.load(Ordering::SeqCst) {
+                // On a listener shared with another process this may be a
+                // client rather than the wake-up connection
+                if let Ok(stream) = stream {
+                    if !hung_up(&stream) {
+                        self.accept(stream);
+                    }
+                }
+                break;
+            }
+            match stream {
+                Ok(stream) => {
+                    backoff = ACCEPT_BACKOFF_MIN;
+                    self.accept(stream);
+                }
+                // The client gave up before we got to it
+                Err(e) if is_peer_error(&e) => debug!("Connection failed: {}", e),
+                Err(e) => {
+                    // Out of descriptors (EMFILE) or memory: retrying at once
+                    // fails the same way, so give connections time to close.
+                    // The pending ones wait in the listen backlog meanwhile.
+                    warn!("Accept failed, retrying in {:?}: {}", backoff, e);
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+                }
+            }
+        }
+        info!("Server stopped accepting connections");
+        self.stopped.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// The span tagging log lines with the server's name, if it has one.
+    fn site_span(&self) -> Option<Span> {
+        self.name
+            .as_ref()
+            .map(|name| info_span!(parent: None, "site", name = %name))
+    }
+
+    fn accept(&self, stream: TcpStream) {
+        let ip = stream
+            .peer_addr()
+            .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |addr| addr.ip());
+        let service = self.service();
+        if !service.trusted_proxies.is_trusted(ip) && !service.is_allowed(ip) {
+            info!("Denied connection from {}", ip);
+            if service.deny_action == DenyAction::Forbid {
+                self.reject(stream, StatusCode::FORBIDDEN, DEFAULT_FORBIDDEN_BODY);
+            }
+            return;
+        }
+        if !service.trusted_proxies.is_trusted(ip) && service.is_banned(ip) {
+            debug!("Dropped connection from banned client {}", ip);
+            return;
+        }
+        if self.queued.load(Ordering::SeqCst) >= self.max_queue {
+            warn!("Rejecting connection from {}: all workers busy", ip);
+            self.stats.record_rejected();
+            self.reject(
+                stream,
+                StatusCode::SERVICE_UNAVAILABLE,
+                DEFAULT_SERVICE_UNAVAILABLE_BODY,
+            );
+            return;
+        }
+        let guard = match self.connection_limiter.try_acquire(ip) {
+            Ok(guard) => guard,
+            Err(reason) => {
+                warn!("Rejecting connection from {}: {}", ip, reason);
+                self.stats.record_rejected();
+                self.reject(
+                    stream,
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    DEFAULT_SERVICE_UNAVAILABLE_BODY,
+                );
+                return;
+            }
+        };
+        self.stats.record_connection();
+        let stats = Arc::clone(&self.stats);
+        let tls = self.tls.clone();
+        let queued = Arc::clone(&self.queued);
+        let reaping = self.reaping.clone();
+        let site = self.site_span();
+        queued.fetch_add(1, Ordering::SeqCst);
+        self.pool.spawn(move || {
+            queued.fetch_sub(1, Ordering::SeqCst);
+            let _site = site.map(Span::entered);
+            // Panics in handlers are answered in `isolate`; this only guards
+            // the rest of the connection handling
+            let served = panic::catch_unwind(AssertUnwindSafe(|| {
+                handle_connection(stream, tls.as_ref(), reaping.as_ref(), &service, &stats)
+            }));
+            if let Err(payload) = served {
+                error!(
+                    "Worker panicked serving {}: {}",
+                    ip,
+                    panic_message(payload.as_ref())
+                );
+            }
+            drop(guard);
+        });
+    }
+
+    /// Answers a connection refused at accept time without handing it to the pool.
+    ///
+    /// TLS connections are closed without an answer, since that would need a
+    /// handshake on the accept thread.
+    fn reject(&self, mut stream: TcpStream, status: StatusCode, body: &'static str) {
+        if self.tls.is_none() {
+            if let Err(e) = stream.set_write_timeout(Some(REJECT_WRITE_TIMEOUT)) {
+                debug!("Failed to set write timeout: {}", e);
+            }
+            let mut response = Response::error(status, body);
+            if let Err(e) = response.write(&mut stream) {
+                debug!("Failed to write rejection response: {}", e);
+            }
+        }
+        if let Err(e) = stream.shutdown(std::net::Shutdown::Both) {
+            debug!("Failed to shutdown stream: {}", e);
+        }
+    }
+}
+
+/// The request pipeline of a [`Server`], usable without a socket.
+///
+/// Embedders and tests can answer requests in-process: [`handle`](Service::handle)
+/// runs a parsed request through the middleware and handler, and
+/// [`serve_request`](Service::serve_request) runs raw bytes through the whole
+/// pipeline as if they had arrived on a connection from `127.0.0.1`.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::message::{StatusCode, Request, Response};
+/// use file_shover::Server;
+///
+/// let service = Server::builder()
+///     .route("/api/version", |_req: &Request| Response::new().body("1.0"))
+///     .build_service()?;
+///
+/// let response = service.handle(&Request::builder().path("/api/version").build());
+/// assert_eq!(response.status, StatusCode::OK);
+///
+/// let raw = service.serve_request(b"GET /missing HTTP/1.1\r\n\r\n");
+/// assert!(raw.starts_with(b"HTTP/1.1 404 Not Found"));
+/// # Ok::<(), file_shover::error::ServerError>(())
+/// ```
+pub struct Service {
+    chain: Chain,
+    security_headers: SecurityHeaders,
+    hashed_names: Option<HashedNames>,
+    cache_ttls: CacheTtls,
+    header_rules: Vec<HeaderRule>,
+    /// Serves the files responses delegate to with `X-Shover-Send`.
+    sendfile: Option<Box<dyn Handler>>,
+    rewrite_rules: Vec<RewriteRule>,
+    redirects: Option<RedirectMap>,
+    ip_filter: IpFilter,
+    deny_action: DenyAction,
+    #[cfg(feature = "geoip")]
+    geoip: Option<Arc<GeoIp>>,
+    #[cfg(feature = "audit")]
+    audit_log: Option<AuditLog>,
+    auto_ban: Option<AutoBan>,
+    trusted_proxies: TrustedProxies,
+    proxy_routes: Vec<ProxyRoute>,
+    proxy_fallback: Option<Upstream>,
+    cgi: Option<Cgi>,
+    fastcgi: Option<FastCgi>,
+    uploads: Option<Uploads>,
+    webdav: bool,
+    file_tree: Option<Arc<FileTree>>,
+    checksums: Option<Arc<ChecksumCache>>,
+    bandwidth: Bandwidth,
+    download_quota: Option<DownloadQuota>,
+    download_limit: Option<DownloadLimit>,
+    request_limits: Limits,
+    slow_request: Option<Duration>,
+    dump: Option<HttpDump>,
+}
+
+impl Service {
+    /// Answers a parsed request with the middleware chain and handler, then
+    /// adds the configured response headers.
+    ///
+    /// Proxy routes and the fallback upstream are not consulted, and the
+    /// client is taken to be `127.0.0.1` unless `req.client_ip` is set.
+    pub fn handle(&self, req: &Request) -> Response {
+        let mut req = req.clone();
+        req.client_ip.get_or_insert(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let response = match self.rewrite(&mut req) {
+            Some(redirect) => redirect,
+            None => self.delegate(&req, isolate(&req, || self.chain.handle(&req))),
+        };
+        self.finish(req.path(), response)
+    }
+
+    /// Runs raw request bytes through the full pipeline, including proxying,
+    /// and returns everything written back.
+    pub fn serve_request(&self, raw: &[u8]) -> Vec<u8> {
+        let mut loopback = Loopback {
+            input: Cursor::new(raw),
+            output: Vec::new(),
+        };
+        let peer = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+        handle_client(&mut loopback, Some(peer), "http", None, self);
+        loopback.output
+    }
+
+    /// Reads the served files, smallest first, until `budget` bytes were
+    /// read, so their digests and cached data are ready before the first
+    /// requests. Does nothing without a file tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a directory cannot be listed; unreadable files
+    /// are skipped.
+    pub fn prewarm(&self, budget: u64) -> io::Result<Prewarmed> {
+        match &self.file_tree {
+            Some(tree) => prewarm::prewarm(tree, self.checksums.as_deref(), budget),
+            None => Ok(Prewarmed::default()),
+        }
+    }
+
+    /// Drops cached checksums and cached file data (metadata and small
+    /// objects kept by the file source) for `path` and everything below it,
+    /// or for every path with `None`. Returns how many entries were dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` for paths that could escape the root.
+    pub fn purge_caches(&self, path: Option<&str>) -> io::Result<usize> {
+        let files = match &self.file_tree {
+            Some(tree) => tree.purge(path)?,
+            None => 0,
+        };
+        let checksums = self.checksums.as_ref().map_or(0, |cache| cache.purge(path));
+        Ok(files + checksums)
+    }
+
+    /// Checks `ip` against the CIDR rules and, with a GeoIP database, the
+    /// country rules.
+    fn is_allowed(&self, ip: IpAddr) -> bool {
+        #[cfg(feature = "geoip")]
+        if self
+            .geoip
+            .as_ref()
+            .is_some_and(|geoip| !geoip.is_allowed(ip))
+        {
+            return false;
+        }
+        self.ip_filter.is_allowed(ip)
+    }
+
+    /// Applies the rewrite rules, then the redirects file: an internal
+    /// rewrite changes `req`, a redirect is returned.
+    fn rewrite(&self, req: &mut Request) -> Option<Response> {
+        let rewritten = rewrite::rewrite(&self.rewrite_rules, req)
+            .or_else(|| self.redirects.as_ref()?.lookup(req))?;
+        match rewritten {
+            Rewritten::Internal(target) => {
+                debug!("Rewrote {} to {}", req.target, target);
+                req.target = target;
+                None
+            }
+            Rewritten::Redirect { status, location } => Some(
+                Response::new()
+                    .status(status)
+                    .header("Location", location)
+                    .content_length(0u64),
+            ),
+        }
+    }
+
+    fn is_banned(&self, ip: IpAddr) -> bool {
+        self.auto_ban
+            .as_ref()
+            .is_some_and(|bans| bans.is_banned(ip))
+    }
+
+    /// Answers with the file `response` names in `X-Shover-Send`, if
+    /// enabled and it names one.
+    fn delegate(&self, req: &Request, response: Response) -> Response {
+        match &self.sendfile {
+            Some(files) => isolate(req, || sendfile::delegate(files.as_ref(), req, response)),
+            None => response,
+        }
+    }
+
+    /// Adds the security headers, cache lifetime and header rules for `path`.
+    fn finish(&self, path: &str, mut response: Response) -> Response {
+        self.security_headers.apply(path, &mut response);
+        if let Some(hashed_names) = &self.hashed_names {
+            hashed_names.apply(path, &mut response);
+        }
+        self.cache_ttls.apply(path, &mut response);
+        for rule in &self.header_rules {
+            rule.apply(path, &mut response);
+        }
+        response
+    }
+}
+
+/// An in-memory connection for [`Service::serve_request`].
+struct Loopback<'a> {
+    input: Cursor<&'a [u8]>,
+    output: Vec<u8>,
+}
+
+impl Read for Loopback<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Loopback<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A buffered client connection that can also be written to.
+///
+/// Keeping one buffer for the whole exchange matters: a proxied request body
+/// follows the head, and part of it may already have been read ahead. The
+/// buffer comes from the worker's pool rather than a fresh allocation.
+struct Connection<S: Read + Write> {
+    stream: S,
+    buffer: pool::Buffer,
+    /// Read position and end of the buffered bytes.
+    pos: usize,
+    filled: usize,
+    /// Bytes read from the client so far.
+    received: u64,
+    /// Copy of the traffic for `--dump-http`.
+    recording: Option<Recording>,
+}
+
+impl<S: Read + Write> Connection<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream,
+            buffer: pool::take(CONNECTION_BUFFER_SIZE),
+            pos: 0,
+            filled: 0,
+            received: 0,
+            recording: None,
+        }
+    }
+}
+
+impl<S: Read + Write> Read for Connection<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Large reads skip the buffer once it is drained, like BufReader
+        if self.pos == self.filled && buf.len() >= self.buffer.len() {
+            let read = self.stream.read(buf)?;
+            self.received += read as u64;
+            if let Some(recording) = &mut self.recording {
+                recording.received(&buf[..read]);
+            }
+            return Ok(read);
+        }
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<S: Read + Write> BufRead for Connection<S> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.filled {
+            self.filled = self.stream.read(&mut self.buffer)?;
+            self.pos = 0;
+            self.received += self.filled as u64;
+            if let Some(recording) = &mut self.recording {
+                recording.received(&self.buffer[..self.filled]);
+            }
+        }
+        Ok(&self.buffer[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.filled);
+    }
+}
+
+impl<S: Read + Write> Write for Connection<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.stream.write(buf)?;
+        if let Some(recording) = &mut self.recording {
+            recording.sent(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let written = self.stream.write_vectored(bufs)?;
+        if let Some(recording) = &mut self.recording {
+            let mut left = written;
+            for buf in bufs {
+                let part = left.min(buf.len());
+                recording.sent(&buf[..part]);
+                left -= part;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Answers a form upload by sending the browser back to the listing.
+fn form_response(req: &Request, result: Result<usize, UploadError>) -> Response {
+    match result {
+        Ok(stored) => {
+            info!("Stored {} file(s) in {}", stored, req.path());
+            let location = format!("{}/", req.target.encoded_path().trim_end_matches('/'));
+            Response::new()
+                .status(StatusCode::SEE_OTHER)
+                .header("Location", location)
+                .content_length(0u64)
+        }
+        Err(e) => write_error(req, e),
+    }
+}
+
+/// Answers a DELETE request with the outcome of the deletion.
+fn delete_response(req: &Request, result: Result<(), UploadError>) -> Response {
+    match result {
+        Ok(()) => {
+            info!("Deleted {}", req.path());
+            Response::new().status(StatusCode::NO_CONTENT)
+        }
+        Err(e) => write_error(req, e),
+    }
+}
+
+/// Answers a PUT request with the outcome of the upload.
+fn upload_response(req: &Request, result: Result<Uploaded, UploadError>) -> Response {
+    match result {
+        Ok(Uploaded::Created) => {
+            info!("Stored new file {}", req.path());
+            Response::new()
+                .status(StatusCode::CREATED)
+                .header("Location", req.target.encoded_path())
+                .content_length(0u64)
+        }
+        Ok(Uploaded::Replaced) => {
+            info!("Replaced file {}", req.path());
+            Response::new().status(StatusCode::NO_CONTENT)
+        }
+        Err(e) => write_error(req, e),
+    }
+}
+
+/// Maps a failed PUT or DELETE to its status code.
+fn write_error(req: &Request, e: UploadError) -> Response {
+    info!("{} {} failed: {}", req.method, req.path(), e);
+    e.response()
+}
+
+/// How writing a response went.
+struct Sent {
+    status: u16,
+    /// Body bytes that went out, also when writing failed part way.
+    bytes: u64,
+    result: io::Result<()>,
+}
+
+/// Adds the configured headers to `response` and writes it.
+fn send<W: Write>(response: Response, path: &str, service: &Service, writer: &mut W) -> Sent {
+    let mut response = service.finish(path, response);
+    let mut bytes = 0;
+    let result = response
+        .write_counted(writer, &mut bytes)
+        .and_then(|()| writer.flush());
+    Sent {
+        status: response.status.as_u16(),
+        bytes,
+        result,
+    }
+}
+
+/// Returns whether `e` means the client went away: the connection was
+/// closed or reset while the response was being written.
+fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::WriteZero
+    )
+}
+
+/// How a request is answered once it has been admitted.
+enum Reply<'a> {
+    Local(Response),
+    /// Forward to an upstream; the path is relative to the route prefix.
+    Proxy(&'a Upstream, &'a str),
+}
+
+/// Forwards a request to an upstream, answering 502 if it is unreachable.
+fn forward<C: BufRead + Write>(
+    upstream: &Upstream,
+    path: &str,
+    req: &Request,
+    client_ip: IpAddr,
+    scheme: &str,
+    client: &mut C,
+    service: &Service,
+) -> Sent {
+    match upstream.forward(
+        req,
+        path,
+        client_ip,
+        scheme,
+        client,
+        service.sendfile.is_some(),
+    ) {
+        Ok(Relayed {
+            delegated: Some(headers),
+            ..
+        }) => {
+            let mut response = Response::new();
+            response.headers.extend(headers);
+            let response = service.delegate(req, response);
+            send(response, req.path(), service, client)
+        }
+        Ok(relayed) => Sent {
+            status: relayed.status,
+            bytes: relayed.bytes,
+            result: Ok(()),
+        },
+        Err(UpstreamError::Io(e)) => Sent {
+            status: StatusCode::BAD_GATEWAY.as_u16(),
+            bytes: 0,
+            result: Err(e),
+        },
+        Err(e) => {
+            warn!("Proxying {} to {} failed: {}", req.path(), upstream, e);
+            let response = Response::error(StatusCode::BAD_GATEWAY, DEFAULT_BAD_GATEWAY_BODY);
+            send(response, req.path(), service, client)
+        }
+    }
+}
+
+/// Binds `address`, moving on to the next port up to `retries` times while
+/// the port is in use.
+fn bind_listener(address: &str, retries: u16) -> io::Result<TcpListener> {
+    let addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+    let mut attempt = 0;
+    loop {
+        let shifted: Vec<SocketAddr> = addresses
+            .iter()
+            .filter_map(|address| {
+                let port = address.port().checked_add(attempt)?;
+                Some(SocketAddr::new(address.ip(), port))
+            })
+            .collect();
+        match TcpListener::bind(&shifted[..]) {
+            Err(e)
+                if e.kind() == ErrorKind::AddrInUse
+                    && attempt < retries
+                    && addresses.iter().all(|address| address.port() != 0) =>
+            {
+                warn!("Port {} is in use, trying the next one", shifted[0].port());
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether the peer closed `stream` without sending anything, as the
+/// connections waking the accept loop do.
+fn hung_up(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    let closed = matches!(stream.peek(&mut [0]), Ok(0));
+    stream.set_nonblocking(false).is_err() || closed
+}
+
+/// Serves one connection, terminating TLS first if configured.
+fn handle_connection(
+    stream: TcpStream,
+    tls: Option<&TlsConfig>,
+    reaping: Option<&Arc<Reaping>>,
+    service: &Service,
+    stats: &Stats,
+) {
+    let peer = stream.peer_addr().ok();
+    let connection_span = info_span!(
+        "connection",
+        peer = %peer.map_or_else(|| "unknown".to_string(), |addr| addr.to_string()),
+        cert = field::Empty,
+    );
+    let _connection = connection_span.enter();
+
+    let tracked = reaping.and_then(|reaping| match reaping.track(&stream) {
+        Ok(tracked) => Some(tracked),
+        Err(e) => {
+            debug!("Failed to track connection: {}", e);
+            None
+        }
+    });
+    let watched = tracked.as_ref().map_or_else(
+        || Watched::unwatched(&stream),
+        |tracked| tracked.watch(&stream),
+    );
+    let (served, received) = match tls {
+        None => handle_client(watched, peer, "http", None, service),
+        Some(tls) => match tls.accept() {
+            Ok(session) => {
+                let mut tls_stream = rustls::StreamOwned::new(session, watched);
+                // Finish the handshake up front so the client certificate,
+                // if any, is known before the request is read
+                let handshake = tls_stream.conn.complete_io(&mut tls_stream.sock);
+                let client_cert = tls_stream
+                    .conn
+                    .peer_certificates()
+                    .and_then(|chain| chain.first())
+                    .and_then(|cert| ClientCert::from_der(cert));
+                if let Some(cert) = &client_cert {
+                    connection_span.record("cert", cert.subject.as_str());
+                }
+                let served = match handshake {
+                    Ok(_) => {
+                        debug!("TLS session: {}", tls::negotiated(&tls_stream.conn));
+                        handle_client(
+                            &mut tls_stream,
+                            peer,
+                            "https",
+                            client_cert.as_ref(),
+                            service,
+                        )
+                    }
+                    Err(e) => {
+                        debug!("TLS handshake failed: {}", e);
+                        (None, 0)
+                    }
+                };
+                tls_stream.conn.send_close_notify();
+                if let Err(e) = tls_stream.flush() {
+                    debug!("Failed to send close_notify: {}", e);
+                }
+                served
+            }
+            Err(e) => {
+                error!("Failed to start TLS session: {}", e);
+                (None, 0)
+            }
+        },
+    };
+    stats.record_received(received);
+    if let Some(served) = served {
+        stats.record_response(served.status, served.bytes);
+        if served.aborted {
+            stats.record_aborted();
+        }
+        if let Some(path) = &served.path {
+            stats.record_path(path, served.bytes);
+            stats.record_request(served.client, path, served.status, served.bytes);
+        }
+    }
+
+    if let Err(e) = stream.shutdown(std::net::Shutdown::Both) {
+        debug!("Failed to shutdown stream: {}", e);
+    }
+}
+
+/// A response written by [`handle_client`].
+struct Served {
+    /// Request path, unless the request could not be parsed.
+    path: Option<String>,
+    /// The client, as forwarded by a trusted proxy if any.
+    client: IpAddr,
+    status: u16,
+    /// Body bytes sent.
+    bytes: u64,
+    /// The client went away before the whole response was written.
+    aborted: bool,
+}
+
+/// Parses and answers one request.
+///
+/// Returns what was sent, if a response was written, and the number of
+/// bytes read from the client.
+fn handle_client<S: Read + Write>(
+    stream: S,
+    peer: Option<SocketAddr>,
+    scheme: &str,
+    client_cert: Option<&ClientCert>,
+    service: &Service,
+) -> (Option<Served>, u64) {
+    let mut conn = Connection::new(service.bandwidth.throttle(stream));
+    conn.recording = service.dump.as_ref().map(HttpDump::recording);
+    let served = answer(&mut conn, peer, scheme, client_cert, service);
+    if let (Some(dump), Some(recording)) = (&service.dump, &conn.recording) {
+        dump.log(recording);
+    }
+    (served, conn.received)
+}
+
+/// Time spent in each phase of a request.
+#[derive(Debug, Clone, Copy, Default)]
+struct Timing {
+    /// From the first byte of the request to the end of its head.
+    parse: Duration,
+    /// Until the response is ready to send: opening the file, building a
+    /// listing or running a handler.
+    open: Duration,
+    /// Sending the response, including reading a file's content or waiting
+    /// on an upstream.
+    write: Duration,
+}
+
+impl Timing {
+    fn total(&self) -> Duration {
+        self.parse + self.open + self.write
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "parse {:?}, open {:?}, write {:?}",
+            self.parse, self.open, self.write
+        )
+    }
+}
+
+/// Reads a request from `conn` and answers it.
+fn answer<S: Read + Write>(
+    conn: &mut Connection<S>,
+    peer: Option<SocketAddr>,
+    scheme: &str,
+    client_cert: Option<&ClientCert>,
+    service: &Service,
+) -> Option<Served> {
+    let peer_ip = peer.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |addr| addr.ip());
+
+    // Wait for the first byte so an idle client does not count as parsing
+    if let Err(e) = conn.fill_buf() {
+        debug!("Failed to read request: {}", e);
+        return None;
+    }
+    let mut timing = Timing::default();
+    let parsing = Instant::now();
+
+    // Parse the request and handle parsing errors
+    let limits = &service.request_limits;
+    let mut req = match parser::read_request(conn, limits) {
+        Ok(request) => request,
+        Err(e) => {
+            let response = match e {
+                // The connection failed or closed early; nobody to answer
+                RequestError::Io(e) => {
+                    debug!("Failed to read request: {}", e);
+                    return None;
+                }
+                RequestError::UriTooLong => {
+                    info!(
+                        "Refused request from {}: request line longer than {} bytes",
+                        peer_ip, limits.max_request_line
+                    );
+                    Response::error(StatusCode::URI_TOO_LONG, DEFAULT_URI_TOO_LONG_BODY)
+                }
+                RequestError::HeadersTooLarge => {
+                    info!(
+                        "Refused request from {}: more than {} headers or {} bytes of head",
+                        peer_ip, limits.max_headers, limits.max_head
+                    );
+                    Response::error(
+                        StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                        DEFAULT_HEADERS_TOO_LARGE_BODY,
+                    )
+                }
+                RequestError::PayloadTooLarge => {
+                    info!(
+                        "Refused request from {}: body larger than {} bytes",
+                        peer_ip,
+                        limits.max_body.unwrap_or(u64::MAX)
+                    );
+                    Response::error(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        DEFAULT_PAYLOAD_TOO_LARGE_BODY,
+                    )
+                }
+                e => {
+                    debug!("Failed to parse request: {}", e);
+                    Response::error(StatusCode::BAD_REQUEST, DEFAULT_BAD_REQUEST_BODY)
+                }
+            };
+
+            let sent = send(response, "", service, conn);
+            return match sent.result {
+                Ok(()) => Some(Served {
+                    path: None,
+                    client: peer_ip,
+                    status: sent.status,
+                    bytes: sent.bytes,
+                    aborted: false,
+                }),
+                Err(write_err) => {
+                    debug!("Failed to write error response: {}", write_err);
+                    None
+                }
+            };
+        }
+    };
+    timing.parse = parsing.elapsed();
+
+    let client_ip = service.trusted_proxies.client_ip(
+        peer_ip,
+        req.header("Forwarded"),
+        req.header("X-Forwarded-For"),
+    );
+    req.client_ip = Some(client_ip);
+    req.client_cert = client_cert.cloned();
+    let request_span = info_span!(
+        "request",
+        client = %client_ip,
+        method = %req.method,
+        path = %req.path(),
+        country = field::Empty,
+        status = field::Empty,
+        bytes = field::Empty,
+        duration_us = field::Empty,
+        parse_us = field::Empty,
+        open_us = field::Empty,
+        write_us = field::Empty,
+    );
+    let _request = request_span.enter();
+    #[cfg(feature = "geoip")]
+    if let Some(country) = service
+        .geoip
+        .as_ref()
+        .and_then(|geoip| geoip.country(client_ip))
+    {
+        request_span.record("country", country);
+    }
+    let started = Instant::now();
+    let redirect = service.rewrite(&mut req);
+
+    // Connections from trusted proxies skip the accept-time IP check, so the
+    // forwarded client is checked here instead
+    let reply = if service.trusted_proxies.is_trusted(peer_ip) && !service.is_allowed(client_ip) {
+        info!("Denied forwarded client {}", client_ip);
+        if service.deny_action == DenyAction::Drop {
+            return None;
+        }
+        Reply::Local(Response::error(
+            StatusCode::FORBIDDEN,
+            DEFAULT_FORBIDDEN_BODY,
+        ))
+    } else if service.trusted_proxies.is_trusted(peer_ip) && service.is_banned(client_ip) {
+        debug!("Refused banned forwarded client {}", client_ip);
+        Reply::Local(Response::error(
+            StatusCode::FORBIDDEN,
+            DEFAULT_FORBIDDEN_BODY,
+        ))
+    } else if let Some(redirect) = redirect {
+        Reply::Local(redirect)
+    } else if let Some(uploads) = service.uploads.as_ref().filter(|uploads| {
+        uploads.is_resumable(req.path())
+            || match req.method {
+                HttpMethod::PUT | HttpMethod::DELETE => true,
+                HttpMethod::POST => uploads.accepts_forms(),
+                HttpMethod::MKCOL | HttpMethod::COPY | HttpMethod::MOVE => service.webdav,
+                _ => false,
+            }
+    }) {
+        match isolate(&req, || service.chain.before(&req)) {
+            Some(rejection) => Reply::Local(rejection),
+            None if uploads.is_resumable(req.path()) => {
+                Reply::Local(uploads.handle_resumable(&req, conn))
+            }
+            None => Reply::Local(match req.method {
+                HttpMethod::DELETE => delete_response(&req, uploads.delete(&req)),
+                HttpMethod::POST => form_response(&req, uploads.post_form(&req, conn)),
+                HttpMethod::MKCOL | HttpMethod::COPY | HttpMethod::MOVE => {
+                    webdav::write(uploads, &req)
+                }
+                _ => upload_response(&req, uploads.put(&req, conn)),
+            }),
+        }
+    } else if let Some(cgi) = service.cgi.as_ref().filter(|cgi| cgi.matches(req.path())) {
+        match isolate(&req, || service.chain.before(&req)) {
+            Some(rejection) => Reply::Local(rejection),
+            None => Reply::Local(match cgi.find(req.path()) {
+                Some(script) => cgi.run(&req, &script, client_ip, scheme, conn),
+                None => Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY),
+            }),
+        }
+    } else if let Some((fastcgi, script)) = service
+        .fastcgi
+        .as_ref()
+        .and_then(|fastcgi| Some((fastcgi, fastcgi.find(req.path())?)))
+    {
+        match isolate(&req, || service.chain.before(&req)) {
+            Some(rejection) => Reply::Local(rejection),
+            None => Reply::Local(fastcgi.run(&req, &script, client_ip, scheme, conn)),
+        }
+    } else if let Some((route, path)) = ProxyRoute::find(&service.proxy_routes, req.path()) {
+        match isolate(&req, || service.chain.before(&req)) {
+            Some(rejection) => Reply::Local(rejection),
+            None => Reply::Proxy(route.upstream(), path),
+        }
+    } else {
+        let response = isolate(&req, || service.chain.handle(&req));
+        match &service.proxy_fallback {
+            Some(upstream)
+                if matches!(
+                    response.status,
+                    StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+                ) =>
+            {
+                Reply::Proxy(upstream, req.path())
+            }
+            _ => Reply::Local(service.delegate(&req, response)),
+        }
+    };
+
+    timing.open = started.elapsed();
+    let writing = Instant::now();
+    let sent = match reply {
+        Reply::Local(response) => send(response, req.path(), service, conn),
+        Reply::Proxy(upstream, path) => {
+            forward(upstream, path, &req, client_ip, scheme, conn, service)
+        }
+    };
+
+    let (status, bytes) = (sent.status, sent.bytes);
+    request_span.record("status", status);
+    request_span.record("bytes", bytes);
+    let aborted = match &sent.result {
+        Ok(()) => false,
+        Err(e) if is_disconnect(e) => {
+            info!("Client disconnected after {} body bytes: {}", bytes, e);
+            true
+        }
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+            // The file shrank while being sent: the connection is closed
+            // short of Content-Length, so the client knows it is incomplete
+            warn!("Response for {} cut short: {}", req.path(), e);
+            false
+        }
+        Err(e) => {
+            debug!("Failed to write response: {}", e);
+            true
+        }
+    };
+    let duration = started.elapsed();
+    timing.write = writing.elapsed();
+    request_span.record("duration_us", duration.as_micros() as u64);
+    request_span.record("parse_us", timing.parse.as_micros() as u64);
+    request_span.record("open_us", timing.open.as_micros() as u64);
+    request_span.record("write_us", timing.write.as_micros() as u64);
+    if let Some(threshold) = service.slow_request {
+        if timing.total() > threshold {
+            warn!(
+                "Slow request: {} {} took {:?} ({})",
+                req.method,
+                req.path(),
+                timing.total(),
+                timing
+            );
+        }
+    }
+    if let Some(quota) = &service.download_quota {
+        quota.record(client_ip, bytes);
+    }
+    if let Some(limit) = &service.download_limit {
+        limit.record(&req, status, aborted);
+    }
+    if let Some(bans) = &service.auto_ban {
+        bans.record(client_ip, status);
+    }
+    #[cfg(feature = "audit")]
+    if let Some(audit_log) = &service.audit_log {
+        audit_log.record(AuditRecord {
+            timestamp: std::time::SystemTime::now(),
+            client: client_ip,
+            method: req.method.to_string(),
+            path: req.path().to_string(),
+            status,
+            bytes,
+            duration,
+            user_agent: req.header("User-Agent").map(str::to_string),
+        });
+    }
+    info!("Request served");
+    Some(Served {
+        path: Some(req.path().to_string()),
+        client: client_ip,
+        status,
+        bytes,
+        aborted,
+    })
+}
+
+/// Runs middleware or handler code, answering 500 if it panics.
+///
+/// `T` is the hook's result: a `Response`, or the `Option<Response>` of a
+/// before-hook, where the 500 short-circuits the chain.
+fn isolate<T: From<Response>>(req: &Request, work: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(work)).unwrap_or_else(|payload| {
+        error!(
+            "Handler panicked on {} {}: {}",
+            req.method,
+            req.path(),
+            panic_message(payload.as_ref())
+        );
+        Response::error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            DEFAULT_INTERNAL_ERROR_BODY,
+        )
+        .into()
+    })
+}
+
+/// Whether a failed `accept()` concerns only the one connection, which the
+/// client closed or reset while it waited in the backlog.
+fn is_peer_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset | ErrorKind::Interrupted
+    )
+}
+
+/// The message passed to `panic!`, if it was a string.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "<non-string panic>"
+    }
+}
+
+/// `/_api/tree`: the served files below `path` as nested JSON.
+struct TreeApi {
+    file_tree: Arc<FileTree>,
+    max_depth: usize,
+    /// Filters the files a request reaches through the `path` parameter.
+    access: Auth,
+}
+
+impl Handler for TreeApi {
+    fn handle(&self, req: &Request) -> Response {
+        if !matches!(req.method, HttpMethod::GET | HttpMethod::HEAD) {
+            return Response::error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                DEFAULT_METHOD_NOT_ALLOWED_BODY,
+            )
+            .header("Allow", "GET, HEAD");
+        }
+        let path = req.query_param("path").map(decode_component);
+        let depth = req.query_param("depth").map(str::parse::<usize>);
+        let (path, depth) = match (path, depth) {
+            (Some(Err(_)), _) | (_, Some(Err(_))) => {
+                return Response::error(StatusCode::BAD_REQUEST, DEFAULT_BAD_REQUEST_BODY)
+            }
+            (path, depth) => (
+                path.and_then(Result::ok).unwrap_or_else(|| "/".to_string()),
+                depth.and_then(Result::ok).unwrap_or(self.max_depth),
+            ),
+        };
+        if path.contains("..") {
+            return Response::error(StatusCode::BAD_REQUEST, DEFAULT_BAD_REQUEST_BODY);
+        }
+        if !self.access.can_read(req, &path) {
+            info!("Rejected tree request for {}", path);
+            return Response::error(StatusCode::FORBIDDEN, DEFAULT_FORBIDDEN_BODY);
+        }
+        let depth = depth.min(self.max_depth);
+        let readable = |path: &str| self.access.can_read(req, path);
+        match TreeNode::walk(&self.file_tree, &path, depth, readable) {
+            Ok(node) => {
+                let json = serde_json::to_string(&node).unwrap_or_default();
+                Response::new()
+                    .status(StatusCode::OK)
+                    .content_type("application/json")
+                    .content_length(json.len())
+                    .body(json)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY)
+            }
+            Err(e) => {
+                info!("Server error walking {}: {}", path, e);
+                Response::error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    DEFAULT_INTERNAL_ERROR_BODY,
+                )
+            }
+        }
+    }
+}
+
+/// The default handler: files from the root, with hotlink protection,
+/// optional directory listings and directory archives.
+struct FileHandler {
+    file_tree: Arc<FileTree>,
+    hotlink_guard: Option<HotlinkGuard>,
+    hotlink_placeholder: Option<(Vec<u8>, String)>,
+    archive_limits: Option<ArchiveLimits>,
+    auto_index: Option<AutoIndex>,
+    checksums: Option<Arc<ChecksumCache>>,
+    checksum_sidecars: bool,
+    /// Consulted to keep the trash out of reach.
+    uploads: Option<Uploads>,
+    /// Filters the files a request reaches through a directory.
+    access: Auth,
+    webdav: bool,
+    attachments: Attachments,
+    /// Listings offer a search box.
+    search: bool,
+    /// 404s offer similarly named files.
+    suggestions: bool,
+}
+
+impl FileHandler {
+    /// Generates `/file.bin.sha256` for an existing `/file.bin`, or returns
+    /// `None` if the request is not for a sidecar.
+    fn sidecar(&self, req: &Request, checksums: &ChecksumCache) -> Option<Response> {
+        let path = req.path().strip_suffix(".sha256")?;
+        let metadata = self.file_tree.metadata(path).ok()?;
+        if metadata.is_dir {
+            return None;
+        }
+        let name = path.rsplit('/').next().unwrap_or(path);
+        Some(match checksums.sha256(&self.file_tree, path, &metadata) {
+            Ok(digest) => {
+                let line = digest.sidecar(name);
+                Response::new()
+                    .status(StatusCode::OK)
+                    .content_type("text/plain; charset=utf-8")
+                    .content_length(line.len())
+                    .body(line)
+            }
+            Err(e) => {
+                info!("Server error hashing {}: {}", path, e);
+                Response::error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    DEFAULT_INTERNAL_ERROR_BODY,
+                )
+            }
+        })
+    }
+
+    /// Lists the requested directory, or returns `None` if it is not one.
+    fn listing(&self, req: &Request, index: &AutoIndex) -> Option<Response> {
+        let dir = req.path();
+        if dir.split('/').any(|segment| segment.starts_with('.')) {
+            return None;
+        }
+        if !self.file_tree.metadata(dir).ok()?.is_dir {
+            return None;
+        }
+        if !dir.ends_with('/') {
+            // Relative links in the listing need the trailing slash
+            let mut location = format!("{}/", req.target.encoded_path());
+            if let Some(query) = req.query() {
+                location.push('?');
+                location.push_str(query);
+            }
+            return Some(
+                Response::new()
+                    .status(StatusCode::MOVED_PERMANENTLY)
+                    .header("Location", location)
+                    .content_length(0u64),
+            );
+        }
+        let query = match ListingQuery::parse(req.query()) {
+            Ok(query) => query,
+            Err(e) => {
+                info!("Bad listing query for {}: {}", dir, e);
+                return Some(Response::error(
+                    StatusCode::BAD_REQUEST,
+                    DEFAULT_BAD_REQUEST_BODY,
+                ));
+            }
+        };
+        let mut listing = match self.file_tree.list(dir) {
+            Ok(entries) => {
+                let mut listing = Listing::new(dir, entries);
+                listing.upload = self.uploads.as_ref().is_some_and(Uploads::accepts_forms);
+                listing.search = self.search;
+                listing
+            }
+            Err(e) => {
+                info!("Server error listing {}: {}", dir, e);
+                return Some(Response::error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    DEFAULT_INTERNAL_ERROR_BODY,
+                ));
+            }
+        };
+        listing.apply(&query);
+        if query.json {
+            let json = serde_json::to_string(&listing).unwrap_or_default();
+            return Some(
+                Response::new()
+                    .status(StatusCode::OK)
+                    .content_type("application/json")
+                    .content_length(json.len())
+                    .body(json),
+            );
+        }
+        Some(match index.render(&listing) {
+            Ok(html) => Response::new()
+                .status(StatusCode::OK)
+                .content_type("text/html; charset=utf-8")
+                .content_length(html.len())
+                .body(html),
+            Err(e) => {
+                error!("Listing template failed for {}: {}", dir, e);
+                Response::error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    DEFAULT_INTERNAL_ERROR_BODY,
+                )
+            }
+        })
+    }
+
+    /// Streams the requested directory as an archive.
+    fn archive(&self, req: &Request, format: ArchiveFormat, limits: &ArchiveLimits) -> Response {
+        let dir = req.path();
+        let entries = match archive::collect(&self.file_tree, dir, limits, |path| {
+            self.access.can_read(req, path)
+        }) {
+            Ok(entries) => entries,
+            Err(ArchiveError::Io(e)) if e.kind() == ErrorKind::NotFound => {
+                info!("Directory not found: {}", dir);
+                return Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY);
+            }
+            Err(e @ (ArchiveError::TooLarge | ArchiveError::TooManyEntries)) => {
+                info!("Refused archive of {}: {}", dir, e);
+                return Response::error(StatusCode::FORBIDDEN, DEFAULT_FORBIDDEN_BODY);
+            }
+            Err(e) => {
+                info!("Server error for {}: {}", dir, e);
+                return Response::error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    DEFAULT_INTERNAL_ERROR_BODY,
+                );
+            }
+        };
+        let base = dir
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+            .unwrap_or("site");
+        let filename = format!("{}.{}", base, format.extension());
+        let response = Response::new()
+            .status(StatusCode::OK)
+            .content_type(format.content_type())
+            .header("Content-Disposition", content_disposition(&filename));
+        if req.method == HttpMethod::HEAD {
+            return response;
+        }
+        info!("Streaming {} ({} files)", filename, entries.len());
+        match archive::stream(Arc::clone(&self.file_tree), dir, base, entries, format) {
+            Ok(reader) => response.body(Body::chunked(reader)),
+            Err(e) => {
+                info!("Server error for {}: {}", dir, e);
+                Response::error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    DEFAULT_INTERNAL_ERROR_BODY,
+                )
+            }
+        }
+    }
+}
+
+impl Handler for FileHandler {
+    fn handle(&self, req: &Request) -> Response {
+        if self
+            .uploads
+            .as_ref()
+            .is_some_and(|uploads| uploads.is_internal(req.path()))
+        {
+            info!("Refused request for upload internals: {}", req.path());
+            return Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY);
+        }
+        let mime_type = get_mime_type(req.path());
+        if let Some(guard) = &self.hotlink_guard {
+            if mime_type.is_media() && !guard.is_allowed(req.header("Referer"), req.header("Host"))
+            {
+                info!(
+                    "Blocked hotlink to {} from {}",
+                    req.path(),
+                    req.header("Referer").unwrap_or("<none>")
+                );
+                return match &self.hotlink_placeholder {
+                    Some((content, mime)) => Response::new()
+                        .status(StatusCode::OK)
+                        .content_type(mime)
+                        .content_length(content.len())
+                        .header("Cache-Control", "no-store")
+                        .body(content.clone()),
+                    None => Response::error(StatusCode::FORBIDDEN, DEFAULT_FORBIDDEN_BODY),
+                };
+            }
+        }
+
+        if self.webdav {
+            match req.method {
+                HttpMethod::OPTIONS => return webdav::options(self.uploads.is_some()),
+                HttpMethod::PROPFIND => return webdav::propfind(&self.file_tree, req),
+                _ => {}
+            }
+        }
+
+        if !matches!(
+            req.method,
+            HttpMethod::GET | HttpMethod::HEAD | HttpMethod::OPTIONS
+        ) {
+            return Response::error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                DEFAULT_METHOD_NOT_ALLOWED_BODY,
+            )
+            .header("Allow", "GET, HEAD, OPTIONS");
+        }
+
+        if let Some(limits) = &self.archive_limits {
+            if let Some(format) = req
+                .query_param("archive")
+                .and_then(ArchiveFormat::from_query)
+            {
+                return self.archive(req, format, limits);
+            }
+        }
+
+        match self.file_tree.get_reader(req.path()) {
+            Err(e) => {
+                if e.kind() == ErrorKind::NotFound {
+                    if let Some(checksums) =
+                        self.checksums.as_ref().filter(|_| self.checksum_sidecars)
+                    {
+                        if let Some(response) = self.sidecar(req, checksums) {
+                            return response;
+                        }
+                    }
+                    if let Some(index) = &self.auto_index {
+                        if let Some(response) = self.listing(req, index) {
+                            return response;
+                        }
+                    }
+                    info!("File not found: {}", req.path());
+                    if self.suggestions {
+                        let mut paths = suggest::suggest(&self.file_tree, req.path());
+                        if let Some(uploads) = &self.uploads {
+                            paths.retain(|path| !uploads.is_internal(path));
+                        }
+                        if !paths.is_empty() {
+                            return suggest::not_found(&paths);
+                        }
+                    }
+                    Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY)
+                } else {
+                    info!("Server error for {}: {}", req.path(), e);
+                    Response::error(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        DEFAULT_INTERNAL_ERROR_BODY,
+                    )
+                }
+            }
+            Ok(FileData { reader, metadata }) => {
+                let etag = || {
+                    let checksums = self.checksums.as_ref()?;
+                    let digest = checksums.sha256(&self.file_tree, req.path(), &metadata);
+                    Some(digest.ok()?.etag())
+                };
+                if !preconditions_hold(req, true, metadata.modified, etag) {
+                    info!("Precondition failed for {}", req.path());
+                    return Response::error(
+                        StatusCode::PRECONDITION_FAILED,
+                        DEFAULT_PRECONDITION_FAILED_BODY,
+                    );
+                }
+                let range = match req.header("Range") {
+                    Some(range) if if_range_holds(req, metadata.modified, etag) => {
+                        ByteRange::resolve(range, metadata.len)
+                    }
+                    _ => ByteRange::Full,
+                };
+                if range == ByteRange::Unsatisfiable {
+                    info!("Unsatisfiable range for {}", req.path());
+                    return Response::error(
+                        StatusCode::RANGE_NOT_SATISFIABLE,
+                        DEFAULT_RANGE_NOT_SATISFIABLE_BODY,
+                    )
+                    .header("Accept-Ranges", "bytes")
+                    .header("Content-Range", format!("bytes */{}", metadata.len));
+                }
+                let mut response = Response::new()
+                    .status(StatusCode::OK)
+                    .content_type(mime_type.as_str())
+                    .content_length(metadata.len)
+                    .header("Accept-Ranges", "bytes");
+                if let Some(modified) = metadata
+                    .modified
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                {
+                    response = response.header("Last-Modified", http_date(modified.as_secs()));
+                }
+                if let Some(checksums) = &self.checksums {
+                    match checksums.sha256(&self.file_tree, req.path(), &metadata) {
+                        Ok(digest) => {
+                            response = response.header("ETag", digest.etag());
+                            // The digest covers the whole file, not a part of it
+                            if range == ByteRange::Full {
+                                response =
+                                    response.header("Content-Digest", digest.content_digest());
+                            }
+                        }
+                        Err(e) => warn!("Could not hash {}: {}", req.path(), e),
+                    }
+                }
+                if self.attachments.wants(req) {
+                    let name = req.path().rsplit('/').next().unwrap_or_default();
+                    response = response.header("Content-Disposition", content_disposition(name));
+                }
+                let ByteRange::Partial { start, end } = range else {
+                    return response.body(Body::reader(reader));
+                };
+                let reader = match start {
+                    0 => Ok(reader),
+                    _ => self.file_tree.open_at(req.path(), start),
+                };
+                match reader {
+                    Ok(reader) => response
+                        .status(StatusCode::PARTIAL_CONTENT)
+                        .content_length(end - start + 1)
+                        .header(
+                            "Content-Range",
+                            format!("bytes {}-{}/{}", start, end, metadata.len),
+                        )
+                        .body(Body::reader(reader.take(end - start + 1))),
+                    Err(e) => {
+                        info!("Server error for {}: {}", req.path(), e);
+                        Response::error(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            DEFAULT_INTERNAL_ERROR_BODY,
+                        )
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SecurityHeadersConfig;
+    use crate::files::MemorySource;
+    use crate::glob::Glob;
+
+    fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_requires_root() {
+        let err = Server::builder().bind("127.0.0.1:0").build().err().unwrap();
+        assert!(matches!(err, ServerError::Config(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_serve_and_shutdown() {
+        let server = Server::builder()
+            .root("test-sites")
+            .bind("127.0.0.1:0")
+            .threads(2)
+            .build()
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let stats = server.stats();
+        let running = std::thread::spawn(move || server.run());
+
+        let response = get(address, "/one-file/index.html");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(get(address, "/missing.html").starts_with("HTTP/1.1 404 Not Found"));
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+        assert!(TcpStream::connect(address).is_err());
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.requests, 2);
+        assert_eq!(snapshot.responses.client_error, 1);
+        let sent = "GET /one-file/index.html HTTP/1.1\r\nHost: test\r\n\r\n".len()
+            + "GET /missing.html HTTP/1.1\r\nHost: test\r\n\r\n".len();
+        assert_eq!(snapshot.bytes_received, sent as u64);
+    }
+
+    #[test]
+    fn test_hand_over() {
+        let old = Server::builder()
+            .root("test-sites")
+            .bind("127.0.0.1:0")
+            .build()
+            .unwrap();
+        let address = old.local_addr().unwrap();
+        let new = Server::builder()
+            .root("test-sites")
+            .listener(old.try_clone_listener().unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(new.local_addr().unwrap(), address);
+        let (old_shutdown, new_shutdown) = (old.shutdown_handle(), new.shutdown_handle());
+        let old_running = std::thread::spawn(move || old.run());
+        let new_running = std::thread::spawn(move || new.run());
+
+        old_shutdown.hand_over();
+        old_running.join().unwrap().unwrap();
+        for _ in 0..3 {
+            assert!(get(address, "/one-file/index.html").starts_with("HTTP/1.1 200 OK"));
+        }
+        new_shutdown.hand_over();
+        new_running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_port_retries() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let bind = format!("127.0.0.1:{}", port);
+        let err = bind_listener(&bind, 0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AddrInUse);
+
+        // The next port may be taken by someone else too, so allow a few
+        match bind_listener(&bind, 5) {
+            Ok(listener) => assert!(listener.local_addr().unwrap().port() > port),
+            Err(e) => assert_eq!(e.kind(), ErrorKind::AddrInUse),
+        }
+    }
+
+    #[test]
+    fn test_full_queue_is_rejected() {
+        let (entered, started) = std::sync::mpsc::channel();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let released = std::sync::Mutex::new(released);
+        let server = Server::builder()
+            .bind("127.0.0.1:0")
+            .threads(1)
+            .max_queue(1)
+            .route("/slow", move |_req: &Request| {
+                entered.send(()).unwrap();
+                released.lock().unwrap().recv().unwrap();
+                Response::new().body("slow")
+            })
+            .route("/fast", |_req: &Request| Response::new().body("fast"))
+            .build()
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = std::thread::spawn(move || server.run());
+
+        // Occupy the only worker, then fill the queue behind it
+        let slow = std::thread::spawn(move || get(address, "/slow"));
+        started.recv().unwrap();
+        let queued = std::thread::spawn(move || get(address, "/fast"));
+        std::thread::sleep(Duration::from_millis(100));
+        // Answered at accept time, before the request is even read
+        let mut rejected = String::new();
+        TcpStream::connect(address)
+            .unwrap()
+            .read_to_string(&mut rejected)
+            .unwrap();
+        assert!(rejected.starts_with("HTTP/1.1 503"));
+
+        release.send(()).unwrap();
+        assert!(slow.join().unwrap().ends_with("slow"));
+        assert!(queued.join().unwrap().ends_with("fast"));
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_route_before_files() {
+        let server = Server::builder()
+            .root("test-sites")
+            .bind("127.0.0.1:0")
+            .route("/one-file/index.html", |_req: &Request| {
+                Response::new().status(StatusCode::FORBIDDEN)
+            })
+            .route("/api/version", |_req: &Request| {
+                Response::new().header("X-Version", "1")
+            })
+            .build()
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = std::thread::spawn(move || server.run());
+
+        assert!(get(address, "/api/version").contains("X-Version: 1"));
+        assert!(get(address, "/one-file/index.html").starts_with("HTTP/1.1 403"));
+        assert!(get(address, "/missing.html").starts_with("HTTP/1.1 404"));
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_service_without_socket() {
+        let service = Server::builder()
+            .root("test-sites")
+            .bearer_auth(BearerAuth::new(vec!["secret".to_string()]))
+            .security_headers(SecurityHeaders::from_config(&SecurityHeadersConfig {
+                enabled: true,
+                ..Default::default()
+            }))
+            .build_service()
+            .unwrap();
+
+        let denied = service.handle(&Request::builder().path("/one-file/index.html").build());
+        assert_eq!(denied.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            denied.headers.get("X-Content-Type-Options"),
+            Some(&"nosniff".to_string())
+        );
+
+        let raw = Request::builder()
+            .path("/one-file/index.html")
+            .header("Authorization", "Bearer secret")
+            .into_bytes();
+        let response = String::from_utf8(service.serve_request(&raw)).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Type: text/html"));
+    }
+
+    #[test]
+    fn test_malformed_requests() {
+        let service = Server::builder()
+            .root("test-sites")
+            .build_service()
+            .unwrap();
+        let answer = |raw: &[u8]| String::from_utf8(service.serve_request(raw)).unwrap();
+
+        assert!(answer(b"GET\r\n\r\n").starts_with("HTTP/1.1 400"));
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(10_000));
+        assert!(answer(long.as_bytes()).starts_with("HTTP/1.1 414"));
+        // Incomplete head: the client went away, so nothing is written
+        assert!(answer(b"GET / HTTP/1.1\r\n").is_empty());
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let service = Server::builder()
+            .root("test-sites")
+            .request_limits(Limits {
+                max_request_line: 64,
+                max_headers: 2,
+                max_head: 256,
+                max_body: Some(16),
+            })
+            .build_service()
+            .unwrap();
+        let answer = |raw: Vec<u8>| String::from_utf8(service.serve_request(&raw)).unwrap();
+
+        let long = Request::builder().path(format!("/{}", "a".repeat(64)));
+        assert!(answer(long.into_bytes()).starts_with("HTTP/1.1 414"));
+        let many = Request::builder()
+            .header("A", "1")
+            .header("B", "2")
+            .header("C", "3");
+        assert!(answer(many.into_bytes()).starts_with("HTTP/1.1 431"));
+        let large = Request::builder().header("X-Padding", "a".repeat(256));
+        assert!(answer(large.into_bytes()).starts_with("HTTP/1.1 431"));
+        let body = Request::builder()
+            .method(HttpMethod::POST)
+            .body("a".repeat(17));
+        assert!(answer(body.into_bytes()).starts_with("HTTP/1.1 413"));
+
+        let fits = Request::builder()
+            .path("/one-file/index.html")
+            .header("A", "1");
+        assert!(answer(fits.into_bytes()).starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn test_file_source() {
+        let service = Server::builder()
+            .file_source(MemorySource::new().with("docs/a.css", "p {}"))
+            .build_service()
+            .unwrap();
+        let raw =
+            String::from_utf8(service.serve_request(b"GET /docs/a.css HTTP/1.1\r\n\r\n")).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 200 OK"));
+        assert!(raw.contains("Content-Type: text/css"));
+        assert!(raw.ends_with("p {}"));
+        let raw = service.serve_request(b"GET /docs HTTP/1.1\r\n\r\n");
+        assert!(raw.starts_with(b"HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn test_rewrites_before_file_lookup() {
+        let service = Server::builder()
+            .file_source(MemorySource::new().with("about.html", "about"))
+            .rewrite_rule("^/about$ => /about.html".parse().unwrap())
+            .rewrite_rule("^/old/(.*)$ => /$1 [301]".parse().unwrap())
+            .build_service()
+            .unwrap();
+        let raw = String::from_utf8(service.serve_request(b"GET /about HTTP/1.1\r\n\r\n")).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 200 OK"));
+        assert!(raw.ends_with("about"));
+        let raw =
+            String::from_utf8(service.serve_request(b"GET /old/about HTTP/1.1\r\n\r\n")).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 301"));
+        assert!(raw.contains("Location: /about\n"));
+    }
+
+    #[test]
+    fn test_archive_downloads() {
+        let source = || {
+            MemorySource::new()
+                .with("photos/a.txt", "a")
+                .with("photos/.b", "b")
+        };
+        let service = Server::builder()
+            .file_source(source())
+            .archive_downloads(ArchiveLimits::default())
+            .build_service()
+            .unwrap();
+        let raw = service.serve_request(b"GET /photos/?archive=zip HTTP/1.1\r\n\r\n");
+        let head = String::from_utf8_lossy(&raw);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("Content-Type: application/zip"));
+        assert!(head.contains("Content-Disposition: attachment; filename=\"photos.zip\""));
+        assert!(head.contains("Transfer-Encoding: chunked"));
+        let raw = service.serve_request(b"GET /missing/?archive=tgz HTTP/1.1\r\n\r\n");
+        assert!(raw.starts_with(b"HTTP/1.1 404"));
+
+        let limited = Server::builder()
+            .file_source(source())
+            .archive_downloads(ArchiveLimits {
+                max_size: 0,
+                max_entries: 10,
+            })
+            .build_service()
+            .unwrap();
+        let raw = limited.serve_request(b"GET /photos/?archive=zip HTTP/1.1\r\n\r\n");
+        assert!(raw.starts_with(b"HTTP/1.1 403"));
+
+        // Without the option the query is ignored
+        let plain = Server::builder()
+            .file_source(source())
+            .build_service()
+            .unwrap();
+        let raw = plain.serve_request(b"GET /photos/?archive=zip HTTP/1.1\r\n\r\n");
+        assert!(raw.starts_with(b"HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn test_archive_access_rules() {
+        let config = crate::config::Config::from_toml(
+            "[[auth.rules]]\npath = \"/photos/private/**\"\naccess = \"token\"\n\
+             [[auth.rules]]\npath = \"/photos/**\"\naccess = \"public\"",
+        )
+        .unwrap();
+        let service = Server::builder()
+            .file_source(
+                MemorySource::new()
+                    .with("photos/a.txt", "a")
+                    .with("photos/private/secret.txt", "s"),
+            )
+            .archive_downloads(ArchiveLimits::default())
+            .bearer_auth(BearerAuth::new(vec!["t".to_string()]))
+            .access_policy(AccessPolicy::from_config(&config.auth).unwrap())
+            .build_service()
+            .unwrap();
+        let archive = |auth: &str| {
+            let raw = format!("GET /photos/?archive=zip HTTP/1.1\r\n{}\r\n", auth);
+            String::from_utf8_lossy(&service.serve_request(raw.as_bytes())).into_owned()
+        };
+
+        let anonymous = archive("");
+        assert!(anonymous.starts_with("HTTP/1.1 200"));
+        assert!(anonymous.contains("photos/a.txt"));
+        assert!(!anonymous.contains("secret.txt"));
+        assert!(archive("Authorization: Bearer t\r\n").contains("photos/private/secret.txt"));
+    }
+
+    #[test]
+    fn test_auto_index() {
+        let service = Server::builder()
+            .file_source(
+                MemorySource::new()
+                    .with("my docs/a.txt", "a")
+                    .with("my docs/.env", "secret"),
+            )
+            .auto_index(AutoIndex::new())
+            .build_service()
+            .unwrap();
+        let raw =
+            String::from_utf8(service.serve_request(b"GET /my%20docs/ HTTP/1.1\r\n\r\n")).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 200 OK"));
+        assert!(raw.contains("Content-Type: text/html; charset=utf-8"));
+        assert!(raw.contains("<a href=\"a.txt\">a.txt</a>"));
+        assert!(!raw.contains(".env"));
+
+        let raw = String::from_utf8(service.serve_request(b"GET /my%20docs?x=1 HTTP/1.1\r\n\r\n"))
+            .unwrap();
+        assert!(raw.starts_with("HTTP/1.1 301"));
+        assert!(raw.contains("Location: /my%20docs/?x=1"));
+        let raw = service.serve_request(b"GET /nowhere/ HTTP/1.1\r\n\r\n");
+        assert!(raw.starts_with(b"HTTP/1.1 404"));
+
+        let raw =
+            String::from_utf8(service.serve_request(
+                b"GET /my%20docs/?format=json&sort=size&order=desc HTTP/1.1\r\n\r\n",
+            ))
+            .unwrap();
+        assert!(raw.starts_with("HTTP/1.1 200 OK"));
+        assert!(raw.contains("Content-Type: application/json"));
+        let body = raw.split_once("\n\n").unwrap().1;
+        let json: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(json["entries"][0]["name"], "a.txt");
+        assert_eq!(json["order"], "desc");
+        let raw = service.serve_request(b"GET /my%20docs/?sort=colour HTTP/1.1\r\n\r\n");
+        assert!(raw.starts_with(b"HTTP/1.1 400"));
+        let raw = String::from_utf8(service.serve_request(b"GET / HTTP/1.1\r\n\r\n")).unwrap();
+        assert!(raw.contains("<a href=\"my%20docs/\">my docs/</a>"));
+    }
+
+    #[test]
+    fn test_tree_api() {
+        let service = Server::builder()
+            .file_source(
+                MemorySource::new()
+                    .with("docs/a/b/c.txt", "c")
+                    .with("docs/index.html", "<h1>Docs</h1>"),
+            )
+            .tree_api(2)
+            .build_service()
+            .unwrap();
+        let get = |target: &str| {
+            let raw = format!("GET {} HTTP/1.1\r\n\r\n", target);
+            String::from_utf8(service.serve_request(raw.as_bytes())).unwrap()
+        };
+        let raw = get("/_api/tree?path=/docs&depth=9");
+        assert!(raw.starts_with("HTTP/1.1 200 OK"));
+        assert!(raw.contains("Content-Type: application/json"));
+        let json: serde_json::Value =
+            serde_json::from_str(raw.split_once("\n\n").unwrap().1).unwrap();
+        assert_eq!(json["name"], "docs");
+        assert_eq!(json["children"][1]["size"], 13);
+        // Capped at two levels: docs/a is expanded, docs/a/b is not
+        assert_eq!(json["children"][0]["children"][0]["name"], "b");
+        assert!(json["children"][0]["children"][0].get("children").is_none());
+
+        assert!(get("/_api/tree").contains("\"name\":\"docs\""));
+        assert!(get("/_api/tree?path=/nope").starts_with("HTTP/1.1 404"));
+        assert!(get("/_api/tree?depth=-1").starts_with("HTTP/1.1 400"));
+        assert!(get("/_api/tree?path=/docs/../..").starts_with("HTTP/1.1 400"));
+        assert!(get("/docs/index.html").ends_with("<h1>Docs</h1>"));
+    }
+
+    #[test]
+    fn test_tree_api_access_rules() {
+        let config = crate::config::Config::from_toml(
+            "[[auth.rules]]\npath = \"/docs/private/**\"\naccess = \"token\"\n\
+             [[auth.rules]]\npath = \"/**\"\naccess = \"public\"",
+        )
+        .unwrap();
+        let service = Server::builder()
+            .file_source(
+                MemorySource::new()
+                    .with("docs/guide.md", "g")
+                    .with("docs/private/salaries.csv", "s"),
+            )
+            .tree_api(3)
+            .bearer_auth(BearerAuth::new(vec!["t".to_string()]))
+            .access_policy(AccessPolicy::from_config(&config.auth).unwrap())
+            .build_service()
+            .unwrap();
+        let get = |target: &str, auth: &str| {
+            let raw = format!("GET {} HTTP/1.1\r\n{}\r\n", target, auth);
+            String::from_utf8(service.serve_request(raw.as_bytes())).unwrap()
+        };
+
+        let raw = get("/_api/tree?path=/docs", "");
+        assert!(raw.contains("guide.md"));
+        assert!(!raw.contains("private"));
+        assert!(get("/_api/tree?path=/docs/private", "").starts_with("HTTP/1.1 403"));
+        let raw = get("/_api/tree?path=/docs", "Authorization: Bearer t\r\n");
+        assert!(raw.contains("salaries.csv"));
+    }
+
+    #[test]
+    fn test_download_quota() {
+        let service = Server::builder()
+            .file_source(MemorySource::new().with("iso.bin", "12345678"))
+            .download_quota(DownloadQuota::new(10, Duration::from_secs(60)))
+            .build_service()
+            .unwrap();
+        let get =
+            || String::from_utf8(service.serve_request(b"GET /iso.bin HTTP/1.1\r\n\r\n")).unwrap();
+
+        assert!(get().starts_with("HTTP/1.1 200"));
+        // Started under the quota, so it is served in full
+        assert!(get().ends_with("12345678"));
+        let refused = get();
+        assert!(refused.starts_with("HTTP/1.1 429"));
+        assert!(refused.contains("Retry-After: 60"));
+    }
+
+    #[test]
+    fn test_download_limit() {
+        let limit = DownloadLimit::new(1);
+        let service = Server::builder()
+            .file_source(MemorySource::new().with("build.zip", "zip"))
+            .download_limit(limit.clone())
+            .build_service()
+            .unwrap();
+        let request = |raw: &str| String::from_utf8(service.serve_request(raw.as_bytes())).unwrap();
+
+        assert!(request("HEAD /build.zip HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 200"));
+        assert!(request("GET /build.zip HTTP/1.1\r\n\r\n").ends_with("zip"));
+        assert_eq!(limit.downloads(), 1);
+        assert!(request("GET /build.zip HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 410"));
+    }
+
+    #[test]
+    fn test_attachments() {
+        let service = Server::builder()
+            .file_source(
+                MemorySource::new()
+                    .with("isos/debian.iso", "iso")
+                    .with("Résumé.txt", "cv"),
+            )
+            .attachments(Attachments::new(vec![Glob::new("*.iso")]))
+            .build_service()
+            .unwrap();
+        let head = |target: &str| {
+            let raw = service.serve_request(format!("GET {} HTTP/1.1\r\n\r\n", target).as_bytes());
+            let raw = String::from_utf8(raw).unwrap();
+            raw.split("\n\n").next().unwrap().to_string()
+        };
+
+        assert!(head("/isos/debian.iso")
+            .contains("Content-Disposition: attachment; filename=\"debian.iso\""));
+        assert!(!head("/isos/debian.iso?download=0").contains("Content-Disposition"));
+        assert!(!head("/R%C3%A9sum%C3%A9.txt").contains("Content-Disposition"));
+        assert!(head("/R%C3%A9sum%C3%A9.txt?download=1").contains(
+            "Content-Disposition: attachment; filename=\"R_sum_.txt\"; filename*=UTF-8''R%C3%A9sum%C3%A9.txt"
+        ));
+    }
+
+    #[test]
+    fn test_timing() {
+        let timing = Timing {
+            parse: Duration::from_micros(150),
+            open: Duration::from_millis(2),
+            write: Duration::from_millis(40),
+        };
+        assert_eq!(timing.total(), Duration::from_micros(42_150));
+        assert_eq!(timing.to_string(), "parse 150µs, open 2ms, write 40ms");
+    }
+
+    #[test]
+    fn test_sendfile_from_route() {
+        let service = Server::builder()
+            .file_source(MemorySource::new().with("store/report.pdf", "0123456789"))
+            .route("/download", |_req: &Request| {
+                Response::new()
+                    .header(sendfile::SEND_HEADER, "/store/report.pdf")
+                    .header("Content-Disposition", "attachment")
+            })
+            .sendfile()
+            .build_service()
+            .unwrap();
+        let raw = service.serve_request(b"GET /download HTTP/1.1\r\nRange: bytes=2-4\r\n\r\n");
+        let raw = String::from_utf8(raw).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 206 Partial Content"));
+        assert!(raw.contains("Content-Disposition: attachment"));
+        assert!(!raw.contains(sendfile::SEND_HEADER));
+        assert!(raw.ends_with("234"));
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let service = Server::builder()
+            .file_source(MemorySource::new().with("docs/install.html", "steps"))
+            .suggestions()
+            .build_service()
+            .unwrap();
+        let get = |target: &str| {
+            String::from_utf8(
+                service.serve_request(format!("GET {} HTTP/1.1\r\n\r\n", target).as_bytes()),
+            )
+            .unwrap()
+        };
+
+        let raw = get("/docs/Instal.html");
+        assert!(raw.starts_with("HTTP/1.1 404 Not Found"));
+        assert!(raw.contains("Link: </docs/install.html>; rel=\"related\""));
+        assert!(raw.contains("<a href=\"/docs/install.html\">"));
+        assert!(!get("/docs/changelog.html").contains("Link:"));
+    }
+
+    #[test]
+    fn test_checksums() {
+        let source = || {
+            MemorySource::new()
+                .with("dist/app.bin", "hello")
+                .with("dist/notes.txt.sha256", "hand-written")
+        };
+        let service = Server::builder()
+            .file_source(source())
+            .checksums(true)
+            .build_service()
+            .unwrap();
+        let get = |target: &str| {
+            let raw = format!("GET {} HTTP/1.1\r\n\r\n", target);
+            String::from_utf8(service.serve_request(raw.as_bytes())).unwrap()
+        };
+        let hex = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let raw = get("/dist/app.bin");
+        assert!(raw.contains(&format!("ETag: \"{}\"", hex)));
+        assert!(
+            raw.contains("Content-Digest: sha-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:")
+        );
+        assert!(raw.ends_with("hello"));
+        let raw = service.serve_request(
+            format!(
+                "GET /dist/app.bin HTTP/1.1\r\nIf-Match: \"{}\"\r\n\r\n",
+                hex
+            )
+            .as_bytes(),
+        );
+        assert!(raw.starts_with(b"HTTP/1.1 200"));
+        let raw = service.serve_request(b"GET /dist/app.bin HTTP/1.1\r\nIf-Match: \"0\"\r\n\r\n");
+        assert!(raw.starts_with(b"HTTP/1.1 412"));
+
+        let raw = get("/dist/app.bin.sha256");
+        assert!(raw.starts_with("HTTP/1.1 200 OK"));
+        assert!(raw.ends_with(&format!("{}  app.bin\n", hex)));
+        // Real sidecars win; missing files and directories get none
+        assert!(get("/dist/notes.txt.sha256").ends_with("hand-written"));
+        assert!(get("/dist/missing.bin.sha256").starts_with("HTTP/1.1 404"));
+        assert!(get("/dist.sha256").starts_with("HTTP/1.1 404"));
+
+        let headers_only = Server::builder()
+            .file_source(source())
+            .checksums(false)
+            .build_service()
+            .unwrap();
+        let raw = headers_only.serve_request(b"GET /dist/app.bin.sha256 HTTP/1.1\r\n\r\n");
+        assert!(raw.starts_with(b"HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn test_ranges() {
+        let service = Server::builder()
+            .file_source(MemorySource::new().with("digits.txt", "0123456789"))
+            .checksums(true)
+            .build_service()
+            .unwrap();
+        let get = |headers: &str| {
+            let raw = format!("GET /digits.txt HTTP/1.1\r\n{}\r\n", headers);
+            String::from_utf8(service.serve_request(raw.as_bytes())).unwrap()
+        };
+        let raw = get("");
+        assert!(raw.starts_with("HTTP/1.1 200"));
+        assert!(raw.contains("Accept-Ranges: bytes"));
+
+        let raw = get("Range: bytes=2-4\r\n");
+        assert!(raw.starts_with("HTTP/1.1 206"));
+        assert!(raw.contains("Content-Range: bytes 2-4/10"));
+        assert!(raw.contains("Content-Length: 3"));
+        assert!(!raw.contains("Content-Digest"));
+        assert!(raw.ends_with("\n234"));
+        assert!(get("Range: bytes=-3\r\n").ends_with("\n789"));
+
+        let raw = get("Range: bytes=10-\r\n");
+        assert!(raw.starts_with("HTTP/1.1 416"));
+        assert!(raw.contains("Content-Range: bytes */10"));
+
+        // A stale If-Range gets the whole file
+        let raw = get("Range: bytes=2-4\r\nIf-Range: \"old\"\r\n");
+        assert!(raw.starts_with("HTTP/1.1 200"));
+        assert!(raw.ends_with("0123456789"));
+    }
+
+    #[test]
+    fn test_put_uploads() {
+        let dir = std::env::temp_dir().join(format!("file-shover-put-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let service = Server::builder()
+            .root(&dir)
+            .writable(Uploads::new(&dir, 1024))
+            .bearer_auth(BearerAuth::new(vec!["ci".to_string()]))
+            .build_service()
+            .unwrap();
+        let put = |auth: &str, body: &str| {
+            let raw = format!(
+                "PUT /artifacts/app%20v1.txt HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}",
+                auth,
+                body.len(),
+                body
+            );
+            String::from_utf8(service.serve_request(raw.as_bytes())).unwrap()
+        };
+
+        assert!(put("", "nope").starts_with("HTTP/1.1 401"));
+        let raw = put("Authorization: Bearer ci\r\n", "v1");
+        assert!(raw.starts_with("HTTP/1.1 201 Created"));
+        assert!(raw.contains("Location: /artifacts/app%20v1.txt"));
+        assert!(put("Authorization: Bearer ci\r\n", "v2").starts_with("HTTP/1.1 204"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("artifacts/app v1.txt")).unwrap(),
+            "v2"
+        );
+        let raw = service.serve_request(
+            b"GET /artifacts/app%20v1.txt HTTP/1.1\r\nAuthorization: Bearer ci\r\n\r\n",
+        );
+        assert!(raw.ends_with(b"v2"));
+
+        let delete = |path: &str| {
+            let raw = format!(
+                "DELETE {} HTTP/1.1\r\nAuthorization: Bearer ci\r\n\r\n",
+                path
+            );
+            String::from_utf8(service.serve_request(raw.as_bytes())).unwrap()
+        };
+        assert!(delete("/artifacts/app%20v1.txt").starts_with("HTTP/1.1 204"));
+        assert!(delete("/artifacts/app%20v1.txt").starts_with("HTTP/1.1 404"));
+        assert!(dir.join(".trash/artifacts/app v1.txt").is_file());
+        let raw = service.serve_request(
+            b"GET /.trash/artifacts/app%20v1.txt HTTP/1.1\r\nAuthorization: Bearer ci\r\n\r\n",
+        );
+        assert!(raw.starts_with(b"HTTP/1.1 404"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_form_uploads() {
+        let dir = std::env::temp_dir().join(format!("file-shover-form-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("inbox")).unwrap();
+        let config = crate::config::Config::from_toml(
+            "[[auth.rules]]\npath = \"*.html\"\naccess = \"token\"\n\
+             [[auth.rules]]\npath = \"/inbox/**\"\naccess = \"public\"",
+        )
+        .unwrap();
+        let service = Server::builder()
+            .root(&dir)
+            .auto_index(AutoIndex::new())
+            .writable(Uploads::new(&dir, 1024).upload_form())
+            .bearer_auth(BearerAuth::new(vec!["t".to_string()]))
+            .access_policy(AccessPolicy::from_config(&config.auth).unwrap())
+            .build_service()
+            .unwrap();
+        let raw =
+            String::from_utf8(service.serve_request(b"GET /inbox/ HTTP/1.1\r\n\r\n")).unwrap();
+        assert!(raw.contains("enctype=\"multipart/form-data\""));
+
+        let post = |filename: &str| {
+            let body = format!(
+                "--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\nx\r\n--b--\r\n",
+                filename
+            );
+            let raw = format!(
+                "POST /inbox HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            String::from_utf8(service.serve_request(raw.as_bytes())).unwrap()
+        };
+        let raw = post("x.txt");
+        assert!(raw.starts_with("HTTP/1.1 303"));
+        assert!(raw.contains("Location: /inbox/"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("inbox/x.txt")).unwrap(),
+            "x"
+        );
+        // The rule for each file applies, not just the one for the directory
+        assert!(post("index.html").starts_with("HTTP/1.1 403"));
+        assert!(!dir.join("inbox/index.html").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_webdav() {
+        let dir = std::env::temp_dir().join(format!("file-shover-webdav-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let service = Server::builder()
+            .root(&dir)
+            .webdav()
+            .writable(Uploads::new(&dir, 1024))
+            .build_service()
+            .unwrap();
+        let raw = String::from_utf8(service.serve_request(b"OPTIONS / HTTP/1.1\r\n\r\n")).unwrap();
+        assert!(raw.contains("DAV: 1"));
+        assert!(raw.contains("MKCOL"));
+
+        let raw = service.serve_request(b"MKCOL /photos HTTP/1.1\r\n\r\n");
+        assert!(raw.starts_with(b"HTTP/1.1 201"));
+        let raw =
+            String::from_utf8(service.serve_request(b"PROPFIND / HTTP/1.1\r\nDepth: 1\r\n\r\n"))
+                .unwrap();
+        assert!(raw.starts_with("HTTP/1.1 207 Multi-Status"));
+        assert!(raw.contains("<D:href>/photos/</D:href>"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_webdav_destination_rules() {
+        let dir =
+            std::env::temp_dir().join(format!("file-shover-dav-rules-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("public")).unwrap();
+        std::fs::create_dir_all(dir.join("private")).unwrap();
+        std::fs::write(dir.join("public/f"), "mine").unwrap();
+        std::fs::write(dir.join("private/secret"), "theirs").unwrap();
+        let config = crate::config::Config::from_toml(
+            "[[auth.rules]]\npath = \"/public/**\"\naccess = \"public\"\n\
+             [[auth.rules]]\npath = \"/private/**\"\naccess = \"token\"",
+        )
+        .unwrap();
+        let service = Server::builder()
+            .root(&dir)
+            .webdav()
+            .writable(Uploads::new(&dir, 1024))
+            .bearer_auth(BearerAuth::new(vec!["t".to_string()]))
+            .access_policy(AccessPolicy::from_config(&config.auth).unwrap())
+            .build_service()
+            .unwrap();
+        let transfer = |method: &str, auth: &str| {
+            let raw = format!(
+                "{} /public/f HTTP/1.1\r\nDestination: /private/secret\r\n{}\r\n",
+                method, auth
+            );
+            String::from_utf8(service.serve_request(raw.as_bytes())).unwrap()
+        };
+
+        for method in ["MOVE", "COPY"] {
+            assert!(
+                transfer(method, "").starts_with("HTTP/1.1 403"),
+                "{}",
+                method
+            );
+        }
+        assert_eq!(
+            std::fs::read_to_string(dir.join("private/secret")).unwrap(),
+            "theirs"
+        );
+        let raw = transfer("COPY", "Authorization: Bearer t\r\n");
+        assert!(raw.starts_with("HTTP/1.1 204"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("private/secret")).unwrap(),
+            "mine"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_webdav_member_rules() {
+        let dir =
+            std::env::temp_dir().join(format!("file-shover-dav-members-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("public/src/inner")).unwrap();
+        std::fs::create_dir_all(dir.join("public/dst")).unwrap();
+        std::fs::write(dir.join("public/src/inner/a"), "a").unwrap();
+        std::fs::write(dir.join("public/src/secret"), "theirs").unwrap();
+        std::fs::write(dir.join("public/dst/keep"), "theirs").unwrap();
+        let config = crate::config::Config::from_toml(
+            "[[auth.rules]]\npath = \"/public/src/secret\"\naccess = \"token\"\n\
+             [[auth.rules]]\npath = \"/public/dst/keep\"\naccess = \"token\"\n\
+             [[auth.rules]]\npath = \"/public/guarded/inner/**\"\naccess = \"token\"\n\
+             [[auth.rules]]\npath = \"/public/**\"\naccess = \"public\"",
+        )
+        .unwrap();
+        let service = Server::builder()
+            .root(&dir)
+            .webdav()
+            .writable(Uploads::new(&dir, 1024))
+            .bearer_auth(BearerAuth::new(vec!["t".to_string()]))
+            .access_policy(AccessPolicy::from_config(&config.auth).unwrap())
+            .build_service()
+            .unwrap();
+        let transfer = |method: &str, destination: &str, auth: &str| {
+            let raw = format!(
+                "{} /public/src HTTP/1.1\r\nDestination: {}\r\n{}\r\n",
+                method, destination, auth
+            );
+            String::from_utf8(service.serve_request(raw.as_bytes())).unwrap()
+        };
+
+        // A copy leaves out the members the client may not read
+        assert!(transfer("COPY", "/public/copy", "").starts_with("HTTP/1.1 201"));
+        assert!(dir.join("public/copy/inner/a").is_file());
+        assert!(!dir.join("public/copy/secret").exists());
+        // Nor may it write members the destination rules refuse
+        assert!(transfer("COPY", "/public/guarded", "").starts_with("HTTP/1.1 403"));
+        assert!(!dir.join("public/guarded").exists());
+        // Nor replace a directory holding members it may not remove
+        assert!(transfer("COPY", "/public/dst", "").starts_with("HTTP/1.1 403"));
+        assert!(dir.join("public/dst/keep").is_file());
+        // A move would take the unreadable members along
+        assert!(transfer("MOVE", "/public/moved", "").starts_with("HTTP/1.1 403"));
+        assert!(dir.join("public/src/secret").is_file());
+        let raw = transfer("MOVE", "/public/moved", "Authorization: Bearer t\r\n");
+        assert!(raw.starts_with("HTTP/1.1 201"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("public/moved/secret")).unwrap(),
+            "theirs"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resumable_target_rules() {
+        let dir =
+            std::env::temp_dir().join(format!("file-shover-tus-rules-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = crate::config::Config::from_toml(
+            "[[auth.rules]]\npath = \"/_tus/**\"\naccess = \"public\"\n\
+             [[auth.rules]]\npath = \"/private/**\"\naccess = \"token\"",
+        )
+        .unwrap();
+        let service = Server::builder()
+            .root(&dir)
+            .writable(Uploads::new(&dir, 1024).resumable())
+            .bearer_auth(BearerAuth::new(vec!["t".to_string()]))
+            .access_policy(AccessPolicy::from_config(&config.auth).unwrap())
+            .build_service()
+            .unwrap();
+        let create = |auth: &str| {
+            // filename "private/x.bin"
+            let raw = format!(
+                "POST /_tus/ HTTP/1.1\r\nTus-Resumable: 1.0.0\r\nUpload-Length: 1\r\n\
+                 Upload-Metadata: filename cHJpdmF0ZS94LmJpbg==\r\n{}\r\n",
+                auth
+            );
+            String::from_utf8(service.serve_request(raw.as_bytes())).unwrap()
+        };
+
+        assert!(create("").starts_with("HTTP/1.1 403"));
+        assert!(!dir.join(".uploads").exists());
+        assert!(create("Authorization: Bearer t\r\n").starts_with("HTTP/1.1 201"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_panicking_handler() {
+        let service = Server::builder()
+            .route("/boom", |_req: &Request| -> Response { panic!("boom") })
+            .route("/fine", |_req: &Request| Response::new().body("ok"))
+            .build_service()
+            .unwrap();
+
+        let response = service.handle(&Request::builder().path("/boom").build());
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+        let raw = String::from_utf8(service.serve_request(b"GET /boom HTTP/1.1\r\n\r\n")).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 500"));
+        // The service keeps answering afterwards
+        let raw = String::from_utf8(service.serve_request(b"GET /fine HTTP/1.1\r\n\r\n")).unwrap();
+        assert!(raw.ends_with("ok"));
+    }
+
+    /// Fetches `path` over TLS, trusting `server_cert` and presenting
+    /// `client` as the client certificate if given.
+    fn https_get(
+        address: SocketAddr,
+        path: &str,
+        server_cert: &rcgen::Certificate,
+        client: Option<(&rcgen::Certificate, &rcgen::KeyPair)>,
+    ) -> String {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(server_cert.der().clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let client_config = match client {
+            None => builder.with_no_client_auth(),
+            Some((cert, key)) => builder
+                .with_client_auth_cert(
+                    vec![cert.der().clone()],
+                    rustls::pki_types::PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+                )
+                .unwrap(),
+        };
+        let session =
+            rustls::ClientConnection::new(Arc::new(client_config), "localhost".try_into().unwrap())
+                .unwrap();
+        let mut stream = rustls::StreamOwned::new(session, TcpStream::connect(address).unwrap());
+        write!(stream, "GET {} HTTP/1.1\r\n\r\n", path).unwrap();
+        let mut response = Vec::new();
+        // The server closes the TCP stream right after close_notify
+        let _ = stream.read_to_end(&mut response);
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[test]
+    fn test_tls_round_trip() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = rustls::pki_types::PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into());
+        let tls = TlsConfig::from_der(vec![cert.cert.der().clone()], key).unwrap();
+        let server = Server::builder()
+            .root("test-sites")
+            .bind("127.0.0.1:0")
+            .tls(tls)
+            .build()
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = std::thread::spawn(move || server.run());
+
+        let response = https_get(address, "/one-file/index.html", &cert.cert, None);
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_client_certificate_rule() {
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let ca_file =
+            std::env::temp_dir().join(format!("file-shover-client-ca-{}.pem", std::process::id()));
+        std::fs::write(&ca_file, ca.pem()).unwrap();
+
+        let client_key = rcgen::KeyPair::generate().unwrap();
+        let mut client_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        client_params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "ci-runner");
+        client_params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+        let client = client_params.signed_by(&client_key, &ca, &ca_key).unwrap();
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = rustls::pki_types::PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into());
+        let tls = TlsConfig::from_der(vec![cert.cert.der().clone()], key)
+            .unwrap()
+            .client_auth(&ca_file, false)
+            .unwrap();
+        std::fs::remove_file(&ca_file).unwrap();
+        let config = crate::config::Config::from_toml(
+            "[[auth.rules]]\npath = \"/one-file/**\"\naccess = \"cert:ci-*\"",
+        )
+        .unwrap();
+        let server = Server::builder()
+            .root("test-sites")
+            .bind("127.0.0.1:0")
+            .tls(tls)
+            .access_policy(AccessPolicy::from_config(&config.auth).unwrap())
+            .build()
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = std::thread::spawn(move || server.run());
+
+        let path = "/one-file/index.html";
+        let response = https_get(address, path, &cert.cert, Some((&client, &client_key)));
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let response = https_get(address, path, &cert.cert, None);
+        assert!(response.starts_with("HTTP/1.1 403"));
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+}
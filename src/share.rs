@@ -0,0 +1,171 @@
+/*
+* Share module
+*
+* Download limits for temporary shares (`file-shover share`): a link that
+* stops working after a number of downloads, like handing over a file in
+* person.
+*
+* A download is a GET answered with 200 whose body was sent in full, for a
+* file or a directory archive. Directory listings, HEAD requests, partial
+* (`Range`) responses and transfers the client gave up on do not count, so
+* a browser peeking at a listing or a resumed download does not use up the
+* link. Once the limit is reached, further requests get 410 Gone; downloads
+* already under way are allowed to finish.
+*/
+
+use crate::message::{HttpMethod, Request, Response, StatusCode};
+use crate::middleware::Middleware;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Body of the 410 answered once a share is used up.
+const DEFAULT_GONE_BODY: &str = "<h1>410 Gone</h1>";
+
+/// Counts completed downloads against a limit.
+///
+/// Clones share the count.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::message::{Request, StatusCode};
+/// use file_shover::share::DownloadLimit;
+/// use std::time::Duration;
+///
+/// let limit = DownloadLimit::new(1);
+/// let request = Request::builder().path("/build.zip").build();
+///
+/// // A download the client abandoned does not count
+/// limit.record(&request, StatusCode::OK.as_u16(), true);
+/// assert!(!limit.wait(Duration::ZERO));
+///
+/// limit.record(&request, StatusCode::OK.as_u16(), false);
+/// assert_eq!(limit.downloads(), 1);
+/// assert!(limit.wait(Duration::ZERO));
+/// ```
+#[derive(Debug, Clone)]
+pub struct DownloadLimit {
+    max: u64,
+    downloads: Arc<(Mutex<u64>, Condvar)>,
+}
+
+impl DownloadLimit {
+    /// Allows `max` completed downloads.
+    pub fn new(max: u64) -> Self {
+        Self {
+            max,
+            downloads: Arc::new((Mutex::new(0), Condvar::new())),
+        }
+    }
+
+    /// Downloads completed so far.
+    pub fn downloads(&self) -> u64 {
+        *self.downloads.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Counts the response to `req` if it was a complete download:
+    /// `status` 200 to a GET for a file or archive, not `aborted`.
+    pub fn record(&self, req: &Request, status: u16, aborted: bool) {
+        let is_file = !req.path().ends_with('/') || req.query_param("archive").is_some();
+        if req.method != HttpMethod::GET || status != StatusCode::OK.as_u16() || aborted || !is_file
+        {
+            return;
+        }
+        let (count, reached) = &*self.downloads;
+        let mut count = count.lock().unwrap_or_else(|e| e.into_inner());
+        *count += 1;
+        info!("📦 Download {} of {}: {}", *count, self.max, req.path());
+        if *count >= self.max {
+            reached.notify_all();
+        }
+    }
+
+    /// Blocks until the limit is reached or `timeout` has passed, and
+    /// returns whether the limit was reached. `Duration::MAX` waits for the
+    /// limit alone.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        let (count, reached) = &*self.downloads;
+        let mut count = count.lock().unwrap_or_else(|e| e.into_inner());
+        while *count < self.max {
+            count = match deadline {
+                None => reached.wait(count).unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return false;
+                    }
+                    reached
+                        .wait_timeout(count, left)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+        true
+    }
+}
+
+impl Middleware for DownloadLimit {
+    fn before(&self, req: &Request) -> Option<Response> {
+        if self.downloads() < self.max {
+            return None;
+        }
+        info!("Refused {}: the share is used up", req.path());
+        Some(Response::error(StatusCode::GONE, DEFAULT_GONE_BODY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(path: &str) -> Request {
+        Request::builder().path(path).build()
+    }
+
+    #[test]
+    fn test_only_complete_downloads_count() {
+        let limit = DownloadLimit::new(10);
+        limit.record(&get("/build.zip"), 206, false);
+        limit.record(&get("/build.zip"), 404, false);
+        limit.record(&get("/build.zip"), 200, true);
+        limit.record(&get("/docs/"), 200, false);
+        limit.record(
+            &Request::builder()
+                .method(HttpMethod::HEAD)
+                .path("/build.zip")
+                .build(),
+            200,
+            false,
+        );
+        assert_eq!(limit.downloads(), 0);
+
+        limit.record(&get("/build.zip"), 200, false);
+        limit.record(&get("/docs/?archive=zip"), 200, false);
+        assert_eq!(limit.downloads(), 2);
+    }
+
+    #[test]
+    fn test_used_up_share_is_gone() {
+        let limit = DownloadLimit::new(1);
+        assert!(limit.before(&get("/build.zip")).is_none());
+        limit.record(&get("/build.zip"), 200, false);
+        let response = limit.before(&get("/build.zip")).unwrap();
+        assert_eq!(response.status, StatusCode::GONE);
+    }
+
+    #[test]
+    fn test_wait_wakes_on_the_last_download() {
+        let limit = DownloadLimit::new(2);
+        let waiter = {
+            let limit = limit.clone();
+            std::thread::spawn(move || limit.wait(Duration::from_secs(10)))
+        };
+        limit.record(&get("/a"), 200, false);
+        limit.record(&get("/b"), 200, false);
+        assert!(waiter.join().unwrap());
+        assert!(!DownloadLimit::new(1).wait(Duration::from_millis(10)));
+    }
+}
@@ -0,0 +1,213 @@
+/*
+* URL signing module
+*
+* HMAC-SHA256 signed, expiring download links. A signed URL looks like
+*
+*   /releases/app.zip?expires=1767225600&sig=5f2c...
+*
+* where `sig` is the hex HMAC of "<path>\n<expires>" under the server secret.
+* Links are produced by `file-shover sign <path>` and checked by the server,
+* which lets a single file be shared for a limited time without turning on
+* authentication for the whole tree.
+*/
+
+use crate::hex::{from_hex, to_hex};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Reasons a signed URL is rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignatureError {
+    /// The URL carries no `sig` parameter.
+    Missing,
+    /// `expires` or `sig` is not well formed.
+    Malformed,
+    /// The link was valid but its expiry time has passed.
+    Expired,
+    /// The signature does not match the path and expiry.
+    Invalid,
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "missing signature"),
+            SignatureError::Malformed => write!(f, "malformed signature parameters"),
+            SignatureError::Expired => write!(f, "signature expired"),
+            SignatureError::Invalid => write!(f, "invalid signature"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Creates and verifies signed URLs.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::signing::{SignatureError, UrlSigner};
+///
+/// let signer = UrlSigner::new(b"server-secret");
+/// let query = signer.signed_query("/reports/q3.pdf", 2_000_000_000);
+///
+/// let params: Vec<(&str, &str)> = query
+///     .split('&')
+///     .filter_map(|pair| pair.split_once('='))
+///     .collect();
+/// let expires = params[0].1;
+/// let sig = params[1].1;
+///
+/// assert!(signer.verify("/reports/q3.pdf", Some(expires), Some(sig), 1_900_000_000).is_ok());
+/// assert_eq!(
+///     signer.verify("/reports/q4.pdf", Some(expires), Some(sig), 1_900_000_000),
+///     Err(SignatureError::Invalid)
+/// );
+/// assert_eq!(
+///     signer.verify("/reports/q3.pdf", Some(expires), Some(sig), 2_000_000_001),
+///     Err(SignatureError::Expired)
+/// );
+/// ```
+#[derive(Clone)]
+pub struct UrlSigner {
+    key: Vec<u8>,
+}
+
+impl std::fmt::Debug for UrlSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UrlSigner").finish_non_exhaustive()
+    }
+}
+
+impl UrlSigner {
+    /// Creates a signer from the shared secret.
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            key: secret.to_vec(),
+        }
+    }
+
+    /// Creates a signer with a fresh secret known only to this process, for
+    /// links that only need to work while it runs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the operating system's random number generator fails.
+    pub fn random() -> Self {
+        let mut key = vec![0; 32];
+        getrandom::getrandom(&mut key).expect("the OS random number generator failed");
+        Self { key }
+    }
+
+    fn mac(&self, path: &str, expires: u64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+        mac.update(path.as_bytes());
+        mac.update(b"\n");
+        mac.update(expires.to_string().as_bytes());
+        mac
+    }
+
+    /// Returns the hex signature for `path` valid until `expires` (Unix seconds).
+    pub fn sign(&self, path: &str, expires: u64) -> String {
+        to_hex(&self.mac(path, expires).finalize().into_bytes())
+    }
+
+    /// Returns the `expires=...&sig=...` query string for `path`.
+    pub fn signed_query(&self, path: &str, expires: u64) -> String {
+        format!("expires={}&sig={}", expires, self.sign(path, expires))
+    }
+
+    /// Checks the `expires` and `sig` query parameters of a request for `path`.
+    ///
+    /// `now` is the current time in Unix seconds.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SignatureError`] describing why the link is not acceptable.
+    pub fn verify(
+        &self,
+        path: &str,
+        expires: Option<&str>,
+        sig: Option<&str>,
+        now: u64,
+    ) -> Result<(), SignatureError> {
+        let sig = sig.ok_or(SignatureError::Missing)?;
+        let expires: u64 = expires
+            .ok_or(SignatureError::Malformed)?
+            .parse()
+            .map_err(|_| SignatureError::Malformed)?;
+        let sig = from_hex(sig).ok_or(SignatureError::Malformed)?;
+
+        // Check the MAC first so expiry is only reported for genuine links
+        self.mac(path, expires)
+            .verify_slice(&sig)
+            .map_err(|_| SignatureError::Invalid)?;
+        if now > expires {
+            return Err(SignatureError::Expired);
+        }
+        Ok(())
+    }
+}
+
+/// Current time in Unix seconds.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_is_deterministic() {
+        let signer = UrlSigner::new(b"k");
+        assert_eq!(signer.sign("/a", 10), signer.sign("/a", 10));
+        assert_ne!(signer.sign("/a", 10), signer.sign("/a", 11));
+        assert_eq!(signer.sign("/a", 10).len(), 64);
+    }
+
+    #[test]
+    fn test_different_secrets_do_not_verify() {
+        let sig = UrlSigner::new(b"one").sign("/a", 100);
+        let other = UrlSigner::new(b"two");
+        assert_eq!(
+            other.verify("/a", Some("100"), Some(&sig), 0),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_random_secrets_differ() {
+        let sig = UrlSigner::random().sign("/a", 100);
+        assert_eq!(
+            UrlSigner::random().verify("/a", Some("100"), Some(&sig), 0),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_malformed_parameters() {
+        let signer = UrlSigner::new(b"k");
+        assert_eq!(
+            signer.verify("/a", Some("100"), None, 0),
+            Err(SignatureError::Missing)
+        );
+        assert_eq!(
+            signer.verify("/a", None, Some("00"), 0),
+            Err(SignatureError::Malformed)
+        );
+        assert_eq!(
+            signer.verify("/a", Some("soon"), Some("00"), 0),
+            Err(SignatureError::Malformed)
+        );
+        assert_eq!(
+            signer.verify("/a", Some("100"), Some("zz"), 0),
+            Err(SignatureError::Malformed)
+        );
+    }
+}
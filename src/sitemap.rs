@@ -0,0 +1,354 @@
+/*
+* Sitemap module
+*
+* A generated `/sitemap.xml` listing every HTML page of the served tree, for
+* sites without a build step that writes one. A `sitemap.xml` in the root
+* still wins over the generated one.
+*
+*   /index.html          -> https://example.com/
+*   /docs/index.html     -> https://example.com/docs/
+*   /docs/setup.html     -> https://example.com/docs/setup.html
+*
+* Dot files and directories, paths matching an exclusion glob and pages
+* that need credentials are left out, and `<lastmod>` is the file's
+* modification date. Walking the tree is
+* not free, so the result is cached: with a file watcher (`--watch`) until
+* the next change, otherwise for a minute.
+*/
+
+use crate::files::FileTree;
+use crate::glob::Glob;
+use crate::listing::escape_html;
+use crate::livereload::LiveReload;
+use crate::message::{
+    HttpMethod, Request, Response, StatusCode, DEFAULT_INTERNAL_ERROR_BODY,
+    DEFAULT_METHOD_NOT_ALLOWED_BODY,
+};
+use crate::middleware::{Auth, Handler};
+use crate::target::encode_path;
+use crate::units::utc_date_time;
+use std::fmt::Write;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+/// Path the sitemap is served at.
+pub const SITEMAP_PATH: &str = "/sitemap.xml";
+
+/// Most URLs one sitemap file may list.
+const MAX_URLS: usize = 50_000;
+
+/// How long a sitemap is reused without a file watcher.
+const REFRESH: Duration = Duration::from_secs(60);
+
+/// Sitemap settings: the site's public URL and paths to leave out.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::MemorySource;
+/// use file_shover::message::Request;
+/// use file_shover::server::Server;
+/// use file_shover::sitemap::Sitemap;
+///
+/// let service = Server::builder()
+///     .file_source(MemorySource::new().with("index.html", "<h1>Hi</h1>"))
+///     .sitemap(Sitemap::new("https://example.com"))
+///     .build_service()?;
+/// let response = service.handle(&Request::builder().path("/sitemap.xml").build());
+/// let mut xml = String::new();
+/// response.body.into_reader().read_to_string(&mut xml)?;
+/// assert!(xml.contains("<loc>https://example.com/</loc>"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sitemap {
+    base_url: String,
+    exclude: Vec<Glob>,
+    changes: Option<LiveReload>,
+}
+
+impl Sitemap {
+    /// Lists pages under `base_url`, e.g. `https://example.com`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            exclude: Vec::new(),
+            changes: None,
+        }
+    }
+
+    /// Leaves out paths matching `pattern`.
+    pub fn exclude(mut self, pattern: Glob) -> Self {
+        self.exclude.push(pattern);
+        self
+    }
+
+    /// Regenerates whenever `watcher` sees files change, instead of every
+    /// minute.
+    pub fn watch(mut self, watcher: LiveReload) -> Self {
+        self.changes = Some(watcher);
+        self
+    }
+
+    /// The handler serving the sitemap of the pages of `file_tree` that
+    /// `access` makes public.
+    pub(crate) fn handler(self, file_tree: Arc<FileTree>, access: Auth) -> SitemapHandler {
+        SitemapHandler {
+            sitemap: self,
+            file_tree,
+            access,
+            cache: Mutex::new(None),
+        }
+    }
+}
+
+/// One listed page.
+#[derive(Debug, Clone, PartialEq)]
+struct Page {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+/// A rendered sitemap and what it was built from.
+struct Cached {
+    xml: Arc<String>,
+    generation: u64,
+    built: Instant,
+}
+
+/// Serves `/sitemap.xml` from a cached rendering.
+pub(crate) struct SitemapHandler {
+    sitemap: Sitemap,
+    file_tree: Arc<FileTree>,
+    access: Auth,
+    cache: Mutex<Option<Cached>>,
+}
+
+impl SitemapHandler {
+    /// The sitemap, rebuilt if files changed or it is too old.
+    fn xml(&self) -> io::Result<Arc<String>> {
+        let generation = self.sitemap.changes.as_ref().map(LiveReload::generation);
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(cached) = cache.as_ref() {
+            let fresh = match generation {
+                Some(generation) => cached.generation == generation,
+                None => cached.built.elapsed() < REFRESH,
+            };
+            if fresh {
+                return Ok(Arc::clone(&cached.xml));
+            }
+        }
+        let mut pages = Vec::new();
+        self.collect("/", &mut pages)?;
+        if pages.len() > MAX_URLS {
+            warn!("Sitemap truncated to {} of {} pages", MAX_URLS, pages.len());
+            pages.truncate(MAX_URLS);
+        }
+        info!("🗺️ Generated sitemap with {} pages", pages.len());
+        let xml = Arc::new(render(&self.sitemap.base_url, &pages));
+        *cache = Some(Cached {
+            xml: Arc::clone(&xml),
+            generation: generation.unwrap_or_default(),
+            built: Instant::now(),
+        });
+        Ok(xml)
+    }
+
+    /// Adds the pages below `dir` (which ends in `/`) to `pages`.
+    fn collect(&self, dir: &str, pages: &mut Vec<Page>) -> io::Result<()> {
+        for entry in self.file_tree.list(dir)? {
+            if entry.name.starts_with('.') {
+                continue;
+            }
+            let path = format!("{}{}", dir, entry.name);
+            if self.sitemap.exclude.iter().any(|glob| glob.matches(&path)) {
+                continue;
+            }
+            if entry.metadata.is_dir {
+                self.collect(&format!("{}/", path), pages)?;
+            } else if is_html(&entry.name) && self.access.is_public(&path) {
+                let path = match entry.name.as_str() {
+                    "index.html" | "index.htm" => dir.to_string(),
+                    _ => path,
+                };
+                pages.push(Page {
+                    path,
+                    modified: entry.metadata.modified,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Handler for SitemapHandler {
+    fn handle(&self, req: &Request) -> Response {
+        if !matches!(req.method, HttpMethod::GET | HttpMethod::HEAD) {
+            return Response::error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                DEFAULT_METHOD_NOT_ALLOWED_BODY,
+            )
+            .header("Allow", "GET, HEAD");
+        }
+        let response = Response::new()
+            .status(StatusCode::OK)
+            .content_type("application/xml; charset=utf-8");
+        // A sitemap shipped with the site is served as is
+        if let Ok(mut file) = self.file_tree.get_reader(SITEMAP_PATH) {
+            let mut xml = Vec::new();
+            return match file.reader.read_to_end(&mut xml) {
+                Ok(_) if req.method == HttpMethod::HEAD => response.content_length(xml.len()),
+                Ok(_) => response.content_length(xml.len()).body(xml),
+                Err(e) => server_error(e),
+            };
+        }
+        match self.xml() {
+            Ok(xml) if req.method == HttpMethod::HEAD => response.content_length(xml.len()),
+            Ok(xml) => response
+                .content_length(xml.len())
+                .body(xml.as_bytes().to_vec()),
+            Err(e) => server_error(e),
+        }
+    }
+}
+
+fn server_error(e: io::Error) -> Response {
+    info!("Server error building sitemap: {}", e);
+    Response::error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        DEFAULT_INTERNAL_ERROR_BODY,
+    )
+}
+
+fn is_html(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with(".html") || name.ends_with(".htm")
+}
+
+/// Renders the sitemap XML for `pages`.
+fn render(base_url: &str, pages: &[Page]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for page in pages {
+        let loc = escape_html(&format!("{}{}", base_url, encode_path(&page.path)));
+        let _ = write!(xml, "  <url><loc>{}</loc>", loc);
+        if let Some(secs) = page
+            .modified
+            .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+        {
+            let (year, month, day, ..) = utc_date_time(secs.as_secs());
+            let _ = write!(
+                xml,
+                "<lastmod>{:04}-{:02}-{:02}</lastmod>",
+                year, month, day
+            );
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{AccessPolicy, BearerAuth};
+    use crate::files::MemorySource;
+
+    fn handler(sitemap: Sitemap, source: MemorySource) -> SitemapHandler {
+        sitemap.handler(Arc::new(FileTree::from_source(source)), Auth::default())
+    }
+
+    fn get(handler: &SitemapHandler) -> String {
+        let response = handler.handle(&Request::builder().path(SITEMAP_PATH).build());
+        assert_eq!(response.status, StatusCode::OK);
+        let mut xml = String::new();
+        response
+            .body
+            .into_reader()
+            .read_to_string(&mut xml)
+            .unwrap();
+        xml
+    }
+
+    #[test]
+    fn test_lists_html_pages() {
+        let sitemap = handler(
+            Sitemap::new("https://example.com/").exclude(Glob::new("/drafts/**")),
+            MemorySource::new()
+                .with("index.html", "home")
+                .with("docs/index.htm", "docs")
+                .with("docs/setup guide.html", "setup")
+                .with("docs/logo.png", "png")
+                .with("drafts/next.html", "draft")
+                .with(".private/notes.html", "hidden"),
+        );
+        let xml = get(&sitemap);
+        let locs: Vec<&str> = xml
+            .match_indices("<loc>")
+            .map(|(start, _)| {
+                let rest = &xml[start + 5..];
+                &rest[..rest.find("</loc>").unwrap()]
+            })
+            .collect();
+        assert_eq!(
+            locs,
+            [
+                "https://example.com/docs/",
+                "https://example.com/docs/setup%20guide.html",
+                "https://example.com/",
+            ]
+        );
+        assert!(xml.starts_with("<?xml"));
+    }
+
+    #[test]
+    fn test_skips_non_public() {
+        let config = crate::config::Config::from_toml(
+            "[[auth.rules]]\npath = \"/members/**\"\naccess = \"token\"",
+        )
+        .unwrap();
+        let access = Auth::new(
+            BearerAuth::new(vec!["t".to_string()]),
+            AccessPolicy::from_config(&config.auth).unwrap(),
+            None,
+            false,
+        );
+        let source = MemorySource::new().with("members/index.html", "m");
+        let sitemap =
+            Sitemap::new("http://x").handler(Arc::new(FileTree::from_source(source)), access);
+        assert!(!get(&sitemap).contains("members"));
+    }
+
+    #[test]
+    fn test_regenerates_on_change() {
+        let watcher = LiveReload::new();
+        let source = MemorySource::new().with("a.html", "a");
+        let sitemap = handler(Sitemap::new("http://x").watch(watcher.clone()), source);
+        assert!(get(&sitemap).contains("http://x/a.html"));
+        let cached = get(&sitemap);
+        assert_eq!(get(&sitemap), cached);
+        watcher.changed();
+        assert!(sitemap.cache.lock().unwrap().as_ref().unwrap().generation != watcher.generation());
+        get(&sitemap);
+        assert_eq!(
+            sitemap.cache.lock().unwrap().as_ref().unwrap().generation,
+            watcher.generation()
+        );
+    }
+
+    #[test]
+    fn test_render_lastmod() {
+        let pages = [Page {
+            path: "/a&b.html".to_string(),
+            modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+        }];
+        assert!(render("https://example.com", &pages).contains(
+            "<url><loc>https://example.com/a&amp;b.html</loc><lastmod>2023-11-14</lastmod></url>"
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+/*
+* Sites module
+*
+* Several independent servers in one process, e.g. one per `[[site]]` block
+* of the configuration file, so a single daemon can replace an instance per
+* site. Each server keeps its own listener, pipeline, thread pool and stats;
+* the manager only runs them side by side:
+*
+*   photos   0.0.0.0:8080  -> /srv/photos
+*   backups  0.0.0.0:8443  -> /srv/backups (TLS, token auth)
+*
+* Every server runs its accept loop on a thread of its own. The manager
+* returns once all of them have stopped, with the first error if any failed.
+*/
+
+use crate::error::ServerError;
+use crate::server::{Server, ShutdownHandle};
+use std::io;
+use std::thread;
+use tracing::error;
+
+/// Runs several servers until all of them stop.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::MemorySource;
+/// use file_shover::server::Server;
+/// use file_shover::sites::SiteManager;
+///
+/// let site = |name: &str| {
+///     Server::builder()
+///         .file_source(MemorySource::new().with("index.html", name))
+///         .bind("127.0.0.1:0")
+///         .name(name)
+///         .build()
+/// };
+/// let sites = SiteManager::new().site(site("photos")?).site(site("backups")?);
+/// let shutdowns = sites.shutdown_handles();
+/// let running = std::thread::spawn(move || sites.run());
+///
+/// for shutdown in shutdowns {
+///     shutdown.shutdown();
+/// }
+/// running.join().unwrap()?;
+/// # Ok::<(), file_shover::error::ServerError>(())
+/// ```
+#[derive(Default)]
+pub struct SiteManager {
+    servers: Vec<Server>,
+}
+
+impl SiteManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a bound server; give it a [`name`](crate::server::ServerBuilder::name)
+    /// to tell its log lines apart.
+    pub fn site(mut self, server: Server) -> Self {
+        self.servers.push(server);
+        self
+    }
+
+    /// Number of servers added.
+    pub fn len(&self) -> usize {
+        self.servers.len()
+    }
+
+    /// Returns true if no server was added.
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty()
+    }
+
+    /// Handles that stop each server, in the order they were added.
+    pub fn shutdown_handles(&self) -> Vec<ShutdownHandle> {
+        self.servers.iter().map(Server::shutdown_handle).collect()
+    }
+
+    /// Runs every server on a thread of its own until all have stopped.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error a server stopped with, or an IO error if a
+    /// thread could not be started or panicked. The other servers keep
+    /// running until they stop on their own.
+    pub fn run(self) -> Result<(), ServerError> {
+        let mut running = Vec::with_capacity(self.servers.len());
+        for (index, server) in self.servers.into_iter().enumerate() {
+            running.push(
+                thread::Builder::new()
+                    .name(format!("site-{}", index))
+                    .spawn(move || server.run())?,
+            );
+        }
+        let mut first_error = None;
+        for site in running {
+            let result = site
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("a site panicked").into()));
+            if let Err(e) = result {
+                error!("Site stopped: {}", e);
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::MemorySource;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    fn get(address: std::net::SocketAddr) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET /index.html HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_sites_are_served_side_by_side() {
+        let site = |body: &str| {
+            Server::builder()
+                .file_source(MemorySource::new().with("index.html", body))
+                .bind("127.0.0.1:0")
+                .name(body)
+                .build()
+                .unwrap()
+        };
+        let (photos, backups) = (site("photos"), site("backups"));
+        let addresses = [photos.local_addr().unwrap(), backups.local_addr().unwrap()];
+        let sites = SiteManager::new().site(photos).site(backups);
+        assert_eq!(sites.len(), 2);
+        let shutdowns = sites.shutdown_handles();
+        let running = thread::spawn(move || sites.run());
+
+        assert!(get(addresses[0]).ends_with("photos"));
+        assert!(get(addresses[1]).ends_with("backups"));
+        for shutdown in shutdowns {
+            shutdown.shutdown();
+        }
+        running.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,488 @@
+/*
+* Stats module
+*
+* Live counters kept by a running server: requests answered (by status
+* class), bytes received and sent and connections accepted or refused. Counters are plain
+* atomics bumped once per request, so keeping them costs next to nothing;
+* they are read through the admin API.
+*
+* For the admin dashboard the server also keeps the last five minutes of
+* requests and bytes per second, request and byte counts per path and per
+* client, and the last few requests answered. All sit behind one short-held
+* lock. The path and client tables are capped: when one is full, only the
+* entries in the top quarter by requests or by bytes are kept, so the
+* rankings stay right for the heavy hitters, which is what they are for
+* (what to pre-warm, what to push to a CDN, who uses the bandwidth).
+*/
+
+use serde::Serialize;
+use std::borrow::Borrow;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Seconds of per-second history kept.
+pub const HISTORY_SECS: usize = 300;
+
+/// Distinct paths, and clients, counted before the table is pruned.
+const PATH_LIMIT: usize = 10_000;
+
+/// Requests kept for [`Stats::recent`].
+const RECENT_LIMIT: usize = 200;
+
+/// Counters of a running [`Server`](crate::Server).
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::stats::Stats;
+///
+/// let stats = Stats::new();
+/// stats.record_response(200, 512);
+/// stats.record_response(404, 20);
+/// let snapshot = stats.snapshot();
+/// assert_eq!(snapshot.requests, 2);
+/// assert_eq!(snapshot.responses.client_error, 1);
+/// assert_eq!(snapshot.bytes_sent, 532);
+///
+/// stats.record_received(130);
+/// assert_eq!(stats.snapshot().bytes_received, 130);
+/// ```
+#[derive(Debug)]
+pub struct Stats {
+    started: Instant,
+    requests: AtomicU64,
+    /// Responses by status class, 1xx to 5xx.
+    classes: [AtomicU64; 5],
+    not_modified: AtomicU64,
+    aborted: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    connections: AtomicU64,
+    rejected: AtomicU64,
+    activity: Mutex<Activity>,
+}
+
+#[derive(Debug, Default)]
+struct Activity {
+    /// Seconds since start with their totals, oldest first.
+    seconds: VecDeque<(u64, Sample)>,
+    paths: HashMap<String, Sample>,
+    clients: HashMap<IpAddr, Sample>,
+    recent: VecDeque<RecentRequest>,
+}
+
+/// A point-in-time copy of [`Stats`], serialized by the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsSnapshot {
+    pub uptime_secs: u64,
+    pub requests: u64,
+    pub responses: StatusCounts,
+    /// `304 Not Modified` answers: requests served from the client's cache.
+    pub not_modified: u64,
+    /// Responses cut off because the client disconnected; their bytes
+    /// count as far as they got.
+    pub aborted: u64,
+    pub bytes_sent: u64,
+    /// Request heads and bodies read from clients.
+    pub bytes_received: u64,
+    pub connections_accepted: u64,
+    /// Connections refused by the connection limits.
+    pub connections_rejected: u64,
+}
+
+/// Responses by status class.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusCounts {
+    pub informational: u64,
+    pub success: u64,
+    pub redirection: u64,
+    pub client_error: u64,
+    pub server_error: u64,
+}
+
+/// Requests and bytes sent in one second, or for one path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Sample {
+    pub requests: u64,
+    pub bytes: u64,
+}
+
+impl Sample {
+    fn add(&mut self, bytes: u64) {
+        self.requests += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// One answered request.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentRequest {
+    /// Seconds since the server started.
+    pub at_secs: u64,
+    pub client: IpAddr,
+    pub path: String,
+    pub status: u16,
+    pub bytes: u64,
+}
+
+/// Traffic of one request path.
+#[derive(Debug, Clone, Serialize)]
+pub struct PathStats {
+    pub path: String,
+    pub requests: u64,
+    pub bytes: u64,
+}
+
+/// Traffic of one client.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientStats {
+    pub client: IpAddr,
+    pub requests: u64,
+    pub bytes: u64,
+}
+
+/// What [`Stats::top_paths_by`] and [`Stats::top_clients`] rank by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Ranking {
+    #[default]
+    Requests,
+    Bytes,
+}
+
+impl Ranking {
+    /// Parses `requests` or `bytes`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "requests" => Some(Self::Requests),
+            "bytes" => Some(Self::Bytes),
+            _ => None,
+        }
+    }
+
+    fn key(self, sample: &Sample) -> u64 {
+        match self {
+            Self::Requests => sample.requests,
+            Self::Bytes => sample.bytes,
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    /// Starts counting from zero; uptime is measured from now.
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            classes: Default::default(),
+            not_modified: AtomicU64::new(0),
+            aborted: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            connections: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            activity: Mutex::new(Activity::default()),
+        }
+    }
+
+    /// Counts one answered request.
+    pub fn record_response(&self, status: u16, bytes: u64) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(class) = self.classes.get(usize::from(status / 100).wrapping_sub(1)) {
+            class.fetch_add(1, Ordering::Relaxed);
+        }
+        if status == 304 {
+            self.not_modified.fetch_add(1, Ordering::Relaxed);
+        }
+        let now = self.started.elapsed().as_secs();
+        let mut activity = self.activity();
+        match activity.seconds.back_mut() {
+            Some((second, sample)) if *second == now => sample.add(bytes),
+            _ => {
+                let mut sample = Sample::default();
+                sample.add(bytes);
+                activity.seconds.push_back((now, sample));
+            }
+        }
+        while activity
+            .seconds
+            .front()
+            .is_some_and(|(second, _)| second + (HISTORY_SECS as u64) < now)
+        {
+            activity.seconds.pop_front();
+        }
+    }
+
+    /// Counts one answered request for `path`.
+    pub fn record_path(&self, path: &str, bytes: u64) {
+        count(&mut self.activity().paths, path, bytes);
+    }
+
+    /// Remembers an answered request for [`recent`](Self::recent) and
+    /// counts it for `client`.
+    pub fn record_request(&self, client: IpAddr, path: &str, status: u16, bytes: u64) {
+        let request = RecentRequest {
+            at_secs: self.started.elapsed().as_secs(),
+            client,
+            path: path.to_string(),
+            status,
+            bytes,
+        };
+        let mut activity = self.activity();
+        count(&mut activity.clients, &client, bytes);
+        if activity.recent.len() == RECENT_LIMIT {
+            activity.recent.pop_front();
+        }
+        activity.recent.push_back(request);
+    }
+
+    /// Counts one response the client disconnected from part way, after
+    /// it was recorded with [`record_response`](Self::record_response).
+    pub fn record_aborted(&self) {
+        self.aborted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts `bytes` read from a client.
+    pub fn record_received(&self, bytes: u64) {
+        self.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Counts one accepted connection.
+    pub fn record_connection(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts one connection refused by the connection limits or a full
+    /// worker queue.
+    pub fn record_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Reads all counters.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let class = |index: usize| self.classes[index].load(Ordering::Relaxed);
+        StatsSnapshot {
+            uptime_secs: self.started.elapsed().as_secs(),
+            requests: self.requests.load(Ordering::Relaxed),
+            responses: StatusCounts {
+                informational: class(0),
+                success: class(1),
+                redirection: class(2),
+                client_error: class(3),
+                server_error: class(4),
+            },
+            not_modified: self.not_modified.load(Ordering::Relaxed),
+            aborted: self.aborted.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            connections_accepted: self.connections.load(Ordering::Relaxed),
+            connections_rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Requests and bytes for each of the last [`HISTORY_SECS`] complete
+    /// seconds, oldest first.
+    pub fn history(&self) -> Vec<Sample> {
+        let now = self.started.elapsed().as_secs();
+        let mut history = vec![Sample::default(); HISTORY_SECS];
+        for (second, sample) in &self.activity().seconds {
+            // Slot HISTORY_SECS - 1 is the second before `now`
+            let age = now.saturating_sub(*second) as usize;
+            if (1..=HISTORY_SECS).contains(&age) {
+                history[HISTORY_SECS - age] = *sample;
+            }
+        }
+        history
+    }
+
+    /// The `limit` most requested paths, most requested first.
+    pub fn top_paths(&self, limit: usize) -> Vec<PathStats> {
+        self.top_paths_by(Ranking::Requests, limit)
+    }
+
+    /// The `limit` paths with the most requests or bytes sent, highest
+    /// first. Counts are approximate once more than ten thousand distinct
+    /// paths were requested.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_shover::stats::{Ranking, Stats};
+    ///
+    /// let stats = Stats::new();
+    /// stats.record_path("/index.html", 2_000);
+    /// stats.record_path("/index.html", 2_000);
+    /// stats.record_path("/video.mp4", 500_000_000);
+    /// assert_eq!(stats.top_paths_by(Ranking::Requests, 1)[0].path, "/index.html");
+    /// assert_eq!(stats.top_paths_by(Ranking::Bytes, 1)[0].path, "/video.mp4");
+    /// ```
+    pub fn top_paths_by(&self, ranking: Ranking, limit: usize) -> Vec<PathStats> {
+        top(&self.activity().paths, ranking, limit)
+            .into_iter()
+            .map(|(path, sample)| PathStats {
+                path: path.clone(),
+                requests: sample.requests,
+                bytes: sample.bytes,
+            })
+            .collect()
+    }
+
+    /// The `limit` clients with the most requests or bytes sent, highest
+    /// first; clients are counted as forwarded by a trusted proxy.
+    pub fn top_clients(&self, ranking: Ranking, limit: usize) -> Vec<ClientStats> {
+        top(&self.activity().clients, ranking, limit)
+            .into_iter()
+            .map(|(client, sample)| ClientStats {
+                client: *client,
+                requests: sample.requests,
+                bytes: sample.bytes,
+            })
+            .collect()
+    }
+
+    /// The last `limit` requests answered, oldest first; only the last few
+    /// hundred are kept.
+    pub fn recent(&self, limit: usize) -> Vec<RecentRequest> {
+        let activity = self.activity();
+        let skip = activity.recent.len().saturating_sub(limit);
+        activity.recent.iter().skip(skip).cloned().collect()
+    }
+
+    fn activity(&self) -> std::sync::MutexGuard<'_, Activity> {
+        self.activity.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Adds a request of `bytes` for `key`, pruning a full table first.
+fn count<K, Q>(table: &mut HashMap<K, Sample>, key: &Q, bytes: u64)
+where
+    K: Borrow<Q> + Hash + Eq,
+    Q: ToOwned<Owned = K> + Hash + Eq + ?Sized,
+{
+    if let Some(sample) = table.get_mut(key) {
+        sample.add(bytes);
+        return;
+    }
+    if table.len() >= PATH_LIMIT {
+        let requests = quartile(table, Ranking::Requests);
+        let most_bytes = quartile(table, Ranking::Bytes);
+        table.retain(|_, sample| sample.requests > requests || sample.bytes > most_bytes);
+    }
+    let mut sample = Sample::default();
+    sample.add(bytes);
+    table.insert(key.to_owned(), sample);
+}
+
+/// The value three quarters of the way up `table` by `ranking`.
+fn quartile<K>(table: &HashMap<K, Sample>, ranking: Ranking) -> u64 {
+    let mut counts: Vec<u64> = table.values().map(|sample| ranking.key(sample)).collect();
+    let (_, quartile, _) = counts.select_nth_unstable(table.len() * 3 / 4);
+    *quartile
+}
+
+/// The `limit` highest entries of `table` by `ranking`, ties by key.
+fn top<K: Ord>(table: &HashMap<K, Sample>, ranking: Ranking, limit: usize) -> Vec<(&K, &Sample)> {
+    let mut entries: Vec<(&K, &Sample)> = table.iter().collect();
+    entries.sort_by(|a, b| {
+        ranking
+            .key(b.1)
+            .cmp(&ranking.key(a.1))
+            .then_with(|| a.0.cmp(b.0))
+    });
+    entries.truncate(limit);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_and_top_paths() {
+        let stats = Stats::new();
+        stats.record_response(200, 100);
+        stats.record_response(304, 0);
+        let history = stats.history();
+        assert_eq!(history.len(), HISTORY_SECS);
+        assert!(history.iter().map(|sample| sample.requests).sum::<u64>() <= 2);
+        assert_eq!(stats.snapshot().not_modified, 1);
+
+        for _ in 0..3 {
+            stats.record_path("/b.txt", 10);
+        }
+        stats.record_path("/a.txt", 50);
+        let top = stats.top_paths(1);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].path, "/b.txt");
+        assert_eq!((top[0].requests, top[0].bytes), (3, 30));
+    }
+
+    #[test]
+    fn test_path_limit_keeps_frequent_paths() {
+        let stats = Stats::new();
+        stats.record_path("/popular", 0);
+        stats.record_path("/popular", 0);
+        for i in 0..PATH_LIMIT {
+            stats.record_path(&format!("/once/{}", i), 0);
+        }
+        let paths = stats.top_paths(usize::MAX);
+        assert!(paths.len() <= PATH_LIMIT);
+        assert_eq!(paths[0].path, "/popular");
+    }
+
+    #[test]
+    fn test_pruning_keeps_heavy_paths() {
+        let stats = Stats::new();
+        stats.record_path("/large.iso", 1 << 30);
+        for i in 0..PATH_LIMIT {
+            stats.record_path(&format!("/small/{}", i), 10);
+        }
+        let by_bytes = stats.top_paths_by(Ranking::Bytes, 2);
+        assert_eq!(by_bytes[0].path, "/large.iso");
+        assert_eq!(by_bytes[0].requests, 1);
+        assert!(stats.top_paths(usize::MAX).len() < PATH_LIMIT);
+    }
+
+    #[test]
+    fn test_top_clients() {
+        let stats = Stats::new();
+        let (a, b) = (IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2]));
+        stats.record_request(a, "/a", 200, 10);
+        stats.record_request(a, "/a", 200, 10);
+        stats.record_request(b, "/b", 200, 1_000);
+        let requests = stats.top_clients(Ranking::Requests, 10);
+        assert_eq!((requests[0].client, requests[0].requests), (a, 2));
+        let bytes = stats.top_clients(Ranking::Bytes, 1);
+        assert_eq!(bytes.len(), 1);
+        assert_eq!((bytes[0].client, bytes[0].bytes), (b, 1_000));
+    }
+
+    #[test]
+    fn test_recent_requests() {
+        let stats = Stats::new();
+        let client = IpAddr::from([192, 168, 1, 20]);
+        for i in 0..RECENT_LIMIT + 5 {
+            stats.record_request(client, &format!("/{}", i), 200, 0);
+        }
+        let recent = stats.recent(2);
+        let paths: Vec<&str> = recent.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                format!("/{}", RECENT_LIMIT + 3),
+                format!("/{}", RECENT_LIMIT + 4)
+            ]
+        );
+        assert_eq!(stats.recent(usize::MAX).len(), RECENT_LIMIT);
+    }
+}
@@ -0,0 +1,162 @@
+/*
+* Suggest module
+*
+* "Did you mean" for 404s (`--suggest`): when a file is missing, the names in
+* the requested directory that are close to it are offered instead, which
+* catches the usual typos in links to docs pages:
+*
+*   /docs/instal.html  ->  404 with /docs/install.html
+*   /docs/README.MD    ->  404 with /docs/README.md
+*
+* Names are compared without regard to case and count as close within a
+* Levenshtein distance of 2. They are listed on the error page and in a
+* `Link` header with `rel="related"`, nearest first. Hidden entries are
+* never suggested, the same as in directory listings.
+*/
+
+use crate::files::FileTree;
+use crate::listing::escape_html;
+use crate::message::{Response, StatusCode};
+use crate::target::encode_path;
+
+/// Largest edit distance a suggested name may have.
+pub const MAX_DISTANCE: usize = 2;
+/// Most names offered for one missing path.
+pub const MAX_SUGGESTIONS: usize = 5;
+
+/// Paths in the directory of `path` whose names are close to its last
+/// segment, nearest first. Directories end in `/`.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::{FileTree, MemorySource};
+/// use file_shover::suggest::suggest;
+///
+/// let tree = FileTree::from_source(
+///     MemorySource::new()
+///         .with("docs/install.html", "")
+///         .with("docs/index.html", "")
+///         .with("docs/.install.html", ""),
+/// );
+/// assert_eq!(suggest(&tree, "/docs/Instal.html"), vec!["/docs/install.html"]);
+/// assert!(suggest(&tree, "/docs/changelog.html").is_empty());
+/// ```
+pub fn suggest(file_tree: &FileTree, path: &str) -> Vec<String> {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name.is_empty() || dir.split('/').any(|segment| segment.starts_with('.')) {
+        return Vec::new();
+    }
+    let Ok(entries) = file_tree.list(dir) else {
+        return Vec::new();
+    };
+    let mut close: Vec<(usize, String)> = entries
+        .into_iter()
+        .filter(|entry| !entry.name.starts_with('.'))
+        .filter_map(|entry| {
+            let distance = distance(name, &entry.name);
+            if distance > MAX_DISTANCE || entry.name == name {
+                return None;
+            }
+            let slash = if entry.metadata.is_dir { "/" } else { "" };
+            Some((distance, format!("{}/{}{}", dir, entry.name, slash)))
+        })
+        .collect();
+    close.sort();
+    close.truncate(MAX_SUGGESTIONS);
+    close.into_iter().map(|(_, path)| path).collect()
+}
+
+/// Levenshtein distance between `a` and `b`, ignoring case.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::suggest::distance;
+///
+/// assert_eq!(distance("README.md", "readme.MD"), 0);
+/// assert_eq!(distance("instal.html", "install.html"), 1);
+/// assert_eq!(distance("kitten", "sitting"), 3);
+/// ```
+pub fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().flat_map(char::to_lowercase).collect();
+    let b: Vec<char> = b.chars().flat_map(char::to_lowercase).collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// A 404 page offering `suggestions`, which are also listed in a `Link`
+/// header.
+pub fn not_found(suggestions: &[String]) -> Response {
+    let mut body = String::from("<h1>404 Not Found</h1>\n<p>Did you mean:</p>\n<ul>\n");
+    for path in suggestions {
+        body.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            escape_html(&encode_path(path)),
+            escape_html(path)
+        ));
+    }
+    body.push_str("</ul>\n");
+    let links: Vec<String> = suggestions
+        .iter()
+        .map(|path| format!("<{}>; rel=\"related\"", encode_path(path)))
+        .collect();
+    Response::new()
+        .status(StatusCode::NOT_FOUND)
+        .content_type("text/html; charset=utf-8")
+        .header("Link", links.join(", "))
+        .content_length(body.len())
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::MemorySource;
+
+    fn tree() -> FileTree {
+        FileTree::from_source(
+            MemorySource::new()
+                .with("docs/install.html", "")
+                .with("docs/instant.html", "")
+                .with("docs/guide/index.html", "")
+                .with("README.md", ""),
+        )
+    }
+
+    #[test]
+    fn test_nearest_first() {
+        assert_eq!(
+            suggest(&tree(), "/docs/instal.html"),
+            vec!["/docs/install.html", "/docs/instant.html"]
+        );
+        assert_eq!(suggest(&tree(), "/docs/guid"), vec!["/docs/guide/"]);
+        assert_eq!(suggest(&tree(), "/readme.md"), vec!["/README.md"]);
+    }
+
+    #[test]
+    fn test_nothing_to_suggest() {
+        assert!(suggest(&tree(), "/docs/").is_empty());
+        assert!(suggest(&tree(), "/missing/install.html").is_empty());
+        assert!(suggest(&tree(), "/.git/HEAD").is_empty());
+    }
+
+    #[test]
+    fn test_not_found_page() {
+        let response = not_found(&["/a b.html".to_string(), "/<x>.html".to_string()]);
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers.get("Link").unwrap(),
+            "</a%20b.html>; rel=\"related\", </%3Cx%3E.html>; rel=\"related\""
+        );
+    }
+}
@@ -0,0 +1,266 @@
+/*
+* Syslog module
+*
+* Log lines for the local syslog daemon (`--log-target syslog`), for hosts
+* that collect logs that way instead of from stderr. Each event is one
+* datagram on the daemon's socket (`/dev/log`, or `/var/run/syslog` on
+* macOS), in the BSD format local daemons accept:
+*
+*   <30>file-shover[4242]: request{method=GET ...}: file_shover::server: Request served
+*
+* The priority combines the facility (`daemon` unless `--syslog-facility`
+* says otherwise) with the event's level: ERROR is `err`, WARN `warning`,
+* INFO `info`, DEBUG and TRACE `debug`. The daemon adds the timestamp and
+* host. Lines longer than 8 KiB are cut, and a line the daemon does not take
+* (not running, queue full) is dropped rather than blocking a request.
+*
+* Only the connection needs Unix sockets; facilities and levels are mapped
+* on every platform so the flags parse the same everywhere.
+*/
+
+use std::fmt;
+#[cfg(unix)]
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::Path;
+use std::str::FromStr;
+#[cfg(unix)]
+use std::sync::Arc;
+use tracing::Level;
+#[cfg(unix)]
+use tracing::Metadata;
+#[cfg(unix)]
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Sockets the local daemon listens on, tried in order.
+pub const SOCKET_PATHS: &[&str] = &["/dev/log", "/var/run/syslog", "/var/run/log"];
+
+/// Name lines are tagged with.
+#[cfg(unix)]
+const TAG: &str = "file-shover";
+
+/// Longest message sent; local daemons drop or cut longer datagrams.
+#[cfg(unix)]
+const MAX_MESSAGE: usize = 8 * 1024;
+
+/// Facility the log lines are filed under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Facility {
+    User,
+    #[default]
+    Daemon,
+    Auth,
+    /// `local0` to `local7`.
+    Local(u8),
+}
+
+impl Facility {
+    /// The facility's number in the syslog priority.
+    pub fn code(self) -> u8 {
+        match self {
+            Facility::User => 1,
+            Facility::Daemon => 3,
+            Facility::Auth => 4,
+            Facility::Local(n) => 16 + n,
+        }
+    }
+}
+
+impl FromStr for Facility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(Facility::User),
+            "daemon" => Ok(Facility::Daemon),
+            "auth" => Ok(Facility::Auth),
+            _ => match s.strip_prefix("local").and_then(|n| n.parse::<u8>().ok()) {
+                Some(n) if n <= 7 => Ok(Facility::Local(n)),
+                _ => Err(format!(
+                    "unknown syslog facility '{}' (expected user, daemon, auth or local0-local7)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Facility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Facility::User => write!(f, "user"),
+            Facility::Daemon => write!(f, "daemon"),
+            Facility::Auth => write!(f, "auth"),
+            Facility::Local(n) => write!(f, "local{}", n),
+        }
+    }
+}
+
+/// Syslog severity of a `tracing` level.
+pub fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// A connection to the local syslog daemon, used as the writer of a `fmt`
+/// layer.
+///
+/// # Examples
+///
+/// ```no_run
+/// use file_shover::syslog::{Facility, Syslog};
+/// use tracing_subscriber::layer::SubscriberExt;
+/// use tracing_subscriber::util::SubscriberInitExt;
+///
+/// let syslog = Syslog::connect(Facility::Local(3))?;
+/// let layer = tracing_subscriber::fmt::layer()
+///     .with_writer(syslog)
+///     .with_ansi(false)
+///     .with_level(false)
+///     .without_time();
+/// tracing_subscriber::registry().with(layer).init();
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct Syslog {
+    socket: Arc<UnixDatagram>,
+    facility: Facility,
+    pid: u32,
+}
+
+#[cfg(unix)]
+impl Syslog {
+    /// Connects to the first of [`SOCKET_PATHS`] that accepts datagrams.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the last socket tried if none works, e.g. when
+    /// no syslog daemon runs.
+    pub fn connect(facility: Facility) -> io::Result<Self> {
+        let mut last = io::Error::new(io::ErrorKind::NotFound, "no syslog socket");
+        for path in SOCKET_PATHS {
+            match Self::connect_to(path, facility) {
+                Ok(syslog) => return Ok(syslog),
+                Err(e) => last = io::Error::new(e.kind(), format!("{}: {}", path, e)),
+            }
+        }
+        Err(last)
+    }
+
+    /// Connects to the daemon listening on `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if nothing takes datagrams at `path`.
+    pub fn connect_to(path: impl AsRef<Path>, facility: Facility) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket: Arc::new(socket),
+            facility,
+            pid: std::process::id(),
+        })
+    }
+
+    /// Sends `message` with the priority for `level`.
+    fn send(&self, level: &Level, message: &[u8]) {
+        let message = String::from_utf8_lossy(message);
+        let mut message = message.trim();
+        if message.len() > MAX_MESSAGE {
+            let mut end = MAX_MESSAGE;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message = &message[..end];
+        }
+        let priority = self.facility.code() * 8 + severity(level);
+        let line = format!("<{}>{}[{}]: {}", priority, TAG, self.pid, message);
+        // Nobody to tell if the daemon is gone; the line is lost
+        let _ = self.socket.send(line.as_bytes());
+    }
+}
+
+/// One event's output, sent when the `fmt` layer writes it.
+#[cfg(unix)]
+pub struct SyslogWriter<'a> {
+    syslog: &'a Syslog,
+    level: Level,
+}
+
+#[cfg(unix)]
+impl Write for SyslogWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.syslog.send(&self.level, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = SyslogWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SyslogWriter {
+            syslog: self,
+            level: Level::INFO,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        SyslogWriter {
+            syslog: self,
+            level: *meta.level(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_facility() {
+        assert_eq!("local3".parse(), Ok(Facility::Local(3)));
+        assert_eq!("daemon".parse(), Ok(Facility::Daemon));
+        assert!("local8".parse::<Facility>().is_err());
+        assert!("kern".parse::<Facility>().is_err());
+        assert_eq!(Facility::Local(7).to_string(), "local7");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_priority_and_format() {
+        let path = std::env::temp_dir().join(format!("file-shover-syslog-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let daemon = UnixDatagram::bind(&path).unwrap();
+        let syslog = Syslog::connect_to(&path, Facility::Local(0)).unwrap();
+
+        syslog.send(&Level::WARN, b"Slow request\n");
+        let mut buf = [0; 256];
+        let len = daemon.recv(&mut buf).unwrap();
+        let expected = format!("<132>file-shover[{}]: Slow request", std::process::id());
+        assert_eq!(std::str::from_utf8(&buf[..len]).unwrap(), expected);
+
+        syslog
+            .make_writer()
+            .write_all(&[b'x'; 3 * MAX_MESSAGE])
+            .unwrap();
+        let mut buf = vec![0; 4 * MAX_MESSAGE];
+        let len = daemon.recv(&mut buf).unwrap();
+        assert!(buf[..len].starts_with(b"<134>"));
+        assert!(len < MAX_MESSAGE + 64);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
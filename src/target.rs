@@ -0,0 +1,314 @@
+/*
+* Request target module
+*
+* The request-target from the request line, split into a normalized path and
+* the raw query string. Every consumer (auth rules, routes, the file tree,
+* proxying) sees the same path:
+*
+*   /docs/./a%20b/../c//d.txt?x=1  ->  path "/docs/c/d.txt", query "x=1"
+*
+* Percent escapes are decoded before dot segments are removed, so encoded
+* `..` segments cannot climb out either. Decoded paths must be UTF-8 and free
+* of NUL and other control characters. Repeated slashes are merged, which also
+* keeps a path from turning absolute when joined to a directory.
+*/
+
+use std::fmt;
+
+/// Why a request target was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetError {
+    /// Not `/path`, `http(s)://authority/path` or `*`.
+    InvalidForm,
+    /// A `%` not followed by two hex digits.
+    InvalidEscape,
+    /// The decoded path is not valid UTF-8.
+    InvalidUtf8,
+    /// The path contains NUL or another control character.
+    ControlCharacter,
+}
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetError::InvalidForm => write!(f, "unsupported request target form"),
+            TargetError::InvalidEscape => write!(f, "invalid percent escape"),
+            TargetError::InvalidUtf8 => write!(f, "path is not valid UTF-8"),
+            TargetError::ControlCharacter => write!(f, "control character in path"),
+        }
+    }
+}
+
+impl std::error::Error for TargetError {}
+
+/// A parsed and normalized request target.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::target::RequestTarget;
+///
+/// let target = RequestTarget::parse("/files/../my%20notes//a.txt?v=2").unwrap();
+/// assert_eq!(target.path(), "/my notes/a.txt");
+/// assert_eq!(target.query(), Some("v=2"));
+/// assert_eq!(target.to_string(), "/my%20notes/a.txt?v=2");
+///
+/// assert!(RequestTarget::parse("/a%00b").is_err());
+/// assert!(RequestTarget::parse("/%2e%2e/%2e%2e/etc/passwd").unwrap().path() == "/etc/passwd");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestTarget {
+    path: String,
+    query: Option<String>,
+}
+
+impl RequestTarget {
+    /// Parses an origin-form (`/path?query`), absolute-form
+    /// (`http://host/path?query`) or asterisk-form (`*`) target.
+    ///
+    /// The authority of an absolute-form target is dropped; the `Host`
+    /// header still names the host. A fragment, if a client sends one, is
+    /// ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `TargetError` for other forms, malformed escapes, and paths
+    /// that decode to invalid UTF-8 or contain control characters.
+    pub fn parse(raw: &str) -> Result<Self, TargetError> {
+        if raw == "*" {
+            return Ok(Self {
+                path: "*".to_string(),
+                query: None,
+            });
+        }
+        let raw = raw.split_once('#').map_or(raw, |(before, _)| before);
+        let origin = match raw.find("://") {
+            Some(scheme_end)
+                if raw[..scheme_end].eq_ignore_ascii_case("http")
+                    || raw[..scheme_end].eq_ignore_ascii_case("https") =>
+            {
+                let rest = &raw[scheme_end + 3..];
+                match rest.find(['/', '?']) {
+                    Some(start) if rest[start..].starts_with('/') => &rest[start..],
+                    Some(start) => return Self::parse(&format!("/{}", &rest[start..])),
+                    None => "/",
+                }
+            }
+            _ => raw,
+        };
+        if !origin.starts_with('/') {
+            return Err(TargetError::InvalidForm);
+        }
+
+        let (path, query) = match origin.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (origin, None),
+        };
+        let decoded =
+            String::from_utf8(percent_decode(path)?).map_err(|_| TargetError::InvalidUtf8)?;
+        if decoded.chars().any(char::is_control) {
+            return Err(TargetError::ControlCharacter);
+        }
+        Ok(Self {
+            path: normalize(&decoded),
+            query,
+        })
+    }
+
+    /// The decoded, normalized path; always starts with `/` (or is `*`).
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The query string as received, without the `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// The path percent-encoded again, for use in a URL or request line.
+    pub fn encoded_path(&self) -> String {
+        encode_path(&self.path)
+    }
+}
+
+impl Default for RequestTarget {
+    fn default() -> Self {
+        Self {
+            path: "/".to_string(),
+            query: None,
+        }
+    }
+}
+
+/// Formats the target as it would appear in a request line.
+impl fmt::Display for RequestTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.encoded_path())?;
+        if let Some(query) = &self.query {
+            write!(f, "?{}", query)?;
+        }
+        Ok(())
+    }
+}
+
+/// Percent-encodes every byte of `path` that may not appear literally in a
+/// URL path.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::target::encode_path;
+///
+/// assert_eq!(encode_path("/a b/100%.txt"), "/a%20b/100%25.txt");
+/// assert_eq!(encode_path("/ümlaut"), "/%C3%BCmlaut");
+/// ```
+pub fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Decodes a query string value: percent escapes, and `+` for a space.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::target::decode_component;
+///
+/// assert_eq!(decode_component("%2A.log").unwrap(), "*.log");
+/// assert_eq!(decode_component("a+b").unwrap(), "a b");
+/// assert!(decode_component("%zz").is_err());
+/// ```
+///
+/// # Errors
+///
+/// Returns `InvalidEscape` or `InvalidUtf8` for malformed values.
+pub fn decode_component(value: &str) -> Result<String, TargetError> {
+    String::from_utf8(percent_decode(&value.replace('+', " "))?)
+        .map_err(|_| TargetError::InvalidUtf8)
+}
+
+fn percent_decode(path: &str) -> Result<Vec<u8>, TargetError> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                .ok_or(TargetError::InvalidEscape)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| TargetError::InvalidEscape)?;
+            let byte = u8::from_str_radix(hex, 16).map_err(|_| TargetError::InvalidEscape)?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(decoded)
+}
+
+/// Removes `.` and `..` segments and merges repeated slashes, keeping a
+/// trailing slash.
+fn normalize(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in path.split('/').skip(1) {
+        trailing_slash = matches!(segment, "" | "." | "..");
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(raw: &str) -> String {
+        RequestTarget::parse(raw).unwrap().path().to_string()
+    }
+
+    #[test]
+    fn test_normalization() {
+        assert_eq!(path("/"), "/");
+        assert_eq!(path("/a/b/"), "/a/b/");
+        assert_eq!(path("/a/./b/../c"), "/a/c");
+        assert_eq!(path("/a/.."), "/");
+        assert_eq!(path("/../../etc/passwd"), "/etc/passwd");
+        assert_eq!(path("//etc//passwd"), "/etc/passwd");
+        assert_eq!(path("/a/%2E%2e/b"), "/b");
+        assert_eq!(path("/a%2Fb"), "/a/b");
+    }
+
+    #[test]
+    fn test_forms() {
+        let target = RequestTarget::parse("http://example.com:8080/x?y=1#frag").unwrap();
+        assert_eq!((target.path(), target.query()), ("/x", Some("y=1")));
+        assert_eq!(path("HTTPS://example.com"), "/");
+        assert_eq!(
+            RequestTarget::parse("http://example.com?q")
+                .unwrap()
+                .query(),
+            Some("q")
+        );
+        assert_eq!(path("*"), "*");
+        assert_eq!(
+            RequestTarget::parse("example.com:443"),
+            Err(TargetError::InvalidForm)
+        );
+        assert_eq!(
+            RequestTarget::parse("ftp://example.com/"),
+            Err(TargetError::InvalidForm)
+        );
+    }
+
+    #[test]
+    fn test_rejected() {
+        assert_eq!(
+            RequestTarget::parse("/a%2"),
+            Err(TargetError::InvalidEscape)
+        );
+        assert_eq!(
+            RequestTarget::parse("/a%zz"),
+            Err(TargetError::InvalidEscape)
+        );
+        assert_eq!(
+            RequestTarget::parse("/a%+f"),
+            Err(TargetError::InvalidEscape)
+        );
+        assert_eq!(RequestTarget::parse("/a%ff"), Err(TargetError::InvalidUtf8));
+        assert_eq!(
+            RequestTarget::parse("/a%0d%0aX"),
+            Err(TargetError::ControlCharacter)
+        );
+        assert_eq!(
+            RequestTarget::parse("/a%00.txt"),
+            Err(TargetError::ControlCharacter)
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let target = RequestTarget::parse("/a%20b/c%3Fd?e=%20").unwrap();
+        assert_eq!(target.path(), "/a b/c?d");
+        assert_eq!(target.to_string(), "/a%20b/c%3Fd?e=%20");
+    }
+}
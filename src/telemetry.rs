@@ -0,0 +1,214 @@
+/*
+* Telemetry module
+*
+* Sets up the `tracing` subscriber used by the server. Every connection and
+* every request gets its own span (method, path, status, bytes, duration), so
+* the fmt output can be filtered with `RUST_LOG` and, when the `otel` feature is
+* enabled, the same spans can be shipped to an OTLP collector such as Jaeger.
+* The filter sits behind a reload layer, so the admin API can change log
+* levels without a restart. Lines go to stderr, or on Unix to the local
+* syslog daemon instead (`--log-target syslog`).
+*/
+
+use crate::syslog::Facility;
+#[cfg(unix)]
+use crate::syslog::Syslog;
+use std::str::FromStr;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Filter used when `RUST_LOG` is not set.
+const DEFAULT_FILTER: &str = "info";
+
+/// Where log lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogTarget {
+    #[default]
+    Stderr,
+    /// The local syslog daemon (Unix only).
+    Syslog,
+}
+
+impl FromStr for LogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stderr" => Ok(LogTarget::Stderr),
+            "syslog" => Ok(LogTarget::Syslog),
+            _ => Err(format!(
+                "unknown log target '{}' (expected 'stderr' or 'syslog')",
+                s
+            )),
+        }
+    }
+}
+
+/// Keeps the telemetry pipeline alive.
+///
+/// Dropping the guard flushes and shuts down the OTLP exporter (if any), so it
+/// should be held until the server exits.
+#[derive(Default)]
+pub struct TelemetryGuard {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+    log_level: Option<LogLevel>,
+}
+
+impl TelemetryGuard {
+    /// Handle for changing the log filter at runtime.
+    pub fn log_level(&self) -> Option<LogLevel> {
+        self.log_level.clone()
+    }
+}
+
+/// Changes the installed log filter at runtime.
+#[derive(Clone)]
+pub struct LogLevel {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogLevel {
+    /// The current filter directives, e.g. `info,file_shover=debug`.
+    pub fn current(&self) -> String {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Replaces the filter with `directives`, in `RUST_LOG` syntax.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directives do not parse or the subscriber is
+    /// gone.
+    pub fn set(&self, directives: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let filter = EnvFilter::try_new(directives)?;
+        self.handle.reload(filter)?;
+        Ok(())
+    }
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to shutdown OTLP exporter: {}", e);
+            }
+        }
+    }
+}
+
+/// Installs the global `tracing` subscriber.
+///
+/// Log levels are taken from `RUST_LOG` (defaults to `info`).
+///
+/// # Arguments
+///
+/// * `otlp_endpoint` - Collector URL (e.g. `http://localhost:4318/v1/traces`).
+///   Spans are only exported when the crate is built with the `otel` feature.
+/// * `quiet` - Print no log lines, e.g. while a terminal view owns the
+///   screen. Spans are still exported and syslog still gets its lines.
+/// * `target` - Where log lines go.
+/// * `facility` - Facility of syslog lines.
+///
+/// # Errors
+///
+/// Returns an error if the OTLP exporter cannot be built, the syslog
+/// daemon cannot be reached (or syslog is asked for off Unix), or a global
+/// subscriber has already been installed.
+pub fn init(
+    otlp_endpoint: Option<&str>,
+    quiet: bool,
+    target: LogTarget,
+    facility: Facility,
+) -> Result<TelemetryGuard, Box<dyn std::error::Error>> {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let (filter, handle) = reload::Layer::new(filter);
+    #[cfg(unix)]
+    let syslog = match target {
+        LogTarget::Syslog => Some(
+            Syslog::connect(facility).map_err(|e| format!("Failed to connect to syslog: {}", e))?,
+        ),
+        LogTarget::Stderr => None,
+    };
+    #[cfg(not(unix))]
+    if target == LogTarget::Syslog {
+        let _ = facility;
+        return Err("syslog output is only available on Unix".into());
+    }
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with((!quiet && target == LogTarget::Stderr).then(tracing_subscriber::fmt::layer));
+    #[cfg(unix)]
+    let registry = registry.with(syslog.map(|syslog| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(syslog)
+            .with_ansi(false)
+            .with_level(false)
+            .without_time()
+    }));
+    let log_level = Some(LogLevel { handle });
+
+    #[cfg(feature = "otel")]
+    {
+        let mut guard = TelemetryGuard {
+            provider: None,
+            log_level,
+        };
+        let layer = match otlp_endpoint {
+            Some(endpoint) => {
+                let (provider, layer) = otel::layer(endpoint)?;
+                guard.provider = Some(provider);
+                Some(layer)
+            }
+            None => None,
+        };
+        registry.with(layer).try_init()?;
+        Ok(guard)
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        if otlp_endpoint.is_some() {
+            eprintln!("OTLP endpoint ignored: file-shover was built without the `otel` feature");
+        }
+        registry.try_init()?;
+        Ok(TelemetryGuard { log_level })
+    }
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+    use tracing::Subscriber;
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+
+    type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync>;
+
+    pub fn layer<S>(
+        endpoint: &str,
+    ) -> Result<(SdkTracerProvider, BoxedLayer<S>), Box<dyn std::error::Error>>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span> + Send + Sync,
+    {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name("file-shover").build())
+            .build();
+        let tracer = provider.tracer("file-shover");
+        let layer = tracing_opentelemetry::layer().with_tracer(tracer).boxed();
+        Ok((provider, layer))
+    }
+}
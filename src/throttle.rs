@@ -0,0 +1,184 @@
+/*
+* Throttle module
+*
+* Bandwidth limits for response bodies. Writes to a client connection pass
+* through byte buckets: one per connection and optionally one shared by all
+* connections. Each bucket refills at its rate and holds at most one second
+* of data, so a transfer may start with a short burst and then settles at the
+* configured rate.
+*
+* A write takes its bytes from the buckets up front, possibly going into
+* debt, and sleeps until the debt is paid off. Writes are cut into small
+* chunks so the shared bucket is handed out in turns between connections.
+*/
+
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Most bytes written in one step while throttled.
+const CHUNK: usize = 16 * 1024;
+
+/// Per-connection and global bandwidth caps, in bytes per second.
+///
+/// Clones share the global bucket.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::throttle::Bandwidth;
+///
+/// let bandwidth = Bandwidth::new(Some(2 << 20), Some(10 << 20));
+/// assert!(!bandwidth.is_unlimited());
+/// assert!(Bandwidth::default().is_unlimited());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Bandwidth {
+    per_connection: Option<u64>,
+    global: Option<Arc<Mutex<ByteBucket>>>,
+}
+
+impl Bandwidth {
+    /// Limits each connection to `per_connection` and all of them together
+    /// to `global` bytes per second; `None` leaves that cap off.
+    pub fn new(per_connection: Option<u64>, global: Option<u64>) -> Self {
+        Self {
+            per_connection,
+            global: global.map(|rate| Arc::new(Mutex::new(ByteBucket::new(rate, Instant::now())))),
+        }
+    }
+
+    /// Returns true if neither cap is set.
+    pub fn is_unlimited(&self) -> bool {
+        self.per_connection.is_none() && self.global.is_none()
+    }
+
+    /// Wraps a new connection; reads pass through untouched.
+    pub(crate) fn throttle<S>(&self, inner: S) -> Throttled<S> {
+        Throttled {
+            inner,
+            connection: self
+                .per_connection
+                .map(|rate| ByteBucket::new(rate, Instant::now())),
+            global: self.global.clone(),
+        }
+    }
+}
+
+/// A byte bucket refilling at `rate` bytes per second, up to one second's worth.
+#[derive(Debug)]
+struct ByteBucket {
+    rate: f64,
+    /// Negative while in debt.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl ByteBucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Takes `bytes` and returns how long to wait before sending them.
+    fn take(&mut self, bytes: usize, now: Instant) -> Duration {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate) - bytes as f64;
+        self.last_refill = now;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// A connection whose writes are held to a [`Bandwidth`].
+pub(crate) struct Throttled<S> {
+    inner: S,
+    connection: Option<ByteBucket>,
+    global: Option<Arc<Mutex<ByteBucket>>>,
+}
+
+impl<S: Read> Read for Throttled<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Write> Write for Throttled<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.connection.is_none() && self.global.is_none() {
+            return self.inner.write(buf);
+        }
+        let chunk = &buf[..buf.len().min(CHUNK)];
+        let now = Instant::now();
+        let own = self
+            .connection
+            .as_mut()
+            .map_or(Duration::ZERO, |bucket| bucket.take(chunk.len(), now));
+        let shared = self.global.as_ref().map_or(Duration::ZERO, |bucket| {
+            bucket
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .take(chunk.len(), now)
+        });
+        let wait = own.max(shared);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+        self.inner.write(chunk)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        if self.connection.is_none() && self.global.is_none() {
+            return self.inner.write_vectored(bufs);
+        }
+        // Throttled writes go out a chunk at a time anyway
+        let buf = bufs
+            .iter()
+            .find(|buf| !buf.is_empty())
+            .map_or(&[][..], |buf| buf);
+        self.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_debt() {
+        let start = Instant::now();
+        let mut bucket = ByteBucket::new(1000, start);
+        assert_eq!(bucket.take(1000, start), Duration::ZERO);
+        assert_eq!(bucket.take(500, start), Duration::from_millis(500));
+        // Paid off after the wait, and never refilled past one second
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.take(1000, later), Duration::ZERO);
+        assert_eq!(bucket.take(1, later), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_throttled_writes() {
+        let bandwidth = Bandwidth::new(Some(64 * 1024), None);
+        let mut out = bandwidth.throttle(Vec::new());
+        let started = Instant::now();
+        // One second of burst, then 32K at 64K/s
+        out.write_all(&[7; 96 * 1024]).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(400));
+        assert_eq!(out.inner.len(), 96 * 1024);
+
+        let mut unlimited = Bandwidth::default().throttle(Vec::new());
+        assert_eq!(unlimited.write(&[0; 100_000]).unwrap(), 100_000);
+    }
+}
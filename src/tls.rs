@@ -0,0 +1,730 @@
+/*
+* TLS module
+*
+* HTTPS termination with rustls (ring provider). A certificate chain and
+* private key are loaded from PEM files; embedders that need more control can
+* hand over a prepared `rustls::ServerConfig` instead.
+*
+* Mutual TLS is opt-in: with a client CA configured, clients must present a
+* certificate issued by it during the handshake. The subject of a verified
+* certificate is logged with the connection and is available to path rules
+* (`access = "cert"`), so CI runners can fetch artifacts by certificate
+* instead of carrying tokens.
+*
+* A `TlsPolicy` (the `[tls]` configuration section) narrows what is offered
+* to clients: the oldest protocol version, the cipher suites and their order,
+* the ALPN protocols and whether session tickets are issued. The parameters
+* each connection ends up with are logged at debug level.
+*
+* For local testing a self-signed certificate can be generated at startup
+* instead of loaded. Kept in a directory, it is reused on later starts as
+* long as it covers the requested names, so a browser exception for it only
+* has to be added once.
+*
+* Certificates loaded from files can be swapped while running: a watcher
+* polls the files' modification times and loads them again after a renewal
+* (e.g. by certbot). New handshakes get the new certificate; established
+* connections keep theirs. A pair that fails to load, such as a new
+* certificate whose key has not been written yet, leaves the old one in
+* place until the files change again.
+*/
+
+use crate::config::{ConfigError, TlsPolicyConfig};
+use crate::error::ServerError;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::WebPkiClientVerifier;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{
+    HandshakeKind, RootCertStore, ServerConfig, ServerConnection, SupportedCipherSuite,
+    SupportedProtocolVersion,
+};
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+use x509_parser::extensions::GeneralName;
+
+/// File names of a self-signed certificate and key kept in a directory.
+const SELF_SIGNED_CERT: &str = "self-signed.pem";
+const SELF_SIGNED_KEY: &str = "self-signed.key";
+
+/// Server-side TLS settings shared by every connection.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    config: Arc<ServerConfig>,
+    /// Crypto provider with all of its cipher suites, before a policy
+    /// narrows them.
+    provider: Arc<CryptoProvider>,
+    /// Set by [`client_auth`](TlsConfig::client_auth); kept when the
+    /// configuration is rebuilt for a policy.
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+    policy: TlsPolicy,
+    /// The certificate served, unless a prepared configuration was handed
+    /// over.
+    certificate: Option<Arc<SwappableCert>>,
+    /// Certificate and key files the certificate was loaded from.
+    files: Option<(PathBuf, PathBuf)>,
+}
+
+impl TlsConfig {
+    /// Loads a PEM certificate chain and private key (PKCS#8, PKCS#1 or SEC1).
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Tls` if either file cannot be read or parsed, or
+    /// if the key does not match the certificate.
+    pub fn from_pem_files<P: AsRef<Path>, K: AsRef<Path>>(
+        cert: P,
+        key: K,
+    ) -> Result<Self, ServerError> {
+        let (cert, key) = (cert.as_ref(), key.as_ref());
+        let (chain, der) = load_pem_files(cert, key)?;
+        Ok(Self {
+            files: Some((cert.to_path_buf(), key.to_path_buf())),
+            ..Self::from_der(chain, der)?
+        })
+    }
+
+    /// Builds the configuration from DER-encoded certificates and key.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Tls` if the key is unsupported or does not match
+    /// the certificate.
+    pub fn from_der(
+        chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<Self, ServerError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let certificate = Arc::new(SwappableCert(RwLock::new(Arc::new(
+            CertifiedKey::from_der(chain, key, &provider)?,
+        ))));
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::clone(&certificate) as Arc<dyn ResolvesServerCert>);
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Self {
+            certificate: Some(certificate),
+            ..Self::from_server_config(Arc::new(config))
+        })
+    }
+
+    /// Loads the certificate and key again from the files passed to
+    /// [`from_pem_files`](TlsConfig::from_pem_files). Clones share the
+    /// certificate, so a server using one serves the new certificate from
+    /// its next handshake on.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Tls` if the certificate was not loaded from
+    /// files or the files are invalid; the current certificate stays.
+    pub fn reload(&self) -> Result<(), ServerError> {
+        let (Some((cert, key)), Some(certificate)) = (&self.files, &self.certificate) else {
+            return Err(ServerError::Tls(rustls::Error::General(
+                "the certificate was not loaded from files".to_string(),
+            )));
+        };
+        let (chain, key) = load_pem_files(cert, key)?;
+        let loaded = CertifiedKey::from_der(chain, key, &self.provider)?;
+        *certificate.0.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(loaded);
+        Ok(())
+    }
+
+    /// Reloads the certificate whenever its files change, checking every
+    /// `interval` on a thread of its own. Does nothing unless the
+    /// certificate was loaded from files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the thread cannot be started.
+    pub fn watch(&self, interval: Duration) -> io::Result<()> {
+        let Some((cert, key)) = self.files.clone() else {
+            return Ok(());
+        };
+        let modified =
+            move || [&cert, &key].map(|path| fs::metadata(path).and_then(|m| m.modified()).ok());
+        let tls = self.clone();
+        let mut seen: [Option<SystemTime>; 2] = modified();
+        std::thread::Builder::new()
+            .name("tls-watch".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                let current = modified();
+                if current == seen {
+                    continue;
+                }
+                seen = current;
+                match tls.reload() {
+                    Ok(()) => info!("🔒 Reloaded the TLS certificate"),
+                    Err(e) => warn!("Keeping the current TLS certificate: {}", e),
+                }
+            })?;
+        Ok(())
+    }
+
+    /// Generates a self-signed certificate for `hostnames`, which may be
+    /// host names or IP addresses. Clients have to be told to trust it, so
+    /// this is only meant for testing.
+    ///
+    /// With `dir`, the certificate and key are stored there and reused on
+    /// later calls while the certificate covers all of `hostnames`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Tls` if a name is invalid, or `ServerError::Io`
+    /// if `dir` cannot be written.
+    pub fn self_signed(hostnames: &[String], dir: Option<&Path>) -> Result<Self, ServerError> {
+        if let Some(dir) = dir {
+            let (cert, key) = (dir.join(SELF_SIGNED_CERT), dir.join(SELF_SIGNED_KEY));
+            if covers(&cert, hostnames) {
+                if let Ok(config) = Self::from_pem_files(&cert, &key) {
+                    return Ok(config);
+                }
+            }
+        }
+
+        let generated = rcgen::generate_simple_self_signed(hostnames.to_vec()).map_err(|e| {
+            ServerError::Tls(rustls::Error::General(format!(
+                "cannot generate a certificate: {}",
+                e
+            )))
+        })?;
+        if let Some(dir) = dir {
+            fs::create_dir_all(dir)?;
+            write_private(
+                &dir.join(SELF_SIGNED_KEY),
+                &generated.key_pair.serialize_pem(),
+            )?;
+            fs::write(dir.join(SELF_SIGNED_CERT), generated.cert.pem())?;
+        }
+        let key = PrivateKeyDer::Pkcs8(generated.key_pair.serialize_der().into());
+        Self::from_der(vec![generated.cert.der().clone()], key)
+    }
+
+    /// Requires clients to present a certificate issued by one of the CAs in
+    /// the PEM file `ca`. With `required` false, clients without a certificate
+    /// are let in too, but one that is presented must still verify.
+    ///
+    /// The server certificate, crypto provider and ALPN protocols are kept;
+    /// other settings of a configuration passed to
+    /// [`from_server_config`](TlsConfig::from_server_config) are reset to the
+    /// defaults or those of the [`policy`](TlsConfig::policy).
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Tls` if the file cannot be read or holds no
+    /// usable CA certificate.
+    pub fn client_auth<P: AsRef<Path>>(
+        mut self,
+        ca: P,
+        required: bool,
+    ) -> Result<Self, ServerError> {
+        let ca = ca.as_ref();
+        let mut roots = RootCertStore::empty();
+        for cert in certificates_from_pem_file(ca)? {
+            roots
+                .add(cert)
+                .map_err(|e| invalid_file("invalid client CA file", ca, &e))?;
+        }
+
+        let mut verifier = WebPkiClientVerifier::builder_with_provider(
+            Arc::new(roots),
+            Arc::clone(&self.provider),
+        );
+        if !required {
+            verifier = verifier.allow_unauthenticated();
+        }
+        self.client_verifier = Some(
+            verifier
+                .build()
+                .map_err(|e| invalid_file("invalid client CA file", ca, &e))?,
+        );
+        self.rebuild()
+    }
+
+    /// Applies a policy for protocol versions, cipher suites, ALPN and
+    /// session tickets, replacing the previous one.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::Tls` if no offered cipher suite fits the allowed
+    /// protocol versions.
+    pub fn policy(mut self, policy: TlsPolicy) -> Result<Self, ServerError> {
+        self.policy = policy;
+        self.rebuild()
+    }
+
+    /// Wraps a prepared rustls configuration.
+    pub fn from_server_config(config: Arc<ServerConfig>) -> Self {
+        Self {
+            provider: Arc::clone(config.crypto_provider()),
+            client_verifier: None,
+            policy: TlsPolicy {
+                alpn_protocols: config.alpn_protocols.clone(),
+                ..TlsPolicy::default()
+            },
+            certificate: None,
+            files: None,
+            config,
+        }
+    }
+
+    /// Builds the rustls configuration again from the certificate, client
+    /// verifier and policy.
+    fn rebuild(mut self) -> Result<Self, ServerError> {
+        let mut provider = (*self.provider).clone();
+        if !self.policy.cipher_suites.is_empty() {
+            provider.cipher_suites = self.policy.cipher_suites.clone();
+        }
+        let builder = ServerConfig::builder_with_provider(Arc::new(provider))
+            .with_protocol_versions(&self.policy.versions)?;
+        let builder = match &self.client_verifier {
+            Some(verifier) => builder.with_client_cert_verifier(Arc::clone(verifier)),
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_cert_resolver(Arc::clone(&self.config.cert_resolver));
+        config.alpn_protocols = self.policy.alpn_protocols.clone();
+        match self.policy.session_tickets {
+            Some(true) => config.ticketer = rustls::crypto::ring::Ticketer::new()?,
+            Some(false) => config.send_tls13_tickets = 0,
+            None => {}
+        }
+        self.config = Arc::new(config);
+        Ok(self)
+    }
+
+    /// Starts the server side of a new TLS session.
+    pub(crate) fn accept(&self) -> Result<ServerConnection, rustls::Error> {
+        ServerConnection::new(Arc::clone(&self.config))
+    }
+}
+
+/// What is offered to clients during the handshake.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::config::Config;
+/// use file_shover::tls::TlsPolicy;
+///
+/// let config = Config::from_toml(r#"
+///     [tls]
+///     min_version = "1.3"
+///     cipher_suites = ["TLS13_AES_256_GCM_SHA384"]
+/// "#).unwrap();
+/// assert!(TlsPolicy::from_config(&config.tls).is_ok());
+///
+/// let config = Config::from_toml("[tls]\nmin_version = \"1.1\"").unwrap();
+/// assert!(TlsPolicy::from_config(&config.tls).is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct TlsPolicy {
+    versions: Vec<&'static SupportedProtocolVersion>,
+    /// Offered in this order; the provider's suites if empty.
+    cipher_suites: Vec<SupportedCipherSuite>,
+    alpn_protocols: Vec<Vec<u8>>,
+    session_tickets: Option<bool>,
+}
+
+impl Default for TlsPolicy {
+    /// TLS 1.2 and 1.3 with every supported cipher suite, `http/1.1` over
+    /// ALPN and the rustls defaults for session tickets.
+    fn default() -> Self {
+        Self {
+            versions: rustls::DEFAULT_VERSIONS.to_vec(),
+            cipher_suites: Vec::new(),
+            alpn_protocols: vec![b"http/1.1".to_vec()],
+            session_tickets: None,
+        }
+    }
+}
+
+impl TlsPolicy {
+    /// Builds the policy from the `[tls]` configuration section.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::Invalid`] for unknown versions, cipher suites
+    /// or ALPN protocols, or if no listed cipher suite fits the allowed
+    /// versions.
+    pub fn from_config(config: &TlsPolicyConfig) -> Result<Self, ConfigError> {
+        let versions = match config.min_version.as_deref() {
+            None | Some("1.2") => rustls::DEFAULT_VERSIONS.to_vec(),
+            Some("1.3") => vec![&rustls::version::TLS13],
+            Some(other) => {
+                return Err(ConfigError::Invalid(format!(
+                    "unsupported TLS version '{}' (expected '1.2' or '1.3')",
+                    other
+                )))
+            }
+        };
+
+        let supported = rustls::crypto::ring::default_provider().cipher_suites;
+        let cipher_suites = config
+            .cipher_suites
+            .iter()
+            .map(|name| {
+                supported
+                    .iter()
+                    .find(|suite| suite.suite().as_str() == Some(name.as_str()))
+                    .copied()
+                    .ok_or_else(|| ConfigError::Invalid(format!("unknown cipher suite '{}'", name)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let fits = |suite: &SupportedCipherSuite| {
+            versions
+                .iter()
+                .any(|version| version.version == suite.version().version)
+        };
+        if !cipher_suites.is_empty() && !cipher_suites.iter().any(fits) {
+            return Err(ConfigError::Invalid(
+                "none of the TLS cipher suites can be used with the allowed versions".to_string(),
+            ));
+        }
+
+        let alpn_protocols = match &config.alpn {
+            None => vec![b"http/1.1".to_vec()],
+            Some(protocols) => protocols
+                .iter()
+                .map(|protocol| match protocol.as_str() {
+                    "http/1.1" | "http/1.0" => Ok(protocol.as_bytes().to_vec()),
+                    _ => Err(ConfigError::Invalid(format!(
+                        "unsupported ALPN protocol '{}' (only HTTP/1.x is served)",
+                        protocol
+                    ))),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+
+        Ok(Self {
+            versions,
+            cipher_suites,
+            alpn_protocols,
+            session_tickets: config.session_tickets,
+        })
+    }
+}
+
+/// Describes what a completed handshake settled on, e.g.
+/// `TLSv1_3 TLS13_AES_256_GCM_SHA384, ALPN http/1.1, full handshake`.
+pub(crate) fn negotiated(conn: &ServerConnection) -> String {
+    let version = conn
+        .protocol_version()
+        .map_or_else(|| "unknown version".to_string(), |v| format!("{:?}", v));
+    let suite = conn.negotiated_cipher_suite().map_or_else(
+        || "unknown suite".to_string(),
+        |s| format!("{:?}", s.suite()),
+    );
+    let alpn = conn
+        .alpn_protocol()
+        .map_or(Cow::Borrowed("none"), String::from_utf8_lossy);
+    let kind = match conn.handshake_kind() {
+        Some(HandshakeKind::Resumed) => "resumed",
+        _ => "full handshake",
+    };
+    format!("{} {}, ALPN {}, {}", version, suite, alpn, kind)
+}
+
+/// The verified certificate a client presented during the TLS handshake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCert {
+    /// Distinguished name of the subject, e.g. `CN=ci-runner, O=Example`.
+    pub subject: String,
+    /// Common name of the subject, if it has one.
+    pub common_name: Option<String>,
+}
+
+impl ClientCert {
+    /// Reads the subject of a DER-encoded certificate.
+    ///
+    /// Returns `None` if the certificate cannot be parsed.
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+        let subject = cert.subject();
+        let common_name = subject
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string);
+        Some(Self {
+            subject: subject.to_string(),
+            common_name,
+        })
+    }
+}
+
+fn invalid_file(what: &str, path: &Path, e: &dyn std::fmt::Display) -> ServerError {
+    ServerError::Tls(rustls::Error::General(format!(
+        "{} {}: {}",
+        what,
+        path.display(),
+        e
+    )))
+}
+
+/// Hands out the certificate loaded last.
+#[derive(Debug)]
+struct SwappableCert(RwLock<Arc<CertifiedKey>>);
+
+impl ResolvesServerCert for SwappableCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(
+            &self.0.read().unwrap_or_else(|e| e.into_inner()),
+        ))
+    }
+}
+
+/// Reads a certificate chain and private key from PEM files.
+fn load_pem_files(
+    cert: &Path,
+    key: &Path,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), ServerError> {
+    let chain = certificates_from_pem_file(cert)?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| invalid_file("invalid private key file", key, &e))?;
+    Ok((chain, key))
+}
+
+/// Whether the certificate in the PEM file at `path` names every one of
+/// `hostnames`.
+fn covers(path: &Path, hostnames: &[String]) -> bool {
+    let Some(der) = CertificateDer::pem_file_iter(path)
+        .ok()
+        .and_then(|mut certs| certs.next())
+        .and_then(Result::ok)
+    else {
+        return false;
+    };
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(&der) else {
+        return false;
+    };
+    let Ok(Some(names)) = cert.subject_alternative_name() else {
+        return false;
+    };
+    hostnames.iter().all(|hostname| {
+        let ip = hostname.parse::<IpAddr>().ok();
+        names
+            .value
+            .general_names
+            .iter()
+            .any(|name| match (name, ip) {
+                (GeneralName::DNSName(dns), None) => dns.eq_ignore_ascii_case(hostname),
+                (GeneralName::IPAddress(octets), Some(IpAddr::V4(ip))) => *octets == ip.octets(),
+                (GeneralName::IPAddress(octets), Some(IpAddr::V6(ip))) => *octets == ip.octets(),
+                _ => false,
+            })
+    })
+}
+
+/// Writes a file only the owner can read.
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+/// Reads every certificate in a PEM file.
+fn certificates_from_pem_file(path: &Path) -> Result<Vec<CertificateDer<'static>>, ServerError> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid_file("invalid certificate file", path, &e))?;
+    if certs.is_empty() {
+        return Err(invalid_file(
+            "invalid certificate file",
+            path,
+            &"no certificates found",
+        ));
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_files() {
+        let err = TlsConfig::from_pem_files("no-such-cert.pem", "no-such-key.pem").unwrap_err();
+        assert!(matches!(err, ServerError::Tls(_)));
+        assert!(err.to_string().contains("no-such-cert.pem"));
+    }
+
+    #[test]
+    fn test_generated_certificate() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into());
+        assert!(TlsConfig::from_der(vec![cert.cert.der().clone()], key).is_ok());
+    }
+
+    #[test]
+    fn test_client_auth() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into());
+        let tls = TlsConfig::from_der(vec![cert.cert.der().clone()], key).unwrap();
+
+        let ca = std::env::temp_dir().join(format!("file-shover-ca-{}.pem", std::process::id()));
+        std::fs::write(&ca, cert.cert.pem()).unwrap();
+        assert!(tls.clone().client_auth(&ca, true).is_ok());
+        std::fs::remove_file(&ca).unwrap();
+
+        let err = tls.client_auth(&ca, false).unwrap_err();
+        assert!(err.to_string().contains("file-shover-ca-"));
+    }
+
+    #[test]
+    fn test_client_cert_subject() {
+        let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "ci-runner");
+        params
+            .distinguished_name
+            .push(rcgen::DnType::OrganizationName, "Example");
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+
+        let client = ClientCert::from_der(cert.der()).unwrap();
+        assert_eq!(client.common_name.as_deref(), Some("ci-runner"));
+        assert!(client.subject.contains("CN=ci-runner"));
+        assert!(client.subject.contains("O=Example"));
+        assert!(ClientCert::from_der(b"not a certificate").is_none());
+    }
+
+    #[test]
+    fn test_self_signed_is_reused() {
+        let dir = std::env::temp_dir().join(format!("file-shover-tls-{}", std::process::id()));
+        let names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+        TlsConfig::self_signed(&names, Some(&dir)).unwrap();
+        let cert = dir.join(SELF_SIGNED_CERT);
+        let first = fs::read(&cert).unwrap();
+        assert!(covers(&cert, &names));
+
+        TlsConfig::self_signed(&names[..1], Some(&dir)).unwrap();
+        assert_eq!(fs::read(&cert).unwrap(), first);
+
+        // A name the stored certificate lacks means a new one
+        let more = vec!["nas.local".to_string(), "::1".to_string()];
+        assert!(!covers(&cert, &more));
+        TlsConfig::self_signed(&more, Some(&dir)).unwrap();
+        assert_ne!(fs::read(&cert).unwrap(), first);
+        assert!(covers(&cert, &more));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_swaps_the_certificate() {
+        let dir = std::env::temp_dir().join(format!("file-shover-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert_file, key_file) = (dir.join("cert.pem"), dir.join("key.pem"));
+        let write = |cert: &rcgen::CertifiedKey| {
+            fs::write(&cert_file, cert.cert.pem()).unwrap();
+            fs::write(&key_file, cert.key_pair.serialize_pem()).unwrap();
+        };
+        let old = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let new = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        write(&old);
+        let tls = TlsConfig::from_pem_files(&cert_file, &key_file)
+            .unwrap()
+            .policy(TlsPolicy::default())
+            .unwrap();
+        let serving = tls.clone();
+
+        write(&new);
+        tls.reload().unwrap();
+        assert!(handshake(&serving, &new.cert, &[&rustls::version::TLS13]).is_ok());
+        assert!(handshake(&serving, &old.cert, &[&rustls::version::TLS13]).is_err());
+
+        // A key that does not match is refused and the current pair kept
+        fs::write(&key_file, old.key_pair.serialize_pem()).unwrap();
+        assert!(tls.reload().is_err());
+        assert!(handshake(&serving, &new.cert, &[&rustls::version::TLS13]).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let generated = TlsConfig::self_signed(&["localhost".to_string()], None).unwrap();
+        assert!(generated.reload().is_err());
+    }
+
+    fn policy(toml: &str) -> Result<TlsPolicy, ConfigError> {
+        TlsPolicy::from_config(&crate::config::Config::from_toml(toml).unwrap().tls)
+    }
+
+    /// Runs a handshake in memory between `server` and a client offering
+    /// `versions`.
+    fn handshake(
+        server: &TlsConfig,
+        server_cert: &rcgen::Certificate,
+        versions: &[&'static SupportedProtocolVersion],
+    ) -> Result<ServerConnection, rustls::Error> {
+        let mut roots = RootCertStore::empty();
+        roots.add(server_cert.der().clone()).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_protocol_versions(versions)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let mut client = rustls::ClientConnection::new(
+            Arc::new(client_config),
+            "localhost".try_into().unwrap(),
+        )?;
+        let mut server = server.accept()?;
+        while client.is_handshaking() || server.is_handshaking() {
+            let mut wire = Vec::new();
+            client.write_tls(&mut wire).unwrap();
+            server.read_tls(&mut wire.as_slice()).unwrap();
+            server.process_new_packets()?;
+            wire.clear();
+            server.write_tls(&mut wire).unwrap();
+            client.read_tls(&mut wire.as_slice()).unwrap();
+            client.process_new_packets()?;
+        }
+        Ok(server)
+    }
+
+    #[test]
+    fn test_policy_from_config() {
+        assert!(policy("").is_ok());
+        assert!(policy("[tls]\ncipher_suites = [\"TLS13_AES_128_CCM_SHA256\"]").is_err());
+        assert!(policy("[tls]\nalpn = [\"h2\"]").is_err());
+        assert!(policy(
+            "[tls]\nmin_version = \"1.3\"\ncipher_suites = [\"TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256\"]"
+        )
+        .is_err());
+        let empty = policy("[tls]\nalpn = []\nsession_tickets = false").unwrap();
+        assert!(empty.alpn_protocols.is_empty());
+        assert_eq!(empty.session_tickets, Some(false));
+    }
+
+    #[test]
+    fn test_policy_is_negotiated() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into());
+        let tls = TlsConfig::from_der(vec![cert.cert.der().clone()], key)
+            .unwrap()
+            .policy(
+                policy(
+                    "[tls]\nmin_version = \"1.3\"\ncipher_suites = [\"TLS13_CHACHA20_POLY1305_SHA256\"]",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+
+        assert!(handshake(&tls, &cert.cert, &[&rustls::version::TLS12]).is_err());
+        let server = handshake(&tls, &cert.cert, &[&rustls::version::TLS13]).unwrap();
+        assert_eq!(
+            negotiated(&server),
+            "TLSv1_3 TLS13_CHACHA20_POLY1305_SHA256, ALPN none, full handshake"
+        );
+    }
+}
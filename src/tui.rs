@@ -0,0 +1,294 @@
+/*
+* Terminal dashboard module
+*
+* `serve --tui` replaces the log output with a live view, for ad-hoc shares
+* run from a terminal: the address served, open connections, throughput
+* with a graph of the last minutes, requests as they are answered and the
+* most requested paths. It reads the same `Stats` as the admin dashboard and
+* redraws a few times a second from its own thread; `q`, Esc or Ctrl+C stop
+* the server.
+*/
+
+use crate::connlimit::ConnectionLimiter;
+use crate::dashboard::format_uptime;
+use crate::server::{Server, ShutdownHandle};
+use crate::stats::{Sample, Stats, HISTORY_SECS};
+use crate::units::format_size;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Sparkline, Table};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Time between redraws, which is also the longest a key press waits.
+const REFRESH: Duration = Duration::from_millis(250);
+
+/// Seconds averaged for the current rates.
+const RATE_WINDOW: usize = 5;
+
+/// Paths listed in the top paths table.
+const TOP_PATHS: usize = 20;
+
+/// A live terminal view of a running server.
+///
+/// # Examples
+///
+/// ```no_run
+/// use file_shover::tui::Tui;
+/// use file_shover::Server;
+///
+/// let server = Server::builder().root("public").build()?;
+/// let tui = Tui::new(&server)?.start(server.shutdown_handle())?;
+/// server.run()?;
+/// tui.stop()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Tui {
+    stats: Arc<Stats>,
+    connections: Arc<ConnectionLimiter>,
+    address: String,
+}
+
+impl Tui {
+    /// A view of the counters and connections of `server`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server's address cannot be read.
+    pub fn new(server: &Server) -> io::Result<Self> {
+        Ok(Self {
+            stats: server.stats(),
+            connections: server.connections(),
+            address: server.local_addr()?.to_string(),
+        })
+    }
+
+    /// Takes over the terminal and redraws it until stopped; quitting from
+    /// the keyboard also triggers `shutdown`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the terminal cannot be switched to raw mode.
+    pub fn start(self, shutdown: ShutdownHandle) -> io::Result<TuiHandle> {
+        let mut terminal = ratatui::try_init()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopping = Arc::clone(&stop);
+        let spawned = std::thread::Builder::new()
+            .name("tui".to_string())
+            .spawn(move || {
+                let result = self.run(&mut terminal, &stopping, &shutdown);
+                ratatui::restore();
+                result
+            });
+        match spawned {
+            Ok(thread) => Ok(TuiHandle { stop, thread }),
+            Err(e) => {
+                ratatui::restore();
+                Err(e)
+            }
+        }
+    }
+
+    fn run(
+        &self,
+        terminal: &mut DefaultTerminal,
+        stop: &AtomicBool,
+        shutdown: &ShutdownHandle,
+    ) -> io::Result<()> {
+        while !stop.load(Ordering::Relaxed) {
+            terminal.draw(|frame| self.draw(frame))?;
+            if !event::poll(REFRESH)? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                let quit = key.kind == KeyEventKind::Press
+                    && match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => true,
+                        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+                        _ => false,
+                    };
+                if quit {
+                    shutdown.shutdown();
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let snapshot = self.stats.snapshot();
+        let history = self.stats.history();
+        let rate = |value: fn(&Sample) -> u64| {
+            let recent = &history[HISTORY_SECS - RATE_WINDOW..];
+            recent.iter().map(value).sum::<u64>() as f64 / RATE_WINDOW as f64
+        };
+        let responses = &snapshot.responses;
+
+        let [header, graph, lists] = Layout::vertical([
+            Constraint::Length(4),
+            Constraint::Length(8),
+            Constraint::Min(4),
+        ])
+        .areas(frame.area());
+        let [requests, paths] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(lists);
+
+        let summary = vec![
+            Line::from(format!(
+                "Serving {} · up {} · {} open connections · q to quit",
+                self.address,
+                format_uptime(snapshot.uptime_secs),
+                self.connections.active()
+            )),
+            Line::from(format!(
+                "{} requests · {} sent · {} received · {:.1} requests/s · {}/s · {} errors",
+                snapshot.requests,
+                format_size(snapshot.bytes_sent),
+                format_size(snapshot.bytes_received),
+                rate(|s| s.requests),
+                format_size(rate(|s| s.bytes) as u64),
+                responses.client_error + responses.server_error
+            )),
+        ];
+        frame.render_widget(
+            Paragraph::new(summary).block(Block::bordered().title(" file-shover ")),
+            header,
+        );
+
+        // One bar per second, as many of the latest as fit
+        let width = usize::from(graph.width.saturating_sub(2)).min(HISTORY_SECS);
+        let bytes: Vec<u64> = history[HISTORY_SECS - width..]
+            .iter()
+            .map(|s| s.bytes)
+            .collect();
+        let peak = bytes.iter().copied().max().unwrap_or(0);
+        frame.render_widget(
+            Sparkline::default()
+                .data(&bytes)
+                .style(Style::default().fg(Color::Cyan))
+                .block(Block::bordered().title(format!(
+                    " Sent per second, last {}s (peak {}/s) ",
+                    width,
+                    format_size(peak)
+                ))),
+            graph,
+        );
+
+        let shown = usize::from(requests.height.saturating_sub(2));
+        let rows = self.stats.recent(shown).into_iter().map(|request| {
+            let color = match request.status {
+                200..=299 => Color::Green,
+                300..=399 => Color::Blue,
+                400..=499 => Color::Yellow,
+                _ => Color::Red,
+            };
+            Row::new(vec![
+                request.status.to_string().fg(color),
+                request.client.to_string().into(),
+                format_size(request.bytes).into(),
+                request.path.into(),
+            ])
+        });
+        frame.render_widget(
+            Table::new(
+                rows,
+                [
+                    Constraint::Length(3),
+                    Constraint::Length(15),
+                    Constraint::Length(10),
+                    Constraint::Fill(1),
+                ],
+            )
+            .block(Block::bordered().title(" Requests ")),
+            requests,
+        );
+
+        let rows = self.stats.top_paths(TOP_PATHS).into_iter().map(|path| {
+            Row::new(vec![
+                path.requests.to_string(),
+                format_size(path.bytes),
+                path.path,
+            ])
+        });
+        frame.render_widget(
+            Table::new(
+                rows,
+                [
+                    Constraint::Length(7),
+                    Constraint::Length(10),
+                    Constraint::Fill(1),
+                ],
+            )
+            .block(Block::bordered().title(" Top paths ")),
+            paths,
+        );
+    }
+}
+
+/// A running [`Tui`]; the terminal is restored when it is stopped.
+pub struct TuiHandle {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<io::Result<()>>,
+}
+
+impl TuiHandle {
+    /// Stops redrawing and gives the terminal back.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error that stopped the view early, if any.
+    pub fn stop(self) -> io::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("terminal view panicked")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_draw() {
+        let stats = Arc::new(Stats::new());
+        let client = IpAddr::from([192, 168, 1, 20]);
+        stats.record_response(200, 2048);
+        stats.record_path("/movie.mp4", 2048);
+        stats.record_request(client, "/movie.mp4", 200, 2048);
+        stats.record_response(404, 10);
+        stats.record_path("/missing", 10);
+        stats.record_request(client, "/missing", 404, 10);
+        let tui = Tui {
+            stats,
+            connections: Arc::new(ConnectionLimiter::new(None, None)),
+            address: "0.0.0.0:7878".to_string(),
+        };
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| tui.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("Serving 0.0.0.0:7878"));
+        assert!(screen.contains("2 requests · 2.0 KiB sent"));
+        assert!(screen.contains("1 errors"));
+        assert!(screen.contains("404 192.168.1.20"));
+        assert!(screen.contains("/movie.mp4"));
+    }
+}
@@ -0,0 +1,548 @@
+/*
+* Tus module
+*
+* Resumable uploads for writable mode, following the tus 1.0.0 protocol
+* (https://tus.io/protocols/resumable-upload) with the creation and
+* termination extensions:
+*
+*   POST /_tus/            Upload-Length, Upload-Metadata: filename <b64>
+*       -> 201 Location: /_tus/<id>
+*   HEAD /_tus/<id>        -> Upload-Offset: bytes stored so far
+*   PATCH /_tus/<id>       Upload-Offset: n, body -> Upload-Offset: n + len
+*   DELETE /_tus/<id>      -> abandons the upload
+*
+* The `filename` metadata names the destination below the root and may
+* contain directories. Data is appended to `.uploads/<id>.part` below the
+* root, so whatever arrived before a connection dropped is kept and the
+* client resumes from there. Once the declared length is reached the part
+* file is renamed onto the destination, which readers see all at once.
+*/
+
+use crate::hex::to_hex;
+use crate::message::{
+    HttpMethod, Request, Response, StatusCode, DEFAULT_BAD_REQUEST_BODY, DEFAULT_CONFLICT_BODY,
+    DEFAULT_METHOD_NOT_ALLOWED_BODY, DEFAULT_NOT_FOUND_BODY,
+};
+use crate::target::{encode_path, RequestTarget};
+use crate::upload::{
+    continue_if_expected, copy_chunked, copy_exact, UploadError, Uploads, WriteOperation,
+};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::info;
+
+/// Where the tus endpoint is mounted.
+pub const TUS_PATH: &str = "/_tus/";
+
+/// Where unfinished uploads are kept, relative to the root.
+pub(crate) const STATE_DIR: &str = ".uploads";
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,termination";
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// What is remembered about an unfinished upload, next to its data.
+#[derive(Debug, Serialize, Deserialize)]
+struct Info {
+    /// Request path of the destination.
+    path: String,
+    length: u64,
+}
+
+/// Uploads currently receiving a PATCH, so that two connections never
+/// append to the same file.
+#[derive(Debug, Default)]
+pub(crate) struct Sessions {
+    busy: Mutex<HashSet<String>>,
+}
+
+/// Releases an upload id when a PATCH finishes, however it finishes.
+struct Busy<'a> {
+    sessions: &'a Sessions,
+    id: String,
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.sessions.busy.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Sessions {
+    fn claim(&self, id: &str) -> Option<Busy<'_>> {
+        if !self.busy.lock().unwrap().insert(id.to_string()) {
+            return None;
+        }
+        Some(Busy {
+            sessions: self,
+            id: id.to_string(),
+        })
+    }
+}
+
+/// Answers a request below [`TUS_PATH`], reading any body from `conn`.
+pub(crate) fn handle<C: BufRead + Write>(
+    uploads: &Uploads,
+    sessions: &Sessions,
+    req: &Request,
+    conn: &mut C,
+) -> Response {
+    let response = if req.method == HttpMethod::OPTIONS {
+        Response::new()
+            .status(StatusCode::NO_CONTENT)
+            .header("Tus-Version", TUS_VERSION)
+            .header("Tus-Extension", TUS_EXTENSIONS)
+            .header("Tus-Max-Size", uploads.max_size().to_string())
+    } else if req.header("Tus-Resumable") != Some(TUS_VERSION) {
+        Response::new()
+            .status(StatusCode::PRECONDITION_FAILED)
+            .header("Tus-Version", TUS_VERSION)
+            .content_length(0u64)
+    } else {
+        let id = req.path().strip_prefix(TUS_PATH).unwrap_or_default();
+        let result = match (&req.method, id) {
+            (HttpMethod::POST, "") => create(uploads, req),
+            (_, "") => Err(TusError::MethodNotAllowed("OPTIONS, POST")),
+            (_, id) if !is_id(id) => Err(TusError::NotFound),
+            (HttpMethod::HEAD, id) => offset(uploads, id),
+            (HttpMethod::PATCH, id) => append(uploads, sessions, id, req, conn),
+            (HttpMethod::DELETE, id) => terminate(uploads, sessions, id),
+            _ => Err(TusError::MethodNotAllowed("OPTIONS, HEAD, PATCH, DELETE")),
+        };
+        result.unwrap_or_else(|e| {
+            info!("Resumable upload {} failed: {}", req.path(), e);
+            e.response()
+        })
+    };
+    response.header("Tus-Resumable", TUS_VERSION)
+}
+
+/// `POST /_tus/`: registers an upload and returns where to send its data.
+fn create(uploads: &Uploads, req: &Request) -> Result<Response, TusError> {
+    let length = req
+        .header("Upload-Length")
+        .and_then(|length| length.trim().parse::<u64>().ok())
+        .ok_or(TusError::BadRequest("missing or invalid Upload-Length"))?;
+    if length > uploads.max_size() {
+        return Err(UploadError::TooLarge.into());
+    }
+    let name = req
+        .header("Upload-Metadata")
+        .and_then(|metadata| metadata_value(metadata, "filename"))
+        .ok_or(TusError::BadRequest("missing filename metadata"))?;
+    let path = format!("/{}", name.trim_start_matches('/'));
+    let target = uploads.resolve(&path)?;
+    let mut target_req = req.clone();
+    target_req.target =
+        RequestTarget::parse(&encode_path(&path)).map_err(|_| UploadError::Forbidden)?;
+    uploads.check_access(&target_req)?;
+    uploads.check(&target_req, WriteOperation::Put)?;
+    if target.is_dir() {
+        return Err(UploadError::Conflict.into());
+    }
+
+    let state = uploads.root().join(STATE_DIR);
+    fs::create_dir_all(&state)?;
+    let id = new_id();
+    File::create_new(state.join(format!("{}.part", id)))?;
+    let info = serde_json::to_vec(&Info { path, length }).map_err(io::Error::other)?;
+    fs::write(state.join(format!("{}.json", id)), info)?;
+    info!(
+        "Started resumable upload {} of {} bytes to {}",
+        id,
+        length,
+        target.display()
+    );
+    if length == 0 {
+        finish(uploads, &id)?;
+    }
+    Ok(Response::new()
+        .status(StatusCode::CREATED)
+        .header("Location", format!("{}{}", TUS_PATH, id))
+        .content_length(0u64))
+}
+
+/// `HEAD /_tus/<id>`: how much of the upload has arrived.
+fn offset(uploads: &Uploads, id: &str) -> Result<Response, TusError> {
+    let (info, part) = load(uploads, id)?;
+    Ok(Response::new()
+        .status(StatusCode::OK)
+        .header("Upload-Offset", fs::metadata(part)?.len().to_string())
+        .header("Upload-Length", info.length.to_string())
+        .header("Cache-Control", "no-store")
+        .content_length(0u64))
+}
+
+/// `PATCH /_tus/<id>`: appends the body at the current offset.
+fn append<C: BufRead + Write>(
+    uploads: &Uploads,
+    sessions: &Sessions,
+    id: &str,
+    req: &Request,
+    conn: &mut C,
+) -> Result<Response, TusError> {
+    if req.header("Content-Type") != Some(OFFSET_CONTENT_TYPE) {
+        return Err(TusError::UnsupportedMediaType);
+    }
+    let claimed = req
+        .header("Upload-Offset")
+        .and_then(|offset| offset.trim().parse::<u64>().ok())
+        .ok_or(TusError::BadRequest("missing or invalid Upload-Offset"))?;
+    let _busy = sessions.claim(id).ok_or(TusError::Busy)?;
+    let (info, part) = load(uploads, id)?;
+    let mut file = OpenOptions::new().append(true).open(&part)?;
+    let offset = file.metadata()?.len();
+    if claimed != offset {
+        return Err(TusError::OffsetMismatch);
+    }
+    let remaining = info.length - offset;
+    let chunked = req
+        .header("Transfer-Encoding")
+        .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+    let length = match req.header("Content-Length") {
+        _ if chunked => None,
+        Some(length) => Some(
+            length
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| TusError::BadRequest("invalid Content-Length"))?,
+        ),
+        None => return Err(UploadError::LengthRequired.into()),
+    };
+    if length.is_some_and(|length| length > remaining) {
+        return Err(UploadError::TooLarge.into());
+    }
+
+    continue_if_expected(req, conn)?;
+    // Whatever arrives is kept, even if the client goes away mid-body
+    let copied = match length {
+        Some(length) => copy_exact(conn, &mut file, length),
+        None => copy_chunked(conn, &mut file, remaining),
+    };
+    file.sync_data()?;
+    copied?;
+    drop(file);
+    let offset = fs::metadata(&part)?.len();
+    if offset == info.length {
+        finish(uploads, id)?;
+    }
+    Ok(Response::new()
+        .status(StatusCode::NO_CONTENT)
+        .header("Upload-Offset", offset.to_string()))
+}
+
+/// `DELETE /_tus/<id>`: drops an unfinished upload.
+fn terminate(uploads: &Uploads, sessions: &Sessions, id: &str) -> Result<Response, TusError> {
+    let _busy = sessions.claim(id).ok_or(TusError::Busy)?;
+    let (_, part) = load(uploads, id)?;
+    fs::remove_file(&part)?;
+    fs::remove_file(part.with_extension("json"))?;
+    info!("Abandoned resumable upload {}", id);
+    Ok(Response::new().status(StatusCode::NO_CONTENT))
+}
+
+/// Moves a complete upload onto its destination.
+fn finish(uploads: &Uploads, id: &str) -> Result<(), TusError> {
+    let (info, part) = load(uploads, id)?;
+    let target = uploads.resolve(&info.path)?;
+    if target.is_dir() {
+        return Err(UploadError::Conflict.into());
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|e| match e.kind() {
+            ErrorKind::AlreadyExists | ErrorKind::NotADirectory => UploadError::Conflict,
+            _ => UploadError::Io(e),
+        })?;
+    }
+    fs::rename(&part, &target)?;
+    fs::remove_file(part.with_extension("json"))?;
+    info!("Completed resumable upload {} to {}", id, info.path);
+    Ok(())
+}
+
+/// Reads the info of upload `id` and returns it with its part file.
+fn load(uploads: &Uploads, id: &str) -> Result<(Info, PathBuf), TusError> {
+    let state = uploads.root().join(STATE_DIR);
+    let info = match fs::read(state.join(format!("{}.json", id))) {
+        Ok(info) => info,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(TusError::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+    let info = serde_json::from_slice(&info).map_err(io::Error::other)?;
+    Ok((info, state.join(format!("{}.part", id))))
+}
+
+/// Finds `key` in `Upload-Metadata` (`key base64,key2 base64`) and decodes
+/// its value.
+fn metadata_value(metadata: &str, key: &str) -> Option<String> {
+    metadata.split(',').find_map(|pair| {
+        let mut fields = pair.split_whitespace();
+        if fields.next()? != key {
+            return None;
+        }
+        let value = base64::engine::general_purpose::STANDARD
+            .decode(fields.next()?)
+            .ok()?;
+        String::from_utf8(value).ok()
+    })
+}
+
+/// An unguessable upload id: 32 hex digits.
+fn new_id() -> String {
+    let mut id = [0; 16];
+    getrandom::getrandom(&mut id).expect("the OS random number generator failed");
+    to_hex(&id)
+}
+
+fn is_id(id: &str) -> bool {
+    id.len() == 32
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Why a tus request was refused.
+#[derive(Debug)]
+enum TusError {
+    Upload(UploadError),
+    BadRequest(&'static str),
+    NotFound,
+    MethodNotAllowed(&'static str),
+    /// The client's `Upload-Offset` is not the stored length.
+    OffsetMismatch,
+    /// Another PATCH or DELETE is in progress.
+    Busy,
+    UnsupportedMediaType,
+}
+
+impl std::fmt::Display for TusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TusError::Upload(err) => write!(f, "{}", err),
+            TusError::BadRequest(reason) => write!(f, "{}", reason),
+            TusError::NotFound => write!(f, "no such upload"),
+            TusError::MethodNotAllowed(_) => write!(f, "method not allowed"),
+            TusError::OffsetMismatch => write!(f, "Upload-Offset does not match"),
+            TusError::Busy => write!(f, "upload is being written by another request"),
+            TusError::UnsupportedMediaType => {
+                write!(f, "Content-Type is not {}", OFFSET_CONTENT_TYPE)
+            }
+        }
+    }
+}
+
+impl From<UploadError> for TusError {
+    fn from(err: UploadError) -> Self {
+        TusError::Upload(err)
+    }
+}
+
+impl From<io::Error> for TusError {
+    fn from(err: io::Error) -> Self {
+        TusError::Upload(UploadError::Io(err))
+    }
+}
+
+impl TusError {
+    fn response(self) -> Response {
+        match self {
+            TusError::NotFound => Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY),
+            TusError::OffsetMismatch | TusError::Busy => {
+                Response::error(StatusCode::CONFLICT, DEFAULT_CONFLICT_BODY)
+            }
+            TusError::BadRequest(_) => {
+                Response::error(StatusCode::BAD_REQUEST, DEFAULT_BAD_REQUEST_BODY)
+            }
+            TusError::MethodNotAllowed(allow) => Response::error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                DEFAULT_METHOD_NOT_ALLOWED_BODY,
+            )
+            .header("Allow", allow),
+            TusError::UnsupportedMediaType => Response::new()
+                .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .content_length(0u64),
+            TusError::Upload(err) => err.response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    /// A connection whose input is a request body and whose output is
+    /// discarded.
+    struct Conn(Cursor<Vec<u8>>);
+
+    impl Read for Conn {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl BufRead for Conn {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            self.0.fill_buf()
+        }
+
+        fn consume(&mut self, amount: usize) {
+            self.0.consume(amount)
+        }
+    }
+
+    impl Write for Conn {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn send(uploads: &Uploads, head: &str, body: &str) -> Response {
+        let req = Request::from_bytes(Cursor::new(format!(
+            "{}\r\nTus-Resumable: 1.0.0\r\n\r\n",
+            head
+        )))
+        .unwrap();
+        uploads.handle_resumable(&req, &mut Conn(Cursor::new(body.as_bytes().to_vec())))
+    }
+
+    fn header<'a>(response: &'a Response, name: &str) -> &'a str {
+        response
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .unwrap()
+    }
+
+    fn patch(uploads: &Uploads, location: &str, offset: u64, body: &str) -> Response {
+        send(
+            uploads,
+            &format!(
+                "PATCH {} HTTP/1.1\r\nContent-Type: application/offset+octet-stream\r\n\
+                 Upload-Offset: {}\r\nContent-Length: {}",
+                location,
+                offset,
+                body.len()
+            ),
+            body,
+        )
+    }
+
+    fn root(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("file-shover-tus-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn create_request(name: &str, length: u64) -> String {
+        format!(
+            "POST /_tus/ HTTP/1.1\r\nUpload-Length: {}\r\nUpload-Metadata: filename {},kind YQ==",
+            length,
+            base64::engine::general_purpose::STANDARD.encode(name)
+        )
+    }
+
+    #[test]
+    fn test_resume() {
+        let dir = root("resume");
+        let uploads = Uploads::new(&dir, 100).resumable();
+        let created = send(&uploads, &create_request("builds/big.bin", 10), "");
+        assert_eq!(created.status, StatusCode::CREATED);
+        assert_eq!(header(&created, "Tus-Resumable"), "1.0.0");
+        let location = header(&created, "Location").to_string();
+        assert!(uploads.is_resumable(&location));
+
+        // The connection drops after four of six bytes
+        let response = send(
+            &uploads,
+            &format!(
+                "PATCH {} HTTP/1.1\r\nContent-Type: application/offset+octet-stream\r\n\
+                 Upload-Offset: 0\r\nContent-Length: 6",
+                location
+            ),
+            "0123",
+        );
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        let head = send(&uploads, &format!("HEAD {} HTTP/1.1", location), "");
+        assert_eq!(header(&head, "Upload-Offset"), "4");
+        assert_eq!(header(&head, "Upload-Length"), "10");
+
+        let stale = patch(&uploads, &location, 0, "0123");
+        assert_eq!(stale.status, StatusCode::CONFLICT);
+        let response = patch(&uploads, &location, 4, "456789");
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(header(&response, "Upload-Offset"), "10");
+        assert_eq!(
+            fs::read_to_string(dir.join("builds/big.bin")).unwrap(),
+            "0123456789"
+        );
+        assert_eq!(fs::read_dir(dir.join(STATE_DIR)).unwrap().count(), 0);
+        let gone = send(&uploads, &format!("HEAD {} HTTP/1.1", location), "");
+        assert_eq!(gone.status, StatusCode::NOT_FOUND);
+        assert!(uploads.is_internal("/.uploads/x.part"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_refused() {
+        let dir = root("refused");
+        let uploads = Uploads::new(&dir, 10)
+            .resumable()
+            .authorize(|req, _op| req.path() != "/denied.bin");
+        let status = |head: &str| send(&uploads, head, "").status;
+
+        assert_eq!(
+            status(&create_request("big.bin", 11)),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(status(&create_request(".env", 1)), StatusCode::FORBIDDEN);
+        assert_eq!(
+            status(&create_request("denied.bin", 1)),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status("POST /_tus/ HTTP/1.1\r\nUpload-Length: 1"),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status("HEAD /_tus/0123456789abcdef0123456789abcdef HTTP/1.1"),
+            StatusCode::NOT_FOUND
+        );
+        let req = Request::from_bytes(Cursor::new("HEAD /_tus/x HTTP/1.1\r\n\r\n")).unwrap();
+        let response = uploads.handle_resumable(&req, &mut Conn(Cursor::new(Vec::new())));
+        assert_eq!(response.status, StatusCode::PRECONDITION_FAILED);
+
+        let created = send(&uploads, &create_request("a.bin", 4), "");
+        let location = header(&created, "Location").to_string();
+        let response = send(
+            &uploads,
+            &format!(
+                "PATCH {} HTTP/1.1\r\nUpload-Offset: 0\r\nContent-Length: 1",
+                location
+            ),
+            "x",
+        );
+        assert_eq!(response.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            patch(&uploads, &location, 0, "12345").status,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        let response = send(&uploads, &format!("DELETE {} HTTP/1.1", location), "");
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(fs::read_dir(dir.join(STATE_DIR)).unwrap().count(), 0);
+        assert!(!dir.join("a.bin").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
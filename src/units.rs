@@ -0,0 +1,284 @@
+/*
+* Units module
+*
+* Parsing helpers for human-friendly quantities given on the command line,
+* such as `30s`, `1h`, `500M` or `2MB/s`, and the matching formatting for pages
+* shown to people.
+*/
+
+use std::time::Duration;
+
+/// Parses a duration such as `90`, `30s`, `10m`, `2h` or `7d`.
+///
+/// A bare number is interpreted as seconds.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::units::parse_duration;
+/// use std::time::Duration;
+///
+/// assert_eq!(parse_duration("45").unwrap(), Duration::from_secs(45));
+/// assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+/// assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+/// assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+/// assert!(parse_duration("soon").is_err());
+/// ```
+///
+/// # Errors
+///
+/// Returns a description of the problem if the value is not a number followed
+/// by one of `ms`, `s`, `m`, `h` or `d`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", s))?;
+    let seconds = match unit.trim() {
+        "ms" => return Ok(Duration::from_millis(value)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit in '{}'", s)),
+    };
+    value
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration '{}' is too large", s))
+}
+
+/// Parses a byte size such as `4096`, `64K`, `500M` or `2G`.
+///
+/// Units are binary (`K` is 1024 bytes) and may be followed by `B` or `iB`.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::units::parse_size;
+///
+/// assert_eq!(parse_size("4096").unwrap(), 4096);
+/// assert_eq!(parse_size("64K").unwrap(), 64 * 1024);
+/// assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+/// assert!(parse_size("lots").is_err());
+/// ```
+///
+/// # Errors
+///
+/// Returns a description of the problem if the value is not a number followed
+/// by one of `K`, `M`, `G` or `T`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let unit = unit
+        .strip_suffix("IB")
+        .or_else(|| unit.strip_suffix('B'))
+        .unwrap_or(&unit);
+    let shift = match unit {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("invalid size unit in '{}'", s)),
+    };
+    value
+        .checked_mul(1u64 << shift)
+        .ok_or_else(|| format!("size '{}' is too large", s))
+}
+
+/// Parses a transfer rate in bytes per second such as `2MB/s`, `512K` or
+/// `1G/s`; the `/s` suffix is optional.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::units::parse_rate;
+///
+/// assert_eq!(parse_rate("2MB/s").unwrap(), 2 << 20);
+/// assert_eq!(parse_rate("512K").unwrap(), 512 << 10);
+/// assert!(parse_rate("0/s").is_err());
+/// ```
+///
+/// # Errors
+///
+/// Returns a description of the problem if the value is not a positive
+/// [size](parse_size), optionally followed by `/s`.
+pub fn parse_rate(s: &str) -> Result<u64, String> {
+    let trimmed = s.trim();
+    let size = trimmed.strip_suffix("/s").unwrap_or(trimmed);
+    match parse_size(size)? {
+        0 => Err(format!("rate '{}' must be positive", s)),
+        rate => Ok(rate),
+    }
+}
+
+/// Whole seconds in `wait`, rounded up, for `Retry-After` headers: a
+/// client told to come back after that long never returns too early.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::units::retry_after_secs;
+/// use std::time::Duration;
+///
+/// assert_eq!(retry_after_secs(Duration::from_millis(1500)), 2);
+/// assert_eq!(retry_after_secs(Duration::from_secs(3)), 3);
+/// assert_eq!(retry_after_secs(Duration::MAX), u64::MAX);
+/// ```
+pub fn retry_after_secs(wait: Duration) -> u64 {
+    wait.as_secs()
+        .saturating_add(u64::from(wait.subsec_nanos() > 0))
+}
+
+/// Formats a byte count with one decimal in the largest fitting binary unit.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::units::format_size;
+///
+/// assert_eq!(format_size(512), "512 B");
+/// assert_eq!(format_size(1536), "1.5 KiB");
+/// assert_eq!(format_size(3 << 30), "3.0 GiB");
+/// ```
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Splits seconds since the Unix epoch into UTC year, month, day, hour,
+/// minute and second.
+pub(crate) fn utc_date_time(secs: u64) -> (i64, u8, u8, u8, u8, u8) {
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        year,
+        month,
+        day,
+        (rest / 3_600) as u8,
+        (rest / 60 % 60) as u8,
+        (rest % 60) as u8,
+    )
+}
+
+/// Formats seconds since the Unix epoch as an HTTP date (RFC 9110 IMF-fixdate).
+pub(crate) fn http_date(secs: u64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (year, month, day, hour, minute, second) = utc_date_time(secs);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(secs / 86_400 % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hour,
+        minute,
+        second
+    )
+}
+
+/// Parses an HTTP date into seconds since the Unix epoch.
+///
+/// Accepts the IMF-fixdate format servers must send
+/// (`Sun, 06 Nov 1994 08:49:37 GMT`); the obsolete formats are not accepted,
+/// and headers holding them are treated as absent.
+pub(crate) fn parse_http_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (_, date) = value.trim().split_once(", ")?;
+    let mut parts = date.split(' ');
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|n| n.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if parts.next() != Some("GMT") || year < 1970 || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days since 1970-01-01 from the civil date (Howard Hinnant's algorithm)
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let yoe = year % 400;
+    let doy = (153 * month + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146_097 + doe).checked_sub(719_468)?;
+    Some(days * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration_units() {
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(604_800));
+        assert_eq!(parse_duration(" 5s ").unwrap(), Duration::from_secs(5));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5y").is_err());
+        assert!(parse_duration("-5s").is_err());
+    }
+
+    #[test]
+    fn test_parse_size_units() {
+        assert_eq!(parse_size("1b").unwrap(), 1);
+        assert_eq!(parse_size("3 MB").unwrap(), 3 << 20);
+        assert_eq!(parse_size("1t").unwrap(), 1 << 40);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("1P").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn test_utc_date_time() {
+        assert_eq!(utc_date_time(0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(utc_date_time(1_709_214_330), (2024, 2, 29, 13, 45, 30));
+        assert_eq!(utc_date_time(951_868_800), (2000, 3, 1, 0, 0, 0));
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(1_709_214_330), "Thu, 29 Feb 2024 13:45:30 GMT");
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        for secs in [0, 951_868_800, 1_709_214_330] {
+            assert_eq!(parse_http_date(&http_date(secs)), Some(secs));
+        }
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+}
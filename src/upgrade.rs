@@ -0,0 +1,79 @@
+/*
+* Upgrade module
+*
+* Binary upgrades without dropping connections. On request (SIGUSR2 in the
+* command line server) the running process starts the binary found under
+* its own name again, with the same arguments, and passes it the listening
+* socket as standard input:
+*
+*   old: accepting ──SIGUSR2──> spawn new ──new is up──> stop accepting,
+*                                                        finish downloads, exit
+*   new:                        adopt socket, serve ──────────────────────────>
+*
+* Both processes accept from the one socket while they overlap, so no
+* connection is refused and none waits in a backlog that is about to close.
+* The old process only lets go once the new one has been running for a
+* moment; a binary or configuration that fails to start leaves it serving.
+*/
+
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::{AsFd, OwnedFd};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Set in the environment of the new process, whose standard input is then
+/// the listening socket.
+const INHERITED_ENV: &str = "FILE_SHOVER_INHERITED_LISTENER";
+/// How often a starting successor is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The listening socket passed by the process being upgraded, if this
+/// process is its successor.
+///
+/// # Errors
+///
+/// Returns an error if the environment announces a socket but standard
+/// input is not one.
+pub fn inherited_listener() -> io::Result<Option<TcpListener>> {
+    if std::env::var_os(INHERITED_ENV).is_none() {
+        return Ok(None);
+    }
+    let listener = TcpListener::from(io::stdin().as_fd().try_clone_to_owned()?);
+    // Fails with ENOTSOCK unless standard input really is a socket
+    listener.local_addr()?;
+    Ok(Some(listener))
+}
+
+/// Starts the successor of this process: the program it was started as,
+/// with the same arguments and `listener` as standard input.
+///
+/// Returns the new process once it has been running for `grace`.
+///
+/// # Errors
+///
+/// Returns an error if the program cannot be started or exits within
+/// `grace`, e.g. because its configuration is invalid.
+pub fn spawn_successor(listener: &TcpListener, grace: Duration) -> io::Result<Child> {
+    let mut args = std::env::args_os();
+    let program = args
+        .next()
+        .ok_or_else(|| io::Error::other("the program name is unknown"))?;
+    let socket = OwnedFd::from(listener.try_clone()?);
+    let mut child = Command::new(program)
+        .args(args)
+        .env(INHERITED_ENV, "1")
+        .stdin(Stdio::from(socket))
+        .spawn()?;
+    let started = Instant::now();
+    while started.elapsed() < grace {
+        if let Some(status) = child.try_wait()? {
+            return Err(io::Error::other(format!(
+                "the new process exited during startup ({})",
+                status
+            )));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    Ok(child)
+}
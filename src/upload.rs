@@ -0,0 +1,983 @@
+/*
+* Upload module
+*
+* Writable mode: `PUT /path/file` stores the request body under the root
+* directory, so CI jobs can push artifacts to the server that serves them.
+*
+*   PUT -> validate path and framing -> (100 Continue) -> temp file
+*       -> fsync -> rename over the target -> 201 Created / 204 No Content
+*
+* The body goes to a hidden temp file next to the target and is renamed into
+* place only once complete, so readers never see a half-written file and a
+* failed upload leaves the old version untouched.
+*
+* `DELETE /path` moves a file or directory into a trash directory (`.trash`
+* below the root unless configured otherwise), keeping its relative path, or
+* removes it for good when permanent deletion is configured. Files in the
+* trash are not served.
+*
+* With forms enabled, `POST /dir/` with a `multipart/form-data` body stores
+* every file field in that directory the same way, for the upload form on
+* directory listings; the browser is sent back to the listing.
+*
+* Resumable uploads (the tus protocol) live in the `tus` module and share
+* the limits, path rules and hook defined here.
+*
+* Paths with hidden segments are refused, which keeps writes away from
+* dotfiles, temp files and the trash. An optional hook can refuse any other
+* write by path or request.
+*/
+
+use crate::checksum::Sha256Digest;
+use crate::conditional::preconditions_hold;
+use crate::message::{
+    Request, Response, StatusCode, DEFAULT_BAD_REQUEST_BODY, DEFAULT_CONFLICT_BODY,
+    DEFAULT_FORBIDDEN_BODY, DEFAULT_INTERNAL_ERROR_BODY, DEFAULT_LENGTH_REQUIRED_BODY,
+    DEFAULT_NOT_FOUND_BODY, DEFAULT_PAYLOAD_TOO_LARGE_BODY, DEFAULT_PRECONDITION_FAILED_BODY,
+};
+use crate::middleware::Auth;
+use crate::multipart::{self, Multipart};
+use crate::target::{encode_path, RequestTarget};
+use crate::tus::{self, Sessions, STATE_DIR, TUS_PATH};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Longest chunk-size line accepted in a chunked body.
+const MAX_LINE_LENGTH: u64 = 4096;
+
+/// Distinguishes temp files of concurrent uploads to the same path.
+static UPLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Default trash directory, relative to the root.
+const DEFAULT_TRASH_DIR: &str = ".trash";
+
+/// The kind of write a hook is asked about.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteOperation {
+    Put,
+    Delete,
+}
+
+/// Decides whether a write is allowed, given the request and its path.
+type WriteHook = dyn Fn(&Request, WriteOperation) -> bool + Send + Sync;
+
+/// What a successful upload did.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Uploaded {
+    /// The file did not exist before (201).
+    Created,
+    /// An existing file was replaced (204).
+    Replaced,
+}
+
+/// Why an upload was refused or failed.
+#[derive(Debug)]
+pub enum UploadError {
+    /// The path cannot be written: a directory path, a hidden segment or
+    /// refused by the authorization hook.
+    Forbidden,
+    /// Nothing to delete.
+    NotFound,
+    /// The target is a directory, or a parent is a file.
+    Conflict,
+    /// Neither Content-Length nor chunked framing (forms need a length).
+    LengthRequired,
+    /// The body is larger than the configured limit.
+    TooLarge,
+    /// `If-Match` or `If-Unmodified-Since` does not hold for the current
+    /// file: someone else changed it first.
+    PreconditionFailed,
+    /// Malformed framing or form data, or the client stopped before the
+    /// end of the body.
+    BadBody(io::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::Forbidden => write!(f, "path is not writable"),
+            UploadError::NotFound => write!(f, "no such file or directory"),
+            UploadError::Conflict => write!(f, "path conflicts with a directory or file"),
+            UploadError::LengthRequired => write!(f, "request body has no length"),
+            UploadError::TooLarge => write!(f, "request body exceeds the upload limit"),
+            UploadError::PreconditionFailed => write!(f, "the file was changed or removed"),
+            UploadError::BadBody(err) => write!(f, "incomplete request body: {}", err),
+            UploadError::Io(err) => write!(f, "IO error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UploadError::BadBody(err) | UploadError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl UploadError {
+    /// The error page a client gets for this error.
+    pub(crate) fn response(&self) -> Response {
+        match self {
+            UploadError::Forbidden => {
+                Response::error(StatusCode::FORBIDDEN, DEFAULT_FORBIDDEN_BODY)
+            }
+            UploadError::NotFound => Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY),
+            UploadError::Conflict => Response::error(StatusCode::CONFLICT, DEFAULT_CONFLICT_BODY),
+            UploadError::LengthRequired => {
+                Response::error(StatusCode::LENGTH_REQUIRED, DEFAULT_LENGTH_REQUIRED_BODY)
+            }
+            UploadError::TooLarge => Response::error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                DEFAULT_PAYLOAD_TOO_LARGE_BODY,
+            ),
+            UploadError::PreconditionFailed => Response::error(
+                StatusCode::PRECONDITION_FAILED,
+                DEFAULT_PRECONDITION_FAILED_BODY,
+            ),
+            UploadError::BadBody(_) => {
+                Response::error(StatusCode::BAD_REQUEST, DEFAULT_BAD_REQUEST_BODY)
+            }
+            UploadError::Io(_) => Response::error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                DEFAULT_INTERNAL_ERROR_BODY,
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for UploadError {
+    fn from(err: io::Error) -> Self {
+        UploadError::Io(err)
+    }
+}
+
+/// Stores uploaded files below a root directory, and deletes them.
+///
+/// # Examples
+///
+/// ```no_run
+/// use file_shover::upload::Uploads;
+/// use file_shover::Server;
+///
+/// let server = Server::builder()
+///     .root("public")
+///     .writable(
+///         Uploads::new("public", 512 * 1024 * 1024)
+///             .authorize(|req, _op| req.path().starts_with("/builds/")),
+///     )
+///     .build()?;
+/// # Ok::<(), file_shover::error::ServerError>(())
+/// ```
+#[derive(Clone)]
+pub struct Uploads {
+    root: PathBuf,
+    max_size: u64,
+    /// Where deleted entries go; `None` deletes permanently.
+    trash: Option<PathBuf>,
+    hook: Option<Arc<WriteHook>>,
+    /// The server's access rules, for write targets other than the
+    /// request path.
+    access: Option<Arc<Auth>>,
+    forms: bool,
+    resumable: Option<Arc<Sessions>>,
+}
+
+impl Uploads {
+    /// Accepts files of up to `max_size` bytes below `root`; deleted
+    /// entries go to `.trash` below `root`.
+    pub fn new(root: impl Into<PathBuf>, max_size: u64) -> Self {
+        Self {
+            root: root.into(),
+            max_size,
+            trash: Some(PathBuf::from(DEFAULT_TRASH_DIR)),
+            hook: None,
+            access: None,
+            forms: false,
+            resumable: None,
+        }
+    }
+
+    /// Accepts resumable uploads with the tus protocol at `/_tus/`.
+    pub fn resumable(mut self) -> Self {
+        self.resumable = Some(Arc::new(Sessions::default()));
+        self
+    }
+
+    /// Returns whether `path` (a request path) is for the tus endpoint.
+    pub fn is_resumable(&self, path: &str) -> bool {
+        self.resumable.is_some()
+            && (path == TUS_PATH.trim_end_matches('/') || path.starts_with(TUS_PATH))
+    }
+
+    /// Answers a request for the tus endpoint, reading its body from `conn`.
+    /// Outside [`is_resumable`](Self::is_resumable) paths this answers 404.
+    pub fn handle_resumable<C: BufRead + Write>(&self, req: &Request, conn: &mut C) -> Response {
+        match &self.resumable {
+            Some(sessions) => tus::handle(self, sessions, req, conn),
+            None => Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY),
+        }
+    }
+
+    /// Accepts `multipart/form-data` POSTs to directories, and offers an
+    /// upload form on directory listings.
+    pub fn upload_form(mut self) -> Self {
+        self.forms = true;
+        self
+    }
+
+    /// Returns whether form uploads are accepted.
+    pub fn accepts_forms(&self) -> bool {
+        self.forms
+    }
+
+    /// Moves deleted entries to `dir` instead of `.trash`; a relative `dir`
+    /// is below the root. It should be on the same filesystem as the root.
+    pub fn trash_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.trash = Some(dir.into());
+        self
+    }
+
+    /// Deletes entries for good instead of moving them to the trash.
+    pub fn permanent_delete(mut self) -> Self {
+        self.trash = None;
+        self
+    }
+
+    /// Asks `hook` before every upload and deletion; returning `false`
+    /// refuses it with 403. The request path is `req.path()`.
+    pub fn authorize(
+        mut self,
+        hook: impl Fn(&Request, WriteOperation) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Returns whether `path` (a request path) is inside the trash, which
+    /// is never served.
+    pub fn in_trash(&self, path: &str) -> bool {
+        let Some(trash) = &self.trash else {
+            return false;
+        };
+        let trash = if trash.is_absolute() {
+            match trash.strip_prefix(&self.root) {
+                Ok(relative) => relative,
+                Err(_) => return false,
+            }
+        } else {
+            trash.as_path()
+        };
+        Path::new(path.trim_start_matches('/')).starts_with(trash)
+    }
+
+    /// Deletes the file or directory at the request path, moving it to the
+    /// trash unless deletion is permanent.
+    ///
+    /// # Errors
+    ///
+    /// `Forbidden` for the root, hidden paths or a refusing hook, `NotFound`
+    /// if nothing is there, and I/O errors from moving or removing.
+    pub fn delete(&self, req: &Request) -> Result<(), UploadError> {
+        let target = self.resolve(req.path().trim_end_matches('/'))?;
+        self.check(req, WriteOperation::Delete)?;
+        self.check_preconditions(req, &target)?;
+        let metadata = fs::symlink_metadata(&target).map_err(|e| match e.kind() {
+            ErrorKind::NotFound | ErrorKind::NotADirectory => UploadError::NotFound,
+            _ => UploadError::Io(e),
+        })?;
+        let Some(trash) = &self.trash else {
+            if metadata.is_dir() {
+                fs::remove_dir_all(&target)?;
+            } else {
+                fs::remove_file(&target)?;
+            }
+            return Ok(());
+        };
+
+        // Keep the relative path inside the trash, numbering repeats
+        let relative = target.strip_prefix(&self.root).unwrap_or(&target);
+        let base = self.root.join(trash).join(relative);
+        if let Some(parent) = base.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut destination = base.clone();
+        let mut n = 0;
+        while fs::symlink_metadata(&destination).is_ok() {
+            n += 1;
+            let mut name = base.as_os_str().to_owned();
+            name.push(format!(".{}", n));
+            destination = PathBuf::from(name);
+        }
+        fs::rename(&target, &destination)?;
+        Ok(())
+    }
+
+    /// Returns whether `path` (a request path) holds the trash or
+    /// unfinished resumable uploads, neither of which is served.
+    pub fn is_internal(&self, path: &str) -> bool {
+        self.in_trash(path)
+            || (self.resumable.is_some()
+                && Path::new(path.trim_start_matches('/')).starts_with(STATE_DIR))
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub(crate) fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Applies the server's access rules to write targets, set when the
+    /// server is built.
+    pub(crate) fn access(mut self, auth: Auth) -> Self {
+        self.access = Some(Arc::new(auth));
+        self
+    }
+
+    /// Refuses a write to the path of `req` that its credentials do not
+    /// allow; for targets that are not the request path, which the access
+    /// rules already checked.
+    pub(crate) fn check_access(&self, req: &Request) -> Result<(), UploadError> {
+        if self.can_write(req, req.path()) {
+            Ok(())
+        } else {
+            Err(UploadError::Forbidden)
+        }
+    }
+
+    /// Whether `req` may read `path`, a source it reaches through another
+    /// path, such as a member of a copied directory.
+    pub(crate) fn can_read(&self, req: &Request, path: &str) -> bool {
+        self.access
+            .as_ref()
+            .is_none_or(|auth| auth.can_read(req, path))
+    }
+
+    /// Whether `req` may write or remove `path`, a target it reaches
+    /// through another path.
+    pub(crate) fn can_write(&self, req: &Request, path: &str) -> bool {
+        self.access
+            .as_ref()
+            .is_none_or(|auth| auth.allows(req, path))
+    }
+
+    pub(crate) fn check(
+        &self,
+        req: &Request,
+        operation: WriteOperation,
+    ) -> Result<(), UploadError> {
+        match &self.hook {
+            Some(hook) if !hook(req, operation) => Err(UploadError::Forbidden),
+            _ => Ok(()),
+        }
+    }
+
+    /// Refuses the write unless `If-Match` and `If-Unmodified-Since` hold
+    /// for the file at `target`.
+    fn check_preconditions(&self, req: &Request, target: &Path) -> Result<(), UploadError> {
+        let metadata = fs::metadata(target).ok();
+        let holds = preconditions_hold(
+            req,
+            metadata.is_some(),
+            metadata.as_ref().and_then(|meta| meta.modified().ok()),
+            || match &metadata {
+                Some(meta) if meta.is_file() => {
+                    let file = File::open(target).ok()?;
+                    Some(Sha256Digest::of_reader(file).ok()?.etag())
+                }
+                _ => None,
+            },
+        );
+        if holds {
+            Ok(())
+        } else {
+            Err(UploadError::PreconditionFailed)
+        }
+    }
+
+    /// Stores the body of a PUT request, read from `conn` just after the
+    /// request head. Answers `Expect: 100-continue` on `conn` once the
+    /// request has been checked.
+    ///
+    /// # Errors
+    ///
+    /// See [`UploadError`]; on any error the target is left as it was.
+    pub fn put<C: BufRead + Write>(
+        &self,
+        req: &Request,
+        conn: &mut C,
+    ) -> Result<Uploaded, UploadError> {
+        if req.path().ends_with('/') {
+            return Err(UploadError::Forbidden);
+        }
+        let target = self.resolve(req.path())?;
+        self.check(req, WriteOperation::Put)?;
+        self.check_preconditions(req, &target)?;
+        let chunked = req
+            .header("Transfer-Encoding")
+            .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+        let length = match req.header("Content-Length") {
+            _ if chunked => None,
+            Some(length) => Some(length.trim().parse::<u64>().map_err(|_| {
+                UploadError::BadBody(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "invalid Content-Length",
+                ))
+            })?),
+            None => return Err(UploadError::LengthRequired),
+        };
+        if length.is_some_and(|length| length > self.max_size) {
+            return Err(UploadError::TooLarge);
+        }
+        if target.is_dir() {
+            return Err(UploadError::Conflict);
+        }
+        let parent = target.parent().unwrap_or(&self.root);
+        fs::create_dir_all(parent).map_err(|e| match e.kind() {
+            ErrorKind::AlreadyExists | ErrorKind::NotADirectory => UploadError::Conflict,
+            _ => UploadError::Io(e),
+        })?;
+
+        continue_if_expected(req, conn)?;
+        write_atomically(&target, |file| match length {
+            Some(length) => copy_exact(conn, file, length),
+            None => copy_chunked(conn, file, self.max_size),
+        })
+    }
+
+    /// Stores the file fields of a `multipart/form-data` POST to an existing
+    /// directory, read from `conn` just after the request head, and returns
+    /// how many files were stored. The whole body counts against the upload
+    /// limit, and the hook is asked about each file's path.
+    ///
+    /// # Errors
+    ///
+    /// See [`UploadError`]; files stored before an error are kept, and the
+    /// file being written when it happened is left as it was.
+    pub fn post_form<C: BufRead + Write>(
+        &self,
+        req: &Request,
+        conn: &mut C,
+    ) -> Result<usize, UploadError> {
+        let dir_path = req.path().trim_end_matches('/');
+        let dir = match dir_path {
+            "" => self.root.clone(),
+            path => self.resolve(path)?,
+        };
+        if !dir.is_dir() {
+            return Err(UploadError::NotFound);
+        }
+        let boundary = req
+            .header("Content-Type")
+            .and_then(multipart::boundary)
+            .ok_or_else(|| {
+                UploadError::BadBody(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "not multipart/form-data",
+                ))
+            })?;
+        let length = req
+            .header("Content-Length")
+            .filter(|_| req.header("Transfer-Encoding").is_none())
+            .ok_or(UploadError::LengthRequired)?
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| {
+                UploadError::BadBody(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "invalid Content-Length",
+                ))
+            })?;
+        if length > self.max_size {
+            return Err(UploadError::TooLarge);
+        }
+
+        continue_if_expected(req, conn)?;
+        let mut form = Multipart::new(conn.take(length), &boundary);
+        let mut stored = 0;
+        while let Some(part) = form.next_part().map_err(UploadError::BadBody)? {
+            // Browsers send bare names, but older ones sent whole paths
+            let Some(name) = part
+                .filename
+                .as_deref()
+                .and_then(|name| name.rsplit(['/', '\\']).next())
+                .filter(|name| !name.is_empty())
+            else {
+                continue;
+            };
+            let path = format!("{}/{}", dir_path, name);
+            let target = self.resolve(&path)?;
+            let mut file_req = req.clone();
+            file_req.target =
+                RequestTarget::parse(&encode_path(&path)).map_err(|_| UploadError::Forbidden)?;
+            self.check_access(&file_req)?;
+            self.check(&file_req, WriteOperation::Put)?;
+            if target.is_dir() {
+                return Err(UploadError::Conflict);
+            }
+            write_atomically(&target, |file| {
+                form.copy_body(file).map_err(UploadError::BadBody)?;
+                Ok(())
+            })?;
+            stored += 1;
+        }
+        // Leave the connection at the end of the body
+        io::copy(&mut form.into_inner(), &mut io::sink()).map_err(UploadError::BadBody)?;
+        Ok(stored)
+    }
+
+    /// Maps a request path to a file below the root.
+    pub(crate) fn resolve(&self, path: &str) -> Result<PathBuf, UploadError> {
+        let relative = path.trim_start_matches('/');
+        if relative.is_empty() {
+            return Err(UploadError::Forbidden);
+        }
+        let mut target = self.root.clone();
+        for segment in relative.split('/') {
+            if segment.is_empty() || segment.starts_with('.') || segment.contains('\\') {
+                return Err(UploadError::Forbidden);
+            }
+            target.push(segment);
+        }
+        Ok(target)
+    }
+}
+
+/// Answers `Expect: 100-continue` once a request has been checked.
+pub(crate) fn continue_if_expected<W: Write>(req: &Request, conn: &mut W) -> io::Result<()> {
+    if req
+        .header("Expect")
+        .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"))
+    {
+        conn.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        conn.flush()?;
+    }
+    Ok(())
+}
+
+/// Writes a temp file with `write`, then syncs and renames it over
+/// `target`; on error the temp file is removed and `target` left alone.
+fn write_atomically(
+    target: &Path,
+    write: impl FnOnce(&mut File) -> Result<(), UploadError>,
+) -> Result<Uploaded, UploadError> {
+    let temp = temp_path(target);
+    let written = File::create_new(&temp)
+        .map_err(UploadError::Io)
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all()?;
+            let existed = target.is_file();
+            fs::rename(&temp, target)?;
+            Ok(if existed {
+                Uploaded::Replaced
+            } else {
+                Uploaded::Created
+            })
+        });
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+/// A hidden, unique sibling of `target` to write into before renaming.
+fn temp_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    target.with_file_name(format!(
+        ".{}.upload-{}-{}",
+        name,
+        std::process::id(),
+        UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Copies exactly `length` bytes of body.
+pub(crate) fn copy_exact<R: Read, W: Write>(
+    body: &mut R,
+    out: &mut W,
+    length: u64,
+) -> Result<(), UploadError> {
+    let copied = io::copy(&mut body.take(length), out).map_err(UploadError::BadBody)?;
+    if copied < length {
+        return Err(UploadError::BadBody(ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(())
+}
+
+/// Decodes a chunked body into `out`, refusing more than `limit` bytes.
+pub(crate) fn copy_chunked<R: BufRead, W: Write>(
+    body: &mut R,
+    out: &mut W,
+    limit: u64,
+) -> Result<(), UploadError> {
+    let mut line = String::new();
+    let mut total = 0u64;
+    loop {
+        read_line(body, &mut line)?;
+        let size = line.trim_end().split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| {
+            UploadError::BadBody(io::Error::new(ErrorKind::InvalidData, "invalid chunk size"))
+        })?;
+        if size == 0 {
+            break;
+        }
+        total = total.saturating_add(size);
+        if total > limit {
+            return Err(UploadError::TooLarge);
+        }
+        copy_exact(body, out, size)?;
+        read_line(body, &mut line)?;
+        if !line.trim_end().is_empty() {
+            return Err(UploadError::BadBody(io::Error::new(
+                ErrorKind::InvalidData,
+                "missing CRLF after chunk",
+            )));
+        }
+    }
+    // Trailer fields are ignored, up to the empty line
+    loop {
+        read_line(body, &mut line)?;
+        if line.trim_end().is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads one line of at most [`MAX_LINE_LENGTH`] bytes, failing on EOF.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> Result<(), UploadError> {
+    line.clear();
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE_LENGTH)
+        .read_line(line)
+        .map_err(UploadError::BadBody)?;
+    if read == 0 {
+        return Err(UploadError::BadBody(ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A connection whose input is `body` and whose output is recorded.
+    struct Conn {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Conn {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl BufRead for Conn {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            self.input.fill_buf()
+        }
+
+        fn consume(&mut self, amount: usize) {
+            self.input.consume(amount)
+        }
+    }
+
+    impl Write for Conn {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn upload(uploads: &Uploads, head: &str, body: &str) -> (Result<Uploaded, UploadError>, Conn) {
+        let req = Request::from_bytes(Cursor::new(format!("{}\r\n\r\n", head))).unwrap();
+        let mut conn = Conn {
+            input: Cursor::new(body.as_bytes().to_vec()),
+            output: Vec::new(),
+        };
+        (uploads.put(&req, &mut conn), conn)
+    }
+
+    fn root(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("file-shover-up-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_create_and_replace() {
+        let dir = root("replace");
+        let uploads = Uploads::new(&dir, 100);
+        let (result, conn) = upload(
+            &uploads,
+            "PUT /builds/1/app.bin HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue",
+            "hello",
+        );
+        assert_eq!(result.unwrap(), Uploaded::Created);
+        assert_eq!(conn.output, b"HTTP/1.1 100 Continue\r\n\r\n");
+        assert_eq!(fs::read(dir.join("builds/1/app.bin")).unwrap(), b"hello");
+
+        let (result, _) = upload(
+            &uploads,
+            "PUT /builds/1/app.bin HTTP/1.1\r\nTransfer-Encoding: chunked",
+            "3\r\nbye\r\n2;ext=1\r\n!!\r\n0\r\nX-Trailer: 1\r\n\r\n",
+        );
+        assert_eq!(result.unwrap(), Uploaded::Replaced);
+        assert_eq!(fs::read(dir.join("builds/1/app.bin")).unwrap(), b"bye!!");
+        // No temp files left behind
+        assert_eq!(fs::read_dir(dir.join("builds/1")).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_preconditions() {
+        let dir = root("preconditions");
+        fs::write(dir.join("doc.txt"), "v1").unwrap();
+        let etag = Sha256Digest::of_reader(&b"v1"[..]).unwrap().etag();
+        let uploads = Uploads::new(&dir, 100);
+        let put = |precondition: &str| {
+            let head = format!(
+                "PUT /doc.txt HTTP/1.1\r\nContent-Length: 2\r\n{}",
+                precondition
+            );
+            upload(&uploads, &head, "v2").0
+        };
+
+        let err = put("If-Match: \"stale\"").unwrap_err();
+        assert!(matches!(err, UploadError::PreconditionFailed));
+        let err = put("If-Unmodified-Since: Thu, 01 Jan 1970 00:00:00 GMT").unwrap_err();
+        assert!(matches!(err, UploadError::PreconditionFailed));
+        assert_eq!(fs::read(dir.join("doc.txt")).unwrap(), b"v1");
+        assert_eq!(
+            put(&format!("If-Match: {}", etag)).unwrap(),
+            Uploaded::Replaced
+        );
+        // The old tag no longer matches once the file changed
+        let req = Request::builder()
+            .method(crate::message::HttpMethod::DELETE)
+            .path("/doc.txt")
+            .header("If-Match", etag)
+            .build();
+        assert!(matches!(
+            uploads.delete(&req),
+            Err(UploadError::PreconditionFailed)
+        ));
+        assert!(dir.join("doc.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_refused_uploads_leave_target_alone() {
+        let dir = root("refused");
+        fs::write(dir.join("keep.txt"), "original").unwrap();
+        let uploads = Uploads::new(&dir, 4);
+        let put = |head: &str, body: &str| upload(&uploads, head, body).0.unwrap_err();
+
+        let err = put("PUT /keep.txt HTTP/1.1\r\nContent-Length: 5", "hello");
+        assert!(matches!(err, UploadError::TooLarge));
+        let err = put(
+            "PUT /keep.txt HTTP/1.1\r\nTransfer-Encoding: chunked",
+            "5\r\nhello\r\n0\r\n\r\n",
+        );
+        assert!(matches!(err, UploadError::TooLarge));
+        let err = put("PUT /keep.txt HTTP/1.1\r\nContent-Length: 4", "hi");
+        assert!(matches!(err, UploadError::BadBody(_)));
+        assert!(matches!(
+            put("PUT /keep.txt HTTP/1.1", ""),
+            UploadError::LengthRequired
+        ));
+        assert!(matches!(
+            put("PUT /.env HTTP/1.1\r\nContent-Length: 1", "x"),
+            UploadError::Forbidden
+        ));
+        assert!(matches!(
+            put("PUT /dir/ HTTP/1.1\r\nContent-Length: 1", "x"),
+            UploadError::Forbidden
+        ));
+        let err = put("PUT /keep.txt/inner HTTP/1.1\r\nContent-Length: 1", "x");
+        assert!(matches!(err, UploadError::Conflict));
+
+        let uploads = uploads
+            .authorize(|req, op| op == WriteOperation::Put && !req.path().starts_with("/keep"));
+        let (result, _) = upload(&uploads, "PUT /keep.txt HTTP/1.1\r\nContent-Length: 1", "x");
+        assert!(matches!(result, Err(UploadError::Forbidden)));
+        let (result, _) = upload(
+            &uploads,
+            "PUT /other.txt HTTP/1.1\r\nContent-Length: 1",
+            "x",
+        );
+        assert!(result.is_ok());
+        assert!(matches!(
+            uploads.delete(&request("DELETE /other.txt HTTP/1.1")),
+            Err(UploadError::Forbidden)
+        ));
+
+        assert_eq!(
+            fs::read_to_string(dir.join("keep.txt")).unwrap(),
+            "original"
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn request(head: &str) -> Request {
+        Request::from_bytes(Cursor::new(format!("{}\r\n\r\n", head))).unwrap()
+    }
+
+    #[test]
+    fn test_delete_to_trash() {
+        let dir = root("trash");
+        fs::create_dir_all(dir.join("builds/1")).unwrap();
+        fs::write(dir.join("builds/1/app.bin"), "v1").unwrap();
+        let uploads = Uploads::new(&dir, 100);
+
+        uploads
+            .delete(&request("DELETE /builds/1/app.bin HTTP/1.1"))
+            .unwrap();
+        assert!(!dir.join("builds/1/app.bin").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join(".trash/builds/1/app.bin")).unwrap(),
+            "v1"
+        );
+
+        // A second deletion of the same path is kept alongside the first
+        fs::write(dir.join("builds/1/app.bin"), "v2").unwrap();
+        uploads
+            .delete(&request("DELETE /builds/1/app.bin HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join(".trash/builds/1/app.bin.1")).unwrap(),
+            "v2"
+        );
+
+        uploads
+            .delete(&request("DELETE /builds/ HTTP/1.1"))
+            .unwrap();
+        assert!(dir.join(".trash/builds/1").is_dir());
+        assert!(!dir.join("builds").exists());
+
+        assert!(uploads.in_trash("/.trash/builds/1/app.bin"));
+        assert!(!uploads.in_trash("/.trashy"));
+        assert!(matches!(
+            uploads.delete(&request("DELETE /builds HTTP/1.1")),
+            Err(UploadError::NotFound)
+        ));
+        assert!(matches!(
+            uploads.delete(&request("DELETE /.trash HTTP/1.1")),
+            Err(UploadError::Forbidden)
+        ));
+        assert!(matches!(
+            uploads.delete(&request("DELETE / HTTP/1.1")),
+            Err(UploadError::Forbidden)
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_permanent_delete() {
+        let dir = root("permanent");
+        fs::create_dir_all(dir.join("tmp")).unwrap();
+        fs::write(dir.join("tmp/a.txt"), "a").unwrap();
+        let uploads = Uploads::new(&dir, 100).permanent_delete();
+        uploads.delete(&request("DELETE /tmp HTTP/1.1")).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        assert!(!uploads.in_trash("/.trash/a"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn post(uploads: &Uploads, path: &str, body: &str) -> (Result<usize, UploadError>, Conn) {
+        let req = request(&format!(
+            "POST {} HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XX\r\n\
+             Content-Length: {}",
+            path,
+            body.len()
+        ));
+        let mut conn = Conn {
+            input: Cursor::new(format!("{}NEXT", body).into_bytes()),
+            output: Vec::new(),
+        };
+        (uploads.post_form(&req, &mut conn), conn)
+    }
+
+    #[test]
+    fn test_form_upload() {
+        let dir = root("form");
+        fs::create_dir_all(dir.join("inbox")).unwrap();
+        let uploads = Uploads::new(&dir, 400)
+            .upload_form()
+            .authorize(|req, _op| req.path() != "/inbox/denied.txt");
+        let part = |filename: &str, body: &str| {
+            format!(
+                "--XX\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n{}\r\n",
+                filename, body
+            )
+        };
+
+        let body = format!(
+            "--XX\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhi\r\n{}{}{}--XX--\r\n",
+            part("a.txt", "alpha"),
+            part("C:\\Users\\me\\b.txt", "beta"),
+            part("", "")
+        );
+        let (result, mut conn) = post(&uploads, "/inbox/", &body);
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(
+            fs::read_to_string(dir.join("inbox/a.txt")).unwrap(),
+            "alpha"
+        );
+        assert_eq!(fs::read_to_string(dir.join("inbox/b.txt")).unwrap(), "beta");
+        // The body was consumed exactly
+        let mut rest = String::new();
+        conn.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "NEXT");
+
+        let body = format!("{}--XX--\r\n", part("a.txt", "root"));
+        assert_eq!(post(&uploads, "/", &body).0.unwrap(), 1);
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "root");
+
+        let refused = [
+            ("/inbox/", part(".env", "x")),
+            ("/inbox/", part("denied.txt", "x")),
+            ("/missing/", part("a.txt", "x")),
+            ("/inbox/", part("a.txt", "truncated")),
+            (
+                "/inbox/",
+                format!("{}--XX--\r\n", part("big", &"x".repeat(400))),
+            ),
+        ];
+        let errors: Vec<_> = refused
+            .iter()
+            .map(|(path, body)| post(&uploads, path, body).0.unwrap_err())
+            .collect();
+        assert!(matches!(errors[0], UploadError::Forbidden));
+        assert!(matches!(errors[1], UploadError::Forbidden));
+        assert!(matches!(errors[2], UploadError::NotFound));
+        assert!(matches!(errors[3], UploadError::BadBody(_)));
+        assert!(matches!(errors[4], UploadError::TooLarge));
+        assert_eq!(
+            fs::read_to_string(dir.join("inbox/a.txt")).unwrap(),
+            "alpha"
+        );
+        assert_eq!(fs::read_dir(dir.join("inbox")).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
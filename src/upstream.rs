@@ -0,0 +1,744 @@
+/*
+* Upstream module
+*
+* Minimal HTTP/1.1 reverse proxying to a plain-HTTP upstream such as a
+* development API server. Every forwarded request opens a fresh upstream
+* connection with `Connection: close`:
+*
+*   client --request head--> file-shover --rewritten head--> upstream
+*          --body stream-->              --body stream-->
+*          <--response stream--          <--response stream--
+*
+* Request bodies are copied as they arrive (Content-Length or chunked framing
+* is passed through unchanged) and the upstream response is relayed verbatim
+* until the upstream closes the connection, so nothing is buffered in full.
+* With `sendfile`, a response head naming a file in `X-Shover-Send` is kept
+* from the client instead, for the server to answer with that file.
+*
+* The forwarded head gets `Host` set to the upstream and the usual
+* `X-Forwarded-For`/`-Host`/`-Proto` headers describing the original request.
+*
+* Proxy routes mount an upstream under a path prefix. The prefix is replaced
+* by the upstream URL's path:
+*
+*   --proxy /api=http://127.0.0.1:8081       /api/users -> /users
+*   --proxy /api=http://127.0.0.1:8081/api   /api/users -> /api/users
+*/
+
+use crate::config::ConfigError;
+use crate::message::Request;
+use crate::sendfile::SEND_HEADER;
+use crate::target::encode_path;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Longest request or status line accepted while relaying.
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const IO_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Headers that describe a single connection and are not forwarded.
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Upgrade",
+    "Expect",
+];
+
+/// Headers framing the body, sent again as it is actually forwarded.
+const FRAMING_HEADERS: &[&str] = &["Content-Length", "Transfer-Encoding"];
+
+/// Headers replaced by [`Upstream::forward`].
+const REWRITTEN_HEADERS: &[&str] = &[
+    "Host",
+    "X-Forwarded-For",
+    "X-Forwarded-Host",
+    "X-Forwarded-Proto",
+];
+
+/// Errors raised while forwarding a request.
+#[derive(Debug)]
+pub enum UpstreamError {
+    /// The upstream could not be reached; nothing was sent to the client.
+    Unavailable(io::Error),
+    /// The upstream answered with something that is not an HTTP response.
+    BadResponse,
+    /// The exchange failed midway, possibly after part of the response was relayed.
+    Io(io::Error),
+}
+
+impl std::fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpstreamError::Unavailable(err) => write!(f, "upstream unavailable: {}", err),
+            UpstreamError::BadResponse => write!(f, "invalid upstream response"),
+            UpstreamError::Io(err) => write!(f, "IO error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for UpstreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UpstreamError::Unavailable(err) | UpstreamError::Io(err) => Some(err),
+            UpstreamError::BadResponse => None,
+        }
+    }
+}
+
+impl From<io::Error> for UpstreamError {
+    fn from(err: io::Error) -> Self {
+        UpstreamError::Io(err)
+    }
+}
+
+/// Where the forwarded request originally came from.
+struct Origin<'a> {
+    client_ip: IpAddr,
+    scheme: &'a str,
+}
+
+/// Summary of a relayed response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relayed {
+    /// Status code sent by the upstream.
+    pub status: u16,
+    /// Body bytes relayed to the client.
+    pub bytes: u64,
+    /// Headers of a response that named a file to send instead (see
+    /// [`crate::sendfile`]); nothing was relayed then.
+    pub delegated: Option<Vec<(String, String)>>,
+}
+
+/// A plain-HTTP upstream server, optionally with a base path.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::upstream::Upstream;
+///
+/// let upstream: Upstream = "http://localhost:3000".parse().unwrap();
+/// assert_eq!(upstream.authority(), "localhost:3000");
+///
+/// let upstream: Upstream = "http://127.0.0.1:8081/v1/".parse().unwrap();
+/// assert_eq!(upstream.to_string(), "http://127.0.0.1:8081/v1");
+///
+/// assert!("https://example.com".parse::<Upstream>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upstream {
+    authority: String,
+    base_path: String,
+}
+
+impl std::str::FromStr for Upstream {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| ConfigError::Invalid(format!("upstream '{}': {}", s, reason));
+        let rest = s
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid("only http:// upstreams are supported"))?;
+        let (authority, base_path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err(invalid("missing host"));
+        }
+        let authority = if authority
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()))
+        {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+        Ok(Self {
+            authority,
+            base_path: base_path.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for Upstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "http://{}{}", self.authority, self.base_path)
+    }
+}
+
+impl Upstream {
+    /// `host:port` of the upstream.
+    pub fn authority(&self) -> &str {
+        &self.authority
+    }
+
+    /// Forwards `req` to the upstream and relays the response to `client`.
+    ///
+    /// `path` is the request path as seen below the upstream's base path
+    /// (`req.path()` minus any mount prefix), `client_ip` the address appended
+    /// to `X-Forwarded-For` and `scheme` the value of `X-Forwarded-Proto`.
+    /// `client` is the client connection positioned just after the request
+    /// head (see [`Request::from_reader`]); the request body is streamed from
+    /// it and the response written back to it. With `sendfile`, a response
+    /// carrying `X-Shover-Send` is not relayed but returned as
+    /// [`Relayed::delegated`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UpstreamError::Unavailable`] or [`UpstreamError::BadResponse`]
+    /// when nothing has been written to `client` yet, so the caller can still
+    /// answer with 502, and [`UpstreamError::Io`] otherwise.
+    pub fn forward<C: BufRead + Write>(
+        &self,
+        req: &Request,
+        path: &str,
+        client_ip: IpAddr,
+        scheme: &str,
+        client: &mut C,
+        sendfile: bool,
+    ) -> Result<Relayed, UpstreamError> {
+        let upstream = self.connect().map_err(UpstreamError::Unavailable)?;
+        upstream.set_read_timeout(Some(IO_TIMEOUT))?;
+        upstream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        if req
+            .header("Expect")
+            .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"))
+        {
+            client.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            client.flush()?;
+        }
+        let origin = Origin { client_ip, scheme };
+        self.write_request(req, path, &origin, client, &mut &upstream)?;
+        relay_response(&mut BufReader::new(&upstream), client, sendfile)
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_error = io::Error::new(ErrorKind::NotFound, "no addresses resolved");
+        for addr in self.authority.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Writes the rewritten request head followed by the streamed body.
+    fn write_request<R: BufRead, W: Write>(
+        &self,
+        req: &Request,
+        path: &str,
+        origin: &Origin,
+        body: &mut R,
+        upstream: &mut W,
+    ) -> io::Result<()> {
+        let framing = framing(req)?;
+        let path = match (self.base_path.is_empty(), path.is_empty()) {
+            (_, false) => format!("{}{}", self.base_path, encode_path(path)),
+            (false, true) => self.base_path.clone(),
+            (true, true) => "/".to_string(),
+        };
+        write!(upstream, "{} {}", req.method, path)?;
+        if let Some(query) = req.query() {
+            write!(upstream, "?{}", query)?;
+        }
+        write!(upstream, " HTTP/1.1\r\n")?;
+        // Options the client gave for this connection only
+        let connection_options: Vec<&str> = req
+            .header("Connection")
+            .map(|value| value.split(',').map(str::trim).collect())
+            .unwrap_or_default();
+        for (name, value) in &req.headers {
+            let skipped = HOP_BY_HOP_HEADERS
+                .iter()
+                .chain(REWRITTEN_HEADERS)
+                .chain(FRAMING_HEADERS)
+                .chain(&connection_options)
+                .any(|hop| hop.eq_ignore_ascii_case(name));
+            if !skipped {
+                write!(upstream, "{}: {}\r\n", name, value)?;
+            }
+        }
+        // Exactly one framing header, describing the body as copied below
+        match &framing {
+            Framing::Chunked(coding) => write!(upstream, "Transfer-Encoding: {}\r\n", coding)?,
+            Framing::Length(length) => write!(upstream, "Content-Length: {}\r\n", length)?,
+            Framing::None => {}
+        }
+        write!(upstream, "Host: {}\r\n", self.authority)?;
+        match req.header("X-Forwarded-For") {
+            Some(chain) => write!(
+                upstream,
+                "X-Forwarded-For: {}, {}\r\n",
+                chain, origin.client_ip
+            )?,
+            None => write!(upstream, "X-Forwarded-For: {}\r\n", origin.client_ip)?,
+        }
+        if let Some(host) = req.header("Host") {
+            write!(upstream, "X-Forwarded-Host: {}\r\n", host)?;
+        }
+        write!(upstream, "X-Forwarded-Proto: {}\r\n", origin.scheme)?;
+        write!(upstream, "Connection: close\r\n\r\n")?;
+
+        match framing {
+            Framing::Chunked(_) => copy_chunked(body, upstream)?,
+            Framing::Length(length) => {
+                if io::copy(&mut body.by_ref().take(length), upstream)? < length {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+            }
+            Framing::None => {}
+        }
+        upstream.flush()
+    }
+}
+
+/// An upstream mounted under a path prefix, written `<prefix>=<url>`.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::upstream::ProxyRoute;
+///
+/// let route: ProxyRoute = "/api=http://127.0.0.1:8081".parse().unwrap();
+/// assert_eq!(route.strip("/api/users"), Some("/users"));
+/// assert_eq!(route.strip("/api"), Some(""));
+/// assert_eq!(route.strip("/apiary"), None);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyRoute {
+    prefix: String,
+    upstream: Upstream,
+}
+
+impl std::str::FromStr for ProxyRoute {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (prefix, url) = s.split_once('=').ok_or_else(|| {
+            ConfigError::Invalid(format!("proxy route '{}': expected '<prefix>=<url>'", s))
+        })?;
+        if !prefix.starts_with('/') {
+            return Err(ConfigError::Invalid(format!(
+                "proxy route '{}': prefix must start with '/'",
+                s
+            )));
+        }
+        Ok(Self {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            upstream: url.parse()?,
+        })
+    }
+}
+
+impl ProxyRoute {
+    /// The mounted upstream.
+    pub fn upstream(&self) -> &Upstream {
+        &self.upstream
+    }
+
+    /// The path prefix, without a trailing slash (`""` for `/`).
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Returns the remainder of `path` below the prefix, or `None` if the route does not apply.
+    pub fn strip<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(self.prefix.as_str())?;
+        (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+    }
+
+    /// Finds the route with the longest prefix matching `path`.
+    ///
+    /// Returns the route and the remainder of the path below its prefix.
+    pub fn find<'r, 'p>(routes: &'r [ProxyRoute], path: &'p str) -> Option<(&'r Self, &'p str)> {
+        routes
+            .iter()
+            .filter_map(|route| route.strip(path).map(|rest| (route, rest)))
+            .max_by_key(|(route, _)| route.prefix.len())
+    }
+}
+
+impl std::fmt::Display for ProxyRoute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/ => {}", self.prefix, self.upstream)
+    }
+}
+
+/// Reads one line of at most [`MAX_LINE_LENGTH`] bytes, failing on EOF.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<()> {
+    line.clear();
+    if reader.by_ref().take(MAX_LINE_LENGTH).read_line(line)? == 0 {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// How a request body is delimited.
+enum Framing<'a> {
+    /// Chunked, with the `Transfer-Encoding` value it arrived with.
+    Chunked(&'a str),
+    Length(u64),
+    None,
+}
+
+/// The framing of the body of `req`, refusing ambiguous combinations that
+/// another server could read differently.
+fn framing(req: &Request) -> io::Result<Framing<'_>> {
+    let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, message.to_string());
+    let find = |wanted: &str| -> Vec<&str> {
+        req.headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(wanted))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    };
+    let (lengths, codings) = (find("Content-Length"), find("Transfer-Encoding"));
+    match (lengths.as_slice(), codings.as_slice()) {
+        ([], []) => Ok(Framing::None),
+        ([length], []) => length
+            .trim()
+            .parse()
+            .map(Framing::Length)
+            .map_err(|_| invalid("invalid Content-Length")),
+        ([], [coding])
+            if coding
+                .rsplit(',')
+                .next()
+                .is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked")) =>
+        {
+            Ok(Framing::Chunked(coding))
+        }
+        ([], [_]) => Err(invalid("unsupported Transfer-Encoding")),
+        _ => Err(invalid("ambiguous request framing")),
+    }
+}
+
+/// Copies a chunked body, framing included, up to and including its trailer.
+fn copy_chunked<R: BufRead, W: Write>(body: &mut R, upstream: &mut W) -> io::Result<()> {
+    let mut line = String::new();
+    loop {
+        read_line(body, &mut line)?;
+        upstream.write_all(line.as_bytes())?;
+        let size = line.trim_end().split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "invalid chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        // Chunk data plus its trailing CRLF
+        if io::copy(&mut body.by_ref().take(size + 2), upstream)? < size + 2 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+    }
+    // Optional trailer fields, terminated by an empty line
+    loop {
+        read_line(body, &mut line)?;
+        upstream.write_all(line.as_bytes())?;
+        if line.trim_end().is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// Relays an upstream response to the client until the upstream closes.
+fn relay_response<R: BufRead, W: Write>(
+    upstream: &mut R,
+    client: &mut W,
+    sendfile: bool,
+) -> Result<Relayed, UpstreamError> {
+    let mut line = String::new();
+    read_line(upstream, &mut line).map_err(UpstreamError::Unavailable)?;
+    let mut parts = line.split_ascii_whitespace();
+    let status = match (parts.next(), parts.next()) {
+        (Some(version), Some(code)) if version.starts_with("HTTP/") => code
+            .parse::<u16>()
+            .map_err(|_| UpstreamError::BadResponse)?,
+        _ => return Err(UpstreamError::BadResponse),
+    };
+
+    let mut head = line.clone();
+    let mut headers = Vec::new();
+    loop {
+        read_line(upstream, &mut line)?;
+        head.push_str(&line);
+        if line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    if sendfile
+        && headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case(SEND_HEADER))
+    {
+        return Ok(Relayed {
+            status,
+            bytes: 0,
+            delegated: Some(headers),
+        });
+    }
+    client.write_all(head.as_bytes())?;
+    let bytes = io::copy(upstream, client)?;
+    client.flush()?;
+    Ok(Relayed {
+        status,
+        bytes,
+        delegated: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::HttpMethod;
+    use std::io::Cursor;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    const ORIGIN: Origin = Origin {
+        client_ip: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+        scheme: "http",
+    };
+
+    /// A client connection: reads from `input`, writes to `output`.
+    struct Duplex {
+        input: BufReader<Cursor<Vec<u8>>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl BufRead for Duplex {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            self.input.fill_buf()
+        }
+
+        fn consume(&mut self, amount: usize) {
+            self.input.consume(amount)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn request(head: &str) -> (Request, BufReader<Cursor<Vec<u8>>>) {
+        let mut reader = BufReader::new(Cursor::new(head.as_bytes().to_vec()));
+        let request = Request::from_reader(&mut reader).unwrap();
+        (request, reader)
+    }
+
+    #[test]
+    fn test_parse_upstream() {
+        let upstream: Upstream = "http://api.internal".parse().unwrap();
+        assert_eq!(upstream.authority(), "api.internal:80");
+        let upstream: Upstream = "http://[::1]:9000/base".parse().unwrap();
+        assert_eq!(upstream.authority(), "[::1]:9000");
+        assert_eq!(upstream.base_path, "/base");
+        assert!("http:///path".parse::<Upstream>().is_err());
+    }
+
+    #[test]
+    fn test_write_request_with_length() {
+        let upstream: Upstream = "http://localhost:3000/api".parse().unwrap();
+        let (req, mut body) = request(
+            "POST /users?page=2 HTTP/1.1\r\nContent-Length: 5\r\nConnection: keep-alive\r\n\r\nhelloEXTRA",
+        );
+        let mut sent = Vec::new();
+        upstream
+            .write_request(&req, req.path(), &ORIGIN, &mut body, &mut sent)
+            .unwrap();
+        let sent = String::from_utf8(sent).unwrap();
+
+        assert!(sent.starts_with("POST /api/users?page=2 HTTP/1.1\r\n"));
+        assert!(sent.contains("Content-Length: 5\r\n"));
+        assert!(!sent.contains("keep-alive"));
+        assert!(sent.ends_with("Connection: close\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_write_request_chunked() {
+        let upstream: Upstream = "http://localhost:3000".parse().unwrap();
+        let (req, mut body) = request(
+            "PUT /f HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\nNEXT",
+        );
+        let mut sent = Vec::new();
+        upstream
+            .write_request(&req, req.path(), &ORIGIN, &mut body, &mut sent)
+            .unwrap();
+        assert!(String::from_utf8(sent)
+            .unwrap()
+            .ends_with("\r\n\r\n5\r\nhello\r\n0\r\n\r\n"));
+
+        let (req, mut body) =
+            request("PUT /f HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel");
+        assert!(upstream
+            .write_request(&req, req.path(), &ORIGIN, &mut body, &mut Vec::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_write_request_framing() {
+        let upstream: Upstream = "http://localhost:3000".parse().unwrap();
+        let (req, mut body) = request(
+            "POST /f HTTP/1.1\r\nConnection: X-Secret, close\r\nX-Secret: 1\r\n\
+             Transfer-Encoding: chunked\r\n\r\n1\r\na\r\n0\r\n\r\n",
+        );
+        let mut sent = Vec::new();
+        upstream
+            .write_request(&req, req.path(), &ORIGIN, &mut body, &mut sent)
+            .unwrap();
+        let sent = String::from_utf8(sent).unwrap();
+        assert_eq!(sent.matches("Transfer-Encoding: chunked\r\n").count(), 1);
+        assert!(!sent.contains("X-Secret"));
+        assert!(!sent.contains("Content-Length"));
+
+        // Built directly, so the parser never refused them
+        let mut req = Request::builder()
+            .method(HttpMethod::POST)
+            .path("/f")
+            .build();
+        req.headers.insert("Content-Length".into(), "1".into());
+        req.headers.insert("content-length".into(), "10".into());
+        let mut sent = Vec::new();
+        assert!(upstream
+            .write_request(&req, req.path(), &ORIGIN, &mut Cursor::new("a"), &mut sent)
+            .is_err());
+        req.headers.remove("content-length");
+        req.headers
+            .insert("Transfer-Encoding".into(), "chunked".into());
+        assert!(upstream
+            .write_request(&req, req.path(), &ORIGIN, &mut Cursor::new("a"), &mut sent)
+            .is_err());
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn test_rewritten_headers() {
+        let route: ProxyRoute = "/api=http://10.0.0.5:8081/v1".parse().unwrap();
+        let (req, mut body) = request(
+            "GET /api/users HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-For: 203.0.113.9\r\nX-Forwarded-Proto: https\r\n\r\n",
+        );
+        let (route, path) = ProxyRoute::find(std::slice::from_ref(&route), req.path()).unwrap();
+        let mut sent = Vec::new();
+        route
+            .upstream()
+            .write_request(&req, path, &ORIGIN, &mut body, &mut sent)
+            .unwrap();
+        let sent = String::from_utf8(sent).unwrap();
+
+        assert!(sent.starts_with("GET /v1/users HTTP/1.1\r\n"));
+        assert!(sent.contains("Host: 10.0.0.5:8081\r\n"));
+        assert!(sent.contains("X-Forwarded-For: 203.0.113.9, 192.0.2.1\r\n"));
+        assert!(sent.contains("X-Forwarded-Host: example.com\r\n"));
+        assert!(sent.contains("X-Forwarded-Proto: http\r\n"));
+        assert!(!sent.contains("https"));
+        assert!(!sent.contains("\nHost: example.com\r\n"));
+    }
+
+    #[test]
+    fn test_route_matching() {
+        let routes: Vec<ProxyRoute> = ["/api=http://a:1", "/api/admin=http://b:2", "/=http://c:3"]
+            .iter()
+            .map(|r| r.parse().unwrap())
+            .collect();
+        let target = |path| {
+            ProxyRoute::find(&routes, path)
+                .map(|(route, rest)| (route.upstream().authority(), rest))
+        };
+        assert_eq!(target("/api/admin/users"), Some(("b:2", "/users")));
+        assert_eq!(target("/api/x"), Some(("a:1", "/x")));
+        assert_eq!(target("/other"), Some(("c:3", "/other")));
+        assert!("api=http://a:1".parse::<ProxyRoute>().is_err());
+        assert!("/api".parse::<ProxyRoute>().is_err());
+    }
+
+    #[test]
+    fn test_relay_response() {
+        let mut upstream =
+            Cursor::new(b"HTTP/1.1 201 Created\r\nX-Id: 7\r\n\r\n{\"ok\":true}".to_vec());
+        let mut client = Vec::new();
+        let relayed = relay_response(&mut upstream, &mut client, true).unwrap();
+        assert_eq!(
+            relayed,
+            Relayed {
+                status: 201,
+                bytes: 11,
+                delegated: None,
+            }
+        );
+        assert!(client.starts_with(b"HTTP/1.1 201 Created\r\nX-Id: 7\r\n\r\n"));
+
+        let mut garbage = Cursor::new(b"SSH-2.0-OpenSSH\r\n".to_vec());
+        assert!(matches!(
+            relay_response(&mut garbage, &mut Vec::new(), true),
+            Err(UpstreamError::BadResponse)
+        ));
+    }
+
+    #[test]
+    fn test_forward_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream: Upstream = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let req = Request::from_reader(&mut reader).unwrap();
+            let length = req.header("Content-Length").unwrap().parse().unwrap();
+            let mut body = String::new();
+            reader.take(length).read_to_string(&mut body).unwrap();
+            let reply = format!("{} {} {}", req.method, req.path(), body);
+            write!(
+                &stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                reply.len(),
+                reply
+            )
+            .unwrap();
+        });
+
+        let (req, input) = request("POST /echo HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi");
+        let mut client = Duplex {
+            input,
+            output: Vec::new(),
+        };
+        let relayed = upstream
+            .forward(
+                &req,
+                req.path(),
+                ORIGIN.client_ip,
+                "https",
+                &mut client,
+                false,
+            )
+            .unwrap();
+        server.join().unwrap();
+
+        assert_eq!(relayed.status, 200);
+        assert!(String::from_utf8(client.output)
+            .unwrap()
+            .ends_with("POST /echo hi"));
+    }
+}
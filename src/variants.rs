@@ -0,0 +1,253 @@
+/*
+* Content negotiation module
+*
+* Serves one of several files for an extensionless URL, picked by the
+* request's `Accept` header, for resources with both a human and a machine
+* representation:
+*
+*   GET /data  Accept: application/json  -> data.json
+*   GET /data  Accept: text/html         -> data.html
+*   GET /data  (no Accept, or any type)  -> the first configured variant
+*
+* Variants are a list of media type and extension pairs, by default HTML then
+* JSON; the order breaks ties. A file that exists under the requested path
+* itself always wins, so negotiation only fills in missing URLs. Negotiated
+* responses carry `Vary: Accept`, so caches keep the variants apart,
+* `Content-Location` naming the file that was picked, and the configured
+* media type as `Content-Type`.
+*/
+
+use crate::files::FileTree;
+use crate::message::{HttpMethod, Request, Response};
+use crate::middleware::Handler;
+use crate::target::{encode_path, RequestTarget};
+use std::sync::Arc;
+
+/// Variants offered by default, in order of preference.
+const DEFAULT_VARIANTS: &[(&str, &str)] = &[("text/html", "html"), ("application/json", "json")];
+
+/// Media types and the file extensions they are stored under.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::MemorySource;
+/// use file_shover::message::Request;
+/// use file_shover::server::Server;
+/// use file_shover::variants::Variants;
+///
+/// let service = Server::builder()
+///     .file_source(
+///         MemorySource::new()
+///             .with("data.html", "<table></table>")
+///             .with("data.json", "[]"),
+///     )
+///     .variants(Variants::new())
+///     .build_service()?;
+/// let request = Request::builder()
+///     .path("/data")
+///     .header("Accept", "application/json")
+///     .build();
+/// let response = service.handle(&request);
+/// assert_eq!(response.headers.get("Content-Location"), Some(&"/data.json".to_string()));
+/// assert_eq!(response.headers.get("Vary"), Some(&"Accept".to_string()));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Variants {
+    variants: Vec<(String, String)>,
+}
+
+impl Default for Variants {
+    fn default() -> Self {
+        Self {
+            variants: DEFAULT_VARIANTS
+                .iter()
+                .map(|(mime, extension)| (mime.to_string(), extension.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl Variants {
+    /// Offers `.html` then `.json` files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Offers exactly the given `(media type, extension)` pairs, most
+    /// preferred first, e.g. `("text/csv", "csv")`.
+    pub fn variants(
+        mut self,
+        variants: impl IntoIterator<Item = (impl Into<String>, impl Into<String>)>,
+    ) -> Self {
+        self.variants = variants
+            .into_iter()
+            .map(|(mime, extension)| {
+                let mime: String = mime.into();
+                let extension: String = extension.into();
+                (
+                    mime.to_ascii_lowercase(),
+                    extension.trim_start_matches('.').to_string(),
+                )
+            })
+            .collect();
+        self
+    }
+
+    /// Wraps the file handler `inner` so extensionless paths are negotiated.
+    pub(crate) fn handler(self, inner: Box<dyn Handler>, file_tree: Arc<FileTree>) -> Negotiated {
+        Negotiated {
+            variants: self,
+            inner,
+            file_tree,
+        }
+    }
+
+    /// The file and media type of the variant of `path` to serve for
+    /// `accept`, if any exists: the one with the highest quality, the
+    /// earliest on ties. Variants the client refuses (`q=0`) are never picked.
+    fn choose(
+        &self,
+        file_tree: &FileTree,
+        path: &str,
+        accept: Option<&str>,
+    ) -> Option<(String, &str)> {
+        let mut best: Option<(f32, String, &str)> = None;
+        for (mime, extension) in &self.variants {
+            let quality = accept.map_or(1.0, |accept| quality(accept, mime));
+            if quality <= 0.0 || best.as_ref().is_some_and(|(q, _, _)| *q >= quality) {
+                continue;
+            }
+            let candidate = format!("{}.{}", path, extension);
+            if file_tree
+                .metadata(&candidate)
+                .is_ok_and(|metadata| !metadata.is_dir)
+            {
+                best = Some((quality, candidate, mime));
+            }
+        }
+        best.map(|(_, candidate, mime)| (candidate, mime))
+    }
+}
+
+/// The file handler with content negotiation in front.
+pub(crate) struct Negotiated {
+    variants: Variants,
+    inner: Box<dyn Handler>,
+    file_tree: Arc<FileTree>,
+}
+
+impl Handler for Negotiated {
+    fn handle(&self, req: &Request) -> Response {
+        let path = req.path();
+        let negotiable = matches!(req.method, HttpMethod::GET | HttpMethod::HEAD)
+            && !path.ends_with('/')
+            && !path.rsplit('/').next().unwrap_or_default().contains('.')
+            && self.file_tree.metadata(path).is_err();
+        if !negotiable {
+            return self.inner.handle(req);
+        }
+        let Some((chosen, mime)) =
+            self.variants
+                .choose(&self.file_tree, path, req.header("Accept"))
+        else {
+            return self.inner.handle(req);
+        };
+        let target = match req.target.query() {
+            Some(query) => format!("{}?{}", encode_path(&chosen), query),
+            None => encode_path(&chosen),
+        };
+        let Ok(target) = RequestTarget::parse(&target) else {
+            return self.inner.handle(req);
+        };
+        let mut variant_req = req.clone();
+        variant_req.target = target;
+        let mut response = self.inner.handle(&variant_req).vary("Accept");
+        if response.status.is_success() {
+            response = response
+                .content_type(mime)
+                .header("Content-Location", encode_path(&chosen));
+        }
+        response
+    }
+}
+
+/// The quality `accept` gives `mime`: the `q` of the most specific matching
+/// media range (`text/html` over `text/*` over `*/*`), or 0 if none matches.
+fn quality(accept: &str, mime: &str) -> f32 {
+    let (kind, _) = mime.split_once('/').unwrap_or((mime, ""));
+    let mut best = (0, 0.0);
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let range = params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let specificity = if range == mime {
+            3
+        } else if range.strip_suffix("/*") == Some(kind) {
+            2
+        } else if range == "*/*" {
+            1
+        } else {
+            continue;
+        };
+        let q = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if specificity > best.0 {
+            best = (specificity, q);
+        }
+    }
+    best.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::MemorySource;
+
+    #[test]
+    fn test_quality() {
+        assert_eq!(quality("application/json", "application/json"), 1.0);
+        assert_eq!(quality("text/html;q=0.5, */*;q=0.1", "text/html"), 0.5);
+        assert_eq!(quality("text/*;q=0.3, */*;q=0.1", "text/html"), 0.3);
+        assert_eq!(quality("text/html;q=0.5, */*;q=0.1", "text/csv"), 0.1);
+        assert_eq!(quality("image/png", "text/html"), 0.0);
+        assert_eq!(quality("*/*, text/html;q=0", "text/html"), 0.0);
+    }
+
+    #[test]
+    fn test_choose() {
+        let tree = FileTree::from_source(
+            MemorySource::new()
+                .with("data.html", "<table></table>")
+                .with("data.json", "[]")
+                .with("only.json", "{}"),
+        );
+        let variants = Variants::new();
+        let choose = |path: &str, accept: Option<&str>| {
+            variants
+                .choose(&tree, path, accept)
+                .map(|(candidate, _)| candidate)
+        };
+        assert_eq!(
+            variants.choose(&tree, "/data", None),
+            Some(("/data.html".to_string(), "text/html"))
+        );
+        assert_eq!(choose("/data", Some("*/*")).as_deref(), Some("/data.html"));
+        assert_eq!(
+            choose("/data", Some("text/html;q=0.8, application/json")).as_deref(),
+            Some("/data.json")
+        );
+        assert_eq!(
+            choose("/only", Some("text/html, */*;q=0.1")).as_deref(),
+            Some("/only.json")
+        );
+        assert_eq!(choose("/only", Some("text/html")), None);
+        assert_eq!(choose("/missing", None), None);
+    }
+}
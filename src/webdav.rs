@@ -0,0 +1,558 @@
+/*
+* WebDAV module
+*
+* A WebDAV class 1 (RFC 4918) layer over the served tree, so it can be
+* mounted as a network drive by Finder, Explorer, davfs2 or backup tools.
+*
+*   OPTIONS  -> DAV: 1 and the allowed methods
+*   PROPFIND -> 207 Multi-Status with the live properties of a resource
+*               (Depth: 0) or of a collection and its members (Depth: 1)
+*
+* Reads work on any file source. With writable mode, MKCOL, COPY and MOVE
+* act on the upload root next to the PUT and DELETE it already accepts,
+* under the same path rules and hook.
+*
+* Request bodies are not parsed: PROPFIND always answers with every live
+* property, which clients asking for a subset accept. Dead properties
+* (PROPPATCH) and locking (class 2) are not supported.
+*/
+
+use crate::data::get_mime_type;
+use crate::files::{FileMetadata, FileTree};
+use crate::message::{
+    HttpMethod, Request, Response, StatusCode, DEFAULT_BAD_REQUEST_BODY, DEFAULT_FORBIDDEN_BODY,
+    DEFAULT_INTERNAL_ERROR_BODY, DEFAULT_METHOD_NOT_ALLOWED_BODY, DEFAULT_NOT_FOUND_BODY,
+};
+use crate::target::{encode_path, RequestTarget};
+use crate::units::http_date;
+use crate::upload::{UploadError, Uploads, WriteOperation};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use tracing::info;
+
+/// Methods answered for a read-only tree.
+const READ_METHODS: &str = "GET, HEAD, OPTIONS, PROPFIND";
+
+/// Methods answered for a writable tree.
+const WRITE_METHODS: &str = "GET, HEAD, OPTIONS, PROPFIND, PUT, DELETE, MKCOL, COPY, MOVE";
+
+/// Answers `OPTIONS` with the WebDAV compliance class.
+pub fn options(writable: bool) -> Response {
+    let allow = if writable {
+        WRITE_METHODS
+    } else {
+        READ_METHODS
+    };
+    Response::new()
+        .status(StatusCode::OK)
+        .header("DAV", "1")
+        .header("MS-Author-Via", "DAV")
+        .header("Allow", allow)
+        .content_length(0u64)
+}
+
+/// Answers `PROPFIND` for a path in `tree`.
+///
+/// Hidden entries are neither listed nor found, matching directory
+/// listings. `Depth: infinity`, the default, is refused with 403 as RFC 4918
+/// allows, since it would walk the whole tree.
+///
+/// # Examples
+///
+/// ```
+/// use file_shover::files::{FileTree, MemorySource};
+/// use file_shover::message::{HttpMethod, Request, StatusCode};
+/// use file_shover::webdav;
+///
+/// let tree = FileTree::from_source(MemorySource::new().with("docs/a.txt", "a"));
+/// let req = Request::builder()
+///     .method(HttpMethod::PROPFIND)
+///     .path("/docs/")
+///     .header("Depth", "1")
+///     .build();
+/// let response = webdav::propfind(&tree, &req);
+/// assert_eq!(response.status, StatusCode::MULTI_STATUS);
+/// ```
+pub fn propfind(tree: &FileTree, req: &Request) -> Response {
+    let depth = match req.header("Depth").map(str::trim) {
+        Some("0") => 0,
+        Some("1") => 1,
+        _ => {
+            info!("Refused PROPFIND of {} without a finite depth", req.path());
+            return Response::error(StatusCode::FORBIDDEN, DEFAULT_FORBIDDEN_BODY);
+        }
+    };
+    let path = req.path();
+    if path.split('/').any(|segment| segment.starts_with('.')) {
+        return Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY);
+    }
+    let metadata = match tree.metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => return io_error(path, e),
+    };
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+    let mut href = encode_path(path);
+    if metadata.is_dir && !href.ends_with('/') {
+        href.push('/');
+    }
+    push_response(&mut xml, &href, name, &metadata);
+    if metadata.is_dir && depth == 1 {
+        let entries = match tree.list(path) {
+            Ok(entries) => entries,
+            Err(e) => return io_error(path, e),
+        };
+        for entry in entries.iter().filter(|entry| !entry.name.starts_with('.')) {
+            let mut child = format!("{}{}", href, encode_path(&entry.name));
+            if entry.metadata.is_dir {
+                child.push('/');
+            }
+            push_response(&mut xml, &child, &entry.name, &entry.metadata);
+        }
+    }
+    xml.push_str("</D:multistatus>\n");
+
+    Response::new()
+        .status(StatusCode::MULTI_STATUS)
+        .content_type("application/xml; charset=utf-8")
+        .content_length(xml.len())
+        .body(xml)
+}
+
+/// Answers `MKCOL`, `COPY` and `MOVE` on the upload root.
+pub(crate) fn write(uploads: &Uploads, req: &Request) -> Response {
+    let result = match req.method {
+        HttpMethod::MKCOL => mkcol(uploads, req),
+        HttpMethod::COPY => transfer(uploads, req, false),
+        HttpMethod::MOVE => transfer(uploads, req, true),
+        _ => Err(DavError::MethodNotAllowed),
+    };
+    match result {
+        Ok(status) => {
+            info!("{} {} succeeded", req.method, req.path());
+            Response::new().status(status).content_length(0u64)
+        }
+        Err(e) => {
+            info!("{} {} failed: {}", req.method, req.path(), e);
+            e.response()
+        }
+    }
+}
+
+/// Creates a directory whose parent exists.
+fn mkcol(uploads: &Uploads, req: &Request) -> Result<StatusCode, DavError> {
+    let target = uploads.resolve(req.path().trim_end_matches('/'))?;
+    uploads.check(req, WriteOperation::Put)?;
+    if req
+        .header("Content-Length")
+        .is_some_and(|length| length.trim() != "0")
+        || req.header("Transfer-Encoding").is_some()
+    {
+        return Err(DavError::UnsupportedMediaType);
+    }
+    if fs::symlink_metadata(&target).is_ok() {
+        return Err(DavError::MethodNotAllowed);
+    }
+    fs::create_dir(&target).map_err(|e| match e.kind() {
+        ErrorKind::NotFound | ErrorKind::NotADirectory => UploadError::Conflict,
+        _ => UploadError::Io(e),
+    })?;
+    Ok(StatusCode::CREATED)
+}
+
+/// Copies or moves the request path to its `Destination`.
+fn transfer(uploads: &Uploads, req: &Request, remove_source: bool) -> Result<StatusCode, DavError> {
+    let source = uploads.resolve(req.path().trim_end_matches('/'))?;
+    let destination_path = req
+        .header("Destination")
+        .and_then(|destination| RequestTarget::parse(destination).ok())
+        .ok_or(DavError::BadDestination)?;
+    let destination_path = destination_path.path().trim_end_matches('/');
+    let destination = uploads.resolve(destination_path)?;
+
+    // The hook sees the destination for the write and the source for the
+    // removal a move implies
+    let mut destination_req = req.clone();
+    destination_req.target = RequestTarget::parse(&encode_path(destination_path))
+        .map_err(|_| DavError::BadDestination)?;
+    uploads.check_access(&destination_req)?;
+    uploads.check(&destination_req, WriteOperation::Put)?;
+    if remove_source {
+        uploads.check(req, WriteOperation::Delete)?;
+    }
+
+    let metadata = fs::symlink_metadata(&source).map_err(|e| match e.kind() {
+        ErrorKind::NotFound | ErrorKind::NotADirectory => UploadError::NotFound,
+        _ => UploadError::Io(e),
+    })?;
+    // Into itself, or onto an ancestor, whose removal would take the
+    // source with it
+    if destination.starts_with(&source) || source.starts_with(&destination) {
+        return Err(UploadError::Forbidden.into());
+    }
+    // The members of a directory are reached through the request path, so
+    // their own rules apply: a copy leaves out those the client may not
+    // read, a move (which removes them) is refused unless it may write
+    // them all, and every target must be writable
+    let source_path = req.path().trim_end_matches('/');
+    let mut members = Vec::new();
+    if remove_source && metadata.is_dir() {
+        walk(&source, "", &mut members, &|_| true)?;
+        if members
+            .iter()
+            .any(|(member, _)| !uploads.can_write(req, &format!("{}{}", source_path, member)))
+        {
+            return Err(UploadError::Forbidden.into());
+        }
+    } else if metadata.is_dir() && req.header("Depth").map(str::trim) != Some("0") {
+        walk(&source, "", &mut members, &|member| {
+            uploads.can_read(req, &format!("{}{}", source_path, member))
+        })?;
+    }
+    if members
+        .iter()
+        .any(|(member, _)| !uploads.can_write(req, &format!("{}{}", destination_path, member)))
+    {
+        return Err(UploadError::Forbidden.into());
+    }
+
+    let existed = fs::symlink_metadata(&destination).is_ok();
+    if existed {
+        if req.header("Overwrite").map(str::trim) == Some("F") {
+            return Err(DavError::PreconditionFailed);
+        }
+        if fs::symlink_metadata(&destination)?.is_dir() {
+            // Overwriting removes every member of the destination
+            let mut replaced = Vec::new();
+            walk(&destination, "", &mut replaced, &|_| true)?;
+            if replaced.iter().any(|(member, _)| {
+                !uploads.can_write(req, &format!("{}{}", destination_path, member))
+            }) {
+                return Err(UploadError::Forbidden.into());
+            }
+            fs::remove_dir_all(&destination)?;
+        } else {
+            fs::remove_file(&destination)?;
+        }
+    }
+    if !destination.parent().is_some_and(Path::is_dir) {
+        return Err(UploadError::Conflict.into());
+    }
+
+    if remove_source {
+        fs::rename(&source, &destination)?;
+    } else if metadata.is_dir() {
+        copy_dir(&source, &destination, &members)?;
+    } else {
+        fs::copy(&source, &destination)?;
+    }
+    Ok(if existed {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CREATED
+    })
+}
+
+/// Creates `destination` and copies the `members` of `source` into it.
+fn copy_dir(source: &Path, destination: &Path, members: &[(String, bool)]) -> io::Result<()> {
+    fs::create_dir(destination)?;
+    for (member, is_dir) in members {
+        let member = member.trim_start_matches('/');
+        if *is_dir {
+            fs::create_dir(destination.join(member))?;
+        } else {
+            fs::copy(source.join(member), destination.join(member))?;
+        }
+    }
+    Ok(())
+}
+
+/// Collects the entries below `dir` as `/`-prefixed paths relative to it,
+/// each directory before its contents, with whether it is a directory.
+/// Entries `keep` refuses are left out along with their contents.
+fn walk(
+    dir: &Path,
+    prefix: &str,
+    entries: &mut Vec<(String, bool)>,
+    keep: &dyn Fn(&str) -> bool,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "file name is not UTF-8"))?;
+        let path = format!("{}/{}", prefix, name);
+        if !keep(&path) {
+            continue;
+        }
+        let is_dir = entry.file_type()?.is_dir();
+        entries.push((path.clone(), is_dir));
+        if is_dir {
+            walk(&entry.path(), &path, entries, keep)?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends one `<D:response>` with the live properties of an entry.
+fn push_response(xml: &mut String, href: &str, name: &str, metadata: &FileMetadata) {
+    let _ = write!(
+        xml,
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname>",
+        escape_xml(href),
+        escape_xml(name)
+    );
+    if metadata.is_dir {
+        xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        let _ = write!(
+            xml,
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+             <D:getcontenttype>{}</D:getcontenttype>",
+            metadata.len,
+            escape_xml(get_mime_type(name).as_str())
+        );
+    }
+    if let Some(modified) = metadata
+        .modified
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+    {
+        let _ = write!(
+            xml,
+            "<D:getlastmodified>{}</D:getlastmodified>",
+            http_date(modified.as_secs())
+        );
+    }
+    xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+}
+
+fn io_error(path: &str, e: io::Error) -> Response {
+    if e.kind() == ErrorKind::NotFound {
+        return Response::error(StatusCode::NOT_FOUND, DEFAULT_NOT_FOUND_BODY);
+    }
+    info!("Server error for PROPFIND {}: {}", path, e);
+    Response::error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        DEFAULT_INTERNAL_ERROR_BODY,
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Why a WebDAV write was refused.
+#[derive(Debug)]
+enum DavError {
+    Upload(UploadError),
+    /// `MKCOL` on an existing path, or an unsupported method.
+    MethodNotAllowed,
+    /// `MKCOL` with a body.
+    UnsupportedMediaType,
+    /// `Overwrite: F` and the destination exists.
+    PreconditionFailed,
+    /// Missing or malformed `Destination` header.
+    BadDestination,
+}
+
+impl std::fmt::Display for DavError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DavError::Upload(err) => write!(f, "{}", err),
+            DavError::MethodNotAllowed => write!(f, "method not allowed here"),
+            DavError::UnsupportedMediaType => write!(f, "MKCOL with a body"),
+            DavError::PreconditionFailed => write!(f, "destination exists"),
+            DavError::BadDestination => write!(f, "missing or invalid Destination"),
+        }
+    }
+}
+
+impl From<UploadError> for DavError {
+    fn from(err: UploadError) -> Self {
+        DavError::Upload(err)
+    }
+}
+
+impl From<io::Error> for DavError {
+    fn from(err: io::Error) -> Self {
+        DavError::Upload(UploadError::Io(err))
+    }
+}
+
+impl DavError {
+    fn response(&self) -> Response {
+        match self {
+            DavError::Upload(err) => err.response(),
+            DavError::MethodNotAllowed => Response::error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                DEFAULT_METHOD_NOT_ALLOWED_BODY,
+            )
+            .header("Allow", WRITE_METHODS),
+            DavError::UnsupportedMediaType => Response::new()
+                .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .content_length(0u64),
+            DavError::PreconditionFailed => Response::new()
+                .status(StatusCode::PRECONDITION_FAILED)
+                .content_length(0u64),
+            DavError::BadDestination => {
+                Response::error(StatusCode::BAD_REQUEST, DEFAULT_BAD_REQUEST_BODY)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::MemorySource;
+    use std::path::PathBuf;
+
+    fn request(method: HttpMethod, path: &str, headers: &[(&str, &str)]) -> Request {
+        let mut builder = Request::builder().method(method).path(path);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.build()
+    }
+
+    fn body(response: Response) -> String {
+        let mut body = String::new();
+        response
+            .body
+            .into_reader()
+            .read_to_string(&mut body)
+            .unwrap();
+        body
+    }
+
+    #[test]
+    fn test_propfind() {
+        let tree = FileTree::from_source(
+            MemorySource::new()
+                .with("a & b/report.html", "12345")
+                .with("a & b/sub/x", "")
+                .with("a & b/.secret", ""),
+        );
+        let response = propfind(
+            &tree,
+            &request(HttpMethod::PROPFIND, "/a%20&%20b", &[("Depth", "1")]),
+        );
+        assert_eq!(response.status, StatusCode::MULTI_STATUS);
+        let xml = body(response);
+        assert_eq!(xml.matches("<D:response>").count(), 3);
+        assert!(xml.contains("<D:href>/a%20&amp;%20b/</D:href>"));
+        assert!(xml.contains("<D:displayname>a &amp; b</D:displayname>"));
+        assert!(xml.contains("<D:href>/a%20&amp;%20b/sub/</D:href>"));
+        assert!(xml.contains("<D:getcontentlength>5</D:getcontentlength>"));
+        assert!(xml.contains("<D:getcontenttype>text/html</D:getcontenttype>"));
+        assert!(!xml.contains("secret"));
+
+        let response = propfind(
+            &tree,
+            &request(HttpMethod::PROPFIND, "/a%20&%20b/", &[("Depth", "0")]),
+        );
+        assert_eq!(body(response).matches("<D:response>").count(), 1);
+        let status = |path: &str, depth: &[(&str, &str)]| {
+            propfind(&tree, &request(HttpMethod::PROPFIND, path, depth)).status
+        };
+        assert_eq!(status("/", &[]), StatusCode::FORBIDDEN);
+        assert_eq!(status("/", &[("Depth", "infinity")]), StatusCode::FORBIDDEN);
+        assert_eq!(status("/missing", &[("Depth", "0")]), StatusCode::NOT_FOUND);
+        assert_eq!(
+            status("/a%20&%20b/.secret", &[("Depth", "0")]),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_write_methods() {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("file-shover-dav-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::write(dir.join("docs/a.txt"), "a").unwrap();
+        let uploads = Uploads::new(&dir, 100).authorize(|req, op| {
+            !(op == WriteOperation::Delete && req.path().starts_with("/keep"))
+        });
+        let status = |method: HttpMethod, path: &str, headers: &[(&str, &str)]| {
+            write(&uploads, &request(method, path, headers)).status
+        };
+
+        assert_eq!(status(HttpMethod::MKCOL, "/new/", &[]), StatusCode::CREATED);
+        assert!(dir.join("new").is_dir());
+        assert_eq!(
+            status(HttpMethod::MKCOL, "/new", &[]),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            status(HttpMethod::MKCOL, "/no/parent", &[]),
+            StatusCode::CONFLICT
+        );
+
+        let to = |path: &str| ("Destination", format!("http://host{}", path));
+        let (name, value) = to("/new/b.txt");
+        assert_eq!(
+            status(HttpMethod::COPY, "/docs/a.txt", &[(name, &value)]),
+            StatusCode::CREATED
+        );
+        assert_eq!(fs::read_to_string(dir.join("new/b.txt")).unwrap(), "a");
+        let overwrite = [(name, value.as_str()), ("Overwrite", "F")];
+        assert_eq!(
+            status(HttpMethod::COPY, "/docs/a.txt", &overwrite),
+            StatusCode::PRECONDITION_FAILED
+        );
+
+        let (name, value) = to("/keep");
+        assert_eq!(
+            status(HttpMethod::COPY, "/docs/", &[(name, &value)]),
+            StatusCode::CREATED
+        );
+        assert_eq!(fs::read_to_string(dir.join("keep/a.txt")).unwrap(), "a");
+        let (name, value) = to("/moved");
+        assert_eq!(
+            status(HttpMethod::MOVE, "/keep", &[(name, &value)]),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(HttpMethod::MOVE, "/docs", &[(name, &value)]),
+            StatusCode::CREATED
+        );
+        assert!(!dir.join("docs").exists());
+        assert_eq!(fs::read_to_string(dir.join("moved/a.txt")).unwrap(), "a");
+
+        let (name, value) = to("/moved/inner");
+        assert_eq!(
+            status(HttpMethod::MOVE, "/moved", &[(name, &value)]),
+            StatusCode::FORBIDDEN
+        );
+        let (name, value) = to("/moved");
+        for method in [HttpMethod::MOVE, HttpMethod::COPY] {
+            assert_eq!(
+                status(method, "/moved/a.txt", &[(name, &value)]),
+                StatusCode::FORBIDDEN
+            );
+        }
+        assert_eq!(fs::read_to_string(dir.join("moved/a.txt")).unwrap(), "a");
+        assert_eq!(
+            status(HttpMethod::MOVE, "/moved", &[]),
+            StatusCode::BAD_REQUEST
+        );
+        let (name, value) = to("/elsewhere");
+        assert_eq!(
+            status(HttpMethod::COPY, "/missing", &[(name, &value)]),
+            StatusCode::NOT_FOUND
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}